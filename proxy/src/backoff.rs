@@ -0,0 +1,317 @@
+use std::cmp;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use rand::{self, Rng};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+/// Configures exponential backoff between reconnection attempts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BackoffConfig {
+    /// The delay after the first failure.
+    min: Duration,
+
+    /// The maximum delay between attempts, regardless of how many have failed.
+    max: Duration,
+
+    /// The fraction of each delay, between 0.0 and 1.0, that is randomized.
+    jitter: f64,
+}
+
+/// Tracks the delay before the next attempt as failures accumulate.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    config: BackoffConfig,
+    failures: u32,
+}
+
+/// Delays reconnection attempts of an inner service after it fails to become
+/// ready.
+///
+/// When `poll_ready` on the inner service fails, the error is returned as-is
+/// and further calls to `poll_ready` return `NotReady` until the backoff
+/// delay has elapsed. The delay is reset once the inner service becomes ready.
+///
+/// If constructed without a `BackoffConfig`, this is a no-op.
+pub struct ReconnectBackoff<S> {
+    inner: S,
+    backoff: Option<Backoff>,
+    timer: Option<ReactorTimeout>,
+    handle: Handle,
+}
+
+/// The default fraction of each delay that is randomized.
+pub const DEFAULT_JITTER: f64 = 0.1;
+
+// Doubling more than this many times overflows a `u32` multiplier.
+const MAX_DOUBLINGS: u32 = 31;
+
+// ===== impl BackoffConfig =====
+
+impl BackoffConfig {
+    /// Creates a new `BackoffConfig`.
+    ///
+    /// `jitter` is clamped to the range `[0.0, 1.0]`, and `max` is raised to
+    /// `min` if it is smaller.
+    pub fn new(min: Duration, max: Duration, jitter: f64) -> Self {
+        let jitter = if jitter.is_nan() {
+            0.0
+        } else {
+            jitter.max(0.0).min(1.0)
+        };
+        BackoffConfig {
+            min,
+            max: cmp::max(min, max),
+            jitter,
+        }
+    }
+}
+
+// ===== impl Backoff =====
+
+impl Backoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        Backoff {
+            config,
+            failures: 0,
+        }
+    }
+
+    /// Records a failure, returning how long to wait before the next attempt.
+    pub fn next_delay<R: Rng>(&mut self, rng: &mut R) -> Duration {
+        let base = self.base_delay();
+        self.failures = self.failures.saturating_add(1);
+
+        if self.config.jitter == 0.0 {
+            return base;
+        }
+
+        // Subtract a random portion of the delay, so that jitter never
+        // pushes the delay above `max`.
+        let jitter = self.config.jitter * rng.gen::<f64>();
+        scale(base, 1.0 - jitter)
+    }
+
+    /// Resets the delay after a successful attempt.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    fn base_delay(&self) -> Duration {
+        let doublings = cmp::min(self.failures, MAX_DOUBLINGS);
+        self.config.min
+            .checked_mul(1 << doublings)
+            .map(|d| cmp::min(d, self.config.max))
+            .unwrap_or(self.config.max)
+    }
+}
+
+/// Multiplies `d` by a non-negative `factor`.
+pub fn scale(d: Duration, factor: f64) -> Duration {
+    let nanos = (d.as_secs() as f64 * 1e9 + f64::from(d.subsec_nanos())) * factor;
+    let secs = (nanos / 1e9) as u64;
+    let subsec_nanos = (nanos % 1e9) as u32;
+    Duration::new(secs, subsec_nanos)
+}
+
+// ===== impl ReconnectBackoff =====
+
+impl<S> ReconnectBackoff<S> {
+    pub fn new(inner: S, config: Option<BackoffConfig>, handle: &Handle) -> Self {
+        ReconnectBackoff {
+            inner,
+            backoff: config.map(Backoff::new),
+            timer: None,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<S> Service for ReconnectBackoff<S>
+where
+    S: Service,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if let Some(mut timer) = self.timer.take() {
+            match timer.poll() {
+                Ok(Async::NotReady) => {
+                    self.timer = Some(timer);
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(())) => {}
+                Err(e) => warn!("backoff timer failed: {}", e),
+            }
+        }
+
+        match self.inner.poll_ready() {
+            Ok(Async::Ready(())) => {
+                if let Some(ref mut backoff) = self.backoff {
+                    backoff.reset();
+                }
+                Ok(Async::Ready(()))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                if let Some(ref mut backoff) = self.backoff {
+                    let delay = backoff.next_delay(&mut rand::thread_rng());
+                    debug!("reconnect failed; backing off for {:?}", delay);
+                    self.timer = ReactorTimeout::new(delay, &self.handle)
+                        .map_err(|err| warn!("could not create backoff timer: {}", err))
+                        .ok();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::{future, Async, Future, Poll};
+    use futures::future::FutureResult;
+    use rand;
+    use tokio_core::reactor::Core;
+    use tower::Service;
+
+    use super::*;
+
+    /// A service whose `poll_ready` yields a scripted sequence of results.
+    struct Flaky(VecDeque<Result<(), ()>>);
+
+    impl Service for Flaky {
+        type Request = ();
+        type Response = ();
+        type Error = ();
+        type Future = FutureResult<(), ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            match self.0.pop_front() {
+                Some(Ok(())) | None => Ok(Async::Ready(())),
+                Some(Err(())) => Err(()),
+            }
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    fn no_jitter(min_ms: u64, max_ms: u64) -> BackoffConfig {
+        BackoffConfig::new(
+            Duration::from_millis(min_ms),
+            Duration::from_millis(max_ms),
+            0.0,
+        )
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let mut rng = rand::thread_rng();
+        let mut backoff = Backoff::new(no_jitter(100, 1_000));
+
+        let delays = (0..6)
+            .map(|_| backoff.next_delay(&mut rng))
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+            Duration::from_millis(800),
+            Duration::from_millis(1_000),
+            Duration::from_millis(1_000),
+        ]);
+    }
+
+    #[test]
+    fn delay_does_not_overflow() {
+        let mut rng = rand::thread_rng();
+        let mut backoff = Backoff::new(no_jitter(100, 10_000));
+        for _ in 0..100 {
+            backoff.next_delay(&mut rng);
+        }
+        assert_eq!(backoff.next_delay(&mut rng), Duration::from_millis(10_000));
+    }
+
+    #[test]
+    fn delay_resets() {
+        let mut rng = rand::thread_rng();
+        let mut backoff = Backoff::new(no_jitter(100, 10_000));
+        backoff.next_delay(&mut rng);
+        backoff.next_delay(&mut rng);
+        assert_eq!(backoff.next_delay(&mut rng), Duration::from_millis(400));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(&mut rng), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let mut rng = rand::thread_rng();
+        let config = BackoffConfig::new(
+            Duration::from_millis(100),
+            Duration::from_millis(100),
+            0.5,
+        );
+        let mut backoff = Backoff::new(config);
+        for _ in 0..100 {
+            let delay = backoff.next_delay(&mut rng);
+            assert!(delay >= Duration::from_millis(50), "{:?} too short", delay);
+            assert!(delay <= Duration::from_millis(100), "{:?} too long", delay);
+        }
+    }
+
+    #[test]
+    fn waits_after_failure_then_resets() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        core.run(future::lazy(|| {
+            let inner = Flaky(vec![Err(()), Err(()), Ok(())].into());
+            let mut svc = ReconnectBackoff::new(inner, Some(no_jitter(1, 1_000)), &handle);
+
+            // The first failure is returned, and a 1ms backoff begins.
+            assert!(svc.poll_ready().is_err());
+            assert_eq!(svc.poll_ready(), Ok(Async::NotReady));
+
+            // Once the backoff has elapsed, the inner service is polled
+            // again; it fails, doubling the delay.
+            thread::sleep(Duration::from_millis(5));
+            assert!(svc.poll_ready().is_err());
+            assert_eq!(svc.backoff.as_ref().unwrap().base_delay(), Duration::from_millis(4));
+
+            thread::sleep(Duration::from_millis(5));
+            assert_eq!(svc.poll_ready(), Ok(Async::Ready(())));
+            assert_eq!(svc.backoff.as_ref().unwrap().base_delay(), Duration::from_millis(1));
+
+            Ok::<_, ()>(())
+        })).unwrap();
+    }
+
+    #[test]
+    fn disabled_does_not_wait() {
+        let core = Core::new().unwrap();
+        future::lazy(|| {
+            let inner = Flaky(vec![Err(()), Ok(())].into());
+            let mut svc = ReconnectBackoff::new(inner, None, &core.handle());
+
+            assert!(svc.poll_ready().is_err());
+            assert_eq!(svc.poll_ready(), Ok(Async::Ready(())));
+
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
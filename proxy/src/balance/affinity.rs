@@ -0,0 +1,98 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Hashes the value of a request's affinity key, e.g. a session cookie, so
+/// that the value itself needn't be kept.
+pub fn hash_key(value: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Chooses the endpoint with which a request whose affinity key hashes to
+/// `key` has affinity, among the endpoints with a non-zero weight.
+///
+/// Endpoints are ranked by a hash of the key and their `id`, so a key keeps
+/// the same endpoint for as long as that endpoint is available. When it's
+/// removed, ejected, or drained, only the keys that had affinity with it move,
+/// each to its next-ranked endpoint.
+///
+/// Returns `None` if every endpoint is drained.
+pub fn choose<W, I>(key: u64, len: usize, weight: W, id: I) -> Option<usize>
+where
+    W: Fn(usize) -> u32,
+    I: Fn(usize) -> u64,
+{
+    (0..len)
+        .filter(|&i| weight(i) > 0)
+        .max_by_key(|&i| {
+            let mut hasher = DefaultHasher::new();
+            (key, id(i)).hash(&mut hasher);
+            hasher.finish()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chosen(ids: &[u64], key: &str) -> Option<u64> {
+        let key = hash_key(key.as_bytes());
+        choose(key, ids.len(), |_| 1, |i| ids[i]).map(|i| ids[i])
+    }
+
+    fn keys() -> Vec<String> {
+        (0..100).map(|i| format!("session-{}", i)).collect()
+    }
+
+    #[test]
+    fn same_key_chooses_same_endpoint() {
+        let ids = vec![1, 2, 3, 4];
+        for key in keys() {
+            let first = chosen(&ids, &key);
+            assert!(first.is_some());
+            assert_eq!(chosen(&ids, &key), first);
+            // The order in which the balancer holds endpoints doesn't matter.
+            let reversed = ids.iter().rev().cloned().collect::<Vec<_>>();
+            assert_eq!(chosen(&reversed, &key), first);
+        }
+    }
+
+    #[test]
+    fn keys_are_spread_over_endpoints() {
+        let ids = vec![1, 2, 3, 4];
+        let mut used = keys().iter()
+            .filter_map(|key| chosen(&ids, key))
+            .collect::<Vec<_>>();
+        used.sort();
+        used.dedup();
+        assert_eq!(used, ids);
+    }
+
+    #[test]
+    fn removing_an_endpoint_rehashes_only_its_keys() {
+        let ids = vec![1, 2, 3, 4];
+        let remaining = vec![1, 3, 4];
+        for key in keys() {
+            let before = chosen(&ids, &key).unwrap();
+            let after = chosen(&remaining, &key).unwrap();
+            if before == 2 {
+                assert_ne!(after, 2);
+            } else {
+                assert_eq!(after, before, "{} moved off a remaining endpoint", key);
+            }
+        }
+    }
+
+    #[test]
+    fn drained_endpoints_are_not_chosen() {
+        let ids = vec![1, 2, 3];
+        for key in keys() {
+            let key = hash_key(key.as_bytes());
+            let i = choose(key, ids.len(), |i| if i == 1 { 0 } else { 1 }, |i| ids[i]);
+            assert!(i.is_some());
+            assert_ne!(i, Some(1));
+        }
+        assert_eq!(choose(hash_key(b"key"), 2, |_| 0, |i| i as u64), None);
+    }
+}
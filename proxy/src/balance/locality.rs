@@ -0,0 +1,157 @@
+use std::sync::{Arc, RwLock};
+
+/// The zone, such as an availability zone, that an endpoint runs in, if
+/// discovery knows it.
+///
+/// Like `Tags`, the zone is shared between the discovery `Watch` that
+/// updates it and the `Weighted` service that is balanced over.
+#[derive(Clone, Debug, Default)]
+pub struct Zone(Arc<RwLock<Option<String>>>);
+
+/// Prefers the endpoints in the proxy's own zone.
+///
+/// Requests are balanced over only the local endpoints while at least
+/// `min_local_endpoints` of them are available, and spill over to the
+/// endpoints in other zones otherwise. An endpoint is unavailable while it
+/// isn't ready, e.g. because it's unhealthy, ejected, or at its concurrency
+/// limit, or while it's drained.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalityConfig {
+    zone: String,
+    min_local_endpoints: usize,
+}
+
+// ===== impl Zone =====
+
+impl Zone {
+    pub fn new(zone: Option<String>) -> Self {
+        Zone(Arc::new(RwLock::new(zone)))
+    }
+
+    pub fn set(&self, zone: Option<String>) {
+        *self.0.write().expect("zone lock poisoned") = zone;
+    }
+
+    pub fn is(&self, zone: &str) -> bool {
+        self.0.read().expect("zone lock poisoned").as_ref().map_or(false, |z| z == zone)
+    }
+}
+
+// ===== impl LocalityConfig =====
+
+impl LocalityConfig {
+    /// Prefers the endpoints in `zone` while any of them is available.
+    pub fn new(zone: String) -> Self {
+        LocalityConfig {
+            zone,
+            min_local_endpoints: 1,
+        }
+    }
+
+    /// Spills over to other zones once fewer than `min` local endpoints are
+    /// available.
+    ///
+    /// A minimum of zero is treated as one, since requests can't be balanced
+    /// over no endpoints.
+    pub fn with_min_local_endpoints(self, min: usize) -> Self {
+        Self {
+            min_local_endpoints: min,
+            ..self
+        }
+    }
+
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    /// Chooses an endpoint with `choose`, considering only the endpoints for
+    /// which `is_local` is true, if enough of them are available.
+    ///
+    /// Endpoints with a weight of zero aren't available.
+    pub fn choose<W, L, C>(&self, len: usize, weight: W, is_local: L, choose: C) -> usize
+    where
+        W: Fn(usize) -> u32,
+        L: Fn(usize) -> bool,
+        C: FnOnce(&Fn(usize) -> u32) -> usize,
+    {
+        let available = (0..len).filter(|&i| is_local(i) && weight(i) > 0).count();
+        let local = available >= self.min_local_endpoints.max(1);
+        if !local && available > 0 {
+            trace!("spilling over from {}: {} local endpoints available", self.zone, available);
+        }
+        choose(&|i| if local && !is_local(i) { 0 } else { weight(i) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use balance::weighted;
+
+    /// Returns the endpoints chosen, given the zone and weight of each.
+    fn chosen(config: &LocalityConfig, endpoints: &[(&str, u32)]) -> Vec<usize> {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let zones = endpoints.iter()
+            .map(|&(zone, _)| Zone::new(Some(zone.to_owned())))
+            .collect::<Vec<_>>();
+        let len = endpoints.len();
+        let mut chosen = (0..100)
+            .map(|_| config.choose(
+                len,
+                |i| endpoints[i].1,
+                |i| zones[i].is(config.zone()),
+                |w| weighted::choose(&mut rng, len, w),
+            ))
+            .collect::<Vec<_>>();
+        chosen.sort();
+        chosen.dedup();
+        chosen
+    }
+
+    #[test]
+    fn prefers_endpoints_in_the_local_zone() {
+        let config = LocalityConfig::new("us-east-1a".to_owned());
+        let endpoints = [
+            ("us-east-1b", 1),
+            ("us-east-1a", 1),
+            ("us-east-1c", 1),
+            ("us-east-1a", 1),
+        ];
+        assert_eq!(chosen(&config, &endpoints), vec![1, 3]);
+    }
+
+    #[test]
+    fn spills_over_when_local_capacity_is_exhausted() {
+        let config = LocalityConfig::new("us-east-1a".to_owned());
+        // The local endpoint has been drained.
+        let endpoints = [("us-east-1a", 0), ("us-east-1b", 1), ("us-east-1c", 1)];
+        assert_eq!(chosen(&config, &endpoints), vec![1, 2]);
+
+        // No endpoint is local, as when the unready local endpoints aren't
+        // offered for balancing at all.
+        let endpoints = [("us-east-1b", 1), ("us-east-1c", 1)];
+        assert_eq!(chosen(&config, &endpoints), vec![0, 1]);
+    }
+
+    #[test]
+    fn spills_over_below_the_minimum_local_endpoints() {
+        let config = LocalityConfig::new("us-east-1a".to_owned()).with_min_local_endpoints(2);
+        let endpoints = [("us-east-1a", 1), ("us-east-1b", 1), ("us-east-1a", 0)];
+        assert_eq!(chosen(&config, &endpoints), vec![0, 1]);
+
+        let endpoints = [("us-east-1a", 1), ("us-east-1b", 1), ("us-east-1a", 1)];
+        assert_eq!(chosen(&config, &endpoints), vec![0, 2]);
+    }
+
+    #[test]
+    fn zones_may_change() {
+        let zone = Zone::new(None);
+        let shared = zone.clone();
+        assert!(!shared.is("us-east-1a"));
+
+        zone.set(Some("us-east-1a".to_owned()));
+        assert!(shared.is("us-east-1a"));
+    }
+}
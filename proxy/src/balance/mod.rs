@@ -0,0 +1,271 @@
+//! Endpoint selection for load balancing across discovered services.
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use rand::Rng;
+use tower_balance::choose::{Choose, Replicas};
+
+mod affinity;
+mod locality;
+mod peak_ewma;
+mod selector;
+mod slow_start;
+mod tags;
+mod weighted;
+
+pub use self::affinity::hash_key;
+pub use self::locality::{LocalityConfig, Zone};
+pub use self::peak_ewma::{PeakEwma, PeakEwmaConfig, WithPeakEwma};
+pub use self::selector::{Endpoint, LeastLoaded, NewSelector, Random, RoundRobin, Selector};
+pub use self::slow_start::{SlowStart, SlowStartConfig, WithSlowStart};
+pub use self::tags::Tags;
+pub use self::weighted::{select, Weight, Weighted, DEFAULT_WEIGHT};
+
+/// Chooses the endpoint to which each request is dispatched.
+#[derive(Debug)]
+pub struct Chooser<R> {
+    rng: R,
+    least_loaded: bool,
+    choices: Choices,
+    tag: Option<String>,
+    locality: Option<LocalityConfig>,
+    selector: Option<Box<Selector>>,
+}
+
+/// Records which endpoint a `Chooser` chose, and lets the next choice be
+/// steered away from an endpoint, so that a request can be hedged to a
+/// different endpoint than the one it was first dispatched to, or towards the
+/// endpoint with which a request has affinity, or to which it's pinned.
+///
+/// Endpoints are identified by their `Weight`s.
+#[derive(Clone, Debug, Default)]
+pub struct Choices(Rc<RefCell<ChoicesInner>>);
+
+#[derive(Debug, Default)]
+struct ChoicesInner {
+    last: Option<Weight>,
+    avoid: Option<Weight>,
+    affinity: Option<u64>,
+    /// The address of the pinned endpoint, and whether it may be chosen if
+    /// it's unhealthy.
+    pinned: Option<(SocketAddr, bool)>,
+    pinned_chosen: bool,
+}
+
+// ===== impl Chooser =====
+
+impl<R: Rng> Chooser<R> {
+    /// Chooses endpoints at random, in proportion to their weights.
+    pub fn weighted_random(rng: R) -> Self {
+        Self {
+            rng,
+            least_loaded: false,
+            choices: Choices::default(),
+            tag: None,
+            locality: None,
+            selector: None,
+        }
+    }
+
+    /// Chooses the less-loaded of two endpoints picked by weight.
+    ///
+    /// Endpoints must be wrapped by a `WithPeakEwma` with a `PeakEwmaConfig`,
+    /// or else every endpoint is considered equally loaded.
+    pub fn peak_ewma(rng: R) -> Self {
+        Self {
+            rng,
+            least_loaded: true,
+            choices: Choices::default(),
+            tag: None,
+            locality: None,
+            selector: None,
+        }
+    }
+
+    /// Records each choice in `choices`.
+    pub fn with_choices(self, choices: Choices) -> Self {
+        Self { choices, ..self }
+    }
+
+    /// Chooses only endpoints tagged with `tag`, unless no endpoint is.
+    pub fn with_tag(self, tag: Option<String>) -> Self {
+        Self { tag, ..self }
+    }
+
+    /// Prefers the endpoints in the proxy's zone, if `locality` is provided.
+    ///
+    /// Among the endpoints with a requested tag, the local ones are
+    /// preferred.
+    pub fn with_locality(self, locality: Option<LocalityConfig>) -> Self {
+        Self { locality, ..self }
+    }
+
+    /// Chooses endpoints with `selector`, rather than by weight or load.
+    ///
+    /// Requests are still restricted by tag and locality, and still sent to
+    /// the endpoints they have affinity with. If `selector` selects no
+    /// endpoint, one is chosen by weight.
+    pub fn with_selector(self, selector: Box<Selector>) -> Self {
+        Self {
+            selector: Some(selector),
+            ..self
+        }
+    }
+}
+
+impl<K, S, R: Rng> Choose<K, SlowStart<PeakEwma<Weighted<S>>>> for Chooser<R> {
+    fn choose(&mut self, replicas: Replicas<K, SlowStart<PeakEwma<Weighted<S>>>>) -> usize {
+        let len = replicas.len();
+        let endpoint = |i: usize| replicas[i].get_ref().get_ref();
+        // A pinned endpoint bypasses every other consideration, as long as
+        // it's ready and, unless it may be unhealthy, has a weight.
+        if let Some((addr, allow_unhealthy)) = self.choices.pinned() {
+            let pinned = (0..len).find(|&i| endpoint(i).addr() == Some(&addr));
+            if let Some(i) = pinned {
+                if allow_unhealthy || replicas[i].effective_weight(endpoint(i).weight()) > 0 {
+                    self.choices.record_pinned(endpoint(i).shared_weight());
+                    return i;
+                }
+            }
+        }
+        // An avoided endpoint is treated as drained, so it is chosen only if
+        // every other endpoint is drained as well.
+        let choices = self.choices.clone();
+        let weight = |i: usize| {
+            if choices.is_avoided(endpoint(i).shared_weight()) {
+                0
+            } else {
+                replicas[i].effective_weight(endpoint(i).weight())
+            }
+        };
+        let tag = self.tag.as_ref();
+        let has_tag = |i: usize| match tag {
+            Some(tag) => endpoint(i).tags().contains(tag),
+            None => false,
+        };
+        // Endpoints are identified by their addresses, which are stable as
+        // they are removed and re-added, or by their positions if unknown.
+        let id = |i: usize| match endpoint(i).addr() {
+            Some(addr) => affinity::hash_key(addr.to_string().as_bytes()),
+            None => i as u64,
+        };
+        let key = self.choices.affinity();
+        let (rng, least_loaded, selector) =
+            (&mut self.rng, self.least_loaded, self.selector.as_mut());
+        let choose = |weight: &Fn(usize) -> u32| {
+            if let Some(i) = key.and_then(|key| affinity::choose(key, len, weight, &id)) {
+                return i;
+            }
+            if let Some(selector) = selector {
+                let endpoints = (0..len)
+                    .map(|i| Endpoint::new(
+                        endpoint(i).addr().cloned(),
+                        weight(i),
+                        replicas[i].get_ref().load(),
+                    ))
+                    .collect::<Vec<_>>();
+                match selector.select(&endpoints) {
+                    Some(i) if i < len => return i,
+                    Some(i) => warn!("selector chose endpoint {} of {}", i, len),
+                    None => {}
+                }
+                return weighted::choose(rng, len, weight);
+            }
+            if least_loaded {
+                peak_ewma::choose(rng, len, weight, |i| replicas[i].get_ref().load())
+            } else {
+                weighted::choose(rng, len, weight)
+            }
+        };
+        let locality = self.locality.as_ref();
+        let chosen = tags::choose(len, weight, has_tag, |weight| match locality {
+            Some(locality) => {
+                let is_local = |i: usize| endpoint(i).zone().is(locality.zone());
+                locality.choose(len, weight, is_local, choose)
+            }
+            None => choose(weight),
+        });
+        self.choices.record(endpoint(chosen).shared_weight());
+        chosen
+    }
+}
+
+// ===== impl Choices =====
+
+impl Choices {
+    /// Forgets the last choice, so that `take_last` returns only a choice
+    /// made after this.
+    pub fn forget(&self) {
+        self.0.borrow_mut().last = None;
+    }
+
+    /// Returns the endpoint that was last chosen, if one has been chosen
+    /// since `forget` was called.
+    pub fn take_last(&self) -> Option<Weight> {
+        self.0.borrow_mut().last.take()
+    }
+
+    /// Avoids `endpoint` in choices made until `stop_avoiding` is called.
+    pub fn avoid(&self, endpoint: Weight) {
+        self.0.borrow_mut().avoid = Some(endpoint);
+    }
+
+    pub fn stop_avoiding(&self) {
+        self.0.borrow_mut().avoid = None;
+    }
+
+    /// Returns true if `endpoint` is being avoided.
+    pub fn is_avoided(&self, endpoint: &Weight) -> bool {
+        self.0.borrow().avoid.as_ref().map_or(false, |a| a.same_endpoint(endpoint))
+    }
+
+    /// Chooses the endpoint with which requests whose affinity key hashes to
+    /// `key` have affinity, until `stop_preferring` is called.
+    pub fn prefer(&self, key: u64) {
+        self.0.borrow_mut().affinity = Some(key);
+    }
+
+    pub fn stop_preferring(&self) {
+        self.0.borrow_mut().affinity = None;
+    }
+
+    /// Returns the affinity key that choices should prefer, if there is one.
+    pub fn affinity(&self) -> Option<u64> {
+        self.0.borrow().affinity
+    }
+
+    /// Chooses only the endpoint at `addr` until `unpin` is called, even if
+    /// it's unhealthy if `allow_unhealthy` is true.
+    pub fn pin(&self, addr: SocketAddr, allow_unhealthy: bool) {
+        let mut inner = self.0.borrow_mut();
+        inner.pinned = Some((addr, allow_unhealthy));
+        inner.pinned_chosen = false;
+    }
+
+    /// Stops choosing the pinned endpoint, returning true if it was chosen
+    /// since `pin` was called.
+    pub fn unpin(&self) -> bool {
+        let mut inner = self.0.borrow_mut();
+        inner.pinned = None;
+        inner.pinned_chosen
+    }
+
+    /// Returns the address of the pinned endpoint, if there is one, and
+    /// whether it may be chosen if it's unhealthy.
+    pub fn pinned(&self) -> Option<(SocketAddr, bool)> {
+        self.0.borrow().pinned
+    }
+
+    /// Records that `endpoint` was chosen.
+    pub fn record(&self, endpoint: &Weight) {
+        self.0.borrow_mut().last = Some(endpoint.clone());
+    }
+
+    /// Records that the pinned endpoint, `endpoint`, was chosen.
+    pub fn record_pinned(&self, endpoint: &Weight) {
+        self.record(endpoint);
+        self.0.borrow_mut().pinned_chosen = true;
+    }
+}
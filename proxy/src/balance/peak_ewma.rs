@@ -0,0 +1,345 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use rand::Rng;
+use tower::Service;
+use tower_discover::{Change, Discover};
+
+use super::weighted;
+
+/// Settings for estimating endpoint load with a peak-EWMA of response
+/// latency.
+#[derive(Copy, Clone, Debug)]
+pub struct PeakEwmaConfig {
+    /// The latency assumed for an endpoint that has not yet responded.
+    default_rtt: Duration,
+    /// The time over which past latencies lose their influence.
+    decay: Duration,
+}
+
+/// A `Discover` that wraps each discovered service in `PeakEwma`.
+#[derive(Debug)]
+pub struct WithPeakEwma<D> {
+    inner: D,
+    config: Option<PeakEwmaConfig>,
+}
+
+/// Middleware that estimates an endpoint's load from its in-flight requests
+/// and the latency of its recent responses.
+///
+/// Latency is measured from dispatch until response headers are received --
+/// the same interval reported as response latency by `telemetry::sensor` --
+/// so that it reflects the endpoint itself rather than the streaming of
+/// response bodies.
+///
+/// If no `PeakEwmaConfig` is given, load is not tracked.
+#[derive(Debug)]
+pub struct PeakEwma<S> {
+    inner: S,
+    cost: Option<Rc<RefCell<Cost>>>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    pending: Option<Pending>,
+}
+
+/// The shared state used to compute an endpoint's load.
+#[derive(Debug)]
+struct Cost {
+    /// The peak-EWMA of response latencies, in nanoseconds.
+    rtt_ns: f64,
+    /// When `rtt_ns` was last updated.
+    updated_at: Instant,
+    /// The number of requests dispatched that have not yet been responded to.
+    pending: usize,
+    decay_ns: f64,
+}
+
+/// Tracks a single in-flight request.
+///
+/// When dropped, the request is no longer counted as pending.
+struct Pending {
+    cost: Rc<RefCell<Cost>>,
+    sent_at: Instant,
+}
+
+// ===== impl PeakEwmaConfig =====
+
+impl PeakEwmaConfig {
+    pub fn new(default_rtt: Duration, decay: Duration) -> Self {
+        Self { default_rtt, decay }
+    }
+}
+
+// ===== impl WithPeakEwma =====
+
+impl<D> WithPeakEwma<D> {
+    pub fn new(inner: D, config: Option<PeakEwmaConfig>) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<D: Discover> Discover for WithPeakEwma<D> {
+    type Key = D::Key;
+    type Request = D::Request;
+    type Response = D::Response;
+    type Error = D::Error;
+    type Service = PeakEwma<D::Service>;
+    type DiscoverError = D::DiscoverError;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(key, svc) => Change::Insert(key, PeakEwma::new(svc, self.config)),
+            Change::Remove(key) => Change::Remove(key),
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+// ===== impl PeakEwma =====
+
+impl<S> PeakEwma<S> {
+    fn new(inner: S, config: Option<PeakEwmaConfig>) -> Self {
+        let cost = config.map(|c| Rc::new(RefCell::new(Cost::new(c, Instant::now()))));
+        Self { inner, cost }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the current load estimate, which is comparable only to that of
+    /// other endpoints.
+    pub fn load(&self) -> f64 {
+        self.cost.as_ref()
+            .map(|cost| cost.borrow().load(Instant::now()))
+            .unwrap_or(0.0)
+    }
+}
+
+impl<S: Service> Service for PeakEwma<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let pending = self.cost.as_ref().map(|cost| {
+            cost.borrow_mut().pending += 1;
+            Pending {
+                cost: cost.clone(),
+                sent_at: Instant::now(),
+            }
+        });
+        ResponseFuture {
+            inner: self.inner.call(req),
+            pending,
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let poll = self.inner.poll();
+        match poll {
+            Ok(Async::NotReady) => {}
+            Ok(Async::Ready(_)) => {
+                if let Some(pending) = self.pending.take() {
+                    pending.complete(Instant::now());
+                }
+            }
+            // Failed requests are no longer pending, but their latency says
+            // little about how quickly the endpoint serves responses.
+            Err(_) => {
+                self.pending = None;
+            }
+        }
+        poll
+    }
+}
+
+// ===== impl Cost =====
+
+impl Cost {
+    fn new(config: PeakEwmaConfig, now: Instant) -> Self {
+        Cost {
+            rtt_ns: nanos(config.default_rtt),
+            updated_at: now,
+            pending: 0,
+            decay_ns: nanos(config.decay),
+        }
+    }
+
+    /// Returns the weight that the current estimate retains at `now`.
+    fn decay(&self, now: Instant) -> f64 {
+        let elapsed = nanos(now.duration_since(self.updated_at));
+        (-elapsed / self.decay_ns).exp()
+    }
+
+    /// Updates the estimate with a new latency sample.
+    ///
+    /// Latencies above the current estimate replace it outright, so that the
+    /// balancer reacts immediately to a slowing endpoint; lower latencies are
+    /// averaged in gradually.
+    fn observe(&mut self, rtt: Duration, now: Instant) {
+        let rtt = nanos(rtt);
+        if rtt > self.rtt_ns {
+            self.rtt_ns = rtt;
+        } else {
+            let decay = self.decay(now);
+            self.rtt_ns = self.rtt_ns * decay + rtt * (1.0 - decay);
+        }
+        self.updated_at = now;
+    }
+
+    /// The estimated cost of sending an endpoint another request.
+    ///
+    /// The latency estimate decays toward zero while an endpoint receives no
+    /// responses, so that an endpoint that was once slow is eventually tried
+    /// again rather than starved indefinitely.
+    fn load(&self, now: Instant) -> f64 {
+        self.rtt_ns * self.decay(now) * (self.pending + 1) as f64
+    }
+}
+
+// ===== impl Pending =====
+
+impl Pending {
+    fn complete(self, now: Instant) {
+        let rtt = now.duration_since(self.sent_at);
+        self.cost.borrow_mut().observe(rtt, now);
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.cost.borrow_mut().pending -= 1;
+    }
+}
+
+/// Chooses the less-loaded of two endpoints, each picked at random in
+/// proportion to its weight.
+///
+/// If all but one endpoint are drained, that endpoint is chosen; if all
+/// endpoints are drained, the two candidates are picked uniformly.
+pub fn choose<R, W, L>(rng: &mut R, len: usize, weight: W, load: L) -> usize
+where
+    R: Rng,
+    W: Fn(usize) -> u32,
+    L: Fn(usize) -> f64,
+{
+    debug_assert!(len >= 2, "power of two choices requires two endpoints");
+    let (a, b) = match weighted::select(rng, len, &weight) {
+        Some(a) => match weighted::select(rng, len, |i| if i == a { 0 } else { weight(i) }) {
+            Some(b) => (a, b),
+            None => return a,
+        },
+        None => {
+            let a = rng.gen_range(0, len);
+            let b = (a + rng.gen_range(1, len)) % len;
+            (a, b)
+        }
+    };
+
+    if load(b) < load(a) { b } else { a }
+}
+
+fn nanos(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1_000_000_000.0 + f64::from(d.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+
+    fn config() -> PeakEwmaConfig {
+        PeakEwmaConfig::new(Duration::from_millis(30), Duration::from_secs(10))
+    }
+
+    #[test]
+    fn cold_endpoint_assumes_default_rtt() {
+        let now = Instant::now();
+        let cost = Cost::new(config(), now);
+        assert_eq!(cost.load(now), nanos(Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn pending_requests_increase_load() {
+        let now = Instant::now();
+        let mut cost = Cost::new(config(), now);
+        let idle = cost.load(now);
+        cost.pending = 2;
+        assert_eq!(cost.load(now), idle * 3.0);
+    }
+
+    #[test]
+    fn peaks_are_observed_immediately() {
+        let now = Instant::now();
+        let mut cost = Cost::new(config(), now);
+        cost.observe(Duration::from_millis(500), now);
+        assert_eq!(cost.load(now), nanos(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn lower_latencies_are_averaged_in() {
+        let now = Instant::now();
+        let mut cost = Cost::new(config(), now);
+        let later = now + Duration::from_secs(1);
+        cost.observe(Duration::from_millis(10), later);
+        let load = cost.load(later);
+        assert!(load < nanos(Duration::from_millis(30)));
+        assert!(load > nanos(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn idle_endpoints_decay() {
+        let now = Instant::now();
+        let mut cost = Cost::new(config(), now);
+        cost.observe(Duration::from_millis(500), now);
+        let later = now + Duration::from_secs(30);
+        assert!(cost.load(later) < nanos(Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn faster_endpoint_receives_most_requests() {
+        let latencies = [Duration::from_millis(5), Duration::from_millis(50)];
+        let mut now = Instant::now();
+        let costs = vec![
+            RefCell::new(Cost::new(config(), now)),
+            RefCell::new(Cost::new(config(), now)),
+        ];
+
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut counts = [0; 2];
+        for _ in 0..1_000 {
+            let i = choose(&mut rng, 2, |_| 1, |i| costs[i].borrow().load(now));
+            counts[i] += 1;
+            now += Duration::from_millis(10);
+            costs[i].borrow_mut().observe(latencies[i], now);
+        }
+        assert!(counts[0] > 900, "fast endpoint got {} of 1000 requests", counts[0]);
+    }
+
+    #[test]
+    fn drained_endpoints_are_avoided_regardless_of_load() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..100 {
+            let i = choose(&mut rng, 3, |i| if i == 0 { 0 } else { 1 }, |i| i as f64);
+            assert_ne!(i, 0);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use super::weighted;
+
+/// Selects the endpoint to which a request is dispatched.
+///
+/// A `Chooser` with a selector still restricts requests to the endpoints
+/// with a requested tag, or in the proxy's zone, and still honors affinity.
+/// It offers the selector every endpoint, but those that shouldn't be chosen
+/// are offered with a weight of zero.
+pub trait Selector: fmt::Debug {
+    /// Returns the index of the endpoint in `endpoints` to dispatch a request
+    /// to, or `None` to let the `Chooser` choose one by weight instead.
+    ///
+    /// `endpoints` is never empty.
+    fn select(&mut self, endpoints: &[Endpoint]) -> Option<usize>;
+}
+
+/// Creates a `Selector` for each balancer.
+pub type NewSelector = Arc<Fn() -> Box<Selector> + Send + Sync>;
+
+/// An endpoint offered to a `Selector`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    addr: Option<SocketAddr>,
+    weight: u32,
+    load: f64,
+}
+
+/// Selects each available endpoint in turn.
+#[derive(Clone, Debug, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+/// Selects endpoints at random, in proportion to their weights.
+pub struct Random<R> {
+    rng: R,
+}
+
+/// Selects the available endpoint with the least load.
+///
+/// Load is only estimated if endpoints are wrapped by a `WithPeakEwma` with a
+/// `PeakEwmaConfig`. Among equally loaded endpoints, the first is selected.
+#[derive(Clone, Debug, Default)]
+pub struct LeastLoaded;
+
+// ===== impl Endpoint =====
+
+impl Endpoint {
+    pub fn new(addr: Option<SocketAddr>, weight: u32, load: f64) -> Self {
+        Endpoint { addr, weight, load }
+    }
+
+    /// The endpoint's address, if discovery knows it.
+    pub fn addr(&self) -> Option<&SocketAddr> {
+        self.addr.as_ref()
+    }
+
+    /// The endpoint's effective weight, as adjusted for slow start, or zero
+    /// if it shouldn't be chosen.
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// The endpoint's estimated load, which is comparable only to that of
+    /// other endpoints.
+    pub fn load(&self) -> f64 {
+        self.load
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.weight > 0
+    }
+}
+
+// ===== impl RoundRobin =====
+
+impl Selector for RoundRobin {
+    fn select(&mut self, endpoints: &[Endpoint]) -> Option<usize> {
+        let len = endpoints.len();
+        let selected = (0..len)
+            .map(|i| (self.next + i) % len)
+            .find(|&i| endpoints[i].is_available())?;
+        self.next = selected + 1;
+        Some(selected)
+    }
+}
+
+// ===== impl Random =====
+
+impl<R: Rng> Random<R> {
+    pub fn new(rng: R) -> Self {
+        Random { rng }
+    }
+}
+
+impl<R> fmt::Debug for Random<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Random").finish()
+    }
+}
+
+impl<R: Rng> Selector for Random<R> {
+    fn select(&mut self, endpoints: &[Endpoint]) -> Option<usize> {
+        weighted::select(&mut self.rng, endpoints.len(), |i| endpoints[i].weight)
+    }
+}
+
+// ===== impl LeastLoaded =====
+
+impl Selector for LeastLoaded {
+    fn select(&mut self, endpoints: &[Endpoint]) -> Option<usize> {
+        endpoints.iter()
+            .enumerate()
+            .filter(|&(_, e)| e.is_available())
+            .fold(None, |least: Option<(usize, &Endpoint)>, (i, e)| match least {
+                Some((_, l)) if l.load <= e.load => least,
+                _ => Some((i, e)),
+            })
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+
+    fn endpoints(weights_and_loads: &[(u32, f64)]) -> Vec<Endpoint> {
+        weights_and_loads.iter()
+            .map(|&(weight, load)| Endpoint::new(None, weight, load))
+            .collect()
+    }
+
+    fn selections<S: Selector>(selector: &mut S, endpoints: &[Endpoint], n: usize) -> Vec<usize> {
+        (0..n).map(|_| selector.select(endpoints).expect("selected")).collect()
+    }
+
+    #[test]
+    fn round_robin_skips_unavailable_endpoints() {
+        let mut rr = RoundRobin::default();
+        let eps = endpoints(&[(1, 0.0), (0, 0.0), (3, 0.0)]);
+        assert_eq!(selections(&mut rr, &eps, 4), vec![0, 2, 0, 2]);
+
+        // Selection continues from where it left off as endpoints change.
+        let eps = endpoints(&[(1, 0.0), (1, 0.0), (1, 0.0), (1, 0.0)]);
+        assert_eq!(selections(&mut rr, &eps, 3), vec![1, 2, 3]);
+
+        let eps = endpoints(&[(0, 0.0), (0, 0.0)]);
+        assert_eq!(rr.select(&eps), None);
+    }
+
+    #[test]
+    fn random_selects_in_proportion_to_weight() {
+        let mut random = Random::new(XorShiftRng::from_seed([1, 2, 3, 4]));
+        let eps = endpoints(&[(1, 0.0), (0, 0.0), (3, 0.0)]);
+        let selected = selections(&mut random, &eps, 4000);
+        let counts = (0..3)
+            .map(|i| selected.iter().filter(|&&s| s == i).count())
+            .collect::<Vec<_>>();
+        assert_eq!(counts[1], 0);
+        assert!(counts[0] > 800 && counts[0] < 1200, "counts={:?}", counts);
+
+        let eps = endpoints(&[(0, 0.0)]);
+        assert_eq!(random.select(&eps), None);
+    }
+
+    #[test]
+    fn least_loaded_selects_the_available_endpoint_with_least_load() {
+        let mut least = LeastLoaded;
+        let eps = endpoints(&[(1, 3.0), (0, 1.0), (1, 2.0), (1, 2.0)]);
+        assert_eq!(selections(&mut least, &eps, 2), vec![2, 2]);
+
+        let eps = endpoints(&[(0, 1.0)]);
+        assert_eq!(least.select(&eps), None);
+    }
+
+    #[test]
+    fn custom_selectors_may_be_used() {
+        /// Selects the endpoint with the highest port.
+        #[derive(Debug)]
+        struct HighestPort;
+
+        impl Selector for HighestPort {
+            fn select(&mut self, endpoints: &[Endpoint]) -> Option<usize> {
+                (0..endpoints.len()).max_by_key(|&i| endpoints[i].addr().map(|a| a.port()))
+            }
+        }
+
+        let eps = vec![
+            Endpoint::new(Some("10.1.1.1:8080".parse().unwrap()), 1, 0.0),
+            Endpoint::new(Some("10.1.1.2:9090".parse().unwrap()), 1, 0.0),
+            Endpoint::new(None, 1, 0.0),
+        ];
+        let mut selector: Box<Selector> = Box::new(HighestPort);
+        assert_eq!(selector.select(&eps), Some(1));
+    }
+}
@@ -0,0 +1,260 @@
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Poll};
+use tower::Service;
+use tower_discover::{Change, Discover};
+
+/// While slow-start is enabled, every weight is scaled by this much, so that
+/// even an endpoint with the smallest weight can be given a small fraction of
+/// its share.
+const RESOLUTION: u32 = 1_000;
+
+/// The smallest fraction of its weight, out of `RESOLUTION`, that a
+/// slow-starting endpoint is given.
+const MIN_RAMP: u32 = 10;
+
+/// How long an endpoint must be unavailable before it slow-starts again once
+/// it becomes ready. Briefer interruptions, e.g. while it is at its
+/// concurrency limit, don't give it time to go cold.
+const MIN_UNAVAILABLE_MS: u64 = 1_000;
+
+/// Settings for ramping up the traffic sent to endpoints as they are added.
+#[derive(Copy, Clone, Debug)]
+pub struct SlowStartConfig {
+    /// The time over which an endpoint's share ramps up to its full weight.
+    window: Duration,
+}
+
+/// A `Discover` that wraps each discovered service in `SlowStart`.
+#[derive(Debug)]
+pub struct WithSlowStart<D> {
+    inner: D,
+    config: Option<SlowStartConfig>,
+}
+
+/// Middleware that reduces the weight of an endpoint that has just been added
+/// to a balancer, ramping it linearly from near zero to its full weight over
+/// the slow-start window.
+///
+/// An endpoint that becomes ready after being unavailable for a while, e.g.
+/// because it was ejected as an outlier or failed its health checks, slow
+/// starts again.
+///
+/// If no `SlowStartConfig` is given, weights are not changed.
+#[derive(Debug)]
+pub struct SlowStart<S> {
+    inner: S,
+    ramp: Option<Ramp>,
+}
+
+#[derive(Debug)]
+struct Ramp {
+    window: Duration,
+    /// When the endpoint was added, or last became ready after being
+    /// unavailable.
+    since: Instant,
+    /// When the endpoint became unavailable, if it is not ready.
+    unavailable_since: Option<Instant>,
+}
+
+// ===== impl SlowStartConfig =====
+
+impl SlowStartConfig {
+    pub fn new(window: Duration) -> Self {
+        Self { window }
+    }
+}
+
+// ===== impl WithSlowStart =====
+
+impl<D> WithSlowStart<D> {
+    pub fn new(inner: D, config: Option<SlowStartConfig>) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<D: Discover> Discover for WithSlowStart<D> {
+    type Key = D::Key;
+    type Request = D::Request;
+    type Response = D::Response;
+    type Error = D::Error;
+    type Service = SlowStart<D::Service>;
+    type DiscoverError = D::DiscoverError;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(key, svc) => Change::Insert(key, SlowStart::new(svc, self.config)),
+            Change::Remove(key) => Change::Remove(key),
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+// ===== impl SlowStart =====
+
+impl<S> SlowStart<S> {
+    fn new(inner: S, config: Option<SlowStartConfig>) -> Self {
+        let ramp = config.map(|c| Ramp::new(c.window, Instant::now()));
+        Self { inner, ramp }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the share of traffic that an endpoint with `weight` is given,
+    /// which is comparable only to that of other endpoints.
+    pub fn effective_weight(&self, weight: u32) -> u32 {
+        match self.ramp {
+            Some(ref ramp) => weight.saturating_mul(ramp.fraction(Instant::now())),
+            None => weight,
+        }
+    }
+}
+
+impl<S: Service> Service for SlowStart<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let ready = self.inner.poll_ready();
+        if let Some(ref mut ramp) = self.ramp {
+            match ready {
+                Ok(Async::Ready(())) => ramp.available(Instant::now()),
+                _ => ramp.unavailable(Instant::now()),
+            }
+        }
+        ready
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+// ===== impl Ramp =====
+
+impl Ramp {
+    fn new(window: Duration, now: Instant) -> Self {
+        Ramp {
+            window,
+            since: now,
+            unavailable_since: None,
+        }
+    }
+
+    /// Returns the fraction of its weight, out of `RESOLUTION`, that the
+    /// endpoint is given at `now`.
+    fn fraction(&self, now: Instant) -> u32 {
+        let elapsed = if now > self.since {
+            now.duration_since(self.since)
+        } else {
+            Duration::from_secs(0)
+        };
+        if elapsed >= self.window {
+            return RESOLUTION;
+        }
+        let ramp = nanos(elapsed) / nanos(self.window) * f64::from(RESOLUTION);
+        cmp::max(ramp as u32, MIN_RAMP)
+    }
+
+    fn available(&mut self, now: Instant) {
+        if let Some(unavailable_since) = self.unavailable_since.take() {
+            let unavailable = now.duration_since(unavailable_since);
+            if unavailable >= Duration::from_millis(MIN_UNAVAILABLE_MS) {
+                self.since = now;
+            }
+        }
+    }
+
+    fn unavailable(&mut self, now: Instant) {
+        if self.unavailable_since.is_none() {
+            self.unavailable_since = Some(now);
+        }
+    }
+}
+
+fn nanos(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1_000_000_000.0 + f64::from(d.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use balance::weighted;
+
+    const REQUESTS: usize = 10_000;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    /// Returns how many of `REQUESTS` requests each endpoint is sent at `now`.
+    fn distribution(ramps: &[Ramp], now: Instant) -> Vec<usize> {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut counts = vec![0; ramps.len()];
+        for _ in 0..REQUESTS {
+            counts[weighted::choose(&mut rng, ramps.len(), |i| ramps[i].fraction(now))] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn new_endpoints_ramp_up_to_full_weight() {
+        let start = Instant::now();
+        let ramp = Ramp::new(secs(10), start);
+        assert_eq!(ramp.fraction(start), MIN_RAMP);
+        assert_eq!(ramp.fraction(start + secs(5)), RESOLUTION / 2);
+        assert_eq!(ramp.fraction(start + secs(10)), RESOLUTION);
+        assert_eq!(ramp.fraction(start + secs(60)), RESOLUTION);
+    }
+
+    #[test]
+    fn new_endpoints_receive_reduced_traffic() {
+        let start = Instant::now();
+        let established = Ramp::new(secs(10), start);
+        let added = Ramp::new(secs(10), start + secs(10));
+
+        // Shortly after being added, the endpoint gets about a tenth of the
+        // traffic that the established endpoint gets.
+        let counts = distribution(&[established, added], start + secs(11));
+        assert!(counts[1] > REQUESTS / 20, "got {} requests", counts[1]);
+        assert!(counts[1] < REQUESTS / 8, "got {} requests", counts[1]);
+
+        // Once the window has passed, they share traffic evenly.
+        let established = Ramp::new(secs(10), start);
+        let added = Ramp::new(secs(10), start + secs(10));
+        let counts = distribution(&[established, added], start + secs(20));
+        assert!(counts[1] > REQUESTS * 9 / 20, "got {} requests", counts[1]);
+        assert!(counts[1] < REQUESTS * 11 / 20, "got {} requests", counts[1]);
+    }
+
+    #[test]
+    fn unavailable_endpoints_slow_start_again() {
+        let start = Instant::now();
+        let mut ramp = Ramp::new(secs(10), start);
+        assert_eq!(ramp.fraction(start + secs(30)), RESOLUTION);
+
+        // Ejected, then re-admitted.
+        ramp.unavailable(start + secs(30));
+        ramp.available(start + secs(60));
+        assert_eq!(ramp.fraction(start + secs(60)), MIN_RAMP);
+        assert_eq!(ramp.fraction(start + secs(65)), RESOLUTION / 2);
+    }
+
+    #[test]
+    fn brief_interruptions_do_not_restart_slow_start() {
+        let start = Instant::now();
+        let mut ramp = Ramp::new(secs(10), start);
+
+        ramp.unavailable(start + secs(30));
+        ramp.unavailable(start + secs(30) + Duration::from_millis(100));
+        ramp.available(start + secs(30) + Duration::from_millis(200));
+        assert_eq!(ramp.fraction(start + secs(31)), RESOLUTION);
+    }
+}
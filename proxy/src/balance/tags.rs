@@ -0,0 +1,81 @@
+use std::sync::{Arc, RwLock};
+
+/// The subsets of a destination, such as versions, that an endpoint belongs
+/// to.
+///
+/// Like a `Weight`, tags are shared between the discovery `Watch` that
+/// updates them and the `Weighted` service that is balanced over, so they can
+/// change without the endpoint being re-bound.
+#[derive(Clone, Debug, Default)]
+pub struct Tags(Arc<RwLock<Vec<String>>>);
+
+// ===== impl Tags =====
+
+impl Tags {
+    pub fn new(tags: Vec<String>) -> Self {
+        Tags(Arc::new(RwLock::new(tags)))
+    }
+
+    pub fn set(&self, tags: Vec<String>) {
+        *self.0.write().expect("tags lock poisoned") = tags;
+    }
+
+    pub fn contains(&self, tag: &str) -> bool {
+        self.0.read().expect("tags lock poisoned").iter().any(|t| t == tag)
+    }
+}
+
+/// Chooses an endpoint with `choose`, considering only the endpoints for
+/// which `has_tag` is true, if there are any.
+///
+/// If no endpoint has the tag, every endpoint is considered, as though no
+/// tag had been requested.
+pub fn choose<W, H, C>(len: usize, weight: W, has_tag: H, choose: C) -> usize
+where
+    W: Fn(usize) -> u32,
+    H: Fn(usize) -> bool,
+    C: FnOnce(&Fn(usize) -> u32) -> usize,
+{
+    let pinned = (0..len).any(&has_tag);
+    choose(&|i| if pinned && !has_tag(i) { 0 } else { weight(i) })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use balance::weighted;
+
+    fn chosen(tagged: &[bool]) -> Vec<usize> {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let len = tagged.len();
+        let mut chosen = (0..100)
+            .map(|_| choose(len, |_| 1, |i| tagged[i], |w| weighted::choose(&mut rng, len, w)))
+            .collect::<Vec<_>>();
+        chosen.sort();
+        chosen.dedup();
+        chosen
+    }
+
+    #[test]
+    fn chooses_only_tagged_endpoints() {
+        assert_eq!(chosen(&[false, true, false, true]), vec![1, 3]);
+    }
+
+    #[test]
+    fn chooses_any_endpoint_if_none_is_tagged() {
+        assert_eq!(chosen(&[false, false, false]), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn tags_may_change() {
+        let tags = Tags::new(vec!["stable".to_owned()]);
+        let shared = tags.clone();
+        assert!(shared.contains("stable"));
+
+        tags.set(vec!["canary".to_owned()]);
+        assert!(!shared.contains("stable"));
+        assert!(shared.contains("canary"));
+    }
+}
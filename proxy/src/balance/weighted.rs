@@ -0,0 +1,227 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::Poll;
+use rand::Rng;
+use tower::Service;
+
+use super::{Tags, Zone};
+
+/// The weight of an endpoint for which discovery provides no weight, e.g.
+/// one resolved through DNS.
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+/// An endpoint's relative share of traffic.
+///
+/// The weight is shared between the discovery `Watch` that updates it and the
+/// `Weighted` service that is balanced over, so it can change without the
+/// endpoint being re-bound. A weight of zero drains the endpoint.
+#[derive(Clone, Debug)]
+pub struct Weight(Arc<AtomicUsize>);
+
+/// Middleware that associates a `Weight` and `Tags`, and the endpoint's
+/// address and `Zone` if they are known, with an endpoint's service.
+#[derive(Debug)]
+pub struct Weighted<S> {
+    inner: S,
+    weight: Weight,
+    tags: Tags,
+    zone: Zone,
+    addr: Option<SocketAddr>,
+}
+
+// ===== impl Weight =====
+
+impl Weight {
+    pub fn new(weight: u32) -> Self {
+        Weight(Arc::new(AtomicUsize::new(weight as usize)))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed) as u32
+    }
+
+    pub fn set(&self, weight: u32) {
+        self.0.store(weight as usize, Ordering::Relaxed);
+    }
+
+    /// Returns true if both are the weight of the same endpoint.
+    ///
+    /// Each endpoint's weight is created when the endpoint is bound, so it
+    /// identifies the endpoint.
+    pub fn same_endpoint(&self, other: &Weight) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Default for Weight {
+    fn default() -> Self {
+        Weight::new(DEFAULT_WEIGHT)
+    }
+}
+
+// ===== impl Weighted =====
+
+impl<S> Weighted<S> {
+    pub fn new(inner: S, weight: Weight) -> Self {
+        Self {
+            inner,
+            weight,
+            tags: Tags::default(),
+            zone: Zone::default(),
+            addr: None,
+        }
+    }
+
+    pub fn with_tags(self, tags: Tags) -> Self {
+        Self { tags, ..self }
+    }
+
+    pub fn with_zone(self, zone: Zone) -> Self {
+        Self { zone, ..self }
+    }
+
+    pub fn with_addr(self, addr: SocketAddr) -> Self {
+        Self {
+            addr: Some(addr),
+            ..self
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight.get()
+    }
+
+    pub fn shared_weight(&self) -> &Weight {
+        &self.weight
+    }
+
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    pub fn zone(&self) -> &Zone {
+        &self.zone
+    }
+
+    pub fn addr(&self) -> Option<&SocketAddr> {
+        self.addr.as_ref()
+    }
+}
+
+impl<S: Service> Service for Weighted<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+/// Chooses an endpoint at random, in proportion to its weight.
+///
+/// Drained endpoints (those with a weight of zero) are never chosen while any
+/// endpoint has a non-zero weight. If every endpoint is drained, one is chosen
+/// uniformly rather than failing the request.
+pub fn choose<R, F>(rng: &mut R, len: usize, weight: F) -> usize
+where
+    R: Rng,
+    F: Fn(usize) -> u32,
+{
+    select(rng, len, weight).unwrap_or_else(|| rng.gen_range(0, len))
+}
+
+/// Returns an index in `[0, len)`, chosen with probability proportional to
+/// `weight(index)`, or `None` if every weight is zero.
+pub fn select<R, F>(rng: &mut R, len: usize, weight: F) -> Option<usize>
+where
+    R: Rng,
+    F: Fn(usize) -> u32,
+{
+    debug_assert!(len > 0, "cannot select from no endpoints");
+    let total: u64 = (0..len).map(|i| u64::from(weight(i))).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = rng.gen_range(0, total);
+    for i in 0..len {
+        let w = u64::from(weight(i));
+        if point < w {
+            return Some(i);
+        }
+        point -= w;
+    }
+    unreachable!("point must be less than the total weight");
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+
+    const REQUESTS: usize = 10_000;
+
+    fn distribution(weights: &[Weight]) -> Vec<usize> {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut counts = vec![0; weights.len()];
+        for _ in 0..REQUESTS {
+            counts[choose(&mut rng, weights.len(), |i| weights[i].get())] += 1;
+        }
+        counts
+    }
+
+    fn assert_share(count: usize, weight: u32, total: u32) {
+        let expected = REQUESTS as f64 * f64::from(weight) / f64::from(total);
+        let tolerance = REQUESTS as f64 * 0.02;
+        assert!(
+            (count as f64 - expected).abs() <= tolerance,
+            "expected ~{} requests, got {}", expected, count
+        );
+    }
+
+    #[test]
+    fn traffic_approximates_weights() {
+        let weights = vec![Weight::new(1), Weight::new(3), Weight::new(6)];
+        let counts = distribution(&weights);
+        assert_share(counts[0], 1, 10);
+        assert_share(counts[1], 3, 10);
+        assert_share(counts[2], 6, 10);
+    }
+
+    #[test]
+    fn zero_weight_drains_endpoint() {
+        let weights = vec![Weight::new(0), Weight::new(1), Weight::new(1)];
+        let counts = distribution(&weights);
+        assert_eq!(counts[0], 0);
+        assert_share(counts[1], 1, 2);
+        assert_share(counts[2], 1, 2);
+    }
+
+    #[test]
+    fn all_drained_endpoints_share_traffic() {
+        let weights = vec![Weight::new(0), Weight::new(0)];
+        let counts = distribution(&weights);
+        assert_share(counts[0], 1, 2);
+        assert_share(counts[1], 1, 2);
+    }
+
+    #[test]
+    fn weight_updates_are_observed() {
+        let weights = vec![Weight::new(1), Weight::new(1)];
+        let svc = Weighted::new((), weights[1].clone());
+        weights[1].set(0);
+        assert_eq!(svc.weight(), 0);
+
+        let counts = distribution(&weights);
+        assert_eq!(counts, vec![REQUESTS, 0]);
+    }
+}
@@ -2,9 +2,9 @@ use std::error::Error;
 use std::fmt;
 use std::default::Default;
 use std::marker::PhantomData;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
 
 use futures::{Future, Poll};
 use futures::future::Map;
@@ -12,35 +12,184 @@ use http::{self, uri};
 use tokio_core::reactor::Handle;
 use tower;
 use tower_h2;
+use tower_in_flight_limit::InFlightLimit;
 use tower_reconnect::Reconnect;
 
+use backoff::{BackoffConfig, ReconnectBackoff};
+use breaker::{BreakerConfig, Breakers, CircuitBreaker};
+use cache::{Cache, CacheBody, ResponseCache};
 use conduit_proxy_controller_grpc;
 use conduit_proxy_router::Reuse;
 use control;
 use ctx;
+use body_limit::{LimitedBody, RequestBodyLimit, ResponseBodyLimit};
+use bound_endpoint::{AnnotateEndpoint, BoundEndpoint};
+use compress::{Compress, CompressBody};
+use deadline::{DeadlineBody, RequestTimeout};
+use dns;
+use drain;
+use fault::{Fault, FaultConfig};
+use graceful::Graceful;
+use header_limit::{HeaderLimits, ResponseHeaderLimit};
+use health::{HealthCheckConfig, HealthChecked, HealthChecks, HttpProbe};
+use outlier::{OutlierConfig, OutlierDetection};
+use pause::{Pausable, Pauses};
+use prewarm::Prewarm;
+use priority::Classify;
+use rate_limit::{RateLimit, RateLimitConfig, RateLimits};
+use replay::{BufferPolicy, BufferRequests, ReplayBody};
+use response_headers::{InjectHeaders, ResponseHeaders};
+use retry::{Retry, RetryBudget, RetryPolicy, RetryRefused};
+use rewrite_host::RewriteHost;
+use route::RoutePolicy;
 use telemetry::{self, sensor};
+use timeout::{HumanDuration, Timeout};
 use transparency::{self, HttpBody, h1};
-use transport;
+use transport::{self, tls};
+use transport::keepalive::KeepaliveConfig;
 
 /// Binds a `Service` from a `SocketAddr`.
 ///
 /// The returned `Service` buffers request until a connection is established.
+/// Services built from a `Bind` should not accept more than `buffer_capacity`
+/// requests at a time.
 ///
-/// # TODO
+/// Each connection attempt fails if it is not established within
+/// `connect_timeout`, and, if a `request_timeout` is configured, each request
+/// fails if it does not complete in time. Idempotent requests are retried
+/// according to the `RetryPolicy`, if one is configured, and requests that
+/// were refused by a connection that is going away are resent on a new
+/// connection if the policy considers them replayable. If a `BufferPolicy` is
+/// configured, the bodies of such requests are buffered so that they may be
+/// replayed. Responses with bodies
+/// larger than `max_response_bytes`, if configured, fail, as do requests with
+/// bodies larger than `max_request_bytes`, and responses with headers that
+/// exceed the `HeaderLimits`.
 ///
-/// Buffering is not bounded and no timeouts are applied.
+/// If a `ResponseCache` is configured, fresh responses to `GET` requests are
+/// served from it, and are shared by all services bound from the same `Bind`
+/// and its clones.
+///
+/// If a `concurrency_limit` is configured, each bound service dispatches at
+/// most that many requests at a time, and is not ready while at the limit.
+///
+/// If a `RateLimitConfig` is provided, requests in excess of its rates are
+/// rejected. Rate limits are shared by all services bound from the same
+/// `Bind` and its clones.
+///
+/// Connections are kept alive according to the `KeepaliveConfig`: TCP
+/// keepalive may be enabled, and HTTP/2 connections may be PINGed, so that
+/// connections to peers that have silently gone away are closed and
+/// re-established.
+///
+/// If a TLS configuration is provided, connections are encrypted whenever the
+/// name of the server being connected to is known. If a client identity is
+/// also provided, it is presented to servers that request one.
+///
+/// Services bound for discovered endpoints are wrapped in circuit breakers,
+/// if a `BreakerConfig` is provided. Breaker state is shared by all services
+/// bound to the same address.
+///
+/// If a `HealthCheckConfig` is provided, discovered endpoints are probed
+/// periodically, and are not dispatched requests while unhealthy. Each
+/// address is probed once, however many services are bound to it.
+///
+/// If an `OutlierConfig` is provided, discovered endpoints whose requests
+/// fail repeatedly are ejected from load balancing for a time.
+///
+/// If a `DiscoveryState` is provided, the status of each discovered endpoint
+/// is reported to it, for the admin API.
+///
+/// When a discovered endpoint is removed, its in-flight requests are given
+/// `drain_grace_period` to complete before they fail.
+///
+/// HTTP/1 requests without an authority are handled according to the
+/// `MissingHostPolicy`.
+///
+/// Proxied requests are assigned IDs by a `RequestIdGen`, which is shared by
+/// all clones of the `Bind`.
 pub struct Bind<C, B> {
     ctx: C,
     sensors: telemetry::Sensors,
+    /// Replace `sensors` for endpoints bound for one direction of traffic.
+    inbound_sensors: Option<telemetry::Sensors>,
+    outbound_sensors: Option<telemetry::Sensors>,
     executor: Handle,
-    req_ids: Arc<AtomicUsize>,
+    req_ids: sensor::SharedRequestIdGen,
+    backoff: Option<BackoffConfig>,
+    buffer_capacity: usize,
+    concurrency_limit: Option<usize>,
+    rate_limits: RateLimits,
+    connect_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    prewarm: bool,
+    request_timeout: Option<Duration>,
+    max_response_bytes: Option<u64>,
+    max_request_bytes: Option<u64>,
+    header_limits: HeaderLimits,
+    classify: Classify,
+    gzip_responses: bool,
+    response_headers: ResponseHeaders,
+    response_cache: Option<ResponseCache>,
+    retry_policy: Option<RetryPolicy>,
+    retry_budget: Option<RetryBudget>,
+    rewrite_host: Option<http::uri::Authority>,
+    faults: Option<FaultConfig>,
+    buffer_policy: Option<BufferPolicy>,
+    breaker: Option<BreakerConfig>,
+    breakers: Breakers,
+    health_check: Option<HealthCheckConfig>,
+    health_checks: HealthChecks,
+    outlier: Option<OutlierConfig>,
+    discovery_state: Option<control::DiscoveryState>,
+    pauses: Pauses,
+    drain_grace_period: Duration,
+    missing_host_policy: MissingHostPolicy,
+    protocol_policy: ProtocolPolicy,
+    h1_settings: transparency::H1Settings,
+    http1_max_conns: Option<usize>,
+    conn_limits: transparency::ConnLimits,
+    connect_concurrency: Option<usize>,
+    connect_limits: transport::ConnectLimits,
+    max_connections: Option<transport::MaxConnections>,
+    h2_settings: transparency::H2Settings,
+    keepalive: KeepaliveConfig,
+    socket_buffers: transport::SocketBuffers,
+    socket_latency: transport::SocketLatency,
+    tls: Option<tls::ClientConfig>,
+    client_identity: Option<tls::ClientIdentity>,
+    connect_proxy: Option<transport::ConnectProxy>,
     _p: PhantomData<B>,
 }
 
+/// Builds a `Bind` from options that are validated together.
+///
+/// Unlike configuring a `Bind` directly, which accepts each option on its
+/// own, `build` fails if the options contradict one another.
+#[derive(Clone)]
+pub struct BindBuilder<C> {
+    ctx: C,
+    sensors: telemetry::Sensors,
+    executor: Handle,
+    backoff: Option<BackoffConfig>,
+    buffer_capacity: usize,
+    connect_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    rate_limits: RateLimits,
+    drain_grace_period: Duration,
+    missing_host_policy: MissingHostPolicy,
+    protocol_policy: ProtocolPolicy,
+    h1_settings: transparency::H1Settings,
+    h2_settings: transparency::H2Settings,
+    keepalive: KeepaliveConfig,
+}
+
 /// Binds a `Service` from a `SocketAddr` for a pre-determined protocol.
 pub struct BindProtocol<C, B> {
     bind: Bind<C, B>,
     protocol: Protocol,
+    tls_name: Option<dns::Name>,
 }
 
 /// Protocol portion of the `Recognize` key for a request.
@@ -51,6 +200,15 @@ pub struct BindProtocol<C, B> {
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Protocol {
     Http1(Host),
+    /// An HTTP/1.1 request asking to upgrade its connection to another
+    /// protocol, such as a WebSocket handshake.
+    ///
+    /// hyper can't switch protocols, so the server tunnels a connection that
+    /// opens with an upgrade before its requests are routed, and refuses
+    /// upgrades later in a connection with `501 Not Implemented` (see
+    /// `transparency::Server`). A service bound for an upgrade forwards its
+    /// handshake on a dedicated connection.
+    Http1Upgrade(Host),
     Http2
 }
 
@@ -60,6 +218,34 @@ pub enum Host {
     NoAuthority,
 }
 
+/// How to handle an HTTP/1 request that has neither an authority in its URI
+/// nor a `Host` header, as may be sent by HTTP/1.0 clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingHostPolicy {
+    /// Fails the request with a `400 Bad Request`.
+    Reject,
+    /// Uses the request's original destination, according to
+    /// `SO_ORIGINAL_DST`, as its authority.
+    ///
+    /// Requests without an original destination are rejected.
+    OriginalDst,
+}
+
+/// Overrides the protocol with which requests are sent to their destinations,
+/// regardless of the protocol with which they were received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolPolicy {
+    /// Sends requests with the protocol with which they were received.
+    Detect,
+    /// Sends HTTP/2 requests over HTTP/1.1.
+    ForceHttp1,
+    /// Sends HTTP/1 requests over HTTP/2 without TLS, with prior knowledge.
+    ///
+    /// Requests asking to upgrade their connections are still sent over
+    /// HTTP/1.
+    ForceHttp2,
+}
+
 /// Rewrites HTTP/1.x requests so that their URIs are in a canonical form.
 ///
 /// The following transformations are applied:
@@ -73,39 +259,208 @@ pub struct NormalizeUri<S> {
     inner: S
 }
 
-pub type Service<B> = Reconnect<NormalizeUri<NewHttp<B>>>;
+pub type Service<B> = AnnotateEndpoint<RateLimit<InFlightLimit<BufferRequests<RetryRefused<
+    Retry<Prewarm<ReconnectBackoff<Reconnect<RequestTimeout<InjectHeaders<Compress<Cache<
+        ResponseBodyLimit<ResponseHeaderLimit<RequestBodyLimit<
+            NormalizeUri<NewHttp<LimitedBody<ReplayBody<B>>>>
+        >>>
+    >>>>>>>>
+>>>>>;
+
+/// A `Service` bound for an endpoint found through service discovery.
+pub type DiscoveredService<B> =
+    Pausable<OutlierDetection<HealthChecked<CircuitBreaker<Graceful<Service<B>>>>>>;
 
-pub type NewHttp<B> = sensor::NewHttp<Client<B>, B, HttpBody>;
+pub type NewHttp<B> = sensor::NewHttp<Fault<RewriteHost<Client<B>>>, B, HttpBody>;
+
+pub type HttpResponse = http::Response<
+    DeadlineBody<CompressBody<CacheBody<LimitedBody<sensor::http::ResponseBody<HttpBody>>>>>
+>;
 
-pub type HttpResponse = http::Response<sensor::http::ResponseBody<HttpBody>>;
+type SensorResponse = http::Response<sensor::http::ResponseBody<HttpBody>>;
 
 pub type HttpRequest<B> = http::Request<sensor::http::RequestBody<B>>;
 
 pub type Client<B> = transparency::Client<
-    sensor::Connect<transport::Connect>,
+    transport::LimitConnect<
+        transport::LimitConnections<sensor::Connect<Timeout<transport::Connect>>>
+    >,
     B,
 >;
 
+/// Probes the health of a discovered endpoint.
+///
+/// Probes are sent without telemetry, so that they are not reported as
+/// proxied requests.
+type Probe<B> = HttpProbe<Reconnect<transparency::Client<Timeout<transport::Connect>, B>>>;
+
+/// The default maximum number of requests that may be buffered by a service.
+const DEFAULT_BUFFER_CAPACITY: usize = 10_000;
+
+/// The default maximum amount of time to wait for a connection to be
+/// established.
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// The default amount of time that a removed endpoint's in-flight requests
+/// may take to receive a response.
+const DEFAULT_DRAIN_GRACE_PERIOD_SECS: u64 = 10;
+
+/// Why an endpoint could not be bound.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BindError {
+    /// The endpoint's address can't be connected to.
+    InvalidAddress(SocketAddr),
+    /// TLS is configured, but the endpoint's name is not a valid TLS server
+    /// name.
+    TlsConfig(dns::Name),
+    /// TLS is configured, but the host the request was addressed to could
+    /// not be resolved to a DNS name to validate the endpoint against.
+    ResolutionFailed(String),
+}
+
+/// Why a `BindBuilder` could not build a `Bind`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BindConfigError {
+    /// The buffer capacity is zero, so every request would be rejected.
+    ZeroBufferCapacity,
+    /// The connect timeout is zero, so every connection attempt would fail.
+    ZeroConnectTimeout,
+    /// The connect timeout is longer than the request timeout, so requests
+    /// would time out before a slow connection attempt does.
+    ConnectTimeoutExceedsRequestTimeout {
+        connect_timeout: Duration,
+        request_timeout: Duration,
+    },
+}
+
+/// Why the task that buffers requests to a target could not be spawned.
+#[derive(Debug)]
+pub struct BufferSpawnError {
+    inbound: bool,
+    /// The authority or address to which requests would have been buffered.
+    target: String,
+    cause: Box<Error + Send + Sync>,
+}
+
+/// The executor refused to run a buffer's task, as when it has shut down.
+///
+/// `tower_buffer::SpawnError` carries only the service that couldn't be
+/// buffered, so this is the cause of a `BufferSpawnError` built from one.
 #[derive(Copy, Clone, Debug)]
-pub enum BufferSpawnError {
-    Inbound,
-    Outbound,
+pub struct ExecutorRefused;
+
+impl BufferSpawnError {
+    pub fn inbound<E>(target: String, cause: E) -> Self
+    where
+        E: Into<Box<Error + Send + Sync>>,
+    {
+        BufferSpawnError {
+            inbound: true,
+            target,
+            cause: cause.into(),
+        }
+    }
+
+    pub fn outbound<E>(target: String, cause: E) -> Self
+    where
+        E: Into<Box<Error + Send + Sync>>,
+    {
+        BufferSpawnError {
+            inbound: false,
+            target,
+            cause: cause.into(),
+        }
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
 }
 
 impl fmt::Display for BufferSpawnError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.pad(self.description())
+        write!(f, "{} to {}: {}", self.description(), self.target, self.cause)
     }
 }
 
 impl Error for BufferSpawnError {
 
+    fn description(&self) -> &str {
+        if self.inbound {
+            "error spawning inbound buffer task"
+        } else {
+            "error spawning outbound buffer task"
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        Some(&*self.cause)
+    }
+}
+
+impl fmt::Display for ExecutorRefused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.description())
+    }
+}
+
+impl Error for ExecutorRefused {
+    fn description(&self) -> &str {
+        "executor refused the task"
+    }
+
+    fn cause(&self) -> Option<&Error> { None }
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindError::InvalidAddress(addr) =>
+                write!(f, "invalid endpoint address: {}", addr),
+            BindError::TlsConfig(ref name) =>
+                write!(f, "invalid TLS server name: {}", name),
+            BindError::ResolutionFailed(ref host) =>
+                write!(f, "could not resolve TLS server name for host: {}", host),
+        }
+    }
+}
+
+impl Error for BindError {
+    fn description(&self) -> &str {
+        match *self {
+            BindError::InvalidAddress(_) => "invalid endpoint address",
+            BindError::TlsConfig(_) => "invalid TLS server name",
+            BindError::ResolutionFailed(_) => "could not resolve TLS server name",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> { None }
+}
+
+impl fmt::Display for BindConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindConfigError::ConnectTimeoutExceedsRequestTimeout {
+                connect_timeout,
+                request_timeout,
+            } => write!(
+                f,
+                "connect timeout ({}) exceeds request timeout ({})",
+                HumanDuration(connect_timeout),
+                HumanDuration(request_timeout),
+            ),
+            _ => f.pad(self.description()),
+        }
+    }
+}
+
+impl Error for BindConfigError {
     fn description(&self) -> &str {
         match *self {
-            BufferSpawnError::Inbound =>
-                "error spawning inbound buffer task",
-            BufferSpawnError::Outbound =>
-                "error spawning outbound buffer task",
+            BindConfigError::ZeroBufferCapacity => "buffer capacity must not be zero",
+            BindConfigError::ZeroConnectTimeout => "connect timeout must not be zero",
+            BindConfigError::ConnectTimeoutExceedsRequestTimeout { .. } =>
+                "connect timeout exceeds request timeout",
         }
     }
 
@@ -118,7 +473,52 @@ impl<B> Bind<(), B> {
             executor,
             ctx: (),
             sensors: telemetry::Sensors::null(),
-            req_ids: Default::default(),
+            inbound_sensors: None,
+            outbound_sensors: None,
+            req_ids: Arc::new(sensor::Sequential::default()),
+            backoff: None,
+            buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            concurrency_limit: None,
+            rate_limits: RateLimits::default(),
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            idle_timeout: None,
+            prewarm: false,
+            request_timeout: None,
+            max_response_bytes: None,
+            max_request_bytes: None,
+            header_limits: HeaderLimits::default(),
+            classify: Classify::default(),
+            gzip_responses: false,
+            response_headers: ResponseHeaders::default(),
+            response_cache: None,
+            retry_policy: None,
+            retry_budget: None,
+            rewrite_host: None,
+            faults: None,
+            buffer_policy: None,
+            breaker: None,
+            breakers: Breakers::default(),
+            health_check: None,
+            health_checks: HealthChecks::default(),
+            outlier: None,
+            discovery_state: None,
+            pauses: Pauses::default(),
+            drain_grace_period: Duration::from_secs(DEFAULT_DRAIN_GRACE_PERIOD_SECS),
+            missing_host_policy: MissingHostPolicy::OriginalDst,
+            protocol_policy: ProtocolPolicy::Detect,
+            h1_settings: transparency::H1Settings::default(),
+            http1_max_conns: None,
+            conn_limits: transparency::ConnLimits::default(),
+            connect_concurrency: None,
+            connect_limits: transport::ConnectLimits::default(),
+            max_connections: None,
+            h2_settings: transparency::H2Settings::default(),
+            keepalive: KeepaliveConfig::default(),
+            socket_buffers: transport::SocketBuffers::default(),
+            socket_latency: transport::SocketLatency::default(),
+            tls: None,
+            client_identity: None,
+            connect_proxy: None,
             _p: PhantomData,
         }
     }
@@ -134,8 +534,55 @@ impl<B> Bind<(), B> {
         Bind {
             ctx,
             sensors: self.sensors,
+            inbound_sensors: self.inbound_sensors,
+            outbound_sensors: self.outbound_sensors,
             executor: self.executor,
             req_ids: self.req_ids,
+            backoff: self.backoff,
+            buffer_capacity: self.buffer_capacity,
+            concurrency_limit: self.concurrency_limit,
+            rate_limits: self.rate_limits,
+            connect_timeout: self.connect_timeout,
+            idle_timeout: self.idle_timeout,
+            prewarm: self.prewarm,
+            request_timeout: self.request_timeout,
+            max_response_bytes: self.max_response_bytes,
+            max_request_bytes: self.max_request_bytes,
+            header_limits: self.header_limits,
+            classify: self.classify.clone(),
+            gzip_responses: self.gzip_responses,
+            response_headers: self.response_headers,
+            response_cache: self.response_cache,
+            // Each context has its own retry budget, so that, e.g., retries
+            // of inbound requests don't exhaust the outbound budget.
+            retry_budget: self.retry_policy.as_ref().map(RetryBudget::new),
+            retry_policy: self.retry_policy,
+            rewrite_host: self.rewrite_host,
+            faults: self.faults,
+            buffer_policy: self.buffer_policy,
+            breaker: self.breaker,
+            breakers: self.breakers,
+            health_check: self.health_check,
+            health_checks: self.health_checks,
+            outlier: self.outlier,
+            discovery_state: self.discovery_state,
+            pauses: self.pauses,
+            drain_grace_period: self.drain_grace_period,
+            missing_host_policy: self.missing_host_policy,
+            protocol_policy: self.protocol_policy,
+            h1_settings: self.h1_settings,
+            http1_max_conns: self.http1_max_conns,
+            conn_limits: self.conn_limits,
+            connect_concurrency: self.connect_concurrency,
+            connect_limits: self.connect_limits,
+            max_connections: self.max_connections,
+            h2_settings: self.h2_settings,
+            keepalive: self.keepalive,
+            socket_buffers: self.socket_buffers,
+            socket_latency: self.socket_latency,
+            tls: self.tls,
+            client_identity: self.client_identity,
+            connect_proxy: self.connect_proxy,
             _p: PhantomData,
         }
     }
@@ -146,8 +593,53 @@ impl<C: Clone, B> Clone for Bind<C, B> {
         Self {
             ctx: self.ctx.clone(),
             sensors: self.sensors.clone(),
+            inbound_sensors: self.inbound_sensors.clone(),
+            outbound_sensors: self.outbound_sensors.clone(),
             executor: self.executor.clone(),
             req_ids: self.req_ids.clone(),
+            backoff: self.backoff,
+            buffer_capacity: self.buffer_capacity,
+            concurrency_limit: self.concurrency_limit,
+            rate_limits: self.rate_limits.clone(),
+            connect_timeout: self.connect_timeout,
+            idle_timeout: self.idle_timeout,
+            prewarm: self.prewarm,
+            request_timeout: self.request_timeout,
+            max_response_bytes: self.max_response_bytes,
+            max_request_bytes: self.max_request_bytes,
+            header_limits: self.header_limits,
+            classify: self.classify.clone(),
+            gzip_responses: self.gzip_responses,
+            response_headers: self.response_headers.clone(),
+            response_cache: self.response_cache.clone(),
+            retry_policy: self.retry_policy.clone(),
+            retry_budget: self.retry_budget.clone(),
+            rewrite_host: self.rewrite_host.clone(),
+            faults: self.faults.clone(),
+            buffer_policy: self.buffer_policy,
+            breaker: self.breaker,
+            breakers: self.breakers.clone(),
+            health_check: self.health_check.clone(),
+            health_checks: self.health_checks.clone(),
+            outlier: self.outlier,
+            discovery_state: self.discovery_state.clone(),
+            pauses: self.pauses.clone(),
+            drain_grace_period: self.drain_grace_period,
+            missing_host_policy: self.missing_host_policy,
+            protocol_policy: self.protocol_policy,
+            h1_settings: self.h1_settings,
+            http1_max_conns: self.http1_max_conns,
+            conn_limits: self.conn_limits.clone(),
+            connect_concurrency: self.connect_concurrency,
+            connect_limits: self.connect_limits.clone(),
+            max_connections: self.max_connections.clone(),
+            h2_settings: self.h2_settings,
+            keepalive: self.keepalive,
+            socket_buffers: self.socket_buffers,
+            socket_latency: self.socket_latency,
+            tls: self.tls.clone(),
+            client_identity: self.client_identity.clone(),
+            connect_proxy: self.connect_proxy.clone(),
             _p: PhantomData,
         }
     }
@@ -156,187 +648,2582 @@ impl<C: Clone, B> Clone for Bind<C, B> {
 
 impl<C, B> Bind<C, B> {
 
-    // pub fn ctx(&self) -> &C {
-    //     &self.ctx
-    // }
-
-    pub fn executor(&self) -> &Handle {
-        &self.executor
+    /// Records the telemetry of endpoints bound for inbound traffic with
+    /// `sensors`, rather than with the sensors that `Bind` was created with.
+    ///
+    /// `sensors` should be configured from the same `Sensors`, so that both
+    /// directions' telemetry is reported together.
+    pub fn with_inbound_sensors(self, sensors: telemetry::Sensors) -> Self {
+        Self {
+            inbound_sensors: Some(sensors),
+            ..self
+        }
     }
 
-    // pub fn req_ids(&self) -> &Arc<AtomicUsize> {
-    //     &self.req_ids
-    // }
+    /// Records the telemetry of endpoints bound for outbound traffic with
+    /// `sensors`, rather than with the sensors that `Bind` was created with.
+    pub fn with_outbound_sensors(self, sensors: telemetry::Sensors) -> Self {
+        Self {
+            outbound_sensors: Some(sensors),
+            ..self
+        }
+    }
 
-    // pub fn sensors(&self) -> &telemetry::Sensors {
-    //     &self.sensors
-    // }
+    /// Assigns IDs to proxied requests with `req_ids`.
+    ///
+    /// By default, requests are numbered sequentially.
+    pub fn with_request_ids(self, req_ids: sensor::SharedRequestIdGen) -> Self {
+        Self {
+            req_ids,
+            ..self
+        }
+    }
 
-}
+    /// Waits between reconnection attempts, backing off exponentially as
+    /// consecutive attempts fail.
+    ///
+    /// By default, reconnects are attempted immediately.
+    pub fn with_backoff(self, backoff: BackoffConfig) -> Self {
+        Self {
+            backoff: Some(backoff),
+            ..self
+        }
+    }
 
-impl<B> Bind<Arc<ctx::Proxy>, B>
-where
-    B: tower_h2::Body + 'static,
-{
-    pub fn bind_service(&self, addr: &SocketAddr, protocol: &Protocol) -> Service<B> {
-        trace!("bind_service addr={}, protocol={:?}", addr, protocol);
-        let client_ctx = ctx::transport::Client::new(
-            &self.ctx,
-            addr,
-            conduit_proxy_controller_grpc::common::Protocol::Http,
-        );
+    /// Limits the number of requests that may be buffered by each service.
+    ///
+    /// Requests in excess of this limit fail immediately rather than waiting
+    /// for a connection.
+    pub fn with_buffer_capacity(self, buffer_capacity: usize) -> Self {
+        Self {
+            buffer_capacity,
+            ..self
+        }
+    }
 
-        // Map a socket address to a connection.
-        let connect = self.sensors.connect(
-            transport::Connect::new(*addr, &self.executor),
-            &client_ctx
-        );
+    /// Limits the number of requests that each bound service dispatches at a
+    /// time.
+    ///
+    /// Requests in excess of this limit wait in the buffer in front of the
+    /// service, which itself holds at most `buffer_capacity` requests.
+    pub fn with_concurrency_limit(self, concurrency_limit: usize) -> Self {
+        Self {
+            concurrency_limit: Some(concurrency_limit),
+            ..self
+        }
+    }
 
-        let client = transparency::Client::new(
-            protocol,
-            connect,
-            self.executor.clone(),
-        );
+    /// Limits the amount of time to wait for each connection to be established.
+    ///
+    /// The timeout applies to each attempt individually; when it elapses, the
+    /// attempt fails and the service reconnects as it would after any other
+    /// connection error.
+    pub fn with_connect_timeout(self, connect_timeout: Duration) -> Self {
+        Self {
+            connect_timeout,
+            ..self
+        }
+    }
 
-        let sensors = self.sensors.http(
-            self.req_ids.clone(),
-            client,
-            &client_ctx
-        );
+    /// Limits the number of connection attempts in flight to each endpoint
+    /// to `max`.
+    ///
+    /// Attempts beyond the limit wait for an earlier attempt to complete, so
+    /// that requests buffered for an endpoint that has just become available
+    /// don't all connect to it at once. The limit is shared by every service
+    /// bound to an endpoint. The time spent waiting isn't counted against
+    /// the connect timeout.
+    pub fn with_connect_concurrency(self, max: usize) -> Self {
+        Self {
+            connect_concurrency: Some(max),
+            ..self
+        }
+    }
 
-        // Rewrite the HTTP/1 URI, if the authorities in the Host header
-        // and request URI are not in agreement, or are not present.
-        let proxy = NormalizeUri::new(sensors);
+    /// Limits the number of connections open to every endpoint, together,
+    /// to `max_connections`.
+    ///
+    /// The limit is shared by every service bound by this `Bind` and its
+    /// clones, including those bound by `with_ctx`. Health check probes
+    /// aren't counted.
+    pub fn with_max_connections(self, max_connections: transport::MaxConnections) -> Self {
+        Self {
+            max_connections: Some(max_connections),
+            ..self
+        }
+    }
 
-        // Automatically perform reconnects if the connection fails.
-        //
-        // TODO: Add some sort of backoff logic.
-        Reconnect::new(proxy)
+    /// Limits the total amount of time each request may take.
+    ///
+    /// The deadline covers the time a request spends buffered waiting for a
+    /// connection, as well as the time spent receiving the response and its
+    /// body.
+    pub fn with_request_timeout(self, request_timeout: Duration) -> Self {
+        Self {
+            request_timeout: Some(request_timeout),
+            ..self
+        }
     }
-}
 
-// ===== impl BindProtocol =====
+    /// Limits the size of each response body to `max_response_bytes`.
+    ///
+    /// A response that declares a larger `Content-Length` fails before its
+    /// body is read. Otherwise, the response body fails, resetting its stream,
+    /// once it has streamed more than `max_response_bytes`.
+    pub fn with_max_response_bytes(self, max_response_bytes: u64) -> Self {
+        Self {
+            max_response_bytes: Some(max_response_bytes),
+            ..self
+        }
+    }
 
+    /// Limits the size of each request body to `max_request_bytes`.
+    ///
+    /// A request body fails, aborting its request, once it has streamed more
+    /// than `max_request_bytes`. Inbound requests that declare a larger
+    /// `Content-Length` are rejected with a `413 Payload Too Large` before
+    /// they are buffered, and before any connection is established.
+    pub fn with_max_request_bytes(self, max_request_bytes: u64) -> Self {
+        Self {
+            max_request_bytes: Some(max_request_bytes),
+            ..self
+        }
+    }
 
-impl<C, B> Bind<C, B> {
-    pub fn with_protocol(self, protocol: Protocol) -> BindProtocol<C, B> {
-        BindProtocol {
-            bind: self,
-            protocol,
+    /// Limits the total size of each message's headers to `max_header_bytes`.
+    ///
+    /// Responses with larger headers fail, with a `PROTOCOL_ERROR`, before
+    /// their bodies are read. Inbound requests with larger headers are
+    /// rejected with a `431 Request Header Fields Too Large`.
+    pub fn with_max_header_bytes(self, max_header_bytes: usize) -> Self {
+        Self {
+            header_limits: self.header_limits.with_max_total_bytes(max_header_bytes),
+            ..self
         }
     }
-}
 
-impl<B> control::discovery::Bind for BindProtocol<Arc<ctx::Proxy>, B>
-where
-    B: tower_h2::Body + 'static,
-{
-    type Request = http::Request<B>;
-    type Response = HttpResponse;
-    type Error = <Service<B> as tower::Service>::Error;
-    type Service = Service<B>;
-    type BindError = ();
+    /// Limits the size of each header line to `max_line_bytes`, as
+    /// `with_max_header_bytes` limits their total size.
+    pub fn with_max_header_line_bytes(self, max_line_bytes: usize) -> Self {
+        Self {
+            header_limits: self.header_limits.with_max_line_bytes(max_line_bytes),
+            ..self
+        }
+    }
 
-    fn bind(&self, addr: &SocketAddr) -> Result<Self::Service, Self::BindError> {
-        Ok(self.bind.bind_service(addr, &self.protocol))
+    /// Classifies requests by their values for `header`, so that, when a
+    /// service's buffer is full, more important requests are admitted in
+    /// place of less important ones.
+    pub fn with_priority_header(self, header: http::header::HeaderName) -> Self {
+        Self {
+            classify: self.classify.with_header(header),
+            ..self
+        }
     }
-}
 
+    /// Compresses responses with gzip for clients that accept it, if
+    /// `enabled`.
+    ///
+    /// Responses that are already encoded, or whose media types are already
+    /// compressed, are sent as they are.
+    pub fn with_gzip_responses(self, enabled: bool) -> Self {
+        Self {
+            gzip_responses: enabled,
+            ..self
+        }
+    }
 
-// ===== impl NormalizeUri =====
+    /// Adds `headers` to every response received from an endpoint.
+    pub fn with_response_headers(self, headers: ResponseHeaders) -> Self {
+        Self {
+            response_headers: headers,
+            ..self
+        }
+    }
 
+    /// Caches responses to `GET` requests that may be cached, up to
+    /// `max_bytes` in total, and serves requests from the cache while the
+    /// responses are fresh.
+    ///
+    /// Every clone of the `Bind` shares the same cache.
+    pub fn with_response_cache(self, max_bytes: usize) -> Self {
+        Self {
+            response_cache: Some(ResponseCache::new(max_bytes)),
+            ..self
+        }
+    }
 
-impl<S> NormalizeUri<S> {
-    fn new (inner: S) -> Self {
-        Self { inner }
+    /// Retries failed requests according to `retry_policy`.
+    ///
+    /// Each attempt is subject to the request timeout, if one is configured,
+    /// and is sent on a new connection if the last attempt's connection
+    /// failed. Retries are limited by a single budget shared by every
+    /// endpoint and route bound by this `Bind` and its clones.
+    pub fn with_retries(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_budget: Some(RetryBudget::new(&retry_policy)),
+            retry_policy: Some(retry_policy),
+            ..self
+        }
     }
-}
 
-impl<S, B> tower::NewService for NormalizeUri<S>
-where
-    S: tower::NewService<
-        Request=http::Request<B>,
-        Response=HttpResponse,
-    >,
-    S::Service: tower::Service<
-        Request=http::Request<B>,
-        Response=HttpResponse,
-    >,
-    NormalizeUri<S::Service>: tower::Service,
-    B: tower_h2::Body,
-{
-    type Request = <Self::Service as tower::Service>::Request;
-    type Response = <Self::Service as tower::Service>::Response;
-    type Error = <Self::Service as tower::Service>::Error;
-    type Service = NormalizeUri<S::Service>;
-    type InitError = S::InitError;
-    type Future = Map<
-        S::Future,
-        fn(S::Service) -> NormalizeUri<S::Service>
-    >;
-    fn new_service(&self) -> Self::Future {
-        self.inner.new_service().map(NormalizeUri::new)
+    /// Buffers the bodies of requests that may be retried or resent,
+    /// according to `buffer_policy`.
+    ///
+    /// A body is buffered only while it fits within both the policy and the
+    /// request body limit, if one is configured; requests whose bodies don't
+    /// are streamed, and are not replayed.
+    pub fn with_buffer_policy(self, buffer_policy: BufferPolicy) -> Self {
+        Self {
+            buffer_policy: Some(buffer_policy),
+            ..self
+        }
     }
-}
 
-impl<S, B> tower::Service for NormalizeUri<S>
-where
-    S: tower::Service<
-        Request=http::Request<B>,
-        Response=HttpResponse,
-    >,
-    B: tower_h2::Body,
-{
-    type Request = S::Request;
-    type Response = HttpResponse;
-    type Error = S::Error;
-    type Future = S::Future;
+    /// Overrides the request timeout, retries, concurrency limit, response
+    /// compression, and the host that requests are sent with, with those set
+    /// by a destination's `RoutePolicy`, and injects the route's faults.
+    /// Requests that aren't classified by header have the route's priority.
+    ///
+    /// A route that sets `max_retries` retries the same methods and statuses
+    /// as the default retry policy, with a budget of its own that is shared
+    /// by every service bound for the route.
+    pub fn with_route_policy(self, policy: &RoutePolicy) -> Self {
+        let (retry_policy, retry_budget) = match policy.max_retries {
+            Some(max_retries) => {
+                let retry_policy = self.retry_policy().with_max_retries(max_retries);
+                let retry_budget = RetryBudget::new(&retry_policy);
+                (Some(retry_policy), Some(retry_budget))
+            },
+            None => (self.retry_policy.clone(), self.retry_budget.clone()),
+        };
+        let classify = match policy.priority {
+            Some(priority) => self.classify.with_default(priority),
+            None => self.classify,
+        };
+        Self {
+            request_timeout: policy.request_timeout.or(self.request_timeout),
+            concurrency_limit: policy.concurrency_limit.or(self.concurrency_limit),
+            gzip_responses: policy.gzip_responses.unwrap_or(self.gzip_responses),
+            rewrite_host: policy.rewrite_host.clone().or(self.rewrite_host),
+            faults: policy.faults.clone().or(self.faults),
+            retry_policy,
+            retry_budget,
+            classify,
+            ..self
+        }
+    }
 
-    fn poll_ready(&mut self) -> Poll<(), S::Error> {
-        self.inner.poll_ready()
+    /// Fails requests to discovered endpoints quickly once they have failed
+    /// repeatedly, as configured by `breaker`.
+    pub fn with_circuit_breaker(self, breaker: BreakerConfig) -> Self {
+        Self {
+            breaker: Some(breaker),
+            ..self
+        }
     }
 
-    fn call(&mut self, mut request: S::Request) -> Self::Future {
-        if request.version() != http::Version::HTTP_2 {
-            h1::normalize_our_view_of_uri(&mut request);
+    /// Withholds discovered endpoints from load balancing while they fail
+    /// the health checks configured by `health_check`.
+    pub fn with_health_check(self, health_check: HealthCheckConfig) -> Self {
+        Self {
+            health_check: Some(health_check),
+            ..self
         }
-        self.inner.call(request)
     }
-}
+
+    /// Ejects discovered endpoints from load balancing for a time once their
+    /// requests have failed repeatedly, as configured by `outlier`.
+    ///
+    /// Requests fail if they receive an error or a `5xx` response.
+    pub fn with_outlier_detection(self, outlier: OutlierConfig) -> Self {
+        Self {
+            outlier: Some(outlier),
+            ..self
+        }
+    }
+
+    /// Reports the health, ejections, and responses of discovered endpoints
+    /// to `state`, and withholds those that are paused through it from load
+    /// balancing.
+    pub fn with_discovery_state(self, state: control::DiscoveryState) -> Self {
+        Self {
+            health_checks: self.health_checks.with_discovery_state(&state),
+            pauses: state.pauses().clone(),
+            discovery_state: Some(state),
+            ..self
+        }
+    }
+
+    /// Limits the time that requests in flight to a discovered endpoint may
+    /// take to receive a response once the endpoint has been removed.
+    pub fn with_drain_grace_period(self, drain_grace_period: Duration) -> Self {
+        Self {
+            drain_grace_period,
+            ..self
+        }
+    }
+
+    /// Determines how HTTP/1 requests without an authority are routed.
+    ///
+    /// By default, such requests are routed to their original destination.
+    pub fn with_missing_host_policy(self, missing_host_policy: MissingHostPolicy) -> Self {
+        Self {
+            missing_host_policy,
+            ..self
+        }
+    }
+
+    /// Overrides the protocol with which requests are sent.
+    ///
+    /// By default, requests are sent with the protocol with which they were
+    /// received.
+    pub fn with_protocol_policy(self, protocol_policy: ProtocolPolicy) -> Self {
+        Self {
+            protocol_policy,
+            ..self
+        }
+    }
+
+    /// Configures the pooling of connections for `Protocol::Http1`.
+    pub fn with_h1_settings(self, h1_settings: transparency::H1Settings) -> Self {
+        Self {
+            h1_settings,
+            ..self
+        }
+    }
+
+    /// Limits the number of HTTP/1 connections open to each endpoint to
+    /// `max_conns`.
+    ///
+    /// Since each HTTP/1 connection serves one request at a time, requests
+    /// beyond this limit are queued until a connection becomes idle or is
+    /// closed. The limit is shared by every service bound to an endpoint,
+    /// and counts idle pooled connections, so it should be configured along
+    /// with `H1Settings` that close idle connections promptly.
+    pub fn with_http1_max_conns(self, max_conns: usize) -> Self {
+        Self {
+            http1_max_conns: Some(max_conns),
+            ..self
+        }
+    }
+
+    /// Closes client connections that have had no requests in flight for
+    /// `idle_timeout`.
+    ///
+    /// An HTTP/2 connection sends a `GOAWAY` before it is closed. The
+    /// connection is re-established when the endpoint is next dispatched a
+    /// request, or right away if it's prewarmed. For `Protocol::Http1`, this
+    /// is the keep-alive timeout of pooled connections, unless the
+    /// `H1Settings` configure one.
+    pub fn with_idle_timeout(self, idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout: Some(idle_timeout),
+            ..self
+        }
+    }
+
+    /// Establishes a connection to each endpoint as soon as it's bound,
+    /// rather than when it's first dispatched a request, if `prewarm` is
+    /// true.
+    ///
+    /// Each endpoint keeps at least one connection established: a connection
+    /// that closes, as for idling, is replaced right away. HTTP/1
+    /// connections are only opened as requests are sent.
+    pub fn with_prewarm(self, prewarm: bool) -> Self {
+        Self {
+            prewarm,
+            ..self
+        }
+    }
+
+    /// Configures the HTTP/2 settings of connections for `Protocol::Http2`.
+    pub fn with_h2_settings(self, h2_settings: transparency::H2Settings) -> Self {
+        Self {
+            h2_settings,
+            ..self
+        }
+    }
+
+    /// Keeps client connections alive according to `keepalive`.
+    ///
+    /// An HTTP/2 connection on which a PING is not acknowledged in time is
+    /// closed, and is re-established when the endpoint is next dispatched a
+    /// request.
+    pub fn with_keepalive(self, keepalive: KeepaliveConfig) -> Self {
+        Self {
+            keepalive,
+            ..self
+        }
+    }
+
+    /// Sizes the socket buffers of client connections according to
+    /// `socket_buffers`.
+    pub fn with_socket_buffers(self, socket_buffers: transport::SocketBuffers) -> Self {
+        Self {
+            socket_buffers,
+            ..self
+        }
+    }
+
+    /// Sets `TCP_NODELAY`, and primes with `TCP_QUICKACK`, client
+    /// connections' sockets as configured by `socket_latency`.
+    pub fn with_socket_latency(self, socket_latency: transport::SocketLatency) -> Self {
+        Self {
+            socket_latency,
+            ..self
+        }
+    }
+
+    /// Originates TLS for connections to servers whose names are known.
+    ///
+    /// Connections to servers without a known name, such as those addressed
+    /// only by IP, are not encrypted.
+    pub fn with_tls(self, tls: tls::ClientConfig) -> Self {
+        let tls = match self.client_identity {
+            Some(ref identity) => tls.with_client_identity(identity),
+            None => tls,
+        };
+        Self {
+            tls: Some(tls),
+            ..self
+        }
+    }
+
+    /// Presents `identity` during TLS handshakes with servers that request
+    /// client authentication.
+    ///
+    /// This has no effect on connections that are not encrypted. The identity
+    /// may be reloaded while services are bound.
+    pub fn with_client_identity(self, identity: tls::ClientIdentity) -> Self {
+        let tls = self.tls.as_ref().map(|tls| tls.with_client_identity(&identity));
+        Self {
+            tls,
+            client_identity: Some(identity),
+            ..self
+        }
+    }
+
+    /// Connects to endpoints through a tunnel that `proxy` opens with
+    /// `CONNECT`.
+    ///
+    /// Connections that are encrypted with TLS are encrypted end-to-end, so
+    /// the proxy can't read them.
+    pub fn with_connect_proxy(self, proxy: transport::ConnectProxy) -> Self {
+        Self {
+            connect_proxy: Some(proxy),
+            ..self
+        }
+    }
+
+    pub fn executor(&self) -> &Handle {
+        &self.executor
+    }
+
+    pub fn buffer_capacity(&self) -> usize {
+        self.buffer_capacity
+    }
+
+    pub fn max_request_bytes(&self) -> Option<u64> {
+        self.max_request_bytes
+    }
+
+    pub fn header_limits(&self) -> HeaderLimits {
+        self.header_limits
+    }
+
+    pub fn classify(&self) -> Classify {
+        self.classify.clone()
+    }
+
+    pub fn missing_host_policy(&self) -> MissingHostPolicy {
+        self.missing_host_policy
+    }
+
+    pub fn protocol_policy(&self) -> ProtocolPolicy {
+        self.protocol_policy
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone().unwrap_or_default()
+    }
+
+    pub fn discovery_state(&self) -> Option<&control::DiscoveryState> {
+        self.discovery_state.as_ref()
+    }
+}
+
+// These accessors exist for introspection (e.g. by diagnostics and tests)
+// rather than for binding services, so the proxy itself may not use them.
+#[allow(dead_code)]
+impl<C, B> Bind<C, B> {
+    pub fn ctx(&self) -> &C {
+        &self.ctx
+    }
+
+    /// Returns the generator from which bound services assign request IDs.
+    ///
+    /// The generator is shared by all clones of this `Bind`.
+    pub fn req_ids(&self) -> &sensor::SharedRequestIdGen {
+        &self.req_ids
+    }
+
+    pub fn sensors(&self) -> &telemetry::Sensors {
+        &self.sensors
+    }
+}
+
+// ===== impl BindBuilder =====
+
+impl BindBuilder<()> {
+    /// Creates a builder with the same defaults as `Bind::new`.
+    pub fn new(executor: Handle) -> Self {
+        BindBuilder {
+            ctx: (),
+            sensors: telemetry::Sensors::null(),
+            executor,
+            backoff: None,
+            buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            idle_timeout: None,
+            request_timeout: None,
+            rate_limits: RateLimits::default(),
+            drain_grace_period: Duration::from_secs(DEFAULT_DRAIN_GRACE_PERIOD_SECS),
+            missing_host_policy: MissingHostPolicy::OriginalDst,
+            protocol_policy: ProtocolPolicy::Detect,
+            h1_settings: transparency::H1Settings::default(),
+            h2_settings: transparency::H2Settings::default(),
+            keepalive: KeepaliveConfig::default(),
+        }
+    }
+}
+
+impl<C> BindBuilder<C> {
+    pub fn with_ctx<D>(self, ctx: D) -> BindBuilder<D> {
+        BindBuilder {
+            ctx,
+            sensors: self.sensors,
+            executor: self.executor,
+            backoff: self.backoff,
+            buffer_capacity: self.buffer_capacity,
+            connect_timeout: self.connect_timeout,
+            idle_timeout: self.idle_timeout,
+            request_timeout: self.request_timeout,
+            rate_limits: self.rate_limits,
+            drain_grace_period: self.drain_grace_period,
+            missing_host_policy: self.missing_host_policy,
+            protocol_policy: self.protocol_policy,
+            h1_settings: self.h1_settings,
+            h2_settings: self.h2_settings,
+            keepalive: self.keepalive,
+        }
+    }
+
+    pub fn with_sensors(self, sensors: telemetry::Sensors) -> Self {
+        Self {
+            sensors,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_backoff`.
+    pub fn with_backoff(self, backoff: BackoffConfig) -> Self {
+        Self {
+            backoff: Some(backoff),
+            ..self
+        }
+    }
+
+    /// See `Bind::with_buffer_capacity`. The capacity must not be zero.
+    pub fn with_buffer_capacity(self, buffer_capacity: usize) -> Self {
+        Self {
+            buffer_capacity,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_connect_timeout`. The timeout must not be zero, nor
+    /// longer than the request timeout.
+    pub fn with_connect_timeout(self, connect_timeout: Duration) -> Self {
+        Self {
+            connect_timeout,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_idle_timeout`.
+    pub fn with_idle_timeout(self, idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout: Some(idle_timeout),
+            ..self
+        }
+    }
+
+    /// See `Bind::with_request_timeout`.
+    pub fn with_request_timeout(self, request_timeout: Duration) -> Self {
+        Self {
+            request_timeout: Some(request_timeout),
+            ..self
+        }
+    }
+
+    /// Rejects requests in excess of the rates configured by `rate_limit`.
+    ///
+    /// Services bound from every `Bind` built by this builder, or its clones,
+    /// share the same rate limits.
+    pub fn with_rate_limit(self, rate_limit: RateLimitConfig) -> Self {
+        Self {
+            rate_limits: RateLimits::new(rate_limit),
+            ..self
+        }
+    }
+
+    /// See `Bind::with_drain_grace_period`.
+    pub fn with_drain_grace_period(self, drain_grace_period: Duration) -> Self {
+        Self {
+            drain_grace_period,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_missing_host_policy`.
+    pub fn with_missing_host_policy(self, missing_host_policy: MissingHostPolicy) -> Self {
+        Self {
+            missing_host_policy,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_protocol_policy`.
+    pub fn with_protocol_policy(self, protocol_policy: ProtocolPolicy) -> Self {
+        Self {
+            protocol_policy,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_h1_settings`.
+    pub fn with_h1_settings(self, h1_settings: transparency::H1Settings) -> Self {
+        Self {
+            h1_settings,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_h2_settings`.
+    pub fn with_h2_settings(self, h2_settings: transparency::H2Settings) -> Self {
+        Self {
+            h2_settings,
+            ..self
+        }
+    }
+
+    /// See `Bind::with_keepalive`.
+    pub fn with_keepalive(self, keepalive: KeepaliveConfig) -> Self {
+        Self {
+            keepalive,
+            ..self
+        }
+    }
+
+    /// Validates the options together, and builds a `Bind` from them.
+    ///
+    /// Options not set by the builder, such as retries and TLS, may still be
+    /// configured on the returned `Bind`.
+    pub fn build<B>(self) -> Result<Bind<C, B>, BindConfigError> {
+        if self.buffer_capacity == 0 {
+            return Err(BindConfigError::ZeroBufferCapacity);
+        }
+        if self.connect_timeout == Duration::from_secs(0) {
+            return Err(BindConfigError::ZeroConnectTimeout);
+        }
+        if let Some(request_timeout) = self.request_timeout {
+            if self.connect_timeout > request_timeout {
+                return Err(BindConfigError::ConnectTimeoutExceedsRequestTimeout {
+                    connect_timeout: self.connect_timeout,
+                    request_timeout,
+                });
+            }
+        }
+
+        let bind = Bind::new(self.executor)
+            .with_sensors(self.sensors)
+            .with_ctx(self.ctx)
+            .with_buffer_capacity(self.buffer_capacity)
+            .with_connect_timeout(self.connect_timeout)
+            .with_drain_grace_period(self.drain_grace_period)
+            .with_missing_host_policy(self.missing_host_policy)
+            .with_protocol_policy(self.protocol_policy)
+            .with_h1_settings(self.h1_settings)
+            .with_h2_settings(self.h2_settings)
+            .with_keepalive(self.keepalive);
+        let bind = Bind {
+            rate_limits: self.rate_limits,
+            ..bind
+        };
+        let bind = match self.backoff {
+            Some(backoff) => bind.with_backoff(backoff),
+            None => bind,
+        };
+        let bind = match self.idle_timeout {
+            Some(timeout) => bind.with_idle_timeout(timeout),
+            None => bind,
+        };
+        let bind = match self.request_timeout {
+            Some(timeout) => bind.with_request_timeout(timeout),
+            None => bind,
+        };
+        Ok(bind)
+    }
+}
+
+impl<B> Bind<Arc<ctx::Proxy>, B>
+where
+    B: tower_h2::Body + Default + 'static,
+{
+    pub fn bind_service(&self, addr: &SocketAddr, protocol: &Protocol) -> Service<B> {
+        self.bind_named_service(addr, protocol, protocol.tls_name())
+    }
+
+    /// Binds a service to `addr`, validating TLS connections against
+    /// `tls_name`.
+    fn bind_named_service(
+        &self,
+        addr: &SocketAddr,
+        protocol: &Protocol,
+        tls_name: Option<dns::Name>,
+    ) -> Service<B> {
+        trace!("bind_service addr={}, protocol={:?}, tls_name={:?}", addr, protocol, tls_name);
+
+        let tls = self.tls_config(tls_name);
+
+        let client_ctx = ctx::transport::Client::new(
+            &self.ctx,
+            addr,
+            conduit_proxy_controller_grpc::common::Protocol::Http,
+            tls.is_some(),
+        );
+
+        let sensors = self.sensors_for(&client_ctx);
+        let connect = sensors.connect(self.connect(addr, tls), &client_ctx);
+        let connect = match *protocol {
+            Protocol::Http2 => connect.with_h2_frames(),
+            _ => connect,
+        };
+        // Wait for a connection to close, if the proxy has as many open as
+        // it may. Like the wait below, this happens outside of the sensors.
+        let connect = transport::LimitConnections::new(
+            connect,
+            self.max_connections.clone(),
+            &self.executor,
+        );
+        // Wait for other attempts to connect to the endpoint before each
+        // attempt, if connect concurrency is limited. This happens outside of
+        // the sensors, so that connect latency isn't inflated by waiting.
+        let connect_limit = self.connect_concurrency
+            .map(|max| self.connect_limits.limit(addr, max));
+        let connect = transport::LimitConnect::new(connect, connect_limit);
+
+        let conn_limit = self.http1_max_conns
+            .map(|max| self.conn_limits.limit(addr, max));
+        let client = transparency::Client::new(
+            protocol,
+            connect,
+            &self.h1_settings,
+            conn_limit,
+            &self.h2_settings,
+            self.idle_timeout,
+            self.keepalive.ping(),
+            self.executor.clone(),
+        );
+
+        // Send requests with the route's host, if it rewrites them. This
+        // happens within the sensors, so that telemetry describes requests
+        // by the authority that they were originally sent to.
+        let client = RewriteHost::new(client, self.rewrite_host.clone());
+
+        // Delay or abort some requests, if the route injects faults. This
+        // happens within the sensors, so that injected aborts are recorded,
+        // and retried, as they would be if the endpoint had failed.
+        let client = Fault::new(client, self.faults.clone(), &self.executor);
+
+        let sensors = sensors.http(
+            self.req_ids.clone(),
+            client,
+            &client_ctx
+        );
+
+        // Rewrite the HTTP/1 URI, if the authorities in the Host header
+        // and request URI are not in agreement, or are not present.
+        let proxy = NormalizeUri::new(sensors);
+
+        // Abort requests with bodies that are too large, if a limit is
+        // configured.
+        let proxy = RequestBodyLimit::new(proxy, self.max_request_bytes);
+
+        // Fail responses with headers that are too large, if limits are
+        // configured.
+        let proxy = ResponseHeaderLimit::new(proxy, self.header_limits);
+
+        // Fail responses with bodies that are too large, if a limit is
+        // configured.
+        let proxy = ResponseBodyLimit::new(proxy, self.max_response_bytes);
+
+        // Serve fresh responses from the cache, if one is configured. This
+        // happens before compression, so that responses are cached as they
+        // were received, whatever encodings their clients accept.
+        let proxy = Cache::new(proxy, self.response_cache.clone());
+
+        // Compress responses for clients that accept gzip, if enabled. This
+        // happens after the response body limit, so that the limit applies
+        // to the bytes received from the endpoint.
+        let proxy = Compress::new(proxy, self.gzip_responses);
+
+        // Add the configured headers to responses, if any are configured.
+        // This happens after compression and caching, so that the headers
+        // they set are seen, and cached responses are given the headers too.
+        let proxy = InjectHeaders::new(proxy, self.response_headers.clone());
+
+        // Fail requests that exceed the request timeout, if one is configured.
+        let proxy = RequestTimeout::new(proxy, self.request_timeout, &self.executor);
+
+        // Automatically perform reconnects if the connection fails, waiting
+        // between attempts if a backoff is configured.
+        //
+        // `Reconnect` only reconnects when its inner service fails to become
+        // ready, and each of the layers between it and the client is only unready
+        // when the client is. HTTP/1 clients are always ready, since hyper
+        // manages their connections, and HTTP/2 clients fail only once their
+        // connections have, so responses -- even server errors -- and failed
+        // requests never cause a healthy connection to be re-established.
+        let proxy = ReconnectBackoff::new(Reconnect::new(proxy), self.backoff, &self.executor);
+
+        // Connect before any request is dispatched, and keep a connection
+        // established, if prewarming is enabled.
+        let proxy = Prewarm::new(proxy, self.prewarm, self.idle_timeout, &self.executor);
+
+        // Retry failed requests that can be replayed, if a retry policy is
+        // configured. This happens outside of `Reconnect`, so that a request
+        // that failed with its connection is retried on a new one, and each
+        // attempt is subject to the request timeout. The budget is shared by
+        // every service that this `Bind` binds, rather than reset whenever a
+        // connection is replaced.
+        let retry_policy = self.retry_policy();
+        let retry_budget = self.retry_budget.clone()
+            .unwrap_or_else(|| RetryBudget::new(&retry_policy));
+        let proxy = Retry::new(proxy, retry_policy, retry_budget);
+
+        // Resend requests that were refused by a connection that is going
+        // away, on a new connection, if they can be replayed.
+        let proxy = RetryRefused::new(proxy, self.retry_policy());
+
+        // Buffer the bodies of requests that may be replayed, if a buffer
+        // policy is configured. A body longer than the request body limit is
+        // never buffered, so that a replay can't exceed the limit.
+        let buffer_policy = self.buffer_policy.map(|policy| match self.max_request_bytes {
+            Some(max) if max < policy.max_bytes() => BufferPolicy::new(max),
+            _ => policy,
+        });
+        let proxy = BufferRequests::new(proxy, buffer_policy, self.retry_policy());
+
+        // Limit the number of requests in flight to this endpoint, if a
+        // concurrency limit is configured.
+        let proxy = InFlightLimit::new(proxy, self.concurrency_limit.unwrap_or(usize::MAX));
+
+        // Reject requests in excess of the rate limits, if any are configured.
+        let proxy = self.rate_limits.rate_limit(proxy);
+
+        // Describe the endpoint to each of the layers above and beneath this
+        // one, now that balancing has chosen it.
+        let endpoint = BoundEndpoint::new(*addr, protocol.clone(), client_ctx.tls);
+        AnnotateEndpoint::new(proxy, endpoint)
+    }
+
+    /// Binds a client that probes the health of `addr`, bypassing telemetry.
+    fn bind_probe(
+        &self,
+        addr: &SocketAddr,
+        protocol: &Protocol,
+        tls_name: Option<dns::Name>,
+        config: &HealthCheckConfig,
+    ) -> Probe<B> {
+        let connect = self.connect(addr, self.tls_config(tls_name));
+        // Probes aren't queued behind requests for connections.
+        let client = transparency::Client::new(
+            protocol,
+            connect,
+            &self.h1_settings,
+            None,
+            &self.h2_settings,
+            self.idle_timeout,
+            self.keepalive.ping(),
+            self.executor.clone(),
+        );
+        HttpProbe::new(Reconnect::new(client), addr, config)
+    }
+
+    /// Selects the sensors for the direction of the traffic that `client`
+    /// dispatches.
+    fn sensors_for(&self, client: &ctx::transport::Client) -> &telemetry::Sensors {
+        let sensors = if client.proxy.is_inbound() {
+            self.inbound_sensors.as_ref()
+        } else {
+            self.outbound_sensors.as_ref()
+        };
+        sensors.unwrap_or(&self.sensors)
+    }
+
+    fn tls_config(&self, tls_name: Option<dns::Name>) -> Option<tls::ConnectionConfig> {
+        match (self.tls.as_ref(), tls_name) {
+            (Some(config), Some(name)) => Some(tls::ConnectionConfig::new(config.clone(), name)),
+            _ => None,
+        }
+    }
+
+    /// Maps a socket address to a connection, giving up on attempts that
+    /// take too long. A connection that completes after the timeout has
+    /// fired is dropped along with the attempt's future.
+    fn connect(
+        &self,
+        addr: &SocketAddr,
+        tls: Option<tls::ConnectionConfig>,
+    ) -> Timeout<transport::Connect> {
+        Timeout::new(
+            transport::Connect::new(*addr, &self.executor)
+                .with_tls(tls)
+                .with_keepalive(self.keepalive.tcp())
+                .with_socket_buffers(self.socket_buffers)
+                .with_socket_latency(self.socket_latency)
+                .with_connect_proxy(self.connect_proxy.clone()),
+            self.connect_timeout,
+            &self.executor,
+        )
+    }
+}
+
+// ===== impl BindProtocol =====
+
+
+impl<C, B> Bind<C, B> {
+    pub fn with_protocol(self, protocol: Protocol) -> BindProtocol<C, B> {
+        BindProtocol {
+            bind: self,
+            protocol,
+            tls_name: None,
+        }
+    }
+}
+
+impl<C, B> BindProtocol<C, B> {
+    /// Validates TLS connections against `tls_name`, rather than any name
+    /// determined from the protocol.
+    pub fn with_tls_name(self, tls_name: dns::Name) -> Self {
+        Self {
+            tls_name: Some(tls_name),
+            ..self
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<C, B> BindProtocol<C, B> {
+    pub fn bind(&self) -> &Bind<C, B> {
+        &self.bind
+    }
+
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+}
+
+impl<B> control::discovery::Bind for BindProtocol<Arc<ctx::Proxy>, B>
+where
+    B: tower_h2::Body + Default + 'static,
+{
+    type Request = http::Request<B>;
+    type Response = HttpResponse;
+    type Error = <DiscoveredService<B> as tower::Service>::Error;
+    type Service = DiscoveredService<B>;
+    type BindError = BindError;
+
+    fn bind(&self, addr: &SocketAddr) -> Result<Self::Service, Self::BindError> {
+        let tls_name = self.discovered_tls_name()?;
+        self.bind_discovered(addr, tls_name, None)
+    }
+
+    fn bind_undiscovered(&self, addr: &SocketAddr) -> Result<Self::Service, Self::BindError> {
+        // A server that the controller doesn't know of can't be expected to
+        // accept TLS.
+        self.bind_discovered(addr, None, None)
+    }
+
+    fn bind_draining(
+        &self,
+        addr: &SocketAddr,
+        drain: drain::Watch,
+    ) -> Result<Self::Service, Self::BindError> {
+        let tls_name = self.discovered_tls_name()?;
+        self.bind_discovered(addr, tls_name, Some(drain))
+    }
+}
+
+impl<B> BindProtocol<Arc<ctx::Proxy>, B>
+where
+    B: tower_h2::Body + Default + 'static,
+{
+    /// Returns the name that a discovered endpoint's certificate is validated
+    /// against, if TLS is configured and the name is known.
+    fn discovered_tls_name(&self) -> Result<Option<dns::Name>, BindError> {
+        if self.bind.tls.is_none() {
+            return Ok(None);
+        }
+
+        let name = match self.tls_name {
+            Some(ref name) => name.clone(),
+            None => match self.protocol.sni_name() {
+                Some(host) => dns::Name::normalize(host)
+                    .map_err(|_| BindError::ResolutionFailed(host.to_owned()))?,
+                None => return Ok(None),
+            },
+        };
+
+        if !tls::is_valid_server_name(&name) {
+            return Err(BindError::TlsConfig(name));
+        }
+
+        Ok(Some(name))
+    }
+
+    fn bind_discovered(
+        &self,
+        addr: &SocketAddr,
+        tls_name: Option<dns::Name>,
+        drain: Option<drain::Watch>,
+    ) -> Result<DiscoveredService<B>, BindError> {
+        if addr.ip().is_unspecified() || addr.port() == 0 {
+            return Err(BindError::InvalidAddress(*addr));
+        }
+
+        let service = self.bind.bind_named_service(addr, &self.protocol, tls_name.clone());
+        let service = Graceful::new(
+            service,
+            drain,
+            self.bind.drain_grace_period,
+            &self.bind.executor,
+        );
+        let service = self.bind.breakers.circuit_breaker(
+            addr,
+            service,
+            self.bind.breaker,
+            &self.bind.executor,
+        );
+        let service = self.bind.health_checks.health_checked(
+            addr,
+            service,
+            self.bind.health_check.as_ref(),
+            &self.bind.executor,
+            |config| self.bind.bind_probe(addr, &self.protocol, tls_name, config),
+        );
+
+        let service = OutlierDetection::new(service, addr, self.bind.outlier, &self.bind.executor);
+        let service = match self.bind.discovery_state {
+            Some(ref state) => service.with_endpoint_state(state.endpoint(addr)),
+            None => service,
+        };
+        Ok(self.bind.pauses.pausable(addr, service))
+    }
+}
+
+
+// ===== impl NormalizeUri =====
+
+
+impl<S> NormalizeUri<S> {
+    fn new (inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, B> tower::NewService for NormalizeUri<S>
+where
+    S: tower::NewService<
+        Request=http::Request<B>,
+        Response=SensorResponse,
+    >,
+    S::Service: tower::Service<
+        Request=http::Request<B>,
+        Response=SensorResponse,
+    >,
+    NormalizeUri<S::Service>: tower::Service,
+    B: tower_h2::Body,
+{
+    type Request = <Self::Service as tower::Service>::Request;
+    type Response = <Self::Service as tower::Service>::Response;
+    type Error = <Self::Service as tower::Service>::Error;
+    type Service = NormalizeUri<S::Service>;
+    type InitError = S::InitError;
+    type Future = Map<
+        S::Future,
+        fn(S::Service) -> NormalizeUri<S::Service>
+    >;
+    fn new_service(&self) -> Self::Future {
+        self.inner.new_service().map(NormalizeUri::new)
+    }
+}
+
+impl<S, B> tower::Service for NormalizeUri<S>
+where
+    S: tower::Service<
+        Request=http::Request<B>,
+        Response=SensorResponse,
+    >,
+    B: tower_h2::Body,
+{
+    type Request = S::Request;
+    type Response = SensorResponse;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: S::Request) -> Self::Future {
+        if request.version() != http::Version::HTTP_2 {
+            h1::normalize_our_view_of_uri(&mut request);
+        }
+        self.inner.call(request)
+    }
+}
 
 // ===== impl Protocol =====
 
 
-impl Protocol {
+impl Protocol {
+
+    pub fn detect<B>(req: &http::Request<B>) -> Self {
+        if req.version() == http::Version::HTTP_2 {
+            return Protocol::Http2
+        }
+
+        let host = Host::detect(req);
+
+        if h1::is_upgrade(req.headers()) {
+            return Protocol::Http1Upgrade(host);
+        }
+
+        Protocol::Http1(host)
+    }
+
+    /// Detects the protocol of a request, applying `policy` to HTTP/1
+    /// requests that have no authority.
+    ///
+    /// Returns `None` if the request should be rejected.
+    pub fn detect_with_fallback<B>(
+        req: &http::Request<B>,
+        policy: MissingHostPolicy,
+    ) -> Option<Self> {
+        match Self::detect(req) {
+            Protocol::Http1(Host::NoAuthority) =>
+                Host::fallback(req, policy).map(Protocol::Http1),
+            Protocol::Http1Upgrade(Host::NoAuthority) =>
+                Host::fallback(req, policy).map(Protocol::Http1Upgrade),
+            proto => Some(proto),
+        }
+    }
+
+    /// Overrides a request's detected protocol according to `policy`.
+    pub fn coerce<B>(self, req: &http::Request<B>, policy: ProtocolPolicy) -> Self {
+        let coerced = match (policy, self) {
+            (ProtocolPolicy::ForceHttp1, Protocol::Http2) => Protocol::Http1(Host::detect(req)),
+            (ProtocolPolicy::ForceHttp2, Protocol::Http1(_)) => Protocol::Http2,
+            (_, proto) => return proto,
+        };
+        trace!("coerced {:?} request to {:?}", req.version(), coerced);
+        coerced
+    }
+
+    pub fn is_cachable(&self) -> bool {
+        match *self {
+            Protocol::Http2 | Protocol::Http1(Host::Authority(_)) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_upgrade(&self) -> bool {
+        match *self {
+            Protocol::Http1Upgrade(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the DNS name of the authority the request was addressed to,
+    /// if it has one.
+    pub fn tls_name(&self) -> Option<dns::Name> {
+        self.sni_name().and_then(|name| dns::Name::normalize(name).ok())
+    }
+
+    fn sni_name(&self) -> Option<&str> {
+        match *self {
+            Protocol::Http1(ref host) | Protocol::Http1Upgrade(ref host) => host.sni_name(),
+            Protocol::Http2 => None,
+        }
+    }
+
+    pub fn into_key<T>(self, key: T) -> Reuse<(T, Protocol)> {
+        if self.is_cachable() {
+            Reuse::Reusable((key, self))
+        } else {
+            Reuse::SingleUse((key, self))
+        }
+    }
+}
+
+// ===== impl Host =====
+
+impl Host {
+    /// If the request has an authority part, use that as the host part of
+    /// the key for an HTTP/1.x request.
+    fn detect<B>(req: &http::Request<B>) -> Self {
+        req.uri().authority_part()
+            .cloned()
+            .or_else(|| h1::authority_from_host(req))
+            .map(Host::Authority)
+            .unwrap_or_else(|| Host::NoAuthority)
+    }
+
+    fn fallback<B>(req: &http::Request<B>, policy: MissingHostPolicy) -> Option<Self> {
+        match policy {
+            MissingHostPolicy::Reject => None,
+            MissingHostPolicy::OriginalDst => h1::authority_from_orig_dst(req).map(Host::Authority),
+        }
+    }
+
+    /// Returns the name to use for TLS SNI and certificate validation.
+    ///
+    /// IP-literal authorities have no such name, and trailing dots are
+    /// stripped from fully-qualified names.
+    pub fn sni_name(&self) -> Option<&str> {
+        match *self {
+            Host::Authority(ref a) => {
+                let host = a.host();
+                if host.starts_with('[') {
+                    // Only IPv6 literals are bracketed, possibly with a zone.
+                    return None;
+                }
+                if host.parse::<IpAddr>().is_ok() {
+                    return None;
+                }
+
+                let name = host.trim_right_matches('.');
+                if name.is_empty() {
+                    None
+                } else {
+                    Some(name)
+                }
+            },
+            Host::NoAuthority => None,
+        }
+    }
+}
+
+/// Serializes protocols for route tables that are written as configuration.
+///
+/// A `Protocol` is serialized as an externally tagged enum, e.g.
+/// `{"Http1":"example.com:8080"}` or `"Http2"`. A `Host` is serialized as its
+/// authority's string form, or as nothing if it has no authority.
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use std::fmt;
+
+    use http::uri;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::de::{self, EnumAccess, VariantAccess, Visitor};
+
+    use super::{Host, Protocol};
+
+    const VARIANTS: &[&str] = &["Http1", "Http1Upgrade", "Http2"];
+
+    enum Variant {
+        Http1,
+        Http1Upgrade,
+        Http2,
+    }
+
+    struct VariantVisitor;
+
+    struct ProtocolVisitor;
+
+    // ===== impl Host =====
+
+    impl Serialize for Host {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match *self {
+                Host::Authority(ref a) => serializer.serialize_some(a.as_str()),
+                Host::NoAuthority => serializer.serialize_none(),
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Host {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let authority = match Option::<String>::deserialize(deserializer)? {
+                Some(authority) => authority,
+                None => return Ok(Host::NoAuthority),
+            };
+            match authority.parse::<uri::Authority>() {
+                Ok(a) => Ok(Host::Authority(a)),
+                Err(_) => Err(de::Error::invalid_value(
+                    de::Unexpected::Str(&authority),
+                    &"a URI authority",
+                )),
+            }
+        }
+    }
+
+    // ===== impl Protocol =====
+
+    impl Serialize for Protocol {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match *self {
+                Protocol::Http1(ref host) => {
+                    serializer.serialize_newtype_variant("Protocol", 0, "Http1", host)
+                }
+                Protocol::Http1Upgrade(ref host) => {
+                    serializer.serialize_newtype_variant("Protocol", 1, "Http1Upgrade", host)
+                }
+                Protocol::Http2 => serializer.serialize_unit_variant("Protocol", 2, "Http2"),
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Protocol {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_enum("Protocol", VARIANTS, ProtocolVisitor)
+        }
+    }
+
+    impl<'de> Visitor<'de> for ProtocolVisitor {
+        type Value = Protocol;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an HTTP protocol")
+        }
+
+        fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Protocol, A::Error> {
+            match data.variant()? {
+                (Variant::Http1, v) => v.newtype_variant().map(Protocol::Http1),
+                (Variant::Http1Upgrade, v) => v.newtype_variant().map(Protocol::Http1Upgrade),
+                (Variant::Http2, v) => v.unit_variant().map(|()| Protocol::Http2),
+            }
+        }
+    }
+
+    // ===== impl Variant =====
+
+    impl<'de> Deserialize<'de> for Variant {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_identifier(VariantVisitor)
+        }
+    }
+
+    impl<'de> Visitor<'de> for VariantVisitor {
+        type Value = Variant;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a protocol name")
+        }
+
+        fn visit_str<E: de::Error>(self, name: &str) -> Result<Variant, E> {
+            match name {
+                "Http1" => Ok(Variant::Http1),
+                "Http1Upgrade" => Ok(Variant::Http1Upgrade),
+                "Http2" => Ok(Variant::Http2),
+                _ => Err(de::Error::unknown_variant(name, VARIANTS)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use bytes::{Buf, Bytes, IntoBuf};
+    use futures::{future, Async, Stream};
+    use h2;
+    use http;
+    use hyper;
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::{Core, Timeout as ReactorTimeout};
+    use tower::{NewService, Service};
+    use tower_buffer;
+    use tower_h2::Body;
+
+    use conduit_proxy_router::Reuse;
+    use control::discovery::Bind as DiscoveryBind;
+    use priority::Priority;
+    #[cfg(feature = "serde")]
+    use serde_json;
+    use super::*;
+
+    fn websocket_handshake(connection: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri("/chat")
+            .header("host", "example.com")
+            .header("connection", connection)
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn detects_upgrade() {
+        for connection in &["Upgrade", "upgrade", "UPGRADE", "keep-alive, Upgrade", "close,upgrade"] {
+            let req = websocket_handshake(connection);
+            match Protocol::detect(&req) {
+                Protocol::Http1Upgrade(Host::Authority(ref a)) => assert_eq!(a, "example.com"),
+                p => panic!("{:?} should be an upgrade: {:?}", connection, p),
+            }
+        }
+    }
+
+    #[test]
+    fn does_not_detect_upgrade_without_connection_token() {
+        let req = websocket_handshake("keep-alive");
+        match Protocol::detect(&req) {
+            Protocol::Http1(_) => {}
+            p => panic!("should not be an upgrade: {:?}", p),
+        }
+
+        let req = http::Request::builder()
+            .header("host", "example.com")
+            .header("connection", "upgrade")
+            .body(())
+            .unwrap();
+        match Protocol::detect(&req) {
+            Protocol::Http1(_) => {}
+            p => panic!("should not be an upgrade without an upgrade header: {:?}", p),
+        }
+    }
+
+    #[test]
+    fn buffer_spawn_errors_describe_their_target_and_cause() {
+        use std::io;
+
+        let cause = io::Error::new(io::ErrorKind::Other, "reactor is gone");
+        let e = BufferSpawnError::outbound("books.test.svc.cluster.local:8080".to_owned(), cause);
+        assert_eq!(e.target(), "books.test.svc.cluster.local:8080");
+        assert_eq!(
+            e.to_string(),
+            "error spawning outbound buffer task to books.test.svc.cluster.local:8080: \
+             reactor is gone"
+        );
+        assert_eq!(e.cause().expect("cause").to_string(), "reactor is gone");
+
+        let e = BufferSpawnError::inbound("10.1.1.1:8080".to_owned(), ExecutorRefused);
+        assert_eq!(
+            e.to_string(),
+            "error spawning inbound buffer task to 10.1.1.1:8080: executor refused the task"
+        );
+        assert_eq!(e.cause().expect("cause").description(), "executor refused the task");
+    }
+
+    #[test]
+    fn upgrades_are_not_reused() {
+        let proto = Protocol::detect(&websocket_handshake("Upgrade"));
+        assert!(proto.is_upgrade());
+        match proto.into_key(()) {
+            Reuse::SingleUse(((), Protocol::Http1Upgrade(_))) => {}
+            _ => panic!("upgrades should bind a single-use service"),
+        }
+    }
 
-    pub fn detect<B>(req: &http::Request<B>) -> Self {
-        if req.version() == http::Version::HTTP_2 {
-            return Protocol::Http2
+    /// An HTTP/1.0 request without a `Host` header, accepted on a connection
+    /// with the given original destination.
+    fn bare_http10_request(orig_dst: Option<SocketAddr>) -> http::Request<()> {
+        let mut req = http::Request::builder()
+            .version(http::Version::HTTP_10)
+            .uri("/")
+            .body(())
+            .unwrap();
+        let proxy = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let local = "127.0.0.1:4140".parse().unwrap();
+        let remote = "10.1.1.2:52000".parse().unwrap();
+        let server = ctx::transport::Server::new(
+            &proxy,
+            &local,
+            &remote,
+            &orig_dst,
+            ::conduit_proxy_controller_grpc::common::Protocol::Http,
+        );
+        req.extensions_mut().insert(server);
+        req
+    }
+
+    #[test]
+    fn missing_host_falls_back_to_original_dst() {
+        let req = bare_http10_request(Some("10.1.1.1:8080".parse().unwrap()));
+        assert_eq!(Protocol::detect(&req), Protocol::Http1(Host::NoAuthority));
+
+        let proto = Protocol::detect_with_fallback(&req, MissingHostPolicy::OriginalDst);
+        assert_eq!(proto, Some(Protocol::Http1(authority("10.1.1.1:8080"))));
+        assert!(proto.unwrap().is_cachable());
+    }
+
+    #[test]
+    fn missing_host_without_original_dst_is_rejected() {
+        let req = bare_http10_request(None);
+        assert_eq!(Protocol::detect_with_fallback(&req, MissingHostPolicy::OriginalDst), None);
+        assert_eq!(Protocol::detect_with_fallback(&req, MissingHostPolicy::Reject), None);
+    }
+
+    #[test]
+    fn missing_host_is_rejected_by_policy() {
+        let req = bare_http10_request(Some("10.1.1.1:8080".parse().unwrap()));
+        assert_eq!(Protocol::detect_with_fallback(&req, MissingHostPolicy::Reject), None);
+
+        let mut req = req;
+        req.headers_mut().insert(http::header::HOST, "example.com".parse().unwrap());
+        assert_eq!(
+            Protocol::detect_with_fallback(&req, MissingHostPolicy::Reject),
+            Some(Protocol::Http1(authority("example.com")))
+        );
+    }
+
+    fn authority(s: &str) -> Host {
+        Host::Authority(s.parse().unwrap())
+    }
+
+    #[test]
+    fn protocol_policy_overrides_detected_protocol() {
+        let h2 = http::Request::builder()
+            .version(http::Version::HTTP_2)
+            .uri("http://example.com/")
+            .body(())
+            .unwrap();
+        let h1 = http::Request::builder()
+            .header("host", "example.com")
+            .body(())
+            .unwrap();
+        let upgrade = websocket_handshake("Upgrade");
+        let coerce = |req: &http::Request<()>, policy| Protocol::detect(req).coerce(req, policy);
+
+        let example_h1 = Protocol::Http1(authority("example.com"));
+
+        assert_eq!(coerce(&h2, ProtocolPolicy::Detect), Protocol::Http2);
+        assert_eq!(coerce(&h1, ProtocolPolicy::Detect), example_h1);
+
+        // HTTP/2 requests keep their authorities to key their connections.
+        assert_eq!(coerce(&h2, ProtocolPolicy::ForceHttp1), example_h1);
+        assert_eq!(coerce(&h1, ProtocolPolicy::ForceHttp1), example_h1);
+
+        assert_eq!(coerce(&h2, ProtocolPolicy::ForceHttp2), Protocol::Http2);
+        assert_eq!(coerce(&h1, ProtocolPolicy::ForceHttp2), Protocol::Http2);
+        // Upgrades are only possible over HTTP/1.
+        assert!(coerce(&upgrade, ProtocolPolicy::ForceHttp2).is_upgrade());
+    }
+
+    #[test]
+    fn sni_name_from_authority() {
+        assert_eq!(authority("example.com").sni_name(), Some("example.com"));
+        assert_eq!(authority("example.com:8443").sni_name(), Some("example.com"));
+        assert_eq!(
+            authority("web.default.svc.cluster.local.:80").sni_name(),
+            Some("web.default.svc.cluster.local")
+        );
+    }
+
+    #[test]
+    fn no_sni_name_for_ip_literals() {
+        assert_eq!(authority("10.1.2.3").sni_name(), None);
+        assert_eq!(authority("10.1.2.3:8080").sni_name(), None);
+        assert_eq!(authority("[::1]:8080").sni_name(), None);
+    }
+
+    #[test]
+    fn no_sni_name_without_authority() {
+        assert_eq!(Host::NoAuthority.sni_name(), None);
+        assert!(Protocol::Http2.tls_name().is_none());
+    }
+
+    #[test]
+    fn tls_name_from_protocol_host() {
+        let proto = Protocol::Http1(authority("example.com."));
+        assert_eq!(proto.tls_name().map(|n| n.to_string()), Some("example.com".to_string()));
+
+        let proto = Protocol::Http1Upgrade(authority("10.1.2.3:80"));
+        assert!(proto.tls_name().is_none());
+    }
+
+    #[test]
+    fn binds_upgrade() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(core.handle()).with_ctx(ctx);
+
+        let proto = Protocol::detect(&websocket_handshake("Upgrade"));
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let mut svc = bind.bind_service(&addr, &proto);
+
+        // HTTP/1 clients connect lazily, so the service is ready before any
+        // connection is established.
+        let ready = future::lazy(|| Ok::<_, ()>(svc.poll_ready())).wait().unwrap();
+        assert!(ready.expect("poll_ready").is_ready());
+    }
+
+    #[test]
+    fn exposes_bound_state() {
+        use telemetry::sensor::RequestIdGen;
+
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(core.handle()).with_ctx(ctx.clone());
+        assert!(Arc::ptr_eq(bind.ctx(), &ctx));
+
+        // Request IDs are shared by clones, so that each proxied request is
+        // assigned a unique ID.
+        let clone = bind.clone();
+        assert_eq!(bind.req_ids().next_id(), 0);
+        assert_eq!(clone.req_ids().next_id(), 1);
+        assert_eq!(bind.req_ids().next_id(), 2);
+
+        let rebound = Bind::<_, ()>::new(core.handle())
+            .with_sensors(bind.sensors().clone())
+            .with_ctx(bind.ctx().clone());
+        assert!(Arc::ptr_eq(rebound.ctx(), &ctx));
+
+        let proto = bind.with_protocol(Protocol::Http2);
+        assert_eq!(proto.protocol(), &Protocol::Http2);
+        assert!(Arc::ptr_eq(proto.bind().ctx(), &ctx));
+    }
+
+    #[test]
+    fn route_policies_override_defaults() {
+        let core = Core::new().unwrap();
+        let bind = Bind::<(), ()>::new(core.handle())
+            .with_request_timeout(Duration::from_secs(10))
+            .with_concurrency_limit(100)
+            .with_retries(RetryPolicy::new(1).with_method(http::Method::POST));
+
+        let unset = bind.clone().with_route_policy(&RoutePolicy::default());
+        assert_eq!(unset.request_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(unset.concurrency_limit, Some(100));
+        assert_eq!(unset.retry_policy(), bind.retry_policy());
+
+        let policy = RoutePolicy {
+            request_timeout: Some(Duration::from_secs(1)),
+            max_retries: Some(3),
+            concurrency_limit: Some(5),
+            gzip_responses: Some(true),
+            rewrite_host: Some("backend.example.com".parse().unwrap()),
+            faults: Some(FaultConfig::default().with_abort(10, http::StatusCode::BAD_GATEWAY)),
+            path_protocols: None,
+            priority: Some(Priority::new(2)),
+        };
+        let route = bind.with_route_policy(&policy);
+        assert_eq!(route.request_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(route.rewrite_host, policy.rewrite_host);
+        assert_eq!(route.faults, policy.faults);
+        assert_eq!(route.concurrency_limit, Some(5));
+        assert!(route.gzip_responses);
+        assert_eq!(route.classify().classify(&http::Request::new(())), Priority::new(2));
+        assert_eq!(
+            route.retry_policy(),
+            RetryPolicy::new(3).with_method(http::Method::POST)
+        );
+
+        // A route that retries has a budget even if the `Bind` doesn't, so
+        // that it's shared by the route's services.
+        let retrying = RoutePolicy {
+            max_retries: Some(2),
+            ..RoutePolicy::default()
+        };
+        let route = Bind::<(), ()>::new(core.handle()).with_route_policy(&retrying);
+        assert!(route.retry_budget.is_some());
+    }
+
+    #[test]
+    fn builder_rejects_contradictory_options() {
+        let core = Core::new().unwrap();
+        let builder = BindBuilder::new(core.handle());
+
+        let empty = builder.clone().with_buffer_capacity(0).build::<()>();
+        assert_eq!(empty.err(), Some(BindConfigError::ZeroBufferCapacity));
+
+        let instant = builder.clone()
+            .with_connect_timeout(Duration::from_secs(0))
+            .build::<()>();
+        assert_eq!(instant.err(), Some(BindConfigError::ZeroConnectTimeout));
+
+        let slow = builder
+            .with_connect_timeout(Duration::from_secs(5))
+            .with_request_timeout(Duration::from_secs(1))
+            .build::<()>();
+        let err = slow.err().expect("connect timeout exceeds request timeout");
+        assert_eq!(err, BindConfigError::ConnectTimeoutExceedsRequestTimeout {
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(1),
+        });
+        assert_eq!(err.to_string(), "connect timeout (5s) exceeds request timeout (1s)");
+    }
+
+    #[test]
+    fn builder_builds_fully_configured_bind() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let secs = Duration::from_secs;
+        let bind = BindBuilder::new(core.handle())
+            .with_ctx(ctx.clone())
+            .with_sensors(telemetry::Sensors::null())
+            .with_backoff(BackoffConfig::new(secs(1), secs(10), 0.1))
+            .with_buffer_capacity(5)
+            .with_connect_timeout(secs(1))
+            .with_idle_timeout(secs(30))
+            .with_request_timeout(secs(1))
+            .with_rate_limit(RateLimitConfig::default())
+            .with_drain_grace_period(secs(5))
+            .with_missing_host_policy(MissingHostPolicy::Reject)
+            .with_protocol_policy(ProtocolPolicy::ForceHttp2)
+            .with_h1_settings(transparency::H1Settings::default().with_max_idle(1))
+            .with_h2_settings(transparency::H2Settings::default())
+            .with_keepalive(KeepaliveConfig::default().with_ping(secs(10), secs(1)))
+            .build::<()>()
+            .ok()
+            .expect("valid options");
+
+        assert!(Arc::ptr_eq(bind.ctx(), &ctx));
+        assert_eq!(bind.backoff, Some(BackoffConfig::new(secs(1), secs(10), 0.1)));
+        assert_eq!(bind.buffer_capacity(), 5);
+        assert_eq!(bind.connect_timeout, secs(1));
+        assert_eq!(bind.idle_timeout, Some(secs(30)));
+        assert_eq!(bind.request_timeout, Some(secs(1)));
+        assert_eq!(bind.drain_grace_period, secs(5));
+        assert_eq!(bind.missing_host_policy(), MissingHostPolicy::Reject);
+        assert_eq!(bind.protocol_policy(), ProtocolPolicy::ForceHttp2);
+        assert_eq!(bind.keepalive.ping(), KeepaliveConfig::default()
+            .with_ping(secs(10), secs(1))
+            .ping());
+    }
+
+    fn tls_config() -> tls::ClientConfig {
+        let pem = include_bytes!("transport/tls/testdata/ca.pem");
+        tls::ClientConfig::from_trust_anchors_pem(&mut ::std::io::Cursor::new(&pem[..]))
+            .expect("trust anchors")
+    }
+
+    fn bind_error(bind: &BindProtocol<Arc<ctx::Proxy>, ()>, addr: &str) -> Option<BindError> {
+        DiscoveryBind::bind(bind, &addr.parse().unwrap()).err()
+    }
+
+    #[test]
+    fn binding_unconnectable_addresses_fails() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(core.handle())
+            .with_ctx(ctx)
+            .with_protocol(Protocol::Http2);
+
+        for addr in &["0.0.0.0:80", "[::]:80", "10.1.2.3:0"] {
+            let expected = BindError::InvalidAddress(addr.parse().unwrap());
+            assert_eq!(bind_error(&bind, addr), Some(expected.clone()));
+            let undiscovered = bind.bind_undiscovered(&addr.parse().unwrap());
+            assert_eq!(undiscovered.err(), Some(expected));
         }
 
-        // If the request has an authority part, use that as the host part of
-        // the key for an HTTP/1.x request.
-        let host = req.uri().authority_part()
-            .cloned()
-            .or_else(|| h1::authority_from_host(req))
-            .map(Host::Authority)
-            .unwrap_or_else(|| Host::NoAuthority);
+        assert_eq!(bind_error(&bind, "10.1.2.3:80"), None);
+    }
 
-        Protocol::Http1(host)
+    #[test]
+    fn binding_with_invalid_tls_name_fails() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let name = dns::Name::normalize("server.123").unwrap();
+        let bind = Bind::<_, ()>::new(core.handle()).with_ctx(ctx);
+
+        // Names are only validated if connections may be encrypted.
+        let plaintext = bind.clone()
+            .with_protocol(Protocol::Http2)
+            .with_tls_name(name.clone());
+        assert_eq!(bind_error(&plaintext, "10.1.2.3:80"), None);
+
+        let tls = bind
+            .with_tls(tls_config())
+            .with_protocol(Protocol::Http2)
+            .with_tls_name(name.clone());
+        assert_eq!(bind_error(&tls, "10.1.2.3:80"), Some(BindError::TlsConfig(name)));
     }
 
-    pub fn is_cachable(&self) -> bool {
-        match *self {
-            Protocol::Http2 | Protocol::Http1(Host::Authority(_)) => true,
-            _ => false,
+    #[test]
+    fn binding_with_unresolvable_host_fails() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(core.handle())
+            .with_ctx(ctx)
+            .with_tls(tls_config())
+            .with_protocol(Protocol::Http1(authority("bad!host.test:80")));
+
+        assert_eq!(
+            bind_error(&bind, "10.1.2.3:80"),
+            Some(BindError::ResolutionFailed("bad!host.test".to_owned()))
+        );
+
+        // Undiscovered endpoints aren't connected to with TLS, so the host
+        // needn't be resolved.
+        assert!(bind.bind_undiscovered(&"10.1.2.3:80".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn connect_times_out() {
+        let mut core = Core::new().unwrap();
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(core.handle())
+            .with_ctx(ctx)
+            .with_connect_timeout(Duration::from_millis(100));
+
+        // A non-routable address, so the handshake never completes.
+        let addr = "10.255.255.1:80".parse().unwrap();
+        let mut svc = bind.bind_service(&addr, &Protocol::Http2);
+
+        let start = Instant::now();
+        let res = core.run(future::poll_fn(|| svc.poll_ready()));
+        assert!(res.is_err(), "connect should not succeed");
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "connect should fail within the timeout; took {:?}",
+            start.elapsed()
+        );
+    }
+
+    /// Responds to every request with `201 Created` and an `x-mock` header.
+    struct Created;
+
+    impl hyper::server::Service for Created {
+        type Request = hyper::server::Request;
+        type Response = hyper::server::Response;
+        type Error = hyper::Error;
+        type Future = future::FutureResult<Self::Response, hyper::Error>;
+
+        fn call(&self, _: Self::Request) -> Self::Future {
+            let mut rsp = hyper::server::Response::new()
+                .with_status(hyper::StatusCode::Created);
+            rsp.headers_mut().set_raw("x-mock", "served");
+            future::ok(rsp)
         }
     }
 
-    pub fn into_key<T>(self, key: T) -> Reuse<(T, Protocol)> {
-        if self.is_cachable() {
-            Reuse::Reusable((key, self))
-        } else {
-            Reuse::SingleUse((key, self))
+    #[test]
+    fn proxies_requests_through_a_mock_transport() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        // Nothing listens on this address; connections to it are in-memory.
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let h1 = hyper::server::Http::<hyper::Chunk>::new();
+        let server_handle = handle.clone();
+        let _listening = transport::mock::listen(addr, transport::mock::Connect::new(move |io| {
+            server_handle.spawn(h1.serve_connection(io, Created).map_err(|_| ()));
+        }));
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone()).with_ctx(ctx);
+        let mut svc = bind.bind_service(&addr, &Protocol::Http1(Host::NoAuthority));
+
+        let req = http::Request::get("http://example.com/").body(()).unwrap();
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        let rsp = core.run(svc.call(req)).ok().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::CREATED);
+        assert_eq!(rsp.headers()["x-mock"], "served");
+    }
+
+    #[test]
+    fn proxied_requests_describe_their_bound_endpoints() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.4:8080".parse().unwrap();
+        let h1 = hyper::server::Http::<hyper::Chunk>::new();
+        let server_handle = handle.clone();
+        let _listening = transport::mock::listen(addr, transport::mock::Connect::new(move |io| {
+            server_handle.spawn(h1.serve_connection(io, Created).map_err(|_| ()));
+        }));
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone()).with_ctx(ctx);
+        let protocol = Protocol::Http1(Host::Authority("example.com".parse().unwrap()));
+        let mut svc = bind.bind_service(&addr, &protocol);
+
+        let req = http::Request::get("http://example.com/").body(()).unwrap();
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        let rsp = core.run(svc.call(req)).ok().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::CREATED);
+
+        let endpoint = rsp.extensions().get::<BoundEndpoint>().expect("bound endpoint");
+        assert_eq!(endpoint.addr(), &addr);
+        assert_eq!(endpoint.protocol(), &protocol);
+        assert!(!endpoint.is_tls());
+    }
+
+    #[test]
+    fn responses_with_oversized_headers_fail() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.8:8080".parse().unwrap();
+        let h1 = hyper::server::Http::<hyper::Chunk>::new();
+        let server_handle = handle.clone();
+        let _listening = transport::mock::listen(addr, transport::mock::Connect::new(move |io| {
+            server_handle.spawn(h1.serve_connection(io, Created).map_err(|_| ()));
+        }));
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone()).with_ctx(ctx);
+        let protocol = Protocol::Http1(Host::Authority("example.com".parse().unwrap()));
+
+        // The `x-mock: served` header line alone is 16 bytes long.
+        let limited = bind.clone().with_max_header_line_bytes(15);
+        let mut svc = limited.bind_service(&addr, &protocol);
+        let req = http::Request::get("http://example.com/").body(()).unwrap();
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        assert!(core.run(svc.call(req)).is_err(), "response must fail");
+
+        let generous = bind.with_max_header_bytes(1024).with_max_header_line_bytes(256);
+        let mut svc = generous.bind_service(&addr, &protocol);
+        let req = http::Request::get("http://example.com/").body(()).unwrap();
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        let rsp = core.run(svc.call(req)).ok().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::CREATED);
+    }
+
+    /// Responds to every request with a gRPC message, followed by trailers.
+    struct GrpcMessage;
+
+    /// A body of one message and then its trailers.
+    #[derive(Debug, Default)]
+    struct Trailed {
+        data: Option<Bytes>,
+        trailers: Option<http::HeaderMap>,
+    }
+
+    const MESSAGE: &[u8] = b"\0\0\0\0\x05hello";
+
+    impl Service for GrpcMessage {
+        type Request = http::Request<tower_h2::RecvBody>;
+        type Response = http::Response<Trailed>;
+        type Error = h2::Error;
+        type Future = future::FutureResult<Self::Response, h2::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", "3".parse().unwrap());
+            trailers.insert("grpc-message", "invalid%20argument".parse().unwrap());
+            trailers.append("x-checksum", "first".parse().unwrap());
+            trailers.append("x-checksum", "second".parse().unwrap());
+            let body = Trailed {
+                data: Some(Bytes::from(MESSAGE)),
+                trailers: Some(trailers),
+            };
+            let rsp = http::Response::builder()
+                .header("content-type", "application/grpc")
+                .body(body)
+                .unwrap();
+            future::ok(rsp)
+        }
+    }
+
+    impl NewService for GrpcMessage {
+        type Request = http::Request<tower_h2::RecvBody>;
+        type Response = http::Response<Trailed>;
+        type Error = h2::Error;
+        type InitError = ::std::io::Error;
+        type Service = GrpcMessage;
+        type Future = future::FutureResult<GrpcMessage, Self::InitError>;
+
+        fn new_service(&self) -> Self::Future {
+            future::ok(GrpcMessage)
+        }
+    }
+
+    impl Body for Trailed {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    /// Serves `GrpcMessage` over HTTP/2 on `addr`, counting the connections
+    /// opened to it.
+    fn serve_h2(
+        handle: &Handle,
+        addr: SocketAddr,
+    ) -> (transport::mock::Listening, Rc<Cell<usize>>) {
+        let connections = Rc::new(Cell::new(0));
+        let h2 = tower_h2::Server::new(GrpcMessage, Default::default(), handle.clone());
+        let server_handle = handle.clone();
+        let opened = connections.clone();
+        let listening = transport::mock::listen(addr, transport::mock::Connect::new(move |io| {
+            opened.set(opened.get() + 1);
+            server_handle.spawn(h2.serve(io).map_err(|_| ()));
+        }));
+        (listening, connections)
+    }
+
+    /// Describes `req` as accepted by the `proxy` server on `addr`, so that
+    /// the sensors record its telemetry.
+    fn accepted<B>(req: &mut http::Request<B>, proxy: &Arc<ctx::Proxy>, addr: &SocketAddr) {
+        let server = ctx::transport::Server::new(
+            proxy,
+            addr,
+            addr,
+            &None,
+            conduit_proxy_controller_grpc::common::Protocol::Http,
+        );
+        req.extensions_mut().insert(server);
+        req.extensions_mut().insert(sensor::http::RequestOpen(Instant::now()));
+    }
+
+    fn run_for(core: &mut Core, duration: Duration) {
+        let timeout = ReactorTimeout::new(duration, &core.handle()).unwrap();
+        core.run(timeout).unwrap();
+    }
+
+    #[test]
+    fn forwards_http2_response_trailers() {
+        use telemetry::events::{LifecycleEvent, RequestEvent};
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.3:8080".parse().unwrap();
+        let (_listening, _) = serve_h2(&handle, addr);
+
+        // The response body is wrapped by the sensors, so that they record
+        // its end, as well as by each of the stack's other layers.
+        let sensors = telemetry::Sensors::null();
+        let mut events = sensors.subscribe(10);
+        let proxy = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone())
+            .with_sensors(sensors)
+            .with_ctx(proxy.clone());
+        let mut svc = bind.bind_service(&addr, &Protocol::Http2);
+
+        let mut req = http::Request::post("http://example.com/svc/Method").body(()).unwrap();
+        accepted(&mut req, &proxy, &addr);
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        let rsp = core.run(svc.call(req)).ok().expect("response");
+        assert_eq!(rsp.headers()["content-type"], "application/grpc");
+
+        let mut body = rsp.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = core.run(future::poll_fn(|| body.poll_data())).expect("data") {
+            data.extend_from_slice(&chunk.into_buf().collect::<Bytes>());
+        }
+        assert_eq!(&data[..], MESSAGE);
+
+        let trailers = core.run(future::poll_fn(|| body.poll_trailers()))
+            .expect("trailers")
+            .expect("response has trailers");
+        assert_eq!(trailers.len(), 4);
+        assert_eq!(trailers["grpc-status"], "3");
+        assert_eq!(trailers["grpc-message"], "invalid%20argument");
+        let checksums = trailers.get_all("x-checksum")
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(checksums, vec!["first", "second"]);
+        drop(body);
+
+        // Having read the trailers, the response ended rather than being
+        // canceled when it was dropped.
+        let ended = future::lazy(|| loop {
+            match events.poll()? {
+                Async::Ready(Some(LifecycleEvent::Request(RequestEvent::Ended {
+                    status,
+                    error,
+                    ..
+                }))) => return Ok::<_, ()>((status, error)),
+                Async::Ready(Some(_)) => {}
+                _ => panic!("the request did not end"),
+            }
+        }).wait().unwrap();
+        assert_eq!(ended, (Some(http::StatusCode::OK), None));
+    }
+
+    /// Records the trailers of each request's body, responding once they've
+    /// been read.
+    #[derive(Clone)]
+    struct RecordTrailers(Rc<RefCell<Option<http::HeaderMap>>>);
+
+    struct ReadTrailers {
+        body: tower_h2::RecvBody,
+        trailers: Rc<RefCell<Option<http::HeaderMap>>>,
+    }
+
+    impl Service for RecordTrailers {
+        type Request = http::Request<tower_h2::RecvBody>;
+        type Response = http::Response<Trailed>;
+        type Error = h2::Error;
+        type Future = ReadTrailers;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            ReadTrailers {
+                body: req.into_body(),
+                trailers: self.0.clone(),
+            }
+        }
+    }
+
+    impl NewService for RecordTrailers {
+        type Request = http::Request<tower_h2::RecvBody>;
+        type Response = http::Response<Trailed>;
+        type Error = h2::Error;
+        type InitError = ::std::io::Error;
+        type Service = RecordTrailers;
+        type Future = future::FutureResult<RecordTrailers, Self::InitError>;
+
+        fn new_service(&self) -> Self::Future {
+            future::ok(self.clone())
+        }
+    }
+
+    impl Future for ReadTrailers {
+        type Item = http::Response<Trailed>;
+        type Error = h2::Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, h2::Error> {
+            while let Some(_) = try_ready!(self.body.poll_data()) {}
+            let trailers = try_ready!(self.body.poll_trailers());
+            *self.trailers.borrow_mut() = trailers;
+            Ok(Async::Ready(http::Response::new(Trailed::default())))
+        }
+    }
+
+    #[test]
+    fn forwards_http2_request_trailers() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.3:8080".parse().unwrap();
+        let recorded = Rc::new(RefCell::new(None));
+        let h2 = tower_h2::Server::new(
+            RecordTrailers(recorded.clone()),
+            Default::default(),
+            handle.clone(),
+        );
+        let server_handle = handle.clone();
+        let _listening = transport::mock::listen(addr, transport::mock::Connect::new(move |io| {
+            server_handle.spawn(h2.serve(io).map_err(|_| ()));
+        }));
+
+        // The request body is wrapped by the sensors, as well as by each of
+        // the stack's other layers.
+        let proxy = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, Trailed>::new(handle.clone()).with_ctx(proxy.clone());
+        let mut svc = bind.bind_service(&addr, &Protocol::Http2);
+
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-timeout", "1S".parse().unwrap());
+        trailers.append("x-checksum", "first".parse().unwrap());
+        trailers.append("x-checksum", "second".parse().unwrap());
+        let body = Trailed {
+            data: Some(Bytes::from(MESSAGE)),
+            trailers: Some(trailers.clone()),
+        };
+        let mut req = http::Request::post("http://example.com/svc/Method").body(body).unwrap();
+        accepted(&mut req, &proxy, &addr);
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        let rsp = core.run(svc.call(req)).ok().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+
+        let received = recorded.borrow_mut().take().expect("request has trailers");
+        assert_eq!(received, trailers);
+        let checksums = received.get_all("x-checksum")
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(checksums, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn selects_sensors_by_direction() {
+        use http::header::AUTHORIZATION;
+        use telemetry::events::{LifecycleEvent, RequestEvent};
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.7:8080".parse().unwrap();
+        let h1 = hyper::server::Http::<hyper::Chunk>::new();
+        let server_handle = handle.clone();
+        let _listening = transport::mock::listen(addr, transport::mock::Connect::new(move |io| {
+            server_handle.spawn(h1.serve_connection(io, Created).map_err(|_| ()));
+        }));
+
+        // Only inbound telemetry redacts credentials.
+        let sensors = telemetry::Sensors::null();
+        let mut events = sensors.subscribe(10);
+        let bind = Bind::<_, ()>::new(handle.clone())
+            .with_sensors(sensors.clone())
+            .with_inbound_sensors(sensors.with_redacted_headers(vec![AUTHORIZATION]));
+
+        let process = ctx::Process::test("test");
+        let mut recorded = Vec::new();
+        for proxy in vec![ctx::Proxy::inbound(&process), ctx::Proxy::outbound(&process)] {
+            let mut svc = bind.clone()
+                .with_ctx(proxy.clone())
+                .bind_service(&addr, &Protocol::Http1(Host::NoAuthority));
+            let mut req = http::Request::get("http://example.com/")
+                .header(AUTHORIZATION, "secret")
+                .body(())
+                .unwrap();
+            accepted(&mut req, &proxy, &addr);
+            core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+            core.run(svc.call(req)).ok().expect("response");
+
+            let started = core.run(future::lazy(|| loop {
+                match events.poll()? {
+                    Async::Ready(Some(LifecycleEvent::Request(RequestEvent::Started(req)))) => {
+                        return Ok::<_, ()>(req);
+                    }
+                    Async::Ready(Some(_)) => {}
+                    _ => panic!("the request did not start"),
+                }
+            })).unwrap();
+            let authorization = started.headers[AUTHORIZATION].to_str().unwrap().to_owned();
+            recorded.push((started.client.proxy.is_inbound(), authorization));
+        }
+
+        assert_eq!(recorded, vec![
+            (true, "[REDACTED]".to_owned()),
+            (false, "secret".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn prewarmed_endpoints_connect_before_requests() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.4:8080".parse().unwrap();
+        let (_listening, connections) = serve_h2(&handle, addr);
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone())
+            .with_ctx(ctx)
+            .with_prewarm(true);
+        let mut svc = bind.bind_service(&addr, &Protocol::Http2);
+
+        run_for(&mut core, Duration::from_millis(50));
+        assert_eq!(connections.get(), 1, "connected before any request was sent");
+
+        // The first request is sent on the warm connection.
+        let req = http::Request::get("http://example.com/").body(()).unwrap();
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        let rsp = core.run(svc.call(req)).ok().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(connections.get(), 1);
+    }
+
+    #[test]
+    fn endpoints_connect_on_first_request_unless_prewarmed() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.5:8080".parse().unwrap();
+        let (_listening, connections) = serve_h2(&handle, addr);
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone()).with_ctx(ctx);
+        let mut svc = bind.bind_service(&addr, &Protocol::Http2);
+
+        run_for(&mut core, Duration::from_millis(50));
+        assert_eq!(connections.get(), 0);
+
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        assert_eq!(connections.get(), 1);
+    }
+
+    #[test]
+    fn concurrent_requests_to_a_cold_endpoint_share_one_connection() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.9:8080".parse().unwrap();
+        let (_listening, connections) = serve_h2(&handle, addr);
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone())
+            .with_ctx(ctx)
+            .with_connect_concurrency(1);
+        // Requests are buffered, as they are by the router, until the
+        // service is ready.
+        let mut svc = tower_buffer::Buffer::new(bind.bind_service(&addr, &Protocol::Http2), &handle)
+            .ok()
+            .expect("buffer");
+
+        // Every request is dispatched before the connection is established.
+        let requests = (0..10)
+            .map(|_| {
+                let req = http::Request::get("http://example.com/").body(()).unwrap();
+                svc.call(req).map(|rsp| rsp.status())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(connections.get(), 0);
+
+        let statuses = core.run(future::join_all(requests)).ok().expect("responses");
+        assert!(statuses.iter().all(|s| *s == http::StatusCode::OK));
+        assert_eq!(connections.get(), 1, "only one handshake occurs");
+    }
+
+    #[test]
+    fn prewarmed_connections_are_replaced_after_idling() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.6:8080".parse().unwrap();
+        let (_listening, connections) = serve_h2(&handle, addr);
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone())
+            .with_ctx(ctx)
+            .with_idle_timeout(Duration::from_millis(20))
+            .with_prewarm(true);
+        let _svc = bind.bind_service(&addr, &Protocol::Http2);
+
+        run_for(&mut core, Duration::from_millis(200));
+        assert!(
+            connections.get() >= 2,
+            "idle connections should be replaced; opened {}",
+            connections.get()
+        );
+    }
+
+    /// Reads the body of each request, except those to `/reject`, which
+    /// are refused before their bodies are read.
+    struct ContinueOrReject;
+
+    impl hyper::server::Service for ContinueOrReject {
+        type Request = hyper::server::Request;
+        type Response = hyper::server::Response;
+        type Error = hyper::Error;
+        type Future = Box<Future<Item = Self::Response, Error = hyper::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            if req.path() == "/reject" {
+                let rsp = hyper::server::Response::new()
+                    .with_status(hyper::StatusCode::ExpectationFailed);
+                return Box::new(future::ok(rsp));
+            }
+
+            // hyper responds `100 Continue` once the body is read.
+            let expects = req.headers().get_raw("expect").is_some();
+            Box::new(req.body().concat2().map(move |body| {
+                let status = if expects && &body[..] == b"hello" {
+                    hyper::StatusCode::Ok
+                } else {
+                    hyper::StatusCode::BadRequest
+                };
+                hyper::server::Response::new().with_status(status)
+            }))
+        }
+    }
+
+    /// A request body that records whether it has been read.
+    #[derive(Debug, Default)]
+    struct Watched {
+        data: Option<Bytes>,
+        read: Rc<Cell<bool>>,
+    }
+
+    impl tower_h2::Body for Watched {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            self.read.set(true);
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    /// Sends a request that expects `100 Continue` to `path` on a mock
+    /// `ContinueOrReject` server, returning the response's status and
+    /// whether the request's body was read.
+    fn expect_continue(path: &str) -> (http::StatusCode, bool) {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "10.1.1.2:8080".parse().unwrap();
+        let h1 = hyper::server::Http::<hyper::Chunk>::new();
+        let server_handle = handle.clone();
+        let _listening = transport::mock::listen(addr, transport::mock::Connect::new(move |io| {
+            server_handle.spawn(h1.serve_connection(io, ContinueOrReject).map_err(|_| ()));
+        }));
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, Watched>::new(handle.clone()).with_ctx(ctx);
+        let mut svc = bind.bind_service(&addr, &Protocol::Http1(Host::NoAuthority));
+
+        let read = Rc::new(Cell::new(false));
+        let body = Watched {
+            data: Some(Bytes::from("hello")),
+            read: read.clone(),
+        };
+        let req = http::Request::post(format!("http://example.com{}", path).as_str())
+            .header("expect", "100-continue")
+            .header("content-length", "5")
+            .body(body)
+            .unwrap();
+        core.run(future::poll_fn(|| svc.poll_ready())).ok().expect("ready");
+        let rsp = core.run(svc.call(req)).ok().expect("response");
+        (rsp.status(), read.get())
+    }
+
+    #[test]
+    fn request_bodies_are_sent_once_upstreams_continue() {
+        let (status, read) = expect_continue("/");
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(read);
+    }
+
+    #[test]
+    fn request_bodies_are_not_read_if_upstreams_respond_early() {
+        let (status, read) = expect_continue("/reject");
+        assert_eq!(status, http::StatusCode::EXPECTATION_FAILED);
+        assert!(!read, "the client must not be told to continue");
+    }
+
+    /// Responds immediately to requests for `/fast`, and never to others.
+    struct FastOrNever;
+
+    impl hyper::server::Service for FastOrNever {
+        type Request = hyper::server::Request;
+        type Response = hyper::server::Response;
+        type Error = hyper::Error;
+        type Future = Box<Future<Item = Self::Response, Error = hyper::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            if req.path() == "/fast" {
+                Box::new(future::ok(hyper::server::Response::new()))
+            } else {
+                Box::new(future::empty())
+            }
+        }
+    }
+
+    fn is_ready<S: Service>(svc: &mut S) -> bool {
+        match svc.poll_ready() {
+            Ok(Async::Ready(())) => true,
+            Ok(Async::NotReady) => false,
+            Err(_) => panic!("service failed"),
+        }
+    }
+
+    #[test]
+    fn concurrency_limit_is_enforced_and_released() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let h1 = hyper::server::Http::<hyper::Chunk>::new();
+        let server_handle = handle.clone();
+        let serve = listener.incoming().for_each(move |(sock, _)| {
+            server_handle.spawn(h1.serve_connection(sock, FastOrNever).map_err(|_| ()));
+            Ok(())
+        });
+        handle.spawn(serve.map_err(|e| panic!("server failed: {}", e)));
+
+        let ctx = ctx::Proxy::outbound(&ctx::Process::test("test"));
+        let bind = Bind::<_, ()>::new(handle.clone())
+            .with_ctx(ctx)
+            .with_concurrency_limit(2);
+        let mut svc = bind.bind_service(&addr, &Protocol::Http1(Host::NoAuthority));
+        let get = |path: &str| {
+            http::Request::get(format!("http://{}{}", addr, path).as_str())
+                .body(())
+                .unwrap()
+        };
+
+        let (slow, fast) = core.run(future::lazy(|| {
+            assert!(is_ready(&mut svc));
+            let slow = svc.call(get("/slow"));
+            assert!(is_ready(&mut svc));
+            let fast = svc.call(get("/fast"));
+            assert!(!is_ready(&mut svc), "service should not be ready at its limit");
+            Ok::<_, ()>((slow, fast))
+        })).unwrap();
+
+        assert!(core.run(fast).is_ok(), "request within the limit should succeed");
+        let ready = core.run(future::lazy(|| Ok::<_, ()>(is_ready(&mut svc)))).unwrap();
+        assert!(ready, "completed request should release its slot");
+
+        // The slot just reserved is in use, as is the slow request's.
+        let _another = svc.call(get("/slow"));
+        let ready = core.run(future::lazy(|| Ok::<_, ()>(is_ready(&mut svc)))).unwrap();
+        assert!(!ready);
+
+        drop(slow);
+        let ready = core.run(future::lazy(|| Ok::<_, ()>(is_ready(&mut svc)))).unwrap();
+        assert!(ready, "dropped request should release its slot");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn protocols_round_trip_through_serde() {
+        let protocols = vec![
+            Protocol::Http1(Host::Authority("example.com:8080".parse().unwrap())),
+            Protocol::Http1(Host::NoAuthority),
+            Protocol::Http1Upgrade(Host::Authority("10.1.1.1".parse().unwrap())),
+            Protocol::Http2,
+        ];
+        let json = serde_json::to_string(&protocols).expect("serialize");
+        assert_eq!(
+            json,
+            "[{\"Http1\":\"example.com:8080\"},{\"Http1\":null},\
+             {\"Http1Upgrade\":\"10.1.1.1\"},\"Http2\"]"
+        );
+        let parsed: Vec<Protocol> = serde_json::from_str(&json).expect("deserialize");
+        assert_eq!(parsed, protocols);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn malformed_authorities_are_not_deserialized() {
+        for json in &[
+            "{\"Http1\":\"bad authority\"}",
+            "{\"Http1\":\"example.com/path\"}",
+            "{\"Http1\":\"http://example.com\"}",
+            "{\"Http1\":\"\"}",
+            "{\"Http3\":null}",
+        ] {
+            assert!(serde_json::from_str::<Protocol>(json).is_err(), "{} should not parse", json);
         }
     }
 }
@@ -0,0 +1,483 @@
+use bytes::{Buf, IntoBuf};
+use futures::{Async, Future, Poll};
+use futures::future::{self, Either, FutureResult};
+use h2;
+use http;
+use http::header::CONTENT_LENGTH;
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use replay::{Buffered, FromBuffered};
+
+/// Bounds the size of each response body.
+///
+/// A response that declares a `Content-Length` greater than `max_bytes` fails
+/// before its body is streamed. Otherwise, the body fails once more than
+/// `max_bytes` of data have been received, so that the rest of the response
+/// is not read.
+///
+/// If constructed without a limit, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct ResponseBodyLimit<S> {
+    inner: S,
+    max_bytes: Option<u64>,
+}
+
+/// Wraps the inner `NewService`'s services in `ResponseBodyLimit`s.
+pub struct Init<F> {
+    future: F,
+    max_bytes: Option<u64>,
+}
+
+/// Bounds the size of each request body.
+///
+/// A request body fails once more than `max_bytes` of data have been sent,
+/// so that a request streaming more than it is allowed is aborted rather than
+/// sent in full.
+///
+/// If constructed without a limit, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct RequestBodyLimit<S> {
+    inner: S,
+    max_bytes: Option<u64>,
+}
+
+/// Wraps the inner `NewService`'s services in `RequestBodyLimit`s.
+pub struct InitRequestBodyLimit<F> {
+    future: F,
+    max_bytes: Option<u64>,
+}
+
+/// Responds with `413 Payload Too Large` to each request that declares a
+/// `Content-Length` greater than `max_bytes`, without dispatching it to the
+/// inner service.
+///
+/// If constructed without a limit, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct RequestLengthLimit<S> {
+    inner: S,
+    max_bytes: Option<u64>,
+}
+
+/// Fails if the response declares a body that is too large.
+pub struct ResponseFuture<F> {
+    inner: F,
+    max_bytes: Option<u64>,
+}
+
+/// Fails once more than `max_bytes` of data have been streamed.
+#[derive(Debug, Default)]
+pub struct LimitedBody<B> {
+    inner: B,
+    max_bytes: Option<u64>,
+    received: u64,
+}
+
+// ===== impl ResponseBodyLimit =====
+
+impl<S> ResponseBodyLimit<S> {
+    pub fn new(inner: S, max_bytes: Option<u64>) -> Self {
+        ResponseBodyLimit { inner, max_bytes }
+    }
+}
+
+impl<N, A, B> NewService for ResponseBodyLimit<N>
+where
+    N: NewService<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    N::Error: From<h2::Reason>,
+    B: Body,
+{
+    type Request = N::Request;
+    type Response = http::Response<LimitedBody<B>>;
+    type Error = N::Error;
+    type Service = ResponseBodyLimit<N::Service>;
+    type InitError = N::InitError;
+    type Future = Init<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        Init {
+            future: self.inner.new_service(),
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+impl<S, A, B> Service for ResponseBodyLimit<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    S::Error: From<h2::Reason>,
+    B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<LimitedBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(req),
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+// ===== impl Init =====
+
+impl<F> Future for Init<F>
+where
+    F: Future,
+{
+    type Item = ResponseBodyLimit<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(ResponseBodyLimit::new(inner, self.max_bytes)))
+    }
+}
+
+// ===== impl RequestBodyLimit =====
+
+impl<S> RequestBodyLimit<S> {
+    pub fn new(inner: S, max_bytes: Option<u64>) -> Self {
+        RequestBodyLimit { inner, max_bytes }
+    }
+}
+
+impl<N, A> NewService for RequestBodyLimit<N>
+where
+    N: NewService<Request = http::Request<LimitedBody<A>>>,
+    A: Body,
+{
+    type Request = http::Request<A>;
+    type Response = N::Response;
+    type Error = N::Error;
+    type Service = RequestBodyLimit<N::Service>;
+    type InitError = N::InitError;
+    type Future = InitRequestBodyLimit<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        InitRequestBodyLimit {
+            future: self.inner.new_service(),
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+impl<S, A> Service for RequestBodyLimit<S>
+where
+    S: Service<Request = http::Request<LimitedBody<A>>>,
+    A: Body,
+{
+    type Request = http::Request<A>;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let max_bytes = self.max_bytes;
+        self.inner.call(req.map(|inner| LimitedBody::new(inner, max_bytes)))
+    }
+}
+
+// ===== impl InitRequestBodyLimit =====
+
+impl<F> Future for InitRequestBodyLimit<F>
+where
+    F: Future,
+{
+    type Item = RequestBodyLimit<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(RequestBodyLimit::new(inner, self.max_bytes)))
+    }
+}
+
+// ===== impl RequestLengthLimit =====
+
+impl<S> RequestLengthLimit<S> {
+    pub fn new(inner: S, max_bytes: Option<u64>) -> Self {
+        RequestLengthLimit { inner, max_bytes }
+    }
+}
+
+impl<S, A, B> Service for RequestLengthLimit<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Default,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<FutureResult<Self::Response, Self::Error>, S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        if let (Some(len), Some(max_bytes)) = (content_length(req.headers()), self.max_bytes) {
+            if len > max_bytes {
+                debug!("request declares {} bytes; limit is {}", len, max_bytes);
+                let rsp = http::Response::builder()
+                    .status(http::StatusCode::PAYLOAD_TOO_LARGE)
+                    .header(CONTENT_LENGTH, "0")
+                    .body(B::default())
+                    .expect("payload too large response must be valid");
+                return Either::A(future::ok(rsp));
+            }
+        }
+
+        Either::B(self.inner.call(req))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: From<h2::Reason>,
+    B: Body,
+{
+    type Item = http::Response<LimitedBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = try_ready!(self.inner.poll());
+
+        if let Some(max_bytes) = self.max_bytes {
+            // A response to a `HEAD` request may declare a length without
+            // having a body.
+            match content_length(rsp.headers()) {
+                Some(len) if len > max_bytes && !rsp.body().is_end_stream() => {
+                    debug!("response declares {} bytes; limit is {}", len, max_bytes);
+                    return Err(h2::Reason::CANCEL.into());
+                }
+                _ => {}
+            }
+        }
+
+        let max_bytes = self.max_bytes;
+        Ok(Async::Ready(rsp.map(|inner| LimitedBody::new(inner, max_bytes))))
+    }
+}
+
+// ===== impl LimitedBody =====
+
+impl<B> LimitedBody<B> {
+    fn new(inner: B, max_bytes: Option<u64>) -> Self {
+        LimitedBody {
+            inner,
+            max_bytes,
+            received: 0,
+        }
+    }
+}
+
+/// Bodies recreated from a buffered copy are not limited, since a body is
+/// buffered only if it fits within the limit.
+impl<B: FromBuffered> FromBuffered for LimitedBody<B> {
+    fn from_buffered(buffered: Buffered) -> Self {
+        LimitedBody::new(B::from_buffered(buffered), None)
+    }
+}
+
+impl<B> Body for LimitedBody<B>
+where
+    B: Body,
+{
+    type Data = <B::Data as IntoBuf>::Buf;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        let data = try_ready!(self.inner.poll_data()).map(IntoBuf::into_buf);
+
+        if let (Some(data), Some(max_bytes)) = (data.as_ref(), self.max_bytes) {
+            self.received += data.remaining() as u64;
+            if self.received > max_bytes {
+                debug!("body exceeded {} bytes", max_bytes);
+                return Err(h2::Reason::CANCEL.into());
+            }
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+fn content_length(headers: &http::HeaderMap) -> Option<u64> {
+    headers.get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use test_support::{Chunks, Upstream};
+    use super::*;
+
+    /// An upstream that responds with `chunks`, declaring a `Content-Length`
+    /// if one is given.
+    fn upstream(chunks: &'static [&'static str], content_length: Option<u64>) -> Upstream {
+        let mut headers = http::HeaderMap::new();
+        if let Some(len) = content_length {
+            headers.insert(CONTENT_LENGTH, len.into());
+        }
+        Upstream::with_body(headers, chunks)
+    }
+
+    fn call<S>(svc: &mut S) -> Result<S::Response, S::Error>
+    where
+        S: Service<Request = http::Request<()>>,
+    {
+        svc.call(http::Request::new(())).wait()
+    }
+
+    /// Reads `body` to the end, returning the number of bytes read.
+    fn read<B: Body>(body: &mut B) -> Result<usize, h2::Error> {
+        let mut read = 0;
+        while let Async::Ready(Some(data)) = body.poll_data()? {
+            read += data.into_buf().remaining();
+        }
+        Ok(read)
+    }
+
+    #[test]
+    fn streamed_body_over_limit_fails() {
+        let upstream = upstream(&["hello ", "world"], None);
+        let mut svc = ResponseBodyLimit::new(upstream, Some(8));
+
+        let mut body = call(&mut svc).expect("response").into_body();
+        let err = read(&mut body).expect_err("body should exceed the limit");
+        assert_eq!(err.reason(), Some(h2::Reason::CANCEL));
+    }
+
+    #[test]
+    fn declared_length_over_limit_fails_before_streaming() {
+        let upstream = upstream(&["hello world"], Some(11));
+        let mut svc = ResponseBodyLimit::new(upstream, Some(8));
+
+        let err = call(&mut svc).err().expect("response should exceed the limit");
+        assert_eq!(err.reason(), Some(h2::Reason::CANCEL));
+    }
+
+    #[test]
+    fn bodies_within_limit_are_streamed() {
+        let upstream = upstream(&["hello ", "world"], Some(11));
+        let mut svc = ResponseBodyLimit::new(upstream, Some(11));
+
+        let mut body = call(&mut svc).expect("response").into_body();
+        assert_eq!(read(&mut body).expect("body"), 11);
+    }
+
+    #[test]
+    fn declared_length_without_body_is_allowed() {
+        // e.g. a response to a `HEAD` request.
+        let upstream = upstream(&[], Some(1024));
+        let mut svc = ResponseBodyLimit::new(upstream, Some(8));
+        assert!(call(&mut svc).is_ok());
+    }
+
+    #[test]
+    fn unlimited_bodies_are_streamed() {
+        let upstream = upstream(&["hello ", "world"], Some(11));
+        let mut svc = ResponseBodyLimit::new(upstream, None);
+
+        let mut body = call(&mut svc).expect("response").into_body();
+        assert_eq!(read(&mut body).expect("body"), 11);
+    }
+
+    /// An endpoint that reads each request's body before responding with
+    /// the number of bytes it read.
+    struct Reader<B> {
+        calls: usize,
+        _p: PhantomData<B>,
+    }
+
+    impl<B> Reader<B> {
+        fn new() -> Self {
+            Reader {
+                calls: 0,
+                _p: PhantomData,
+            }
+        }
+    }
+
+    impl<B: Body> Service for Reader<B> {
+        type Request = http::Request<B>;
+        type Response = http::Response<usize>;
+        type Error = h2::Error;
+        type Future = FutureResult<Self::Response, h2::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            self.calls += 1;
+            let mut body = req.into_body();
+            future::result(read(&mut body).map(http::Response::new))
+        }
+    }
+
+    fn request(chunks: Vec<&'static str>, content_length: Option<u64>) -> http::Request<Chunks> {
+        let mut req = http::Request::new(Chunks::new(&chunks[..]));
+        if let Some(len) = content_length {
+            req.headers_mut().insert(CONTENT_LENGTH, len.into());
+        }
+        req
+    }
+
+    #[test]
+    fn declared_request_length_over_limit_is_rejected_before_dispatch() {
+        let mut svc = RequestLengthLimit::new(Reader::new(), Some(8));
+
+        let rsp = svc.call(request(vec!["hello world"], Some(11))).wait().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(svc.inner.calls, 0);
+
+        let rsp = svc.call(request(vec!["hello"], Some(5))).wait().expect("response");
+        assert_eq!(*rsp.body(), 5);
+        assert_eq!(svc.inner.calls, 1);
+    }
+
+    #[test]
+    fn streamed_request_body_over_limit_fails() {
+        let mut svc = RequestBodyLimit::new(Reader::new(), Some(8));
+
+        let err = svc.call(request(vec!["hello ", "world"], None)).wait()
+            .expect_err("body should exceed the limit");
+        assert_eq!(err.reason(), Some(h2::Reason::CANCEL));
+
+        let rsp = svc.call(request(vec!["hello ", "wo"], None)).wait().expect("response");
+        assert_eq!(*rsp.body(), 8);
+    }
+}
@@ -0,0 +1,146 @@
+use std::net::SocketAddr;
+
+use futures::{Future, Poll};
+use http;
+use tower::Service;
+
+use bind::Protocol;
+
+/// Describes the endpoint that a request was bound to, once it's been
+/// routed and balanced.
+///
+/// `AnnotateEndpoint` adds this to each request's extensions, so that the
+/// layers beneath it, such as telemetry, can read it, and to each response's
+/// extensions, so that the layers above it can read it too.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BoundEndpoint {
+    addr: SocketAddr,
+    protocol: Protocol,
+    tls: bool,
+}
+
+/// Annotates requests, and their responses, with the `BoundEndpoint` they're
+/// sent to.
+#[derive(Clone, Debug)]
+pub struct AnnotateEndpoint<S> {
+    inner: S,
+    endpoint: BoundEndpoint,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    endpoint: Option<BoundEndpoint>,
+}
+
+// ===== impl BoundEndpoint =====
+
+impl BoundEndpoint {
+    pub fn new(addr: SocketAddr, protocol: Protocol, tls: bool) -> Self {
+        BoundEndpoint { addr, protocol, tls }
+    }
+
+    pub fn addr(&self) -> &SocketAddr {
+        &self.addr
+    }
+
+    /// The protocol with which requests are sent to the endpoint.
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    /// Whether requests are sent to the endpoint over TLS.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Returns the endpoint that `req` was bound to, if it's been bound.
+    pub fn of<B>(req: &http::Request<B>) -> Option<&Self> {
+        req.extensions().get::<Self>()
+    }
+}
+
+// ===== impl AnnotateEndpoint =====
+
+impl<S> AnnotateEndpoint<S> {
+    pub fn new(inner: S, endpoint: BoundEndpoint) -> Self {
+        AnnotateEndpoint { inner, endpoint }
+    }
+}
+
+impl<S, A, B> Service for AnnotateEndpoint<S>
+where
+    S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: Self::Request) -> Self::Future {
+        // A request that was bound before, such as one that's being hedged,
+        // is described by the endpoint it's now sent to.
+        req.extensions_mut().insert(self.endpoint.clone());
+        ResponseFuture {
+            inner: self.inner.call(req),
+            endpoint: Some(self.endpoint.clone()),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+        if let Some(endpoint) = self.endpoint.take() {
+            rsp.extensions_mut().insert(endpoint);
+        }
+        Ok(rsp.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bind::Host;
+    use super::*;
+    use test_support::Endpoint;
+
+    /// Returns the endpoint that each request `inner` was sent was bound to.
+    fn seen(inner: &Endpoint) -> Vec<Option<BoundEndpoint>> {
+        inner.requests().iter().map(|req| BoundEndpoint::of(req).cloned()).collect()
+    }
+
+    #[test]
+    fn annotates_requests_and_responses_with_their_endpoints() {
+        let inner = Endpoint::default();
+        let endpoint = BoundEndpoint::new(
+            "10.1.1.1:8080".parse().unwrap(),
+            Protocol::Http1(Host::Authority("example.com".parse().unwrap())),
+            true,
+        );
+        let mut svc = AnnotateEndpoint::new(inner.clone(), endpoint.clone());
+
+        let rsp = svc.call(http::Request::new(())).wait().unwrap();
+        assert_eq!(rsp.extensions().get::<BoundEndpoint>(), Some(&endpoint));
+        assert_eq!(seen(&inner), vec![Some(endpoint.clone())]);
+
+        // A request that was bound elsewhere is described by its new
+        // endpoint.
+        let addr = "10.1.1.2:8080".parse().unwrap();
+        let elsewhere = BoundEndpoint::new(addr, Protocol::Http2, false);
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(elsewhere);
+        svc.call(req).wait().unwrap();
+        assert_eq!(seen(&inner)[1], Some(endpoint));
+    }
+}
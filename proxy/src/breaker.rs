@@ -0,0 +1,633 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use futures::{task, Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+/// Settings for circuit breakers.
+#[derive(Copy, Clone, Debug)]
+pub struct BreakerConfig {
+    failure_threshold: usize,
+    open_timeout: Duration,
+}
+
+/// The circuit breaker state for each endpoint address.
+///
+/// Each address's state is held only weakly, so that it is dropped along with
+/// the last service bound to that address. Once discovery removes an endpoint
+/// and its services are dropped, its entry is pruned the next time a service
+/// is bound.
+#[derive(Clone, Debug, Default)]
+pub struct Breakers {
+    states: Arc<Mutex<HashMap<SocketAddr, Weak<Mutex<State>>>>>,
+}
+
+/// Fails requests to an endpoint quickly after it has failed repeatedly.
+///
+/// After `failure_threshold` consecutive requests fail, the breaker opens and
+/// the service is not ready; requests dispatched anyway fail immediately with
+/// `BreakerError::Open`. Once `open_timeout` has elapsed, the service is ready
+/// again and a single request is allowed through as a probe: if it succeeds,
+/// the breaker closes again; otherwise, it remains open for another
+/// `open_timeout`.
+///
+/// Only errors count as failures; error responses from a reachable endpoint
+/// do not.
+///
+/// If constructed without a `BreakerConfig`, this is a no-op.
+pub struct CircuitBreaker<S> {
+    inner: S,
+    breaker: Option<Breaker>,
+    /// Fires when the open breaker's probe is due.
+    timer: Option<ReactorTimeout>,
+}
+
+/// An error produced by a `CircuitBreaker`.
+#[derive(Debug)]
+pub enum BreakerError<E> {
+    /// Indicates that the breaker for the given endpoint is open.
+    Open(SocketAddr),
+    /// Indicates that the underlying service failed.
+    Error(E),
+}
+
+pub struct ResponseFuture<F> {
+    inner: Option<F>,
+    breaker: Option<Breaker>,
+    permit: Permit,
+}
+
+#[derive(Clone, Debug)]
+struct Breaker {
+    addr: SocketAddr,
+    config: BreakerConfig,
+    state: Arc<Mutex<State>>,
+    handle: Handle,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed { failures: usize },
+    Open { until: Instant },
+    /// A probe request is in flight; all other requests fail until it
+    /// completes, and the tasks waiting for it are notified when it does.
+    HalfOpen { waiting: Vec<task::Task> },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Permit {
+    Allowed,
+    Probe,
+    Rejected,
+}
+
+// ===== impl BreakerConfig =====
+
+impl BreakerConfig {
+    pub fn new(failure_threshold: usize, open_timeout: Duration) -> Self {
+        BreakerConfig {
+            failure_threshold,
+            open_timeout,
+        }
+    }
+}
+
+// ===== impl Breakers =====
+
+impl Breakers {
+    /// Wraps `inner` in a `CircuitBreaker` that shares its state with every
+    /// other service bound to `addr`.
+    pub fn circuit_breaker<S>(
+        &self,
+        addr: &SocketAddr,
+        inner: S,
+        config: Option<BreakerConfig>,
+        handle: &Handle,
+    ) -> CircuitBreaker<S> {
+        let breaker = config.map(|config| Breaker {
+            addr: *addr,
+            config,
+            state: self.state(addr),
+            handle: handle.clone(),
+        });
+        CircuitBreaker {
+            inner,
+            breaker,
+            timer: None,
+        }
+    }
+
+    fn state(&self, addr: &SocketAddr) -> Arc<Mutex<State>> {
+        let mut states = self.states.lock().expect("breaker states lock");
+
+        // Drop the state of endpoints that are no longer bound.
+        states.retain(|_, state| state.upgrade().is_some());
+
+        if let Some(state) = states.get(addr).and_then(Weak::upgrade) {
+            return state;
+        }
+
+        let state = Arc::new(Mutex::new(State::Closed { failures: 0 }));
+        states.insert(*addr, Arc::downgrade(&state));
+        state
+    }
+}
+
+// ===== impl CircuitBreaker =====
+
+impl<S> Service for CircuitBreaker<S>
+where
+    S: Service,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = BreakerError<S::Error>;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // While the breaker is open, requests would only fail, so the service
+        // isn't ready until a probe may be dispatched.
+        if let Some(ref breaker) = self.breaker {
+            if breaker.poll_open(&mut self.timer) {
+                return Ok(Async::NotReady);
+            }
+        }
+
+        let breaker = &self.breaker;
+        self.inner.poll_ready().map_err(|e| {
+            if let Some(ref breaker) = *breaker {
+                breaker.failure();
+            }
+            BreakerError::Error(e)
+        })
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let permit = self.breaker.as_ref()
+            .map(Breaker::acquire)
+            .unwrap_or(Permit::Allowed);
+
+        let inner = if permit == Permit::Rejected {
+            None
+        } else {
+            Some(self.inner.call(req))
+        };
+
+        ResponseFuture {
+            inner,
+            breaker: self.breaker.clone(),
+            permit,
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = BreakerError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.inner {
+            Some(ref mut f) => match f.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                result => result,
+            },
+            None => {
+                let addr = self.breaker.as_ref()
+                    .expect("only breakers reject requests")
+                    .addr;
+                return Err(BreakerError::Open(addr));
+            }
+        };
+
+        // The request has completed, so it no longer needs to be accounted
+        // for if this future is dropped.
+        self.inner = None;
+        self.permit = Permit::Allowed;
+
+        if let Some(ref breaker) = self.breaker {
+            match result {
+                Ok(_) => breaker.success(),
+                Err(_) => breaker.failure(),
+            }
+        }
+
+        result.map_err(BreakerError::Error)
+    }
+}
+
+impl<F> Drop for ResponseFuture<F> {
+    fn drop(&mut self) {
+        // If a probe is canceled, allow the next request to probe instead, so
+        // that the breaker doesn't remain half-open forever.
+        if self.permit == Permit::Probe && self.inner.is_some() {
+            if let Some(ref breaker) = self.breaker {
+                breaker.set(State::Open { until: Instant::now() });
+            }
+        }
+    }
+}
+
+// ===== impl Breaker =====
+
+impl Breaker {
+    /// Returns true if the breaker is open, in which case the current task is
+    /// notified once a probe may be dispatched.
+    fn poll_open(&self, timer: &mut Option<ReactorTimeout>) -> bool {
+        let mut state = self.state.lock().expect("breaker state lock");
+        let until = match *state {
+            State::Closed { .. } => {
+                *timer = None;
+                return false;
+            }
+            State::Open { until } => until,
+            State::HalfOpen { ref mut waiting } => {
+                *timer = None;
+                // A balancer polls its unready endpoints repeatedly, so each
+                // task waits for the probe only once.
+                if !waiting.iter().any(|t| t.will_notify_current()) {
+                    waiting.push(task::current());
+                }
+                return true;
+            }
+        };
+
+        if Instant::now() >= until {
+            *timer = None;
+            return false;
+        }
+
+        if timer.is_none() {
+            match ReactorTimeout::new_at(until, &self.handle) {
+                Ok(t) => *timer = Some(t),
+                // Without a timer, nothing would wake the balancer once the
+                // probe is due, so permit it now.
+                Err(e) => {
+                    warn!("could not create circuit breaker timer for {}: {}", self.addr, e);
+                    return false;
+                }
+            }
+        }
+
+        match timer.as_mut().map(Future::poll) {
+            Some(Ok(Async::NotReady)) => true,
+            _ => {
+                *timer = None;
+                false
+            }
+        }
+    }
+
+    fn acquire(&self) -> Permit {
+        let mut state = self.state.lock().expect("breaker state lock");
+        let permit = match *state {
+            State::Closed { .. } => Permit::Allowed,
+            State::Open { until } if Instant::now() >= until => Permit::Probe,
+            State::Open { .. } | State::HalfOpen { .. } => Permit::Rejected,
+        };
+        if permit == Permit::Probe {
+            debug!("probing endpoint {} after circuit breaker opened", self.addr);
+            *state = State::HalfOpen { waiting: Vec::new() };
+        }
+        permit
+    }
+
+    fn success(&self) {
+        let mut state = self.state.lock().expect("breaker state lock");
+        match *state {
+            // A request dispatched before the breaker opened says nothing
+            // about whether the endpoint has recovered.
+            State::Open { .. } => {}
+            State::HalfOpen { .. } => {
+                debug!("closing circuit breaker for {}", self.addr);
+                state.set(State::Closed { failures: 0 });
+            }
+            State::Closed { .. } => {
+                *state = State::Closed { failures: 0 };
+            }
+        }
+    }
+
+    fn failure(&self) {
+        let mut state = self.state.lock().expect("breaker state lock");
+        let open = match *state {
+            State::Open { .. } => false,
+            State::HalfOpen { .. } => true,
+            State::Closed { ref mut failures } => {
+                *failures += 1;
+                *failures >= self.config.failure_threshold
+            }
+        };
+        if open {
+            debug!(
+                "opening circuit breaker for {} for {:?}",
+                self.addr,
+                self.config.open_timeout,
+            );
+            state.set(State::Open { until: Instant::now() + self.config.open_timeout });
+        }
+    }
+
+    fn set(&self, new: State) {
+        self.state.lock().expect("breaker state lock").set(new);
+    }
+}
+
+// ===== impl State =====
+
+impl State {
+    /// Transitions to `new`, notifying any tasks that were waiting for a
+    /// probe to complete.
+    fn set(&mut self, new: State) {
+        if let State::HalfOpen { ref mut waiting } = *self {
+            for task in waiting.drain(..) {
+                task.notify();
+            }
+        }
+        *self = new;
+    }
+}
+
+// ===== impl BreakerError =====
+
+impl<E> fmt::Display for BreakerError<E>
+where
+    E: fmt::Display
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BreakerError::Open(ref addr) =>
+                write!(f, "circuit breaker for {} is open", addr),
+            BreakerError::Error(ref err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl<E> Error for BreakerError<E>
+where
+    E: Error
+{
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            BreakerError::Error(ref err) => Some(err),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            BreakerError::Open(_) => "circuit breaker is open",
+            BreakerError::Error(ref err) => err.description(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::{future, Future};
+    use http;
+    use tokio_core::reactor::Core;
+    use tower::Service;
+
+    use super::*;
+    use test_support::Endpoint;
+
+    fn addr() -> SocketAddr {
+        "10.1.1.1:8080".parse().unwrap()
+    }
+
+    /// Binds a breaker to an endpoint that fails until it's set otherwise.
+    fn endpoint(
+        breakers: &Breakers,
+        config: BreakerConfig,
+        core: &Core,
+    ) -> (CircuitBreaker<Endpoint>, Endpoint) {
+        let endpoint = Endpoint::default();
+        endpoint.set_failing(true);
+        let svc = breakers.circuit_breaker(&addr(), endpoint.clone(), Some(config), &core.handle());
+        (svc, endpoint)
+    }
+
+    fn req() -> http::Request<()> {
+        http::Request::new(())
+    }
+
+    fn is_ready(core: &mut Core, svc: &mut CircuitBreaker<Endpoint>) -> bool {
+        core.run(future::lazy(|| svc.poll_ready()))
+            .expect("poll_ready")
+            .is_ready()
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let mut core = Core::new().unwrap();
+        let config = BreakerConfig::new(3, Duration::from_secs(60));
+        let (mut svc, endpoint) = endpoint(&Breakers::default(), config, &core);
+
+        for _ in 0..3 {
+            assert!(is_ready(&mut core, &mut svc));
+            match svc.call(req()).wait() {
+                Err(BreakerError::Error(())) => {}
+                _ => panic!("request should reach the failing endpoint"),
+            }
+        }
+
+        assert!(!is_ready(&mut core, &mut svc), "open breaker should not be ready");
+        match svc.call(req()).wait() {
+            Err(BreakerError::Open(a)) => assert_eq!(a, addr()),
+            _ => panic!("breaker should be open"),
+        }
+        assert_eq!(endpoint.calls(), 3);
+    }
+
+    #[test]
+    fn open_breaker_is_ready_once_probe_is_due() {
+        let mut core = Core::new().unwrap();
+        let timeout = Duration::from_millis(50);
+        let config = BreakerConfig::new(1, timeout);
+        let (mut svc, endpoint) = endpoint(&Breakers::default(), config, &core);
+
+        assert!(svc.call(req()).wait().is_err());
+        assert!(!is_ready(&mut core, &mut svc));
+
+        // The breaker's timer wakes the task once the probe is due.
+        let deadline = ReactorTimeout::new(timeout * 20, &core.handle()).unwrap();
+        {
+            let ready = future::poll_fn(|| svc.poll_ready());
+            match core.run(ready.select2(deadline)) {
+                Ok(future::Either::A(_)) => {}
+                _ => panic!("breaker should be ready once the probe is due"),
+            }
+        }
+
+        // While the probe is in flight, the breaker isn't ready.
+        endpoint.set_failing(false);
+        let probe = svc.call(req());
+        assert!(!is_ready(&mut core, &mut svc));
+        assert!(probe.wait().is_ok(), "probe should succeed");
+        assert!(is_ready(&mut core, &mut svc), "breaker should be closed");
+        assert_eq!(endpoint.calls(), 2);
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let core = Core::new().unwrap();
+        let config = BreakerConfig::new(2, Duration::from_secs(60));
+        let (mut svc, endpoint) = endpoint(&Breakers::default(), config, &core);
+
+        assert!(svc.call(req()).wait().is_err());
+        endpoint.set_failing(false);
+        assert!(svc.call(req()).wait().is_ok());
+        endpoint.set_failing(true);
+        assert!(svc.call(req()).wait().is_err());
+
+        // Only one failure has occurred since the success.
+        assert!(svc.call(req()).wait().is_err());
+        assert_eq!(endpoint.calls(), 4);
+    }
+
+    #[test]
+    fn closes_after_successful_probe() {
+        let core = Core::new().unwrap();
+        let timeout = Duration::from_millis(10);
+        let config = BreakerConfig::new(1, timeout);
+        let (mut svc, endpoint) = endpoint(&Breakers::default(), config, &core);
+
+        assert!(svc.call(req()).wait().is_err());
+        match svc.call(req()).wait() {
+            Err(BreakerError::Open(_)) => {}
+            _ => panic!("breaker should be open"),
+        }
+
+        thread::sleep(timeout * 2);
+        endpoint.set_failing(false);
+        assert!(svc.call(req()).wait().is_ok(), "probe should succeed");
+        assert!(svc.call(req()).wait().is_ok(), "breaker should be closed");
+        assert_eq!(endpoint.calls(), 3);
+    }
+
+    #[test]
+    fn waits_for_a_probe_once_per_task() {
+        let mut core = Core::new().unwrap();
+        let timeout = Duration::from_millis(10);
+        let config = BreakerConfig::new(1, timeout);
+        let (mut svc, _) = endpoint(&Breakers::default(), config, &core);
+
+        assert!(svc.call(req()).wait().is_err());
+        thread::sleep(timeout * 2);
+        let probe = svc.call(req());
+
+        core.run(future::lazy(|| {
+            for _ in 0..3 {
+                assert!(svc.poll_ready().expect("poll_ready").is_not_ready());
+            }
+            Ok::<(), ()>(())
+        })).unwrap();
+        let breaker = svc.breaker.as_ref().expect("breaker");
+        match *breaker.state.lock().unwrap() {
+            State::HalfOpen { ref waiting } => assert_eq!(waiting.len(), 1),
+            ref state => panic!("breaker should be half-open: {:?}", state),
+        }
+        drop(probe);
+    }
+
+    #[test]
+    fn reopens_after_failed_probe() {
+        let core = Core::new().unwrap();
+        let timeout = Duration::from_millis(10);
+        let config = BreakerConfig::new(1, timeout);
+        let (mut svc, endpoint) = endpoint(&Breakers::default(), config, &core);
+
+        assert!(svc.call(req()).wait().is_err());
+        thread::sleep(timeout * 2);
+        match svc.call(req()).wait() {
+            Err(BreakerError::Error(())) => {}
+            _ => panic!("probe should reach the endpoint"),
+        }
+        match svc.call(req()).wait() {
+            Err(BreakerError::Open(_)) => {}
+            _ => panic!("breaker should be open again"),
+        }
+        assert_eq!(endpoint.calls(), 2);
+    }
+
+    #[test]
+    fn only_one_probe_at_a_time() {
+        let core = Core::new().unwrap();
+        let timeout = Duration::from_millis(10);
+        let config = BreakerConfig::new(1, timeout);
+        let (mut svc, endpoint) = endpoint(&Breakers::default(), config, &core);
+
+        assert!(svc.call(req()).wait().is_err());
+        thread::sleep(timeout * 2);
+
+        let probe = svc.call(req());
+        match svc.call(req()).wait() {
+            Err(BreakerError::Open(_)) => {}
+            _ => panic!("only the probe should be dispatched"),
+        }
+
+        // Canceling the probe allows another.
+        drop(probe);
+        assert!(svc.call(req()).wait().is_err());
+        assert_eq!(endpoint.calls(), 3);
+    }
+
+    #[test]
+    fn state_is_shared_by_address() {
+        let core = Core::new().unwrap();
+        let breakers = Breakers::default();
+        let config = BreakerConfig::new(1, Duration::from_secs(60));
+        let (mut first, _) = endpoint(&breakers, config, &core);
+        let (mut second, endpoint) = endpoint(&breakers, config, &core);
+
+        assert!(first.call(req()).wait().is_err());
+        match second.call(req()).wait() {
+            Err(BreakerError::Open(_)) => {}
+            _ => panic!("breaker should be open for all services"),
+        }
+        assert_eq!(endpoint.calls(), 0);
+    }
+
+    #[test]
+    fn unbound_addresses_are_forgotten() {
+        let core = Core::new().unwrap();
+        let breakers = Breakers::default();
+        let config = BreakerConfig::new(1, Duration::from_secs(60));
+        let (mut svc, _) = endpoint(&breakers, config, &core);
+        assert!(svc.call(req()).wait().is_err());
+        drop(svc);
+
+        let other: SocketAddr = "10.1.1.2:8080".parse().unwrap();
+        let _ = breakers.circuit_breaker(&other, (), Some(config), &core.handle());
+        let states = breakers.states.lock().unwrap();
+        assert_eq!(states.len(), 1);
+        assert!(states.contains_key(&other));
+    }
+
+    #[test]
+    fn disabled_does_not_open() {
+        let core = Core::new().unwrap();
+        let endpoint = Endpoint::default();
+        endpoint.set_failing(true);
+        let inner = endpoint.clone();
+        let mut svc = Breakers::default().circuit_breaker(&addr(), inner, None, &core.handle());
+
+        for _ in 0..10 {
+            match svc.call(req()).wait() {
+                Err(BreakerError::Error(())) => {}
+                _ => panic!("request should reach the failing endpoint"),
+            }
+        }
+        assert_eq!(endpoint.calls(), 10);
+    }
+}
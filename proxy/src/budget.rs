@@ -0,0 +1,462 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use http;
+use http::header::{HeaderValue, CONTENT_LENGTH};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+use telemetry::sensor::http::RequestOpen;
+
+/// The response header that names the limit of the budget that a request
+/// exhausted.
+pub const BUDGET_EXHAUSTED: &str = "conduit-budget-exhausted";
+
+/// The limits on what each request may spend. Unset limits are unlimited.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BudgetConfig {
+    max_duration: Option<Duration>,
+    max_attempts: Option<usize>,
+    max_buffered_bytes: Option<u64>,
+}
+
+/// What remains of a request's budget, carried in its extensions.
+///
+/// Every layer that spends time, attempts, or buffered bytes on a request
+/// spends them from its budget, so that a request that's retried, hedged,
+/// and resent is limited in total, rather than by each layer alone. Replays
+/// of a request share its budget.
+#[derive(Clone, Debug)]
+pub struct RequestBudget(Arc<Mutex<Spent>>);
+
+/// The limit of a request's budget that the request exhausted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exhausted {
+    /// The request took longer than this.
+    Duration(Duration),
+    /// The request would have been attempted more than this many times.
+    Attempts(usize),
+    /// More than this many bytes of the request's body would have been
+    /// buffered.
+    BufferedBytes(u64),
+}
+
+#[derive(Debug)]
+struct Spent {
+    config: BudgetConfig,
+    deadline: Option<Instant>,
+    attempts: usize,
+    buffered_bytes: u64,
+    exhausted: Option<Exhausted>,
+}
+
+/// Gives each request a `RequestBudget`, and fails requests that exhaust it
+/// with a response whose status and `BUDGET_EXHAUSTED` header describe the
+/// limit that was reached.
+///
+/// The budget's duration is measured from the request's `RequestOpen`
+/// timestamp, so the time a request spends buffered counts against it. The
+/// layers beneath spend its attempts and buffered bytes.
+///
+/// If constructed without any limits, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct Budget<S> {
+    inner: S,
+    config: BudgetConfig,
+    handle: Handle,
+}
+
+/// Fails if the request exhausts its budget.
+pub struct ResponseFuture<F> {
+    /// `None` once the request's duration is exhausted.
+    inner: Option<F>,
+    budget: Option<RequestBudget>,
+    timer: Option<ReactorTimeout>,
+}
+
+// ===== impl BudgetConfig =====
+
+impl BudgetConfig {
+    /// Limits the time from when each request is received until it's
+    /// responded to.
+    pub fn with_max_duration(self, max_duration: Duration) -> Self {
+        Self {
+            max_duration: Some(max_duration),
+            ..self
+        }
+    }
+
+    /// Limits the attempts made to send each request, including its first,
+    /// across retries, hedges, and resends.
+    pub fn with_max_attempts(self, max_attempts: usize) -> Self {
+        Self {
+            max_attempts: Some(max_attempts),
+            ..self
+        }
+    }
+
+    /// Limits the bytes of each request's body that may be buffered so
+    /// that it can be replayed.
+    pub fn with_max_buffered_bytes(self, max_buffered_bytes: u64) -> Self {
+        Self {
+            max_buffered_bytes: Some(max_buffered_bytes),
+            ..self
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.max_duration.is_none() &&
+            self.max_attempts.is_none() &&
+            self.max_buffered_bytes.is_none()
+    }
+}
+
+// ===== impl RequestBudget =====
+
+impl RequestBudget {
+    /// Starts spending a budget for a request received at `start`, which has
+    /// been attempted once.
+    pub fn new(config: BudgetConfig, start: Instant) -> Self {
+        RequestBudget(Arc::new(Mutex::new(Spent {
+            config,
+            deadline: config.max_duration.map(|max| start + max),
+            attempts: 1,
+            buffered_bytes: 0,
+            exhausted: None,
+        })))
+    }
+
+    /// Returns the budget of `req`, if it has one.
+    pub fn of<B>(req: &http::Request<B>) -> Option<&RequestBudget> {
+        req.extensions().get::<RequestBudget>()
+    }
+
+    /// Returns when the request's duration is exhausted, if it's limited.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.spent().deadline
+    }
+
+    /// Spends another attempt to send the request, unless every attempt has
+    /// been spent.
+    pub fn spend_attempt(&self) -> Result<(), Exhausted> {
+        let mut spent = self.spent();
+        match spent.config.max_attempts {
+            Some(max) if spent.attempts >= max => Err(Exhausted::Attempts(max)),
+            _ => {
+                spent.attempts += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Spends `bytes` more of the request's body being buffered, unless that
+    /// would buffer more than the limit.
+    pub fn spend_buffered(&self, bytes: u64) -> Result<(), Exhausted> {
+        let mut spent = self.spent();
+        let buffered = spent.buffered_bytes.saturating_add(bytes);
+        match spent.config.max_buffered_bytes {
+            Some(max) if buffered > max => Err(Exhausted::BufferedBytes(max)),
+            _ => {
+                spent.buffered_bytes = buffered;
+                Ok(())
+            }
+        }
+    }
+
+    /// Fails the request because it exhausted its budget, unless it already
+    /// had.
+    pub fn exhaust(&self, exhausted: Exhausted) {
+        let mut spent = self.spent();
+        if spent.exhausted.is_none() {
+            debug!("{}", exhausted);
+            spent.exhausted = Some(exhausted);
+        }
+    }
+
+    /// Returns the limit that the request exhausted first, if it has
+    /// exhausted any.
+    pub fn exhausted(&self) -> Option<Exhausted> {
+        self.spent().exhausted
+    }
+
+    /// Exhausts the request's duration.
+    fn expire(&self) {
+        let max = self.spent().config.max_duration.expect("expired without a duration");
+        self.exhaust(Exhausted::Duration(max));
+    }
+
+    fn spent(&self) -> MutexGuard<Spent> {
+        self.0.lock().expect("request budget lock poisoned")
+    }
+}
+
+// ===== impl Exhausted =====
+
+impl Exhausted {
+    /// The name of the limit, as given in the `BUDGET_EXHAUSTED` header.
+    pub fn limit(&self) -> &'static str {
+        match *self {
+            Exhausted::Duration(_) => "duration",
+            Exhausted::Attempts(_) => "attempts",
+            Exhausted::BufferedBytes(_) => "buffered-bytes",
+        }
+    }
+
+    fn status(&self) -> http::StatusCode {
+        match *self {
+            Exhausted::Duration(_) => http::StatusCode::GATEWAY_TIMEOUT,
+            Exhausted::Attempts(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+            Exhausted::BufferedBytes(_) => http::StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+
+    fn response<B: Default>(&self) -> http::Response<B> {
+        http::Response::builder()
+            .status(self.status())
+            .header(CONTENT_LENGTH, "0")
+            .header(BUDGET_EXHAUSTED, HeaderValue::from_static(self.limit()))
+            .body(B::default())
+            .expect("budget exhausted response is valid")
+    }
+}
+
+impl fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Exhausted::Duration(max) => write!(f, "request took longer than {:?}", max),
+            Exhausted::Attempts(max) => write!(f, "request attempted {} times", max),
+            Exhausted::BufferedBytes(max) => {
+                write!(f, "request body longer than {} buffered bytes", max)
+            }
+        }
+    }
+}
+
+// ===== impl Budget =====
+
+impl<S> Budget<S> {
+    pub fn new(inner: S, config: BudgetConfig, handle: &Handle) -> Self {
+        Budget {
+            inner,
+            config,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<S, A, B> Service for Budget<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Default,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: Self::Request) -> Self::Future {
+        if self.config.is_unlimited() {
+            return ResponseFuture {
+                inner: Some(self.inner.call(req)),
+                budget: None,
+                timer: None,
+            };
+        }
+
+        let start = req.extensions()
+            .get::<RequestOpen>()
+            .map(|&RequestOpen(t)| t)
+            .unwrap_or_else(Instant::now);
+        let budget = RequestBudget::new(self.config, start);
+        let timer = budget.deadline().map(|at| {
+            ReactorTimeout::new_at(at, &self.handle).expect("reactor gone")
+        });
+        req.extensions_mut().insert(budget.clone());
+
+        ResponseFuture {
+            inner: Some(self.inner.call(req)),
+            budget: Some(budget),
+            timer,
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Default,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let budget = match self.budget {
+            Some(ref budget) => budget,
+            None => return self.inner.as_mut().expect("polled after ready").poll(),
+        };
+
+        let elapsed = match self.timer {
+            Some(ref mut timer) => timer.poll().expect("timer failed").is_ready(),
+            None => false,
+        };
+        if elapsed {
+            budget.expire();
+            // The request is canceled if it's still in flight.
+            self.inner = None;
+            self.timer = None;
+        }
+
+        // The request fails if the budget is exhausted by the time it's done
+        // with, whether it succeeded or not.
+        let res = match self.inner {
+            Some(ref mut inner) => match inner.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                res => Some(res),
+            },
+            None => None,
+        };
+        match budget.exhausted() {
+            Some(exhausted) => Ok(Async::Ready(exhausted.response())),
+            None => res.expect("request canceled without exhausting its budget"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use bytes::{Buf, Bytes};
+    use futures::future::{self, FutureResult};
+    use tokio_core::reactor::Core;
+    use tower_h2::Body;
+
+    use replay::{BufferPolicy, BufferRequests, ReplayBody};
+    use retry::{Retry, RetryBudget, RetryPolicy};
+    use test_support::{send, Unresponsive};
+    use transparency::HttpBody;
+
+    use super::*;
+
+    /// Responds to each request with `503 Service Unavailable`, once its
+    /// body has been read, counting the requests it receives.
+    struct Unavailable(Rc<RefCell<usize>>);
+
+    impl Service for Unavailable {
+        type Request = http::Request<ReplayBody<HttpBody>>;
+        type Response = http::Response<()>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            *self.0.borrow_mut() += 1;
+            let mut body = req.into_body();
+            loop {
+                match body.poll_data() {
+                    Ok(Async::Ready(Some(data))) => assert!(data.remaining() > 0),
+                    Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                    Err(_) => return future::err(()),
+                }
+            }
+            let rsp = http::Response::builder()
+                .status(http::StatusCode::SERVICE_UNAVAILABLE)
+                .body(())
+                .unwrap();
+            future::ok(rsp)
+        }
+    }
+
+    /// A service that buffers and retries `POST`s up to five times before
+    /// sending them to an `Unavailable`, with `config` as their budgets.
+    fn retrying(
+        config: BudgetConfig,
+        core: &Core,
+    ) -> (Budget<BufferRequests<Retry<Unavailable>>>, Rc<RefCell<usize>>) {
+        let sent = Rc::new(RefCell::new(0));
+        let upstream = Unavailable(sent.clone());
+        let policy = RetryPolicy::new(5).with_method(http::Method::POST);
+        let retry = Retry::new(upstream, policy.clone(), RetryBudget::new(&policy));
+        let buffer = BufferRequests::new(retry, Some(BufferPolicy::new(100)), policy);
+        (Budget::new(buffer, config, &core.handle()), sent)
+    }
+
+    fn post(body: &'static str) -> http::Request<HttpBody> {
+        let mut req = http::Request::new(HttpBody::Buffered(Some(Bytes::from(body)), None));
+        *req.method_mut() = http::Method::POST;
+        req
+    }
+
+    fn assert_exhausted<B>(rsp: &http::Response<B>, status: http::StatusCode, limit: &str) {
+        assert_eq!(rsp.status(), status);
+        assert_eq!(rsp.headers()[BUDGET_EXHAUSTED], limit);
+    }
+
+    #[test]
+    fn requests_exhaust_their_durations() {
+        let mut core = Core::new().unwrap();
+        let max = Duration::from_millis(50);
+        let config = BudgetConfig::default().with_max_duration(max);
+        let mut svc = Budget::new(Unresponsive::default(), config, &core.handle());
+
+        let started = Instant::now();
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(RequestOpen(started));
+        let rsp = core.run(future::lazy(|| svc.call(req))).unwrap();
+        assert_exhausted(&rsp, http::StatusCode::GATEWAY_TIMEOUT, "duration");
+        assert!(started.elapsed() >= max);
+    }
+
+    #[test]
+    fn requests_exhaust_their_attempts() {
+        let mut core = Core::new().unwrap();
+        let config = BudgetConfig::default().with_max_attempts(3);
+        let (mut svc, sent) = retrying(config, &core);
+
+        let rsp = core.run(send(&mut svc, post("hello"))).unwrap();
+        assert_exhausted(&rsp, http::StatusCode::SERVICE_UNAVAILABLE, "attempts");
+        assert_eq!(*sent.borrow(), 3, "retries should stop once attempts are spent");
+    }
+
+    #[test]
+    fn requests_exhaust_their_buffered_bytes() {
+        let mut core = Core::new().unwrap();
+        let config = BudgetConfig::default()
+            .with_max_attempts(3)
+            .with_max_buffered_bytes(4);
+        let (mut svc, sent) = retrying(config, &core);
+
+        let rsp = core.run(send(&mut svc, post("hello"))).unwrap();
+        assert_exhausted(&rsp, http::StatusCode::PAYLOAD_TOO_LARGE, "buffered-bytes");
+        assert_eq!(*sent.borrow(), 1, "requests with failed bodies aren't retried");
+    }
+
+    #[test]
+    fn unexhausted_budgets_pass_responses_through() {
+        let mut core = Core::new().unwrap();
+        let config = BudgetConfig::default()
+            .with_max_attempts(10)
+            .with_max_buffered_bytes(5);
+        let (mut svc, sent) = retrying(config, &core);
+
+        let rsp = core.run(send(&mut svc, post("hello"))).unwrap();
+        assert_eq!(rsp.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(rsp.headers().get(BUDGET_EXHAUSTED).is_none());
+        assert_eq!(*sent.borrow(), 6);
+    }
+}
@@ -0,0 +1,773 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use bytes::{Buf, Bytes, BytesMut, IntoBuf};
+use futures::{Async, Future, Poll};
+use h2;
+use http;
+use http::header::{
+    AGE,
+    AUTHORIZATION,
+    CACHE_CONTROL,
+    CONTENT_LENGTH,
+    DATE,
+    EXPIRES,
+    SET_COOKIE,
+    VARY,
+    HeaderMap,
+    HeaderName,
+    HeaderValue,
+};
+use hyper::header::HttpDate;
+use tower::Service;
+use tower_h2::Body;
+
+use transparency::h1;
+
+/// Holds the responses to `GET` requests in memory, until they expire.
+///
+/// Entries are keyed by the request's method, authority, and path, along
+/// with the request's values of any headers named by the response's `Vary`.
+/// Once the entries exceed `max_bytes` in total, the least recently used are
+/// evicted.
+///
+/// Clones share the same entries.
+#[derive(Clone, Debug)]
+pub struct ResponseCache(Rc<RefCell<Entries>>);
+
+/// Serves responses to `GET` requests from a `ResponseCache` while they are
+/// fresh, rather than dispatching the requests to the inner service.
+///
+/// A response is cached if it has a cacheable status, and is fresh for some
+/// time according to its `Cache-Control: s-maxage` or `max-age` directive, or
+/// its `Expires` header. Responses that are `no-store`, `no-cache`, or
+/// `private` are never cached, nor are responses that set cookies, vary on
+/// `*`, or end with trailers. Requests with credentials bypass the cache, as
+/// do requests that are `no-cache` or `no-store` themselves.
+///
+/// Responses served from the cache are given an `Age` header.
+///
+/// If constructed without a `ResponseCache`, this is a no-op.
+#[derive(Debug)]
+pub struct Cache<S> {
+    inner: S,
+    cache: Option<ResponseCache>,
+}
+
+pub struct ResponseFuture<F> {
+    state: State<F>,
+}
+
+enum State<F> {
+    Hit(Option<Hit>),
+    Upstream(F, Option<Pending>),
+}
+
+/// A response body, which is copied into the cache as it is read if its
+/// response is cacheable.
+#[derive(Debug)]
+pub enum CacheBody<B> {
+    Cached(Option<Bytes>),
+    Upstream(B, Option<Capture>),
+}
+
+/// The data of a `CacheBody`.
+pub enum Data<D> {
+    Cached(Cursor<Bytes>),
+    Upstream(D),
+}
+
+/// A cacheable response's body and metadata, as it is being read.
+#[derive(Debug)]
+pub struct Capture {
+    cache: ResponseCache,
+    key: Key,
+    entry: Entry,
+    data: BytesMut,
+}
+
+/// Statuses that may be cached, given explicit freshness.
+const CACHEABLE_STATUSES: &[u16] = &[200, 203, 204, 300, 301, 404, 405, 410, 414, 501];
+
+/// The longest that a response is considered fresh, in seconds, so that its
+/// expiry can't overflow.
+const MAX_LIFETIME_SECS: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Debug)]
+struct Entries {
+    max_bytes: usize,
+    bytes: usize,
+    /// Counts the uses of entries, so that they are ordered by recency.
+    clock: u64,
+    /// The variants of each response, by key.
+    entries: HashMap<Key, Vec<Entry>>,
+    /// The key of each entry, by when it was last used.
+    lru: BTreeMap<u64, Key>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Key {
+    method: http::Method,
+    authority: String,
+    path: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The request's value of each header named by the response's `Vary`.
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    status: http::StatusCode,
+    version: http::Version,
+    headers: HeaderMap,
+    body: Bytes,
+    /// The response's age when it was received, according to its `Age`
+    /// header.
+    age: Duration,
+    received: Instant,
+    expires: Instant,
+    used: u64,
+}
+
+/// A request whose response may be cached.
+struct Pending {
+    cache: ResponseCache,
+    key: Key,
+    headers: HeaderMap,
+}
+
+/// A response served from the cache.
+struct Hit {
+    status: http::StatusCode,
+    version: http::Version,
+    headers: HeaderMap,
+    body: Bytes,
+    age: Duration,
+}
+
+/// The `Cache-Control` directives that determine caching.
+#[derive(Debug, Default)]
+struct Directives {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+}
+
+// ===== impl ResponseCache =====
+
+impl ResponseCache {
+    /// Caches responses totaling up to `max_bytes`, including their headers.
+    pub fn new(max_bytes: usize) -> Self {
+        ResponseCache(Rc::new(RefCell::new(Entries {
+            max_bytes,
+            bytes: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+        })))
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.0.borrow().max_bytes
+    }
+}
+
+// ===== impl Cache =====
+
+impl<S> Cache<S> {
+    pub fn new(inner: S, cache: Option<ResponseCache>) -> Self {
+        Cache { inner, cache }
+    }
+}
+
+impl<S, A, B> Service for Cache<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<CacheBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let pending = match (self.cache.as_ref(), Key::of(&req)) {
+            (Some(cache), Some(key)) if !req.headers().contains_key(AUTHORIZATION) => {
+                let directives = Directives::parse(req.headers());
+                if directives.no_store {
+                    None
+                } else {
+                    if !directives.no_cache {
+                        let hit = cache.0.borrow_mut().get(&key, req.headers(), Instant::now());
+                        if let Some(hit) = hit {
+                            trace!("serving {:?} from cache", key);
+                            return ResponseFuture {
+                                state: State::Hit(Some(hit)),
+                            };
+                        }
+                    }
+                    Some(Pending {
+                        cache: cache.clone(),
+                        key,
+                        headers: req.headers().clone(),
+                    })
+                }
+            }
+            _ => None,
+        };
+
+        ResponseFuture {
+            state: State::Upstream(self.inner.call(req), pending),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<CacheBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            State::Hit(ref mut hit) => {
+                let hit = hit.take().expect("polled after ready");
+                Ok(Async::Ready(hit.into_response()))
+            }
+            State::Upstream(ref mut inner, ref mut pending) => {
+                let rsp = try_ready!(inner.poll());
+                let capture = pending.take().and_then(|pending| pending.capture(&rsp));
+                let (parts, body) = rsp.into_parts();
+                let body = CacheBody::Upstream(body, capture);
+                Ok(Async::Ready(http::Response::from_parts(parts, body)))
+            }
+        }
+    }
+}
+
+// ===== impl CacheBody =====
+
+impl<B: Body> Body for CacheBody<B> {
+    type Data = Data<<B::Data as IntoBuf>::Buf>;
+
+    fn is_end_stream(&self) -> bool {
+        match *self {
+            CacheBody::Cached(ref data) => data.is_none(),
+            CacheBody::Upstream(ref body, _) => body.is_end_stream(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        match *self {
+            CacheBody::Cached(ref mut data) => {
+                Ok(Async::Ready(data.take().map(|d| Data::Cached(d.into_buf()))))
+            }
+            CacheBody::Upstream(ref mut body, ref mut capture) => {
+                let data = match body.poll_data() {
+                    Ok(Async::Ready(data)) => data,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        *capture = None;
+                        return Err(e);
+                    }
+                };
+                let data = match data {
+                    Some(data) => data.into_buf(),
+                    None => {
+                        // Unless trailers follow, the body is complete.
+                        if body.is_end_stream() {
+                            if let Some(capture) = capture.take() {
+                                capture.store();
+                            }
+                        }
+                        return Ok(Async::Ready(None));
+                    }
+                };
+
+                let too_large = capture.as_ref()
+                    .map(|c| c.data.len() + data.remaining() > c.cache.max_bytes())
+                    .unwrap_or(false);
+                if too_large {
+                    debug!("response body is larger than the cache; not caching it");
+                    *capture = None;
+                }
+
+                match *capture {
+                    Some(ref mut capture) => {
+                        let data: Bytes = data.collect();
+                        capture.data.extend_from_slice(&data);
+                        Ok(Async::Ready(Some(Data::Cached(data.into_buf()))))
+                    }
+                    None => Ok(Async::Ready(Some(Data::Upstream(data)))),
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match *self {
+            CacheBody::Cached(_) => Ok(Async::Ready(None)),
+            CacheBody::Upstream(ref mut body, ref mut capture) => {
+                let trailers = match body.poll_trailers() {
+                    Ok(Async::Ready(trailers)) => trailers,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        *capture = None;
+                        return Err(e);
+                    }
+                };
+                // Trailers aren't cached, so neither are their responses.
+                if let Some(capture) = capture.take() {
+                    if trailers.is_none() {
+                        capture.store();
+                    }
+                }
+                Ok(Async::Ready(trailers))
+            }
+        }
+    }
+}
+
+impl<B> Default for CacheBody<B> {
+    fn default() -> Self {
+        CacheBody::Cached(None)
+    }
+}
+
+// ===== impl Data =====
+
+impl<D: Buf> Buf for Data<D> {
+    fn remaining(&self) -> usize {
+        match *self {
+            Data::Cached(ref d) => d.remaining(),
+            Data::Upstream(ref d) => d.remaining(),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Data::Cached(ref d) => d.bytes(),
+            Data::Upstream(ref d) => d.bytes(),
+        }
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        match *self {
+            Data::Cached(ref mut d) => d.advance(cnt),
+            Data::Upstream(ref mut d) => d.advance(cnt),
+        }
+    }
+}
+
+// ===== impl Capture =====
+
+impl Capture {
+    fn store(self) {
+        let Capture { cache, key, entry, data } = self;
+        let entry = Entry {
+            body: data.freeze(),
+            ..entry
+        };
+        trace!("caching {:?}", key);
+        cache.0.borrow_mut().insert(key, entry);
+    }
+}
+
+// ===== impl Entries =====
+
+impl Entries {
+    /// Returns a fresh response for `key` that matches the request's
+    /// `headers`, if one is cached.
+    fn get(&mut self, key: &Key, headers: &HeaderMap, now: Instant) -> Option<Hit> {
+        let (used, expired) = {
+            let entry = self.entries.get(key)?.iter().find(|e| e.matches(headers))?;
+            (entry.used, entry.expires <= now)
+        };
+        if expired {
+            self.remove(used);
+            return None;
+        }
+
+        self.clock += 1;
+        let clock = self.clock;
+        let key = self.lru.remove(&used).expect("cache entry must be ordered");
+        self.lru.insert(clock, key.clone());
+        let entry = self.entries.get_mut(&key)
+            .and_then(|entries| entries.iter_mut().find(|e| e.used == used))
+            .expect("cache entry must exist");
+        entry.used = clock;
+
+        Some(Hit {
+            status: entry.status,
+            version: entry.version,
+            headers: entry.headers.clone(),
+            body: entry.body.clone(),
+            age: entry.age + now.duration_since(entry.received),
+        })
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry) {
+        let size = entry.size();
+        if size > self.max_bytes {
+            return;
+        }
+
+        // Replace any variant that the new entry matches.
+        let replaced = self.entries.get(&key)
+            .and_then(|entries| entries.iter().find(|e| e.vary == entry.vary))
+            .map(|e| e.used);
+        if let Some(used) = replaced {
+            self.remove(used);
+        }
+
+        while self.bytes + size > self.max_bytes {
+            let oldest = *self.lru.keys().next().expect("cache must not be empty");
+            self.remove(oldest);
+        }
+
+        self.clock += 1;
+        self.bytes += size;
+        self.lru.insert(self.clock, key.clone());
+        self.entries.entry(key).or_insert_with(Vec::new).push(Entry {
+            used: self.clock,
+            ..entry
+        });
+    }
+
+    /// Removes the entry that was last used at `used`.
+    fn remove(&mut self, used: u64) {
+        let key = match self.lru.remove(&used) {
+            Some(key) => key,
+            None => return,
+        };
+        let empty = match self.entries.get_mut(&key) {
+            Some(entries) => {
+                if let Some(i) = entries.iter().position(|e| e.used == used) {
+                    self.bytes -= entries.remove(i).size();
+                }
+                entries.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+// ===== impl Key =====
+
+impl Key {
+    /// Returns the key for a request, if its response may be cached.
+    fn of<B>(req: &http::Request<B>) -> Option<Self> {
+        if *req.method() != http::Method::GET {
+            return None;
+        }
+        let authority = req.uri().authority_part()
+            .cloned()
+            .or_else(|| h1::authority_from_host(req))?;
+        let path = req.uri().path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        Some(Key {
+            method: req.method().clone(),
+            authority: authority.as_str().to_ascii_lowercase(),
+            path: path.to_owned(),
+        })
+    }
+}
+
+// ===== impl Entry =====
+
+impl Entry {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary.iter().all(|&(ref name, ref value)| headers.get(name) == value.as_ref())
+    }
+
+    /// Approximates the memory used by the entry.
+    fn size(&self) -> usize {
+        let headers: usize = self.headers.iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        self.body.len() + headers
+    }
+}
+
+// ===== impl Pending =====
+
+impl Pending {
+    /// Begins capturing `rsp`, if it is cacheable.
+    fn capture<B>(self, rsp: &http::Response<B>) -> Option<Capture> {
+        let headers = rsp.headers();
+        if !CACHEABLE_STATUSES.contains(&rsp.status().as_u16()) ||
+            headers.contains_key(SET_COOKIE)
+        {
+            return None;
+        }
+
+        let len = headers.get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<usize>().ok());
+        if len.map(|len| len > self.cache.max_bytes()).unwrap_or(false) {
+            return None;
+        }
+
+        let lifetime = freshness_lifetime(headers)?;
+        let age = headers.get(AGE)
+            .and_then(|age| age.to_str().ok())
+            .and_then(|age| age.parse::<u64>().ok())
+            .map(|secs| Duration::from_secs(cmp::min(secs, MAX_LIFETIME_SECS)))
+            .unwrap_or_default();
+        if lifetime <= age {
+            return None;
+        }
+
+        let mut vary = Vec::new();
+        for value in headers.get_all(VARY) {
+            for name in value.to_str().ok()?.split(',') {
+                let name = name.trim();
+                if name == "*" {
+                    return None;
+                }
+                let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+                let value = self.headers.get(&name).cloned();
+                vary.push((name, value));
+            }
+        }
+
+        let now = Instant::now();
+        let entry = Entry {
+            vary,
+            status: rsp.status(),
+            version: rsp.version(),
+            headers: headers.clone(),
+            body: Bytes::new(),
+            age,
+            received: now,
+            expires: now + (lifetime - age),
+            used: 0,
+        };
+        Some(Capture {
+            cache: self.cache,
+            key: self.key,
+            entry,
+            data: BytesMut::new(),
+        })
+    }
+}
+
+/// Returns how long a response is fresh for after it was generated, if it
+/// may be cached.
+fn freshness_lifetime(headers: &HeaderMap) -> Option<Duration> {
+    let directives = Directives::parse(headers);
+    if directives.no_store || directives.no_cache || directives.private {
+        return None;
+    }
+    if let Some(secs) = directives.s_maxage.or(directives.max_age) {
+        return Some(Duration::from_secs(cmp::min(secs, MAX_LIFETIME_SECS)));
+    }
+
+    // An `Expires` header that can't be parsed means that the response has
+    // already expired.
+    let expires = parse_date(headers.get(EXPIRES)?)?;
+    let date = headers.get(DATE)
+        .and_then(parse_date)
+        .unwrap_or_else(SystemTime::now);
+    expires.duration_since(date)
+        .ok()
+        .map(|lifetime| cmp::min(lifetime, Duration::from_secs(MAX_LIFETIME_SECS)))
+}
+
+fn parse_date(value: &HeaderValue) -> Option<SystemTime> {
+    value.to_str().ok()?
+        .parse::<HttpDate>()
+        .ok()
+        .map(SystemTime::from)
+}
+
+// ===== impl Hit =====
+
+impl Hit {
+    fn into_response<B>(self) -> http::Response<CacheBody<B>> {
+        let body = if self.body.is_empty() {
+            None
+        } else {
+            Some(self.body)
+        };
+        let mut rsp = http::Response::new(CacheBody::Cached(body));
+        *rsp.status_mut() = self.status;
+        *rsp.version_mut() = self.version;
+        *rsp.headers_mut() = self.headers;
+        rsp.headers_mut().insert(AGE, HeaderValue::from(self.age.as_secs()));
+        rsp
+    }
+}
+
+// ===== impl Directives =====
+
+impl Directives {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut directives = Directives::default();
+        for value in headers.get_all(CACHE_CONTROL) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            for directive in value.split(',') {
+                let mut parts = directive.splitn(2, '=');
+                let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+                let secs = parts.next()
+                    .and_then(|secs| secs.trim().trim_matches('"').parse::<u64>().ok());
+                match name.as_ref() {
+                    "no-store" => directives.no_store = true,
+                    "no-cache" => directives.no_cache = true,
+                    "private" => directives.private = true,
+                    "max-age" => directives.max_age = secs.or(Some(0)),
+                    "s-maxage" => directives.s_maxage = secs.or(Some(0)),
+                    _ => {}
+                }
+            }
+        }
+        directives
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::future;
+
+    use test_support::{self, Chunks, Upstream};
+    use super::*;
+
+    /// Caches the responses of an upstream that responds to each request
+    /// with `headers` and a body naming the number of requests it has
+    /// received.
+    fn cache(
+        max_bytes: usize,
+        headers: &[(&'static str, &'static str)],
+    ) -> (Cache<Upstream>, Rc<Cell<usize>>) {
+        let headers = test_support::headers(headers);
+        let upstream = Upstream::new(move |calls| {
+            let body = Chunks::new(&["hello, ".to_owned(), format!("response {}", calls)]);
+            let mut rsp = http::Response::new(body);
+            *rsp.headers_mut() = headers.clone();
+            rsp
+        });
+        let calls = upstream.calls();
+        (Cache::new(upstream, Some(ResponseCache::new(max_bytes))), calls)
+    }
+
+    fn get(path: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri(format!("http://example.com{}", path).as_str())
+            .body(())
+            .unwrap()
+    }
+
+    /// Sends `req`, returning the response's headers and body.
+    fn send(svc: &mut Cache<Upstream>, req: http::Request<()>) -> (HeaderMap, String) {
+        let rsp = svc.call(req).wait().expect("response");
+        let (parts, mut body) = rsp.into_parts();
+        let data = future::lazy(move || {
+            let mut data = Vec::new();
+            while let Some(chunk) = try_ready!(body.poll_data()) {
+                data.extend_from_slice(chunk.bytes());
+            }
+            try_ready!(body.poll_trailers());
+            Ok::<_, h2::Error>(Async::Ready(data))
+        }).wait().expect("body");
+        (parts.headers, String::from_utf8(data).unwrap())
+    }
+
+    #[test]
+    fn fresh_responses_are_served_from_cache() {
+        let (mut svc, calls) = cache(1024, &[("cache-control", "public, max-age=60")]);
+
+        let (headers, body) = send(&mut svc, get("/a"));
+        assert_eq!(body, "hello, response 1");
+        assert!(headers.get(AGE).is_none());
+
+        let (headers, body) = send(&mut svc, get("/a"));
+        assert_eq!(body, "hello, response 1");
+        assert_eq!(headers.get(AGE).unwrap(), "0");
+        assert_eq!(headers.get(CACHE_CONTROL).unwrap(), "public, max-age=60");
+        assert_eq!(calls.get(), 1);
+
+        // Other paths aren't served the cached response.
+        let (_, body) = send(&mut svc, get("/b"));
+        assert_eq!(body, "hello, response 2");
+
+        // Nor are requests that ask not to be served from a cache.
+        let mut req = get("/a");
+        req.headers_mut().insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+        let (_, body) = send(&mut svc, req);
+        assert_eq!(body, "hello, response 3");
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn no_store_responses_bypass_cache() {
+        let (mut svc, calls) = cache(1024, &[("cache-control", "max-age=60, no-store")]);
+
+        let (_, body) = send(&mut svc, get("/a"));
+        assert_eq!(body, "hello, response 1");
+        let (headers, body) = send(&mut svc, get("/a"));
+        assert_eq!(body, "hello, response 2");
+        assert!(headers.get(AGE).is_none());
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn responses_vary_on_request_headers() {
+        let (mut svc, calls) = cache(1024, &[
+            ("cache-control", "max-age=60"),
+            ("vary", "Accept-Language"),
+        ]);
+        let get_lang = |lang: &str| {
+            let mut req = get("/a");
+            req.headers_mut().insert("accept-language", lang.parse().unwrap());
+            req
+        };
+
+        assert_eq!(send(&mut svc, get_lang("en")).1, "hello, response 1");
+        assert_eq!(send(&mut svc, get_lang("fr")).1, "hello, response 2");
+        assert_eq!(send(&mut svc, get_lang("en")).1, "hello, response 1");
+        assert_eq!(send(&mut svc, get_lang("fr")).1, "hello, response 2");
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        // Each entry is 40 bytes: a 17-byte body and 23 bytes of headers.
+        let (mut svc, calls) = cache(100, &[("cache-control", "max-age=60")]);
+
+        send(&mut svc, get("/a"));
+        send(&mut svc, get("/b"));
+        // Using `/a` makes `/b` the least recently used.
+        assert_eq!(send(&mut svc, get("/a")).1, "hello, response 1");
+        send(&mut svc, get("/c"));
+        assert_eq!(calls.get(), 3);
+
+        assert_eq!(send(&mut svc, get("/a")).1, "hello, response 1");
+        assert_eq!(send(&mut svc, get("/c")).1, "hello, response 3");
+        assert_eq!(send(&mut svc, get("/b")).1, "hello, response 4");
+        assert_eq!(calls.get(), 4);
+    }
+}
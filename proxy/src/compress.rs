@@ -0,0 +1,520 @@
+use std::fmt;
+use std::io::{self, Cursor, Write};
+use std::mem;
+
+use bytes::{Buf, Bytes, IntoBuf};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::{Async, Future, Poll};
+use h2;
+use http;
+use http::header::{
+    ACCEPT_ENCODING,
+    CACHE_CONTROL,
+    CONTENT_ENCODING,
+    CONTENT_LENGTH,
+    CONTENT_RANGE,
+    CONTENT_TYPE,
+    ETAG,
+    VARY,
+    HeaderValue,
+};
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+/// Compresses responses with gzip for clients that accept it.
+///
+/// A response is compressed if its request advertises `Accept-Encoding:
+/// gzip`, and the upstream sent it with a body that is not already encoded.
+/// Compressed responses have their `Content-Length` removed, as it is not
+/// known until the body has been compressed, and are sent with
+/// `Content-Encoding: gzip` and `Vary: Accept-Encoding`.
+///
+/// Responses whose media types are already compressed, such as images, are
+/// sent as they are, as are gRPC responses, which are compressed by gRPC
+/// itself.
+///
+/// If constructed disabled, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct Compress<S> {
+    inner: S,
+    enabled: bool,
+}
+
+/// Wraps the inner `NewService`'s services in `Compress`es.
+pub struct Init<F> {
+    future: F,
+    enabled: bool,
+}
+
+/// Starts compressing the response, if it should be compressed.
+pub struct ResponseFuture<F> {
+    inner: F,
+    accepts_gzip: bool,
+}
+
+/// A response body, compressed with gzip if the response is compressed.
+///
+/// Each chunk of data received from the upstream is compressed and flushed
+/// before the next is polled, so that the body streams as it is received.
+/// Since the upstream is not polled for more data until the client has
+/// taken the last compressed chunk, HTTP/2 flow control still applies
+/// backpressure to the upstream, rather than the response being buffered.
+#[derive(Debug, Default)]
+pub struct CompressBody<B> {
+    inner: B,
+    gzip: Option<Gzip>,
+}
+
+/// The data of a `CompressBody`.
+#[derive(Debug)]
+pub enum Data<D> {
+    Identity(D),
+    Gzip(Cursor<Bytes>),
+}
+
+struct Gzip {
+    encoder: GzEncoder<Vec<u8>>,
+    finished: bool,
+}
+
+/// Media types that are not compressed, as they are compressed already.
+const INCOMPRESSIBLE_TYPE_PREFIXES: &[&str] = &[
+    "application/grpc",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zip",
+    "audio/",
+    "image/",
+    "video/",
+];
+
+// ===== impl Compress =====
+
+impl<S> Compress<S> {
+    pub fn new(inner: S, enabled: bool) -> Self {
+        Compress { inner, enabled }
+    }
+}
+
+impl<N, A, B> NewService for Compress<N>
+where
+    N: NewService<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Body,
+{
+    type Request = N::Request;
+    type Response = http::Response<CompressBody<B>>;
+    type Error = N::Error;
+    type Service = Compress<N::Service>;
+    type InitError = N::InitError;
+    type Future = Init<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        Init {
+            future: self.inner.new_service(),
+            enabled: self.enabled,
+        }
+    }
+}
+
+impl<S, A, B> Service for Compress<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<CompressBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        // Responses to `HEAD` requests have no body to compress.
+        let accepts_gzip = self.enabled
+            && req.method() != http::Method::HEAD
+            && accepts_gzip(req.headers());
+        ResponseFuture {
+            inner: self.inner.call(req),
+            accepts_gzip,
+        }
+    }
+}
+
+// ===== impl Init =====
+
+impl<F> Future for Init<F>
+where
+    F: Future,
+{
+    type Item = Compress<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(Compress::new(inner, self.enabled)))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Body,
+{
+    type Item = http::Response<CompressBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+
+        let gzip = self.accepts_gzip && is_compressible(&rsp);
+        if gzip {
+            trace!("compressing response with gzip");
+            let headers = rsp.headers_mut();
+            headers.remove(CONTENT_LENGTH);
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+
+            // The compressed body is no longer byte-for-byte identical to the
+            // representation that a strong validator identifies.
+            let weak_etag = match headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+                Some(etag) if etag.starts_with('"') => format!("W/{}", etag).parse().ok(),
+                _ => None,
+            };
+            if let Some(etag) = weak_etag {
+                headers.insert(ETAG, etag);
+            }
+        }
+
+        Ok(Async::Ready(rsp.map(|inner| CompressBody::new(inner, gzip))))
+    }
+}
+
+// ===== impl CompressBody =====
+
+impl<B> CompressBody<B> {
+    fn new(inner: B, gzip: bool) -> Self {
+        let gzip = if gzip {
+            Some(Gzip {
+                encoder: GzEncoder::new(Vec::new(), Compression::default()),
+                finished: false,
+            })
+        } else {
+            None
+        };
+        CompressBody { inner, gzip }
+    }
+}
+
+impl<B> Body for CompressBody<B>
+where
+    B: Body,
+{
+    type Data = Data<<B::Data as IntoBuf>::Buf>;
+
+    fn is_end_stream(&self) -> bool {
+        match self.gzip {
+            // The end of the compressed stream must still be sent.
+            Some(ref gzip) if !gzip.finished => false,
+            _ => self.inner.is_end_stream(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        let gzip = match self.gzip {
+            Some(ref mut gzip) => gzip,
+            None => {
+                let data = try_ready!(self.inner.poll_data());
+                return Ok(Async::Ready(data.map(|d| Data::Identity(d.into_buf()))));
+            }
+        };
+
+        while !gzip.finished {
+            let compressed = match try_ready!(self.inner.poll_data()) {
+                Some(data) => gzip.compress(data.into_buf()),
+                None => gzip.finish(),
+            };
+            let compressed = compressed.map_err(|e| {
+                warn!("failed to compress response body: {}", e);
+                h2::Error::from(h2::Reason::INTERNAL_ERROR)
+            })?;
+
+            // The encoder may buffer small chunks without producing output.
+            if !compressed.is_empty() {
+                return Ok(Async::Ready(Some(Data::Gzip(Cursor::new(compressed)))));
+            }
+        }
+
+        Ok(Async::Ready(None))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+// ===== impl Data =====
+
+impl<D: Buf> Buf for Data<D> {
+    fn remaining(&self) -> usize {
+        match *self {
+            Data::Identity(ref d) => d.remaining(),
+            Data::Gzip(ref d) => d.remaining(),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Data::Identity(ref d) => d.bytes(),
+            Data::Gzip(ref d) => d.bytes(),
+        }
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        match *self {
+            Data::Identity(ref mut d) => d.advance(cnt),
+            Data::Gzip(ref mut d) => d.advance(cnt),
+        }
+    }
+}
+
+// ===== impl Gzip =====
+
+impl Gzip {
+    /// Compresses `data`, returning as much of the compressed stream as may
+    /// be sent before more data is compressed.
+    fn compress<D: Buf>(&mut self, mut data: D) -> io::Result<Bytes> {
+        while data.has_remaining() {
+            let n = {
+                let chunk = data.bytes();
+                self.encoder.write_all(chunk)?;
+                chunk.len()
+            };
+            data.advance(n);
+        }
+        self.encoder.flush()?;
+        Ok(self.take())
+    }
+
+    /// Ends the compressed stream, returning the rest of it.
+    fn finish(&mut self) -> io::Result<Bytes> {
+        self.encoder.try_finish()?;
+        self.finished = true;
+        Ok(self.take())
+    }
+
+    fn take(&mut self) -> Bytes {
+        mem::replace(self.encoder.get_mut(), Vec::new()).into()
+    }
+}
+
+impl fmt::Debug for Gzip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Gzip")
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+/// Returns true if `headers` accept a gzip-encoded response.
+///
+/// A `gzip` coding is preferred over a `*` wildcard, so that a client may
+/// refuse gzip while accepting other encodings.
+fn accepts_gzip(headers: &http::HeaderMap) -> bool {
+    let mut gzip = None;
+    let mut wildcard = None;
+    let codings = headers.get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','));
+    for coding in codings {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or("").trim();
+        // A coding may be refused with a quality value of zero.
+        let accepted = params
+            .filter_map(|p| {
+                let p = p.trim();
+                if p.starts_with("q=") || p.starts_with("Q=") {
+                    p[2..].parse::<f32>().ok()
+                } else {
+                    None
+                }
+            })
+            .next()
+            .map(|q| q > 0.0)
+            .unwrap_or(true);
+
+        if name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(accepted);
+        } else if name == "*" {
+            wildcard = Some(accepted);
+        }
+    }
+    gzip.or(wildcard).unwrap_or(false)
+}
+
+/// Returns true if `rsp` has an unencoded body that may be compressed.
+fn is_compressible<B: Body>(rsp: &http::Response<B>) -> bool {
+    let status = rsp.status();
+    if status.is_informational()
+        || status == http::StatusCode::NO_CONTENT
+        || status == http::StatusCode::NOT_MODIFIED
+        || status == http::StatusCode::PARTIAL_CONTENT
+        || rsp.body().is_end_stream()
+    {
+        return false;
+    }
+
+    let headers = rsp.headers();
+    // Ranges of a response refer to its unencoded bytes.
+    if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+        return false;
+    }
+
+    let no_transform = headers.get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+    if no_transform {
+        return false;
+    }
+
+    match headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        Some(content_type) => {
+            let content_type = content_type.trim().to_ascii_lowercase();
+            !INCOMPRESSIBLE_TYPE_PREFIXES
+                .iter()
+                .any(|prefix| content_type.starts_with(prefix))
+                || content_type.starts_with("image/svg+xml")
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use futures::future;
+
+    use test_support::{self, Chunks, Upstream};
+    use super::*;
+
+    /// An upstream that responds to each request with `headers` and a body
+    /// of `chunks`, followed by trailers.
+    fn upstream(
+        headers: &[(&'static str, &'static str)],
+        chunks: &'static [&'static str],
+    ) -> Upstream {
+        let headers = test_support::headers(headers);
+        Upstream::new(move |_| {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("x-checksum", "1234".parse().unwrap());
+            let mut rsp = http::Response::new(Chunks::new(chunks).with_trailers(trailers));
+            *rsp.headers_mut() = headers.clone();
+            rsp
+        })
+    }
+
+    fn get(accept_encoding: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri("http://example.com/")
+            .header(ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .unwrap()
+    }
+
+    /// Reads `body` to its end, returning its data and trailers.
+    fn read<B: Body>(mut body: B) -> (Vec<u8>, Option<http::HeaderMap>) {
+        future::lazy(move || {
+            let mut data = Vec::new();
+            while let Some(chunk) = try_ready!(body.poll_data()) {
+                data.extend_from_slice(chunk.into_buf().bytes());
+            }
+            let trailers = try_ready!(body.poll_trailers());
+            Ok::<_, h2::Error>(Async::Ready((data, trailers)))
+        }).map(|ready| match ready {
+            Async::Ready(read) => read,
+            Async::NotReady => panic!("body should be ready"),
+        }).wait().expect("body")
+    }
+
+    #[test]
+    fn compresses_responses_for_clients_that_accept_gzip() {
+        let chunks = &["hello ", "hello hello ", "world"];
+        let up = upstream(&[("content-length", "23"), ("etag", "\"v1\"")], chunks);
+        let mut svc = Compress::new(up, true);
+
+        let rsp = svc.call(get("br;q=1.0, gzip;q=0.5")).wait().unwrap();
+        assert_eq!(rsp.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(rsp.headers().get(VARY).unwrap(), "accept-encoding");
+        assert_eq!(rsp.headers().get(ETAG).unwrap(), "W/\"v1\"");
+        assert!(rsp.headers().get(CONTENT_LENGTH).is_none());
+
+        let (compressed, trailers) = read(rsp.into_parts().1);
+        let mut decoded = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut decoded).expect("gzip");
+        assert_eq!(decoded, "hello hello hello world");
+        assert_eq!(trailers.unwrap().get("x-checksum").unwrap(), "1234");
+    }
+
+    #[test]
+    fn leaves_compressed_responses_alone() {
+        let headers = [("content-length", "6"), ("content-encoding", "br")];
+        let mut svc = Compress::new(upstream(&headers, &["brotli"]), true);
+        let rsp = svc.call(get("gzip, br")).wait().unwrap();
+        assert_eq!(rsp.headers().get(CONTENT_LENGTH).unwrap(), "6");
+        assert_eq!(rsp.headers().get(CONTENT_ENCODING).unwrap(), "br");
+        assert!(rsp.headers().get(VARY).is_none());
+        assert_eq!(read(rsp.into_parts().1).0, b"brotli");
+
+        let headers = [("content-type", "image/png")];
+        let mut svc = Compress::new(upstream(&headers, &["png"]), true);
+        let rsp = svc.call(get("gzip")).wait().unwrap();
+        assert!(rsp.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(read(rsp.into_parts().1).0, b"png");
+    }
+
+    #[test]
+    fn leaves_responses_alone_unless_gzip_is_accepted_and_enabled() {
+        let headers = [("content-length", "5"), ("content-type", "text/plain")];
+        for &(enabled, accept_encoding) in &[(true, "gzip;q=0, *"), (true, "br"), (false, "gzip")] {
+            let mut svc = Compress::new(upstream(&headers, &["plain"]), enabled);
+            let rsp = svc.call(get(accept_encoding)).wait().unwrap();
+            assert!(rsp.headers().get(CONTENT_ENCODING).is_none(), "{}", accept_encoding);
+            assert_eq!(rsp.headers().get(CONTENT_LENGTH).unwrap(), "5");
+            let (data, trailers) = read(rsp.into_parts().1);
+            assert_eq!(data, b"plain");
+            assert!(trailers.is_some());
+        }
+    }
+
+    #[test]
+    fn parses_accept_encoding() {
+        let accepts = |value: &str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+            accepts_gzip(&headers)
+        };
+        assert!(accepts("gzip"));
+        assert!(accepts("deflate, GZIP"));
+        assert!(accepts("x-gzip;q=0.1"));
+        assert!(accepts("*"));
+        assert!(!accepts("identity"));
+        assert!(!accepts("gzip; q=0"));
+        assert!(!accepts("*, gzip;q=0"));
+        assert!(!accepts(""));
+    }
+}
@@ -9,7 +9,16 @@ use std::time::Duration;
 use http;
 use indexmap::IndexSet;
 
-use transport::{Host, HostAndPort, HostAndPortError};
+use bind::{MissingHostPolicy, ProtocolPolicy};
+use fault::FaultConfig;
+use pin::UnhealthyPins;
+use priority::Priority;
+use response_headers::{self, ResponseHeaders};
+use route::RoutePolicy;
+use split::{self, Split};
+use telemetry;
+use telemetry::sensor::trace;
+use transport::{DnsNameAndPort, Host, HostAndPort, HostAndPortError};
 use convert::TryFrom;
 
 // TODO:
@@ -47,15 +56,413 @@ pub struct Config {
     /// The path to "/etc/resolv.conf"
     pub resolv_conf_path: PathBuf,
 
+    /// The path to PEM-encoded trust anchors, if outbound connections should
+    /// be encrypted with TLS.
+    pub tls_trust_anchors: Option<PathBuf>,
+
+    /// The paths to a PEM-encoded certificate chain and its private key,
+    /// presented to servers that request client authentication, if TLS is
+    /// enabled.
+    pub tls_client_identity: Option<(PathBuf, PathBuf)>,
+
+    /// The time between reloads of the client certificate and key, so that
+    /// they may be rotated.
+    pub tls_client_identity_reload_interval: Duration,
+
     /// Where to talk to the control plane.
     pub control_host_and_port: HostAndPort,
 
     /// Event queue capacity.
     pub event_buffer_capacity: usize,
 
+    /// If set, connection and request lifecycle events are logged, with up
+    /// to this many events buffered for logging.
+    pub lifecycle_event_log_capacity: Option<usize>,
+
+    /// If set, a record of each completed request is written to stdout in
+    /// this format.
+    pub access_log_format: Option<telemetry::access_log::Format>,
+
+    /// The request headers whose values are included in access log records.
+    pub access_log_headers: Vec<http::header::HeaderName>,
+
+    /// The number of completed requests that may be buffered for the access
+    /// log before records are dropped.
+    pub access_log_capacity: usize,
+
+    /// If set, up to this many recently completed requests may be recorded
+    /// and served by the metrics server.
+    pub recent_requests_capacity: Option<usize>,
+
+    /// Whether recent requests are recorded from startup, rather than once
+    /// recording is enabled through the metrics server.
+    pub recent_requests_enabled: bool,
+
+    /// The upper bounds, in milliseconds, of latency histogram buckets, if
+    /// the defaults should not be used.
+    pub metrics_latency_buckets: Option<Vec<u32>>,
+
+    /// The maximum number of distinct authorities to label metrics with.
+    pub metrics_max_authorities: usize,
+
+    /// If set, metrics are also exported to a StatsD collector at this
+    /// address over UDP.
+    pub statsd_addr: Option<SocketAddr>,
+
+    /// The prefix of the names of metrics exported to StatsD.
+    pub statsd_prefix: String,
+
+    /// How often metrics are exported to StatsD.
+    pub metrics_export_interval: Duration,
+
+    /// Headers whose values are redacted from telemetry.
+    pub redacted_headers: Vec<http::header::HeaderName>,
+
+    /// If set, the headers whose values are redacted from the telemetry of
+    /// inbound requests, instead of `redacted_headers`.
+    pub inbound_redacted_headers: Option<Vec<http::header::HeaderName>>,
+
+    /// If set, the headers whose values are redacted from the telemetry of
+    /// outbound requests, instead of `redacted_headers`.
+    pub outbound_redacted_headers: Option<Vec<http::header::HeaderName>>,
+
+    /// If set, the only HTTP statuses that classify responses as failures.
+    /// Otherwise, server errors are failures.
+    pub failure_status_codes: Option<Vec<http::StatusCode>>,
+
+    /// If set, each proxied request is sent with its ID in this header,
+    /// unless it already has one.
+    pub request_id_header: Option<http::header::HeaderName>,
+
+    /// If set, trace context is propagated across each proxied request, and
+    /// new traces are begun in this format.
+    pub trace_propagation: Option<trace::Propagation>,
+
+    /// If set, how responses that upstreams compressed with gzip are
+    /// decompressed for telemetry: `inspect` decompresses them only for
+    /// telemetry, and `forward` also sends them to clients decompressed.
+    pub decompress_responses: Option<telemetry::sensor::Decompress>,
+
+    /// If set, the most bytes that a response may decompress into before
+    /// it fails, rather than the sensors' default.
+    pub decompress_max_bytes: Option<usize>,
+
     /// Timeout after which to cancel binding a request.
     pub bind_timeout: Duration,
 
+    /// Timeout after which to fail a request, including the time spent
+    /// streaming its response, if requests should time out.
+    pub request_timeout: Option<Duration>,
+
+    /// Whether gRPC requests must complete within their `grpc-timeout`s,
+    /// which are propagated to upstreams.
+    pub grpc_deadlines: bool,
+
+    /// The longest that each request may take in total, across every
+    /// attempt, if request budgets limit their durations.
+    pub request_budget_max_duration: Option<Duration>,
+
+    /// The most times that each request may be attempted, across retries,
+    /// hedges, and resends, if request budgets limit their attempts.
+    pub request_budget_max_attempts: Option<usize>,
+
+    /// The most bytes of each request's body that may be buffered, if
+    /// request budgets limit their buffering.
+    pub request_budget_max_buffered_bytes: Option<u64>,
+
+    /// The maximum size of a response body, in bytes, if response bodies
+    /// should be limited.
+    pub max_response_bytes: Option<u64>,
+
+    /// The maximum total size of a message's headers, in bytes, and of each
+    /// of its header lines, if headers should be limited.
+    pub max_header_bytes: Option<usize>,
+    pub max_header_line_bytes: Option<usize>,
+
+    /// The header whose value classifies a request's priority, if requests
+    /// should be prioritized when buffers are full.
+    pub priority_header: Option<http::header::HeaderName>,
+
+    /// Whether responses are compressed with gzip for clients that accept
+    /// it, unless a route overrides this.
+    pub gzip_responses: bool,
+
+    /// The headers added to every proxied response.
+    pub response_headers: ResponseHeaders,
+
+    /// The maximum total size of the responses cached for outbound requests,
+    /// in bytes, if responses should be cached.
+    pub outbound_response_cache_max_bytes: Option<usize>,
+
+    /// The address of an HTTP proxy through which outbound connections are
+    /// tunneled with `CONNECT`, if they should be.
+    pub outbound_connect_proxy_addr: Option<SocketAddr>,
+
+    /// The value of the `Proxy-Authorization` header sent to the `CONNECT`
+    /// proxy, if it requires authorization.
+    pub outbound_connect_proxy_authorization: Option<http::header::HeaderValue>,
+
+    /// The maximum size of an inbound request body, in bytes, if request
+    /// bodies should be limited.
+    pub inbound_max_request_bytes: Option<u64>,
+
+    /// The time to wait for a response before sending a retry-safe request
+    /// to another endpoint, if outbound requests should be hedged.
+    pub hedge_delay: Option<Duration>,
+
+    /// If set, outbound requests fail once discovery has had no endpoints
+    /// for their destination for this long.
+    pub fail_fast_grace_period: Option<Duration>,
+
+    /// The maximum number of times to retry a failed request, if requests
+    /// should be retried.
+    pub max_retries: Option<usize>,
+
+    /// Methods to retry in addition to the idempotent methods.
+    pub retry_methods: Vec<http::Method>,
+
+    /// The percentage of requests that may be retried, in addition to the
+    /// minimum retries each second.
+    pub retry_budget_percent: u32,
+
+    /// The number of retries that may be made each second, however few
+    /// requests are sent.
+    pub retry_budget_min_per_second: u32,
+
+    /// If set, the bodies of requests that may be retried are buffered, up
+    /// to this many bytes, so that they may be replayed.
+    pub retry_buffer_max_bytes: Option<u64>,
+
+    /// Timeouts, retries, and concurrency limits for outbound requests to
+    /// particular authorities, overriding the defaults, and faults to inject
+    /// into those requests.
+    pub route_policies: Vec<(DnsNameAndPort, RoutePolicy)>,
+
+    /// Outbound authorities whose requests are split between other
+    /// authorities by weight, until the split is changed through the admin
+    /// API.
+    pub traffic_splits: Vec<(DnsNameAndPort, Split)>,
+
+    /// The number of consecutive failures after which to stop sending
+    /// requests to an endpoint, if circuit breaking is enabled.
+    pub breaker_failure_threshold: Option<usize>,
+
+    /// The time to wait after an endpoint's circuit breaker opens before
+    /// sending it another request.
+    pub breaker_open_timeout: Duration,
+
+    /// The path requested from discovered endpoints to check their health,
+    /// if health checking is enabled.
+    pub health_check_path: Option<http::uri::PathAndQuery>,
+
+    /// The time between health checks of each discovered endpoint.
+    pub health_check_interval: Duration,
+
+    /// The status with which a healthy endpoint responds to health checks.
+    pub health_check_status: http::StatusCode,
+
+    /// The number of consecutive health checks an unhealthy endpoint must
+    /// pass to receive requests again.
+    pub health_check_healthy_threshold: usize,
+
+    /// The number of consecutive health checks after whose failure an
+    /// endpoint stops receiving requests.
+    pub health_check_unhealthy_threshold: usize,
+
+    /// The number of consecutive failed requests after which a discovered
+    /// endpoint is ejected from load balancing, if outlier detection is
+    /// enabled.
+    pub outlier_consecutive_failures: Option<usize>,
+
+    /// The time for which an outlier is first ejected. Each consecutive
+    /// ejection is twice as long.
+    pub outlier_base_ejection_time: Duration,
+
+    /// The longest time for which an outlier may be ejected.
+    pub outlier_max_ejection_time: Duration,
+
+    /// The time that requests in flight to a removed endpoint may take to
+    /// receive a response.
+    pub endpoint_drain_grace_period: Duration,
+
+    /// The time that in-flight requests may take to complete once shutdown
+    /// is signaled, after which the proxy exits anyway. If unset, the proxy
+    /// waits for all requests to complete.
+    pub shutdown_grace_period: Option<Duration>,
+
+    /// The initial delay between failed reconnection attempts, if reconnects
+    /// should back off.
+    pub reconnect_backoff_min: Option<Duration>,
+
+    /// The maximum delay between failed reconnection attempts.
+    pub reconnect_backoff_max: Duration,
+
+    /// The maximum number of requests that may be buffered for each endpoint.
+    pub buffer_capacity: usize,
+
+    /// The maximum number of requests that may be in flight to each endpoint
+    /// at once, if any.
+    pub endpoint_concurrency_limit: Option<usize>,
+
+    /// The maximum rate, in requests per second, of requests to all
+    /// destinations, if requests should be rate limited.
+    pub global_rate_limit: Option<u32>,
+
+    /// The maximum rate, in requests per second, of requests to each
+    /// authority, if requests should be rate limited.
+    pub authority_rate_limit: Option<u32>,
+
+    /// The maximum number of requests allowed in a burst by each rate limit,
+    /// if it differs from the rate limit's requests per second.
+    pub rate_limit_burst: Option<u32>,
+
+    /// The maximum number of idle HTTP/1 connections kept alive to each
+    /// endpoint, if idle connections should not all be kept.
+    pub h1_max_idle_connections: Option<usize>,
+
+    /// The maximum number of HTTP/1 connections open to each endpoint, if
+    /// it should be limited.
+    pub h1_max_connections: Option<usize>,
+
+    /// The maximum number of connection attempts in flight to each
+    /// endpoint, if it should be limited.
+    pub connect_concurrency: Option<usize>,
+
+    /// The maximum number of connections open to all endpoints together,
+    /// if it should be limited.
+    pub max_connections: Option<usize>,
+
+    /// How long a connection attempt waits for another connection to close
+    /// once `max_connections` are open, if it shouldn't wait indefinitely.
+    pub max_connections_wait_timeout: Option<Duration>,
+
+    /// How long an idle HTTP/1 connection is kept alive, if the default
+    /// should not be used.
+    pub h1_idle_timeout: Option<Duration>,
+
+    /// How long an HTTP/1 request that expects `100 Continue` waits for its
+    /// upstream to respond before sending its body anyway, if it shouldn't
+    /// wait until the upstream responds.
+    pub h1_expect_continue_timeout: Option<Duration>,
+
+    /// The time after which a client connection without any requests in
+    /// flight is closed, if idle connections should be closed.
+    pub connection_idle_timeout: Option<Duration>,
+
+    /// The initial flow-control window of each HTTP/2 stream, if the default
+    /// should not be used.
+    pub h2_initial_stream_window_size: Option<u32>,
+
+    /// The initial flow-control window of each HTTP/2 connection, if the
+    /// default should not be used.
+    pub h2_initial_connection_window_size: Option<u32>,
+
+    /// The maximum number of concurrent streams advertised to HTTP/2 servers,
+    /// if the default should not be used.
+    pub h2_max_concurrent_streams: Option<u32>,
+
+    /// How long a client connection may be idle before TCP keepalive probes
+    /// are sent, if TCP keepalive should be enabled.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// The size of client connections' socket receive buffers, if the
+    /// kernel's default should not be used.
+    pub socket_recv_buffer_bytes: Option<usize>,
+
+    /// The size of client connections' socket send buffers, if the kernel's
+    /// default should not be used.
+    pub socket_send_buffer_bytes: Option<usize>,
+
+    /// Whether `TCP_NODELAY` is set on accepted and client connections.
+    pub tcp_nodelay: bool,
+
+    /// Whether accepted and client connections are primed with
+    /// `TCP_QUICKACK`.
+    pub tcp_quickack: bool,
+
+    /// How often HTTP/2 client connections are PINGed, if they should be.
+    pub h2_keepalive_interval: Option<Duration>,
+
+    /// The time after which an HTTP/2 client connection whose PING has not
+    /// been acknowledged is closed.
+    pub h2_keepalive_timeout: Duration,
+
+    /// How long an HTTP/2 client connection may idle before it's PINGed
+    /// again before being reused, if it should be.
+    pub h2_keepalive_idle_validation: Option<Duration>,
+
+    /// How outbound requests are balanced over a destination's endpoints.
+    pub outbound_load_balancer: LoadBalancer,
+
+    /// Overrides the protocol with which inbound requests are sent to the
+    /// local application.
+    pub inbound_protocol_policy: ProtocolPolicy,
+
+    /// Overrides the protocol with which outbound requests are sent.
+    pub outbound_protocol_policy: ProtocolPolicy,
+
+    /// How outbound HTTP/1 requests without a `Host` header are handled.
+    pub outbound_missing_host_policy: MissingHostPolicy,
+
+    /// Whether outbound requests are sent to their original destinations
+    /// while discovery has no endpoints for their authorities.
+    pub outbound_orig_dst_fallback: bool,
+
+    /// Upstreams, by authority or address, that speak HTTP/2 without TLS
+    /// with prior knowledge, to which outbound HTTP/1 requests are sent as
+    /// HTTP/2.
+    pub outbound_h2c_upstreams: Vec<HostAndPort>,
+
+    /// The latency assumed for an endpoint that has not yet responded, when
+    /// balancing by peak-EWMA.
+    pub peak_ewma_default_rtt: Duration,
+
+    /// The time over which past latencies lose their influence on the
+    /// peak-EWMA load estimate.
+    pub peak_ewma_decay: Duration,
+
+    /// The time over which the share of outbound requests sent to a newly
+    /// added endpoint ramps up to its full weight, if endpoints should
+    /// slow-start.
+    pub outbound_slow_start_window: Option<Duration>,
+
+    /// The authority to which a copy of inbound requests is sent, if inbound
+    /// requests should be mirrored.
+    pub inbound_mirror_authority: Option<http::uri::Authority>,
+
+    /// The percentage of inbound requests that are mirrored.
+    pub inbound_mirror_percent: u32,
+
+    /// The largest request body that is buffered so that its request may be
+    /// mirrored. Requests with larger bodies, or bodies of unknown length,
+    /// are not mirrored.
+    pub inbound_mirror_max_body_bytes: u64,
+
+    /// The header whose value restricts an outbound request to the endpoints
+    /// with a matching tag, if requests may be routed by tag.
+    pub route_tag_header: Option<http::header::HeaderName>,
+
+    /// The zone that the proxy runs in, if outbound requests should prefer
+    /// the endpoints in the same zone.
+    pub zone: Option<String>,
+
+    /// How few of the endpoints in the proxy's zone may be available before
+    /// outbound requests spill over to other zones, if not the default of 1.
+    pub zone_min_local_endpoints: Option<usize>,
+
+    /// The header whose value gives outbound requests affinity with an
+    /// endpoint, if requests are sticky. Takes precedence over
+    /// `outbound_affinity_cookie`.
+    pub outbound_affinity_header: Option<http::header::HeaderName>,
+
+    /// The cookie whose value gives outbound requests affinity with an
+    /// endpoint, if requests are sticky.
+    pub outbound_affinity_cookie: Option<String>,
+
+    /// Whether outbound requests pinned to endpoints that aren't healthy
+    /// are sent anyway, if not the default of rejecting them.
+    pub outbound_unhealthy_pins: Option<UnhealthyPins>,
+
     pub pod_namespace: String,
 }
 
@@ -68,6 +475,17 @@ pub struct Listener {
     pub addr: Addr,
 }
 
+/// Strategies for balancing outbound requests over a destination's endpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadBalancer {
+    /// Choose endpoints at random, in proportion to their weights.
+    WeightedRandom,
+
+    /// Choose the less-loaded of two endpoints, estimating load from pending
+    /// requests and a peak-EWMA of response latency.
+    PeakEwma,
+}
+
 /// A logical address. This abstracts over the various strategies for cross
 /// process communication.
 #[derive(Clone, Copy, Debug)]
@@ -85,6 +503,26 @@ pub enum ParseError {
     NotANumber,
     HostIsNotAnIpAddress,
     NotUnicode,
+    NotAMethod,
+    NotAHeaderName,
+    NotAHeaderValue,
+    NotAStatusCode,
+    NotAPath,
+    NotALoadBalancer,
+    NotAMissingHostPolicy,
+    NotAProtocolPolicy,
+    NotABool,
+    NotASocketAddr,
+    NotATracePropagation,
+    NotADecompression,
+    NotAnAuthority,
+    NotARoutePolicy,
+    NotATrafficSplit,
+    NotAPercentage,
+    NotACookieName,
+    NotAnUnhealthyPinPolicy,
+    NotAnAccessLogFormat,
+    NotAResponseHeader,
     UrlError(UrlError),
 }
 
@@ -123,14 +561,117 @@ pub struct TestEnv {
 
 // Environment variables to look at when loading the configuration
 const ENV_EVENT_BUFFER_CAPACITY: &str = "CONDUIT_PROXY_EVENT_BUFFER_CAPACITY";
+const ENV_LIFECYCLE_EVENT_LOG_CAPACITY: &str = "CONDUIT_PROXY_LIFECYCLE_EVENT_LOG_CAPACITY";
+pub const ENV_ACCESS_LOG_FORMAT: &str = "CONDUIT_PROXY_ACCESS_LOG_FORMAT";
+pub const ENV_ACCESS_LOG_HEADERS: &str = "CONDUIT_PROXY_ACCESS_LOG_HEADERS";
+pub const ENV_ACCESS_LOG_CAPACITY: &str = "CONDUIT_PROXY_ACCESS_LOG_CAPACITY";
+pub const ENV_RECENT_REQUESTS_CAPACITY: &str = "CONDUIT_PROXY_RECENT_REQUESTS_CAPACITY";
+pub const ENV_RECENT_REQUESTS_ENABLED: &str = "CONDUIT_PROXY_RECENT_REQUESTS_ENABLED";
+pub const ENV_BUFFER_CAPACITY: &str = "CONDUIT_PROXY_BUFFER_CAPACITY";
+pub const ENV_ENDPOINT_CONCURRENCY_LIMIT: &str = "CONDUIT_PROXY_ENDPOINT_CONCURRENCY_LIMIT";
+pub const ENV_GLOBAL_RATE_LIMIT: &str = "CONDUIT_PROXY_GLOBAL_RATE_LIMIT";
+pub const ENV_AUTHORITY_RATE_LIMIT: &str = "CONDUIT_PROXY_AUTHORITY_RATE_LIMIT";
+pub const ENV_RATE_LIMIT_BURST: &str = "CONDUIT_PROXY_RATE_LIMIT_BURST";
+pub const ENV_H1_MAX_IDLE_CONNECTIONS: &str = "CONDUIT_PROXY_H1_MAX_IDLE_CONNECTIONS";
+pub const ENV_H1_MAX_CONNECTIONS: &str = "CONDUIT_PROXY_H1_MAX_CONNECTIONS";
+pub const ENV_CONNECT_CONCURRENCY: &str = "CONDUIT_PROXY_CONNECT_CONCURRENCY";
+pub const ENV_MAX_CONNECTIONS: &str = "CONDUIT_PROXY_MAX_CONNECTIONS";
+pub const ENV_MAX_CONNECTIONS_WAIT_TIMEOUT: &str = "CONDUIT_PROXY_MAX_CONNECTIONS_WAIT_TIMEOUT";
+pub const ENV_H1_IDLE_TIMEOUT: &str = "CONDUIT_PROXY_H1_IDLE_TIMEOUT";
+pub const ENV_H1_EXPECT_CONTINUE_TIMEOUT: &str = "CONDUIT_PROXY_H1_EXPECT_CONTINUE_TIMEOUT";
+pub const ENV_CONNECTION_IDLE_TIMEOUT: &str = "CONDUIT_PROXY_CONNECTION_IDLE_TIMEOUT";
+pub const ENV_H2_INITIAL_STREAM_WINDOW_SIZE: &str = "CONDUIT_PROXY_H2_INITIAL_STREAM_WINDOW_SIZE";
+pub const ENV_H2_INITIAL_CONNECTION_WINDOW_SIZE: &str =
+    "CONDUIT_PROXY_H2_INITIAL_CONNECTION_WINDOW_SIZE";
+pub const ENV_H2_MAX_CONCURRENT_STREAMS: &str = "CONDUIT_PROXY_H2_MAX_CONCURRENT_STREAMS";
+pub const ENV_TCP_KEEPALIVE: &str = "CONDUIT_PROXY_TCP_KEEPALIVE";
+pub const ENV_SOCKET_RECV_BUFFER_BYTES: &str = "CONDUIT_PROXY_SOCKET_RECV_BUFFER_BYTES";
+pub const ENV_SOCKET_SEND_BUFFER_BYTES: &str = "CONDUIT_PROXY_SOCKET_SEND_BUFFER_BYTES";
+pub const ENV_TCP_NODELAY: &str = "CONDUIT_PROXY_TCP_NODELAY";
+pub const ENV_TCP_QUICKACK: &str = "CONDUIT_PROXY_TCP_QUICKACK";
+pub const ENV_H2_KEEPALIVE_INTERVAL: &str = "CONDUIT_PROXY_H2_KEEPALIVE_INTERVAL";
+pub const ENV_H2_KEEPALIVE_TIMEOUT: &str = "CONDUIT_PROXY_H2_KEEPALIVE_TIMEOUT";
+pub const ENV_H2_KEEPALIVE_IDLE_VALIDATION: &str = "CONDUIT_PROXY_H2_KEEPALIVE_IDLE_VALIDATION";
+pub const ENV_OUTBOUND_LOAD_BALANCER: &str = "CONDUIT_PROXY_OUTBOUND_LOAD_BALANCER";
+pub const ENV_INBOUND_PROTOCOL_POLICY: &str = "CONDUIT_PROXY_INBOUND_PROTOCOL_POLICY";
+pub const ENV_OUTBOUND_PROTOCOL_POLICY: &str = "CONDUIT_PROXY_OUTBOUND_PROTOCOL_POLICY";
+pub const ENV_OUTBOUND_MISSING_HOST_POLICY: &str = "CONDUIT_PROXY_OUTBOUND_MISSING_HOST_POLICY";
+pub const ENV_OUTBOUND_ORIG_DST_FALLBACK: &str = "CONDUIT_PROXY_OUTBOUND_ORIG_DST_FALLBACK";
+pub const ENV_OUTBOUND_H2C_UPSTREAMS: &str = "CONDUIT_PROXY_OUTBOUND_H2C_UPSTREAMS";
+pub const ENV_PEAK_EWMA_DEFAULT_RTT: &str = "CONDUIT_PROXY_PEAK_EWMA_DEFAULT_RTT";
+pub const ENV_PEAK_EWMA_DECAY: &str = "CONDUIT_PROXY_PEAK_EWMA_DECAY";
+pub const ENV_OUTBOUND_SLOW_START_WINDOW: &str = "CONDUIT_PROXY_OUTBOUND_SLOW_START_WINDOW";
+pub const ENV_INBOUND_MIRROR_AUTHORITY: &str = "CONDUIT_PROXY_INBOUND_MIRROR_AUTHORITY";
+pub const ENV_INBOUND_MIRROR_PERCENT: &str = "CONDUIT_PROXY_INBOUND_MIRROR_PERCENT";
+pub const ENV_INBOUND_MIRROR_MAX_BODY_BYTES: &str = "CONDUIT_PROXY_INBOUND_MIRROR_MAX_BODY_BYTES";
+pub const ENV_ROUTE_TAG_HEADER: &str = "CONDUIT_PROXY_ROUTE_TAG_HEADER";
+pub const ENV_ZONE: &str = "CONDUIT_PROXY_ZONE";
+pub const ENV_ZONE_MIN_LOCAL_ENDPOINTS: &str = "CONDUIT_PROXY_ZONE_MIN_LOCAL_ENDPOINTS";
+pub const ENV_OUTBOUND_AFFINITY_HEADER: &str = "CONDUIT_PROXY_OUTBOUND_AFFINITY_HEADER";
+pub const ENV_OUTBOUND_AFFINITY_COOKIE: &str = "CONDUIT_PROXY_OUTBOUND_AFFINITY_COOKIE";
+pub const ENV_OUTBOUND_UNHEALTHY_PINS: &str = "CONDUIT_PROXY_OUTBOUND_UNHEALTHY_PINS";
+pub const ENV_REQUEST_TIMEOUT: &str = "CONDUIT_PROXY_REQUEST_TIMEOUT";
+pub const ENV_GRPC_DEADLINES: &str = "CONDUIT_PROXY_GRPC_DEADLINES";
+pub const ENV_REQUEST_BUDGET_MAX_DURATION: &str = "CONDUIT_PROXY_REQUEST_BUDGET_MAX_DURATION";
+pub const ENV_REQUEST_BUDGET_MAX_ATTEMPTS: &str = "CONDUIT_PROXY_REQUEST_BUDGET_MAX_ATTEMPTS";
+pub const ENV_REQUEST_BUDGET_MAX_BUFFERED_BYTES: &str =
+    "CONDUIT_PROXY_REQUEST_BUDGET_MAX_BUFFERED_BYTES";
+pub const ENV_MAX_RESPONSE_BYTES: &str = "CONDUIT_PROXY_MAX_RESPONSE_BYTES";
+pub const ENV_MAX_HEADER_BYTES: &str = "CONDUIT_PROXY_MAX_HEADER_BYTES";
+pub const ENV_MAX_HEADER_LINE_BYTES: &str = "CONDUIT_PROXY_MAX_HEADER_LINE_BYTES";
+pub const ENV_PRIORITY_HEADER: &str = "CONDUIT_PROXY_PRIORITY_HEADER";
+pub const ENV_GZIP_RESPONSES: &str = "CONDUIT_PROXY_GZIP_RESPONSES";
+pub const ENV_RESPONSE_HEADERS: &str = "CONDUIT_PROXY_RESPONSE_HEADERS";
+pub const ENV_OUTBOUND_RESPONSE_CACHE_MAX_BYTES: &str =
+    "CONDUIT_PROXY_OUTBOUND_RESPONSE_CACHE_MAX_BYTES";
+pub const ENV_OUTBOUND_CONNECT_PROXY_ADDR: &str = "CONDUIT_PROXY_OUTBOUND_CONNECT_PROXY_ADDR";
+pub const ENV_OUTBOUND_CONNECT_PROXY_AUTHORIZATION: &str =
+    "CONDUIT_PROXY_OUTBOUND_CONNECT_PROXY_AUTHORIZATION";
+pub const ENV_INBOUND_MAX_REQUEST_BYTES: &str = "CONDUIT_PROXY_INBOUND_MAX_REQUEST_BYTES";
+pub const ENV_HEDGE_DELAY: &str = "CONDUIT_PROXY_HEDGE_DELAY";
+pub const ENV_FAIL_FAST_GRACE_PERIOD: &str = "CONDUIT_PROXY_FAIL_FAST_GRACE_PERIOD";
+pub const ENV_MAX_RETRIES: &str = "CONDUIT_PROXY_MAX_RETRIES";
+pub const ENV_RETRY_METHODS: &str = "CONDUIT_PROXY_RETRY_METHODS";
+pub const ENV_RETRY_BUDGET_PERCENT: &str = "CONDUIT_PROXY_RETRY_BUDGET_PERCENT";
+pub const ENV_RETRY_BUDGET_MIN_PER_SECOND: &str = "CONDUIT_PROXY_RETRY_BUDGET_MIN_PER_SECOND";
+pub const ENV_RETRY_BUFFER_MAX_BYTES: &str = "CONDUIT_PROXY_RETRY_BUFFER_MAX_BYTES";
+pub const ENV_ROUTE_POLICIES: &str = "CONDUIT_PROXY_ROUTE_POLICIES";
+pub const ENV_TRAFFIC_SPLITS: &str = "CONDUIT_PROXY_TRAFFIC_SPLITS";
+pub const ENV_BREAKER_FAILURE_THRESHOLD: &str = "CONDUIT_PROXY_BREAKER_FAILURE_THRESHOLD";
+pub const ENV_BREAKER_OPEN_TIMEOUT: &str = "CONDUIT_PROXY_BREAKER_OPEN_TIMEOUT";
+pub const ENV_HEALTH_CHECK_PATH: &str = "CONDUIT_PROXY_HEALTH_CHECK_PATH";
+pub const ENV_HEALTH_CHECK_INTERVAL: &str = "CONDUIT_PROXY_HEALTH_CHECK_INTERVAL";
+pub const ENV_HEALTH_CHECK_STATUS: &str = "CONDUIT_PROXY_HEALTH_CHECK_STATUS";
+pub const ENV_HEALTH_CHECK_HEALTHY_THRESHOLD: &str = "CONDUIT_PROXY_HEALTH_CHECK_HEALTHY_THRESHOLD";
+pub const ENV_HEALTH_CHECK_UNHEALTHY_THRESHOLD: &str = "CONDUIT_PROXY_HEALTH_CHECK_UNHEALTHY_THRESHOLD";
+pub const ENV_OUTLIER_CONSECUTIVE_FAILURES: &str = "CONDUIT_PROXY_OUTLIER_CONSECUTIVE_FAILURES";
+pub const ENV_OUTLIER_BASE_EJECTION_TIME: &str = "CONDUIT_PROXY_OUTLIER_BASE_EJECTION_TIME";
+pub const ENV_OUTLIER_MAX_EJECTION_TIME: &str = "CONDUIT_PROXY_OUTLIER_MAX_EJECTION_TIME";
 pub const ENV_PRIVATE_LISTENER: &str = "CONDUIT_PROXY_PRIVATE_LISTENER";
 pub const ENV_PRIVATE_FORWARD: &str = "CONDUIT_PROXY_PRIVATE_FORWARD";
 pub const ENV_PUBLIC_LISTENER: &str = "CONDUIT_PROXY_PUBLIC_LISTENER";
 pub const ENV_CONTROL_LISTENER: &str = "CONDUIT_PROXY_CONTROL_LISTENER";
 pub const ENV_METRICS_LISTENER: &str = "CONDUIT_PROXY_METRICS_LISTENER";
+pub const ENV_METRICS_LATENCY_BUCKETS: &str = "CONDUIT_PROXY_METRICS_LATENCY_BUCKETS";
+pub const ENV_METRICS_MAX_AUTHORITIES: &str = "CONDUIT_PROXY_METRICS_MAX_AUTHORITIES";
+pub const ENV_METRICS_EXPORT_INTERVAL: &str = "CONDUIT_PROXY_METRICS_EXPORT_INTERVAL";
+pub const ENV_STATSD_ADDR: &str = "CONDUIT_PROXY_STATSD_ADDR";
+pub const ENV_STATSD_PREFIX: &str = "CONDUIT_PROXY_STATSD_PREFIX";
+pub const ENV_REDACTED_HEADERS: &str = "CONDUIT_PROXY_REDACTED_HEADERS";
+pub const ENV_INBOUND_REDACTED_HEADERS: &str = "CONDUIT_PROXY_INBOUND_REDACTED_HEADERS";
+pub const ENV_OUTBOUND_REDACTED_HEADERS: &str = "CONDUIT_PROXY_OUTBOUND_REDACTED_HEADERS";
+pub const ENV_FAILURE_STATUS_CODES: &str = "CONDUIT_PROXY_FAILURE_STATUS_CODES";
+pub const ENV_REQUEST_ID_HEADER: &str = "CONDUIT_PROXY_REQUEST_ID_HEADER";
+pub const ENV_TRACE_PROPAGATION: &str = "CONDUIT_PROXY_TRACE_PROPAGATION";
+pub const ENV_DECOMPRESS_RESPONSES: &str = "CONDUIT_PROXY_DECOMPRESS_RESPONSES";
+pub const ENV_DECOMPRESS_MAX_BYTES: &str = "CONDUIT_PROXY_DECOMPRESS_MAX_BYTES";
 const ENV_PRIVATE_CONNECT_TIMEOUT: &str = "CONDUIT_PROXY_PRIVATE_CONNECT_TIMEOUT";
 const ENV_PUBLIC_CONNECT_TIMEOUT: &str = "CONDUIT_PROXY_PUBLIC_CONNECT_TIMEOUT";
 pub const ENV_BIND_TIMEOUT: &str = "CONDUIT_PROXY_BIND_TIMEOUT";
+pub const ENV_ENDPOINT_DRAIN_GRACE_PERIOD: &str = "CONDUIT_PROXY_ENDPOINT_DRAIN_GRACE_PERIOD";
+pub const ENV_SHUTDOWN_GRACE_PERIOD: &str = "CONDUIT_PROXY_SHUTDOWN_GRACE_PERIOD";
+pub const ENV_RECONNECT_BACKOFF_MIN: &str = "CONDUIT_PROXY_RECONNECT_BACKOFF_MIN";
+pub const ENV_RECONNECT_BACKOFF_MAX: &str = "CONDUIT_PROXY_RECONNECT_BACKOFF_MAX";
 
 // These *disable* our protocol detection for connections whose SO_ORIGINAL_DST
 // has a port in the provided list.
@@ -141,9 +682,18 @@ pub const ENV_POD_NAMESPACE: &str = "CONDUIT_PROXY_POD_NAMESPACE";
 
 pub const ENV_CONTROL_URL: &str = "CONDUIT_PROXY_CONTROL_URL";
 const ENV_RESOLV_CONF: &str = "CONDUIT_RESOLV_CONF";
+pub const ENV_TLS_TRUST_ANCHORS: &str = "CONDUIT_PROXY_TLS_TRUST_ANCHORS";
+pub const ENV_TLS_CLIENT_CERT: &str = "CONDUIT_PROXY_TLS_CLIENT_CERT";
+pub const ENV_TLS_CLIENT_KEY: &str = "CONDUIT_PROXY_TLS_CLIENT_KEY";
+pub const ENV_TLS_CLIENT_IDENTITY_RELOAD_INTERVAL: &str = "CONDUIT_PROXY_TLS_CLIENT_IDENTITY_RELOAD_INTERVAL";
 
 // Default values for various configuration fields
 const DEFAULT_EVENT_BUFFER_CAPACITY: usize = 10_000; // FIXME
+const DEFAULT_BUFFER_CAPACITY: usize = 10_000;
+const DEFAULT_ACCESS_LOG_CAPACITY: usize = 10_000;
+const DEFAULT_METRICS_MAX_AUTHORITIES: usize = 1_000;
+const DEFAULT_METRICS_EXPORT_INTERVAL_MS: u64 = 10_000;
+const DEFAULT_STATSD_PREFIX: &str = "conduit.proxy";
 const DEFAULT_PRIVATE_LISTENER: &str = "tcp://127.0.0.1:4140";
 const DEFAULT_PUBLIC_LISTENER: &str = "tcp://0.0.0.0:4143";
 const DEFAULT_CONTROL_LISTENER: &str = "tcp://0.0.0.0:4190";
@@ -151,6 +701,22 @@ const DEFAULT_METRICS_LISTENER: &str = "tcp://127.0.0.1:4191";
 const DEFAULT_PRIVATE_CONNECT_TIMEOUT_MS: u64 = 20;
 const DEFAULT_PUBLIC_CONNECT_TIMEOUT_MS: u64 = 300;
 const DEFAULT_BIND_TIMEOUT_MS: u64 = 10_000; // ten seconds, as in Linkerd.
+const DEFAULT_H2_KEEPALIVE_TIMEOUT_MS: u64 = 20_000;
+const DEFAULT_RECONNECT_BACKOFF_MAX_MS: u64 = 10_000;
+const DEFAULT_BREAKER_OPEN_TIMEOUT_MS: u64 = 5_000;
+const DEFAULT_HEALTH_CHECK_INTERVAL_MS: u64 = 10_000;
+const DEFAULT_TLS_CLIENT_IDENTITY_RELOAD_INTERVAL_MS: u64 = 60_000;
+const DEFAULT_HEALTH_CHECK_HEALTHY_THRESHOLD: usize = 2;
+const DEFAULT_HEALTH_CHECK_UNHEALTHY_THRESHOLD: usize = 3;
+const DEFAULT_RETRY_BUDGET_PERCENT: u32 = 20;
+const DEFAULT_RETRY_BUDGET_MIN_PER_SECOND: u32 = 0;
+const DEFAULT_OUTLIER_BASE_EJECTION_TIME_MS: u64 = 30_000;
+const DEFAULT_OUTLIER_MAX_EJECTION_TIME_MS: u64 = 300_000;
+const DEFAULT_ENDPOINT_DRAIN_GRACE_PERIOD_MS: u64 = 10_000;
+const DEFAULT_PEAK_EWMA_DEFAULT_RTT_MS: u64 = 30;
+const DEFAULT_PEAK_EWMA_DECAY_MS: u64 = 10_000;
+const DEFAULT_INBOUND_MIRROR_PERCENT: u32 = 100;
+const DEFAULT_INBOUND_MIRROR_MAX_BODY_BYTES: u64 = 64 * 1024;
 const DEFAULT_RESOLV_CONF: &str = "/etc/resolv.conf";
 
 // By default, we keep a list of known assigned ports of server-first protocols.
@@ -180,8 +746,166 @@ impl<'a> TryFrom<&'a Strings> for Config {
         let inbound_disable_ports = parse(strings, ENV_INBOUND_PORTS_DISABLE_PROTOCOL_DETECTION, parse_port_set);
         let outbound_disable_ports = parse(strings, ENV_OUTBOUND_PORTS_DISABLE_PROTOCOL_DETECTION, parse_port_set);
         let bind_timeout = parse(strings, ENV_BIND_TIMEOUT, parse_number);
+        let request_timeout = parse(strings, ENV_REQUEST_TIMEOUT, parse_number);
+        let grpc_deadlines = parse(strings, ENV_GRPC_DEADLINES, parse_bool);
+        let request_budget_max_duration =
+            parse(strings, ENV_REQUEST_BUDGET_MAX_DURATION, parse_number);
+        let request_budget_max_attempts =
+            parse(strings, ENV_REQUEST_BUDGET_MAX_ATTEMPTS, parse_number);
+        let request_budget_max_buffered_bytes =
+            parse(strings, ENV_REQUEST_BUDGET_MAX_BUFFERED_BYTES, parse_number);
+        let max_response_bytes = parse(strings, ENV_MAX_RESPONSE_BYTES, parse_number);
+        let max_header_bytes = parse(strings, ENV_MAX_HEADER_BYTES, parse_number);
+        let max_header_line_bytes = parse(strings, ENV_MAX_HEADER_LINE_BYTES, parse_number);
+        let priority_header = parse(strings, ENV_PRIORITY_HEADER, parse_header_name);
+        let gzip_responses = parse(strings, ENV_GZIP_RESPONSES, parse_bool);
+        let response_headers = parse(strings, ENV_RESPONSE_HEADERS, parse_response_headers);
+        let outbound_response_cache_max_bytes =
+            parse(strings, ENV_OUTBOUND_RESPONSE_CACHE_MAX_BYTES, parse_number);
+        let outbound_connect_proxy_addr =
+            parse(strings, ENV_OUTBOUND_CONNECT_PROXY_ADDR, parse_socket_addr);
+        let outbound_connect_proxy_authorization =
+            parse(strings, ENV_OUTBOUND_CONNECT_PROXY_AUTHORIZATION, parse_header_value);
+        let inbound_max_request_bytes =
+            parse(strings, ENV_INBOUND_MAX_REQUEST_BYTES, parse_number);
+        let hedge_delay = parse(strings, ENV_HEDGE_DELAY, parse_number);
+        let fail_fast_grace_period = parse(strings, ENV_FAIL_FAST_GRACE_PERIOD, parse_number);
+        let max_retries = parse(strings, ENV_MAX_RETRIES, parse_number);
+        let retry_methods = parse(strings, ENV_RETRY_METHODS, parse_method_list);
+        let retry_budget_percent = parse(strings, ENV_RETRY_BUDGET_PERCENT, parse_number);
+        let retry_budget_min_per_second =
+            parse(strings, ENV_RETRY_BUDGET_MIN_PER_SECOND, parse_number);
+        let retry_buffer_max_bytes = parse(strings, ENV_RETRY_BUFFER_MAX_BYTES, parse_number);
+        let route_policies = parse(strings, ENV_ROUTE_POLICIES, parse_route_policies);
+        let traffic_splits = parse(strings, ENV_TRAFFIC_SPLITS, parse_traffic_splits);
+        let breaker_failure_threshold = parse(strings, ENV_BREAKER_FAILURE_THRESHOLD, parse_number);
+        let breaker_open_timeout = parse(strings, ENV_BREAKER_OPEN_TIMEOUT, parse_number);
+        let health_check_path = parse(strings, ENV_HEALTH_CHECK_PATH, parse_path);
+        let health_check_interval = parse(strings, ENV_HEALTH_CHECK_INTERVAL, parse_number);
+        let health_check_status = parse(strings, ENV_HEALTH_CHECK_STATUS, parse_status_code);
+        let health_check_healthy_threshold =
+            parse(strings, ENV_HEALTH_CHECK_HEALTHY_THRESHOLD, parse_number);
+        let health_check_unhealthy_threshold =
+            parse(strings, ENV_HEALTH_CHECK_UNHEALTHY_THRESHOLD, parse_number);
+        let outlier_consecutive_failures =
+            parse(strings, ENV_OUTLIER_CONSECUTIVE_FAILURES, parse_number);
+        let outlier_base_ejection_time =
+            parse(strings, ENV_OUTLIER_BASE_EJECTION_TIME, parse_number);
+        let outlier_max_ejection_time =
+            parse(strings, ENV_OUTLIER_MAX_EJECTION_TIME, parse_number);
+        let endpoint_drain_grace_period =
+            parse(strings, ENV_ENDPOINT_DRAIN_GRACE_PERIOD, parse_number);
+        let shutdown_grace_period = parse(strings, ENV_SHUTDOWN_GRACE_PERIOD, parse_number);
+        let reconnect_backoff_min = parse(strings, ENV_RECONNECT_BACKOFF_MIN, parse_number);
+        let reconnect_backoff_max = parse(strings, ENV_RECONNECT_BACKOFF_MAX, parse_number);
         let resolv_conf_path = strings.get(ENV_RESOLV_CONF);
+        let tls_trust_anchors = strings.get(ENV_TLS_TRUST_ANCHORS);
+        let tls_client_cert = strings.get(ENV_TLS_CLIENT_CERT);
+        let tls_client_key = strings.get(ENV_TLS_CLIENT_KEY);
+        let tls_client_identity_reload_interval =
+            parse(strings, ENV_TLS_CLIENT_IDENTITY_RELOAD_INTERVAL, parse_number);
         let event_buffer_capacity = parse(strings, ENV_EVENT_BUFFER_CAPACITY, parse_number);
+        let lifecycle_event_log_capacity =
+            parse(strings, ENV_LIFECYCLE_EVENT_LOG_CAPACITY, parse_number);
+        let access_log_format = parse(strings, ENV_ACCESS_LOG_FORMAT, parse_access_log_format);
+        let access_log_headers = parse(strings, ENV_ACCESS_LOG_HEADERS, parse_header_name_list);
+        let access_log_capacity = parse(strings, ENV_ACCESS_LOG_CAPACITY, parse_number);
+        let recent_requests_capacity = parse(strings, ENV_RECENT_REQUESTS_CAPACITY, parse_number);
+        let recent_requests_enabled = parse(strings, ENV_RECENT_REQUESTS_ENABLED, parse_bool);
+        let metrics_latency_buckets = parse(strings, ENV_METRICS_LATENCY_BUCKETS, parse_number_list);
+        let metrics_max_authorities = parse(strings, ENV_METRICS_MAX_AUTHORITIES, parse_number);
+        let metrics_export_interval = parse(strings, ENV_METRICS_EXPORT_INTERVAL, parse_number);
+        let statsd_addr = parse(strings, ENV_STATSD_ADDR, parse_socket_addr);
+        let statsd_prefix = strings.get(ENV_STATSD_PREFIX);
+        let redacted_headers = parse(strings, ENV_REDACTED_HEADERS, parse_header_name_list);
+        let inbound_redacted_headers =
+            parse(strings, ENV_INBOUND_REDACTED_HEADERS, parse_header_name_list);
+        let outbound_redacted_headers =
+            parse(strings, ENV_OUTBOUND_REDACTED_HEADERS, parse_header_name_list);
+        let failure_status_codes = parse(strings, ENV_FAILURE_STATUS_CODES, parse_status_list);
+        let request_id_header = parse(strings, ENV_REQUEST_ID_HEADER, parse_header_name);
+        let trace_propagation = parse(strings, ENV_TRACE_PROPAGATION, parse_trace_propagation);
+        let decompress_responses = parse(strings, ENV_DECOMPRESS_RESPONSES, parse_decompression);
+        let decompress_max_bytes = parse(strings, ENV_DECOMPRESS_MAX_BYTES, parse_number);
+        let buffer_capacity = parse(strings, ENV_BUFFER_CAPACITY, parse_number);
+        let endpoint_concurrency_limit =
+            parse(strings, ENV_ENDPOINT_CONCURRENCY_LIMIT, parse_number);
+        let global_rate_limit = parse(strings, ENV_GLOBAL_RATE_LIMIT, parse_number);
+        let authority_rate_limit = parse(strings, ENV_AUTHORITY_RATE_LIMIT, parse_number);
+        let rate_limit_burst = parse(strings, ENV_RATE_LIMIT_BURST, parse_number);
+        let h1_max_idle_connections = parse(strings, ENV_H1_MAX_IDLE_CONNECTIONS, parse_number);
+        let h1_max_connections = parse(strings, ENV_H1_MAX_CONNECTIONS, parse_number);
+        let connect_concurrency = parse(strings, ENV_CONNECT_CONCURRENCY, parse_number);
+        let max_connections = parse(strings, ENV_MAX_CONNECTIONS, parse_number);
+        let max_connections_wait_timeout =
+            parse(strings, ENV_MAX_CONNECTIONS_WAIT_TIMEOUT, parse_number);
+        let h1_idle_timeout = parse(strings, ENV_H1_IDLE_TIMEOUT, parse_number);
+        let h1_expect_continue_timeout =
+            parse(strings, ENV_H1_EXPECT_CONTINUE_TIMEOUT, parse_number);
+        let connection_idle_timeout = parse(strings, ENV_CONNECTION_IDLE_TIMEOUT, parse_number);
+        let h2_initial_stream_window_size =
+            parse(strings, ENV_H2_INITIAL_STREAM_WINDOW_SIZE, parse_number);
+        let h2_initial_connection_window_size =
+            parse(strings, ENV_H2_INITIAL_CONNECTION_WINDOW_SIZE, parse_number);
+        let h2_max_concurrent_streams =
+            parse(strings, ENV_H2_MAX_CONCURRENT_STREAMS, parse_number);
+        let tcp_keepalive = parse(strings, ENV_TCP_KEEPALIVE, parse_number);
+        let socket_recv_buffer_bytes = parse(strings, ENV_SOCKET_RECV_BUFFER_BYTES, parse_number);
+        let socket_send_buffer_bytes = parse(strings, ENV_SOCKET_SEND_BUFFER_BYTES, parse_number);
+        let tcp_nodelay = parse(strings, ENV_TCP_NODELAY, parse_bool);
+        let tcp_quickack = parse(strings, ENV_TCP_QUICKACK, parse_bool);
+        let h2_keepalive_interval = parse(strings, ENV_H2_KEEPALIVE_INTERVAL, parse_number);
+        let h2_keepalive_timeout = parse(strings, ENV_H2_KEEPALIVE_TIMEOUT, parse_number);
+        let h2_keepalive_idle_validation =
+            parse(strings, ENV_H2_KEEPALIVE_IDLE_VALIDATION, parse_number);
+        let outbound_load_balancer =
+            parse(strings, ENV_OUTBOUND_LOAD_BALANCER, parse_load_balancer);
+        let inbound_protocol_policy =
+            parse(strings, ENV_INBOUND_PROTOCOL_POLICY, parse_protocol_policy);
+        let outbound_protocol_policy =
+            parse(strings, ENV_OUTBOUND_PROTOCOL_POLICY, parse_protocol_policy);
+        let outbound_missing_host_policy =
+            parse(strings, ENV_OUTBOUND_MISSING_HOST_POLICY, parse_missing_host_policy);
+        let outbound_orig_dst_fallback =
+            parse(strings, ENV_OUTBOUND_ORIG_DST_FALLBACK, parse_bool);
+        let outbound_h2c_upstreams =
+            parse(strings, ENV_OUTBOUND_H2C_UPSTREAMS, parse_authority_list);
+        let peak_ewma_default_rtt = parse(strings, ENV_PEAK_EWMA_DEFAULT_RTT, parse_number);
+        let peak_ewma_decay = parse(strings, ENV_PEAK_EWMA_DECAY, parse_number);
+        let outbound_slow_start_window =
+            parse(strings, ENV_OUTBOUND_SLOW_START_WINDOW, parse_number);
+        let inbound_mirror_authority =
+            parse(strings, ENV_INBOUND_MIRROR_AUTHORITY, parse_dns_authority);
+        let inbound_mirror_max_body_bytes =
+            parse(strings, ENV_INBOUND_MIRROR_MAX_BODY_BYTES, parse_number);
+        let route_tag_header = parse(strings, ENV_ROUTE_TAG_HEADER, parse_header_name);
+        let zone = strings.get(ENV_ZONE)
+            .map(|zone| zone.and_then(|z| if z.is_empty() { None } else { Some(z) }));
+        let zone_min_local_endpoints =
+            parse(strings, ENV_ZONE_MIN_LOCAL_ENDPOINTS, parse_number);
+        let outbound_affinity_header =
+            parse(strings, ENV_OUTBOUND_AFFINITY_HEADER, parse_header_name);
+        let outbound_affinity_cookie =
+            parse(strings, ENV_OUTBOUND_AFFINITY_COOKIE, parse_cookie_name);
+        let outbound_unhealthy_pins =
+            parse(strings, ENV_OUTBOUND_UNHEALTHY_PINS, parse_unhealthy_pins);
+        let inbound_mirror_percent = parse(strings, ENV_INBOUND_MIRROR_PERCENT, parse_number)
+            .and_then(|percent| match percent {
+                Some(percent) if percent > 100 => {
+                    error!("{} must not be greater than 100", ENV_INBOUND_MIRROR_PERCENT);
+                    Err(Error::InvalidEnvVar)
+                },
+                percent => Ok(percent.unwrap_or(DEFAULT_INBOUND_MIRROR_PERCENT)),
+            });
+        // A client certificate cannot be presented without its key.
+        let tls_client_identity = match (tls_client_cert?, tls_client_key?) {
+            (Some(cert), Some(key)) => Ok(Some((PathBuf::from(cert), PathBuf::from(key)))),
+            (None, None) => Ok(None),
+            _ => {
+                error!("{} and {} must be set together", ENV_TLS_CLIENT_CERT, ENV_TLS_CLIENT_KEY);
+                Err(Error::InvalidEnvVar)
+            },
+        };
         let pod_namespace = strings.get(ENV_POD_NAMESPACE).and_then(|maybe_value| {
             // There cannot be a default pod namespace, and the pod namespace is required.
             maybe_value.ok_or_else(|| {
@@ -233,11 +957,148 @@ impl<'a> TryFrom<&'a Strings> for Config {
             resolv_conf_path: resolv_conf_path?
                 .unwrap_or(DEFAULT_RESOLV_CONF.into())
                 .into(),
+            tls_trust_anchors: tls_trust_anchors?.map(PathBuf::from),
+            tls_client_identity: tls_client_identity?,
+            tls_client_identity_reload_interval: Duration::from_millis(
+                tls_client_identity_reload_interval?
+                    .unwrap_or(DEFAULT_TLS_CLIENT_IDENTITY_RELOAD_INTERVAL_MS)
+            ),
             control_host_and_port: control_host_and_port?,
 
             event_buffer_capacity: event_buffer_capacity?.unwrap_or(DEFAULT_EVENT_BUFFER_CAPACITY),
+            lifecycle_event_log_capacity: lifecycle_event_log_capacity?,
+            access_log_format: access_log_format?,
+            access_log_headers: access_log_headers?.unwrap_or_else(Vec::new),
+            access_log_capacity: access_log_capacity?.unwrap_or(DEFAULT_ACCESS_LOG_CAPACITY),
+            recent_requests_capacity: recent_requests_capacity?,
+            recent_requests_enabled: recent_requests_enabled?.unwrap_or(false),
+            metrics_latency_buckets: metrics_latency_buckets?,
+            metrics_max_authorities: metrics_max_authorities?
+                .unwrap_or(DEFAULT_METRICS_MAX_AUTHORITIES),
+            statsd_addr: statsd_addr?,
+            statsd_prefix: statsd_prefix?
+                .unwrap_or_else(|| DEFAULT_STATSD_PREFIX.to_owned()),
+            metrics_export_interval: Duration::from_millis(
+                metrics_export_interval?.unwrap_or(DEFAULT_METRICS_EXPORT_INTERVAL_MS)
+            ),
+            redacted_headers: redacted_headers?.unwrap_or_else(Vec::new),
+            inbound_redacted_headers: inbound_redacted_headers?,
+            outbound_redacted_headers: outbound_redacted_headers?,
+            failure_status_codes: failure_status_codes?,
+            request_id_header: request_id_header?,
+            trace_propagation: trace_propagation?,
+            decompress_responses: decompress_responses?,
+            decompress_max_bytes: decompress_max_bytes?,
+            buffer_capacity: buffer_capacity?.unwrap_or(DEFAULT_BUFFER_CAPACITY),
+            endpoint_concurrency_limit: endpoint_concurrency_limit?,
+            global_rate_limit: global_rate_limit?,
+            authority_rate_limit: authority_rate_limit?,
+            rate_limit_burst: rate_limit_burst?,
+            h1_max_idle_connections: h1_max_idle_connections?,
+            h1_max_connections: h1_max_connections?,
+            connect_concurrency: connect_concurrency?,
+            max_connections: max_connections?,
+            max_connections_wait_timeout: max_connections_wait_timeout?.map(Duration::from_millis),
+            h1_idle_timeout: h1_idle_timeout?.map(Duration::from_millis),
+            h1_expect_continue_timeout: h1_expect_continue_timeout?.map(Duration::from_millis),
+            connection_idle_timeout: connection_idle_timeout?.map(Duration::from_millis),
+            h2_initial_stream_window_size: h2_initial_stream_window_size?,
+            h2_initial_connection_window_size: h2_initial_connection_window_size?,
+            h2_max_concurrent_streams: h2_max_concurrent_streams?,
+            tcp_keepalive: tcp_keepalive?.map(Duration::from_millis),
+            socket_recv_buffer_bytes: socket_recv_buffer_bytes?,
+            socket_send_buffer_bytes: socket_send_buffer_bytes?,
+            tcp_nodelay: tcp_nodelay?.unwrap_or(true),
+            tcp_quickack: tcp_quickack?.unwrap_or(false),
+            h2_keepalive_interval: h2_keepalive_interval?.map(Duration::from_millis),
+            h2_keepalive_timeout: Duration::from_millis(
+                h2_keepalive_timeout?.unwrap_or(DEFAULT_H2_KEEPALIVE_TIMEOUT_MS)
+            ),
+            h2_keepalive_idle_validation: h2_keepalive_idle_validation?
+                .map(Duration::from_millis),
+            outbound_load_balancer: outbound_load_balancer?
+                .unwrap_or(LoadBalancer::WeightedRandom),
+            inbound_protocol_policy: inbound_protocol_policy?
+                .unwrap_or(ProtocolPolicy::Detect),
+            outbound_protocol_policy: outbound_protocol_policy?
+                .unwrap_or(ProtocolPolicy::Detect),
+            outbound_missing_host_policy: outbound_missing_host_policy?
+                .unwrap_or(MissingHostPolicy::OriginalDst),
+            outbound_orig_dst_fallback: outbound_orig_dst_fallback?.unwrap_or(false),
+            outbound_h2c_upstreams: outbound_h2c_upstreams?.unwrap_or_default(),
+            peak_ewma_default_rtt: Duration::from_millis(
+                peak_ewma_default_rtt?.unwrap_or(DEFAULT_PEAK_EWMA_DEFAULT_RTT_MS)
+            ),
+            peak_ewma_decay: Duration::from_millis(
+                peak_ewma_decay?.unwrap_or(DEFAULT_PEAK_EWMA_DECAY_MS)
+            ),
+            outbound_slow_start_window: outbound_slow_start_window?.map(Duration::from_millis),
+            inbound_mirror_authority: inbound_mirror_authority?,
+            inbound_mirror_percent: inbound_mirror_percent?,
+            inbound_mirror_max_body_bytes: inbound_mirror_max_body_bytes?
+                .unwrap_or(DEFAULT_INBOUND_MIRROR_MAX_BODY_BYTES),
+            route_tag_header: route_tag_header?,
+            zone: zone?,
+            zone_min_local_endpoints: zone_min_local_endpoints?,
+            outbound_affinity_header: outbound_affinity_header?,
+            outbound_affinity_cookie: outbound_affinity_cookie?,
+            outbound_unhealthy_pins: outbound_unhealthy_pins?,
             bind_timeout:
                 Duration::from_millis(bind_timeout?.unwrap_or(DEFAULT_BIND_TIMEOUT_MS)),
+            request_timeout: request_timeout?.map(Duration::from_millis),
+            grpc_deadlines: grpc_deadlines?.unwrap_or(false),
+            request_budget_max_duration: request_budget_max_duration?.map(Duration::from_millis),
+            request_budget_max_attempts: request_budget_max_attempts?,
+            request_budget_max_buffered_bytes: request_budget_max_buffered_bytes?,
+            max_response_bytes: max_response_bytes?,
+            max_header_bytes: max_header_bytes?,
+            max_header_line_bytes: max_header_line_bytes?,
+            priority_header: priority_header?,
+            gzip_responses: gzip_responses?.unwrap_or(false),
+            response_headers: response_headers?.unwrap_or_default(),
+            outbound_response_cache_max_bytes: outbound_response_cache_max_bytes?,
+            outbound_connect_proxy_addr: outbound_connect_proxy_addr?,
+            outbound_connect_proxy_authorization: outbound_connect_proxy_authorization?,
+            inbound_max_request_bytes: inbound_max_request_bytes?,
+            hedge_delay: hedge_delay?.map(Duration::from_millis),
+            fail_fast_grace_period: fail_fast_grace_period?.map(Duration::from_millis),
+            max_retries: max_retries?,
+            retry_methods: retry_methods?.unwrap_or_else(Vec::new),
+            retry_budget_percent: retry_budget_percent?
+                .unwrap_or(DEFAULT_RETRY_BUDGET_PERCENT),
+            retry_budget_min_per_second: retry_budget_min_per_second?
+                .unwrap_or(DEFAULT_RETRY_BUDGET_MIN_PER_SECOND),
+            retry_buffer_max_bytes: retry_buffer_max_bytes?,
+            route_policies: route_policies?.unwrap_or_default(),
+            traffic_splits: traffic_splits?.unwrap_or_default(),
+            breaker_failure_threshold: breaker_failure_threshold?,
+            breaker_open_timeout: Duration::from_millis(
+                breaker_open_timeout?.unwrap_or(DEFAULT_BREAKER_OPEN_TIMEOUT_MS)
+            ),
+            health_check_path: health_check_path?,
+            health_check_interval: Duration::from_millis(
+                health_check_interval?.unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL_MS)
+            ),
+            health_check_status: health_check_status?.unwrap_or(http::StatusCode::OK),
+            health_check_healthy_threshold: health_check_healthy_threshold?
+                .unwrap_or(DEFAULT_HEALTH_CHECK_HEALTHY_THRESHOLD),
+            health_check_unhealthy_threshold: health_check_unhealthy_threshold?
+                .unwrap_or(DEFAULT_HEALTH_CHECK_UNHEALTHY_THRESHOLD),
+            outlier_consecutive_failures: outlier_consecutive_failures?,
+            outlier_base_ejection_time: Duration::from_millis(
+                outlier_base_ejection_time?.unwrap_or(DEFAULT_OUTLIER_BASE_EJECTION_TIME_MS)
+            ),
+            outlier_max_ejection_time: Duration::from_millis(
+                outlier_max_ejection_time?.unwrap_or(DEFAULT_OUTLIER_MAX_EJECTION_TIME_MS)
+            ),
+            endpoint_drain_grace_period: Duration::from_millis(
+                endpoint_drain_grace_period?.unwrap_or(DEFAULT_ENDPOINT_DRAIN_GRACE_PERIOD_MS)
+            ),
+            shutdown_grace_period: shutdown_grace_period?.map(Duration::from_millis),
+            reconnect_backoff_min: reconnect_backoff_min?.map(Duration::from_millis),
+            reconnect_backoff_max: Duration::from_millis(
+                reconnect_backoff_max?.unwrap_or(DEFAULT_RECONNECT_BACKOFF_MAX_MS)
+            ),
             pod_namespace: pod_namespace?,
         })
     }
@@ -308,6 +1169,25 @@ fn parse_number<T>(s: &str) -> Result<T, ParseError> where T: FromStr {
     s.parse().map_err(|_| ParseError::NotANumber)
 }
 
+fn parse_percent(s: &str) -> Result<u32, ParseError> {
+    match parse_number(s)? {
+        percent if percent > 100 => Err(ParseError::NotAPercentage),
+        percent => Ok(percent),
+    }
+}
+
+fn parse_bool(s: &str) -> Result<bool, ParseError> {
+    match s.trim() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(ParseError::NotABool),
+    }
+}
+
+fn parse_socket_addr(s: &str) -> Result<SocketAddr, ParseError> {
+    s.trim().parse().map_err(|_| ParseError::NotASocketAddr)
+}
+
 fn parse_url(s: &str) -> Result<HostAndPort, ParseError> {
     let url = s.parse::<http::Uri>().map_err(|_| ParseError::UrlError(UrlError::SyntaxError))?;
     if url.scheme_part().map(|s| s.as_str()) != Some("tcp") {
@@ -327,6 +1207,10 @@ fn parse_url(s: &str) -> Result<HostAndPort, ParseError> {
         .map_err(|e| ParseError::UrlError(UrlError::AuthorityError(e)))
 }
 
+fn parse_number_list<T>(s: &str) -> Result<Vec<T>, ParseError> where T: FromStr {
+    s.split(',').map(|n| parse_number(n.trim())).collect()
+}
+
 fn parse_port_set(s: &str) -> Result<IndexSet<u16>, ParseError> {
     let mut set = IndexSet::new();
     for num in s.split(',') {
@@ -335,6 +1219,255 @@ fn parse_port_set(s: &str) -> Result<IndexSet<u16>, ParseError> {
     Ok(set)
 }
 
+fn parse_method_list(s: &str) -> Result<Vec<http::Method>, ParseError> {
+    s.split(',')
+        .map(|m| http::Method::from_bytes(m.trim().as_bytes())
+            .map_err(|_| ParseError::NotAMethod))
+        .collect()
+}
+
+fn parse_header_name(s: &str) -> Result<http::header::HeaderName, ParseError> {
+    http::header::HeaderName::from_bytes(s.trim().as_bytes())
+        .map_err(|_| ParseError::NotAHeaderName)
+}
+
+fn parse_header_value(s: &str) -> Result<http::header::HeaderValue, ParseError> {
+    http::header::HeaderValue::from_str(s.trim()).map_err(|_| ParseError::NotAHeaderValue)
+}
+
+fn parse_header_name_list(s: &str) -> Result<Vec<http::header::HeaderName>, ParseError> {
+    s.split(',').map(parse_header_name).collect()
+}
+
+fn parse_cookie_name(s: &str) -> Result<String, ParseError> {
+    let name = s.trim();
+    let invalid = |c: char| c.is_whitespace() || c.is_control() || "=;,\"".contains(c);
+    if name.is_empty() || name.contains(invalid) {
+        return Err(ParseError::NotACookieName);
+    }
+    Ok(name.to_owned())
+}
+
+fn parse_status_code(s: &str) -> Result<http::StatusCode, ParseError> {
+    let code = parse_number(s.trim()).map_err(|_| ParseError::NotAStatusCode)?;
+    http::StatusCode::from_u16(code).map_err(|_| ParseError::NotAStatusCode)
+}
+
+fn parse_status_list(s: &str) -> Result<Vec<http::StatusCode>, ParseError> {
+    s.split(',').map(parse_status_code).collect()
+}
+
+fn parse_path(s: &str) -> Result<http::uri::PathAndQuery, ParseError> {
+    if !s.starts_with('/') {
+        return Err(ParseError::NotAPath);
+    }
+    s.parse().map_err(|_| ParseError::NotAPath)
+}
+
+fn parse_load_balancer(s: &str) -> Result<LoadBalancer, ParseError> {
+    match s.trim() {
+        "weighted-random" => Ok(LoadBalancer::WeightedRandom),
+        "peak-ewma" => Ok(LoadBalancer::PeakEwma),
+        _ => Err(ParseError::NotALoadBalancer),
+    }
+}
+
+fn parse_protocol_policy(s: &str) -> Result<ProtocolPolicy, ParseError> {
+    match s.trim() {
+        "detect" => Ok(ProtocolPolicy::Detect),
+        "http1" => Ok(ProtocolPolicy::ForceHttp1),
+        "http2" => Ok(ProtocolPolicy::ForceHttp2),
+        _ => Err(ParseError::NotAProtocolPolicy),
+    }
+}
+
+fn parse_missing_host_policy(s: &str) -> Result<MissingHostPolicy, ParseError> {
+    match s.trim() {
+        "reject" => Ok(MissingHostPolicy::Reject),
+        "original-dst" => Ok(MissingHostPolicy::OriginalDst),
+        _ => Err(ParseError::NotAMissingHostPolicy),
+    }
+}
+
+/// Parses a comma-separated list of authorities, such as `web.default:8080`
+/// or `10.1.2.3:80`. Port 80 is assumed if an authority has no port.
+fn parse_authority_list(s: &str) -> Result<Vec<HostAndPort>, ParseError> {
+    s.split(',')
+        .map(|a| {
+            let authority = a.trim().parse::<http::uri::Authority>()
+                .map_err(|_| ParseError::NotAnAuthority)?;
+            HostAndPort::normalize(&authority, Some(80))
+                .map_err(|_| ParseError::NotAnAuthority)
+        })
+        .collect()
+}
+
+/// Parses an authority with a DNS name, such as `web.default:8080`.
+fn parse_dns_authority(s: &str) -> Result<http::uri::Authority, ParseError> {
+    let authority = s.trim().parse::<http::uri::Authority>()
+        .map_err(|_| ParseError::NotAnAuthority)?;
+    match HostAndPort::normalize(&authority, Some(80)) {
+        Ok(HostAndPort { host: Host::DnsName(_), .. }) => Ok(authority),
+        _ => Err(ParseError::NotAnAuthority),
+    }
+}
+
+/// Parses a semicolon-separated list of routes, each an authority and a
+/// comma-separated list of settings, such as
+/// `web.default:8080=timeout:500,retries:2;api.example.com=concurrency:10`.
+///
+/// `timeout` is in milliseconds, `gzip` is `true` or `false`, and `host` is
+/// the authority to send requests with. Port 80 is assumed if an authority
+/// has no port.
+///
+/// Faults are injected into a route's requests by `abort-percent` with
+/// `abort-status`, which respond to that percentage of requests with that
+/// status, and by `delay-percent` with `delay-duration`, which delay that
+/// percentage of requests by that many milliseconds.
+///
+/// `priority` is the priority, from 0 to 255, of a route's requests that
+/// aren't classified by the priority header.
+///
+/// `path-protocol` selects the protocol with which requests are sent by
+/// their paths, as a path prefix and a protocol policy, such as
+/// `path-protocol:/package.Service/=http2`. It may be given more than once,
+/// and the first prefix to match a request's path applies.
+fn parse_route_policies(s: &str) -> Result<Vec<(DnsNameAndPort, RoutePolicy)>, ParseError> {
+    s.split(';')
+        .map(|route| {
+            let mut parts = route.splitn(2, '=');
+            let authority = parts.next()
+                .ok_or(ParseError::NotARoutePolicy)?
+                .trim()
+                .parse::<http::uri::Authority>()
+                .map_err(|_| ParseError::NotAnAuthority)?;
+            let authority = match HostAndPort::normalize(&authority, Some(80)) {
+                Ok(HostAndPort { host: Host::DnsName(host), port }) =>
+                    DnsNameAndPort { host, port },
+                _ => return Err(ParseError::NotAnAuthority),
+            };
+
+            let settings = parts.next().ok_or(ParseError::NotARoutePolicy)?;
+            let mut policy = RoutePolicy::default();
+            let (mut abort_percent, mut abort_status) = (None, None);
+            let (mut delay_percent, mut delay_duration) = (None, None);
+            for setting in settings.split(',') {
+                let mut kv = setting.splitn(2, ':');
+                let key = kv.next().map(str::trim);
+                let value = kv.next().map(str::trim).ok_or(ParseError::NotARoutePolicy)?;
+                match key {
+                    Some("timeout") => {
+                        policy.request_timeout = Some(Duration::from_millis(parse_number(value)?));
+                    },
+                    Some("retries") => policy.max_retries = Some(parse_number(value)?),
+                    Some("concurrency") => policy.concurrency_limit = Some(parse_number(value)?),
+                    Some("gzip") => policy.gzip_responses = Some(parse_bool(value)?),
+                    Some("priority") => policy.priority = Some(Priority::new(parse_number(value)?)),
+                    Some("host") => {
+                        let host = value.parse().map_err(|_| ParseError::NotAnAuthority)?;
+                        policy.rewrite_host = Some(host);
+                    },
+                    Some("abort-percent") => abort_percent = Some(parse_percent(value)?),
+                    Some("abort-status") => abort_status = Some(parse_status_code(value)?),
+                    Some("delay-percent") => delay_percent = Some(parse_percent(value)?),
+                    Some("delay-duration") => {
+                        delay_duration = Some(Duration::from_millis(parse_number(value)?));
+                    },
+                    Some("path-protocol") => {
+                        let mut rule = value.rsplitn(2, '=');
+                        let protocol = parse_protocol_policy(rule.next().unwrap_or(""))?;
+                        let prefix = rule.next().ok_or(ParseError::NotARoutePolicy)?;
+                        policy.path_protocols = Some(
+                            policy.path_protocols.take()
+                                .unwrap_or_default()
+                                .with_prefix(prefix.to_owned(), protocol)
+                        );
+                    },
+                    _ => return Err(ParseError::NotARoutePolicy),
+                }
+            }
+
+            // A fault's percentage must be set with its status or duration.
+            let faults = match (abort_percent, abort_status) {
+                (Some(percent), Some(status)) => {
+                    Some(FaultConfig::default().with_abort(percent, status))
+                },
+                (None, None) => None,
+                _ => return Err(ParseError::NotARoutePolicy),
+            };
+            policy.faults = match (delay_percent, delay_duration) {
+                (Some(percent), Some(duration)) => {
+                    Some(faults.unwrap_or_default().with_delay(percent, duration))
+                },
+                (None, None) => faults,
+                _ => return Err(ParseError::NotARoutePolicy),
+            };
+
+            Ok((authority, policy))
+        })
+        .collect()
+}
+
+/// Parses a semicolon-separated list of traffic splits, each an authority
+/// and the weighted backends to split its requests between, such as
+/// `books:8080=books-v1:8080=90,books-v2:8080=10`.
+fn parse_traffic_splits(s: &str) -> Result<Vec<(DnsNameAndPort, Split)>, ParseError> {
+    s.split(';')
+        .map(|s| {
+            let mut parts = s.splitn(2, '=');
+            let authority = parts.next()
+                .and_then(split::parse_authority)
+                .ok_or(ParseError::NotAnAuthority)?;
+            let split = parts.next()
+                .and_then(Split::parse)
+                .ok_or(ParseError::NotATrafficSplit)?;
+            Ok((authority, split))
+        })
+        .collect()
+}
+
+/// Parses headers to add to responses, one per line, each as `set name:
+/// value`, to override the upstream's values, or `add name: value`, to add
+/// the value alongside them.
+fn parse_response_headers(s: &str) -> Result<ResponseHeaders, ParseError> {
+    s.lines()
+        .filter(|line| !line.trim().is_empty())
+        .fold(Ok(ResponseHeaders::default()), |headers, line| {
+            let line = line.trim();
+            let policy = if line.starts_with("set ") {
+                response_headers::Policy::Override
+            } else if line.starts_with("add ") {
+                response_headers::Policy::Append
+            } else {
+                return Err(ParseError::NotAResponseHeader);
+            };
+            let mut parts = line[4..].splitn(2, ':');
+            let name = parse_header_name(parts.next().ok_or(ParseError::NotAResponseHeader)?)?;
+            let value = parse_header_value(parts.next().ok_or(ParseError::NotAResponseHeader)?)?;
+            Ok(headers?.with_header(name, value, policy))
+        })
+}
+
+fn parse_access_log_format(s: &str) -> Result<telemetry::access_log::Format, ParseError> {
+    telemetry::access_log::Format::parse(s.trim()).ok_or(ParseError::NotAnAccessLogFormat)
+}
+
+fn parse_trace_propagation(s: &str) -> Result<trace::Propagation, ParseError> {
+    match s.trim() {
+        "w3c" => Ok(trace::Propagation::W3c),
+        "b3" => Ok(trace::Propagation::B3),
+        _ => Err(ParseError::NotATracePropagation),
+    }
+}
+
+fn parse_decompression(s: &str) -> Result<telemetry::sensor::Decompress, ParseError> {
+    telemetry::sensor::Decompress::parse(s.trim()).ok_or(ParseError::NotADecompression)
+}
+
+fn parse_unhealthy_pins(s: &str) -> Result<UnhealthyPins, ParseError> {
+    UnhealthyPins::parse(s.trim()).ok_or(ParseError::NotAnUnhealthyPinPolicy)
+}
+
 fn parse<T, Parse>(strings: &Strings, name: &str, parse: Parse) -> Result<Option<T>, Error>
     where Parse: FnOnce(&str) -> Result<T, ParseError> {
     match strings.get(name)? {
@@ -1,39 +1,97 @@
 use bytes::Buf;
 use futures::*;
 use std;
+use std::fmt;
 use std::io;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio_core::net::{TcpListener, TcpStreamNew, TcpStream};
 use tokio_core::reactor::Handle;
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use config::Addr;
-use transport::GetOriginalDst;
+use transport::{GetOriginalDst, SocketBuffers, SocketLatency};
+#[cfg(test)]
+use transport::mock;
+use transport::tls;
+use transport::tunnel;
 
 pub type PlaintextSocket = TcpStream;
 
 pub struct BoundPort {
     inner: std::net::TcpListener,
     local_addr: SocketAddr,
+    latency: SocketLatency,
 }
 
 /// Initiates a client connection to the given address.
-pub fn connect(addr: &SocketAddr, executor: &Handle) -> Connecting {
-    Connecting(PlaintextSocket::connect(addr, executor))
+///
+/// If `tls` is provided, a TLS handshake is performed once the socket has
+/// connected. If `keepalive` is provided, TCP keepalive probes are sent once
+/// the connection has been idle for that long. The socket's buffers are
+/// sized by `buffers`, and its `latency` options are set, before any
+/// handshake.
+pub fn connect(
+    addr: &SocketAddr,
+    executor: &Handle,
+    tls: Option<tls::ConnectionConfig>,
+    keepalive: Option<Duration>,
+    buffers: SocketBuffers,
+    latency: SocketLatency,
+) -> Connecting {
+    let socket = PlaintextSocket::connect(addr, executor);
+    let options = (keepalive, buffers, latency);
+    Connecting(ConnectingState::Plaintext(socket, None, tls, options))
+}
+
+/// Initiates a client connection to `addr` through a tunnel that `proxy`
+/// opens with `CONNECT`.
+///
+/// The connection to the proxy is established as by `connect`, and `tls`,
+/// if provided, is negotiated with `addr` once the tunnel is open.
+pub fn connect_through(
+    proxy: &tunnel::ConnectProxy,
+    addr: &SocketAddr,
+    executor: &Handle,
+    tls: Option<tls::ConnectionConfig>,
+    keepalive: Option<Duration>,
+    buffers: SocketBuffers,
+    latency: SocketLatency,
+) -> Connecting {
+    let socket = PlaintextSocket::connect(proxy.addr(), executor);
+    let tunnel = Some((proxy.clone(), *addr));
+    let options = (keepalive, buffers, latency);
+    Connecting(ConnectingState::Plaintext(socket, tunnel, tls, options))
 }
 
 /// A socket that is in the process of connecting.
-pub struct Connecting(TcpStreamNew);
+pub struct Connecting(ConnectingState);
+
+enum ConnectingState {
+    Plaintext(
+        TcpStreamNew,
+        Option<(tunnel::ConnectProxy, SocketAddr)>,
+        Option<tls::ConnectionConfig>,
+        (Option<Duration>, SocketBuffers, SocketLatency),
+    ),
+    Tunnel(tunnel::Tunnel, Option<tls::ConnectionConfig>),
+    UpgradeToTls(tls::UpgradeClientToTls),
+    #[cfg(test)]
+    Mock(Option<mock::Io>),
+}
 
 /// Abstracts a plaintext socket vs. a TLS decorated one.
 ///
-/// A `Connection` has the `TCP_NODELAY` option set automatically. Also
-/// it strictly controls access to information about the underlying
-/// socket to reduce the chance of TLS protections being accidentally
-/// subverted.
-#[derive(Debug)]
+/// A `Connection` has the `TCP_NODELAY` option set, unless its
+/// `SocketLatency` disables it. Also it strictly controls access to
+/// information about the underlying socket to reduce the chance of TLS
+/// protections being accidentally subverted.
 pub enum Connection {
     Plain(PlaintextSocket),
+    TlsClient(Box<tls::ClientTlsStream>),
+    /// An in-memory connection, which has no socket.
+    #[cfg(test)]
+    Mock(mock::Io),
 }
 
 // ===== impl BoundPort =====
@@ -45,9 +103,18 @@ impl BoundPort {
         Ok(BoundPort {
             inner,
             local_addr,
+            latency: SocketLatency::default(),
         })
     }
 
+    /// Sets the `latency` options of accepted connections' sockets.
+    pub fn with_socket_latency(self, latency: SocketLatency) -> Self {
+        BoundPort {
+            latency,
+            ..self
+        }
+    }
+
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
     }
@@ -63,17 +130,18 @@ impl BoundPort {
         F: Fn(T, (Connection, SocketAddr)) -> Fut + 'static,
         T: 'static,
         Fut: IntoFuture<Item = T, Error = std::io::Error> + 'static {
+        let latency = self.latency;
         let fut = TcpListener::from_listener(self.inner, &self.local_addr, &executor)
             .expect("from_listener") // TODO: get rid of this `expect()`.
             .incoming()
             .fold(initial, move |b, (socket, remote_addr)| {
                 // TODO: On Linux and most other platforms it would be better
-                // to set the `TCP_NODELAY` option on the bound socket and
+                // to set the socket options on the bound socket and
                 // then have the listening sockets inherit it. However, that
                 // doesn't work on all platforms and also the underlying
                 // libraries don't have the necessary API for that, so just
                 // do it here.
-                set_nodelay_or_warn(&socket);
+                latency.apply(&socket);
                 f(b, (Connection::Plain(socket), remote_addr))
             });
 
@@ -83,14 +151,61 @@ impl BoundPort {
 
 // ===== impl Connecting =====
 
+impl Connecting {
+    /// Completes immediately with an in-memory connection.
+    #[cfg(test)]
+    pub fn mock(io: mock::Io) -> Self {
+        Connecting(ConnectingState::Mock(Some(io)))
+    }
+}
+
 impl Future for Connecting {
     type Item = Connection;
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let socket = try_ready!(self.0.poll());
-        set_nodelay_or_warn(&socket);
-        Ok(Async::Ready(Connection::Plain(socket)))
+        loop {
+            let next = match self.0 {
+                ConnectingState::Plaintext(
+                    ref mut connect,
+                    ref mut tunnel,
+                    ref mut tls,
+                    (keepalive, buffers, latency),
+                ) => {
+                    let socket = try_ready!(connect.poll());
+                    latency.apply(&socket);
+                    if let Some(keepalive) = keepalive {
+                        set_keepalive_or_warn(&socket, keepalive);
+                    }
+                    buffers.apply(&socket);
+                    if let Some((proxy, target)) = tunnel.take() {
+                        ConnectingState::Tunnel(proxy.tunnel(&target, socket), tls.take())
+                    } else {
+                        match tls.take() {
+                            Some(tls) => ConnectingState::UpgradeToTls(tls.upgrade(socket)),
+                            None => return Ok(Async::Ready(Connection::Plain(socket))),
+                        }
+                    }
+                },
+                ConnectingState::Tunnel(ref mut tunnel, ref mut tls) => {
+                    let socket = try_ready!(tunnel.poll());
+                    match tls.take() {
+                        Some(tls) => ConnectingState::UpgradeToTls(tls.upgrade(socket)),
+                        None => return Ok(Async::Ready(Connection::Plain(socket))),
+                    }
+                },
+                ConnectingState::UpgradeToTls(ref mut upgrade) => {
+                    let tls = try_ready!(upgrade.poll());
+                    return Ok(Async::Ready(Connection::TlsClient(Box::new(tls))));
+                },
+                #[cfg(test)]
+                ConnectingState::Mock(ref mut io) => {
+                    let io = io.take().expect("polled after completed");
+                    return Ok(Async::Ready(Connection::Mock(io)));
+                },
+            };
+            self.0 = next;
+        }
     }
 }
 
@@ -119,7 +234,24 @@ impl Connection {
     // as is done above.
     fn socket(&self) -> &PlaintextSocket {
         match self {
-            &Connection::Plain(ref socket) => socket
+            &Connection::Plain(ref socket) => socket,
+            &Connection::TlsClient(ref stream) => stream.get_ref().0,
+            #[cfg(test)]
+            &Connection::Mock(_) => panic!("mock connections have no socket"),
+        }
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Connection::Plain(ref socket) =>
+                f.debug_tuple("Plain").field(socket).finish(),
+            Connection::TlsClient(ref stream) =>
+                f.debug_tuple("TlsClient").field(stream.get_ref().0).finish(),
+            #[cfg(test)]
+            Connection::Mock(ref io) =>
+                f.debug_tuple("Mock").field(io).finish(),
         }
     }
 }
@@ -130,6 +262,9 @@ impl io::Read for Connection {
 
         match *self {
             Plain(ref mut t) => t.read(buf),
+            TlsClient(ref mut t) => t.read(buf),
+            #[cfg(test)]
+            Mock(ref mut t) => t.read(buf),
         }
     }
 }
@@ -140,6 +275,9 @@ impl AsyncRead for Connection {
 
         match *self {
             Plain(ref t) => t.prepare_uninitialized_buffer(buf),
+            TlsClient(ref t) => t.prepare_uninitialized_buffer(buf),
+            #[cfg(test)]
+            Mock(ref t) => t.prepare_uninitialized_buffer(buf),
         }
     }
 }
@@ -150,6 +288,9 @@ impl io::Write for Connection {
 
         match *self {
             Plain(ref mut t) => t.write(buf),
+            TlsClient(ref mut t) => t.write(buf),
+            #[cfg(test)]
+            Mock(ref mut t) => t.write(buf),
         }
     }
 
@@ -158,6 +299,9 @@ impl io::Write for Connection {
 
         match *self {
             Plain(ref mut t) => t.flush(),
+            TlsClient(ref mut t) => t.flush(),
+            #[cfg(test)]
+            Mock(ref mut t) => t.flush(),
         }
     }
 }
@@ -178,6 +322,14 @@ impl AsyncWrite for Connection {
                 // the pipe if one side closes.
                 TcpStream::shutdown(t, Shutdown::Write).map(Async::Ready)
             },
+            TlsClient(ref mut t) => {
+                // Send a TLS close_notify before shutting down the socket's
+                // write side, as above.
+                try_ready!(AsyncWrite::shutdown(&mut **t));
+                TcpStream::shutdown(t.get_ref().0, Shutdown::Write).map(Async::Ready)
+            },
+            #[cfg(test)]
+            Mock(ref mut t) => AsyncWrite::shutdown(t),
         }
     }
 
@@ -186,6 +338,9 @@ impl AsyncWrite for Connection {
 
         match *self {
             Plain(ref mut t) => t.write_buf(buf),
+            TlsClient(ref mut t) => t.write_buf(buf),
+            #[cfg(test)]
+            Mock(ref mut t) => t.write_buf(buf),
         }
     }
 }
@@ -217,10 +372,10 @@ impl<T: AsMut<[u8]>> Future for Peek<T> {
 
 // Misc.
 
-fn set_nodelay_or_warn(socket: &PlaintextSocket) {
-    if let Err(e) = socket.set_nodelay(true) {
+fn set_keepalive_or_warn(socket: &PlaintextSocket, keepalive: Duration) {
+    if let Err(e) = socket.set_keepalive(Some(keepalive)) {
         warn!(
-            "could not set TCP_NODELAY on {:?}/{:?}: {}",
+            "could not set SO_KEEPALIVE on {:?}/{:?}: {}",
             socket.local_addr(),
             socket.peer_addr(),
             e
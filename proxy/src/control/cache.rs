@@ -59,6 +59,10 @@ where
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     pub fn set_reset_on_next_modification(&mut self) {
         self.reset_on_next_modification = true;
     }
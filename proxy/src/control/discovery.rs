@@ -1,3 +1,4 @@
+use std::cmp;
 use std::collections::VecDeque;
 use std::collections::hash_map::{Entry, HashMap};
 use std::fmt;
@@ -10,14 +11,19 @@ use futures::{Async, Future, Poll, Stream};
 use futures::sync::mpsc;
 use futures_watch;
 use http;
+use rand::{self, Rng};
 use tokio_core::reactor::Handle;
 use tower::Service;
 use tower_h2::{HttpService, BoxBody, RecvBody};
 use tower_discover::{Change, Discover};
 use tower_grpc as grpc;
 
+use backoff;
+use balance::{Tags, Weight, Weighted, Zone, DEFAULT_WEIGHT};
 use dns::{self, IpAddrListFuture};
-use super::fully_qualified_authority::FullyQualifiedAuthority;
+use drain;
+use super::fully_qualified_authority;
+use super::state::WatchState;
 
 use conduit_proxy_controller_grpc::common::{Destination, TcpAddress};
 use conduit_proxy_controller_grpc::destination::{
@@ -48,9 +54,35 @@ pub struct Watch<B> {
     /// This is used to update the `Labeled` middleware on those services
     /// without requiring the service stack to be re-bound.
     metric_labels: HashMap<SocketAddr, futures_watch::Store<Option<DstLabels>>>,
+    /// Map associating addresses with the weights of their services, so that
+    /// they may be updated, or drained, without re-binding the service.
+    weights: HashMap<SocketAddr, Weight>,
+    /// Map associating addresses with the tags of their services.
+    tags: HashMap<SocketAddr, Tags>,
+    /// Map associating addresses with the zones of their services.
+    zones: HashMap<SocketAddr, Zone>,
+    /// Map associating addresses with the signals used to drain their
+    /// services when they are removed.
+    drains: HashMap<SocketAddr, drain::Signal>,
+    /// An address to connect to directly while discovery has no endpoints,
+    /// if one is configured.
+    fallback: Option<Fallback>,
+    /// Records the watch's endpoints for the admin API, if it is served.
+    state: Option<WatchState>,
     bind: B,
 }
 
+/// Tracks whether a `Watch`'s fallback address is in use.
+#[derive(Debug)]
+struct Fallback {
+    addr: SocketAddr,
+    /// Indicates that a service has been bound to `addr`.
+    bound: bool,
+    /// Indicates that an endpoint has been discovered while the fallback is
+    /// bound, so its service should be removed.
+    displaced: bool,
+}
+
 /// A background handle to eventually bind on the controller thread.
 #[derive(Debug)]
 pub struct Background {
@@ -64,7 +96,9 @@ pub struct Background {
 // TODO: debug impl
 pub struct DiscoveryWork<T: HttpService<ResponseBody = RecvBody>> {
     dns_resolver: dns::Resolver,
-    default_destination_namespace: String,
+    /// Normalizes authorities, relative to the default destination namespace,
+    /// for querying the Destination service.
+    authorities: fully_qualified_authority::Cache,
     destinations: HashMap<DnsNameAndPort, DestinationSet<T>>,
     /// A queue of authorities that need to be reconnected.
     reconnects: VecDeque<DnsNameAndPort>,
@@ -80,6 +114,12 @@ pub struct DiscoveryWork<T: HttpService<ResponseBody = RecvBody>> {
 pub struct Metadata {
     /// A set of Prometheus metric labels describing the destination.
     metric_labels: Option<DstLabels>,
+    /// The endpoint's relative share of traffic to the destination.
+    weight: u32,
+    /// The subsets of the destination that the endpoint belongs to.
+    tags: Vec<String>,
+    /// The zone that the endpoint runs in, if it's known.
+    zone: Option<String>,
 }
 
 struct DestinationSet<T: HttpService<ResponseBody = RecvBody>> {
@@ -132,6 +172,8 @@ enum Update {
     Insert(SocketAddr, Metadata),
     Remove(SocketAddr),
     ChangeMetadata(SocketAddr, Metadata),
+    /// Indicates that the destination is known to have no endpoints.
+    NoEndpoints,
 }
 
 /// Bind a `SocketAddr` with a protocol.
@@ -145,13 +187,36 @@ pub trait Bind {
     /// Errors produced by the discovered services
     type Error;
 
-    type BindError;
+    /// Errors produced when an address can't be bound.
+    type BindError: fmt::Debug;
 
     /// The discovered `Service` instance.
     type Service: Service<Request = Self::Request, Response = Self::Response, Error = Self::Error>;
 
     /// Bind a socket address with a service.
     fn bind(&self, addr: &SocketAddr) -> Result<Self::Service, Self::BindError>;
+
+    /// Bind a socket address that was not discovered, but is connected to
+    /// directly because discovery has no endpoints.
+    ///
+    /// By default, the address is bound as though it had been discovered.
+    fn bind_undiscovered(&self, addr: &SocketAddr) -> Result<Self::Service, Self::BindError> {
+        self.bind(addr)
+    }
+
+    /// Bind a socket address with a service that is notified by `drain` when
+    /// the address is removed from discovery, so that it may let its
+    /// in-flight requests complete before it is torn down.
+    ///
+    /// By default, `drain` is ignored.
+    fn bind_draining(
+        &self,
+        addr: &SocketAddr,
+        drain: drain::Watch,
+    ) -> Result<Self::Service, Self::BindError> {
+        drop(drain);
+        self.bind(addr)
+    }
 }
 
 /// Creates a "channel" of `Discovery` to `Background` handles.
@@ -183,22 +248,71 @@ impl Discovery {
             .unbounded_send((authority.clone(), tx))
             .expect("unbounded can't fail");
 
+        Watch::new(rx, bind)
+    }
+}
+
+// ==== impl Watch =====
+
+impl<B> Watch<B> {
+    fn new(rx: mpsc::UnboundedReceiver<Update>, bind: B) -> Self {
         Watch {
             rx,
             metric_labels: HashMap::new(),
+            weights: HashMap::new(),
+            tags: HashMap::new(),
+            zones: HashMap::new(),
+            drains: HashMap::new(),
+            fallback: None,
+            state: None,
             bind,
         }
     }
-}
 
-// ==== impl Watch =====
+    /// Connects directly to `addr` whenever discovery has no endpoints for
+    /// this destination.
+    ///
+    /// The fallback is removed once an endpoint is discovered.
+    pub fn with_fallback(self, addr: SocketAddr) -> Self {
+        let fallback = Fallback {
+            addr,
+            bound: false,
+            displaced: false,
+        };
+        Self {
+            fallback: Some(fallback),
+            ..self
+        }
+    }
+
+    /// Records the endpoints that are bound to `state`.
+    pub fn with_state(self, state: WatchState) -> Self {
+        Self {
+            state: Some(state),
+            ..self
+        }
+    }
 
-impl<B> Watch<B> {
     fn update_metadata(&mut self,
                        addr: SocketAddr,
                        meta: Metadata)
                        -> Result<(), ()>
     {
+        if let Some(weight) = self.weights.get(&addr) {
+            if meta.weight == 0 {
+                debug!("update_metadata: draining {:?}", addr);
+            }
+            weight.set(meta.weight);
+        }
+
+        if let Some(tags) = self.tags.get(&addr) {
+            tags.set(meta.tags);
+        }
+
+        if let Some(zone) = self.zones.get(&addr) {
+            zone.set(meta.zone);
+        }
+
         if let Some(store) = self.metric_labels.get_mut(&addr) {
             store.store(meta.metric_labels)
                 .map_err(|e| {
@@ -227,10 +341,22 @@ where
     type Request = B::Request;
     type Response = B::Response;
     type Error = B::Error;
-    type Service = Labeled<B::Service>;
+    type Service = Weighted<Labeled<B::Service>>;
     type DiscoverError = ();
 
     fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
+        if let Some(ref mut fallback) = self.fallback {
+            if fallback.displaced {
+                debug!("watch: removing fallback to {:?}", fallback.addr);
+                fallback.bound = false;
+                fallback.displaced = false;
+                if let Some(ref state) = self.state {
+                    state.remove(&fallback.addr);
+                }
+                return Ok(Async::Ready(Change::Remove(fallback.addr)));
+            }
+        }
+
         loop {
             let up = self.rx.poll();
             trace!("watch: {:?}", up);
@@ -245,10 +371,43 @@ where
                         futures_watch::Watch::new(meta.metric_labels);
                     self.metric_labels.insert(addr, labels_store);
 
-                    let service = self.bind.bind(&addr)
-                        .map(|svc| Labeled::new(svc, labels_watch))
-                        .map_err(|_| ())?;
+                    let weight = Weight::new(meta.weight);
+                    self.weights.insert(addr, weight.clone());
+
+                    let tags = Tags::new(meta.tags);
+                    self.tags.insert(addr, tags.clone());
+
+                    let zone = Zone::new(meta.zone);
+                    self.zones.insert(addr, zone.clone());
+
+                    let (drain_signal, drain_watch) = drain::channel();
+                    self.drains.insert(addr, drain_signal);
+
+                    let service = self.bind.bind_draining(&addr, drain_watch)
+                        .map(|svc| {
+                            Weighted::new(Labeled::new(svc, labels_watch), weight)
+                                .with_tags(tags)
+                                .with_zone(zone)
+                                .with_addr(addr)
+                        })
+                        .map_err(|e| error!("watch: failed to bind {:?}: {:?}", addr, e))?;
+
+                    // A discovered endpoint supersedes the fallback, which is
+                    // removed on the next poll, unless this endpoint has
+                    // replaced it.
+                    if let Some(ref mut fallback) = self.fallback {
+                        if fallback.bound {
+                            if fallback.addr == addr {
+                                fallback.bound = false;
+                            } else {
+                                fallback.displaced = true;
+                            }
+                        }
+                    }
 
+                    if let Some(ref state) = self.state {
+                        state.insert(addr);
+                    }
                     return Ok(Async::Ready(Change::Insert(addr, service)))
                 },
                 Update::ChangeMetadata(addr, meta) => {
@@ -261,8 +420,46 @@ where
                     // still exists --- it will simply read the final
                     // value from the watch.
                     self.metric_labels.remove(&addr);
+                    self.weights.remove(&addr);
+                    self.tags.remove(&addr);
+                    self.zones.remove(&addr);
+                    // The balancer stops routing requests to the service
+                    // once it is removed, but requests that are already in
+                    // flight may be allowed to complete.
+                    if let Some(signal) = self.drains.remove(&addr) {
+                        drop(signal.drain());
+                    }
+                    if let Some(ref state) = self.state {
+                        state.remove(&addr);
+                    }
                     return Ok(Async::Ready(Change::Remove(addr)));
                 },
+                Update::NoEndpoints => {
+                    // Any endpoints that were removed have already been
+                    // removed from the watch.
+                    if !self.weights.is_empty() {
+                        continue;
+                    }
+                    if let Some(ref mut fallback) = self.fallback {
+                        if !fallback.bound {
+                            debug!("watch: no endpoints; falling back to {:?}", fallback.addr);
+                            // The controller has no labels or weight to add
+                            // to an undiscovered address.
+                            let addr = fallback.addr;
+                            let service = self.bind.bind_undiscovered(&addr)
+                                .map(|svc| {
+                                    Weighted::new(Labeled::none(svc), Weight::default())
+                                        .with_addr(addr)
+                                })
+                                .map_err(|e| error!("watch: failed to bind {:?}: {:?}", addr, e))?;
+                            fallback.bound = true;
+                            if let Some(ref state) = self.state {
+                                state.insert(addr);
+                            }
+                            return Ok(Async::Ready(Change::Insert(fallback.addr, service)));
+                        }
+                    }
+                },
             }
         }
     }
@@ -278,7 +475,10 @@ impl Background {
     {
         DiscoveryWork {
             dns_resolver: dns::Resolver::new(self.dns_config, executor),
-            default_destination_namespace: self.default_destination_namespace,
+            authorities: fully_qualified_authority::Cache::new(
+                self.default_destination_namespace,
+                fully_qualified_authority::DEFAULT_CACHE_CAPACITY,
+            ),
             destinations: HashMap::new(),
             reconnects: VecDeque::new(),
             rpc_ready: false,
@@ -341,7 +541,7 @@ where
                             // we may already know of some addresses here, so push
                             // them onto the new watch first
                             match set.addrs {
-                                Exists::Yes(ref cache) => {
+                                Exists::Yes(ref cache) if !cache.is_empty() => {
                                     for (&addr, meta) in cache {
                                         let update = Update::Insert(
                                             addr,
@@ -351,14 +551,18 @@ where
                                             .expect("unbounded_send does not fail");
                                     }
                                 },
-                                Exists::No | Exists::Unknown => (),
+                                Exists::Yes(_) | Exists::No => {
+                                    tx.unbounded_send(Update::NoEndpoints)
+                                        .expect("unbounded_send does not fail");
+                                },
+                                Exists::Unknown => (),
                             }
                             set.txs.push(tx);
                         }
                         Entry::Vacant(vac) => {
                             let query =
                                 DestinationServiceQuery::connect_maybe(
-                                    &self.default_destination_namespace,
+                                    &mut self.authorities,
                                     client,
                                     vac.key(),
                                     "connect");
@@ -398,7 +602,7 @@ where
         while let Some(auth) = self.reconnects.pop_front() {
             if let Some(set) = self.destinations.get_mut(&auth) {
                 set.query = DestinationServiceQuery::connect_maybe(
-                    &self.default_destination_namespace,
+                    &mut self.authorities,
                     client,
                     &auth,
                     "reconnect");
@@ -462,14 +666,14 @@ impl<T: HttpService<RequestBody = BoxBody, ResponseBody = RecvBody>> Destination
     // given authority's host is of a form suitable for using to query the Destination service.
     // Otherwise, returns `None`.
     fn connect_maybe(
-        default_destination_namespace: &str,
+        authorities: &mut fully_qualified_authority::Cache,
         client: &mut T,
         auth: &DnsNameAndPort,
         connect_or_reconnect: &str)
         -> Option<Self>
     {
         trace!("DestinationServiceQuery {} {:?}", connect_or_reconnect, auth);
-        FullyQualifiedAuthority::normalize(auth, default_destination_namespace)
+        authorities.normalize(auth)
             .map(|auth| {
                 let req = Destination {
                     scheme: "k8s".into(),
@@ -562,31 +766,33 @@ impl<T> DestinationSet<T>
         trace!("checking DNS for {:?}", authority);
         while let Some(mut query) = self.dns_query.take() {
             trace!("polling DNS for {:?}", authority);
-            match query.poll() {
+            let ttl = match query.poll() {
                 Ok(Async::NotReady) => {
                     trace!("DNS query not ready {:?}", authority);
                     self.dns_query = Some(query);
                     return;
                 },
-                Ok(Async::Ready(dns::Response::Exists(ips))) => {
+                Ok(Async::Ready(dns::Lookup { response: dns::Response::Exists(ips), ttl })) => {
                     trace!("positive result of DNS query for {:?}: {:?}", authority, ips);
                     self.add(authority, ips.iter().map(|ip| {
                         (SocketAddr::from((*ip, authority.port)), Metadata::no_metadata())
                     }));
+                    ttl
                 },
-                Ok(Async::Ready(dns::Response::DoesNotExist)) => {
+                Ok(Async::Ready(dns::Lookup { response: dns::Response::DoesNotExist, ttl })) => {
                     trace!("negative result (NXDOMAIN) of DNS query for {:?}", authority);
                     self.no_endpoints(authority, false);
+                    ttl
                 },
                 Err(e) => {
                     trace!("DNS resolution failed for {}: {}", &authority.host, e);
                     // Do nothing so that the most recent non-error response is used until a
                     // non-error response is received.
+                    Duration::from_secs(dns::DEFAULT_TTL_SECS)
                 },
             };
-            // TODO: When we have a TTL to use, we should use that TTL instead of hard-coding this
-            // delay.
-            self.reset_dns_query(dns_resolver, Duration::from_secs(5), &authority)
+            let delay = dns_refresh_delay(ttl, &mut rand::thread_rng());
+            self.reset_dns_query(dns_resolver, delay, &authority)
         }
     }
 }
@@ -645,6 +851,10 @@ impl <T: HttpService<ResponseBody = RecvBody>> DestinationSet<T> {
         } else {
             Exists::No
         };
+        // retain is used to drop any senders that are dead
+        self.txs.retain(|tx| {
+            tx.unbounded_send(Update::NoEndpoints).is_ok()
+        });
     }
 
     fn on_change(txs: &mut Vec<mpsc::UnboundedSender<Update>>,
@@ -672,6 +882,7 @@ impl<F, S, E> Bind for F
 where
     F: Fn(&SocketAddr) -> Result<S, E>,
     S: Service,
+    E: fmt::Debug,
 {
     type Request = S::Request;
     type Response = S::Response;
@@ -715,10 +926,29 @@ impl Metadata {
     fn no_metadata() -> Self {
         Metadata {
             metric_labels: None,
+            weight: DEFAULT_WEIGHT,
+            tags: Vec::new(),
+            zone: None,
         }
     }
 }
 
+/// The largest fraction of a DNS name's TTL that is added to the delay
+/// before it is resolved again.
+const DNS_REFRESH_JITTER: f64 = 0.2;
+
+/// Returns how long to wait before resolving a name again, after its last
+/// response was valid for `ttl`.
+///
+/// The name isn't resolved again until the response expires, but not so
+/// often that a zero TTL busy-loops. The delay is extended by a random
+/// portion of itself, so that proxies that resolved a name at the same time
+/// don't all resolve it again at once.
+fn dns_refresh_delay<R: Rng>(ttl: Duration, rng: &mut R) -> Duration {
+    let delay = cmp::max(ttl, Duration::from_secs(1));
+    delay + backoff::scale(delay, DNS_REFRESH_JITTER * rng.gen::<f64>())
+}
+
 /// Construct a new labeled `SocketAddr `from a protobuf `WeightedAddr`.
 fn pb_to_addr_meta(pb: WeightedAddr, set_labels: &Arc<HashMap<String, String>>)
             -> Option<(SocketAddr, Metadata)> {
@@ -729,6 +959,9 @@ fn pb_to_addr_meta(pb: WeightedAddr, set_labels: &Arc<HashMap<String, String>>)
             .chain(pb.metric_labels.iter());
     let meta = Metadata {
         metric_labels: DstLabels::new(label_iter),
+        weight: pb.weight,
+        tags: pb.tags,
+        zone: if pb.zone.is_empty() { None } else { Some(pb.zone) },
     };
     Some((addr, meta))
 }
@@ -784,3 +1017,279 @@ fn pb_to_sock_addr(pb: TcpAddress) -> Option<SocketAddr> {
         None => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::net::IpAddr;
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::future;
+    use futures::sync::oneshot;
+    use tokio_core::reactor::Core;
+
+    use graceful::Graceful;
+    use timeout::TimeoutError;
+    use super::*;
+
+    /// Responds to each request when its body's `oneshot` is completed.
+    struct Deferred;
+
+    impl Service for Deferred {
+        type Request = http::Request<oneshot::Receiver<&'static str>>;
+        type Response = &'static str;
+        type Error = oneshot::Canceled;
+        type Future = oneshot::Receiver<&'static str>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            req.into_parts().1
+        }
+    }
+
+    struct BindDeferred(Handle);
+
+    impl Bind for BindDeferred {
+        type Request = http::Request<oneshot::Receiver<&'static str>>;
+        type Response = &'static str;
+        type Error = TimeoutError<oneshot::Canceled>;
+        type BindError = ();
+        type Service = Graceful<Deferred>;
+
+        fn bind(&self, _: &SocketAddr) -> Result<Self::Service, Self::BindError> {
+            Ok(Graceful::new(Deferred, None, Duration::from_secs(10), &self.0))
+        }
+
+        fn bind_draining(
+            &self,
+            _: &SocketAddr,
+            drain: drain::Watch,
+        ) -> Result<Self::Service, Self::BindError> {
+            Ok(Graceful::new(Deferred, Some(drain), Duration::from_secs(10), &self.0))
+        }
+    }
+
+    #[test]
+    fn removed_endpoint_completes_in_flight_requests() {
+        let mut core = Core::new().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let mut watch = Watch::new(rx, BindDeferred(core.handle()));
+
+        let removed: SocketAddr = "10.1.1.1:80".parse().unwrap();
+        let remaining: SocketAddr = "10.1.1.2:80".parse().unwrap();
+        for &addr in &[removed, remaining] {
+            tx.unbounded_send(Update::Insert(addr, Metadata::no_metadata())).unwrap();
+        }
+
+        // Track the endpoints as a balancer would.
+        let mut endpoints = HashMap::new();
+        for _ in 0..2 {
+            match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+                Change::Insert(addr, svc) => {
+                    endpoints.insert(addr, svc);
+                }
+                Change::Remove(_) => panic!("nothing should be removed yet"),
+            }
+        }
+
+        let (rsp_tx, rsp_rx) = oneshot::channel();
+        let in_flight = endpoints.get_mut(&removed)
+            .expect("removed endpoint")
+            .call(http::Request::new(rsp_rx));
+
+        tx.unbounded_send(Update::Remove(removed)).unwrap();
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Remove(addr) => {
+                assert_eq!(addr, removed);
+                endpoints.remove(&addr);
+            }
+            Change::Insert(..) => panic!("endpoint should be removed"),
+        }
+
+        // New requests can only be routed to the remaining endpoint ...
+        assert_eq!(endpoints.keys().collect::<Vec<_>>(), vec![&remaining]);
+
+        // ... while the request in flight to the removed endpoint completes.
+        rsp_tx.send("done").unwrap();
+        assert_eq!(core.run(in_flight).ok(), Some("done"));
+    }
+
+    /// Binds `Deferred` services, recording each address that is bound.
+    #[derive(Default)]
+    struct BindRecorded(Rc<RefCell<Vec<SocketAddr>>>);
+
+    impl Bind for BindRecorded {
+        type Request = http::Request<oneshot::Receiver<&'static str>>;
+        type Response = &'static str;
+        type Error = oneshot::Canceled;
+        type BindError = ();
+        type Service = Deferred;
+
+        fn bind(&self, addr: &SocketAddr) -> Result<Self::Service, Self::BindError> {
+            self.0.borrow_mut().push(*addr);
+            Ok(Deferred)
+        }
+    }
+
+    #[test]
+    fn falls_back_to_orig_dst_while_there_are_no_endpoints() {
+        let mut core = Core::new().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let bind = BindRecorded::default();
+        let bound = bind.0.clone();
+        let orig_dst: SocketAddr = "10.1.1.1:80".parse().unwrap();
+        let mut watch = Watch::new(rx, bind).with_fallback(orig_dst);
+
+        tx.unbounded_send(Update::NoEndpoints).unwrap();
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Insert(addr, _) => assert_eq!(addr, orig_dst),
+            Change::Remove(_) => panic!("the fallback should be inserted"),
+        }
+        assert_eq!(*bound.borrow(), vec![orig_dst]);
+
+        // Once an endpoint is discovered, it replaces the fallback.
+        let endpoint: SocketAddr = "10.1.1.2:80".parse().unwrap();
+        tx.unbounded_send(Update::Insert(endpoint, Metadata::no_metadata())).unwrap();
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Insert(addr, _) => assert_eq!(addr, endpoint),
+            Change::Remove(_) => panic!("the endpoint should be inserted first"),
+        }
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Remove(addr) => assert_eq!(addr, orig_dst),
+            Change::Insert(..) => panic!("the fallback should be removed"),
+        }
+
+        // If the endpoint is removed, the fallback is used again.
+        tx.unbounded_send(Update::Remove(endpoint)).unwrap();
+        tx.unbounded_send(Update::NoEndpoints).unwrap();
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Remove(addr) => assert_eq!(addr, endpoint),
+            Change::Insert(..) => panic!("the endpoint should be removed"),
+        }
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Insert(addr, _) => assert_eq!(addr, orig_dst),
+            Change::Remove(_) => panic!("the fallback should be inserted"),
+        }
+        assert_eq!(*bound.borrow(), vec![orig_dst, endpoint, orig_dst]);
+    }
+
+    #[test]
+    fn does_not_fall_back_unless_configured() {
+        let mut core = Core::new().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let bind = BindRecorded::default();
+        let bound = bind.0.clone();
+        let mut watch = Watch::new(rx, bind);
+
+        tx.unbounded_send(Update::NoEndpoints).unwrap();
+        let polled = core.run(future::lazy(|| Ok::<_, ()>(watch.poll()))).unwrap();
+        assert!(polled.expect("poll").is_not_ready());
+        assert!(bound.borrow().is_empty());
+    }
+
+    /// A Destination service client that is never called, for destinations
+    /// that are only resolved through DNS.
+    struct NoDestinationService;
+
+    impl Service for NoDestinationService {
+        type Request = http::Request<BoxBody>;
+        type Response = http::Response<RecvBody>;
+        type Error = ();
+        type Future = future::Empty<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    /// Answers the next DNS query for `set` with `ips`.
+    fn answer_dns(set: &mut DestinationSet<NoDestinationService>, ips: &[&str]) {
+        let ips = ips.iter()
+            .map(|ip| ip.parse::<IpAddr>().unwrap())
+            .collect::<Vec<_>>();
+        let lookup = dns::Lookup {
+            response: dns::Response::Exists(dns::IpList::from(ips)),
+            ttl: Duration::from_secs(5),
+        };
+        set.dns_query = Some(Box::new(future::ok(lookup)));
+    }
+
+    #[test]
+    fn endpoints_migrate_when_dns_answer_changes() {
+        let mut core = Core::new().unwrap();
+        let config = dns::Config::from_file(Path::new("/nonexistent/resolv.conf"));
+        let resolver = dns::Resolver::new(config, &core.handle());
+        let authority = DnsNameAndPort {
+            host: dns::Name::normalize("lb.example.com").unwrap(),
+            port: 80,
+        };
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut set = DestinationSet::<NoDestinationService> {
+            addrs: Exists::Unknown,
+            query: None,
+            dns_query: None,
+            txs: vec![tx],
+        };
+        let bind = BindRecorded::default();
+        let bound = bind.0.clone();
+        let mut watch = Watch::new(rx, bind);
+
+        let old: SocketAddr = "10.1.1.1:80".parse().unwrap();
+        let new: SocketAddr = "10.1.1.2:80".parse().unwrap();
+
+        answer_dns(&mut set, &["10.1.1.1"]);
+        core.run(future::lazy(|| {
+            set.poll_dns(&resolver, &authority);
+            Ok::<_, ()>(())
+        })).unwrap();
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Insert(addr, _) => assert_eq!(addr, old),
+            Change::Remove(_) => panic!("the resolved address should be inserted"),
+        }
+        assert!(set.dns_query.is_some(), "the name should be resolved again");
+
+        // The name moves to a new address, so the new endpoint is added
+        // before the old one is removed and drained.
+        answer_dns(&mut set, &["10.1.1.2"]);
+        core.run(future::lazy(|| {
+            set.poll_dns(&resolver, &authority);
+            Ok::<_, ()>(())
+        })).unwrap();
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Insert(addr, _) => assert_eq!(addr, new),
+            Change::Remove(_) => panic!("the new address should be inserted first"),
+        }
+        match core.run(future::poll_fn(|| watch.poll())).unwrap() {
+            Change::Remove(addr) => assert_eq!(addr, old),
+            Change::Insert(..) => panic!("the old address should be removed"),
+        }
+        assert_eq!(*bound.borrow(), vec![old, new]);
+    }
+
+    #[test]
+    fn dns_refresh_delay_is_jittered() {
+        let mut rng = rand::thread_rng();
+        let ttl = Duration::from_secs(10);
+        let delays = (0..100)
+            .map(|_| dns_refresh_delay(ttl, &mut rng))
+            .collect::<Vec<_>>();
+        for delay in &delays {
+            assert!(*delay >= ttl, "refreshed before the TTL expired: {:?}", delay);
+            assert!(*delay <= Duration::from_secs(12), "jitter too large: {:?}", delay);
+        }
+        assert!(delays.iter().any(|d| *d != delays[0]), "delays should be jittered");
+
+        let delay = dns_refresh_delay(Duration::from_secs(0), &mut rng);
+        assert!(delay >= Duration::from_secs(1), "a zero TTL should not busy-loop");
+    }
+}
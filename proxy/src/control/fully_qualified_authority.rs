@@ -1,11 +1,38 @@
+use std::collections::HashMap;
+
 use bytes::{BytesMut};
 
 use transport::DnsNameAndPort;
 
+/// The number of authorities whose normalizations a `Cache` retains by
+/// default.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1_000;
+
 /// A normalized `Authority`.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct FullyQualifiedAuthority(String);
 
+/// Memoizes `FullyQualifiedAuthority::normalize` for a default namespace.
+///
+/// At most `capacity` authorities are retained, so that a stream of distinct
+/// authorities cannot grow the cache without bound; the least recently used
+/// authority is evicted to make room for another.
+#[derive(Debug)]
+pub struct Cache {
+    default_namespace: String,
+    capacity: usize,
+    entries: HashMap<DnsNameAndPort, CacheEntry>,
+    /// Incremented on every lookup to order entries by recency.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// `None` if the authority is not local to the cluster.
+    normalized: Option<FullyQualifiedAuthority>,
+    last_used: u64,
+}
+
 impl FullyQualifiedAuthority {
     /// Normalizes the name according to Kubernetes service naming conventions.
     /// Case folding is not done; that is done internally inside `Authority`.
@@ -123,26 +150,68 @@ impl FullyQualifiedAuthority {
     }
 }
 
+// ===== impl Cache =====
+
+impl Cache {
+    pub fn new(default_namespace: String, capacity: usize) -> Self {
+        assert!(capacity > 0, "cache capacity must be positive");
+        Cache {
+            default_namespace,
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            clock: 0,
+        }
+    }
+
+    /// Normalizes `authority` as `FullyQualifiedAuthority::normalize` would,
+    /// reusing the result of an earlier normalization of `authority` if it is
+    /// still cached.
+    pub fn normalize(&mut self, authority: &DnsNameAndPort) -> Option<&FullyQualifiedAuthority> {
+        self.clock += 1;
+        if !self.entries.contains_key(authority) {
+            if self.entries.len() >= self.capacity {
+                self.evict_least_recently_used();
+            }
+            let normalized = FullyQualifiedAuthority::normalize(authority, &self.default_namespace);
+            self.entries.insert(authority.clone(), CacheEntry { normalized, last_used: 0 });
+        }
+
+        let entry = self.entries.get_mut(authority).expect("authority was just cached");
+        entry.last_used = self.clock;
+        entry.normalized.as_ref()
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let lru = self.entries.iter()
+            .min_by_key(|&(_, entry)| entry.last_used)
+            .map(|(authority, _)| authority.clone());
+        if let Some(authority) = lru {
+            trace!("evicting {:?} from the authority cache", authority);
+            self.entries.remove(&authority);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use transport::{DnsNameAndPort, Host, HostAndPort};
     use http::uri::Authority;
     use std::str::FromStr;
 
-    #[test]
-    fn test_normalized_authority() {
-        fn dns_name_and_port_from_str(input: &str) -> DnsNameAndPort {
-            let authority = Authority::from_str(input).unwrap();
-            match HostAndPort::normalize(&authority, Some(80)) {
-                Ok(HostAndPort { host: Host::DnsName(host), port }) =>
-                    DnsNameAndPort { host, port },
-                Err(e) => {
-                    unreachable!("{:?} when parsing {:?}", e, input)
-                },
-                _ => unreachable!("Not a DNS name: {:?}", input),
-            }
+    fn dns_name_and_port_from_str(input: &str) -> DnsNameAndPort {
+        let authority = Authority::from_str(input).unwrap();
+        match HostAndPort::normalize(&authority, Some(80)) {
+            Ok(HostAndPort { host: Host::DnsName(host), port }) =>
+                DnsNameAndPort { host, port },
+            Err(e) => {
+                unreachable!("{:?} when parsing {:?}", e, input)
+            },
+            _ => unreachable!("Not a DNS name: {:?}", input),
         }
+    }
 
+    #[test]
+    fn test_normalized_authority() {
         fn local(input: &str, default_namespace: &str) -> String {
             let name = dns_name_and_port_from_str(input);
             let output = super::FullyQualifiedAuthority::normalize(&name, default_namespace);
@@ -215,4 +284,35 @@ mod tests {
         assert_eq!("name.namespace.svc.cluster.local",
                    local("name.namespace.SVC.cluster.local", "namespace"));
     }
+
+    #[test]
+    fn cached_normalization_matches_uncached() {
+        let mut cache = super::Cache::new("namespace".into(), 10);
+        for input in &["name", "name.namespace.svc:1234", "name.", "name.namespace.svc.cluster"] {
+            let name = dns_name_and_port_from_str(input);
+            let uncached = super::FullyQualifiedAuthority::normalize(&name, "namespace");
+            assert_eq!(cache.normalize(&name), uncached.as_ref(), "miss: {}", input);
+            assert_eq!(cache.normalize(&name), uncached.as_ref(), "hit: {}", input);
+        }
+        assert_eq!(cache.entries.len(), 4);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used_authority() {
+        let mut cache = super::Cache::new("namespace".into(), 2);
+        let (a, b, c) = (
+            dns_name_and_port_from_str("a"),
+            dns_name_and_port_from_str("b"),
+            dns_name_and_port_from_str("c"),
+        );
+        cache.normalize(&a);
+        cache.normalize(&b);
+        cache.normalize(&a);
+        cache.normalize(&c);
+
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.entries.contains_key(&a));
+        assert!(!cache.entries.contains_key(&b), "b was least recently used");
+        assert!(cache.entries.contains_key(&c));
+    }
 }
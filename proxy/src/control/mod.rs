@@ -25,11 +25,15 @@ pub mod discovery;
 mod fully_qualified_authority;
 mod observe;
 pub mod pb;
+pub mod state;
 
 use self::discovery::{Background as DiscoBg, Discovery, Watch};
 pub use self::discovery::Bind;
+pub use self::fully_qualified_authority::FullyQualifiedAuthority;
 pub use self::observe::Observe;
+pub use self::state::DiscoveryState;
 
+#[derive(Clone)]
 pub struct Control {
     disco: Discovery,
 }
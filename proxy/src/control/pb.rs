@@ -37,7 +37,7 @@ impl event::StreamResponseEnd {
         let end = tap_event::http::ResponseEnd {
             id: Some(tap_event::http::StreamId {
                 base: 0, // TODO FIXME
-                stream: ctx.id as u64,
+                stream: ctx.id,
             }),
             since_request_init: Some(pb_duration(&self.since_request_open)),
             since_response_init: Some(pb_duration(&self.since_response_open)),
@@ -63,7 +63,7 @@ impl event::StreamResponseFail {
         let end = tap_event::http::ResponseEnd {
             id: Some(tap_event::http::StreamId {
                 base: 0, // TODO FIXME
-                stream: ctx.id as u64,
+                stream: ctx.id,
             }),
             since_request_init: Some(pb_duration(&self.since_request_open)),
             since_response_init: Some(pb_duration(&self.since_response_open)),
@@ -89,7 +89,7 @@ impl event::StreamRequestFail {
         let end = tap_event::http::ResponseEnd {
             id: Some(tap_event::http::StreamId {
                 base: 0, // TODO FIXME
-                stream: ctx.id as u64,
+                stream: ctx.id,
             }),
             since_request_init: Some(pb_duration(&self.since_request_open)),
             since_response_init: None,
@@ -119,7 +119,7 @@ impl<'a> TryFrom<&'a Event> for common::TapEvent {
                     id: Some(tap_event::http::StreamId {
                         base: 0,
                         // TODO FIXME
-                        stream: ctx.id as u64,
+                        stream: ctx.id,
                     }),
                     method: Some((&ctx.method).into()),
                     scheme: ctx.uri.scheme_part().map(common::Scheme::from),
@@ -146,7 +146,7 @@ impl<'a> TryFrom<&'a Event> for common::TapEvent {
                     id: Some(tap_event::http::StreamId {
                         base: 0,
                         // TODO FIXME
-                        stream: ctx.request.id as u64,
+                        stream: ctx.request.id,
                     }),
                     since_request_init: Some(pb_duration(&rsp.since_request_open)),
                     http_status: u32::from(ctx.status.as_u16()),
@@ -0,0 +1,619 @@
+//! Exposes the endpoints known to discovery, for debugging.
+//!
+//! `GET /endpoints` on the metrics server describes, in JSON, the endpoints
+//! of each destination that is being watched, keyed by its authority:
+//!
+//! ```text
+//! {"books.default.svc.cluster.local:8080":[
+//!   {"addr":"10.1.1.1:8080","healthy":true,"ejected":false,"paused":false,
+//!    "in_flight":2,"success_rate":0.98}
+//! ]}
+//! ```
+//!
+//! An endpoint's success rate is the share of its most recent responses that
+//! weren't failures, or `null` if it hasn't responded yet.
+//!
+//! `POST /endpoints/pause?addr=10.1.1.1:8080` stops new requests from being
+//! dispatched to an endpoint, while those already dispatched to it complete,
+//! and `POST /endpoints/resume?addr=10.1.1.1:8080` dispatches requests to it
+//! again. A paused endpoint stays paused until it's resumed, even if
+//! discovery removes it and adds it again, or it's unhealthy in the meantime.
+//!
+//! `POST /splits?authority=books:8080&backends=books-v1:8080=90,books-v2:8080=10`
+//! splits the outbound requests for an authority between the backends, by
+//! weight, replacing its previous split, and `DELETE /splits?authority=books:8080`
+//! stops splitting them.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+
+use futures::future::{self, FutureResult};
+use hyper::{self, Method, StatusCode};
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::{
+    Service as HyperService,
+    Request as HyperRequest,
+    Response as HyperResponse
+};
+
+use pause::Pauses;
+use split::{self, Split, TrafficSplits};
+use telemetry::sensor::InFlight;
+use transport::DnsNameAndPort;
+
+/// The number of responses over which an endpoint's success rate is measured.
+const RECENT_RESPONSES: usize = 100;
+
+/// The endpoints of every destination that is being watched, along with the
+/// status of each endpoint.
+///
+/// Clones share the same state.
+#[derive(Clone, Debug)]
+pub struct DiscoveryState {
+    table: Arc<Mutex<Table>>,
+    in_flight: InFlight,
+    pauses: Pauses,
+    splits: TrafficSplits,
+}
+
+/// Records the endpoints of a single `Watch`, until it is dropped.
+#[derive(Debug)]
+pub struct WatchState {
+    id: usize,
+    table: Arc<Mutex<Table>>,
+}
+
+/// Records the status of an endpoint.
+///
+/// As with health checks, every service bound to an address shares its
+/// status, which is held only weakly by the `DiscoveryState`.
+#[derive(Clone, Debug)]
+pub struct EndpointState(Arc<Mutex<Status>>);
+
+/// The endpoints of every watched destination, as of a single instant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Each destination's endpoints, ordered by address, keyed by authority.
+    pub destinations: BTreeMap<String, Vec<Endpoint>>,
+}
+
+/// Describes a discovered endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    pub addr: SocketAddr,
+    /// False while the endpoint is failing its health checks.
+    pub healthy: bool,
+    /// True while the endpoint is ejected by outlier detection.
+    pub ejected: bool,
+    /// True while the endpoint is paused through the admin API.
+    pub paused: bool,
+    pub in_flight: usize,
+    /// The share of the endpoint's recent responses that succeeded, if it
+    /// has responded at all.
+    pub success_rate: Option<f64>,
+}
+
+/// Serves the admin API for discovery state.
+#[derive(Clone, Debug)]
+pub struct Serve(DiscoveryState);
+
+#[derive(Debug, Default)]
+struct Table {
+    next_id: usize,
+    /// The authority and endpoints of each watch, by ID.
+    watches: HashMap<usize, (String, Vec<SocketAddr>)>,
+    statuses: HashMap<SocketAddr, Weak<Mutex<Status>>>,
+}
+
+#[derive(Debug)]
+struct Status {
+    healthy: bool,
+    ejected: bool,
+    /// Whether each of the most recent responses succeeded, oldest first.
+    outcomes: VecDeque<bool>,
+}
+
+// ===== impl DiscoveryState =====
+
+impl DiscoveryState {
+    /// Reports endpoints' in-flight requests as counted by `in_flight`.
+    pub fn new(in_flight: &InFlight) -> Self {
+        DiscoveryState {
+            table: Arc::new(Mutex::new(Table::default())),
+            in_flight: in_flight.clone(),
+            pauses: Pauses::default(),
+            splits: TrafficSplits::default(),
+        }
+    }
+
+    /// Returns the endpoints that are paused through the admin API.
+    pub fn pauses(&self) -> &Pauses {
+        &self.pauses
+    }
+
+    /// Returns the traffic splits that are updated through the admin API.
+    pub fn splits(&self) -> &TrafficSplits {
+        &self.splits
+    }
+
+    /// Records the endpoints of a new watch on `authority`.
+    pub fn watch(&self, authority: &DnsNameAndPort) -> WatchState {
+        let mut table = self.table.lock().expect("discovery state lock poisoned");
+        let id = table.next_id;
+        table.next_id += 1;
+        let authority = format!("{}:{}", authority.host, authority.port);
+        table.watches.insert(id, (authority, Vec::new()));
+        WatchState {
+            id,
+            table: self.table.clone(),
+        }
+    }
+
+    /// Returns the status shared by the services bound to `addr`.
+    pub fn endpoint(&self, addr: &SocketAddr) -> EndpointState {
+        let mut table = self.table.lock().expect("discovery state lock poisoned");
+
+        // Drop the status of endpoints that are no longer bound.
+        table.statuses.retain(|_, status| status.upgrade().is_some());
+
+        if let Some(status) = table.statuses.get(addr).and_then(Weak::upgrade) {
+            return EndpointState(status);
+        }
+
+        let status = Arc::new(Mutex::new(Status {
+            healthy: true,
+            ejected: false,
+            outcomes: VecDeque::with_capacity(RECENT_RESPONSES),
+        }));
+        table.statuses.insert(*addr, Arc::downgrade(&status));
+        EndpointState(status)
+    }
+
+    /// Returns the endpoints of every watched destination.
+    ///
+    /// Watches update their endpoints while holding the same lock as the
+    /// snapshot, so that it never reflects part of an update.
+    pub fn snapshot(&self) -> Snapshot {
+        let table = self.table.lock().expect("discovery state lock poisoned");
+        let in_flight = self.in_flight.snapshot();
+
+        let mut destinations = BTreeMap::new();
+        for &(ref authority, ref addrs) in table.watches.values() {
+            let endpoints = destinations.entry(authority.clone()).or_insert_with(Vec::new);
+            for addr in addrs {
+                let status = table.statuses.get(addr).and_then(Weak::upgrade);
+                let endpoint = match status {
+                    Some(status) => {
+                        let status = status.lock().expect("endpoint state lock poisoned");
+                        Endpoint {
+                            addr: *addr,
+                            healthy: status.healthy,
+                            ejected: status.ejected,
+                            paused: false,
+                            in_flight: 0,
+                            success_rate: status.success_rate(),
+                        }
+                    }
+                    None => Endpoint {
+                        addr: *addr,
+                        healthy: true,
+                        ejected: false,
+                        paused: false,
+                        in_flight: 0,
+                        success_rate: None,
+                    },
+                };
+                endpoints.push(Endpoint {
+                    paused: self.pauses.is_paused(addr),
+                    in_flight: in_flight.get(addr).cloned().unwrap_or(0),
+                    ..endpoint
+                });
+            }
+        }
+
+        // A destination may be watched more than once, e.g. by both the
+        // outbound router and the mirror.
+        for endpoints in destinations.values_mut() {
+            endpoints.sort_by_key(|e| (e.addr.ip(), e.addr.port()));
+            endpoints.dedup_by_key(|e| e.addr);
+        }
+
+        Snapshot { destinations }
+    }
+
+    /// Serves the admin API for this state.
+    pub fn serve(&self) -> Serve {
+        Serve(self.clone())
+    }
+}
+
+// ===== impl WatchState =====
+
+impl WatchState {
+    pub fn insert(&self, addr: SocketAddr) {
+        let mut table = self.table.lock().expect("discovery state lock poisoned");
+        if let Some(&mut (_, ref mut addrs)) = table.watches.get_mut(&self.id) {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+
+    pub fn remove(&self, addr: &SocketAddr) {
+        let mut table = self.table.lock().expect("discovery state lock poisoned");
+        if let Some(&mut (_, ref mut addrs)) = table.watches.get_mut(&self.id) {
+            addrs.retain(|a| a != addr);
+        }
+    }
+}
+
+impl Drop for WatchState {
+    fn drop(&mut self) {
+        if let Ok(mut table) = self.table.lock() {
+            table.watches.remove(&self.id);
+        }
+    }
+}
+
+// ===== impl EndpointState =====
+
+impl EndpointState {
+    pub fn set_healthy(&self, healthy: bool) {
+        self.0.lock().expect("endpoint state lock poisoned").healthy = healthy;
+    }
+
+    pub fn set_ejected(&self, ejected: bool) {
+        self.0.lock().expect("endpoint state lock poisoned").ejected = ejected;
+    }
+
+    /// Records whether a response succeeded.
+    pub fn record(&self, success: bool) {
+        let mut status = self.0.lock().expect("endpoint state lock poisoned");
+        if status.outcomes.len() == RECENT_RESPONSES {
+            status.outcomes.pop_front();
+        }
+        status.outcomes.push_back(success);
+    }
+}
+
+// ===== impl Status =====
+
+impl Status {
+    fn success_rate(&self) -> Option<f64> {
+        if self.outcomes.is_empty() {
+            return None;
+        }
+        let successes = self.outcomes.iter().filter(|&&success| success).count();
+        Some(successes as f64 / self.outcomes.len() as f64)
+    }
+}
+
+// ===== impl Snapshot =====
+
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (i, (authority, endpoints)) in self.destinations.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            push_json_str(&mut out, authority);
+            out.push_str(":[");
+            for (j, endpoint) in endpoints.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                out.push_str("{\"addr\":");
+                push_json_str(&mut out, &endpoint.addr.to_string());
+                out.push_str(&format!(
+                    concat!(
+                        ",\"healthy\":{},\"ejected\":{},\"paused\":{},",
+                        "\"in_flight\":{},\"success_rate\":"
+                    ),
+                    endpoint.healthy,
+                    endpoint.ejected,
+                    endpoint.paused,
+                    endpoint.in_flight,
+                ));
+                match endpoint.success_rate {
+                    Some(rate) => out.push_str(&rate.to_string()),
+                    None => out.push_str("null"),
+                }
+                out.push('}');
+            }
+            out.push(']');
+        }
+        out.push('}');
+        out
+    }
+}
+
+/// Appends `s` to `out` as a JSON string.
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// ===== impl Serve =====
+
+impl Serve {
+    /// Returns true if `path` is served by the admin API for discovery state.
+    pub fn serves(path: &str) -> bool {
+        match path {
+            "/endpoints" | "/endpoints/pause" | "/endpoints/resume" | "/splits" => true,
+            _ => false,
+        }
+    }
+
+    /// Pauses or resumes the endpoint named by the request's `addr` query
+    /// parameter.
+    fn pause(&self, req: &HyperRequest, pause: bool) -> StatusCode {
+        let addr = query_param(req, "addr").and_then(|a| a.parse::<SocketAddr>().ok());
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return StatusCode::BadRequest,
+        };
+
+        if pause {
+            self.0.pauses.pause(addr);
+        } else {
+            self.0.pauses.resume(&addr);
+        }
+        StatusCode::NoContent
+    }
+
+    /// Splits the requests for the request's `authority` query parameter
+    /// between its `backends`, or, if `backends` is `None`, stops splitting
+    /// them.
+    fn split(&self, req: &HyperRequest, backends: Option<&str>) -> StatusCode {
+        let authority = match query_param(req, "authority").and_then(split::parse_authority) {
+            Some(authority) => authority,
+            None => return StatusCode::BadRequest,
+        };
+
+        match backends {
+            Some(backends) => match Split::parse(backends) {
+                Some(split) => self.0.splits.set(authority, split),
+                None => return StatusCode::BadRequest,
+            },
+            None => self.0.splits.remove(&authority),
+        }
+        StatusCode::NoContent
+    }
+}
+
+/// Returns the value of the request's query parameter `name`, if it has one.
+fn query_param<'a>(req: &'a HyperRequest, name: &str) -> Option<&'a str> {
+    req.query()?
+        .split('&')
+        .find(|p| p.starts_with(name) && p[name.len()..].starts_with('='))
+        .map(|p| &p[name.len() + 1..])
+}
+
+impl HyperService for Serve {
+    type Request = HyperRequest;
+    type Response = HyperResponse;
+    type Error = hyper::Error;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let status = match (req.method(), req.path()) {
+            (&Method::Get, "/endpoints") => None,
+            (&Method::Post, "/endpoints/pause") => Some(self.pause(&req, true)),
+            (&Method::Post, "/endpoints/resume") => Some(self.pause(&req, false)),
+            (&Method::Post, "/splits") => {
+                let status = match query_param(&req, "backends") {
+                    Some(backends) => self.split(&req, Some(backends)),
+                    None => StatusCode::BadRequest,
+                };
+                Some(status)
+            }
+            (&Method::Delete, "/splits") => Some(self.split(&req, None)),
+            (_, "/endpoints") |
+            (_, "/endpoints/pause") |
+            (_, "/endpoints/resume") |
+            (_, "/splits") => Some(StatusCode::MethodNotAllowed),
+            _ => Some(StatusCode::NotFound),
+        };
+        if let Some(status) = status {
+            return future::ok(HyperResponse::new().with_status(status));
+        }
+
+        let body = self.0.snapshot().to_json();
+        future::ok(HyperResponse::new()
+            .with_header(ContentLength(body.len() as u64))
+            .with_header(ContentType::json())
+            .with_body(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+
+    use super::*;
+    use dns;
+
+    fn authority(host: &str, port: u16) -> DnsNameAndPort {
+        DnsNameAndPort {
+            host: dns::Name::normalize(host).expect("name"),
+            port,
+        }
+    }
+
+    #[test]
+    fn snapshot_describes_each_watched_endpoint() {
+        let in_flight = InFlight::default();
+        let state = DiscoveryState::new(&in_flight);
+        let a = "10.1.1.1:8080".parse().unwrap();
+        let b = "10.1.1.2:8080".parse().unwrap();
+        let c = "10.1.2.1:9090".parse().unwrap();
+
+        let books = state.watch(&authority("books.test", 8080));
+        books.insert(b);
+        books.insert(a);
+        let authors = state.watch(&authority("authors.test", 9090));
+        authors.insert(c);
+
+        let status_a = state.endpoint(&a);
+        status_a.record(true);
+        status_a.record(true);
+        status_a.record(true);
+        status_a.record(false);
+        let _guard = in_flight.endpoint(&a).start();
+        let status_b = state.endpoint(&b);
+        status_b.set_healthy(false);
+        status_b.set_ejected(true);
+        state.pauses().pause(b);
+
+        assert_eq!(
+            state.snapshot().to_json(),
+            "{\"authors.test:9090\":[\
+             {\"addr\":\"10.1.2.1:9090\",\"healthy\":true,\"ejected\":false,\
+             \"paused\":false,\"in_flight\":0,\"success_rate\":null}],\
+             \"books.test:8080\":[\
+             {\"addr\":\"10.1.1.1:8080\",\"healthy\":true,\"ejected\":false,\
+             \"paused\":false,\"in_flight\":1,\"success_rate\":0.75},\
+             {\"addr\":\"10.1.1.2:8080\",\"healthy\":false,\"ejected\":true,\
+             \"paused\":true,\"in_flight\":0,\"success_rate\":null}]}"
+        );
+
+        // Removed endpoints, and dropped watches, are forgotten.
+        books.remove(&b);
+        drop(authors);
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.destinations.len(), 1);
+        let endpoints = &snapshot.destinations["books.test:8080"];
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].addr, a);
+    }
+
+    #[test]
+    fn success_rate_covers_recent_responses() {
+        let state = DiscoveryState::new(&InFlight::default());
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let status = state.endpoint(&addr);
+        for _ in 0..RECENT_RESPONSES {
+            status.record(false);
+        }
+        for _ in 0..(RECENT_RESPONSES / 2) {
+            status.record(true);
+        }
+
+        let watch = state.watch(&authority("books.test", 8080));
+        watch.insert(addr);
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.destinations["books.test:8080"][0].success_rate, Some(0.5));
+    }
+
+    #[test]
+    fn serves_snapshot_as_json() {
+        let state = DiscoveryState::new(&InFlight::default());
+        let watch = state.watch(&authority("books.test", 8080));
+        watch.insert("10.1.1.1:8080".parse().unwrap());
+        let serve = state.serve();
+
+        let rsp = serve.call(HyperRequest::new(Method::Get, "/endpoints".parse().unwrap()))
+            .wait()
+            .expect("response");
+        assert_eq!(rsp.status(), StatusCode::Ok);
+        assert_eq!(rsp.headers().get::<ContentType>(), Some(&ContentType::json()));
+        let body = rsp.body().concat2().wait().expect("body");
+        assert_eq!(body.as_ref(), state.snapshot().to_json().as_bytes());
+
+        let rsp = serve.call(HyperRequest::new(Method::Post, "/endpoints".parse().unwrap()))
+            .wait()
+            .expect("response");
+        assert_eq!(rsp.status(), StatusCode::MethodNotAllowed);
+    }
+
+    #[test]
+    fn serves_pause_and_resume() {
+        let state = DiscoveryState::new(&InFlight::default());
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let watch = state.watch(&authority("books.test", 8080));
+        watch.insert(addr);
+        let serve = state.serve();
+        let call = |method, uri: &str| {
+            serve.call(HyperRequest::new(method, uri.parse().unwrap()))
+                .wait()
+                .expect("response")
+                .status()
+        };
+
+        let status = call(Method::Post, "/endpoints/pause?addr=10.1.1.1:8080");
+        assert_eq!(status, StatusCode::NoContent);
+        assert!(state.pauses().is_paused(&addr));
+        assert!(state.snapshot().destinations["books.test:8080"][0].paused);
+
+        // Rediscovering the endpoint doesn't resume it.
+        watch.remove(&addr);
+        watch.insert(addr);
+        assert!(state.snapshot().destinations["books.test:8080"][0].paused);
+
+        let status = call(Method::Post, "/endpoints/resume?addr=10.1.1.1:8080");
+        assert_eq!(status, StatusCode::NoContent);
+        assert!(!state.pauses().is_paused(&addr));
+        assert!(!state.snapshot().destinations["books.test:8080"][0].paused);
+
+        assert_eq!(call(Method::Post, "/endpoints/pause"), StatusCode::BadRequest);
+        assert_eq!(call(Method::Post, "/endpoints/pause?addr=books"), StatusCode::BadRequest);
+        assert_eq!(call(Method::Get, "/endpoints/pause"), StatusCode::MethodNotAllowed);
+        assert!(Serve::serves("/endpoints/resume"));
+    }
+
+    #[test]
+    fn serves_traffic_splits() {
+        use rand;
+
+        let state = DiscoveryState::new(&InFlight::default());
+        let serve = state.serve();
+        let call = |method, uri: &str| {
+            serve.call(HyperRequest::new(method, uri.parse().unwrap()))
+                .wait()
+                .expect("response")
+                .status()
+        };
+        let books = authority("books.test", 8080);
+        let route = || state.splits().route(&books, &mut rand::thread_rng());
+
+        let status = call(
+            Method::Post,
+            "/splits?authority=books.test:8080&backends=books-v1.test:8080=0,books-v2.test:8080=1",
+        );
+        assert_eq!(status, StatusCode::NoContent);
+        assert_eq!(route(), Some(authority("books-v2.test", 8080)));
+
+        let status = call(
+            Method::Post,
+            "/splits?backends=books-v1.test:8080=1&authority=books.test:8080",
+        );
+        assert_eq!(status, StatusCode::NoContent);
+        assert_eq!(route(), Some(authority("books-v1.test", 8080)));
+
+        let status = call(Method::Delete, "/splits?authority=books.test:8080");
+        assert_eq!(status, StatusCode::NoContent);
+        assert_eq!(route(), None);
+
+        let invalid = [
+            "/splits?authority=books.test:8080",
+            "/splits?authority=books.test:8080&backends=books-v1.test:8080",
+            "/splits?authority=10.1.1.1:8080&backends=books-v1.test:8080=1",
+        ];
+        for uri in &invalid {
+            assert_eq!(call(Method::Post, uri), StatusCode::BadRequest, "{}", uri);
+        }
+        assert_eq!(call(Method::Delete, "/splits"), StatusCode::BadRequest);
+        assert_eq!(call(Method::Get, "/splits"), StatusCode::MethodNotAllowed);
+        assert!(Serve::serves("/splits"));
+    }
+}
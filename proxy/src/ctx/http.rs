@@ -1,17 +1,24 @@
 use http;
+use std::hash::{Hash, Hasher};
+use std::ops;
 use std::sync::Arc;
 
 use ctx;
 use telemetry::metrics;
+use telemetry::sensor::trace;
 
 /// Describes a stream's request headers.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Request {
     // A numeric ID useful for debugging & correlation.
-    pub id: usize,
+    pub id: u64,
 
     pub uri: http::Uri,
     pub method: http::Method,
+    pub version: http::Version,
+
+    /// The request's headers, with any sensitive values redacted.
+    pub headers: Headers,
 
     /// Identifies the proxy server that received the request.
     pub server: Arc<ctx::transport::Server>,
@@ -23,6 +30,10 @@ pub struct Request {
     /// be provided by the control plane for destinations lookups against its
     /// discovery API.
     pub dst_labels: Option<metrics::DstLabels>,
+
+    /// The proxy's span of the request's trace, if trace context is
+    /// propagated.
+    pub span: Option<trace::Span>,
 }
 
 /// Describes a stream's response headers.
@@ -31,8 +42,18 @@ pub struct Response {
     pub request: Arc<Request>,
 
     pub status: http::StatusCode,
+
+    /// The response's headers, with any sensitive values redacted.
+    pub headers: Headers,
 }
 
+/// Headers captured for telemetry.
+///
+/// These may differ from the headers that were actually sent, as the values
+/// of sensitive headers are redacted before they are captured.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers(http::HeaderMap);
+
 // TODO Describe a request's EOS.
 //pub struct EndRequest {
 //    pub response: Arc<Request>,
@@ -45,7 +66,8 @@ impl Request {
         request: &http::Request<B>,
         server: &Arc<ctx::transport::Server>,
         client: &Arc<ctx::transport::Client>,
-        id: usize,
+        id: u64,
+        headers: Headers,
     ) -> Arc<Self> {
         // Look up whether the request has been extended with optional
         // destination labels from the control plane's discovery API.
@@ -53,13 +75,17 @@ impl Request {
             .extensions()
             .get::<metrics::DstLabels>()
             .cloned();
+        let span = request.extensions().get::<trace::Span>().cloned();
         let r = Self {
             id,
             uri: request.uri().clone(),
             method: request.method().clone(),
+            version: request.version(),
+            headers,
             server: Arc::clone(server),
             client: Arc::clone(client),
             dst_labels,
+            span,
         };
 
         Arc::new(r)
@@ -67,12 +93,41 @@ impl Request {
 }
 
 impl Response {
-    pub fn new<B>(response: &http::Response<B>, request: &Arc<Request>) -> Arc<Self> {
+    pub fn new<B>(
+        response: &http::Response<B>,
+        request: &Arc<Request>,
+        headers: Headers,
+    ) -> Arc<Self> {
         let r = Self {
             status: response.status(),
             request: Arc::clone(request),
+            headers,
         };
 
         Arc::new(r)
     }
 }
+
+impl Headers {
+    pub fn new(headers: http::HeaderMap) -> Self {
+        Headers(headers)
+    }
+}
+
+impl ops::Deref for Headers {
+    type Target = http::HeaderMap;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// `http::HeaderMap` is not `Hash`, so its entries are hashed in order.
+impl Hash for Headers {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for (name, value) in self.0.iter() {
+            name.hash(state);
+            value.hash(state);
+        }
+    }
+}
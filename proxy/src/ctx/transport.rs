@@ -27,6 +27,9 @@ pub struct Client {
     pub proxy: Arc<ctx::Proxy>,
     pub remote: SocketAddr,
     pub protocol: Protocol,
+
+    /// Whether the connection is encrypted with TLS.
+    pub tls: bool,
 }
 
 impl Ctx {
@@ -86,11 +89,13 @@ impl Client {
         proxy: &Arc<ctx::Proxy>,
         remote: &SocketAddr,
         protocol: Protocol,
+        tls: bool,
     ) -> Arc<Client> {
         let c = Client {
             proxy: Arc::clone(proxy),
             remote: *remote,
             protocol: protocol,
+            tls,
         };
 
         Arc::new(c)
@@ -0,0 +1,402 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use h2;
+use http;
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use budget::RequestBudget;
+use grpc_timeout::{self, GrpcDeadline, GRPC_TIMEOUT};
+use telemetry::sensor::http::RequestOpen;
+use timeout::TimeoutError;
+
+/// Bounds the total time spent on each request, including the time spent
+/// streaming the response body.
+///
+/// The deadline is measured from the request's `RequestOpen` timestamp, if it
+/// has one, so that time spent buffered waiting for a connection counts
+/// against it. Requests without a `RequestOpen` timestamp are measured from
+/// when they are dispatched to this service.
+///
+/// A request with a `GrpcDeadline` must also complete by its deadline, if
+/// that's sooner. Its `grpc-timeout` is replaced with what remains of the
+/// deadline when it's dispatched, so that the upstream doesn't wait on it
+/// for longer than its client does.
+///
+/// If constructed without a timeout, only `GrpcDeadline`s, and the durations
+/// of `RequestBudget`s, are enforced.
+#[derive(Clone, Debug)]
+pub struct RequestTimeout<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    handle: Handle,
+}
+
+/// Wraps the inner `NewService`'s services in `RequestTimeout`s.
+pub struct Init<F> {
+    future: F,
+    timeout: Option<Duration>,
+    handle: Handle,
+}
+
+/// Fails if the response is not received before the request's deadline.
+pub struct ResponseFuture<F> {
+    inner: F,
+    deadline: Option<Deadline>,
+}
+
+/// Fails if the response body does not complete before the request's
+/// deadline.
+#[derive(Debug, Default)]
+pub struct DeadlineBody<B> {
+    inner: B,
+    deadline: Option<Deadline>,
+}
+
+struct Deadline {
+    timeout: Duration,
+    timer: ReactorTimeout,
+}
+
+// ===== impl RequestTimeout =====
+
+impl<S> RequestTimeout<S> {
+    pub fn new(inner: S, timeout: Option<Duration>, handle: &Handle) -> Self {
+        RequestTimeout {
+            inner,
+            timeout,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<N, A, B> NewService for RequestTimeout<N>
+where
+    N: NewService<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Body,
+{
+    type Request = N::Request;
+    type Response = http::Response<DeadlineBody<B>>;
+    type Error = TimeoutError<N::Error>;
+    type Service = RequestTimeout<N::Service>;
+    type InitError = N::InitError;
+    type Future = Init<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        Init {
+            future: self.inner.new_service(),
+            timeout: self.timeout,
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<S, A, B> Service for RequestTimeout<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<DeadlineBody<B>>;
+    type Error = TimeoutError<S::Error>;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(TimeoutError::Error)
+    }
+
+    fn call(&mut self, mut req: Self::Request) -> Self::Future {
+        let start = req.extensions()
+            .get::<RequestOpen>()
+            .map(|&RequestOpen(t)| t)
+            .unwrap_or_else(Instant::now);
+
+        let grpc = req.extensions().get::<GrpcDeadline>().map(|&GrpcDeadline(at)| at);
+        if let Some(at) = grpc {
+            let remaining = saturating_since(at, Instant::now());
+            req.headers_mut().insert(GRPC_TIMEOUT, grpc_timeout::encode(remaining));
+        }
+
+        // The request must complete by the soonest of its deadlines. Once its
+        // budget's duration is exhausted, its response is replaced above.
+        let budget = RequestBudget::of(&req).and_then(RequestBudget::deadline);
+        let deadlines = [self.timeout.map(|timeout| start + timeout), grpc, budget];
+        let deadline = deadlines.iter().filter_map(|&at| at).min().map(|at| {
+            let timer = ReactorTimeout::new_at(at, &self.handle).expect("reactor gone");
+            Deadline {
+                timeout: saturating_since(at, start),
+                timer,
+            }
+        });
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            deadline,
+        }
+    }
+}
+
+/// Returns the time from `earlier` until `later`, or zero if `later` isn't
+/// later.
+fn saturating_since(later: Instant, earlier: Instant) -> Duration {
+    if later > earlier {
+        later - earlier
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+// ===== impl Init =====
+
+impl<F> Future for Init<F>
+where
+    F: Future,
+{
+    type Item = RequestTimeout<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(RequestTimeout::new(inner, self.timeout, &self.handle)))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Body,
+{
+    type Item = http::Response<DeadlineBody<B>>;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // The response is preferred if it is ready at the same time as the
+        // deadline elapses.
+        match self.inner.poll() {
+            Ok(Async::Ready(rsp)) => {
+                // The remainder of the deadline applies to the body.
+                let deadline = self.deadline.take();
+                let rsp = rsp.map(|inner| DeadlineBody { inner, deadline });
+                return Ok(Async::Ready(rsp));
+            }
+            Ok(Async::NotReady) => {}
+            Err(e) => return Err(TimeoutError::Error(e)),
+        }
+
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.poll_elapsed() {
+                debug!("request timed out after {:?}", deadline.timeout);
+                return Err(TimeoutError::Timeout(deadline.timeout));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+// ===== impl DeadlineBody =====
+
+impl<B> DeadlineBody<B> {
+    fn poll_deadline(&mut self) -> Result<(), h2::Error> {
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.poll_elapsed() {
+                debug!("response body timed out after {:?}", deadline.timeout);
+                return Err(h2::Reason::CANCEL.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B> Body for DeadlineBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        match self.inner.poll_data()? {
+            Async::Ready(data) => Ok(Async::Ready(data)),
+            Async::NotReady => {
+                self.poll_deadline()?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match self.inner.poll_trailers()? {
+            Async::Ready(trls) => {
+                // The body is complete, so there's no need to keep the timer.
+                self.deadline = None;
+                Ok(Async::Ready(trls))
+            }
+            Async::NotReady => {
+                self.poll_deadline()?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+// ===== impl Deadline =====
+
+impl Deadline {
+    fn poll_elapsed(&mut self) -> bool {
+        match self.timer.poll().expect("timer failed") {
+            Async::Ready(()) => true,
+            Async::NotReady => false,
+        }
+    }
+}
+
+// We have to provide a custom implementation of Debug, because
+// tokio_core::reactor::Timeout is not Debug.
+impl fmt::Debug for Deadline {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Deadline")
+           .field("timeout", &self.timeout)
+           .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use futures::{future, Async, Future, Poll};
+    use futures::future::FutureResult;
+    use h2;
+    use http;
+    use tokio_core::reactor::Core;
+    use tower::Service;
+    use tower_h2::Body;
+
+    use telemetry::sensor::http::RequestOpen;
+    use test_support::{PendingBody, Unresponsive};
+    use timeout::TimeoutError;
+    use super::*;
+
+    /// An upstream that responds immediately with a body that never ends.
+    struct Trickle;
+
+    impl Service for Trickle {
+        type Request = http::Request<()>;
+        type Response = http::Response<PendingBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            future::ok(http::Response::new(PendingBody))
+        }
+    }
+
+    #[test]
+    fn slow_response_times_out() {
+        let mut core = Core::new().unwrap();
+        let timeout = Duration::from_millis(50);
+        let mut svc = RequestTimeout::new(Unresponsive::default(), Some(timeout), &core.handle());
+
+        let rsp = core.run(future::lazy(|| svc.call(http::Request::new(()))));
+        match rsp {
+            Err(TimeoutError::Timeout(t)) => assert_eq!(t, timeout),
+            Err(TimeoutError::Error(())) => panic!("upstream should not fail"),
+            Ok(_) => panic!("upstream should not respond"),
+        }
+    }
+
+    #[test]
+    fn time_before_dispatch_counts_against_deadline() {
+        let core = Core::new().unwrap();
+        let timeout = Duration::from_millis(50);
+        let mut svc = RequestTimeout::new(Unresponsive::default(), Some(timeout), &core.handle());
+
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(RequestOpen(Instant::now() - timeout * 2));
+
+        let mut rsp = svc.call(req);
+        let polled = future::lazy(|| Ok::<_, ()>(rsp.poll())).wait().unwrap();
+        match polled {
+            Err(TimeoutError::Timeout(_)) => {}
+            _ => panic!("an already-elapsed deadline should fail immediately"),
+        }
+    }
+
+    #[test]
+    fn slow_body_times_out() {
+        let mut core = Core::new().unwrap();
+        let timeout = Duration::from_millis(50);
+        let mut svc = RequestTimeout::new(Trickle, Some(timeout), &core.handle());
+
+        let rsp = core.run(future::lazy(|| svc.call(http::Request::new(()))))
+            .expect("response");
+        let (_, mut body) = rsp.into_parts();
+        let data = core.run(future::poll_fn(|| body.poll_data()));
+        let err = data.expect_err("body should time out");
+        assert_eq!(err.reason(), Some(h2::Reason::CANCEL));
+    }
+
+    #[test]
+    fn grpc_deadlines_are_enforced_and_propagated() {
+        let mut core = Core::new().unwrap();
+        let upstream = Unresponsive::default();
+        let timeout = Some(Duration::from_secs(10));
+        let mut svc = RequestTimeout::new(upstream.clone(), timeout, &core.handle());
+
+        let timeout = Duration::from_millis(100);
+        let open = Instant::now() - Duration::from_millis(40);
+        let mut req = http::Request::builder()
+            .header(GRPC_TIMEOUT, "100m")
+            .body(())
+            .unwrap();
+        req.extensions_mut().insert(RequestOpen(open));
+        req.extensions_mut().insert(GrpcDeadline(open + timeout));
+
+        // The gRPC deadline is sooner than the request timeout.
+        let rsp = core.run(future::lazy(|| svc.call(req)));
+        match rsp {
+            Err(TimeoutError::Timeout(t)) => assert_eq!(t, timeout),
+            _ => panic!("the gRPC deadline should be enforced"),
+        }
+        assert!(open.elapsed() >= timeout);
+
+        // The upstream is given what remained of the deadline when the
+        // request was dispatched.
+        let received = upstream.requests()[0].headers()[GRPC_TIMEOUT].clone();
+        let propagated = grpc_timeout::parse(received.as_bytes()).unwrap();
+        assert!(propagated > Duration::from_millis(0), "{:?}", propagated);
+        assert!(propagated <= Duration::from_millis(60), "{:?}", propagated);
+    }
+
+    #[test]
+    fn disabled_does_not_time_out() {
+        let core = Core::new().unwrap();
+        let mut svc = RequestTimeout::new(Unresponsive::default(), None, &core.handle());
+
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(RequestOpen(Instant::now() - Duration::from_secs(60)));
+
+        let mut rsp = svc.call(req);
+        let polled = future::lazy(|| Ok::<_, ()>(rsp.poll())).wait().unwrap();
+        assert!(polled.unwrap().is_not_ready());
+    }
+}
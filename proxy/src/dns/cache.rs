@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::Instant;
+
+use futures::{future, Future};
+
+use super::{IpAddrListFuture, Lookup, Name, Resolve};
+
+/// Caches the lookups of a `Resolve` until their TTLs expire.
+///
+/// Clones of a `Cache` share its entries, so that a name that is watched
+/// for several destinations (e.g. on different ports) is only looked up
+/// once per TTL.
+pub struct Cache<R> {
+    resolver: Rc<R>,
+    entries: Rc<RefCell<HashMap<Name, Entry>>>,
+}
+
+struct Entry {
+    lookup: Lookup,
+    expires: Instant,
+}
+
+// ===== impl Cache =====
+
+impl<R> Cache<R> {
+    pub fn new(resolver: R) -> Self {
+        Cache {
+            resolver: Rc::new(resolver),
+            entries: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the number of lookups cached, including those that have
+    /// expired but haven't yet been forgotten.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+}
+
+impl<R: Resolve> Resolve for Cache<R> {
+    /// Looks up `name`, unless an earlier lookup of `name` has not yet
+    /// expired.
+    ///
+    /// The TTL of a cached lookup is the time remaining until it expires.
+    fn lookup(&self, name: &Name) -> IpAddrListFuture {
+        let now = Instant::now();
+        if let Some(entry) = self.entries.borrow().get(name) {
+            if entry.expires > now {
+                trace!("using cached lookup of {}", name);
+                let lookup = Lookup {
+                    response: entry.lookup.response.clone(),
+                    ttl: entry.expires - now,
+                };
+                return Box::new(future::ok(lookup));
+            }
+        }
+
+        let entries = self.entries.clone();
+        let name = name.clone();
+        let f = self.resolver.lookup(&name).map(move |lookup| {
+            let now = Instant::now();
+            let mut entries = entries.borrow_mut();
+            // Forget about any names that are no longer being resolved.
+            entries.retain(|_, entry| entry.expires > now);
+            let entry = Entry {
+                lookup: lookup.clone(),
+                expires: now + lookup.ttl,
+            };
+            entries.insert(name, entry);
+            lookup
+        });
+        Box::new(f)
+    }
+}
+
+impl<R> Clone for Cache<R> {
+    fn clone(&self) -> Self {
+        Cache {
+            resolver: self.resolver.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<R> fmt::Debug for Cache<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("entries", &self.entries.borrow().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::net::IpAddr;
+    use std::thread;
+    use std::time::Duration;
+
+    use abstract_ns::{self, IpList};
+
+    use dns::Response;
+    use super::*;
+
+    /// Responds to every lookup with `response`, or fails if there is none,
+    /// counting the lookups.
+    struct Stub {
+        response: Option<Response>,
+        ttl: Duration,
+        lookups: Rc<Cell<usize>>,
+    }
+
+    impl Resolve for Stub {
+        fn lookup(&self, _: &Name) -> IpAddrListFuture {
+            self.lookups.set(self.lookups.get() + 1);
+            let lookup = match self.response {
+                Some(ref response) => Ok(Lookup {
+                    response: response.clone(),
+                    ttl: self.ttl,
+                }),
+                None => Err(abstract_ns::Error::NameNotFound),
+            };
+            Box::new(future::result(lookup))
+        }
+    }
+
+    fn stub(response: Option<Response>, ttl: Duration) -> (Cache<Stub>, Rc<Cell<usize>>) {
+        let lookups = Rc::new(Cell::new(0));
+        let stub = Stub {
+            response,
+            ttl,
+            lookups: lookups.clone(),
+        };
+        (Cache::new(stub), lookups)
+    }
+
+    fn ips(addrs: &[&str]) -> Response {
+        let addrs = addrs.iter()
+            .map(|a| a.parse::<IpAddr>().unwrap())
+            .collect::<Vec<_>>();
+        Response::Exists(IpList::from(addrs))
+    }
+
+    fn name() -> Name {
+        Name::normalize("foo.ns1.example.com").unwrap()
+    }
+
+    #[test]
+    fn lookups_are_cached_until_their_ttl_expires() {
+        let ttl = Duration::from_millis(50);
+        let (cache, lookups) = stub(Some(ips(&["10.1.1.1"])), ttl);
+
+        assert!(cache.lookup(&name()).wait().is_ok());
+        assert_eq!(lookups.get(), 1);
+
+        let cached = cache.clone().lookup(&name()).wait().unwrap();
+        assert_eq!(lookups.get(), 1, "an unexpired lookup should be cached");
+        assert!(cached.ttl <= ttl);
+
+        thread::sleep(ttl * 2);
+        assert!(cache.lookup(&name()).wait().is_ok());
+        assert_eq!(lookups.get(), 2, "an expired lookup should be re-resolved");
+    }
+
+    #[test]
+    fn all_addresses_are_returned() {
+        let (cache, _) = stub(Some(ips(&["10.1.1.1", "10.1.1.2"])), Duration::from_secs(10));
+        match cache.lookup(&name()).wait().unwrap().response {
+            Response::Exists(ips) => {
+                let ips = ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+                assert_eq!(ips, vec!["10.1.1.1", "10.1.1.2"]);
+            }
+            Response::DoesNotExist => panic!("the name should exist"),
+        }
+    }
+
+    #[test]
+    fn nonexistent_names_are_cached() {
+        let (cache, lookups) = stub(Some(Response::DoesNotExist), Duration::from_secs(10));
+        for _ in 0..2 {
+            match cache.lookup(&name()).wait().unwrap().response {
+                Response::DoesNotExist => {}
+                Response::Exists(ips) => panic!("the name should not exist: {:?}", ips),
+            }
+        }
+        assert_eq!(lookups.get(), 1);
+    }
+
+    #[test]
+    fn failed_lookups_are_not_cached() {
+        let (cache, lookups) = stub(None, Duration::from_secs(10));
+        assert!(cache.lookup(&name()).wait().is_err());
+        assert!(cache.lookup(&name()).wait().is_err());
+        assert_eq!(lookups.get(), 2);
+    }
+}
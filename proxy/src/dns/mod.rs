@@ -0,0 +1,302 @@
+use abstract_ns;
+use abstract_ns::HostResolve;
+pub use abstract_ns::IpList;
+use domain;
+use futures::prelude::*;
+use ns_dns_tokio;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Timeout};
+use telemetry::sensor::DnsLookups;
+use transport;
+
+mod cache;
+mod sensor;
+
+use self::cache::Cache;
+use self::sensor::{Sensor, Timer};
+
+/// How long the results of lookups are cached when their TTLs are unknown.
+///
+/// # TODO
+///
+/// `ns_dns_tokio` does not expose the TTLs of the records it resolves, so
+/// this is used for all lookups through the system resolver.
+pub const DEFAULT_TTL_SECS: u64 = 5;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    resolv_conf: domain::resolv::ResolvConf,
+    sensor: DnsLookups,
+}
+
+/// Resolves names through the system's resolver, caching the results of
+/// lookups for all of a name's addresses until their TTLs expire.
+///
+/// The latency and failures of the system resolver's lookups, and the number
+/// of lookups cached, are recorded in the `Config`'s `DnsLookups`.
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    resolver: ns_dns_tokio::DnsResolver,
+    lookups: Cache<Sensor<System>>,
+    sensor: DnsLookups,
+    executor: Handle,
+}
+
+/// Looks up all of the IP addresses for a name.
+///
+/// This is implemented for the system's resolver, and lets lookups be stubbed
+/// out in tests.
+pub trait Resolve {
+    fn lookup(&self, name: &Name) -> IpAddrListFuture;
+}
+
+/// Implements `Resolve` with the system's resolver.
+#[derive(Clone, Debug)]
+struct System(ns_dns_tokio::DnsResolver);
+
+pub enum IpAddrFuture {
+    DNS(ns_dns_tokio::HostFuture, Option<Timer>),
+    Fixed(IpAddr),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NoAddressesFound,
+    ResolutionFailed(<ns_dns_tokio::HostFuture as Future>::Error),
+}
+
+#[derive(Clone, Debug)]
+pub enum Response {
+    Exists(IpList),
+    /// The name does not exist (NXDOMAIN).
+    DoesNotExist,
+}
+
+/// The result of looking up all of a name's addresses.
+#[derive(Clone, Debug)]
+pub struct Lookup {
+    pub response: Response,
+    /// How long the response may be cached.
+    pub ttl: Duration,
+}
+
+// `Box<Future>` implements `Future` so it doesn't need to be implemented manually.
+pub type IpAddrListFuture = Box<Future<Item=Lookup, Error=abstract_ns::Error>>;
+
+/// A DNS name.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Name(abstract_ns::Name);
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+impl Name {
+    /// Parses the input string as a DNS name, normalizing it to lowercase.
+    pub fn normalize(s: &str) -> Result<Self, ()> {
+        // XXX: `abstract_ns::Name::from_str()` wrongly accepts IP addresses as
+        // domain names. Protect against this. TODO: Fix abstract_ns.
+        if let Ok(_) = IpAddr::from_str(s) {
+            return Err(());
+        }
+        // XXX: `abstract_ns::Name::from_str()` doesn't accept uppercase letters.
+        //  TODO: Avoid this extra allocation.
+        let s = s.to_ascii_lowercase();
+        abstract_ns::Name::from_str(&s)
+            .map(Name)
+            .map_err(|_| ())
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+impl Config {
+    /// Note that this ignores any errors reading or parsing the resolve.conf
+    /// file, just like the `domain` crate does.
+    pub fn from_file(resolve_conf_path: &Path) -> Self {
+        let mut resolv_conf = domain::resolv::ResolvConf::new();
+        let _ = resolv_conf.parse_file(resolve_conf_path);
+        resolv_conf.finalize();
+        Config {
+            resolv_conf,
+            sensor: DnsLookups::default(),
+        }
+    }
+
+    /// Records the lookups of resolvers built from this `Config` in `sensor`.
+    pub fn with_sensor(self, sensor: DnsLookups) -> Self {
+        Config {
+            sensor,
+            ..self
+        }
+    }
+}
+
+impl Resolver {
+    pub fn new(config: Config, executor: &Handle) -> Self {
+        let resolver = ns_dns_tokio::DnsResolver::new_from_resolver(
+            domain::resolv::Resolver::from_conf(executor, config.resolv_conf));
+        Resolver {
+            lookups: Cache::new(Sensor::new(System(resolver.clone()), config.sensor.clone())),
+            resolver,
+            sensor: config.sensor,
+            executor: executor.clone()
+        }
+    }
+
+    /// Resolves the IP addresses for `host`, in the order they were returned.
+    pub fn resolve_ips(&self, host: &transport::Host) -> IpAddrFuture {
+        match *host {
+            transport::Host::DnsName(ref name) => {
+                trace!("resolve_ips {}", name);
+                let timer = Timer::start(&self.sensor, name);
+                IpAddrFuture::DNS(self.resolver.resolve_host(&name.0), Some(timer))
+            }
+            transport::Host::Ip(addr) => IpAddrFuture::Fixed(addr),
+        }
+    }
+
+    /// Resolves all of the IP addresses for `host` after `delay`.
+    ///
+    /// If the addresses have been resolved recently enough that their TTL
+    /// has not expired, the cached result is used.
+    pub fn resolve_all_ips(&self, delay: Duration, host: &Name) -> IpAddrListFuture {
+        let name = host.clone();
+        trace!("resolve_all_ips {}", &name);
+        let lookups = self.lookups.clone();
+        let sensor = self.sensor.clone();
+        let f = Timeout::new(delay, &self.executor)
+            .expect("Timeout::new() won't fail")
+            .then(move |_| {
+                trace!("resolve_all_ips {} after delay", &name);
+                let name_clone = name.clone();
+                lookups.lookup(&name).then(move |result| {
+                    trace!("resolve_all_ips {}: completed with {:?}", name_clone, &result);
+                    sensor.set_cached(lookups.len());
+                    result
+                })
+            });
+        Box::new(f)
+    }
+}
+
+impl Resolve for System {
+    fn lookup(&self, name: &Name) -> IpAddrListFuture {
+        let f = self.0.resolve_host(&name.0)
+            .then(|result| {
+                let response = match result {
+                    Ok(ips) => Response::Exists(ips),
+                    Err(abstract_ns::Error::NameNotFound) => Response::DoesNotExist,
+                    Err(e) => return Err(e),
+                };
+                Ok(Lookup {
+                    response,
+                    ttl: Duration::from_secs(DEFAULT_TTL_SECS),
+                })
+            });
+        Box::new(f)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoAddressesFound => write!(f, "no addresses found"),
+            Error::ResolutionFailed(ref e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl Future for IpAddrFuture {
+    type Item = Vec<IpAddr>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            IpAddrFuture::DNS(ref mut inner, ref mut timer) => {
+                let result = match inner.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(ips)) => Ok(ips),
+                    Err(e) => Err(e),
+                };
+                if let Some(timer) = timer.take() {
+                    // A name that doesn't exist has been answered.
+                    let failed = match result {
+                        Err(abstract_ns::Error::NameNotFound) | Ok(_) => false,
+                        Err(_) => true,
+                    };
+                    timer.stop(failed);
+                }
+
+                let ips: Vec<IpAddr> = result
+                    .map_err(Error::ResolutionFailed)?
+                    .iter()
+                    .cloned()
+                    .collect();
+                if ips.is_empty() {
+                    return Err(Error::NoAddressesFound);
+                }
+                Ok(Async::Ready(ips))
+            },
+            IpAddrFuture::Fixed(addr) => Ok(Async::Ready(vec![addr])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Name;
+
+    #[test]
+    fn test_dns_name_parsing() {
+        struct Case {
+            input: &'static str,
+            output: &'static str,
+        }
+
+        static VALID: &[Case] = &[
+            // Almost all digits and dots, similar to IPv4 addresses.
+            Case { input: "1.2.3.x", output: "1.2.3.x", },
+            Case { input: "1.2.3.x", output: "1.2.3.x", },
+            Case { input: "1.2.3.4A", output: "1.2.3.4a", },
+            Case { input: "a.1.2.3", output: "a.1.2.3", },
+            Case { input: "1.2.x.3", output: "1.2.x.3", },
+            Case { input: "a.b.c.d", output: "a.b.c.d", },
+
+            // Uppercase letters in labels
+            Case { input: "A.b.c.d", output: "a.b.c.d", },
+            Case { input: "a.mIddle.c", output: "a.middle.c", },
+            Case { input: "a.b.c.D", output: "a.b.c.d", },
+        ];
+
+        for case in VALID {
+            let name = Name::normalize(case.input).expect("is a valid DNS name");
+            assert_eq!(name.as_ref(), case.output);
+        }
+
+        static INVALID: &[&str] = &[
+            "",
+            "1.2.3.4",
+            "::1",
+            "[::1]",
+            ":1234",
+            "1.2.3.4:11234",
+            "abc.com:1234",
+        ];
+
+        for case in INVALID {
+            assert!(Name::normalize(case).is_err(),
+                    "{} is invalid", case);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::Instant;
+
+use futures::Future;
+
+use telemetry::sensor::DnsLookups;
+use super::{IpAddrListFuture, Name, Resolve};
+
+/// Records the latency of a `Resolve`'s lookups, and whether they failed, in
+/// `DnsLookups`.
+///
+/// Lookups that find that a name does not exist are answered, so only those
+/// that fail outright are counted as failures.
+pub struct Sensor<R> {
+    inner: R,
+    lookups: DnsLookups,
+}
+
+/// Times a single lookup of a name.
+#[derive(Debug)]
+pub struct Timer {
+    lookups: DnsLookups,
+    name: Name,
+    started: Instant,
+}
+
+// ===== impl Sensor =====
+
+impl<R> Sensor<R> {
+    pub fn new(inner: R, lookups: DnsLookups) -> Self {
+        Sensor { inner, lookups }
+    }
+}
+
+impl<R: Resolve> Resolve for Sensor<R> {
+    fn lookup(&self, name: &Name) -> IpAddrListFuture {
+        let timer = Timer::start(&self.lookups, name);
+        let f = self.inner.lookup(name).then(move |result| {
+            timer.stop(result.is_err());
+            result
+        });
+        Box::new(f)
+    }
+}
+
+// ===== impl Timer =====
+
+impl Timer {
+    pub fn start(lookups: &DnsLookups, name: &Name) -> Self {
+        Timer {
+            lookups: lookups.clone(),
+            name: name.clone(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Records the lookup's latency, and whether it `failed`.
+    pub fn stop(self, failed: bool) {
+        self.lookups.record(self.name.as_ref(), self.started.elapsed(), failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use abstract_ns::{self, IpList};
+    use futures::future;
+    use tokio_core::reactor::{Core, Handle, Timeout};
+
+    use dns::{Lookup, Response};
+    use super::*;
+
+    /// Answers every lookup after `delay`, or fails if it has no answer.
+    struct Slow {
+        delay: Duration,
+        response: Option<Response>,
+        handle: Handle,
+    }
+
+    impl Resolve for Slow {
+        fn lookup(&self, _: &Name) -> IpAddrListFuture {
+            let lookup = match self.response {
+                Some(ref response) => Ok(Lookup {
+                    response: response.clone(),
+                    ttl: Duration::from_secs(10),
+                }),
+                None => Err(abstract_ns::Error::NameNotFound),
+            };
+            let f = Timeout::new(self.delay, &self.handle)
+                .expect("timeout")
+                .then(move |_| future::result(lookup));
+            Box::new(f)
+        }
+    }
+
+    fn count(lookups: &DnsLookups, name: &str) -> u64 {
+        let authorities = lookups.authorities();
+        let latency = &authorities.get(name).expect("lookups recorded").latency;
+        latency.into_iter().map(|(_, count)| count).sum()
+    }
+
+    #[test]
+    fn records_the_latency_and_failures_of_lookups() {
+        let mut core = Core::new().unwrap();
+        let lookups = DnsLookups::default();
+        let delay = Duration::from_millis(20);
+        let name = Name::normalize("slow.example.com").unwrap();
+
+        let ips = IpList::from(vec!["10.1.1.1".parse::<IpAddr>().unwrap()]);
+        let answers = Sensor::new(Slow {
+            delay,
+            response: Some(Response::Exists(ips)),
+            handle: core.handle(),
+        }, lookups.clone());
+        core.run(answers.lookup(&name)).unwrap();
+        core.run(answers.lookup(&name)).unwrap();
+
+        let authorities = lookups.authorities();
+        let slow = authorities.get("slow.example.com").expect("lookups recorded");
+        assert_eq!(slow.failures, 0);
+        assert_eq!(count(&lookups, "slow.example.com"), 2);
+        assert!(slow.latency.sum_in_ms() >= 40.0, "latency={}", slow.latency.sum_in_ms());
+
+        let fails = Sensor::new(Slow {
+            delay,
+            response: None,
+            handle: core.handle(),
+        }, lookups.clone());
+        let failing = Name::normalize("failing.example.com").unwrap();
+        assert!(core.run(fails.lookup(&failing)).is_err());
+
+        let authorities = lookups.authorities();
+        assert_eq!(authorities["failing.example.com"].failures, 1);
+        assert_eq!(count(&lookups, "failing.example.com"), 1);
+        assert_eq!(authorities["slow.example.com"].failures, 0);
+    }
+}
@@ -1,8 +1,10 @@
 use std::mem;
+use std::time::Duration;
 
 use futures::{Async, Future, Poll, Stream};
 use futures::future::Shared;
 use futures::sync::{mpsc, oneshot};
+use tokio_core::reactor::{Handle, Timeout};
 
 /// Creates a drain channel.
 ///
@@ -65,6 +67,22 @@ pub struct Drained {
     drained_rx: mpsc::Receiver<Never>,
 }
 
+/// A future that resolves when all `Watch`ers have been dropped, or when a
+/// grace period elapses, whichever happens first.
+pub struct DrainedWithin {
+    drained: Drained,
+    deadline: Option<Timeout>,
+}
+
+/// How a drain completed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Completion {
+    /// All watchers finished.
+    Drained,
+    /// The grace period elapsed before all watchers finished.
+    Expired,
+}
+
 // ===== impl Signal =====
 
 impl Signal {
@@ -136,6 +154,22 @@ where
 
 // ===== impl Drained =====
 
+impl Drained {
+    /// Stops waiting for watchers to finish once `grace_period` has elapsed.
+    ///
+    /// Any watchers that have not finished by then are expected to be
+    /// dropped by the caller.
+    pub fn within(self, grace_period: Duration, handle: &Handle) -> DrainedWithin {
+        let deadline = Timeout::new(grace_period, handle)
+            .map_err(|e| warn!("failed to create drain timeout: {}", e))
+            .ok();
+        DrainedWithin {
+            drained: self,
+            deadline,
+        }
+    }
+}
+
 impl Future for Drained {
     type Item = ();
     type Error = ();
@@ -148,9 +182,38 @@ impl Future for Drained {
     }
 }
 
+// ===== impl DrainedWithin =====
+
+impl Future for DrainedWithin {
+    type Item = Completion;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.drained.poll()?.is_ready() {
+            return Ok(Async::Ready(Completion::Drained));
+        }
+
+        let expired = match self.deadline.as_mut().map(Future::poll) {
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Ok(Async::NotReady)) | None => false,
+            Some(Err(e)) => {
+                warn!("drain timeout failed: {}", e);
+                self.deadline = None;
+                false
+            }
+        };
+        if expired {
+            Ok(Async::Ready(Completion::Expired))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use futures::{future, Async, Future, Poll};
+    use tokio_core::reactor::Core;
     use super::*;
 
     struct TestMe {
@@ -264,4 +327,38 @@ mod tests {
             Ok::<_, ()>(())
         }).wait().unwrap();
     }
+
+    #[test]
+    fn pending_watchers_finish_within_grace_period() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (tx, rx) = channel();
+
+        // A request that completes shortly after the drain begins.
+        let (respond, response) = oneshot::channel::<()>();
+        let completed = rx.watch(response.then(|_| Ok(true)), |_| {});
+        let respond = Timeout::new(Duration::from_millis(10), &handle).unwrap()
+            .then(move |_| respond.send(()));
+        handle.spawn(respond);
+
+        let drained = tx.drain().within(Duration::from_secs(10), &handle);
+        let (completed, completion) = core.run(completed.join(drained)).unwrap();
+        assert!(completed);
+        assert_eq!(completion, Completion::Drained);
+    }
+
+    #[test]
+    fn drain_expires_after_grace_period() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (tx, rx) = channel();
+
+        // A request that never completes.
+        let (_respond, response) = oneshot::channel::<()>();
+        let watch = rx.watch(response.map_err(|_| ()), |_| {});
+        handle.spawn(watch);
+
+        let drained = tx.drain().within(Duration::from_millis(10), &handle);
+        assert_eq!(core.run(drained), Ok(Completion::Expired));
+    }
 }
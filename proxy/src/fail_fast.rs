@@ -0,0 +1,371 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+use tower_discover::{Change, Discover};
+
+/// Fails requests fast while discovery has no endpoints for them.
+///
+/// A balancer with no endpoints is never ready, so requests would otherwise
+/// wait until they time out. Once `grace_period` has elapsed without any
+/// endpoints, the service becomes ready and requests fail immediately with
+/// `FailFastError::NoEndpoints`, until an endpoint is discovered. The grace
+/// period allows for discovery to catch up, e.g. just after the service is
+/// bound.
+///
+/// Requests still wait while endpoints exist but are not ready.
+///
+/// If constructed without a grace period, this is a no-op.
+pub struct FailFast<S> {
+    inner: S,
+    endpoints: Endpoints,
+    grace_period: Option<Duration>,
+    handle: Handle,
+    state: State,
+}
+
+/// Counts the endpoints a `Discover` has inserted and not since removed.
+pub struct CountEndpoints<D: Discover> {
+    inner: D,
+    keys: HashSet<D::Key>,
+    endpoints: Endpoints,
+}
+
+/// The number of endpoints currently discovered.
+#[derive(Clone, Debug, Default)]
+pub struct Endpoints(Rc<Cell<usize>>);
+
+/// An error produced by a `FailFast`.
+#[derive(Debug)]
+pub enum FailFastError<E> {
+    /// Indicates that no endpoints have been available for the grace period.
+    NoEndpoints,
+    /// Indicates that the underlying service failed.
+    Error(E),
+}
+
+pub struct ResponseFuture<F> {
+    /// If `None`, the request failed because there were no endpoints.
+    inner: Option<F>,
+}
+
+enum State {
+    /// Endpoints are available, or have not been missing.
+    Available,
+    /// Waiting for the grace period to elapse before failing requests.
+    Waiting(ReactorTimeout),
+    /// Requests fail until an endpoint is available.
+    Failing,
+}
+
+// ===== impl FailFast =====
+
+impl<S> FailFast<S> {
+    pub fn new(
+        inner: S,
+        endpoints: Endpoints,
+        grace_period: Option<Duration>,
+        handle: &Handle,
+    ) -> Self {
+        FailFast {
+            inner,
+            endpoints,
+            grace_period,
+            handle: handle.clone(),
+            state: State::Available,
+        }
+    }
+}
+
+impl<S: Service> Service for FailFast<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = FailFastError<S::Error>;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self.inner.poll_ready() {
+            Ok(Async::Ready(())) => {
+                self.state = State::Available;
+                return Ok(Async::Ready(()));
+            }
+            Ok(Async::NotReady) => {}
+            Err(e) => return Err(FailFastError::Error(e)),
+        }
+
+        let grace_period = match self.grace_period {
+            Some(grace_period) => grace_period,
+            None => return Ok(Async::NotReady),
+        };
+        if self.endpoints.count() > 0 {
+            self.state = State::Available;
+            return Ok(Async::NotReady);
+        }
+
+        loop {
+            self.state = match mem::replace(&mut self.state, State::Available) {
+                State::Available => match ReactorTimeout::new(grace_period, &self.handle) {
+                    Ok(timer) => State::Waiting(timer),
+                    Err(e) => {
+                        warn!("could not create fail-fast timer: {}", e);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                State::Waiting(mut timer) => match timer.poll() {
+                    Ok(Async::NotReady) => {
+                        self.state = State::Waiting(timer);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => {
+                        debug!("no endpoints available after {:?}; failing requests", grace_period);
+                        State::Failing
+                    }
+                    Err(e) => {
+                        warn!("fail-fast timer failed: {}", e);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                State::Failing => {
+                    self.state = State::Failing;
+                    return Ok(Async::Ready(()));
+                }
+            };
+        }
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let inner = match self.state {
+            // The inner service is not ready, so the request is not sent.
+            State::Failing => None,
+            _ => Some(self.inner.call(req)),
+        };
+        ResponseFuture { inner }
+    }
+}
+
+// ===== impl CountEndpoints =====
+
+impl<D> CountEndpoints<D>
+where
+    D: Discover,
+    D::Key: Hash + Eq,
+{
+    pub fn new(inner: D, endpoints: Endpoints) -> Self {
+        Self {
+            inner,
+            keys: HashSet::new(),
+            endpoints,
+        }
+    }
+}
+
+impl<D> Discover for CountEndpoints<D>
+where
+    D: Discover,
+    D::Key: Hash + Eq + Clone,
+{
+    type Key = D::Key;
+    type Request = D::Request;
+    type Response = D::Response;
+    type Error = D::Error;
+    type Service = D::Service;
+    type DiscoverError = D::DiscoverError;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
+        let change = try_ready!(self.inner.poll());
+        match change {
+            Change::Insert(ref key, _) => {
+                self.keys.insert(key.clone());
+            }
+            Change::Remove(ref key) => {
+                self.keys.remove(key);
+            }
+        }
+        self.endpoints.0.set(self.keys.len());
+        Ok(Async::Ready(change))
+    }
+}
+
+// ===== impl Endpoints =====
+
+impl Endpoints {
+    fn count(&self) -> usize {
+        self.0.get()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = FailFastError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.as_mut() {
+            Some(f) => f.poll().map_err(FailFastError::Error),
+            None => Err(FailFastError::NoEndpoints),
+        }
+    }
+}
+
+// ===== impl FailFastError =====
+
+impl<E> fmt::Display for FailFastError<E>
+where
+    E: fmt::Display
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FailFastError::NoEndpoints => f.pad("no endpoints available"),
+            FailFastError::Error(ref err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl<E> Error for FailFastError<E>
+where
+    E: Error
+{
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            FailFastError::Error(ref err) => Some(err),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            FailFastError::NoEndpoints => "no endpoints available",
+            FailFastError::Error(ref err) => err.description(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use futures::future;
+    use http;
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use test_support::Endpoint;
+
+    /// Discovers each of the given changes in turn.
+    struct Changes(VecDeque<Change<usize, Endpoint>>);
+
+    impl Discover for Changes {
+        type Key = usize;
+        type Request = http::Request<()>;
+        type Response = http::Response<()>;
+        type Error = ();
+        type Service = Endpoint;
+        type DiscoverError = ();
+
+        fn poll(&mut self) -> Poll<Change<usize, Endpoint>, ()> {
+            match self.0.pop_front() {
+                Some(change) => Ok(Async::Ready(change)),
+                None => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    fn fail_fast(core: &Core) -> (FailFast<Endpoint>, Endpoint, Endpoints) {
+        let endpoint = Endpoint::default();
+        endpoint.set_ready(false);
+        let endpoints = Endpoints::default();
+        let svc = FailFast::new(
+            endpoint.clone(),
+            endpoints.clone(),
+            Some(Duration::from_millis(10)),
+            &core.handle(),
+        );
+        (svc, endpoint, endpoints)
+    }
+
+    fn req() -> http::Request<()> {
+        http::Request::new(())
+    }
+
+    #[test]
+    fn fails_fast_once_no_endpoints_are_available_for_the_grace_period() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, endpoint, endpoints) = fail_fast(&core);
+
+        // Requests wait at first, while discovery catches up...
+        let ready = core.run(future::lazy(|| svc.poll_ready())).expect("poll_ready");
+        assert!(ready.is_not_ready());
+
+        // ...and fail once the grace period elapses.
+        core.run(future::poll_fn(|| svc.poll_ready())).expect("ready");
+        match core.run(svc.call(req())) {
+            Err(FailFastError::NoEndpoints) => {}
+            rsp => panic!("expected request to fail fast: {:?}", rsp),
+        }
+        assert!(svc.poll_ready().expect("poll_ready").is_ready());
+
+        // Once an endpoint is discovered and ready, requests are sent to it.
+        endpoints.0.set(1);
+        endpoint.set_ready(true);
+        assert!(svc.poll_ready().expect("poll_ready").is_ready());
+        core.run(svc.call(req())).expect("response");
+    }
+
+    #[test]
+    fn waits_while_endpoints_are_not_ready() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, _, endpoints) = fail_fast(&core);
+        endpoints.0.set(1);
+
+        let ready = future::poll_fn(|| svc.poll_ready());
+        let timeout = ReactorTimeout::new(Duration::from_millis(50), &core.handle()).unwrap();
+        match core.run(ready.select2(timeout)) {
+            Ok(future::Either::B(_)) => {}
+            _ => panic!("service should not become ready"),
+        }
+    }
+
+    #[test]
+    fn waits_without_a_grace_period() {
+        let mut core = Core::new().unwrap();
+        let endpoint = Endpoint::default();
+        endpoint.set_ready(false);
+        let mut svc = FailFast::new(endpoint, Endpoints::default(), None, &core.handle());
+
+        let ready = future::poll_fn(|| svc.poll_ready());
+        let timeout = ReactorTimeout::new(Duration::from_millis(50), &core.handle()).unwrap();
+        match core.run(ready.select2(timeout)) {
+            Ok(future::Either::B(_)) => {}
+            _ => panic!("service should not become ready"),
+        }
+    }
+
+    #[test]
+    fn counts_discovered_endpoints() {
+        let changes = vec![
+            Change::Insert(1, Endpoint::default()),
+            Change::Insert(2, Endpoint::default()),
+            // Replaces the first endpoint.
+            Change::Insert(1, Endpoint::default()),
+            Change::Remove(1),
+            Change::Remove(2),
+        ];
+        let endpoints = Endpoints::default();
+        let mut discover = CountEndpoints::new(Changes(changes.into()), endpoints.clone());
+
+        let mut counts = Vec::new();
+        while discover.poll().expect("discover").is_ready() {
+            counts.push(endpoints.count());
+        }
+        assert_eq!(counts, vec![1, 2, 2, 1, 0]);
+    }
+}
@@ -0,0 +1,368 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use http;
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::{NewService, Service};
+
+use mirror::Sample;
+
+/// Faults to inject into a percentage of the requests sent to a destination,
+/// so that its clients' resilience to failures and latency can be tested.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FaultConfig {
+    abort: Option<(u32, http::StatusCode)>,
+    delay: Option<(u32, Duration)>,
+}
+
+/// Marks a response that was injected as a fault, rather than received from
+/// an endpoint.
+///
+/// Telemetry classifies these responses as injected, and outlier detection
+/// ignores them, so that they aren't mistaken for failures of the endpoint.
+#[derive(Copy, Clone, Debug)]
+pub struct Injected;
+
+/// Delays some requests before they're sent, and responds to others without
+/// sending them at all, as configured by a `FaultConfig`.
+///
+/// A request that's both delayed and aborted is responded to once the delay
+/// has elapsed.
+///
+/// If constructed without a `FaultConfig`, this is a no-op.
+pub struct Fault<T> {
+    inner: Rc<RefCell<T>>,
+    faults: Option<Faults>,
+    handle: Handle,
+}
+
+/// Wraps the inner `NewService`'s services in `Fault`s.
+pub struct Init<F> {
+    future: F,
+    faults: Option<Faults>,
+    handle: Handle,
+}
+
+pub enum ResponseFuture<S: Service> {
+    /// Waiting for a delay to elapse before the request is sent or aborted.
+    Delayed {
+        timer: ReactorTimeout,
+        service: Rc<RefCell<S>>,
+        request: Option<S::Request>,
+        abort: Option<http::StatusCode>,
+    },
+    /// Waiting for the service to become ready for a delayed request.
+    Dispatching {
+        service: Rc<RefCell<S>>,
+        request: Option<S::Request>,
+    },
+    Pending(S::Future),
+    Aborted(Option<http::StatusCode>),
+}
+
+/// Chooses the requests into which each fault is injected, at evenly spaced
+/// intervals, so that exactly the configured percentage of requests have
+/// each fault injected.
+///
+/// Samples are shared by all of a `NewService`'s services, so that a
+/// reconnect doesn't reset them.
+#[derive(Clone, Debug)]
+struct Faults {
+    abort: Option<(Sample, http::StatusCode)>,
+    delay: Option<(Sample, Duration)>,
+}
+
+/// Returns whether `rsp` was injected as a fault.
+pub fn is_injected<B>(rsp: &http::Response<B>) -> bool {
+    rsp.extensions().get::<Injected>().is_some()
+}
+
+// ===== impl FaultConfig =====
+
+impl FaultConfig {
+    /// Responds to `percent` of requests with `status`, without sending them
+    /// to an endpoint.
+    pub fn with_abort(self, percent: u32, status: http::StatusCode) -> Self {
+        Self {
+            abort: Some((percent, status)),
+            ..self
+        }
+    }
+
+    /// Waits for `delay` before sending `percent` of requests.
+    pub fn with_delay(self, percent: u32, delay: Duration) -> Self {
+        Self {
+            delay: Some((percent, delay)),
+            ..self
+        }
+    }
+}
+
+// ===== impl Fault =====
+
+impl<T> Fault<T> {
+    pub fn new(inner: T, config: Option<FaultConfig>, handle: &Handle) -> Self {
+        let faults = config.map(|config| Faults {
+            abort: config.abort.map(|(percent, status)| (Sample::new(percent), status)),
+            delay: config.delay.map(|(percent, delay)| (Sample::new(percent), delay)),
+        });
+        Fault {
+            inner: Rc::new(RefCell::new(inner)),
+            faults,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<N, A, B> NewService for Fault<N>
+where
+    N: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+    B: Default,
+{
+    type Request = N::Request;
+    type Response = N::Response;
+    type Error = N::Error;
+    type Service = Fault<N::Service>;
+    type InitError = N::InitError;
+    type Future = Init<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        Init {
+            future: self.inner.borrow().new_service(),
+            faults: self.faults.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<S, A, B> Service for Fault<S>
+where
+    S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+    B: Default,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.borrow_mut().poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let (delay, abort) = match self.faults {
+            Some(ref faults) => (faults.delay(), faults.abort()),
+            None => (None, None),
+        };
+
+        if let Some(delay) = delay {
+            debug!("delaying request by {:?}", delay);
+            match ReactorTimeout::new(delay, &self.handle) {
+                Ok(timer) => {
+                    return ResponseFuture::Delayed {
+                        timer,
+                        service: self.inner.clone(),
+                        request: Some(req),
+                        abort,
+                    };
+                }
+                Err(e) => warn!("could not create fault delay timer: {}", e),
+            }
+        }
+
+        match abort {
+            Some(status) => ResponseFuture::Aborted(Some(status)),
+            None => ResponseFuture::Pending(self.inner.borrow_mut().call(req)),
+        }
+    }
+}
+
+// ===== impl Init =====
+
+impl<F> Future for Init<F>
+where
+    F: Future,
+{
+    type Item = Fault<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(Fault {
+            inner: Rc::new(RefCell::new(inner)),
+            faults: self.faults.clone(),
+            handle: self.handle.clone(),
+        }))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, B> Future for ResponseFuture<S>
+where
+    S: Service<Response = http::Response<B>>,
+    B: Default,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match mem::replace(self, ResponseFuture::Aborted(None)) {
+                ResponseFuture::Delayed { mut timer, service, request, abort } => {
+                    match timer.poll() {
+                        Ok(Async::NotReady) => {
+                            *self = ResponseFuture::Delayed { timer, service, request, abort };
+                            return Ok(Async::NotReady);
+                        }
+                        Ok(Async::Ready(())) => {}
+                        Err(e) => warn!("fault delay timer failed: {}", e),
+                    }
+                    match abort {
+                        Some(status) => ResponseFuture::Aborted(Some(status)),
+                        None => ResponseFuture::Dispatching { service, request },
+                    }
+                }
+                ResponseFuture::Dispatching { service, mut request } => {
+                    let ready = service.borrow_mut().poll_ready()?;
+                    if ready.is_not_ready() {
+                        *self = ResponseFuture::Dispatching { service, request };
+                        return Ok(Async::NotReady);
+                    }
+                    let req = request.take().expect("dispatched after completed");
+                    let fut = service.borrow_mut().call(req);
+                    ResponseFuture::Pending(fut)
+                }
+                ResponseFuture::Pending(mut fut) => {
+                    let poll = fut.poll();
+                    *self = ResponseFuture::Pending(fut);
+                    return poll;
+                }
+                ResponseFuture::Aborted(status) => {
+                    let status = status.expect("polled after complete");
+                    debug!("aborting request with {}", status);
+                    let mut rsp = http::Response::new(B::default());
+                    *rsp.status_mut() = status;
+                    rsp.extensions_mut().insert(Injected);
+                    return Ok(Async::Ready(rsp));
+                }
+            };
+        }
+    }
+}
+
+// ===== impl Faults =====
+
+impl Faults {
+    /// Returns the status to abort the next request with, if it's aborted.
+    fn abort(&self) -> Option<http::StatusCode> {
+        match self.abort {
+            Some((ref sample, status)) if sample.choose() => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Returns how long to delay the next request, if it's delayed.
+    fn delay(&self) -> Option<Duration> {
+        match self.delay {
+            Some((ref sample, delay)) if sample.choose() => Some(delay),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use test_support::Endpoint;
+
+    fn fault(core: &Core, config: FaultConfig) -> (Fault<Endpoint>, Endpoint) {
+        let endpoint = Endpoint::default();
+        let svc = Fault::new(endpoint.clone(), Some(config), &core.handle());
+        (svc, endpoint)
+    }
+
+    fn send(core: &mut Core, svc: &mut Fault<Endpoint>) -> http::Response<()> {
+        let req = http::Request::get("/").body(()).unwrap();
+        core.run(svc.call(req)).unwrap()
+    }
+
+    #[test]
+    fn aborts_the_configured_percent_of_requests() {
+        let mut core = Core::new().unwrap();
+        let config = FaultConfig::default()
+            .with_abort(25, http::StatusCode::SERVICE_UNAVAILABLE);
+        let (mut svc, endpoint) = fault(&core, config);
+
+        let aborted = (0..100)
+            .map(|_| send(&mut core, &mut svc))
+            .filter(|rsp| {
+                if rsp.status() == http::StatusCode::SERVICE_UNAVAILABLE {
+                    assert!(is_injected(rsp));
+                    true
+                } else {
+                    assert!(!is_injected(rsp));
+                    false
+                }
+            })
+            .count();
+        assert_eq!(aborted, 25);
+        assert_eq!(endpoint.calls(), 75);
+    }
+
+    #[test]
+    fn delays_the_configured_percent_of_requests() {
+        let mut core = Core::new().unwrap();
+        let delay = Duration::from_millis(20);
+        let config = FaultConfig::default().with_delay(50, delay);
+        let (mut svc, endpoint) = fault(&core, config);
+
+        let delayed = (0..10)
+            .filter(|_| {
+                let start = Instant::now();
+                let rsp = send(&mut core, &mut svc);
+                assert_eq!(rsp.status(), http::StatusCode::OK);
+                start.elapsed() >= delay
+            })
+            .count();
+        assert_eq!(delayed, 5);
+        assert_eq!(endpoint.calls(), 10);
+    }
+
+    #[test]
+    fn delayed_requests_may_also_be_aborted() {
+        let mut core = Core::new().unwrap();
+        let delay = Duration::from_millis(20);
+        let config = FaultConfig::default()
+            .with_delay(100, delay)
+            .with_abort(100, http::StatusCode::BAD_GATEWAY);
+        let (mut svc, endpoint) = fault(&core, config);
+
+        let start = Instant::now();
+        let rsp = send(&mut core, &mut svc);
+        assert!(start.elapsed() >= delay);
+        assert_eq!(rsp.status(), http::StatusCode::BAD_GATEWAY);
+        assert!(is_injected(&rsp));
+        assert_eq!(endpoint.calls(), 0);
+    }
+
+    #[test]
+    fn does_nothing_without_a_config() {
+        let mut core = Core::new().unwrap();
+        let endpoint = Endpoint::default();
+        let mut svc = Fault::new(endpoint.clone(), None, &core.handle());
+
+        for _ in 0..10 {
+            let rsp = send(&mut core, &mut svc);
+            assert_eq!(rsp.status(), http::StatusCode::OK);
+        }
+        assert_eq!(endpoint.calls(), 10);
+    }
+}
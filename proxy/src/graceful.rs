@@ -0,0 +1,233 @@
+use std::fmt;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+use drain;
+use timeout::TimeoutError;
+
+/// Lets an endpoint's in-flight requests complete, within a grace period,
+/// once the endpoint has been removed from service discovery.
+///
+/// Discovery signals the `drain::Watch` when the endpoint is removed, at which
+/// point the balancer stops routing new requests to it. Requests that are
+/// still awaiting a response then have `grace_period` to receive one before
+/// they fail and their streams are dropped. Once the balancer has dropped this
+/// service and no requests remain, the endpoint's connection is closed.
+///
+/// Without a `drain::Watch`, this is a no-op.
+pub struct Graceful<S> {
+    inner: S,
+    drain: Option<drain::Watch>,
+    grace_period: Duration,
+    handle: Handle,
+}
+
+pub struct ResponseFuture<F>(Inner<F>);
+
+enum Inner<F> {
+    Watching(drain::Watching<Grace<F>, fn(&mut Grace<F>)>),
+    Unwatched(F),
+}
+
+/// A response future that fails if the grace period elapses once started.
+struct Grace<F> {
+    inner: F,
+    grace_period: Duration,
+    handle: Handle,
+    timer: Option<ReactorTimeout>,
+}
+
+// ===== impl Graceful =====
+
+impl<S> Graceful<S> {
+    pub fn new(
+        inner: S,
+        drain: Option<drain::Watch>,
+        grace_period: Duration,
+        handle: &Handle,
+    ) -> Self {
+        Graceful {
+            inner,
+            drain,
+            grace_period,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<S: Service> Service for Graceful<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = TimeoutError<S::Error>;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(TimeoutError::Error)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let inner = self.inner.call(req);
+        match self.drain {
+            Some(ref drain) => {
+                let grace = Grace {
+                    inner,
+                    grace_period: self.grace_period,
+                    handle: self.handle.clone(),
+                    timer: None,
+                };
+                let start: fn(&mut Grace<S::Future>) = Grace::start;
+                ResponseFuture(Inner::Watching(drain.clone().watch(grace, start)))
+            }
+            None => ResponseFuture(Inner::Unwatched(inner)),
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Graceful<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Graceful")
+            .field("inner", &self.inner)
+            .field("grace_period", &self.grace_period)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Inner::Watching(ref mut f) => f.poll(),
+            Inner::Unwatched(ref mut f) => f.poll().map_err(TimeoutError::Error),
+        }
+    }
+}
+
+// ===== impl Grace =====
+
+impl<F> Grace<F> {
+    fn start(&mut self) {
+        debug!("endpoint removed; awaiting response for up to {:?}", self.grace_period);
+        let timer = ReactorTimeout::new(self.grace_period, &self.handle)
+            .expect("reactor gone");
+        self.timer = Some(timer);
+    }
+}
+
+impl<F: Future> Future for Grace<F> {
+    type Item = F::Item;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(rsp) = self.inner.poll().map_err(TimeoutError::Error)? {
+            return Ok(Async::Ready(rsp));
+        }
+
+        if let Some(ref mut timer) = self.timer {
+            if timer.poll().expect("timer failed").is_ready() {
+                debug!("removed endpoint did not respond within {:?}", self.grace_period);
+                return Err(TimeoutError::Timeout(self.grace_period));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use futures::{future, Future};
+    use futures::sync::oneshot;
+    use tokio_core::reactor::{Core, Handle, Timeout as ReactorTimeout};
+    use tower::Service;
+
+    use drain;
+    use timeout::TimeoutError;
+    use super::*;
+
+    /// Responds to each request when the request's `oneshot` is completed.
+    struct Deferred;
+
+    impl Service for Deferred {
+        type Request = oneshot::Receiver<&'static str>;
+        type Response = &'static str;
+        type Error = oneshot::Canceled;
+        type Future = oneshot::Receiver<&'static str>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, rsp: Self::Request) -> Self::Future {
+            rsp
+        }
+    }
+
+    /// Completes `tx` after `delay`.
+    fn respond_after(delay: Duration, tx: oneshot::Sender<&'static str>, handle: &Handle) {
+        let respond = ReactorTimeout::new(delay, handle)
+            .unwrap()
+            .map(move |_| { let _ = tx.send("ok"); })
+            .map_err(|_| ());
+        handle.spawn(respond);
+    }
+
+    #[test]
+    fn in_flight_request_completes_within_grace_period() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (signal, watch) = drain::channel();
+        let mut svc = Graceful::new(Deferred, Some(watch), Duration::from_secs(10), &handle);
+
+        let (tx, rx) = oneshot::channel();
+        let mut rsp = svc.call(rx);
+        let polled = core.run(future::lazy(|| Ok::<_, ()>(rsp.poll()))).unwrap();
+        assert!(polled.expect("poll").is_not_ready());
+
+        // The endpoint is removed while the request is in flight.
+        drop(signal.drain());
+
+        respond_after(Duration::from_millis(10), tx, &handle);
+        assert_eq!(core.run(rsp).ok(), Some("ok"));
+    }
+
+    #[test]
+    fn in_flight_request_fails_after_grace_period() {
+        let mut core = Core::new().unwrap();
+        let grace_period = Duration::from_millis(100);
+        let (signal, watch) = drain::channel();
+        let mut svc = Graceful::new(Deferred, Some(watch), grace_period, &core.handle());
+
+        let (_tx, rx) = oneshot::channel();
+        let rsp = svc.call(rx);
+        drop(signal.drain());
+
+        let start = Instant::now();
+        match core.run(rsp) {
+            Err(TimeoutError::Timeout(t)) => assert_eq!(t, grace_period),
+            r => panic!("request should time out: {:?}", r.map_err(|_| ())),
+        }
+        assert!(start.elapsed() >= grace_period);
+    }
+
+    #[test]
+    fn requests_are_not_limited_until_drained() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (_signal, watch) = drain::channel();
+        let mut svc = Graceful::new(Deferred, Some(watch), Duration::from_millis(1), &handle);
+
+        let (tx, rx) = oneshot::channel();
+        let rsp = svc.call(rx);
+        respond_after(Duration::from_millis(50), tx, &handle);
+        assert_eq!(core.run(rsp).ok(), Some("ok"));
+    }
+}
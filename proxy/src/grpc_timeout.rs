@@ -0,0 +1,311 @@
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use http;
+use http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+use telemetry::sensor::http::RequestOpen;
+
+/// The header in which gRPC clients send the time they're willing to wait
+/// for a call to complete.
+pub const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+/// The largest value that a `grpc-timeout` may have, in any unit.
+const MAX_TIMEOUT_VALUE: u64 = 99_999_999;
+
+/// The gRPC status with which calls that exceed their deadlines fail.
+const DEADLINE_EXCEEDED: &str = "4";
+
+/// The deadline of a gRPC request, read from its `grpc-timeout`, carried in
+/// its extensions.
+///
+/// The deadline is enforced on each dispatch of the request to an upstream,
+/// along with any request timeout, and what remains of it is propagated to
+/// the upstream in the request's `grpc-timeout`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GrpcDeadline(pub Instant);
+
+/// Reads the deadlines of gRPC requests from their `grpc-timeout` headers.
+///
+/// The deadline is measured from the request's `RequestOpen` timestamp.
+/// Requests that have already exceeded their deadlines fail with a
+/// `DEADLINE_EXCEEDED` status without being dispatched to the inner service,
+/// as do requests that aren't responded to in time.
+///
+/// If constructed disabled, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct GrpcTimeout<S> {
+    inner: S,
+    enabled: bool,
+    handle: Handle,
+}
+
+/// Fails with a `DEADLINE_EXCEEDED` status if the response isn't received
+/// before the request's deadline.
+pub struct ResponseFuture<F> {
+    /// `None` if the deadline was exceeded before the request was
+    /// dispatched.
+    inner: Option<F>,
+    timer: Option<ReactorTimeout>,
+}
+
+// ===== impl GrpcTimeout =====
+
+impl<S> GrpcTimeout<S> {
+    pub fn new(inner: S, enabled: bool, handle: &Handle) -> Self {
+        GrpcTimeout {
+            inner,
+            enabled,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<S, A, B> Service for GrpcTimeout<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Default,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: Self::Request) -> Self::Future {
+        let timeout = if self.enabled {
+            req.headers().get(GRPC_TIMEOUT).and_then(|v| parse(v.as_bytes()))
+        } else {
+            None
+        };
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => {
+                return ResponseFuture {
+                    inner: Some(self.inner.call(req)),
+                    timer: None,
+                };
+            }
+        };
+
+        let start = req.extensions()
+            .get::<RequestOpen>()
+            .map(|&RequestOpen(t)| t)
+            .unwrap_or_else(Instant::now);
+        let deadline = start + timeout;
+        if deadline <= Instant::now() {
+            debug!("request exceeded its gRPC deadline of {:?} on arrival", timeout);
+            return ResponseFuture {
+                inner: None,
+                timer: None,
+            };
+        }
+
+        req.extensions_mut().insert(GrpcDeadline(deadline));
+        let timer = ReactorTimeout::new_at(deadline, &self.handle).expect("reactor gone");
+        ResponseFuture {
+            inner: Some(self.inner.call(req)),
+            timer: Some(timer),
+        }
+    }
+}
+
+/// Parses a `grpc-timeout`: up to 8 digits, followed by a unit of hours
+/// (`H`), minutes (`M`), seconds (`S`), milliseconds (`m`), microseconds
+/// (`u`), or nanoseconds (`n`).
+pub fn parse(value: &[u8]) -> Option<Duration> {
+    let (unit, digits) = match value.split_last() {
+        Some((&unit, digits)) => (unit, digits),
+        None => return None,
+    };
+    if digits.is_empty() || digits.len() > 8 || digits.iter().any(|&d| d < b'0' || d > b'9') {
+        return None;
+    }
+    let n = digits.iter().fold(0, |n, &d| n * 10 + u64::from(d - b'0'));
+
+    let timeout = match unit {
+        b'H' => Duration::from_secs(n * 60 * 60),
+        b'M' => Duration::from_secs(n * 60),
+        b'S' => Duration::from_secs(n),
+        b'm' => Duration::from_millis(n),
+        b'u' => Duration::new(n / 1_000_000, (n % 1_000_000) as u32 * 1_000),
+        b'n' => Duration::new(n / 1_000_000_000, (n % 1_000_000_000) as u32),
+        _ => return None,
+    };
+    Some(timeout)
+}
+
+/// Formats `timeout` as a `grpc-timeout`, in the finest unit in which it
+/// fits, rounded up.
+pub fn encode(timeout: Duration) -> HeaderValue {
+    let nanos = timeout.as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::from(timeout.subsec_nanos()));
+    let units = [
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (60 * 1_000_000_000, 'M'),
+        (60 * 60 * 1_000_000_000, 'H'),
+    ];
+    let (n, unit) = units.iter()
+        .map(|&(per, unit)| (nanos / per + if nanos % per > 0 { 1 } else { 0 }, unit))
+        .find(|&(n, _)| n <= MAX_TIMEOUT_VALUE)
+        .expect("saturated timeouts fit in hours");
+    HeaderValue::from_str(&format!("{}{}", n, unit)).expect("grpc-timeout is a valid header")
+}
+
+/// A trailers-only gRPC response with a `DEADLINE_EXCEEDED` status.
+fn deadline_exceeded<B: Default>() -> http::Response<B> {
+    http::Response::builder()
+        .header(CONTENT_TYPE, "application/grpc")
+        .header(CONTENT_LENGTH, "0")
+        .header("grpc-status", DEADLINE_EXCEEDED)
+        .header("grpc-message", "Deadline Exceeded")
+        .body(B::default())
+        .expect("deadline exceeded response is valid")
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Default,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // The deadline is checked first, so that the error with which a
+        // request timeout fails the request at the same deadline is replaced.
+        let elapsed = match self.timer {
+            Some(ref mut timer) => timer.poll().expect("timer failed").is_ready(),
+            None => false,
+        };
+        if let Some(ref mut inner) = self.inner {
+            if !elapsed {
+                return inner.poll();
+            }
+            debug!("request exceeded its gRPC deadline");
+        }
+
+        // The request is canceled if it's still in flight.
+        self.inner = None;
+        self.timer = None;
+        Ok(Async::Ready(deadline_exceeded()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use tokio_core::reactor::Core;
+
+    use test_support::Unresponsive;
+    use super::*;
+
+    fn request(timeout: &'static str, open: Instant) -> http::Request<()> {
+        let mut req = http::Request::builder()
+            .header(GRPC_TIMEOUT, timeout)
+            .body(())
+            .unwrap();
+        req.extensions_mut().insert(RequestOpen(open));
+        req
+    }
+
+    /// Returns the deadline of each request dispatched to `upstream`.
+    fn deadlines(upstream: &Unresponsive) -> Vec<Option<GrpcDeadline>> {
+        upstream
+            .requests()
+            .iter()
+            .map(|req| req.extensions().get::<GrpcDeadline>().cloned())
+            .collect()
+    }
+
+    fn assert_deadline_exceeded<B>(rsp: &http::Response<B>) {
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(rsp.headers()["grpc-status"], DEADLINE_EXCEEDED);
+    }
+
+    #[test]
+    fn parses_every_unit() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse(b"2H"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse(b"3M"), Some(Duration::from_secs(3 * 60)));
+        assert_eq!(parse(b"10S"), Some(Duration::from_secs(10)));
+        assert_eq!(parse(b"250m"), Some(ms(250)));
+        assert_eq!(parse(b"1500u"), Some(Duration::new(0, 1_500_000)));
+        assert_eq!(parse(b"99999999n"), Some(Duration::new(0, 99_999_999)));
+        assert_eq!(parse(b"0m"), Some(ms(0)));
+
+        for invalid in &["", "m", "10", "10s", "-1S", "1.5S", "123456789m"] {
+            assert_eq!(parse(invalid.as_bytes()), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn encodes_in_the_finest_unit_that_fits() {
+        let encoded = |d| encode(d).to_str().unwrap().to_owned();
+        assert_eq!(encoded(Duration::new(0, 1_500)), "1500n");
+        assert_eq!(encoded(Duration::new(0, 250_000_001)), "250001u");
+        assert_eq!(encoded(Duration::from_secs(90)), "90000000u");
+        assert_eq!(encoded(Duration::from_secs(100_000)), "100000S");
+        assert_eq!(encoded(Duration::from_secs(24 * 60 * 60 * 365 * 10)), "5256000M");
+        assert_eq!(encoded(Duration::from_secs(u64::max_value())), "5124096H");
+
+        for &timeout in &["7H", "250m", "1500u"] {
+            let parsed = parse(timeout.as_bytes()).unwrap();
+            assert_eq!(parse(encode(parsed).as_bytes()), Some(parsed));
+        }
+    }
+
+    #[test]
+    fn exceeded_deadlines_are_not_dispatched() {
+        let core = Core::new().unwrap();
+        let upstream = Unresponsive::default();
+        let mut svc = GrpcTimeout::new(upstream.clone(), true, &core.handle());
+
+        let open = Instant::now() - Duration::from_millis(100);
+        for &timeout in &["0n", "50m"] {
+            let rsp = svc.call(request(timeout, open)).wait().unwrap();
+            assert_deadline_exceeded(&rsp);
+        }
+        assert!(upstream.requests().is_empty(), "requests should not be dispatched");
+    }
+
+    #[test]
+    fn slow_responses_exceed_their_deadlines() {
+        let mut core = Core::new().unwrap();
+        let upstream = Unresponsive::default();
+        let mut svc = GrpcTimeout::new(upstream.clone(), true, &core.handle());
+
+        let started = Instant::now();
+        let timeout = Duration::from_millis(50);
+        let rsp = core.run(future::lazy(|| svc.call(request("50m", started)))).unwrap();
+        assert_deadline_exceeded(&rsp);
+        assert!(started.elapsed() >= timeout);
+        assert_eq!(deadlines(&upstream), vec![Some(GrpcDeadline(started + timeout))]);
+    }
+
+    #[test]
+    fn disabled_ignores_grpc_timeouts() {
+        let core = Core::new().unwrap();
+        let upstream = Unresponsive::default();
+        let mut svc = GrpcTimeout::new(upstream.clone(), false, &core.handle());
+
+        let mut rsp = svc.call(request("0n", Instant::now() - Duration::from_secs(1)));
+        let polled = future::lazy(|| Ok::<_, ()>(rsp.poll())).wait().unwrap();
+        assert!(polled.unwrap().is_not_ready());
+        assert_eq!(deadlines(&upstream), vec![None]);
+    }
+}
@@ -0,0 +1,333 @@
+use futures::{Async, Future, Poll};
+use futures::future::{self, Either, FutureResult};
+use h2;
+use http;
+use http::header::CONTENT_LENGTH;
+use tower::{NewService, Service};
+
+/// Limits on the size of a message's headers.
+///
+/// Each header is measured as it would be written as an HTTP/1 header line,
+/// `name: value\r\n`, and the headers' size is the sum of their lines. The
+/// request line, status line, and trailers aren't counted.
+///
+/// Neither `hyper` nor `h2` can be configured to limit the headers they
+/// decode, so messages are checked once they've been decoded, before they're
+/// proxied any further.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderLimits {
+    max_total_bytes: Option<usize>,
+    max_line_bytes: Option<usize>,
+}
+
+/// Describes how a message's headers exceeded its `HeaderLimits`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Exceeded {
+    /// The headers totaled `bytes`.
+    Total { bytes: usize, max: usize },
+    /// The line of the header `name` was `bytes` long.
+    Line { name: http::header::HeaderName, bytes: usize, max: usize },
+}
+
+/// Responds with `431 Request Header Fields Too Large` to each request with
+/// headers that exceed its `HeaderLimits`, without dispatching it to the
+/// inner service.
+#[derive(Clone, Debug)]
+pub struct RequestHeaderLimit<S> {
+    inner: S,
+    limits: HeaderLimits,
+}
+
+/// Fails each response with headers that exceed its `HeaderLimits`, with a
+/// `PROTOCOL_ERROR`, before its body is read.
+#[derive(Clone, Debug)]
+pub struct ResponseHeaderLimit<S> {
+    inner: S,
+    limits: HeaderLimits,
+}
+
+/// Wraps the inner `NewService`'s services in `ResponseHeaderLimit`s.
+pub struct Init<F> {
+    future: F,
+    limits: HeaderLimits,
+}
+
+/// Fails if the response's headers are too large.
+pub struct ResponseFuture<F> {
+    inner: F,
+    limits: HeaderLimits,
+}
+
+/// The bytes that each header line adds to its name and value: `: ` and
+/// `\r\n`.
+const LINE_OVERHEAD: usize = 4;
+
+// ===== impl HeaderLimits =====
+
+impl HeaderLimits {
+    /// Limits the total size of each message's headers to `max` bytes.
+    pub fn with_max_total_bytes(self, max: usize) -> Self {
+        Self {
+            max_total_bytes: Some(max),
+            ..self
+        }
+    }
+
+    /// Limits the size of each header line to `max` bytes.
+    pub fn with_max_line_bytes(self, max: usize) -> Self {
+        Self {
+            max_line_bytes: Some(max),
+            ..self
+        }
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.max_total_bytes.is_some() || self.max_line_bytes.is_some()
+    }
+
+    /// Checks `headers` against the limits, returning how they were
+    /// exceeded, if they were.
+    pub fn check(&self, headers: &http::HeaderMap) -> Result<(), Exceeded> {
+        if !self.is_limited() {
+            return Ok(());
+        }
+
+        let mut total = 0;
+        for (name, value) in headers.iter() {
+            let bytes = name.as_str().len() + value.len() + LINE_OVERHEAD;
+            if let Some(max) = self.max_line_bytes {
+                if bytes > max {
+                    return Err(Exceeded::Line { name: name.clone(), bytes, max });
+                }
+            }
+            total += bytes;
+        }
+
+        match self.max_total_bytes {
+            Some(max) if total > max => Err(Exceeded::Total { bytes: total, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+// ===== impl RequestHeaderLimit =====
+
+impl<S> RequestHeaderLimit<S> {
+    pub fn new(inner: S, limits: HeaderLimits) -> Self {
+        RequestHeaderLimit { inner, limits }
+    }
+}
+
+impl<S, A, B> Service for RequestHeaderLimit<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Default,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<FutureResult<Self::Response, Self::Error>, S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        if let Err(exceeded) = self.limits.check(req.headers()) {
+            debug!("request headers too large: {:?}", exceeded);
+            let rsp = http::Response::builder()
+                .status(http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+                .header(CONTENT_LENGTH, "0")
+                .body(B::default())
+                .expect("request header fields too large response must be valid");
+            return Either::A(future::ok(rsp));
+        }
+
+        Either::B(self.inner.call(req))
+    }
+}
+
+// ===== impl ResponseHeaderLimit =====
+
+impl<S> ResponseHeaderLimit<S> {
+    pub fn new(inner: S, limits: HeaderLimits) -> Self {
+        ResponseHeaderLimit { inner, limits }
+    }
+}
+
+impl<N, A, B> NewService for ResponseHeaderLimit<N>
+where
+    N: NewService<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    N::Error: From<h2::Reason>,
+{
+    type Request = N::Request;
+    type Response = N::Response;
+    type Error = N::Error;
+    type Service = ResponseHeaderLimit<N::Service>;
+    type InitError = N::InitError;
+    type Future = Init<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        Init {
+            future: self.inner.new_service(),
+            limits: self.limits,
+        }
+    }
+}
+
+impl<S, A, B> Service for ResponseHeaderLimit<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    S::Error: From<h2::Reason>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(req),
+            limits: self.limits,
+        }
+    }
+}
+
+// ===== impl Init =====
+
+impl<F> Future for Init<F>
+where
+    F: Future,
+{
+    type Item = ResponseHeaderLimit<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(ResponseHeaderLimit::new(inner, self.limits)))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: From<h2::Reason>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = try_ready!(self.inner.poll());
+
+        // Unlike a response body that's too large, which is canceled, the
+        // response is treated as malformed, so that the two are told apart.
+        if let Err(exceeded) = self.limits.check(rsp.headers()) {
+            debug!("response headers too large: {:?}", exceeded);
+            return Err(h2::Reason::PROTOCOL_ERROR.into());
+        }
+
+        Ok(Async::Ready(rsp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+
+    use test_support::Upstream;
+    use super::*;
+
+    /// Responds to each request with `headers`.
+    fn upstream(headers: &[(&'static str, usize)]) -> Upstream {
+        Upstream::with_body(header_map(headers), &[])
+    }
+
+    /// Builds headers with values of the given lengths.
+    fn header_map(headers: &[(&'static str, usize)]) -> http::HeaderMap {
+        let mut map = http::HeaderMap::new();
+        for &(name, len) in headers {
+            let value = iter::repeat('a').take(len).collect::<String>();
+            map.append(name, value.parse().unwrap());
+        }
+        map
+    }
+
+    fn request(headers: &[(&'static str, usize)]) -> http::Request<()> {
+        let mut req = http::Request::new(());
+        *req.headers_mut() = header_map(headers);
+        req
+    }
+
+    #[test]
+    fn measures_headers_as_lines() {
+        // `x-a: aaaaaa\r\n` is 3 + 6 + 4 bytes long.
+        let headers = header_map(&[("x-a", 6), ("x-b", 6)]);
+        let limits = HeaderLimits::default();
+        assert_eq!(limits.check(&headers), Ok(()));
+        assert_eq!(limits.with_max_total_bytes(26).check(&headers), Ok(()));
+        assert_eq!(
+            limits.with_max_total_bytes(25).check(&headers),
+            Err(Exceeded::Total { bytes: 26, max: 25 })
+        );
+        assert_eq!(limits.with_max_line_bytes(13).check(&headers), Ok(()));
+        assert_eq!(
+            limits.with_max_line_bytes(12).check(&headers),
+            Err(Exceeded::Line { name: "x-a".parse().unwrap(), bytes: 13, max: 12 })
+        );
+    }
+
+    #[test]
+    fn rejects_requests_with_oversized_headers() {
+        let limits = HeaderLimits::default()
+            .with_max_total_bytes(1024)
+            .with_max_line_bytes(256);
+        let upstream = upstream(&[]);
+        let calls = upstream.calls();
+        let mut svc = RequestHeaderLimit::new(upstream, limits);
+
+        let rsp = svc.call(request(&[("x-a", 200), ("x-b", 200)])).wait().unwrap();
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+
+        let long_line = request(&[("x-a", 300)]);
+        let rsp = svc.call(long_line).wait().unwrap();
+        assert_eq!(rsp.status(), http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        let many_lines = [("x-a", 200), ("x-b", 200), ("x-a", 200), ("x-c", 200), ("x-d", 200)];
+        let rsp = svc.call(request(&many_lines)).wait().unwrap();
+        assert_eq!(rsp.status(), http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        assert_eq!(calls.get(), 1, "rejected requests must not be dispatched");
+    }
+
+    #[test]
+    fn fails_responses_with_oversized_headers() {
+        let limits = HeaderLimits::default().with_max_total_bytes(1024);
+        let mut svc = ResponseHeaderLimit::new(upstream(&[("x-a", 600), ("x-b", 600)]), limits);
+        let err = svc.call(http::Request::new(())).wait().expect_err("response must fail");
+        assert_eq!(err.reason(), Some(h2::Reason::PROTOCOL_ERROR));
+
+        let limits = HeaderLimits::default().with_max_line_bytes(256);
+        let mut svc = ResponseHeaderLimit::new(upstream(&[("x-a", 300)]), limits);
+        let err = svc.call(http::Request::new(())).wait().expect_err("response must fail");
+        assert_eq!(err.reason(), Some(h2::Reason::PROTOCOL_ERROR));
+
+        let mut svc = ResponseHeaderLimit::new(upstream(&[("x-a", 200)]), limits);
+        let rsp = svc.call(http::Request::new(())).wait().unwrap();
+        assert_eq!(rsp.headers().len(), 1);
+    }
+}
@@ -0,0 +1,539 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use futures::{task, Async, Future, Poll};
+use http;
+use http::uri::PathAndQuery;
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+use control::state::{DiscoveryState, EndpointState};
+
+/// Settings for active health checks.
+#[derive(Clone, Debug)]
+pub struct HealthCheckConfig {
+    path: PathAndQuery,
+    expected_status: http::StatusCode,
+    interval: Duration,
+    healthy_threshold: usize,
+    unhealthy_threshold: usize,
+}
+
+/// The health of each endpoint address.
+///
+/// As with circuit breakers, each address's state is held only weakly, so
+/// that it is dropped along with the last service bound to that address.
+/// Health checks for an address stop once its state has been dropped.
+#[derive(Clone, Debug, Default)]
+pub struct HealthChecks {
+    states: Arc<Mutex<HashMap<SocketAddr, Weak<Mutex<State>>>>>,
+    /// Reports each endpoint's health to the admin API, if it is served.
+    discovery_state: Option<DiscoveryState>,
+}
+
+/// Withholds an endpoint from load balancing while its health checks fail.
+///
+/// An endpoint is marked unhealthy once `unhealthy_threshold` consecutive
+/// probes have failed, and healthy again once `healthy_threshold` consecutive
+/// probes have passed. Endpoints are considered healthy until probed
+/// otherwise.
+///
+/// While its endpoint is unhealthy, the service is not ready, so that a
+/// balancer dispatches requests to other endpoints.
+///
+/// If constructed without a `HealthCheckConfig`, this is a no-op.
+#[derive(Debug)]
+pub struct HealthChecked<S> {
+    inner: S,
+    state: Option<Arc<Mutex<State>>>,
+}
+
+/// Probes an endpoint over HTTP, passing if the response has the expected
+/// status.
+#[derive(Debug)]
+pub struct HttpProbe<S> {
+    inner: S,
+    uri: http::Uri,
+    expected_status: http::StatusCode,
+}
+
+pub struct ProbeFuture<F> {
+    inner: F,
+    expected_status: http::StatusCode,
+}
+
+/// Probes a single endpoint every `interval`, for as long as any service is
+/// bound to it.
+///
+/// Each probe is given until the next is due to complete, after which it
+/// fails.
+struct Prober<P: Service> {
+    addr: SocketAddr,
+    probe: P,
+    config: HealthCheckConfig,
+    state: Weak<Mutex<State>>,
+    endpoint_state: Option<EndpointState>,
+    handle: Handle,
+    /// Fires when the next probe is due.
+    timer: Option<ReactorTimeout>,
+    in_flight: Option<P::Future>,
+}
+
+#[derive(Debug)]
+struct State {
+    healthy: bool,
+    passes: usize,
+    failures: usize,
+    /// Tasks to notify once the endpoint is healthy again.
+    waiting: Vec<task::Task>,
+}
+
+// ===== impl HealthCheckConfig =====
+
+impl HealthCheckConfig {
+    /// Probes endpoints every `interval` with a `GET` request for `path`,
+    /// expecting a `200 OK` response.
+    pub fn new(path: PathAndQuery, interval: Duration) -> Self {
+        HealthCheckConfig {
+            path,
+            expected_status: http::StatusCode::OK,
+            interval,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+
+    pub fn with_expected_status(self, expected_status: http::StatusCode) -> Self {
+        Self {
+            expected_status,
+            ..self
+        }
+    }
+
+    /// Sets the number of consecutive probes that must pass for an endpoint
+    /// to become healthy, and that must fail for it to become unhealthy.
+    ///
+    /// Each threshold is at least one.
+    pub fn with_thresholds(self, healthy: usize, unhealthy: usize) -> Self {
+        Self {
+            healthy_threshold: cmp::max(healthy, 1),
+            unhealthy_threshold: cmp::max(unhealthy, 1),
+            ..self
+        }
+    }
+}
+
+// ===== impl HealthChecks =====
+
+impl HealthChecks {
+    /// Reports the health of each endpoint to `state`.
+    pub fn with_discovery_state(self, state: &DiscoveryState) -> Self {
+        Self {
+            discovery_state: Some(state.clone()),
+            ..self
+        }
+    }
+
+    /// Wraps `inner` in a `HealthChecked` that shares its endpoint's health
+    /// with every other service bound to `addr`.
+    ///
+    /// If `addr` is not already being probed, a probe is made from the
+    /// config with `new_probe`, and spawned on `handle`.
+    pub fn health_checked<S, P, F>(
+        &self,
+        addr: &SocketAddr,
+        inner: S,
+        config: Option<&HealthCheckConfig>,
+        handle: &Handle,
+        new_probe: F,
+    ) -> HealthChecked<S>
+    where
+        P: Service<Request = (), Response = bool> + 'static,
+        F: FnOnce(&HealthCheckConfig) -> P,
+    {
+        let config = match config {
+            Some(config) => config,
+            None => return HealthChecked { inner, state: None },
+        };
+
+        let mut states = self.states.lock().expect("health states lock");
+
+        // Drop the state of endpoints that are no longer bound.
+        states.retain(|_, state| state.upgrade().is_some());
+
+        if let Some(state) = states.get(addr).and_then(Weak::upgrade) {
+            return HealthChecked { inner, state: Some(state) };
+        }
+
+        let state = Arc::new(Mutex::new(State {
+            healthy: true,
+            passes: 0,
+            failures: 0,
+            waiting: Vec::new(),
+        }));
+        states.insert(*addr, Arc::downgrade(&state));
+
+        trace!("health checking {} every {:?}", addr, config.interval);
+        handle.spawn(Prober {
+            addr: *addr,
+            probe: new_probe(config),
+            config: config.clone(),
+            state: Arc::downgrade(&state),
+            endpoint_state: self.discovery_state.as_ref().map(|s| s.endpoint(addr)),
+            handle: handle.clone(),
+            timer: None,
+            in_flight: None,
+        });
+
+        HealthChecked { inner, state: Some(state) }
+    }
+}
+
+// ===== impl HealthChecked =====
+
+impl<S: Service> Service for HealthChecked<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if let Some(ref state) = self.state {
+            let mut state = state.lock().expect("health state lock");
+            if !state.healthy {
+                if !state.waiting.iter().any(|t| t.will_notify_current()) {
+                    state.waiting.push(task::current());
+                }
+                return Ok(Async::NotReady);
+            }
+        }
+
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+// ===== impl HttpProbe =====
+
+impl<S> HttpProbe<S> {
+    /// Probes `addr` by sending requests for `config`'s path through `inner`.
+    pub fn new(inner: S, addr: &SocketAddr, config: &HealthCheckConfig) -> Self {
+        let uri = format!("http://{}{}", addr, config.path)
+            .parse()
+            .expect("health check URI must be valid");
+        HttpProbe {
+            inner,
+            uri,
+            expected_status: config.expected_status,
+        }
+    }
+}
+
+impl<S, A, B> Service for HttpProbe<S>
+where
+    S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+    A: Default,
+{
+    type Request = ();
+    type Response = bool;
+    type Error = S::Error;
+    type Future = ProbeFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, _: ()) -> Self::Future {
+        let mut req = http::Request::new(A::default());
+        *req.uri_mut() = self.uri.clone();
+        ProbeFuture {
+            inner: self.inner.call(req),
+            expected_status: self.expected_status,
+        }
+    }
+}
+
+// ===== impl ProbeFuture =====
+
+impl<F, B> Future for ProbeFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = bool;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = try_ready!(self.inner.poll());
+        Ok(Async::Ready(rsp.status() == self.expected_status))
+    }
+}
+
+// ===== impl Prober =====
+
+impl<P> Prober<P>
+where
+    P: Service<Request = (), Response = bool>,
+{
+    /// Waits `interval` before the next probe is due.
+    ///
+    /// Returns false if the reactor can no longer run timers.
+    fn reset_timer(&mut self) -> bool {
+        match ReactorTimeout::new(self.config.interval, &self.handle) {
+            Ok(timer) => {
+                self.timer = Some(timer);
+                true
+            }
+            Err(e) => {
+                warn!("could not create health check timer for {}: {}", self.addr, e);
+                false
+            }
+        }
+    }
+
+    fn record(&self, state: &Mutex<State>, passed: bool) {
+        let mut state = state.lock().expect("health state lock");
+        if passed {
+            state.failures = 0;
+            state.passes += 1;
+            if !state.healthy && state.passes >= self.config.healthy_threshold {
+                debug!("{} passed {} health checks; marking healthy", self.addr, state.passes);
+                state.healthy = true;
+                if let Some(ref endpoint_state) = self.endpoint_state {
+                    endpoint_state.set_healthy(true);
+                }
+                for task in state.waiting.drain(..) {
+                    task.notify();
+                }
+            }
+        } else {
+            state.passes = 0;
+            state.failures += 1;
+            if state.healthy && state.failures >= self.config.unhealthy_threshold {
+                debug!("{} failed {} health checks; marking unhealthy", self.addr, state.failures);
+                state.healthy = false;
+                if let Some(ref endpoint_state) = self.endpoint_state {
+                    endpoint_state.set_healthy(false);
+                }
+            }
+        }
+    }
+}
+
+impl<P> Future for Prober<P>
+where
+    P: Service<Request = (), Response = bool>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            let state = match self.state.upgrade() {
+                Some(state) => state,
+                None => {
+                    trace!("{} is no longer bound; stopping health checks", self.addr);
+                    return Ok(Async::Ready(()));
+                }
+            };
+
+            if self.in_flight.is_some() {
+                let passed = match self.in_flight.as_mut().expect("probe in flight").poll() {
+                    Ok(Async::Ready(passed)) => passed,
+                    Err(_) => false,
+                    Ok(Async::NotReady) => {
+                        // The probe fails if the next one is due before it
+                        // completes.
+                        match self.timer.as_mut().map(Future::poll) {
+                            Some(Ok(Async::NotReady)) => return Ok(Async::NotReady),
+                            _ => {
+                                self.timer = None;
+                                false
+                            }
+                        }
+                    }
+                };
+                self.in_flight = None;
+                self.record(&state, passed);
+                continue;
+            }
+
+            if let Some(poll) = self.timer.as_mut().map(Future::poll) {
+                if let Ok(Async::NotReady) = poll {
+                    return Ok(Async::NotReady);
+                }
+                self.timer = None;
+            }
+
+            match self.probe.poll_ready() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {
+                    if !self.reset_timer() {
+                        return Ok(Async::Ready(()));
+                    }
+                    self.in_flight = Some(self.probe.call(()));
+                }
+                Err(_) => {
+                    self.record(&state, false);
+                    if !self.reset_timer() {
+                        return Ok(Async::Ready(()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use futures::future::{self, FutureResult};
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use test_support::Endpoint;
+
+    /// A probe whose results are scripted, passing once the script runs out.
+    #[derive(Clone)]
+    struct Script(Rc<RefCell<VecDeque<bool>>>);
+
+    impl Script {
+        fn new(results: Vec<bool>) -> Self {
+            Script(Rc::new(RefCell::new(results.into())))
+        }
+
+        fn remaining(&self) -> usize {
+            self.0.borrow().len()
+        }
+    }
+
+    impl Service for Script {
+        type Request = ();
+        type Response = bool;
+        type Error = ();
+        type Future = FutureResult<bool, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(self.0.borrow_mut().pop_front().unwrap_or(true))
+        }
+    }
+
+    fn config() -> HealthCheckConfig {
+        HealthCheckConfig::new("/healthz".parse().unwrap(), Duration::from_millis(10))
+            .with_thresholds(2, 2)
+    }
+
+    /// Runs `core` until `done` is true, panicking if it takes too long.
+    fn turn_until<F: FnMut() -> bool>(core: &mut Core, mut done: F) {
+        for _ in 0..200 {
+            if done() {
+                return;
+            }
+            core.turn(Some(Duration::from_millis(5)));
+        }
+        panic!("timed out");
+    }
+
+    fn is_ready(core: &mut Core, svc: &mut HealthChecked<Endpoint>) -> bool {
+        core.run(future::lazy(|| svc.poll_ready()))
+            .expect("poll_ready")
+            .is_ready()
+    }
+
+    #[test]
+    fn endpoints_are_withheld_while_unhealthy() {
+        let mut core = Core::new().unwrap();
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let script = Script::new(vec![true, false, false, false, true]);
+        let probe = script.clone();
+        let endpoint = Endpoint::default();
+        let mut svc = HealthChecks::default()
+            .health_checked(&addr, endpoint, Some(&config()), &core.handle(), |_| probe);
+
+        // Endpoints are healthy until probed otherwise, and a single failure
+        // is below the threshold.
+        assert!(is_ready(&mut core, &mut svc));
+        turn_until(&mut core, || script.remaining() == 3);
+        assert!(is_ready(&mut core, &mut svc));
+
+        turn_until(&mut core, || script.remaining() == 2);
+        assert!(!is_ready(&mut core, &mut svc));
+
+        // A single pass is below the threshold...
+        turn_until(&mut core, || script.remaining() == 0);
+        assert!(!is_ready(&mut core, &mut svc));
+
+        // ...but the endpoint is ready again after the next.
+        let ready = future::poll_fn(|| svc.poll_ready());
+        let timeout = ReactorTimeout::new(Duration::from_secs(1), &core.handle()).unwrap();
+        match core.run(ready.select2(timeout)) {
+            Ok(future::Either::A(_)) => {}
+            _ => panic!("service should become ready"),
+        }
+    }
+
+    #[test]
+    fn services_bound_to_an_address_share_its_health() {
+        let mut core = Core::new().unwrap();
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let checks = HealthChecks::default();
+        let script = Script::new(vec![false, false]);
+        let probe = script.clone();
+        let (a, b) = (Endpoint::default(), Endpoint::default());
+        let mut first = checks
+            .health_checked(&addr, a, Some(&config()), &core.handle(), |_| probe);
+        let mut second = checks
+            .health_checked(&addr, b, Some(&config()), &core.handle(), |_| -> Script {
+                panic!("address should only be probed once")
+            });
+
+        turn_until(&mut core, || script.remaining() == 0);
+        assert!(!is_ready(&mut core, &mut first));
+        assert!(!is_ready(&mut core, &mut second));
+    }
+
+    #[test]
+    fn probes_stop_once_no_services_are_bound() {
+        let mut core = Core::new().unwrap();
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let script = Script::new(vec![]);
+        let probe = script.clone();
+        let endpoint = Endpoint::default();
+        let svc = HealthChecks::default()
+            .health_checked(&addr, endpoint, Some(&config()), &core.handle(), |_| probe);
+
+        turn_until(&mut core, || Rc::strong_count(&script.0) == 2);
+        drop(svc);
+        turn_until(&mut core, || Rc::strong_count(&script.0) == 1);
+    }
+
+    #[test]
+    fn http_probes_expect_a_status() {
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let config = config().with_expected_status(http::StatusCode::NO_CONTENT);
+        let probe = |status| {
+            let upstream = Endpoint::with_statuses(&[status]);
+            let passed = HttpProbe::new(upstream.clone(), &addr, &config).call(()).wait();
+
+            let req = &upstream.requests()[0];
+            assert_eq!(*req.method(), http::Method::GET);
+            assert_eq!(req.uri(), "http://10.1.1.1:8080/healthz");
+            passed
+        };
+        assert_eq!(probe(204), Ok(true));
+        assert_eq!(probe(200), Ok(false));
+        assert_eq!(probe(503), Ok(false));
+    }
+}
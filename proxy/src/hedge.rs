@@ -0,0 +1,376 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use http;
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+use tower_h2::Body;
+
+use balance::{Choices, Weight};
+use pin::PinnedEndpoint;
+use replay::FromBuffered;
+use retry::{Replay, RetryPolicy};
+
+/// Hedges requests that are slow to receive a response.
+///
+/// If a request has not been answered within `delay`, it is sent again, and
+/// whichever attempt is answered first is used. The other attempt is
+/// canceled. The inner service is expected to be a balancer whose `Chooser`
+/// records its choices in `choices`, so that the second attempt is sent to a
+/// different endpoint than the first.
+///
+/// Only requests that the `RetryPolicy` considers replayable are hedged, and
+/// requests pinned to an endpoint never are. If constructed without a delay,
+/// this is a no-op.
+pub struct Hedge<S> {
+    inner: Rc<RefCell<S>>,
+    delay: Option<Duration>,
+    policy: Rc<RetryPolicy>,
+    choices: Choices,
+    handle: Handle,
+}
+
+pub struct ResponseFuture<S: Service> {
+    service: Rc<RefCell<S>>,
+    choices: Choices,
+    primary: Option<S::Future>,
+    /// The endpoint the first attempt was sent to, if it is known.
+    endpoint: Option<Weight>,
+    hedge: Hedging<S::Future>,
+}
+
+enum Hedging<F> {
+    /// Waiting for the delay to elapse before hedging.
+    Waiting(ReactorTimeout, Replay),
+    /// Waiting for the service to become ready for the second attempt.
+    Dispatching(Replay),
+    /// Waiting for a response to the second attempt.
+    Pending(F),
+    /// The request is not, or is no longer, hedged.
+    Done,
+}
+
+// ===== impl Hedge =====
+
+impl<S> Hedge<S> {
+    pub fn new(
+        inner: S,
+        delay: Option<Duration>,
+        policy: RetryPolicy,
+        choices: Choices,
+        handle: &Handle,
+    ) -> Self {
+        Hedge {
+            inner: Rc::new(RefCell::new(inner)),
+            delay,
+            policy: Rc::new(policy),
+            choices,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<S, A, B> Service for Hedge<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    A: Body + FromBuffered,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // The endpoint chosen while becoming ready is the one the next
+        // request is sent to.
+        self.choices.forget();
+        self.inner.borrow_mut().poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let pinned = req.extensions().get::<PinnedEndpoint>().is_some();
+        let hedge = match self.delay {
+            Some(delay) if self.policy.is_replayable(&req) && !pinned => {
+                match ReactorTimeout::new(delay, &self.handle) {
+                    Ok(timer) => Hedging::Waiting(timer, Replay::new(&req)),
+                    Err(e) => {
+                        warn!("could not create hedge timer: {}", e);
+                        Hedging::Done
+                    }
+                }
+            }
+            _ => Hedging::Done,
+        };
+
+        let primary = self.inner.borrow_mut().call(req);
+        ResponseFuture {
+            service: self.inner.clone(),
+            choices: self.choices.clone(),
+            primary: Some(primary),
+            endpoint: self.choices.take_last(),
+            hedge,
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, A, B> ResponseFuture<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    A: FromBuffered,
+{
+    /// Sends the second attempt once the service is ready, steering it away
+    /// from the endpoint the first attempt was sent to.
+    fn dispatch(&mut self, replay: Replay) -> Hedging<S::Future> {
+        if let Some(ref endpoint) = self.endpoint {
+            self.choices.avoid(endpoint.clone());
+        }
+        let ready = self.service.borrow_mut().poll_ready();
+        self.choices.stop_avoiding();
+
+        match ready {
+            Ok(Async::NotReady) => Hedging::Dispatching(replay),
+            // The first attempt may still succeed, so the request doesn't
+            // fail for want of an attempt to hedge it with.
+            Ok(Async::Ready(())) if !replay.spend_attempt(false) => {
+                debug!("request budget has no attempts left to hedge with");
+                Hedging::Done
+            }
+            Ok(Async::Ready(())) => {
+                debug!("hedging request");
+                Hedging::Pending(self.service.borrow_mut().call(replay.attempt()))
+            }
+            Err(_) => {
+                debug!("service failed before request could be hedged");
+                Hedging::Done
+            }
+        }
+    }
+}
+
+impl<S, A, B> Future for ResponseFuture<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    A: FromBuffered,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let primary = match self.primary.as_mut().map(Future::poll) {
+            Some(Ok(Async::Ready(rsp))) => Some(Ok(rsp)),
+            Some(Err(e)) => Some(Err(e)),
+            Some(Ok(Async::NotReady)) | None => None,
+        };
+        match primary {
+            // Dropping the second attempt, if there is one, cancels it.
+            Some(Ok(rsp)) => return Ok(Async::Ready(rsp)),
+            Some(Err(e)) => match self.hedge {
+                // If the second attempt has been sent, it may yet succeed.
+                Hedging::Pending(_) => {
+                    debug!("first attempt failed; waiting for hedged request");
+                    self.primary = None;
+                }
+                _ => return Err(e),
+            },
+            None => {}
+        }
+
+        loop {
+            self.hedge = match mem::replace(&mut self.hedge, Hedging::Done) {
+                Hedging::Waiting(mut timer, replay) => match timer.poll() {
+                    Ok(Async::NotReady) => {
+                        self.hedge = Hedging::Waiting(timer, replay);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => Hedging::Dispatching(replay),
+                    Err(e) => {
+                        warn!("hedge timer failed: {}", e);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Hedging::Dispatching(replay) => match self.dispatch(replay) {
+                    Hedging::Dispatching(replay) => {
+                        self.hedge = Hedging::Dispatching(replay);
+                        return Ok(Async::NotReady);
+                    }
+                    Hedging::Done => return Ok(Async::NotReady),
+                    hedge => hedge,
+                },
+                Hedging::Pending(mut future) => match future.poll() {
+                    Ok(Async::NotReady) => {
+                        self.hedge = Hedging::Pending(future);
+                        return Ok(Async::NotReady);
+                    }
+                    // Dropping the first attempt cancels it.
+                    Ok(Async::Ready(rsp)) => {
+                        self.primary = None;
+                        return Ok(Async::Ready(rsp));
+                    }
+                    Err(e) => {
+                        if self.primary.is_none() {
+                            return Err(e);
+                        }
+                        debug!("hedged request failed; waiting for first attempt");
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Hedging::Done => return Ok(Async::NotReady),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::future;
+    use tokio_core::reactor::Core;
+
+    use replay::Buffered;
+
+    use super::*;
+
+    /// Balances requests over a slow endpoint and a fast one, preferring the
+    /// slow endpoint unless it is being avoided.
+    struct Endpoints {
+        slow: Weight,
+        fast: Weight,
+        choices: Choices,
+        chosen: Option<Weight>,
+        requests: Rc<RefCell<Vec<&'static str>>>,
+        canceled: Rc<Cell<bool>>,
+    }
+
+    /// Sets a flag if dropped.
+    struct CancelGuard(Rc<Cell<bool>>);
+
+    impl Service for Endpoints {
+        type Request = http::Request<()>;
+        type Response = http::Response<&'static str>;
+        type Error = ();
+        type Future = Box<Future<Item = Self::Response, Error = ()>>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            let chosen = if self.choices.is_avoided(&self.slow) {
+                self.fast.clone()
+            } else {
+                self.slow.clone()
+            };
+            self.choices.record(&chosen);
+            self.chosen = Some(chosen);
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let chosen = self.chosen.take().expect("called before ready");
+            if chosen.same_endpoint(&self.slow) {
+                self.requests.borrow_mut().push("slow");
+                let guard = CancelGuard(self.canceled.clone());
+                let rsp = future::empty::<Self::Response, ()>();
+                Box::new(rsp.map(move |rsp| {
+                    drop(guard);
+                    rsp
+                }))
+            } else {
+                self.requests.borrow_mut().push("fast");
+                Box::new(future::ok(http::Response::new("fast")))
+            }
+        }
+    }
+
+    impl FromBuffered for () {
+        fn from_buffered(_: Buffered) -> Self {}
+    }
+
+    impl Drop for CancelGuard {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    fn hedge(
+        delay: Option<Duration>,
+        core: &Core,
+    ) -> (Hedge<Endpoints>, Rc<RefCell<Vec<&'static str>>>, Rc<Cell<bool>>) {
+        let choices = Choices::default();
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let canceled = Rc::new(Cell::new(false));
+        let endpoints = Endpoints {
+            slow: Weight::default(),
+            fast: Weight::default(),
+            choices: choices.clone(),
+            chosen: None,
+            requests: requests.clone(),
+            canceled: canceled.clone(),
+        };
+        let svc = Hedge::new(endpoints, delay, RetryPolicy::default(), choices, &core.handle());
+        (svc, requests, canceled)
+    }
+
+    fn request(method: http::Method) -> http::Request<()> {
+        let mut req = http::Request::new(());
+        *req.method_mut() = method;
+        req
+    }
+
+    #[test]
+    fn slow_requests_are_hedged_to_another_endpoint() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, requests, canceled) = hedge(Some(Duration::from_millis(10)), &core);
+
+        assert!(svc.poll_ready().unwrap().is_ready());
+        let rsp = core.run(svc.call(request(http::Method::GET))).expect("response");
+
+        assert_eq!(*rsp.body(), "fast");
+        assert_eq!(*requests.borrow(), vec!["slow", "fast"]);
+        assert!(canceled.get(), "slow attempt should be canceled");
+    }
+
+    #[test]
+    fn requests_that_are_not_replayable_are_not_hedged() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, requests, canceled) = hedge(Some(Duration::from_millis(10)), &core);
+
+        assert!(svc.poll_ready().unwrap().is_ready());
+        let rsp = svc.call(request(http::Method::POST));
+        let timeout = ReactorTimeout::new(Duration::from_millis(50), &core.handle()).unwrap();
+        match core.run(rsp.select2(timeout)) {
+            Ok(future::Either::B(_)) => {}
+            _ => panic!("request should not be answered"),
+        }
+
+        assert_eq!(*requests.borrow(), vec!["slow"]);
+        assert!(canceled.get(), "dropped request should be canceled");
+    }
+
+    #[test]
+    fn requests_are_not_hedged_without_a_delay() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, requests, _) = hedge(None, &core);
+
+        assert!(svc.poll_ready().unwrap().is_ready());
+        let rsp = svc.call(request(http::Method::GET));
+        let timeout = ReactorTimeout::new(Duration::from_millis(50), &core.handle()).unwrap();
+        match core.run(rsp.select2(timeout)) {
+            Ok(future::Either::B(_)) => {}
+            _ => panic!("request should not be answered"),
+        }
+
+        assert_eq!(*requests.borrow(), vec!["slow"]);
+    }
+}
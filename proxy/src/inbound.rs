@@ -4,12 +4,15 @@ use std::sync::Arc;
 use http;
 use tower;
 use tower_buffer::{self, Buffer};
-use tower_in_flight_limit::{self, InFlightLimit};
+use tower_in_flight_limit;
 use tower_h2;
 use conduit_proxy_router::{Reuse, Recognize};
 
 use bind;
+use body_limit::RequestLengthLimit;
 use ctx;
+use header_limit::RequestHeaderLimit;
+use priority::PriorityLimit;
 
 type Bind<B> = bind::Bind<Arc<ctx::Proxy>, B>;
 
@@ -18,8 +21,6 @@ pub struct Inbound<B> {
     bind: Bind<B>,
 }
 
-const MAX_IN_FLIGHT: usize = 10_000;
-
 // ===== impl Inbound =====
 
 impl<B> Inbound<B> {
@@ -33,7 +34,7 @@ impl<B> Inbound<B> {
 
 impl<B> Recognize for Inbound<B>
 where
-    B: tower_h2::Body + 'static,
+    B: tower_h2::Body + Default + 'static,
 {
     type Request = http::Request<B>;
     type Response = bind::HttpResponse;
@@ -44,7 +45,9 @@ where
     >;
     type Key = (SocketAddr, bind::Protocol);
     type RouteError = bind::BufferSpawnError;
-    type Service = InFlightLimit<Buffer<bind::Service<B>>>;
+    type Service = RequestHeaderLimit<
+        RequestLengthLimit<PriorityLimit<Buffer<bind::Service<B>>>>
+    >;
 
     fn recognize(&self, req: &Self::Request) -> Option<Reuse<Self::Key>> {
         let key = req.extensions()
@@ -55,7 +58,7 @@ where
             })
             .or_else(|| self.default_addr);
 
-        let proto = bind::Protocol::detect(req);
+        let proto = bind::Protocol::detect(req).coerce(req, self.bind.protocol_policy());
 
         let key = key.map(move|addr| proto.into_key(addr));
         trace!("recognize key={:?}", key);
@@ -66,19 +69,33 @@ where
 
     /// Builds a static service to a single endpoint.
     ///
+    /// At most `buffer_capacity` requests, as configured on the `Bind`, may be
+    /// buffered; additional requests fail immediately, unless they're more
+    /// important than a buffered request, which is shed in their place.
+    /// Requests that declare bodies larger than the `Bind`'s
+    /// `max_request_bytes`, or with headers that exceed its `HeaderLimits`,
+    /// are rejected before they are buffered.
+    ///
     /// # TODO
     ///
-    /// Buffering is currently unbounded and does not apply timeouts. This must be
-    /// changed.
+    /// Buffering does not apply timeouts. This must be changed.
     fn bind_service(&mut self, key: &Self::Key) -> Result<Self::Service, Self::RouteError> {
         let &(ref addr, ref proto) = key;
         debug!("building inbound {:?} client to {}", proto, addr);
 
+        let capacity = self.bind.buffer_capacity();
+        let max_request_bytes = self.bind.max_request_bytes();
+        let header_limits = self.bind.header_limits();
+        let classify = self.bind.classify();
         Buffer::new(self.bind.bind_service(addr, proto), self.bind.executor())
             .map(|buffer| {
-                InFlightLimit::new(buffer, MAX_IN_FLIGHT)
+                let svc = PriorityLimit::new(buffer, capacity, classify);
+                let svc = RequestLengthLimit::new(svc, max_request_bytes);
+                RequestHeaderLimit::new(svc, header_limits)
+            })
+            .map_err(|_| {
+                bind::BufferSpawnError::inbound(addr.to_string(), bind::ExecutorRefused)
             })
-            .map_err(|_| bind::BufferSpawnError::Inbound)
     }
 }
 
@@ -87,8 +104,11 @@ mod tests {
     use std::net;
     use std::sync::Arc;
 
+    use futures::{future, Future};
     use http;
     use tokio_core::reactor::Core;
+    use tower::Service;
+    use tower_in_flight_limit;
     use conduit_proxy_router::Recognize;
 
     use super::Inbound;
@@ -185,4 +205,86 @@ mod tests {
             )
         }
     }
+
+    #[test]
+    fn rejects_requests_beyond_buffer_capacity() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::inbound(&ctx::Process::test("test"));
+        let bind = Bind::new(core.handle())
+            .with_ctx(ctx.clone())
+            .with_buffer_capacity(2);
+        let mut inbound = Inbound::new(None, bind);
+
+        // The reactor is never turned, so the connection is never
+        // established and every request remains buffered.
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let mut svc = inbound.bind_service(&(addr, bind::Protocol::Http2))
+            .expect("bind_service");
+
+        future::lazy(|| {
+            let _first = svc.call(http::Request::new(()));
+            let _second = svc.call(http::Request::new(()));
+
+            match svc.call(http::Request::new(())).poll() {
+                Err(tower_in_flight_limit::Error::NoCapacity) => {},
+                _ => panic!("request beyond buffer capacity should be rejected"),
+            }
+
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn sheds_less_important_requests_beyond_buffer_capacity() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::inbound(&ctx::Process::test("test"));
+        let bind = Bind::new(core.handle())
+            .with_ctx(ctx.clone())
+            .with_buffer_capacity(1)
+            .with_priority_header(http::header::HeaderName::from_static("x-priority"));
+        let mut inbound = Inbound::new(None, bind);
+
+        // The reactor is never turned, so every request remains buffered.
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let mut svc = inbound.bind_service(&(addr, bind::Protocol::Http2))
+            .expect("bind_service");
+
+        future::lazy(|| {
+            let mut low = svc.call(http::Request::new(()));
+            assert!(low.poll().ok().expect("buffered").is_not_ready());
+
+            let mut req = http::Request::new(());
+            req.headers_mut().insert("x-priority", "1".parse().unwrap());
+            let mut high = svc.call(req);
+            assert!(high.poll().ok().expect("buffered").is_not_ready());
+
+            match low.poll() {
+                Err(tower_in_flight_limit::Error::NoCapacity) => {},
+                _ => panic!("less important request should be shed"),
+            }
+
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn rejects_requests_with_oversized_headers() {
+        let core = Core::new().unwrap();
+        let ctx = ctx::Proxy::inbound(&ctx::Process::test("test"));
+        let bind = Bind::new(core.handle())
+            .with_ctx(ctx.clone())
+            .with_max_header_bytes(64);
+        let mut inbound = Inbound::new(None, bind);
+
+        // The reactor is never turned, so a request that was dispatched
+        // would never be answered.
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let mut svc = inbound.bind_service(&(addr, bind::Protocol::Http2))
+            .expect("bind_service");
+
+        let mut req = http::Request::new(());
+        req.headers_mut().insert("x-padding", "a".repeat(64).parse().unwrap());
+        let rsp = svc.call(req).wait().ok().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
 }
@@ -8,6 +8,7 @@ extern crate conduit_proxy_controller_grpc;
 extern crate convert;
 extern crate domain;
 extern crate env_logger;
+extern crate flate2;
 #[macro_use]
 extern crate futures;
 extern crate futures_mpsc_lossy;
@@ -30,9 +31,15 @@ extern crate prost_types;
 #[macro_use]
 extern crate quickcheck;
 extern crate rand;
+extern crate rustls;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 extern crate tokio_connect;
 extern crate tokio_core;
 extern crate tokio_io;
+extern crate tokio_rustls;
 extern crate tower;
 extern crate tower_balance;
 extern crate tower_buffer;
@@ -43,6 +50,7 @@ extern crate tower_reconnect;
 extern crate conduit_proxy_router;
 extern crate tower_util;
 extern crate tower_in_flight_limit;
+extern crate webpki;
 
 use futures::*;
 
@@ -54,35 +62,81 @@ use std::thread;
 use std::time::Duration;
 
 use indexmap::IndexSet;
-use tokio_core::reactor::{Core, Handle};
+use tokio_core::reactor::{Core, Handle, Interval};
 use tower::NewService;
 use tower_fn::*;
 use conduit_proxy_router::{Recognize, Router, Error as RouteError};
 
 pub mod app;
+mod backoff;
+mod balance;
 mod bind;
+mod body_limit;
+mod bound_endpoint;
+mod breaker;
+mod budget;
+mod cache;
+mod compress;
 pub mod config;
 mod connection;
 pub mod control;
 mod ctx;
+mod deadline;
 mod dns;
 mod drain;
+mod fail_fast;
+mod fault;
+mod graceful;
+mod grpc_timeout;
+mod header_limit;
+mod health;
+mod hedge;
 mod inbound;
 mod logging;
 mod map_err;
+mod mirror;
 mod outbound;
+mod outlier;
+mod pause;
+mod pin;
+mod prewarm;
+mod priority;
+mod rate_limit;
+mod replay;
+mod response_headers;
+mod retry;
+mod rewrite_host;
+mod route;
+mod split;
+mod sticky;
 mod telemetry;
+#[cfg(test)]
+mod test_support;
 mod transparency;
 mod transport;
 pub mod timeout;
 mod tower_fn; // TODO: move to tower-fn
 
-use bind::Bind;
+use backoff::BackoffConfig;
+use bind::{Bind, BindBuilder};
+use breaker::BreakerConfig;
+use budget::{Budget, BudgetConfig};
 use connection::BoundPort;
+use health::HealthCheckConfig;
 use inbound::Inbound;
+use grpc_timeout::GrpcTimeout;
 use map_err::MapErr;
+use mirror::Mirror;
+use outlier::OutlierConfig;
+use rate_limit::{Rate, RateLimitConfig};
+use replay::BufferPolicy;
+use retry::RetryPolicy;
+use route::RoutePolicies;
+use telemetry::classify::StatusClassifier;
+pub use telemetry::sensor::{RequestIdGen, SharedRequestIdGen};
 use transparency::{HttpBody, Server};
 pub use transport::{GetOriginalDst, SoOriginalDst};
+use transport::keepalive::KeepaliveConfig;
 use outbound::Outbound;
 
 /// Runs a sidecar proxy.
@@ -108,6 +162,8 @@ pub struct Main<G> {
 
     get_original_dst: G,
 
+    req_ids: SharedRequestIdGen,
+
     reactor: Core,
 }
 
@@ -120,9 +176,11 @@ where
         let control_listener = BoundPort::new(config.control_listener.addr)
             .expect("controller listener bind");
         let inbound_listener = BoundPort::new(config.public_listener.addr)
-            .expect("public listener bind");
+            .expect("public listener bind")
+            .with_socket_latency(socket_latency(&config));
         let outbound_listener = BoundPort::new(config.private_listener.addr)
-            .expect("private listener bind");
+            .expect("private listener bind")
+            .with_socket_latency(socket_latency(&config));
 
         let reactor = Core::new().expect("reactor");
 
@@ -135,10 +193,20 @@ where
             outbound_listener,
             metrics_listener,
             get_original_dst,
+            req_ids: Arc::new(telemetry::sensor::Sequential::default()),
             reactor,
         }
     }
 
+    /// Assigns IDs to proxied requests with `req_ids`, rather than numbering
+    /// them sequentially from when the proxy starts.
+    pub fn with_request_ids(self, req_ids: SharedRequestIdGen) -> Self {
+        Main {
+            req_ids,
+            ..self
+        }
+    }
+
 
     pub fn control_addr(&self) -> SocketAddr {
         self.control_listener.local_addr()
@@ -165,6 +233,7 @@ where
         F: Future<Item = (), Error = ()>,
     {
         let process_ctx = ctx::Process::new(&self.config);
+        let socket_latency = socket_latency(&self.config);
 
         let Main {
             config,
@@ -173,6 +242,7 @@ where
             outbound_listener,
             metrics_listener,
             get_original_dst,
+            req_ids,
             reactor: mut core,
         } = self;
 
@@ -198,38 +268,422 @@ where
             config.outbound_ports_disable_protocol_detection,
         );
 
+        let metrics_config = {
+            let metrics_config = telemetry::metrics::Config::new(config.metrics_max_authorities);
+            match config.metrics_latency_buckets {
+                Some(ref buckets) => metrics_config.with_latency_buckets_ms(buckets),
+                None => metrics_config,
+            }
+        };
         let (sensors, telemetry) = telemetry::new(
             &process_ctx,
             config.event_buffer_capacity,
+            metrics_config,
         );
+        let sensors = sensors.with_redacted_headers(config.redacted_headers.clone());
+        let sensors = match config.failure_status_codes {
+            Some(ref statuses) => {
+                let classifier = StatusClassifier::with_failure_statuses(statuses.clone());
+                sensors.with_classifier(Arc::new(classifier))
+            }
+            None => sensors,
+        };
+        let sensors = match config.request_id_header {
+            Some(ref header) => sensors.with_request_id_header(header.clone()),
+            None => sensors,
+        };
+        let sensors = match config.trace_propagation {
+            Some(propagation) => sensors.with_trace_propagation(propagation),
+            None => sensors,
+        };
+        let sensors = match config.decompress_responses {
+            Some(decompress) => sensors.with_decompression(decompress),
+            None => sensors,
+        };
+        let sensors = match config.decompress_max_bytes {
+            Some(max) => sensors.with_max_decompressed_bytes(max),
+            None => sensors,
+        };
+        if let Some(capacity) = config.lifecycle_event_log_capacity {
+            let log = sensors.subscribe(capacity).log();
+            core.handle().spawn(::logging::context_future("lifecycle", log));
+        }
+        if let Some(format) = config.access_log_format {
+            let log = telemetry::access_log::AccessLog::new(
+                sensors.subscribe(config.access_log_capacity),
+                format,
+            ).with_headers(config.access_log_headers.clone());
+            core.handle().spawn(::logging::context_future("access_log", log));
+        }
+        let telemetry = match config.recent_requests_capacity {
+            Some(capacity) if capacity > 0 => {
+                let recent = telemetry::RecentRequests::new(capacity);
+                if config.recent_requests_enabled {
+                    recent.enable();
+                }
+                let recorder = recent.recorder(sensors.subscribe(capacity));
+                core.handle().spawn(::logging::context_future("recent_requests", recorder));
+                telemetry.with_recent_requests(&recent)
+            }
+            _ => telemetry,
+        };
+        let discovery_state = control::DiscoveryState::new(sensors.in_flight());
+        for (authority, split) in config.traffic_splits.clone() {
+            discovery_state.splits().set(authority, split);
+        }
+        let traffic_splits = discovery_state.splits().clone();
+        let telemetry = telemetry.with_discovery_state(&discovery_state);
+        let statsd_addr = config.statsd_addr;
+        let statsd_prefix = config.statsd_prefix.clone();
+        let metrics_export_interval = config.metrics_export_interval;
 
-        let dns_config = dns::Config::from_file(&config.resolv_conf_path);
+        let dns_config = dns::Config::from_file(&config.resolv_conf_path)
+            .with_sensor(sensors.dns_lookups().clone());
 
         let (control, control_bg) = control::new(dns_config.clone(), config.pod_namespace.clone());
 
         let executor = core.handle();
         let (drain_tx, drain_rx) = drain::channel();
 
-        let bind = Bind::new(executor.clone()).with_sensors(sensors.clone());
+        let rate_limit = {
+            let rate = |rps| match config.rate_limit_burst {
+                Some(burst) => Rate::per_second(rps).with_burst(burst),
+                None => Rate::per_second(rps),
+            };
+            let rate_limit = RateLimitConfig::default();
+            let rate_limit = match config.global_rate_limit {
+                Some(rps) => rate_limit.with_global(rate(rps)),
+                None => rate_limit,
+            };
+            match config.authority_rate_limit {
+                Some(rps) => rate_limit.with_per_authority(rate(rps)),
+                None => rate_limit,
+            }
+        };
+        let h1_settings = transparency::H1Settings::default();
+        let h1_settings = match config.h1_max_idle_connections {
+            Some(max) => h1_settings.with_max_idle(max),
+            None => h1_settings,
+        };
+        let h1_settings = match config.h1_idle_timeout {
+            Some(timeout) => h1_settings.with_idle_timeout(timeout),
+            None => h1_settings,
+        };
+        let h1_settings = match config.h1_expect_continue_timeout {
+            Some(timeout) => h1_settings.with_expect_continue_timeout(timeout),
+            None => h1_settings,
+        };
+        let h2_settings = Ok(transparency::H2Settings::default())
+            .and_then(|s| match config.h2_initial_stream_window_size {
+                Some(size) => s.with_initial_stream_window_size(size),
+                None => Ok(s),
+            })
+            .and_then(|s| match config.h2_initial_connection_window_size {
+                Some(size) => s.with_initial_connection_window_size(size),
+                None => Ok(s),
+            })
+            .and_then(|s| match config.h2_max_concurrent_streams {
+                Some(max) => s.with_max_concurrent_streams(max),
+                None => Ok(s),
+            })
+            .expect("invalid HTTP/2 settings");
+        let keepalive = KeepaliveConfig::default();
+        let keepalive = match config.tcp_keepalive {
+            Some(idle) => keepalive.with_tcp(idle),
+            None => keepalive,
+        };
+        let keepalive = match config.h2_keepalive_interval {
+            Some(interval) => keepalive.with_ping(interval, config.h2_keepalive_timeout),
+            None => keepalive,
+        };
+        let keepalive = match config.h2_keepalive_idle_validation {
+            Some(idle) => keepalive.with_idle_validation(idle),
+            None => keepalive,
+        };
+
+        // Each direction's `Bind` is built from a clone of this builder, so
+        // that their options are validated together with the direction's
+        // connect timeout and policies.
+        let builder = BindBuilder::new(executor.clone())
+            .with_sensors(sensors.clone())
+            .with_buffer_capacity(config.buffer_capacity)
+            .with_drain_grace_period(config.endpoint_drain_grace_period)
+            .with_rate_limit(rate_limit)
+            .with_h1_settings(h1_settings)
+            .with_h2_settings(h2_settings)
+            .with_keepalive(keepalive);
+        let builder = match config.reconnect_backoff_min {
+            Some(min) => builder.with_backoff(BackoffConfig::new(
+                min,
+                config.reconnect_backoff_max,
+                backoff::DEFAULT_JITTER,
+            )),
+            None => builder,
+        };
+        let builder = match config.connection_idle_timeout {
+            Some(timeout) => builder.with_idle_timeout(timeout),
+            None => builder,
+        };
+        let builder = match config.request_timeout {
+            Some(timeout) => builder.with_request_timeout(timeout),
+            None => builder,
+        };
+        let max_connections = config.max_connections.map(|max| {
+            let max_connections = transport::MaxConnections::new(max);
+            match config.max_connections_wait_timeout {
+                Some(timeout) => max_connections.with_wait_timeout(timeout),
+                None => max_connections,
+            }
+        });
+        let socket_buffers = transport::SocketBuffers::default();
+        let socket_buffers = match config.socket_recv_buffer_bytes {
+            Some(size) => socket_buffers.with_recv_buffer_size(size),
+            None => socket_buffers,
+        };
+        let socket_buffers = match config.socket_send_buffer_bytes {
+            Some(size) => socket_buffers.with_send_buffer_size(size),
+            None => socket_buffers,
+        };
+
+        let outbound_ctx = ctx::Proxy::outbound(&process_ctx);
+        let inbound_ctx = ctx::Proxy::inbound(&process_ctx);
+        let (outbound_bind, inbound_bind) = {
+            // Configures the options that the builder doesn't, which are the
+            // same for both directions.
+            let configure = |bind: Bind<Arc<ctx::Proxy>, _>| {
+                let bind = bind
+                    .with_request_ids(req_ids.clone())
+                    .with_discovery_state(discovery_state.clone());
+                let bind = match config.inbound_redacted_headers {
+                    Some(ref names) => {
+                        let inbound = sensors.clone().with_redacted_headers(names.clone());
+                        bind.with_inbound_sensors(inbound)
+                    }
+                    None => bind,
+                };
+                let bind = match config.outbound_redacted_headers {
+                    Some(ref names) => {
+                        let outbound = sensors.clone().with_redacted_headers(names.clone());
+                        bind.with_outbound_sensors(outbound)
+                    }
+                    None => bind,
+                };
+                let bind = match config.endpoint_concurrency_limit {
+                    Some(limit) => bind.with_concurrency_limit(limit),
+                    None => bind,
+                };
+                let bind = match config.h1_max_connections {
+                    Some(max) => bind.with_http1_max_conns(max),
+                    None => bind,
+                };
+                let bind = match config.connect_concurrency {
+                    Some(max) => bind.with_connect_concurrency(max),
+                    None => bind,
+                };
+                let bind = match max_connections {
+                    Some(ref max_connections) => bind.with_max_connections(max_connections.clone()),
+                    None => bind,
+                };
+                let bind = bind
+                    .with_socket_buffers(socket_buffers)
+                    .with_socket_latency(socket_latency);
+                let bind = match config.max_response_bytes {
+                    Some(max) => bind.with_max_response_bytes(max),
+                    None => bind,
+                };
+                let bind = match config.max_header_bytes {
+                    Some(max) => bind.with_max_header_bytes(max),
+                    None => bind,
+                };
+                let bind = match config.max_header_line_bytes {
+                    Some(max) => bind.with_max_header_line_bytes(max),
+                    None => bind,
+                };
+                let bind = match config.priority_header {
+                    Some(ref header) => bind.with_priority_header(header.clone()),
+                    None => bind,
+                };
+                let bind = bind
+                    .with_gzip_responses(config.gzip_responses)
+                    .with_response_headers(config.response_headers.clone());
+                let bind = match config.breaker_failure_threshold {
+                    Some(threshold) => bind.with_circuit_breaker(BreakerConfig::new(
+                        threshold,
+                        config.breaker_open_timeout,
+                    )),
+                    None => bind,
+                };
+                let bind = match config.health_check_path {
+                    Some(ref path) => bind.with_health_check(
+                        HealthCheckConfig::new(path.clone(), config.health_check_interval)
+                            .with_expected_status(config.health_check_status)
+                            .with_thresholds(
+                                config.health_check_healthy_threshold,
+                                config.health_check_unhealthy_threshold,
+                            ),
+                    ),
+                    None => bind,
+                };
+                let bind = match config.outlier_consecutive_failures {
+                    Some(failures) => bind.with_outlier_detection(
+                        OutlierConfig::new(failures, config.outlier_base_ejection_time)
+                            .with_max_ejection_time(config.outlier_max_ejection_time),
+                    ),
+                    None => bind,
+                };
+                let bind = match config.max_retries {
+                    Some(max_retries) => {
+                        let policy = config.retry_methods.iter()
+                            .fold(RetryPolicy::new(max_retries), |policy, method| {
+                                policy.with_method(method.clone())
+                            })
+                            .with_budget(
+                                config.retry_budget_percent,
+                                config.retry_budget_min_per_second,
+                            );
+                        bind.with_retries(policy)
+                    },
+                    None => bind,
+                };
+                match config.retry_buffer_max_bytes {
+                    Some(max_bytes) => bind.with_buffer_policy(BufferPolicy::new(max_bytes)),
+                    None => bind,
+                }
+            };
+
+            let outbound_bind = {
+                let bind = builder.clone()
+                    .with_ctx(outbound_ctx.clone())
+                    .with_connect_timeout(config.public_connect_timeout)
+                    .with_missing_host_policy(config.outbound_missing_host_policy)
+                    .with_protocol_policy(config.outbound_protocol_policy)
+                    .build()
+                    .expect("invalid outbound proxy configuration");
+                let bind = configure(bind);
+                let bind = match config.outbound_response_cache_max_bytes {
+                    Some(max_bytes) => bind.with_response_cache(max_bytes),
+                    None => bind,
+                };
+                let bind = match config.outbound_connect_proxy_addr {
+                    Some(addr) => {
+                        let authorization = config.outbound_connect_proxy_authorization.clone();
+                        let proxy = transport::ConnectProxy::new(addr)
+                            .with_authorization(authorization);
+                        bind.with_connect_proxy(proxy)
+                    },
+                    None => bind,
+                };
+                let bind = match config.tls_trust_anchors {
+                    Some(ref path) => {
+                        let tls = transport::tls::ClientConfig::load_trust_anchors(path)
+                            .expect("load TLS trust anchors");
+                        bind.with_tls(tls)
+                    },
+                    None => bind,
+                };
+                match config.tls_client_identity {
+                    Some((ref cert_path, ref key_path)) => {
+                        let identity = transport::tls::ClientIdentity::load(cert_path, key_path)
+                            .expect("load TLS client identity");
+                        let reload = identity.clone();
+                        let interval = config.tls_client_identity_reload_interval;
+                        let reload = Interval::new(interval, &executor)
+                            .expect("TLS client identity reload interval")
+                            .for_each(move |()| {
+                                if let Err(e) = reload.reload() {
+                                    warn!("could not reload TLS client identity: {}", e);
+                                }
+                                Ok(())
+                            })
+                            .map_err(|e| error!("TLS client identity reload failed: {}", e));
+                        executor.spawn(::logging::context_future("tls_client_identity", reload));
+                        bind.with_client_identity(identity)
+                    },
+                    None => bind,
+                }
+            };
+
+            let inbound_bind = {
+                let bind = builder
+                    .with_ctx(inbound_ctx.clone())
+                    .with_connect_timeout(config.private_connect_timeout)
+                    .with_protocol_policy(config.inbound_protocol_policy)
+                    .build()
+                    .expect("invalid inbound proxy configuration");
+                let bind = configure(bind);
+                match config.inbound_max_request_bytes {
+                    Some(max) => bind.with_max_request_bytes(max),
+                    None => bind,
+                }
+            };
+
+            (outbound_bind, inbound_bind)
+        };
+        let peak_ewma = match config.outbound_load_balancer {
+            config::LoadBalancer::PeakEwma => Some(balance::PeakEwmaConfig::new(
+                config.peak_ewma_default_rtt,
+                config.peak_ewma_decay,
+            )),
+            config::LoadBalancer::WeightedRandom => None,
+        };
+        let request_budget = BudgetConfig::default();
+        let request_budget = match config.request_budget_max_duration {
+            Some(max) => request_budget.with_max_duration(max),
+            None => request_budget,
+        };
+        let request_budget = match config.request_budget_max_attempts {
+            Some(max) => request_budget.with_max_attempts(max),
+            None => request_budget,
+        };
+        let request_budget = match config.request_budget_max_buffered_bytes {
+            Some(max) => request_budget.with_max_buffered_bytes(max),
+            None => request_budget,
+        };
 
         // Setup the public listener. This will listen on a publicly accessible
         // address and listen for inbound connections that should be forwarded
         // to the managed application (private destination).
         let inbound = {
-            let ctx = ctx::Proxy::inbound(&process_ctx);
+            let ctx = inbound_ctx;
 
-            let bind = bind.clone().with_ctx(ctx.clone());
+            if config.inbound_protocol_policy == bind::ProtocolPolicy::ForceHttp2 {
+                // There's no way to know whether the application speaks
+                // HTTP/2 until its connections fail.
+                warn!("inbound requests are forced to HTTP/2; \
+                       the application must accept HTTP/2 without TLS");
+            }
 
             let default_addr = config.private_forward.map(|a| a.into());
 
+            // Mirrored requests are routed by their new authority, like
+            // outbound requests, but never to their original destinations.
+            let shadow = config.inbound_mirror_authority.as_ref().map(|authority| {
+                let outbound = Outbound::new(
+                    outbound_bind.clone(),
+                    control.clone(),
+                    config.bind_timeout,
+                    peak_ewma,
+                );
+                mirror::Shadow::new(
+                    Router::new(outbound),
+                    authority.clone(),
+                    config.inbound_mirror_percent,
+                    BufferPolicy::new(config.inbound_mirror_max_body_bytes),
+                    &executor,
+                )
+            });
+
             let fut = serve(
                 inbound_listener,
-                Inbound::new(default_addr, bind),
+                Inbound::new(default_addr, inbound_bind),
                 config.private_connect_timeout,
                 config.inbound_ports_disable_protocol_detection,
                 ctx,
                 sensors.clone(),
                 get_original_dst.clone(),
+                shadow,
+                config.grpc_deadlines,
+                request_budget,
                 drain_rx.clone(),
                 &executor,
             );
@@ -240,9 +694,60 @@ where
         // address and listen for outbound requests that should be routed
         // to a remote service (public destination).
         let outbound = {
-            let ctx = ctx::Proxy::outbound(&process_ctx);
-            let bind = bind.clone().with_ctx(ctx.clone());
-            let outgoing = Outbound::new(bind, control, config.bind_timeout);
+            let ctx = outbound_ctx;
+            let outgoing = Outbound::new(outbound_bind, control, config.bind_timeout, peak_ewma);
+            let outgoing = if config.outbound_orig_dst_fallback {
+                outgoing.with_orig_dst_fallback()
+            } else {
+                outgoing
+            };
+            let outgoing = outgoing.with_h2c_upstreams(config.outbound_h2c_upstreams);
+            let routes = config.route_policies.into_iter()
+                .fold(RoutePolicies::new(config.pod_namespace.clone()), |routes, (dst, policy)| {
+                    routes.with_route(dst, policy)
+                });
+            let outgoing = outgoing
+                .with_route_policies(routes)
+                .with_traffic_splits(traffic_splits);
+            let outgoing = match config.outbound_slow_start_window {
+                Some(window) => outgoing.with_slow_start(balance::SlowStartConfig::new(window)),
+                None => outgoing,
+            };
+            let outgoing = match config.hedge_delay {
+                Some(delay) => outgoing.with_hedge_delay(delay),
+                None => outgoing,
+            };
+            let outgoing = match config.fail_fast_grace_period {
+                Some(grace_period) => outgoing.with_fail_fast(grace_period),
+                None => outgoing,
+            };
+            let outgoing = match config.route_tag_header {
+                Some(header) => outgoing.with_route_tag_header(header),
+                None => outgoing,
+            };
+            let affinity_key = match config.outbound_affinity_header {
+                Some(header) => Some(sticky::AffinityKey::Header(header)),
+                None => config.outbound_affinity_cookie.map(sticky::AffinityKey::Cookie),
+            };
+            let outgoing = match affinity_key {
+                Some(key) => outgoing.with_affinity_key(key),
+                None => outgoing,
+            };
+            let outgoing = match config.outbound_unhealthy_pins {
+                Some(unhealthy_pins) => outgoing.with_unhealthy_pins(unhealthy_pins),
+                None => outgoing,
+            };
+            let outgoing = match config.zone {
+                Some(zone) => {
+                    let locality = balance::LocalityConfig::new(zone);
+                    let locality = match config.zone_min_local_endpoints {
+                        Some(min) => locality.with_min_local_endpoints(min),
+                        None => locality,
+                    };
+                    outgoing.with_locality(locality)
+                }
+                None => outgoing,
+            };
             let fut = serve(
                 outbound_listener,
                 outgoing,
@@ -251,6 +756,9 @@ where
                 ctx,
                 sensors,
                 get_original_dst,
+                None,
+                config.grpc_deadlines,
+                request_budget,
                 drain_rx,
                 &executor,
             );
@@ -285,6 +793,19 @@ where
                     let metrics_server = telemetry
                         .serve_metrics(metrics_listener);
 
+                    if let Some(addr) = statsd_addr {
+                        let export = telemetry::export::Statsd::new(addr, &statsd_prefix)
+                            .and_then(|statsd| {
+                                telemetry.export_metrics(Box::new(statsd), metrics_export_interval)
+                            });
+                        match export {
+                            Ok(export) => executor.spawn(
+                                ::logging::context_future("export_metrics", export)
+                            ),
+                            Err(e) => error!("failed to export metrics to {}: {}", addr, e),
+                        }
+                    }
+
                     let client = control_bg.bind(
                         control_host_and_port,
                         dns_config,
@@ -310,15 +831,40 @@ where
             .map_err(|err| error!("main error: {:?}", err));
 
         core.handle().spawn(fut);
+        let shutdown_grace_period = config.shutdown_grace_period;
+        let shutdown_handle = core.handle();
         let shutdown_signal = shutdown_signal.and_then(move |()| {
             debug!("shutdown signaled");
-            drain_tx.drain()
+            let drained = drain_tx.drain();
+            match shutdown_grace_period {
+                // Connections that are still open when the grace period
+                // expires are closed when the reactor is dropped.
+                Some(grace_period) => future::Either::A(
+                    drained.within(grace_period, &shutdown_handle).map(|completion| {
+                        if completion == drain::Completion::Expired {
+                            warn!("shutdown grace period expired; closing open connections");
+                        }
+                    })
+                ),
+                None => future::Either::B(drained),
+            }
         });
         core.run(shutdown_signal).expect("executor");
         debug!("shutdown complete");
     }
 }
 
+/// Mirrors inbound requests to a shadow destination.
+type Shadow = mirror::Shadow<Router<Outbound<HttpBody>>>;
+
+/// The socket options for latency that apply to proxied connections, both
+/// accepted and client.
+fn socket_latency(config: &config::Config) -> transport::SocketLatency {
+    transport::SocketLatency::default()
+        .with_nodelay(config.tcp_nodelay)
+        .with_quickack(config.tcp_quickack)
+}
+
 fn serve<R, B, E, F, G>(
     bound_port: BoundPort,
     recognize: R,
@@ -327,6 +873,9 @@ fn serve<R, B, E, F, G>(
     proxy_ctx: Arc<ctx::Proxy>,
     sensors: telemetry::Sensors,
     get_orig_dst: G,
+    shadow: Option<Shadow>,
+    grpc_deadlines: bool,
+    request_budget: BudgetConfig,
     drain_rx: drain::Watch,
     executor: &Handle,
 ) -> Box<Future<Item = (), Error = io::Error> + 'static>
@@ -336,7 +885,7 @@ where
     F: Error + 'static,
     R: Recognize<
         Request = http::Request<HttpBody>,
-        Response = http::Response<telemetry::sensor::http::ResponseBody<B>>,
+        Response = http::Response<B>,
         Error = E,
         RouteError = F,
     >
@@ -344,6 +893,8 @@ where
     G: GetOriginalDst + 'static,
 {
     let router = Router::new(recognize);
+    let request_attempts = sensors.request_attempts().clone();
+    let handle = executor.clone();
     let stack = Arc::new(NewServiceFn::new(move || {
         // Clone the router handle
         let router = router.clone();
@@ -359,17 +910,37 @@ where
                     error!("turning {} into 500", i);
                     http::StatusCode::INTERNAL_SERVER_ERROR
                 }
+                // Requests are only unrecognized when neither the request
+                // nor its connection identifies a destination.
                 RouteError::NotRecognized => {
-                    error!("turning route not recognized error into 500");
-                    http::StatusCode::INTERNAL_SERVER_ERROR
+                    error!("turning route not recognized error into 400");
+                    http::StatusCode::BAD_REQUEST
                 }
             }
         });
 
+        // Mirror requests beneath the request open timestamp, so that
+        // mirrored requests are timed from when the original was received.
+        let mirror = Mirror::new(map_err, shadow.clone());
+
+        // Read the deadlines of gRPC requests, if enabled, beneath the
+        // request open timestamp, from which they're measured, and above
+        // everything that would dispatch a request that's already late.
+        let grpc_timeout = GrpcTimeout::new(mirror, grpc_deadlines, &handle);
+
+        // Give each request a budget, if any of its limits are configured,
+        // which every layer beneath that spends time, attempts, or buffered
+        // bytes on the request spends from. It's also measured from the
+        // request open timestamp.
+        let budget = Budget::new(grpc_timeout, request_budget, &handle);
+
         // Install the request open timestamp module at the very top
         // of the stack, in order to take the timestamp as close as
-        // possible to the beginning of the request's lifetime.
-        telemetry::sensor::http::TimestampRequestOpen::new(map_err)
+        // possible to the beginning of the request's lifetime. Attempts
+        // are counted from here, so that retries beneath the buffer and
+        // reconnect layers are counted too.
+        telemetry::sensor::http::TimestampRequestOpen::new(budget)
+            .with_attempts(request_attempts.clone())
     }));
 
     let listen_addr = bound_port.local_addr();
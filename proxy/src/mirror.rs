@@ -0,0 +1,490 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use futures::{future, Async, Future, Poll};
+use futures::future::Either;
+use h2;
+use http;
+use http::header::{CONTENT_LENGTH, HOST};
+use tokio_core::reactor::Handle;
+use tower::Service;
+use tower_h2::Body;
+
+use budget::RequestBudget;
+use replay::{BufferBody, BufferPolicy, FromBuffered, ReplayBody};
+use retry::Replay;
+use transparency::HttpBody;
+
+/// Mirrors a sample of requests to a shadow service.
+///
+/// A mirrored request's body is read into memory, according to the shadow's
+/// `BufferPolicy`, before the request is sent both to the inner service and,
+/// with its authority replaced by the shadow's, to the shadow service. The
+/// buffered body is recorded in the request, so that the inner service may
+/// replay it without buffering it again. Only the inner service's response is
+/// returned; the shadow's response is discarded, and its failures are
+/// ignored.
+///
+/// Requests that are not mirrored are streamed to the inner service as they
+/// are received. If constructed without a `Shadow`, this is a no-op.
+pub struct Mirror<S, M> {
+    inner: Rc<RefCell<S>>,
+    shadow: Option<Shadow<M>>,
+}
+
+/// Where, and how many, requests are mirrored.
+pub struct Shadow<M> {
+    service: Rc<RefCell<M>>,
+    authority: http::uri::Authority,
+    sample: Sample,
+    buffer: BufferPolicy,
+    handle: Handle,
+}
+
+/// Chooses `percent` of the requests it is asked about.
+///
+/// Requests are chosen at evenly spaced intervals, rather than at random, so
+/// that exactly `percent` of requests are chosen, e.g. to be mirrored.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    percent: u32,
+    credit: Rc<Cell<u32>>,
+}
+
+pub struct ResponseFuture<S: Service, M> {
+    service: Rc<RefCell<S>>,
+    state: State<S::Future, M>,
+}
+
+enum State<F, M> {
+    /// Reading the body of a request that is to be mirrored.
+    Buffering(Buffering<M>),
+    /// Waiting for the inner service to become ready for a mirrored request.
+    Dispatching(Option<http::Request<HttpBody>>),
+    /// Waiting for the inner service's response.
+    Pending(F),
+}
+
+struct Buffering<M> {
+    parts: Option<http::request::Parts>,
+    body: BufferBody<HttpBody>,
+    shadow: Shadow<M>,
+}
+
+/// Reads a shadow response's body to its end, so that its connection may be
+/// reused.
+struct Drain<B> {
+    body: B,
+    data_ended: bool,
+}
+
+// ===== impl Mirror =====
+
+impl<S, M> Mirror<S, M> {
+    pub fn new(inner: S, shadow: Option<Shadow<M>>) -> Self {
+        Mirror {
+            inner: Rc::new(RefCell::new(inner)),
+            shadow,
+        }
+    }
+}
+
+impl<S, B, M, C> Service for Mirror<S, M>
+where
+    S: Service<
+        Request = http::Request<HttpBody>,
+        Response = http::Response<B>,
+        Error = h2::Error,
+    >,
+    M: Service<
+        Request = http::Request<HttpBody>,
+        Response = http::Response<C>,
+    > + 'static,
+    M::Future: 'static,
+    C: Body + 'static,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, M>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.borrow_mut().poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let state = match self.shadow {
+            Some(ref shadow) if shadow.mirrors(&req) => {
+                let budget = RequestBudget::of(&req).cloned();
+                let (parts, body) = req.into_parts();
+                State::Buffering(Buffering {
+                    parts: Some(parts),
+                    body: shadow.buffer.buffer(body).with_budget(budget),
+                    shadow: shadow.clone(),
+                })
+            }
+            _ => State::Pending(self.inner.borrow_mut().call(req)),
+        };
+        ResponseFuture {
+            service: self.inner.clone(),
+            state,
+        }
+    }
+}
+
+// ===== impl Shadow =====
+
+impl<M> Shadow<M> {
+    /// Mirrors `percent` of requests to `authority` through `service`.
+    ///
+    /// Only requests whose bodies may be buffered according to `buffer` are
+    /// mirrored. Nor are requests with bodies of unknown length, since one
+    /// that turned out to be too long to buffer could no longer be sent to
+    /// the inner service.
+    pub fn new(
+        service: M,
+        authority: http::uri::Authority,
+        percent: u32,
+        buffer: BufferPolicy,
+        handle: &Handle,
+    ) -> Self {
+        Shadow {
+            service: Rc::new(RefCell::new(service)),
+            authority,
+            sample: Sample::new(percent),
+            buffer,
+            handle: handle.clone(),
+        }
+    }
+
+    fn mirrors(&self, req: &http::Request<HttpBody>) -> bool {
+        let buffers = req.body().is_end_stream() ||
+            (req.headers().contains_key(CONTENT_LENGTH) && self.buffer.may_buffer(req.headers()));
+        buffers && self.sample.choose()
+    }
+}
+
+impl<M, C> Shadow<M>
+where
+    M: Service<
+        Request = http::Request<HttpBody>,
+        Response = http::Response<C>,
+    > + 'static,
+    M::Future: 'static,
+    C: Body + 'static,
+{
+    /// Sends `req` to the shadow service, in the background.
+    fn send(&self, mut req: http::Request<HttpBody>) {
+        set_authority(&mut req, &self.authority);
+
+        let mut service = self.service.borrow_mut();
+        match service.poll_ready() {
+            Ok(Async::Ready(())) => {}
+            Ok(Async::NotReady) | Err(_) => {
+                debug!("shadow service is not ready; request is not mirrored");
+                return;
+            }
+        }
+
+        let mirrored = service.call(req).then(|rsp| match rsp {
+            Ok(rsp) => Either::A(Drain {
+                body: rsp.into_body(),
+                data_ended: false,
+            }),
+            Err(_) => {
+                debug!("mirrored request failed");
+                Either::B(future::ok(()))
+            }
+        });
+        self.handle.spawn(mirrored);
+    }
+}
+
+impl<M> Clone for Shadow<M> {
+    fn clone(&self) -> Self {
+        Shadow {
+            service: self.service.clone(),
+            authority: self.authority.clone(),
+            sample: self.sample.clone(),
+            buffer: self.buffer,
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+/// Replaces the authority of `req`'s URI, if it has one, and its `Host`
+/// header, if it has one or is not an HTTP/2 request.
+fn set_authority<B>(req: &mut http::Request<B>, authority: &http::uri::Authority) {
+    if req.uri().authority_part().is_some() {
+        let mut parts = req.uri().clone().into_parts();
+        parts.authority = Some(authority.clone());
+        *req.uri_mut() = http::Uri::from_parts(parts).expect("a valid URI with a new authority");
+    }
+    if req.version() != http::Version::HTTP_2 || req.headers().contains_key(HOST) {
+        let host = http::header::HeaderValue::from_str(authority.as_str())
+            .expect("an authority is a valid header value");
+        req.headers_mut().insert(HOST, host);
+    }
+}
+
+// ===== impl Sample =====
+
+impl Sample {
+    pub fn new(percent: u32) -> Self {
+        Sample {
+            percent,
+            credit: Rc::new(Cell::new(0)),
+        }
+    }
+
+    pub fn choose(&self) -> bool {
+        let credit = self.credit.get() + self.percent;
+        if credit >= 100 {
+            self.credit.set(credit - 100);
+            true
+        } else {
+            self.credit.set(credit);
+            false
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, B, M, C> Future for ResponseFuture<S, M>
+where
+    S: Service<
+        Request = http::Request<HttpBody>,
+        Response = http::Response<B>,
+        Error = h2::Error,
+    >,
+    M: Service<
+        Request = http::Request<HttpBody>,
+        Response = http::Response<C>,
+    > + 'static,
+    M::Future: 'static,
+    C: Body + 'static,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Pending(ref mut future) => return future.poll(),
+                State::Buffering(ref mut buffering) => {
+                    let req = try_ready!(buffering.poll());
+                    State::Dispatching(Some(req))
+                }
+                State::Dispatching(ref mut req) => {
+                    try_ready!(self.service.borrow_mut().poll_ready());
+                    let req = req.take().expect("request dispatched more than once");
+                    State::Pending(self.service.borrow_mut().call(req))
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+// ===== impl Buffering =====
+
+impl<M, C> Buffering<M>
+where
+    M: Service<
+        Request = http::Request<HttpBody>,
+        Response = http::Response<C>,
+    > + 'static,
+    M::Future: 'static,
+    C: Body + 'static,
+{
+    /// Reads the request's body, and once it has been read, sends the request
+    /// to the shadow and returns it to be sent to the inner service.
+    fn poll(&mut self) -> Poll<http::Request<HttpBody>, h2::Error> {
+        let buffered = match try_ready!(self.body.poll()) {
+            ReplayBody::Buffered(buffered) => buffered,
+            // A body can't exceed the `Content-Length` it was checked
+            // against without failing.
+            _ => {
+                debug!("mirrored request body exceeded its length");
+                return Err(h2::Reason::PROTOCOL_ERROR.into());
+            }
+        };
+
+        let mut parts = self.parts.take().expect("polled after ready");
+        parts.extensions.insert(buffered.clone());
+        let req = http::Request::from_parts(parts, HttpBody::from_buffered(buffered));
+        self.shadow.send(Replay::new(&req).request());
+
+        Ok(Async::Ready(req))
+    }
+}
+
+// ===== impl Drain =====
+
+impl<B: Body> Future for Drain<B> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        while !self.data_ended {
+            match self.body.poll_data() {
+                Ok(Async::Ready(Some(_))) => {}
+                Ok(Async::Ready(None)) => self.data_ended = true,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(_) => return Ok(Async::Ready(())),
+            }
+        }
+        match self.body.poll_trailers() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            _ => Ok(Async::Ready(())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use tokio_core::reactor::Core;
+
+    use super::*;
+
+    /// Records the URI, `Host` header, and body of each request it receives.
+    #[derive(Clone, Default)]
+    struct Records(Rc<RefCell<Vec<(String, Option<String>, Bytes)>>>);
+
+    /// Responds to each request with `200 OK`, or with an error if `fails`.
+    struct Upstream {
+        records: Records,
+        fails: bool,
+    }
+
+    impl Service for Upstream {
+        type Request = http::Request<HttpBody>;
+        type Response = http::Response<HttpBody>;
+        type Error = h2::Error;
+        type Future = Box<Future<Item = Self::Response, Error = h2::Error>>;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            let uri = req.uri().to_string();
+            let host = req.headers().get(HOST).map(|h| h.to_str().unwrap().to_owned());
+            let mut body = req.into_body();
+            let mut data = BytesMut::new();
+            while let Async::Ready(Some(chunk)) = body.poll_data().unwrap() {
+                data.extend_from_slice(&chunk);
+            }
+            self.records.0.borrow_mut().push((uri, host, data.freeze()));
+
+            if self.fails {
+                let err: h2::Error = h2::Reason::INTERNAL_ERROR.into();
+                return Box::new(future::err(err));
+            }
+            Box::new(future::ok(http::Response::new(HttpBody::default())))
+        }
+    }
+
+    fn request(body: &'static str) -> http::Request<HttpBody> {
+        let data = if body.is_empty() { None } else { Some(Bytes::from(body)) };
+        let mut req = http::Request::new(HttpBody::Buffered(data, None));
+        *req.uri_mut() = "/path".parse().unwrap();
+        req.headers_mut().insert(HOST, "primary.test".parse().unwrap());
+        req.headers_mut().insert(CONTENT_LENGTH, body.len().into());
+        req
+    }
+
+    fn mirror(
+        core: &Core,
+        percent: u32,
+        shadow_fails: bool,
+    ) -> (Mirror<Upstream, Upstream>, Records, Records) {
+        let primary = Records::default();
+        let shadowed = Records::default();
+        let shadow = Shadow::new(
+            Upstream { records: shadowed.clone(), fails: shadow_fails },
+            "shadow.test:8080".parse().unwrap(),
+            percent,
+            BufferPolicy::new(10),
+            &core.handle(),
+        );
+        let svc = Mirror::new(Upstream { records: primary.clone(), fails: false }, Some(shadow));
+        (svc, primary, shadowed)
+    }
+
+    fn send(core: &mut Core, svc: &mut Mirror<Upstream, Upstream>, req: http::Request<HttpBody>)
+        -> Result<http::Response<HttpBody>, h2::Error>
+    {
+        core.run(future::lazy(|| {
+            assert!(svc.poll_ready().expect("poll_ready").is_ready());
+            svc.call(req)
+        }))
+    }
+
+    #[test]
+    fn mirrors_requests_to_the_shadow_authority() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, primary, shadowed) = mirror(&core, 100, false);
+
+        let rsp = send(&mut core, &mut svc, request("hello")).expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+
+        let expected = |host: &str| vec![
+            ("/path".to_owned(), Some(host.to_owned()), Bytes::from("hello")),
+        ];
+        assert_eq!(*primary.0.borrow(), expected("primary.test"));
+        assert_eq!(*shadowed.0.borrow(), expected("shadow.test:8080"));
+    }
+
+    #[test]
+    fn mirrors_the_configured_percent_of_requests() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, primary, shadowed) = mirror(&core, 25, false);
+
+        for _ in 0..8 {
+            send(&mut core, &mut svc, request("hello")).expect("response");
+        }
+        assert_eq!(primary.0.borrow().len(), 8);
+        assert_eq!(shadowed.0.borrow().len(), 2);
+    }
+
+    #[test]
+    fn shadow_failures_do_not_affect_responses() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, primary, shadowed) = mirror(&core, 100, true);
+
+        let rsp = send(&mut core, &mut svc, request("")).expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(primary.0.borrow().len(), 1);
+        assert_eq!(shadowed.0.borrow().len(), 1);
+    }
+
+    #[test]
+    fn does_not_mirror_requests_with_long_or_unknown_bodies() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, primary, shadowed) = mirror(&core, 100, false);
+
+        send(&mut core, &mut svc, request("longer than ten bytes")).expect("response");
+        let mut chunked = request("hello");
+        chunked.headers_mut().remove(CONTENT_LENGTH);
+        send(&mut core, &mut svc, chunked).expect("response");
+
+        assert_eq!(primary.0.borrow().len(), 2);
+        assert!(shadowed.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn does_not_mirror_requests_that_expect_continue() {
+        let mut core = Core::new().unwrap();
+        let (mut svc, primary, shadowed) = mirror(&core, 100, false);
+
+        let mut req = request("hello");
+        req.headers_mut().insert(http::header::EXPECT, "100-continue".parse().unwrap());
+        send(&mut core, &mut svc, req).expect("response");
+
+        assert_eq!(primary.0.borrow().len(), 1);
+        assert!(shadowed.0.borrow().is_empty());
+    }
+}
@@ -1,4 +1,5 @@
 use std::{error, fmt};
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::time::Duration;
 use std::sync::Arc;
@@ -7,17 +8,36 @@ use http;
 use futures::{Async, Poll};
 use rand;
 use tower;
-use tower_balance::{self, choose, load, Balance};
+use tower_balance::Balance;
 use tower_buffer::Buffer;
 use tower_discover::{Change, Discover};
-use tower_in_flight_limit::InFlightLimit;
 use tower_h2;
 use conduit_proxy_router::{Reuse, Recognize};
 
+use balance::{
+    Chooser,
+    Choices,
+    LocalityConfig,
+    NewSelector,
+    PeakEwmaConfig,
+    SlowStartConfig,
+    Weight,
+    Weighted,
+    WithPeakEwma,
+    WithSlowStart,
+};
 use bind::{self, Bind, Protocol};
 use control::{self, discovery};
 use control::discovery::Bind as BindTrait;
 use ctx;
+use fail_fast::{CountEndpoints, Endpoints, FailFast};
+use hedge::Hedge;
+use pin::{Pin, Pins, UnhealthyPins};
+use priority::PriorityLimit;
+use replay::FromBuffered;
+use route::RoutePolicies;
+use split::TrafficSplits;
+use sticky::{AffinityKey, Sticky};
 use telemetry::metrics;
 use timeout::Timeout;
 use transparency::h1;
@@ -29,47 +49,261 @@ pub struct Outbound<B> {
     bind: Bind<Arc<ctx::Proxy>, B>,
     discovery: control::Control,
     bind_timeout: Duration,
+    /// If set, requests are balanced by peak-EWMA load rather than by weight
+    /// alone.
+    peak_ewma: Option<PeakEwmaConfig>,
+    /// If set, endpoints are given a reduced share of requests as they are
+    /// added.
+    slow_start: Option<SlowStartConfig>,
+    /// If true, requests for destinations that discovery has no endpoints for
+    /// are sent to their original destinations.
+    orig_dst_fallback: bool,
+    h2c_upstreams: H2cUpstreams,
+    /// If set, retry-safe requests that have not been answered within this
+    /// delay are also sent to another endpoint.
+    hedge_delay: Option<Duration>,
+    /// If set, requests fail once discovery has had no endpoints for their
+    /// destination for this long.
+    fail_fast: Option<Duration>,
+    /// Overrides the `Bind`'s settings for particular destinations.
+    routes: Option<RoutePolicies>,
+    /// If set, requests with this header are sent only to the endpoints
+    /// tagged with its value, if there are any.
+    route_tag_header: Option<http::header::HeaderName>,
+    /// If set, requests with this key are sent to the same endpoint as other
+    /// requests with the same value, while it is available.
+    affinity_key: Option<AffinityKey>,
+    /// If set, requests routed through discovery prefer the endpoints in
+    /// the proxy's zone.
+    locality: Option<LocalityConfig>,
+    /// If set, creates the `Selector` with which each balancer chooses
+    /// endpoints.
+    selector: Option<NewSelector>,
+    /// If set, requests for the authorities that are split are routed to
+    /// one of their backends instead.
+    splits: Option<TrafficSplits>,
+    /// Whether requests pinned to endpoints that aren't healthy are sent
+    /// anyway.
+    unhealthy_pins: UnhealthyPins,
 }
 
-const MAX_IN_FLIGHT: usize = 10_000;
+/// Upstreams that speak HTTP/2 without TLS ("h2c") with prior knowledge.
+///
+/// Names match requests routed through service discovery, and addresses
+/// match requests routed to their original destinations.
+#[derive(Clone, Debug, Default)]
+struct H2cUpstreams {
+    names: HashSet<DnsNameAndPort>,
+    addrs: HashSet<SocketAddr>,
+}
 
 // ===== impl Outbound =====
 
 impl<B> Outbound<B> {
     pub fn new(bind: Bind<Arc<ctx::Proxy>, B>,
                discovery: control::Control,
-               bind_timeout: Duration,)
+               bind_timeout: Duration,
+               peak_ewma: Option<PeakEwmaConfig>)
                -> Outbound<B> {
         Self {
             bind,
             discovery,
             bind_timeout,
+            peak_ewma,
+            slow_start: None,
+            orig_dst_fallback: false,
+            h2c_upstreams: H2cUpstreams::default(),
+            hedge_delay: None,
+            fail_fast: None,
+            routes: None,
+            route_tag_header: None,
+            affinity_key: None,
+            locality: None,
+            selector: None,
+            splits: None,
+            unhealthy_pins: UnhealthyPins::default(),
+        }
+    }
+
+    /// Connects directly to a request's original destination while discovery
+    /// has no endpoints for the request's authority.
+    pub fn with_orig_dst_fallback(self) -> Self {
+        Self {
+            orig_dst_fallback: true,
+            ..self
+        }
+    }
+
+    /// Sends HTTP/1 requests to `upstreams` as HTTP/2, without TLS.
+    ///
+    /// Requests asking to upgrade their connections are still sent over
+    /// HTTP/1.
+    pub fn with_h2c_upstreams<I>(self, upstreams: I) -> Self
+    where
+        I: IntoIterator<Item = HostAndPort>,
+    {
+        let mut h2c_upstreams = self.h2c_upstreams;
+        for HostAndPort { host, port } in upstreams {
+            match host {
+                Host::DnsName(host) => {
+                    h2c_upstreams.names.insert(DnsNameAndPort { host, port });
+                }
+                Host::Ip(ip) => {
+                    h2c_upstreams.addrs.insert(SocketAddr::new(ip, port));
+                }
+            }
+        }
+        Self {
+            h2c_upstreams,
+            ..self
+        }
+    }
+
+    /// Ramps up the share of requests that each endpoint is sent, as it is
+    /// added, according to `slow_start`.
+    pub fn with_slow_start(self, slow_start: SlowStartConfig) -> Self {
+        Self {
+            slow_start: Some(slow_start),
+            ..self
+        }
+    }
+
+    /// Hedges retry-safe requests that have not been answered within `delay`.
+    ///
+    /// The first response received is used, and the other request is
+    /// canceled.
+    pub fn with_hedge_delay(self, delay: Duration) -> Self {
+        Self {
+            hedge_delay: Some(delay),
+            ..self
+        }
+    }
+
+    /// Fails requests once discovery has had no endpoints for their
+    /// destination for `grace_period`, rather than waiting for an endpoint
+    /// until they time out.
+    pub fn with_fail_fast(self, grace_period: Duration) -> Self {
+        Self {
+            fail_fast: Some(grace_period),
+            ..self
+        }
+    }
+
+    /// Binds services for the destinations in `routes` with their own
+    /// timeouts, retries, and concurrency limits.
+    ///
+    /// Requests routed to their original destinations, rather than by
+    /// authority, always use the defaults.
+    pub fn with_route_policies(self, routes: RoutePolicies) -> Self {
+        Self {
+            routes: Some(routes),
+            ..self
+        }
+    }
+
+    /// Sends requests with a `header` only to the endpoints that discovery
+    /// has tagged with the header's value.
+    ///
+    /// Requests are balanced over all of their destination's endpoints if
+    /// none has the tag. Requests routed to their original destinations are
+    /// never restricted.
+    pub fn with_route_tag_header(self, header: http::header::HeaderName) -> Self {
+        Self {
+            route_tag_header: Some(header),
+            ..self
+        }
+    }
+
+    /// Sends requests with the same value for `key`, such as a session
+    /// cookie, to the same endpoint.
+    ///
+    /// The value is hashed to choose among the destination's endpoints, so
+    /// when its endpoint is removed or becomes unavailable, a request is sent
+    /// to another endpoint that the value then sticks to. Requests without
+    /// the key are balanced as usual.
+    pub fn with_affinity_key(self, key: AffinityKey) -> Self {
+        Self {
+            affinity_key: Some(key),
+            ..self
+        }
+    }
+
+    /// Sends requests routed through discovery to the endpoints in the
+    /// proxy's zone, spilling over to other zones as `locality` permits.
+    ///
+    /// Endpoints whose zones discovery doesn't know are never local.
+    pub fn with_locality(self, locality: LocalityConfig) -> Self {
+        Self {
+            locality: Some(locality),
+            ..self
+        }
+    }
+
+    /// Balances each destination's requests with a `Selector` created by
+    /// `new_selector`, rather than by weight or peak-EWMA load.
+    pub fn with_selector(self, new_selector: NewSelector) -> Self {
+        Self {
+            selector: Some(new_selector),
+            ..self
+        }
+    }
+
+    /// Splits the requests for the authorities in `splits` between their
+    /// backends, as `splits` is updated.
+    ///
+    /// A request is routed, from its authority's route policy onwards, as if
+    /// it had been sent to the backend chosen for it, each of which is
+    /// balanced over separately. Requests routed to their original
+    /// destinations are never split.
+    pub fn with_traffic_splits(self, splits: TrafficSplits) -> Self {
+        Self {
+            splits: Some(splits),
+            ..self
+        }
+    }
+
+    /// Sets what becomes of requests with a `PinnedEndpoint` whose address
+    /// isn't among the healthy endpoints of their destination.
+    ///
+    /// By default, they're rejected.
+    pub fn with_unhealthy_pins(self, unhealthy_pins: UnhealthyPins) -> Self {
+        Self {
+            unhealthy_pins,
+            ..self
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Destination {
-    Hostname(DnsNameAndPort),
+    /// A name to be resolved through service discovery, along with the
+    /// address to fall back to if discovery has no endpoints for it, and the
+    /// tag that its endpoints are restricted to.
+    ///
+    /// Each tag is balanced over separately.
+    Hostname(DnsNameAndPort, Option<SocketAddr>, Option<String>),
     ImplicitOriginalDst(SocketAddr),
 }
 
 impl<B> Recognize for Outbound<B>
 where
-    B: tower_h2::Body + 'static,
+    B: tower_h2::Body + FromBuffered + 'static,
 {
     type Request = http::Request<B>;
     type Response = bind::HttpResponse;
     type Error = <Self::Service as tower::Service>::Error;
     type Key = (Destination, Protocol);
     type RouteError = bind::BufferSpawnError;
-    type Service = InFlightLimit<Timeout<Buffer<Balance<
-        load::WithPendingRequests<Discovery<B>>,
-        choose::PowerOfTwoChoices<rand::ThreadRng>
-    >>>>;
+    type Service = PriorityLimit<Timeout<Buffer<Sticky<Pin<Hedge<FailFast<Balance<
+        WithSlowStart<WithPeakEwma<PinnedDiscovery<B>>>,
+        Chooser<rand::ThreadRng>
+    >>>>>>>>;
 
     fn recognize(&self, req: &Self::Request) -> Option<Reuse<Self::Key>> {
-        let proto = bind::Protocol::detect(req);
+        // HTTP/1 requests without an authority are rejected, unless the
+        // `MissingHostPolicy` lets them fall back to their original
+        // destination.
+        let proto = bind::Protocol::detect_with_fallback(req, self.bind.missing_host_policy())?;
 
         // The request URI and Host: header have not yet been normalized
         // by `NormalizeUri`, as we need to know whether the request will
@@ -81,22 +315,35 @@ where
         // for a valid authority, before we fall back to SO_ORIGINAL_DST.
             .or_else(|| h1::authority_from_host(req));
 
+        let orig_dst = req.extensions()
+            .get::<Arc<ctx::transport::Server>>()
+            .and_then(|ctx| {
+                ctx.orig_dst_if_not_local()
+            });
+
         // TODO: Return error when `HostAndPort::normalize()` fails.
         let mut dest = match authority.as_ref()
             .and_then(|auth| HostAndPort::normalize(auth, Some(80)).ok()) {
-            Some(HostAndPort { host: Host::DnsName(dns_name), port }) =>
-                Some(Destination::Hostname(DnsNameAndPort { host: dns_name, port })),
+            Some(HostAndPort { host: Host::DnsName(dns_name), port }) => {
+                let fallback = if self.orig_dst_fallback { orig_dst } else { None };
+                let name = DnsNameAndPort { host: dns_name, port };
+                let name = match self.splits {
+                    Some(ref splits) => splits.route(&name, &mut rand::thread_rng())
+                        .unwrap_or(name),
+                    None => name,
+                };
+                let tag = self.route_tag_header.as_ref()
+                    .and_then(|header| req.headers().get(header))
+                    .and_then(|tag| tag.to_str().ok())
+                    .map(String::from);
+                Some(Destination::Hostname(name, fallback, tag))
+            },
             Some(HostAndPort { host: Host::Ip(_), .. }) |
             None => None,
         };
 
         if dest.is_none() {
-            dest = req.extensions()
-                .get::<Arc<ctx::transport::Server>>()
-                .and_then(|ctx| {
-                    ctx.orig_dst_if_not_local()
-                })
-                .map(Destination::ImplicitOriginalDst)
+            dest = orig_dst.map(Destination::ImplicitOriginalDst)
         };
 
         // If there is no authority in the request URI or in the Host header,
@@ -107,6 +354,23 @@ where
         // original destination.
         let dest = dest?;
 
+        // An HTTP/1 request is translated to HTTP/2 if its upstream is known
+        // to speak h2c.
+        let proto = match proto {
+            Protocol::Http1(_) if self.h2c_upstreams.contains(&dest) => Protocol::Http2,
+            proto => proto,
+        };
+        // A destination's path rules take precedence over the proxy-wide
+        // protocol policy.
+        let policy = match dest {
+            Destination::Hostname(ref name, _, _) => self.routes.as_ref()
+                .and_then(|routes| routes.get(name))
+                .and_then(|route| route.path_protocols.as_ref())
+                .and_then(|protocols| protocols.get(req.uri().path())),
+            Destination::ImplicitOriginalDst(_) => None,
+        };
+        let proto = proto.coerce(req, policy.unwrap_or_else(|| self.bind.protocol_policy()));
+
         Some(proto.into_key(dest))
     }
 
@@ -115,10 +379,9 @@ where
     /// Resolves the authority in service discovery and initializes a service that buffers
     /// and load balances requests across.
     ///
-    /// # TODO
-    ///
-    /// Buffering is currently unbounded and does not apply timeouts. This must be
-    /// changed.
+    /// At most `buffer_capacity` requests, as configured on the `Bind`, may be
+    /// buffered; additional requests fail immediately, unless they're more
+    /// important than a buffered request, which is shed in their place.
     fn bind_service(
         &mut self,
         key: &Self::Key,
@@ -126,37 +389,124 @@ where
         let &(ref dest, ref protocol) = key;
         debug!("building outbound {:?} client to {:?}", protocol, dest);
 
+        let policy = match *dest {
+            Destination::Hostname(ref authority, _, _) => {
+                self.routes.as_ref().and_then(|routes| routes.get(authority))
+            },
+            Destination::ImplicitOriginalDst(_) => None,
+        };
+        let bind = match policy {
+            Some(policy) => {
+                debug!("using route policy for {:?}: {:?}", dest, policy);
+                self.bind.clone().with_route_policy(policy)
+            },
+            None => self.bind.clone(),
+        };
+
         let resolve = match *dest {
-            Destination::Hostname(ref authority) => {
-                Discovery::NamedSvc(self.discovery.resolve(
+            Destination::Hostname(ref authority, fallback, _) => {
+                let watch = self.discovery.resolve(
                     authority,
-                    self.bind.clone().with_protocol(protocol.clone()),
-                ))
+                    bind.clone()
+                        .with_protocol(protocol.clone())
+                        .with_tls_name(authority.host.clone()),
+                );
+                let watch = match bind.discovery_state() {
+                    Some(state) => watch.with_state(state.watch(authority)),
+                    None => watch,
+                };
+                match fallback {
+                    Some(addr) => Discovery::NamedSvc(watch.with_fallback(addr)),
+                    None => Discovery::NamedSvc(watch),
+                }
             },
             Destination::ImplicitOriginalDst(addr) => {
-                Discovery::ImplicitOriginalDst(Some((addr, self.bind.clone()
+                Discovery::ImplicitOriginalDst(Some((addr, bind.clone()
                     .with_protocol(protocol.clone()))))
             }
         };
 
-        let loaded = tower_balance::load::WithPendingRequests::new(resolve);
-
-        let balance = tower_balance::power_of_two_choices(loaded, rand::thread_rng());
+        let choose = match self.peak_ewma {
+            Some(_) => Chooser::peak_ewma(rand::thread_rng()),
+            None => Chooser::weighted_random(rand::thread_rng()),
+        };
+        // The hedge uses the balancer's choices to send its second attempt
+        // to a different endpoint, `Pin` uses them to send requests to the
+        // endpoints they're pinned to, and `Sticky` uses them to send
+        // requests to the endpoints their keys have affinity with.
+        let choices = Choices::default();
+        let choose = choose.with_choices(choices.clone());
+        let choose = match self.selector {
+            Some(ref new_selector) => choose.with_selector(new_selector()),
+            None => choose,
+        };
+        let choose = match *dest {
+            Destination::Hostname(_, _, ref tag) => choose
+                .with_tag(tag.clone())
+                .with_locality(self.locality.clone()),
+            Destination::ImplicitOriginalDst(_) => choose,
+        };
+        let endpoints = Endpoints::default();
+        let counted = CountEndpoints::new(resolve, endpoints.clone());
+        // Endpoints bound only for pinned requests aren't counted, so that
+        // requests still fail fast while discovery has no endpoints.
+        let pins = Pins::default();
+        let pinned = PinnedDiscovery {
+            inner: counted,
+            bind: bind.clone().with_protocol(protocol.clone()),
+            pins: pins.clone(),
+        };
+        let loaded = WithPeakEwma::new(pinned, self.peak_ewma);
+        let warmed = WithSlowStart::new(loaded, self.slow_start);
+        let balance = Balance::new(warmed, choose);
 
-        // use the same executor as the underlying `Bind` for the `Buffer` and
-        // `Timeout`.
+        // use the same executor as the underlying `Bind` for the `FailFast`,
+        // `Hedge`, `Buffer` and `Timeout`.
         let handle = self.bind.executor();
 
-        let buffer = Buffer::new(balance, handle)
-            .map_err(|_| bind::BufferSpawnError::Outbound)?;
+        let fail_fast = FailFast::new(balance, endpoints, self.fail_fast, handle);
+
+        let hedge = Hedge::new(
+            fail_fast,
+            self.hedge_delay,
+            bind.retry_policy(),
+            choices.clone(),
+            handle,
+        );
+
+        let pin = Pin::new(hedge, choices.clone(), pins, self.unhealthy_pins);
+
+        let sticky = Sticky::new(pin, self.affinity_key.clone(), choices);
+
+        let buffer = Buffer::new(sticky, handle)
+            .map_err(|_| {
+                let target = match *dest {
+                    Destination::Hostname(ref authority, _, _) => {
+                        format!("{}:{}", authority.host, authority.port)
+                    }
+                    Destination::ImplicitOriginalDst(addr) => addr.to_string(),
+                };
+                bind::BufferSpawnError::outbound(target, bind::ExecutorRefused)
+            })?;
 
         let timeout = Timeout::new(buffer, self.bind_timeout, handle);
 
-        Ok(InFlightLimit::new(timeout, MAX_IN_FLIGHT))
+        Ok(PriorityLimit::new(timeout, bind.buffer_capacity(), bind.classify()))
 
     }
 }
 
+// ===== impl H2cUpstreams =====
+
+impl H2cUpstreams {
+    fn contains(&self, dest: &Destination) -> bool {
+        match *dest {
+            Destination::Hostname(ref name, _, _) => self.names.contains(name),
+            Destination::ImplicitOriginalDst(ref addr) => self.addrs.contains(addr),
+        }
+    }
+}
+
 pub enum Discovery<B> {
     NamedSvc(discovery::Watch<BindProtocol<B>>),
     ImplicitOriginalDst(Option<(SocketAddr, BindProtocol<B>)>),
@@ -164,13 +514,13 @@ pub enum Discovery<B> {
 
 impl<B> Discover for Discovery<B>
 where
-    B: tower_h2::Body + 'static,
+    B: tower_h2::Body + Default + 'static,
 {
     type Key = SocketAddr;
     type Request = http::Request<B>;
     type Response = bind::HttpResponse;
     type Error = <Self::Service as tower::Service>::Error;
-    type Service = metrics::Labeled<bind::Service<B>>;
+    type Service = Weighted<metrics::Labeled<bind::DiscoveredService<B>>>;
     type DiscoverError = BindError;
 
     fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
@@ -180,15 +530,18 @@ where
             Discovery::ImplicitOriginalDst(ref mut opt) => {
                 // This "discovers" a single address for an external service
                 // that never has another change. This can mean it floats
-                // in the Balancer forever. However, when we finally add
-                // circuit-breaking, this should be able to take care of itself,
-                // closing down when the connection is no longer usable.
+                // in the Balancer forever. If a circuit breaker is
+                // configured, requests fail fast while the endpoint is
+                // unusable.
                 if let Some((addr, bind)) = opt.take() {
                     let svc = bind.bind(&addr)
-                        // The controller has no labels to add to an external
-                        // service.
-                        .map(metrics::Labeled::none)
-                        .map_err(|_| BindError::External{ addr })?;
+                        // The controller has no labels or weight to add to
+                        // an external service.
+                        .map(|svc| {
+                            Weighted::new(metrics::Labeled::none(svc), Weight::default())
+                                .with_addr(addr)
+                        })
+                        .map_err(|cause| BindError::External { addr, cause })?;
                     Ok(Async::Ready(Change::Insert(addr, svc)))
                 } else {
                     Ok(Async::NotReady)
@@ -197,17 +550,66 @@ where
         }
     }
 }
-#[derive(Copy, Clone, Debug)]
+/// Discovers the endpoints of a `Discovery`, along with the endpoints for
+/// the addresses that requests are pinned to that it hasn't discovered.
+///
+/// Endpoints bound for pinned requests have no weight, so that requests that
+/// aren't pinned to them are sent elsewhere, and aren't removed unless
+/// they're discovered and then removed from discovery.
+pub struct PinnedDiscovery<B> {
+    inner: CountEndpoints<Discovery<B>>,
+    bind: BindProtocol<B>,
+    pins: Pins,
+}
+
+impl<B> Discover for PinnedDiscovery<B>
+where
+    B: tower_h2::Body + Default + 'static,
+{
+    type Key = SocketAddr;
+    type Request = http::Request<B>;
+    type Response = bind::HttpResponse;
+    type Error = <Self::Service as tower::Service>::Error;
+    type Service = Weighted<metrics::Labeled<bind::DiscoveredService<B>>>;
+    type DiscoverError = BindError;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
+        if let Async::Ready(change) = self.inner.poll()? {
+            match change {
+                Change::Insert(ref addr, _) => self.pins.insert(*addr),
+                Change::Remove(ref addr) => self.pins.remove(addr),
+            }
+            return Ok(Async::Ready(change));
+        }
+
+        match self.pins.take_unknown() {
+            Some(addr) => {
+                debug!("binding undiscovered {:?} for pinned requests", addr);
+                let svc = self.bind.bind_undiscovered(&addr)
+                    .map(|svc| {
+                        Weighted::new(metrics::Labeled::none(svc), Weight::new(0))
+                            .with_addr(addr)
+                    })
+                    .map_err(|cause| BindError::External { addr, cause })?;
+                self.pins.insert(addr);
+                Ok(Async::Ready(Change::Insert(addr, svc)))
+            }
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum BindError {
-    External { addr: SocketAddr },
+    External { addr: SocketAddr, cause: bind::BindError },
     Internal,
 }
 
 impl fmt::Display for BindError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            BindError::External { addr } =>
-                write!(f, "binding external service for {:?} failed", addr),
+            BindError::External { addr, ref cause } =>
+                write!(f, "binding external service for {:?} failed: {}", addr, cause),
             BindError::Internal =>
                 write!(f, "binding internal service failed"),
         }
@@ -223,5 +625,10 @@ impl error::Error for BindError {
         }
     }
 
-    fn cause(&self) -> Option<&error::Error> { None }
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            BindError::External { ref cause, .. } => Some(cause),
+            BindError::Internal => None,
+        }
+    }
 }
@@ -0,0 +1,453 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use http;
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+use control::state::EndpointState;
+use fault;
+
+/// Settings for passive outlier detection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OutlierConfig {
+    consecutive_failures: usize,
+    base_ejection_time: Duration,
+    max_ejection_time: Duration,
+}
+
+/// Ejects an endpoint from load balancing once it has failed repeatedly.
+///
+/// After `consecutive_failures` requests in a row fail with an error or a
+/// `5xx` response, the endpoint is ejected: the service is not ready, so
+/// that a balancer dispatches requests to other endpoints. Once the
+/// ejection time has elapsed, the endpoint is re-admitted to probe whether
+/// it has recovered. If its first response after re-admission is also a
+/// failure, it is ejected again for twice as long as before, up to the
+/// maximum ejection time; a success resets the ejection time.
+///
+/// Unlike circuit breakers and health checks, ejection state belongs to the
+/// service rather than being shared by address, so an endpoint is ejected
+/// only from the balancer whose requests it failed. An endpoint that
+/// discovery removes and re-adds is bound anew, forgetting its ejections.
+///
+/// If constructed without an `OutlierConfig`, this is a no-op.
+pub struct OutlierDetection<S> {
+    inner: S,
+    detector: Option<Detector>,
+    /// Reports the endpoint's responses and ejections to the admin API, if
+    /// it is served.
+    endpoint_state: Option<EndpointState>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    detector: Option<Detector>,
+    endpoint_state: Option<EndpointState>,
+}
+
+#[derive(Clone)]
+struct Detector {
+    addr: SocketAddr,
+    config: OutlierConfig,
+    state: Rc<RefCell<State>>,
+    endpoint_state: Option<EndpointState>,
+    handle: Handle,
+}
+
+struct State {
+    failures: usize,
+    /// The number of times the endpoint has been ejected since it last
+    /// succeeded.
+    ejections: u32,
+    ejected_until: Option<Instant>,
+    /// Fires when the ejected endpoint is to be re-admitted.
+    timer: Option<ReactorTimeout>,
+}
+
+/// The most that the ejection time is doubled, so that its multiplier
+/// can't overflow.
+const MAX_DOUBLINGS: u32 = 16;
+
+// ===== impl OutlierConfig =====
+
+impl OutlierConfig {
+    /// Ejects endpoints after `consecutive_failures` failures, for
+    /// `base_ejection_time` the first time.
+    ///
+    /// The threshold is at least one. The maximum ejection time is ten times
+    /// the base ejection time, unless configured otherwise.
+    pub fn new(consecutive_failures: usize, base_ejection_time: Duration) -> Self {
+        OutlierConfig {
+            consecutive_failures: cmp::max(consecutive_failures, 1),
+            base_ejection_time,
+            max_ejection_time: base_ejection_time * 10,
+        }
+    }
+
+    /// Limits how long an endpoint that fails repeatedly may be ejected.
+    ///
+    /// The maximum is raised to the base ejection time if it is smaller.
+    pub fn with_max_ejection_time(self, max_ejection_time: Duration) -> Self {
+        Self {
+            max_ejection_time: cmp::max(max_ejection_time, self.base_ejection_time),
+            ..self
+        }
+    }
+
+    fn ejection_time(&self, ejections: u32) -> Duration {
+        let doublings = cmp::min(ejections.saturating_sub(1), MAX_DOUBLINGS);
+        let time = self.base_ejection_time * (1 << doublings);
+        cmp::min(time, self.max_ejection_time)
+    }
+}
+
+// ===== impl OutlierDetection =====
+
+impl<S> OutlierDetection<S> {
+    pub fn new(
+        inner: S,
+        addr: &SocketAddr,
+        config: Option<OutlierConfig>,
+        handle: &Handle,
+    ) -> Self {
+        let detector = config.map(|config| Detector {
+            addr: *addr,
+            config,
+            state: Rc::new(RefCell::new(State {
+                failures: 0,
+                ejections: 0,
+                ejected_until: None,
+                timer: None,
+            })),
+            endpoint_state: None,
+            handle: handle.clone(),
+        });
+        OutlierDetection {
+            inner,
+            detector,
+            endpoint_state: None,
+        }
+    }
+
+    /// Reports whether each response succeeded, and whether the endpoint is
+    /// ejected, to `endpoint_state`.
+    ///
+    /// Responses are reported even if outlier detection isn't configured.
+    pub fn with_endpoint_state(self, endpoint_state: EndpointState) -> Self {
+        let detector = self.detector.map(|detector| Detector {
+            endpoint_state: Some(endpoint_state.clone()),
+            ..detector
+        });
+        OutlierDetection {
+            inner: self.inner,
+            detector,
+            endpoint_state: Some(endpoint_state),
+        }
+    }
+}
+
+impl<S, B> Service for OutlierDetection<S>
+where
+    S: Service<Response = http::Response<B>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if let Some(ref detector) = self.detector {
+            if detector.poll_ejected() {
+                return Ok(Async::NotReady);
+            }
+        }
+
+        let detector = &self.detector;
+        self.inner.poll_ready().map_err(|e| {
+            if let Some(ref detector) = *detector {
+                detector.failure();
+            }
+            e
+        })
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(req),
+            detector: self.detector.clone(),
+            endpoint_state: self.endpoint_state.clone(),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.inner.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            result => result,
+        };
+
+        // An injected fault says nothing about the endpoint's health.
+        let injected = match result {
+            Ok(Async::Ready(ref rsp)) => fault::is_injected(rsp),
+            _ => false,
+        };
+        if injected {
+            return result;
+        }
+
+        let success = match result {
+            Ok(Async::Ready(ref rsp)) => !rsp.status().is_server_error(),
+            _ => false,
+        };
+        if let Some(ref endpoint_state) = self.endpoint_state {
+            endpoint_state.record(success);
+        }
+        if let Some(ref detector) = self.detector {
+            if success {
+                detector.success();
+            } else {
+                detector.failure();
+            }
+        }
+
+        result
+    }
+}
+
+// ===== impl Detector =====
+
+impl Detector {
+    /// Returns true if the endpoint is ejected, in which case the current
+    /// task is notified once it is re-admitted.
+    fn poll_ejected(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        let until = match state.ejected_until {
+            Some(until) => until,
+            None => return false,
+        };
+
+        if state.timer.is_none() {
+            match ReactorTimeout::new_at(until, &self.handle) {
+                Ok(timer) => state.timer = Some(timer),
+                // Without a timer, nothing would wake the balancer to
+                // re-admit the endpoint, so don't eject it at all.
+                Err(e) => {
+                    warn!("failed to eject outlier {}: {}", self.addr, e);
+                    state.ejected_until = None;
+                    if let Some(ref endpoint_state) = self.endpoint_state {
+                        endpoint_state.set_ejected(false);
+                    }
+                    return false;
+                }
+            }
+        }
+
+        let readmitted = match state.timer.as_mut().map(Future::poll) {
+            Some(Ok(Async::NotReady)) => false,
+            _ => true,
+        };
+        if readmitted {
+            debug!("re-admitting outlier {}", self.addr);
+            state.ejected_until = None;
+            state.timer = None;
+            if let Some(ref endpoint_state) = self.endpoint_state {
+                endpoint_state.set_ejected(false);
+            }
+        }
+        !readmitted
+    }
+
+    fn success(&self) {
+        let mut state = self.state.borrow_mut();
+        // A request dispatched before the endpoint was ejected says nothing
+        // about whether it has recovered.
+        if state.ejected_until.is_none() {
+            state.failures = 0;
+            state.ejections = 0;
+        }
+    }
+
+    fn failure(&self) {
+        let mut state = self.state.borrow_mut();
+        if state.ejected_until.is_some() {
+            return;
+        }
+
+        state.failures += 1;
+        // A re-admitted endpoint is ejected again as soon as it fails.
+        let threshold = if state.ejections > 0 {
+            1
+        } else {
+            self.config.consecutive_failures
+        };
+        if state.failures >= threshold {
+            state.ejections += 1;
+            let time = self.config.ejection_time(state.ejections);
+            debug!("ejecting outlier {} for {:?}", self.addr, time);
+            state.failures = 0;
+            state.ejected_until = Some(Instant::now() + time);
+            state.timer = None;
+            if let Some(ref endpoint_state) = self.endpoint_state {
+                endpoint_state.set_ejected(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::{self, FutureResult};
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use test_support::Endpoint;
+
+    fn detect(core: &Core, statuses: &[u16]) -> OutlierDetection<Endpoint> {
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let config = OutlierConfig::new(2, Duration::from_millis(20))
+            .with_max_ejection_time(Duration::from_millis(30));
+        let endpoint = Endpoint::with_statuses(statuses);
+        OutlierDetection::new(endpoint, &addr, Some(config), &core.handle())
+    }
+
+    fn is_ready(core: &mut Core, svc: &mut OutlierDetection<Endpoint>) -> bool {
+        core.run(future::lazy(|| svc.poll_ready()))
+            .expect("poll_ready")
+            .is_ready()
+    }
+
+    fn send(core: &mut Core, svc: &mut OutlierDetection<Endpoint>) {
+        core.run(svc.call(http::Request::new(()))).expect("response");
+    }
+
+    /// Runs `core` until `svc` is ready, returning how long that took.
+    fn readmission(core: &mut Core, svc: &mut OutlierDetection<Endpoint>) -> Duration {
+        let start = Instant::now();
+        core.run(future::poll_fn(|| svc.poll_ready())).expect("poll_ready");
+        start.elapsed()
+    }
+
+    #[test]
+    fn consecutive_failures_eject_endpoints_until_readmitted() {
+        let mut core = Core::new().unwrap();
+        let mut svc = detect(&core, &[500, 200, 500, 503]);
+
+        // Failures must be consecutive.
+        send(&mut core, &mut svc);
+        send(&mut core, &mut svc);
+        send(&mut core, &mut svc);
+        assert!(is_ready(&mut core, &mut svc));
+
+        send(&mut core, &mut svc);
+        assert!(!is_ready(&mut core, &mut svc));
+        assert!(readmission(&mut core, &mut svc) >= Duration::from_millis(10));
+
+        // Once re-admitted, an endpoint that succeeds stays in rotation.
+        send(&mut core, &mut svc);
+        send(&mut core, &mut svc);
+        assert!(is_ready(&mut core, &mut svc));
+    }
+
+    #[test]
+    fn repeated_ejections_are_longer() {
+        let mut core = Core::new().unwrap();
+        let mut svc = detect(&core, &[500, 500, 500, 500, 200, 500]);
+
+        send(&mut core, &mut svc);
+        send(&mut core, &mut svc);
+        assert!(!is_ready(&mut core, &mut svc));
+        let first = readmission(&mut core, &mut svc);
+
+        // A re-admitted endpoint is ejected by a single failure, for twice
+        // as long, up to the maximum.
+        send(&mut core, &mut svc);
+        assert!(!is_ready(&mut core, &mut svc));
+        let second = readmission(&mut core, &mut svc);
+        assert!(second > first, "{:?} should be longer than {:?}", second, first);
+
+        send(&mut core, &mut svc);
+        assert!(!is_ready(&mut core, &mut svc));
+        let third = readmission(&mut core, &mut svc);
+        assert!(third < Duration::from_millis(60), "{:?} exceeds the maximum", third);
+
+        // A success resets the ejection time, so that a single failure no
+        // longer ejects the endpoint.
+        send(&mut core, &mut svc);
+        assert!(is_ready(&mut core, &mut svc));
+        send(&mut core, &mut svc);
+        assert!(is_ready(&mut core, &mut svc));
+    }
+
+    #[test]
+    fn rebound_endpoints_forget_ejections() {
+        let mut core = Core::new().unwrap();
+        let mut svc = detect(&core, &[500, 500]);
+        send(&mut core, &mut svc);
+        send(&mut core, &mut svc);
+        assert!(!is_ready(&mut core, &mut svc));
+
+        let mut rebound = detect(&core, &[]);
+        assert!(is_ready(&mut core, &mut rebound));
+    }
+
+    #[test]
+    fn ejection_times_double_up_to_the_maximum() {
+        let config = OutlierConfig::new(1, Duration::from_secs(1))
+            .with_max_ejection_time(Duration::from_secs(5));
+        let times = (1..5).map(|n| config.ejection_time(n)).collect::<Vec<_>>();
+        assert_eq!(times, vec![
+            Duration::from_secs(1),
+            Duration::from_secs(2),
+            Duration::from_secs(4),
+            Duration::from_secs(5),
+        ]);
+        assert_eq!(config.ejection_time(u32::max_value()), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn injected_faults_are_not_failures() {
+        /// Responds to every request with an injected `503`.
+        struct Aborts;
+
+        impl Service for Aborts {
+            type Request = http::Request<()>;
+            type Response = http::Response<()>;
+            type Error = ();
+            type Future = FutureResult<Self::Response, ()>;
+
+            fn poll_ready(&mut self) -> Poll<(), ()> {
+                Ok(Async::Ready(()))
+            }
+
+            fn call(&mut self, _: Self::Request) -> Self::Future {
+                let mut rsp = http::Response::new(());
+                *rsp.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+                rsp.extensions_mut().insert(fault::Injected);
+                future::ok(rsp)
+            }
+        }
+
+        let mut core = Core::new().unwrap();
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let config = OutlierConfig::new(2, Duration::from_millis(20));
+        let mut svc = OutlierDetection::new(Aborts, &addr, Some(config), &core.handle());
+        for _ in 0..3 {
+            core.run(svc.call(http::Request::new(()))).expect("response");
+        }
+        assert!(core.run(future::lazy(|| svc.poll_ready())).expect("poll_ready").is_ready());
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::{task, Async, Poll};
+use tower::Service;
+
+/// The endpoint addresses that have been paused through the admin API.
+///
+/// Unlike health and circuit breaker state, a pause is held strongly, until
+/// the endpoint is resumed: an endpoint that discovery removes and then adds
+/// again is still paused, as is one that's rebound for any other reason.
+///
+/// Clones share the same pauses.
+#[derive(Clone, Debug, Default)]
+pub struct Pauses(Arc<Mutex<HashMap<SocketAddr, Vec<task::Task>>>>);
+
+/// Withholds an endpoint from load balancing while it's paused.
+///
+/// While its endpoint is paused, the service is not ready, so that a
+/// balancer dispatches new requests to other endpoints. Requests already
+/// dispatched to it are unaffected, so that they complete as the endpoint
+/// drains.
+#[derive(Debug)]
+pub struct Pausable<S> {
+    inner: S,
+    addr: SocketAddr,
+    pauses: Pauses,
+}
+
+// ===== impl Pauses =====
+
+impl Pauses {
+    /// Stops dispatching new requests to `addr`, returning false if it was
+    /// already paused.
+    ///
+    /// An address may be paused before any endpoint is bound to it.
+    pub fn pause(&self, addr: SocketAddr) -> bool {
+        let mut paused = self.0.lock().expect("pauses lock poisoned");
+        if paused.contains_key(&addr) {
+            return false;
+        }
+        debug!("pausing {}", addr);
+        paused.insert(addr, Vec::new());
+        true
+    }
+
+    /// Resumes dispatching requests to `addr`, returning false if it wasn't
+    /// paused.
+    pub fn resume(&self, addr: &SocketAddr) -> bool {
+        let waiting = self.0.lock().expect("pauses lock poisoned").remove(addr);
+        match waiting {
+            Some(waiting) => {
+                debug!("resuming {}", addr);
+                for task in waiting {
+                    task.notify();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_paused(&self, addr: &SocketAddr) -> bool {
+        self.0.lock().expect("pauses lock poisoned").contains_key(addr)
+    }
+
+    /// Wraps the service bound to `addr`, so that it isn't ready while `addr`
+    /// is paused.
+    pub fn pausable<S>(&self, addr: &SocketAddr, inner: S) -> Pausable<S> {
+        Pausable {
+            inner,
+            addr: *addr,
+            pauses: self.clone(),
+        }
+    }
+}
+
+// ===== impl Pausable =====
+
+impl<S: Service> Service for Pausable<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        {
+            let mut paused = self.pauses.0.lock().expect("pauses lock poisoned");
+            if let Some(waiting) = paused.get_mut(&self.addr) {
+                if !waiting.iter().any(|t| t.will_notify_current()) {
+                    waiting.push(task::current());
+                }
+                return Ok(Async::NotReady);
+            }
+        }
+
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Future};
+    use http;
+
+    use super::*;
+    use test_support::Endpoint;
+
+    type Endpoints = Vec<(Pausable<Endpoint>, Endpoint)>;
+
+    fn endpoints(pauses: &Pauses, addrs: &[SocketAddr]) -> Endpoints {
+        addrs.iter()
+            .map(|addr| {
+                let endpoint = Endpoint::default();
+                (pauses.pausable(addr, endpoint.clone()), endpoint)
+            })
+            .collect()
+    }
+
+    /// Dispatches `n` requests over `endpoints` in turn, as a balancer would,
+    /// skipping those that aren't ready.
+    fn dispatch(endpoints: &mut [(Pausable<Endpoint>, Endpoint)], n: usize) {
+        future::lazy(|| {
+            for i in 0..n {
+                let len = endpoints.len();
+                let ready = (0..len)
+                    .map(|j| (i + j) % len)
+                    .find(|&j| endpoints[j].0.poll_ready().unwrap().is_ready())
+                    .expect("an endpoint should be ready");
+                endpoints[ready].0.call(http::Request::new(())).wait().unwrap();
+            }
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    fn dispatched(endpoints: &[(Pausable<Endpoint>, Endpoint)]) -> Vec<usize> {
+        endpoints.iter().map(|&(_, ref endpoint)| endpoint.calls()).collect()
+    }
+
+    #[test]
+    fn paused_endpoints_receive_no_requests_until_resumed() {
+        let pauses = Pauses::default();
+        let a = "10.1.1.1:8080".parse().unwrap();
+        let b = "10.1.1.2:8080".parse().unwrap();
+        let mut endpoints = endpoints(&pauses, &[a, b]);
+
+        dispatch(&mut endpoints, 4);
+        assert_eq!(dispatched(&endpoints), vec![2, 2]);
+
+        assert!(pauses.pause(a));
+        assert!(!pauses.pause(a), "already paused");
+        assert!(pauses.is_paused(&a));
+        dispatch(&mut endpoints, 4);
+        assert_eq!(dispatched(&endpoints), vec![2, 6]);
+
+        assert!(pauses.resume(&a));
+        assert!(!pauses.resume(&a), "already resumed");
+        dispatch(&mut endpoints, 4);
+        assert_eq!(dispatched(&endpoints), vec![4, 8]);
+    }
+
+    #[test]
+    fn pauses_outlive_rebinding() {
+        let pauses = Pauses::default();
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        assert!(pauses.pause(addr));
+
+        // The endpoint is removed by discovery, and then added again.
+        let removed = endpoints(&pauses, &[addr]);
+        drop(removed);
+        let mut readded = endpoints(&pauses, &[addr]);
+
+        let ready = future::lazy(|| readded[0].0.poll_ready()).wait().unwrap();
+        assert!(ready.is_not_ready());
+        assert!(pauses.is_paused(&addr));
+    }
+
+    #[test]
+    fn resuming_notifies_waiting_tasks() {
+        use futures::executor::{self, Notify};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct Flag(AtomicBool);
+        impl Notify for Flag {
+            fn notify(&self, _: usize) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let pauses = Pauses::default();
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        pauses.pause(addr);
+        let svc = pauses.pausable(&addr, Endpoint::default());
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let mut task = executor::spawn(future::poll_fn({
+            let mut svc = svc;
+            move || svc.poll_ready()
+        }));
+        assert!(task.poll_future_notify(&flag, 0).unwrap().is_not_ready());
+        assert!(!flag.0.load(Ordering::SeqCst));
+
+        pauses.resume(&addr);
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert!(task.poll_future_notify(&flag, 0).unwrap().is_ready());
+    }
+}
@@ -0,0 +1,408 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::error::Error as StdError;
+use std::fmt;
+use std::mem;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use futures::{task, Async, Future, Poll};
+use http;
+use tower::Service;
+
+use balance::Choices;
+
+/// Pins a request to the endpoint at this address, bypassing load balancing,
+/// when inserted into the request's extensions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PinnedEndpoint(pub SocketAddr);
+
+/// What becomes of requests pinned to addresses that aren't among the
+/// balancer's healthy endpoints.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnhealthyPins {
+    /// Requests are sent to the pinned address anyway. Addresses that
+    /// discovery doesn't know of are connected to directly.
+    Allow,
+    /// Requests fail with `Error::Unavailable` without being sent.
+    Reject,
+}
+
+/// The addresses that a balancer has endpoints for, shared with the `Pin` in
+/// front of it, so that the addresses of requests pinned to endpoints it
+/// doesn't have may be bound.
+#[derive(Clone, Debug, Default)]
+pub struct Pins(Rc<RefCell<PinsInner>>);
+
+#[derive(Debug, Default)]
+struct PinsInner {
+    known: HashSet<SocketAddr>,
+    /// Pinned addresses waiting to be bound, in the order they were pinned.
+    unknown: Vec<SocketAddr>,
+}
+
+/// Sends each request with a `PinnedEndpoint` to the endpoint at its address,
+/// rather than to the endpoint the balancer would choose.
+///
+/// The inner service is expected to be a balancer whose `Chooser` uses
+/// `choices`, and whose discovery binds the addresses requested through
+/// `pins`. The balancer is readied again for each pinned request, choosing
+/// only the pinned endpoint. Requests without a `PinnedEndpoint` are
+/// balanced as normal.
+pub struct Pin<S> {
+    inner: Rc<RefCell<S>>,
+    choices: Choices,
+    pins: Pins,
+    unhealthy: UnhealthyPins,
+}
+
+pub enum ResponseFuture<S: Service> {
+    /// Waiting for the service to become ready with the pinned endpoint.
+    Dispatching {
+        service: Rc<RefCell<S>>,
+        choices: Choices,
+        pins: Pins,
+        unhealthy: UnhealthyPins,
+        addr: SocketAddr,
+        request: Option<S::Request>,
+    },
+    Pending(S::Future),
+    Failed(Option<Error<S::Error>>),
+}
+
+/// An error produced by a `Pin`.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Indicates that a request was pinned to an address that the balancer
+    /// has no healthy endpoint for.
+    Unavailable(SocketAddr),
+    /// Indicates that the underlying service failed.
+    Inner(E),
+}
+
+// ===== impl UnhealthyPins =====
+
+impl Default for UnhealthyPins {
+    fn default() -> Self {
+        UnhealthyPins::Reject
+    }
+}
+
+impl UnhealthyPins {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "allow" => Some(UnhealthyPins::Allow),
+            "reject" => Some(UnhealthyPins::Reject),
+            _ => None,
+        }
+    }
+}
+
+// ===== impl Pins =====
+
+impl Pins {
+    /// Records that the balancer has an endpoint for `addr`.
+    pub fn insert(&self, addr: SocketAddr) {
+        let mut inner = self.0.borrow_mut();
+        inner.unknown.retain(|a| *a != addr);
+        inner.known.insert(addr);
+    }
+
+    /// Records that the balancer no longer has an endpoint for `addr`.
+    pub fn remove(&self, addr: &SocketAddr) {
+        self.0.borrow_mut().known.remove(addr);
+    }
+
+    /// Returns a pinned address that the balancer has no endpoint for, if
+    /// there is one, so that it may be bound.
+    pub fn take_unknown(&self) -> Option<SocketAddr> {
+        let mut inner = self.0.borrow_mut();
+        if inner.unknown.is_empty() {
+            None
+        } else {
+            Some(inner.unknown.remove(0))
+        }
+    }
+
+    /// Requests that `addr` be bound, returning false if the balancer
+    /// already has an endpoint for it.
+    fn request(&self, addr: SocketAddr) -> bool {
+        let mut inner = self.0.borrow_mut();
+        if inner.known.contains(&addr) {
+            return false;
+        }
+        if !inner.unknown.contains(&addr) {
+            inner.unknown.push(addr);
+        }
+        true
+    }
+}
+
+// ===== impl Pin =====
+
+impl<S> Pin<S> {
+    pub fn new(inner: S, choices: Choices, pins: Pins, unhealthy: UnhealthyPins) -> Self {
+        Pin {
+            inner: Rc::new(RefCell::new(inner)),
+            choices,
+            pins,
+            unhealthy,
+        }
+    }
+}
+
+impl<S, A> Service for Pin<S>
+where
+    S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = Error<S::Error>;
+    type Future = ResponseFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.borrow_mut().poll_ready().map_err(Error::Inner)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let fut = match req.extensions().get::<PinnedEndpoint>() {
+            None => ResponseFuture::Pending(self.inner.borrow_mut().call(req)),
+            Some(&PinnedEndpoint(addr)) => ResponseFuture::Dispatching {
+                service: self.inner.clone(),
+                choices: self.choices.clone(),
+                pins: self.pins.clone(),
+                unhealthy: self.unhealthy,
+                addr,
+                request: Some(req),
+            },
+        };
+        fut.dispatch()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S: Service> ResponseFuture<S> {
+    /// Sends the request once the service is ready with the pinned endpoint,
+    /// or fails it if the endpoint is unhealthy and may not be used.
+    fn dispatch(self) -> Self {
+        match self {
+            ResponseFuture::Dispatching {
+                service,
+                choices,
+                pins,
+                unhealthy,
+                addr,
+                mut request,
+            } => {
+                choices.pin(addr, unhealthy == UnhealthyPins::Allow);
+                let ready = service.borrow_mut().poll_ready();
+                let pinned = choices.unpin();
+
+                match ready {
+                    Ok(Async::Ready(())) if pinned => {
+                        let req = request.take().expect("dispatched after completed");
+                        let fut = service.borrow_mut().call(req);
+                        ResponseFuture::Pending(fut)
+                    }
+                    Ok(Async::Ready(())) if unhealthy == UnhealthyPins::Reject => {
+                        debug!("no healthy endpoint for pinned request to {}", addr);
+                        ResponseFuture::Failed(Some(Error::Unavailable(addr)))
+                    }
+                    Ok(ready) => {
+                        // If the balancer doesn't have the endpoint, its
+                        // discovery binds it when next polled. Otherwise, the
+                        // endpoint isn't ready, and the balancer notifies
+                        // this task once it is.
+                        if ready.is_ready() && pins.request(addr) {
+                            debug!("binding {} for pinned request", addr);
+                            task::current().notify();
+                        }
+                        ResponseFuture::Dispatching {
+                            service,
+                            choices,
+                            pins,
+                            unhealthy,
+                            addr,
+                            request,
+                        }
+                    }
+                    Err(e) => ResponseFuture::Failed(Some(Error::Inner(e))),
+                }
+            }
+            fut => fut,
+        }
+    }
+}
+
+impl<S: Service> Future for ResponseFuture<S> {
+    type Item = S::Response;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match mem::replace(self, ResponseFuture::Failed(None)) {
+                fut @ ResponseFuture::Dispatching { .. } => match fut.dispatch() {
+                    fut @ ResponseFuture::Dispatching { .. } => {
+                        *self = fut;
+                        return Ok(Async::NotReady);
+                    }
+                    fut => fut,
+                },
+                ResponseFuture::Pending(mut fut) => {
+                    let poll = fut.poll().map_err(Error::Inner);
+                    *self = ResponseFuture::Pending(fut);
+                    return poll;
+                }
+                ResponseFuture::Failed(e) => {
+                    return Err(e.expect("polled after failed"));
+                }
+            };
+        }
+    }
+}
+
+// ===== impl Error =====
+
+impl<E> fmt::Display for Error<E>
+where
+    E: fmt::Display
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Unavailable(ref addr) => write!(f, "pinned endpoint {} unavailable", addr),
+            Error::Inner(ref err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl<E> StdError for Error<E>
+where
+    E: StdError
+{
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::Inner(ref err) => Some(err),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Unavailable(_) => "pinned endpoint unavailable",
+            Error::Inner(ref err) => err.description(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::{self, FutureResult};
+
+    use balance::Weight;
+    use super::*;
+
+    /// A balancer over endpoints at `healthy`, and at `unhealthy`, that
+    /// binds the pinned addresses it doesn't know of, and responds with the
+    /// address it chose.
+    struct Balancer {
+        choices: Choices,
+        pins: Pins,
+        healthy: Vec<SocketAddr>,
+        unhealthy: Vec<SocketAddr>,
+        chosen: Option<SocketAddr>,
+    }
+
+    impl Service for Balancer {
+        type Request = http::Request<()>;
+        type Response = SocketAddr;
+        type Error = ();
+        type Future = FutureResult<SocketAddr, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            if let Some(addr) = self.pins.take_unknown() {
+                self.pins.insert(addr);
+                self.unhealthy.push(addr);
+            }
+
+            let chosen = match self.choices.pinned() {
+                Some((addr, allow_unhealthy)) => {
+                    let available = self.healthy.contains(&addr) ||
+                        (allow_unhealthy && self.unhealthy.contains(&addr));
+                    if available {
+                        self.choices.record_pinned(&Weight::new(1));
+                        addr
+                    } else {
+                        self.healthy[0]
+                    }
+                }
+                None => self.healthy[0],
+            };
+            self.chosen = Some(chosen);
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            future::ok(self.chosen.take().expect("called before ready"))
+        }
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn pin(unhealthy: UnhealthyPins) -> Pin<Balancer> {
+        let (choices, pins) = (Choices::default(), Pins::default());
+        let healthy = vec![addr("10.0.0.1:80"), addr("10.0.0.2:80")];
+        let unhealthy_addrs = vec![addr("10.0.0.3:80")];
+        for &a in healthy.iter().chain(&unhealthy_addrs) {
+            pins.insert(a);
+        }
+        let svc = Balancer {
+            choices: choices.clone(),
+            pins: pins.clone(),
+            healthy,
+            unhealthy: unhealthy_addrs,
+            chosen: None,
+        };
+        Pin::new(svc, choices, pins, unhealthy)
+    }
+
+    fn send(svc: &mut Pin<Balancer>, to: Option<&str>) -> Result<SocketAddr, Error<()>> {
+        let mut req = http::Request::new(());
+        if let Some(to) = to {
+            req.extensions_mut().insert(PinnedEndpoint(addr(to)));
+        }
+        assert!(svc.poll_ready().unwrap().is_ready());
+        future::lazy(|| svc.call(req)).wait()
+    }
+
+    #[test]
+    fn pinned_requests_are_sent_to_their_endpoints() {
+        let mut svc = pin(UnhealthyPins::Reject);
+        assert_eq!(send(&mut svc, Some("10.0.0.2:80")).unwrap(), addr("10.0.0.2:80"));
+        assert_eq!(send(&mut svc, None).unwrap(), addr("10.0.0.1:80"));
+    }
+
+    #[test]
+    fn rejects_pins_to_unhealthy_or_unknown_endpoints() {
+        let mut svc = pin(UnhealthyPins::Reject);
+        for &to in &["10.0.0.3:80", "10.0.0.9:80"] {
+            match send(&mut svc, Some(to)) {
+                Err(Error::Unavailable(a)) => assert_eq!(a, addr(to)),
+                rsp => panic!("pinned request to {} should fail: {:?}", to, rsp),
+            }
+        }
+        assert!(svc.pins.take_unknown().is_none(), "unknown pins should not be bound");
+    }
+
+    #[test]
+    fn allows_pins_to_unhealthy_or_unknown_endpoints() {
+        let mut svc = pin(UnhealthyPins::Allow);
+        assert_eq!(send(&mut svc, Some("10.0.0.3:80")).unwrap(), addr("10.0.0.3:80"));
+
+        // An unknown address is bound before the request is sent to it.
+        assert_eq!(send(&mut svc, Some("10.0.0.9:80")).unwrap(), addr("10.0.0.9:80"));
+        assert!(svc.inner.borrow().unhealthy.contains(&addr("10.0.0.9:80")));
+    }
+}
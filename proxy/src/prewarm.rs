@@ -0,0 +1,266 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+use futures::{task, Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+use tower::Service;
+
+/// How often a warm endpoint is readied again, so that a connection that
+/// has closed is replaced.
+const CHECK_INTERVAL_SECS: u64 = 1;
+
+/// Establishes a connection to an endpoint as soon as it's bound, rather
+/// than when it's first dispatched a request, and keeps one established.
+///
+/// The inner service is expected to connect as it becomes ready, as a
+/// `Reconnect` does. It's shared with a task that readies it when the
+/// endpoint is bound, and then readies it again every second, or every idle
+/// timeout if that's shorter. A connection still closes once it has idled
+/// for the idle timeout, and is then replaced by a new one. The task stops
+/// once this service is dropped.
+///
+/// HTTP/1 connections are opened by hyper's pool as requests are sent, so
+/// only HTTP/2 connections are established ahead of requests.
+///
+/// If constructed without prewarming, this is a no-op.
+pub struct Prewarm<S: Service> {
+    shared: Rc<RefCell<Shared<S>>>,
+}
+
+/// The service, shared by the task that dispatches requests to it and the
+/// task that keeps it warm.
+///
+/// A service notifies only the task that polled it last, so whichever task
+/// finds it ready notifies the other, if the other is waiting for it.
+struct Shared<S: Service> {
+    inner: S,
+    dispatcher: Option<task::Task>,
+    warmer: Option<task::Task>,
+    /// An error that the warming task found while the dispatching task was
+    /// waiting for the service to become ready.
+    error: Option<S::Error>,
+}
+
+/// Readies a shared service whenever it's due to be checked.
+struct Warm<S: Service> {
+    shared: Weak<RefCell<Shared<S>>>,
+    interval: Duration,
+    handle: Handle,
+    /// Fires when the service is next due to be checked.
+    timer: Option<ReactorTimeout>,
+    /// Whether the service was ready when it was last checked.
+    connected: bool,
+}
+
+// ===== impl Prewarm =====
+
+impl<S> Prewarm<S>
+where
+    S: Service + 'static,
+{
+    pub fn new(inner: S, prewarm: bool, idle_timeout: Option<Duration>, handle: &Handle) -> Self {
+        let shared = Rc::new(RefCell::new(Shared {
+            inner,
+            dispatcher: None,
+            warmer: None,
+            error: None,
+        }));
+
+        if prewarm {
+            let check_interval = Duration::from_secs(CHECK_INTERVAL_SECS);
+            let interval = idle_timeout
+                .map(|timeout| cmp::min(timeout, check_interval))
+                .unwrap_or(check_interval);
+            handle.spawn(Warm {
+                shared: Rc::downgrade(&shared),
+                interval,
+                handle: handle.clone(),
+                timer: None,
+                connected: false,
+            });
+        }
+
+        Prewarm { shared }
+    }
+}
+
+impl<S: Service> Service for Prewarm<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.shared.borrow_mut().poll_dispatch()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.shared.borrow_mut().inner.call(req)
+    }
+}
+
+// ===== impl Shared =====
+
+impl<S: Service> Shared<S> {
+    /// Readies the service for the task that dispatches requests to it.
+    fn poll_dispatch(&mut self) -> Poll<(), S::Error> {
+        if let Some(e) = self.error.take() {
+            self.dispatcher = None;
+            return Err(e);
+        }
+
+        let ready = self.inner.poll_ready();
+        if let Ok(Async::NotReady) = ready {
+            self.dispatcher = Some(task::current());
+        } else {
+            self.dispatcher = None;
+            if let Some(warmer) = self.warmer.take() {
+                warmer.notify();
+            }
+        }
+        ready
+    }
+
+    /// Readies the service for the task that keeps it warm, returning
+    /// whether it's ready or has failed.
+    fn poll_warm(&mut self) -> Async<Result<(), ()>> {
+        match self.inner.poll_ready() {
+            Ok(Async::NotReady) => {
+                self.warmer = Some(task::current());
+                Async::NotReady
+            }
+            Ok(Async::Ready(())) => {
+                self.warmer = None;
+                if let Some(dispatcher) = self.dispatcher.take() {
+                    dispatcher.notify();
+                }
+                Async::Ready(Ok(()))
+            }
+            Err(e) => {
+                self.warmer = None;
+                // Only a task that's waiting for the service to become ready
+                // would have seen this error.
+                if let Some(dispatcher) = self.dispatcher.take() {
+                    self.error = Some(e);
+                    dispatcher.notify();
+                }
+                Async::Ready(Err(()))
+            }
+        }
+    }
+}
+
+// ===== impl Warm =====
+
+impl<S: Service> Future for Warm<S> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            if let Some(ref mut timer) = self.timer {
+                match timer.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => {}
+                    Err(e) => {
+                        warn!("prewarm timer failed: {}", e);
+                        return Ok(Async::Ready(()));
+                    }
+                }
+            }
+            self.timer = None;
+
+            let shared = match self.shared.upgrade() {
+                Some(shared) => shared,
+                // The endpoint is no longer bound.
+                None => return Ok(Async::Ready(())),
+            };
+            let warmed = shared.borrow_mut().poll_warm();
+            match warmed {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(Ok(())) => self.connected = true,
+                // A connection that was established has closed, so it's
+                // replaced right away. Otherwise, connecting failed, and is
+                // tried again when the service is next checked.
+                Async::Ready(Err(())) if self.connected => {
+                    self.connected = false;
+                    continue;
+                }
+                Async::Ready(Err(())) => {}
+            }
+
+            match ReactorTimeout::new(self.interval, &self.handle) {
+                Ok(timer) => self.timer = Some(timer),
+                Err(e) => {
+                    warn!("could not create prewarm timer: {}", e);
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::future::{self, FutureResult};
+    use tokio_core::reactor::Core;
+
+    use super::*;
+
+    /// Counts the times it is readied.
+    struct Connects(Rc<Cell<usize>>);
+
+    impl Service for Connects {
+        type Request = ();
+        type Response = ();
+        type Error = ();
+        type Future = FutureResult<(), ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            self.0.set(self.0.get() + 1);
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    fn run_for(core: &mut Core, ms: u64) {
+        let timeout = ReactorTimeout::new(Duration::from_millis(ms), &core.handle()).unwrap();
+        core.run(timeout).unwrap();
+    }
+
+    #[test]
+    fn readies_the_service_until_it_is_dropped() {
+        let mut core = Core::new().unwrap();
+        let readied = Rc::new(Cell::new(0));
+        let idle_timeout = Some(Duration::from_millis(10));
+        let svc = Prewarm::new(Connects(readied.clone()), true, idle_timeout, &core.handle());
+
+        run_for(&mut core, 50);
+        assert!(readied.get() > 1, "readied {} times", readied.get());
+
+        drop(svc);
+        let readied_before_drop = readied.get();
+        run_for(&mut core, 50);
+        assert_eq!(readied.get(), readied_before_drop);
+    }
+
+    #[test]
+    fn does_nothing_without_prewarming() {
+        let mut core = Core::new().unwrap();
+        let readied = Rc::new(Cell::new(0));
+        let mut svc = Prewarm::new(Connects(readied.clone()), false, None, &core.handle());
+
+        run_for(&mut core, 20);
+        assert_eq!(readied.get(), 0);
+
+        assert!(svc.poll_ready().unwrap().is_ready());
+        assert_eq!(readied.get(), 1);
+    }
+}
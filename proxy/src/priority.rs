@@ -0,0 +1,358 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use futures::{task, Async, Future, Poll};
+use http;
+use http::header::HeaderName;
+use tower::Service;
+use tower_in_flight_limit::Error;
+
+/// How important a request is when capacity is constrained. Greater
+/// priorities are more important.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(u8);
+
+/// Classifies requests by `Priority`.
+///
+/// If a `header` is configured, a request whose value for it is an integer
+/// from 0 to 255 has that priority. Every other request has the default
+/// priority, which is the lowest unless a route sets another. By default,
+/// every request has the same priority, so none is preferred over another.
+#[derive(Clone, Debug, Default)]
+pub struct Classify {
+    header: Option<HeaderName>,
+    default: Priority,
+}
+
+/// Limits the number of requests in flight to `max`, shedding less important
+/// requests to admit more important ones when at the limit.
+///
+/// At the limit, a request that is more important than one in flight takes
+/// its place: the least important request in flight, and, among those, the
+/// most recently admitted, which has likely made the least progress, is
+/// canceled and fails with `Error::NoCapacity`. A request that isn't more
+/// important than any in flight fails with `Error::NoCapacity`, as it would
+/// with an `InFlightLimit`.
+///
+/// Unlike an `InFlightLimit`, requests are admitted when they're called
+/// rather than when the service is polled, once their priorities are known,
+/// so this is ready whenever its inner service is.
+pub struct PriorityLimit<S> {
+    inner: S,
+    classify: Classify,
+    slots: Rc<RefCell<Slots>>,
+}
+
+/// Fails if the request was rejected, or once it has been shed.
+pub struct ResponseFuture<F> {
+    inner: Option<(F, Permit)>,
+}
+
+/// Holds an admitted request's slot until dropped.
+struct Permit {
+    slots: Rc<RefCell<Slots>>,
+    admitted: Rc<Admitted>,
+}
+
+#[derive(Debug)]
+struct Admitted {
+    priority: Priority,
+    shed: Cell<bool>,
+    /// The task polling the request's response, notified if it's shed.
+    task: RefCell<Option<task::Task>>,
+}
+
+#[derive(Debug)]
+struct Slots {
+    max: usize,
+    /// The requests in flight, in the order they were admitted.
+    admitted: Vec<Rc<Admitted>>,
+}
+
+// ===== impl Priority =====
+
+impl Priority {
+    pub fn new(priority: u8) -> Self {
+        Priority(priority)
+    }
+}
+
+// ===== impl Classify =====
+
+impl Classify {
+    /// Classifies requests by their values for `header`.
+    pub fn with_header(self, header: HeaderName) -> Self {
+        Self {
+            header: Some(header),
+            ..self
+        }
+    }
+
+    /// Sets the priority of requests that aren't classified by header.
+    pub fn with_default(self, default: Priority) -> Self {
+        Self {
+            default,
+            ..self
+        }
+    }
+
+    pub fn classify<B>(&self, req: &http::Request<B>) -> Priority {
+        self.header.as_ref()
+            .and_then(|header| req.headers().get(header))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Priority)
+            .unwrap_or(self.default)
+    }
+}
+
+// ===== impl PriorityLimit =====
+
+impl<S> PriorityLimit<S> {
+    pub fn new(inner: S, max: usize, classify: Classify) -> Self {
+        let slots = Slots {
+            max,
+            admitted: Vec::new(),
+        };
+        PriorityLimit {
+            inner,
+            classify,
+            slots: Rc::new(RefCell::new(slots)),
+        }
+    }
+}
+
+impl<S, B> Service for PriorityLimit<S>
+where
+    S: Service<Request = http::Request<B>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = Error<S::Error>;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Error::Upstream)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let priority = self.classify.classify(&req);
+        let admitted = self.slots.borrow_mut().admit(priority);
+        let inner = match admitted {
+            Some(admitted) => {
+                let permit = Permit {
+                    slots: self.slots.clone(),
+                    admitted,
+                };
+                Some((self.inner.call(req), permit))
+            }
+            None => {
+                debug!("rejecting {:?} request at capacity", priority);
+                None
+            }
+        };
+        ResponseFuture { inner }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = Error<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let poll = match self.inner {
+            Some((ref mut f, ref permit)) => {
+                if permit.admitted.shed.get() {
+                    Err(Error::NoCapacity)
+                } else {
+                    let poll = f.poll();
+                    if let Ok(Async::NotReady) = poll {
+                        *permit.admitted.task.borrow_mut() = Some(task::current());
+                        return Ok(Async::NotReady);
+                    }
+                    poll.map_err(Error::Upstream)
+                }
+            }
+            None => Err(Error::NoCapacity),
+        };
+
+        // The request's slot is released as soon as it's answered, and a
+        // request that has been shed is canceled by dropping its future.
+        self.inner = None;
+        poll
+    }
+}
+
+// ===== impl Permit =====
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let admitted = &self.admitted;
+        self.slots.borrow_mut().admitted.retain(|a| !Rc::ptr_eq(a, admitted));
+    }
+}
+
+// ===== impl Slots =====
+
+impl Slots {
+    /// Admits a request of `priority`, shedding a less important request if
+    /// at the limit, or returns `None` if it can't be admitted.
+    ///
+    /// A shed request releases its slot immediately, so until its future is
+    /// next polled, one more request than `max` may be in flight.
+    fn admit(&mut self, priority: Priority) -> Option<Rc<Admitted>> {
+        if self.admitted.len() >= self.max {
+            let shed = self.admitted.iter()
+                .enumerate()
+                .filter(|&(_, a)| a.priority < priority)
+                .fold(None, |least: Option<(usize, Priority)>, (i, a)| match least {
+                    Some((_, p)) if p < a.priority => least,
+                    _ => Some((i, a.priority)),
+                })
+                .map(|(i, _)| i)?;
+
+            let shed = self.admitted.remove(shed);
+            debug!("shedding {:?} request for {:?} request", shed.priority, priority);
+            shed.shed.set(true);
+            if let Some(task) = shed.task.borrow_mut().take() {
+                task.notify();
+            }
+        }
+
+        let admitted = Rc::new(Admitted {
+            priority,
+            shed: Cell::new(false),
+            task: RefCell::new(None),
+        });
+        self.admitted.push(admitted.clone());
+        Some(admitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use futures::sync::oneshot;
+
+    use super::*;
+
+    /// Answers each request once its sender is sent.
+    #[derive(Default)]
+    struct Upstream {
+        pending: Vec<oneshot::Sender<()>>,
+    }
+
+    impl Service for Upstream {
+        type Request = http::Request<()>;
+        type Response = ();
+        type Error = oneshot::Canceled;
+        type Future = oneshot::Receiver<()>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let (tx, rx) = oneshot::channel();
+            self.pending.push(tx);
+            rx
+        }
+    }
+
+    fn request(priority: Option<&str>) -> http::Request<()> {
+        let mut req = http::Request::new(());
+        if let Some(priority) = priority {
+            req.headers_mut().insert("x-priority", priority.parse().unwrap());
+        }
+        req
+    }
+
+    fn is_shed<T, E>(poll: Poll<T, Error<E>>) -> bool {
+        match poll {
+            Err(Error::NoCapacity) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn classifies_requests_by_header() {
+        let classify = Classify::default();
+        assert_eq!(classify.classify(&request(Some("3"))), Priority(0));
+
+        let classify = classify.with_header(HeaderName::from_static("x-priority"));
+        assert_eq!(classify.classify(&request(Some("3"))), Priority(3));
+        assert_eq!(classify.classify(&request(Some("high"))), Priority(0));
+        assert_eq!(classify.classify(&request(Some("256"))), Priority(0));
+        assert_eq!(classify.classify(&request(None)), Priority(0));
+
+        let classify = classify.with_default(Priority(2));
+        assert_eq!(classify.classify(&request(Some("1"))), Priority(1));
+        assert_eq!(classify.classify(&request(None)), Priority(2));
+    }
+
+    #[test]
+    fn more_important_requests_preempt_less_important_ones() {
+        let classify = Classify::default().with_header(HeaderName::from_static("x-priority"));
+        let mut svc = PriorityLimit::new(Upstream::default(), 2, classify);
+
+        future::lazy(|| {
+            // Capacity is filled with unimportant requests.
+            let mut low_a = svc.call(request(None));
+            let mut low_b = svc.call(request(None));
+            assert!(low_a.poll().unwrap().is_not_ready());
+            assert!(low_b.poll().unwrap().is_not_ready());
+
+            // An important request is admitted in place of the most recent
+            // of them, which is canceled.
+            let mut high_a = svc.call(request(Some("1")));
+            assert!(high_a.poll().unwrap().is_not_ready());
+            assert!(is_shed(low_b.poll()));
+            assert!(svc.inner.pending[1].is_canceled());
+            assert!(low_a.poll().unwrap().is_not_ready());
+
+            // Further unimportant requests are rejected without being sent.
+            assert!(is_shed(svc.call(request(None)).poll()));
+            assert_eq!(svc.inner.pending.len(), 3);
+
+            // Another important request preempts the remaining unimportant
+            // one, but once capacity is filled with important requests,
+            // requests as important are rejected.
+            let mut high_b = svc.call(request(Some("1")));
+            assert!(high_b.poll().unwrap().is_not_ready());
+            assert!(is_shed(low_a.poll()));
+            assert!(is_shed(svc.call(request(Some("1"))).poll()));
+            assert!(high_a.poll().unwrap().is_not_ready());
+
+            // As important requests are answered, capacity is released.
+            svc.inner.pending.remove(2).send(()).unwrap();
+            assert!(high_a.poll().unwrap().is_ready());
+            let mut low_c = svc.call(request(None));
+            assert!(low_c.poll().unwrap().is_not_ready());
+            assert!(high_b.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn a_single_tier_is_never_preempted() {
+        let mut svc = PriorityLimit::new(Upstream::default(), 1, Classify::default());
+
+        future::lazy(|| {
+            let mut first = svc.call(request(Some("1")));
+            assert!(first.poll().unwrap().is_not_ready());
+
+            // Without a header to classify requests by, every request is as
+            // important as every other.
+            assert!(is_shed(svc.call(request(Some("255"))).poll()));
+            assert!(first.poll().unwrap().is_not_ready());
+
+            svc.inner.pending.remove(0).send(()).unwrap();
+            assert!(first.poll().unwrap().is_ready());
+            let mut next = svc.call(request(None));
+            assert!(next.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
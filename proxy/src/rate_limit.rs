@@ -0,0 +1,390 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use http;
+use http::header::{CONTENT_LENGTH, RETRY_AFTER};
+use http::uri::Authority;
+use tower::Service;
+
+use transparency::h1;
+
+/// Settings for rate limiting requests.
+///
+/// Requests may be limited by a `global` rate, shared by every service bound
+/// by a `Bind`, and by a `per_authority` rate, shared by every service that
+/// receives requests for the same authority.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RateLimitConfig {
+    global: Option<Rate>,
+    per_authority: Option<Rate>,
+}
+
+/// A sustained rate of requests, allowing bursts of up to `burst` requests.
+#[derive(Copy, Clone, Debug)]
+pub struct Rate {
+    per_second: u32,
+    burst: u32,
+}
+
+/// The token buckets shared by every service bound with the same
+/// `RateLimitConfig`.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    config: RateLimitConfig,
+    global: Arc<Mutex<Option<Bucket>>>,
+    authorities: Arc<Mutex<HashMap<Authority, Bucket>>>,
+}
+
+/// Responds to requests in excess of the configured rates with
+/// `429 Too Many Requests`, rather than dispatching them.
+///
+/// The response's `Retry-After` header indicates how many seconds remain
+/// until a request would be allowed. Requests without an authority are only
+/// limited by the global rate.
+///
+/// If constructed without rates, this is a no-op.
+#[derive(Debug)]
+pub struct RateLimit<S> {
+    inner: S,
+    limits: RateLimits,
+}
+
+pub struct ResponseFuture<F>(Inner<F>);
+
+enum Inner<F> {
+    Dispatched(F),
+    Limited { retry_after: Duration },
+}
+
+/// A token bucket that refills continuously, by the time elapsed since it
+/// was last refilled.
+#[derive(Debug)]
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    refilled: Instant,
+}
+
+// ===== impl RateLimitConfig =====
+
+impl RateLimitConfig {
+    /// Limits requests to all bound services to `rate`.
+    pub fn with_global(self, rate: Rate) -> Self {
+        Self {
+            global: Some(rate),
+            ..self
+        }
+    }
+
+    /// Limits requests for each authority to `rate`.
+    pub fn with_per_authority(self, rate: Rate) -> Self {
+        Self {
+            per_authority: Some(rate),
+            ..self
+        }
+    }
+}
+
+// ===== impl Rate =====
+
+impl Rate {
+    /// Allows `per_second` requests each second, and bursts of as many.
+    pub fn per_second(per_second: u32) -> Self {
+        assert!(per_second > 0, "rate must be positive");
+        Rate {
+            per_second,
+            burst: per_second,
+        }
+    }
+
+    /// Allows bursts of up to `burst` requests.
+    pub fn with_burst(self, burst: u32) -> Self {
+        assert!(burst > 0, "burst must be positive");
+        Self {
+            burst,
+            ..self
+        }
+    }
+}
+
+// ===== impl RateLimits =====
+
+impl RateLimits {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        RateLimits {
+            config,
+            global: Arc::new(Mutex::new(config.global.map(|rate| Bucket::full(rate, now)))),
+            authorities: Default::default(),
+        }
+    }
+
+    /// Wraps `inner` in a `RateLimit` that shares these buckets.
+    pub fn rate_limit<S>(&self, inner: S) -> RateLimit<S> {
+        RateLimit {
+            inner,
+            limits: self.clone(),
+        }
+    }
+
+    /// Takes a token for a request to `authority` from each bucket that
+    /// applies to it.
+    ///
+    /// If any bucket is empty, no tokens are taken, and the time until every
+    /// bucket has a token is returned.
+    fn acquire(&self, authority: Option<&Authority>, now: Instant) -> Result<(), Duration> {
+        let mut authorities = self.authorities.lock().expect("rate limit authorities lock");
+        let per_authority = match (self.config.per_authority, authority) {
+            (Some(rate), Some(authority)) => {
+                if !authorities.contains_key(authority) {
+                    // Buckets that have refilled completely are no different
+                    // from new ones, so there's no need to keep them.
+                    authorities.retain(|_, bucket| !bucket.is_full(now));
+                    authorities.insert(authority.clone(), Bucket::full(rate, now));
+                }
+                authorities.get_mut(authority)
+            }
+            _ => None,
+        };
+
+        let mut global = self.global.lock().expect("rate limit global lock");
+        let mut buckets = per_authority.into_iter().chain(global.as_mut()).collect::<Vec<_>>();
+
+        let mut wait = Duration::from_secs(0);
+        for bucket in &mut buckets {
+            bucket.refill(now);
+            wait = cmp::max(wait, bucket.wait());
+        }
+        if wait > Duration::from_secs(0) {
+            return Err(wait);
+        }
+
+        for bucket in &mut buckets {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+// ===== impl RateLimit =====
+
+impl<S, A, B> Service for RateLimit<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    B: Default,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        // HTTP/1 requests may not have been normalized yet, so the authority
+        // may only be in the `Host` header.
+        let authority = req.uri().authority_part()
+            .cloned()
+            .or_else(|| h1::authority_from_host(&req));
+
+        match self.limits.acquire(authority.as_ref(), Instant::now()) {
+            Ok(()) => ResponseFuture(Inner::Dispatched(self.inner.call(req))),
+            Err(retry_after) => {
+                debug!("rate limited request to {:?} for {:?}", authority, retry_after);
+                ResponseFuture(Inner::Limited { retry_after })
+            }
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Default,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Inner::Dispatched(ref mut f) => f.poll(),
+            Inner::Limited { retry_after } => {
+                // Round up, so that a request retried after `Retry-After`
+                // seconds is allowed.
+                let mut secs = retry_after.as_secs();
+                if retry_after.subsec_nanos() > 0 {
+                    secs += 1;
+                }
+                let rsp = http::Response::builder()
+                    .status(http::StatusCode::TOO_MANY_REQUESTS)
+                    .header(RETRY_AFTER, &*secs.to_string())
+                    .header(CONTENT_LENGTH, "0")
+                    .body(Default::default())
+                    .expect("rate limited response must be valid");
+                Ok(Async::Ready(rsp))
+            }
+        }
+    }
+}
+
+// ===== impl Bucket =====
+
+impl Bucket {
+    fn full(rate: Rate, now: Instant) -> Self {
+        Bucket {
+            rate,
+            tokens: rate.burst as f64,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.refilled {
+            let elapsed = secs(now - self.refilled);
+            let tokens = self.tokens + elapsed * self.rate.per_second as f64;
+            self.tokens = tokens.min(self.rate.burst as f64);
+            self.refilled = now;
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.rate.burst as f64
+    }
+
+    /// Returns the time until a token is available.
+    fn wait(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::from_secs(0);
+        }
+        let secs = (1.0 - self.tokens) / self.rate.per_second as f64;
+        Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+    }
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use futures::future::{self, FutureResult};
+
+    use super::*;
+
+    /// Responds to every request with `200 OK`.
+    struct Ok200;
+
+    impl Service for Ok200 {
+        type Request = http::Request<()>;
+        type Response = http::Response<()>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            future::ok(http::Response::new(()))
+        }
+    }
+
+    fn request(authority: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri(format!("http://{}/", authority).as_str())
+            .body(())
+            .unwrap()
+    }
+
+    fn status<S>(svc: &mut S, authority: &str) -> http::StatusCode
+    where
+        S: Service<Request = http::Request<()>, Response = http::Response<()>, Error = ()>,
+    {
+        svc.call(request(authority)).wait().unwrap().status()
+    }
+
+    #[test]
+    fn rejects_bursts_until_refilled() {
+        let rate = Rate::per_second(20).with_burst(2);
+        let limits = RateLimits::new(RateLimitConfig::default().with_global(rate));
+        let mut svc = limits.rate_limit(Ok200);
+
+        assert_eq!(status(&mut svc, "a.example.com"), http::StatusCode::OK);
+        assert_eq!(status(&mut svc, "a.example.com"), http::StatusCode::OK);
+
+        let rsp = svc.call(request("a.example.com")).wait().unwrap();
+        assert_eq!(rsp.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rsp.headers()[RETRY_AFTER], "1");
+
+        // A token is added every 50ms.
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(status(&mut svc, "a.example.com"), http::StatusCode::OK);
+        assert_eq!(status(&mut svc, "a.example.com"), http::StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn global_limit_is_shared_by_all_services() {
+        let rate = Rate::per_second(1);
+        let limits = RateLimits::new(RateLimitConfig::default().with_global(rate));
+        let mut a = limits.rate_limit(Ok200);
+        let mut b = limits.rate_limit(Ok200);
+
+        assert_eq!(status(&mut a, "a.example.com"), http::StatusCode::OK);
+        assert_eq!(status(&mut b, "b.example.com"), http::StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn authorities_are_limited_independently() {
+        let rate = Rate::per_second(1);
+        let limits = RateLimits::new(RateLimitConfig::default().with_per_authority(rate));
+        let mut a = limits.rate_limit(Ok200);
+        let mut b = limits.rate_limit(Ok200);
+
+        assert_eq!(status(&mut a, "a.example.com"), http::StatusCode::OK);
+        assert_eq!(status(&mut b, "a.example.com"), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status(&mut b, "b.example.com"), http::StatusCode::OK);
+    }
+
+    #[test]
+    fn limited_requests_do_not_take_tokens_from_other_buckets() {
+        let config = RateLimitConfig::default()
+            .with_global(Rate::per_second(2))
+            .with_per_authority(Rate::per_second(1));
+        let limits = RateLimits::new(config);
+        let mut svc = limits.rate_limit(Ok200);
+
+        assert_eq!(status(&mut svc, "a.example.com"), http::StatusCode::OK);
+        assert_eq!(status(&mut svc, "a.example.com"), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status(&mut svc, "b.example.com"), http::StatusCode::OK);
+        assert_eq!(status(&mut svc, "c.example.com"), http::StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn buckets_refill_continuously() {
+        let now = Instant::now();
+        let mut bucket = Bucket::full(Rate::per_second(10), now);
+        bucket.tokens = 0.0;
+        assert_eq!(bucket.wait(), Duration::from_millis(100));
+
+        bucket.refill(now + Duration::from_millis(250));
+        assert!((bucket.tokens - 2.5).abs() < 1e-6);
+        assert_eq!(bucket.wait(), Duration::from_secs(0));
+
+        bucket.refill(now + Duration::from_secs(60));
+        assert!(bucket.is_full(now + Duration::from_secs(60)));
+        assert_eq!(bucket.tokens, 10.0);
+    }
+}
@@ -0,0 +1,466 @@
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
+use futures::{Async, Future, Poll};
+use h2;
+use http;
+use http::header::CONTENT_LENGTH;
+use tower::Service;
+use tower_h2::Body;
+
+use budget::RequestBudget;
+use retry::RetryPolicy;
+use transparency::{h1, HttpBody};
+
+/// Determines how much of a request's body may be read into memory, so that
+/// the request can be sent more than once.
+///
+/// The retry and mirror layers share the bodies buffered according to this
+/// policy: once a layer has buffered a body, it records it in the request's
+/// extensions, and the layers beneath it replay the request from that copy
+/// rather than buffering the body again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BufferPolicy {
+    max_bytes: u64,
+}
+
+/// A request body that has been read into memory in its entirety.
+#[derive(Clone, Debug, Default)]
+pub struct Buffered {
+    data: Option<Bytes>,
+    trailers: Option<http::HeaderMap>,
+}
+
+/// A request body that can be recreated from a buffered copy.
+pub trait FromBuffered: Default {
+    fn from_buffered(buffered: Buffered) -> Self;
+}
+
+/// Buffers the bodies of requests that the `RetryPolicy` could replay.
+///
+/// A body that fits within the `BufferPolicy` is read before the request is
+/// dispatched, so that the request may be retried or resent. A body that
+/// turns out to be larger is streamed to the inner service after the data
+/// that was read, and its request is not replayable. If constructed without
+/// a `BufferPolicy`, bodies are never buffered.
+pub struct BufferRequests<S> {
+    inner: Rc<RefCell<S>>,
+    buffer: Option<BufferPolicy>,
+    retry_policy: RetryPolicy,
+}
+
+pub struct ResponseFuture<S: Service, B> {
+    service: Rc<RefCell<S>>,
+    state: State<S::Future, B>,
+}
+
+enum State<F, B> {
+    /// Reading the body of a request.
+    Buffering(Option<http::request::Parts>, BufferBody<B>),
+    /// Waiting for the inner service to become ready for a buffered request.
+    Dispatching(Option<http::Request<ReplayBody<B>>>),
+    /// Waiting for the inner service's response.
+    Pending(F),
+}
+
+/// Reads a body into memory until it ends or exceeds a limit.
+///
+/// The bytes read are spent from the request's `RequestBudget`, if it has
+/// one, and the body fails if they exhaust it.
+pub struct BufferBody<B> {
+    body: Option<B>,
+    data: BytesMut,
+    data_ended: bool,
+    max_bytes: u64,
+    budget: Option<RequestBudget>,
+}
+
+/// A request body that has either been buffered or is being streamed.
+#[derive(Debug)]
+pub enum ReplayBody<B> {
+    Buffered(Buffered),
+    /// A body that was too large to buffer, and the data that was read from
+    /// it before that was known.
+    Streaming(Option<Bytes>, B),
+    /// A body that failed while it was being buffered, so that the request
+    /// fails as it would have if its body had been streamed.
+    Failed(Option<h2::Error>),
+}
+
+pub enum Data<D> {
+    Buffered(Cursor<Bytes>),
+    Streaming(D),
+}
+
+// ===== impl BufferPolicy =====
+
+impl BufferPolicy {
+    /// Buffers bodies of up to `max_bytes`.
+    pub fn new(max_bytes: u64) -> Self {
+        BufferPolicy { max_bytes }
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Returns true if a body sent with `headers` may be buffered.
+    ///
+    /// A body that declares a length longer than the limit is never
+    /// buffered, nor is a body that a client won't send until it is told to
+    /// `100 Continue`, since reading it ahead of the upstream would tell the
+    /// client to continue before the upstream has agreed.
+    pub fn may_buffer(&self, headers: &http::HeaderMap) -> bool {
+        if h1::expects_continue(headers) {
+            return false;
+        }
+        headers.get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<u64>().ok())
+            .map(|len| len <= self.max_bytes)
+            .unwrap_or(true)
+    }
+
+    /// Reads `body` into memory, unless it exceeds the limit.
+    pub fn buffer<B>(&self, body: B) -> BufferBody<B> {
+        BufferBody {
+            body: Some(body),
+            data: BytesMut::new(),
+            data_ended: false,
+            max_bytes: self.max_bytes,
+            budget: None,
+        }
+    }
+}
+
+// ===== impl Buffered =====
+
+impl Buffered {
+    /// Returns the buffered body of `req`, if a layer above has buffered it.
+    pub fn of<B>(req: &http::Request<B>) -> Option<&Buffered> {
+        req.extensions().get::<Buffered>()
+    }
+}
+
+impl FromBuffered for HttpBody {
+    fn from_buffered(buffered: Buffered) -> Self {
+        HttpBody::Buffered(buffered.data, buffered.trailers)
+    }
+}
+
+// ===== impl BufferRequests =====
+
+impl<S> BufferRequests<S> {
+    /// Buffers the bodies of requests with methods that `retry_policy`
+    /// replays, according to `buffer`.
+    pub fn new(inner: S, buffer: Option<BufferPolicy>, retry_policy: RetryPolicy) -> Self {
+        BufferRequests {
+            inner: Rc::new(RefCell::new(inner)),
+            buffer,
+            retry_policy,
+        }
+    }
+
+    fn buffers<B: Body>(&self, req: &http::Request<B>) -> Option<BufferPolicy> {
+        let buffer = self.buffer?;
+        let buffers = self.retry_policy.replays(req.method()) &&
+            !req.body().is_end_stream() &&
+            Buffered::of(req).is_none() &&
+            buffer.may_buffer(req.headers());
+        if buffers {
+            Some(buffer)
+        } else {
+            None
+        }
+    }
+}
+
+impl<S, B> Service for BufferRequests<S>
+where
+    S: Service<Request = http::Request<ReplayBody<B>>>,
+    B: Body,
+{
+    type Request = http::Request<B>;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.borrow_mut().poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let state = match self.buffers(&req) {
+            Some(buffer) => {
+                let budget = RequestBudget::of(&req).cloned();
+                let (parts, body) = req.into_parts();
+                State::Buffering(Some(parts), buffer.buffer(body).with_budget(budget))
+            }
+            None => {
+                let req = req.map(|body| ReplayBody::Streaming(None, body));
+                State::Pending(self.inner.borrow_mut().call(req))
+            }
+        };
+        ResponseFuture {
+            service: self.inner.clone(),
+            state,
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, B> Future for ResponseFuture<S, B>
+where
+    S: Service<Request = http::Request<ReplayBody<B>>>,
+    B: Body,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Pending(ref mut future) => return future.poll(),
+                State::Buffering(ref mut parts, ref mut body) => {
+                    let body = match body.poll() {
+                        Ok(Async::Ready(body)) => body,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => ReplayBody::Failed(Some(e)),
+                    };
+                    let mut parts = parts.take().expect("polled after ready");
+                    if let ReplayBody::Buffered(ref buffered) = body {
+                        parts.extensions.insert(buffered.clone());
+                    }
+                    State::Dispatching(Some(http::Request::from_parts(parts, body)))
+                }
+                State::Dispatching(ref mut req) => {
+                    try_ready!(self.service.borrow_mut().poll_ready());
+                    let req = req.take().expect("request dispatched more than once");
+                    State::Pending(self.service.borrow_mut().call(req))
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+// ===== impl BufferBody =====
+
+impl<B> BufferBody<B> {
+    /// Spends the bytes read from `budget`, if there is one.
+    pub fn with_budget(self, budget: Option<RequestBudget>) -> Self {
+        Self { budget, ..self }
+    }
+}
+
+impl<B: Body> Future for BufferBody<B> {
+    type Item = ReplayBody<B>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let trailers = {
+            let body = self.body.as_mut().expect("polled after ready");
+            while !self.data_ended {
+                match try_ready!(body.poll_data()) {
+                    Some(data) => {
+                        let data = data.into_buf();
+                        if let Some(ref budget) = self.budget {
+                            if let Err(exhausted) = budget.spend_buffered(data.remaining() as u64) {
+                                budget.exhaust(exhausted);
+                                return Err(h2::Reason::CANCEL.into());
+                            }
+                        }
+                        let len = (self.data.len() + data.remaining()) as u64;
+                        self.data.reserve(data.remaining());
+                        self.data.put(data);
+                        if len > self.max_bytes {
+                            debug!("body exceeded {} bytes; streaming it", self.max_bytes);
+                            break;
+                        }
+                    }
+                    None => self.data_ended = true,
+                }
+            }
+            if self.data_ended {
+                Some(try_ready!(body.poll_trailers()))
+            } else {
+                None
+            }
+        };
+
+        let data = if self.data.is_empty() {
+            None
+        } else {
+            Some(self.data.take().freeze())
+        };
+        let body = self.body.take().expect("polled after ready");
+        let body = match trailers {
+            Some(trailers) => ReplayBody::Buffered(Buffered { data, trailers }),
+            None => ReplayBody::Streaming(data, body),
+        };
+        Ok(Async::Ready(body))
+    }
+}
+
+// ===== impl ReplayBody =====
+
+impl<B: Body> Body for ReplayBody<B> {
+    type Data = Data<<B::Data as IntoBuf>::Buf>;
+
+    fn is_end_stream(&self) -> bool {
+        match *self {
+            ReplayBody::Buffered(ref b) => b.data.is_none() && b.trailers.is_none(),
+            ReplayBody::Streaming(ref data, ref body) => data.is_none() && body.is_end_stream(),
+            ReplayBody::Failed(_) => false,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        match *self {
+            ReplayBody::Buffered(ref mut b) => {
+                Ok(Async::Ready(b.data.take().map(|d| Data::Buffered(d.into_buf()))))
+            }
+            ReplayBody::Streaming(ref mut data, ref mut body) => {
+                if let Some(data) = data.take() {
+                    return Ok(Async::Ready(Some(Data::Buffered(data.into_buf()))));
+                }
+                let data = try_ready!(body.poll_data());
+                Ok(Async::Ready(data.map(|d| Data::Streaming(d.into_buf()))))
+            }
+            ReplayBody::Failed(ref mut e) => Err(failed(e)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match *self {
+            ReplayBody::Buffered(ref mut b) => Ok(Async::Ready(b.trailers.take())),
+            ReplayBody::Streaming(_, ref mut body) => body.poll_trailers(),
+            ReplayBody::Failed(ref mut e) => Err(failed(e)),
+        }
+    }
+}
+
+/// Returns the error that a body failed with, the first time it is polled.
+fn failed(error: &mut Option<h2::Error>) -> h2::Error {
+    error.take().unwrap_or_else(|| h2::Reason::INTERNAL_ERROR.into())
+}
+
+impl<B> Default for ReplayBody<B> {
+    fn default() -> Self {
+        ReplayBody::Buffered(Buffered::default())
+    }
+}
+
+impl<B> FromBuffered for ReplayBody<B> {
+    fn from_buffered(buffered: Buffered) -> Self {
+        ReplayBody::Buffered(buffered)
+    }
+}
+
+// ===== impl Data =====
+
+impl<D: Buf> Buf for Data<D> {
+    fn remaining(&self) -> usize {
+        match *self {
+            Data::Buffered(ref d) => d.remaining(),
+            Data::Streaming(ref d) => d.remaining(),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Data::Buffered(ref d) => d.bytes(),
+            Data::Streaming(ref d) => d.bytes(),
+        }
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        match *self {
+            Data::Buffered(ref mut d) => d.advance(cnt),
+            Data::Streaming(ref mut d) => d.advance(cnt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::{self, FutureResult};
+
+    use retry::{Retry, RetryBudget};
+    use test_support::send;
+
+    use super::*;
+
+    /// Fails the first `failures` requests it receives, and then responds
+    /// with `200 OK`, recording the body of each request.
+    struct Upstream {
+        failures: usize,
+        bodies: Rc<RefCell<Vec<Bytes>>>,
+    }
+
+    impl Service for Upstream {
+        type Request = http::Request<ReplayBody<HttpBody>>;
+        type Response = http::Response<()>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            let mut body = req.into_body();
+            let mut data = BytesMut::new();
+            while let Async::Ready(Some(chunk)) = body.poll_data().unwrap() {
+                data.extend_from_slice(chunk.bytes());
+            }
+            self.bodies.borrow_mut().push(data.freeze());
+
+            if self.failures > 0 {
+                self.failures -= 1;
+                return future::err(());
+            }
+            future::ok(http::Response::new(()))
+        }
+    }
+
+    /// Sends a `POST` with `body` through a service that retries once, and
+    /// buffers bodies of up to ten bytes, to an upstream that fails once.
+    fn send(body: &'static str) -> (Result<http::Response<()>, ()>, Vec<Bytes>) {
+        let bodies = Rc::new(RefCell::new(Vec::new()));
+        let upstream = Upstream {
+            failures: 1,
+            bodies: bodies.clone(),
+        };
+        let policy = RetryPolicy::new(1).with_method(http::Method::POST);
+        let budget = RetryBudget::new(&policy);
+        let retry = Retry::new(upstream, policy.clone(), budget);
+        let mut svc = BufferRequests::new(retry, Some(BufferPolicy::new(10)), policy);
+
+        // The body's length isn't declared, so it's only known once it has
+        // been read whether it fits.
+        let mut req = http::Request::new(HttpBody::Buffered(Some(Bytes::from(body)), None));
+        *req.method_mut() = http::Method::POST;
+        let rsp = send(&mut svc, req).wait();
+
+        let bodies = bodies.borrow().clone();
+        (rsp, bodies)
+    }
+
+    #[test]
+    fn small_bodies_are_buffered_and_retried() {
+        let (rsp, bodies) = send("hello");
+        assert!(rsp.is_ok());
+        assert_eq!(bodies, vec![Bytes::from("hello"), Bytes::from("hello")]);
+    }
+
+    #[test]
+    fn large_bodies_are_streamed_and_not_retried() {
+        let (rsp, bodies) = send("longer than ten bytes");
+        assert!(rsp.is_err());
+        assert_eq!(bodies, vec![Bytes::from("longer than ten bytes")]);
+    }
+}
@@ -0,0 +1,286 @@
+use std::sync::Arc;
+
+use futures::{Async, Future, Poll};
+use http;
+use http::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_EXPOSE_HEADERS,
+    ALLOW,
+    CACHE_CONTROL,
+    CONTENT_LANGUAGE,
+    LINK,
+    SET_COOKIE,
+    VARY,
+    VIA,
+    WARNING,
+    HeaderMap,
+    HeaderName,
+    HeaderValue,
+};
+use tower::{NewService, Service};
+
+/// How an injected header is combined with the values that the upstream
+/// sent for it, if any.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Replaces any values the upstream sent.
+    Override,
+    /// Adds the value alongside those the upstream sent, unless the upstream
+    /// already sent it. Headers that may only have a single value keep the
+    /// upstream's value, if it sent one.
+    Append,
+}
+
+/// The headers added to every proxied response.
+#[derive(Clone, Debug, Default)]
+pub struct ResponseHeaders {
+    headers: Arc<Vec<(HeaderName, HeaderValue, Policy)>>,
+}
+
+/// Adds `ResponseHeaders` to the responses of the inner service, such as a
+/// `Server` header or security headers that the upstream doesn't set itself.
+///
+/// If constructed without any headers, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct InjectHeaders<S> {
+    inner: S,
+    headers: ResponseHeaders,
+}
+
+/// Wraps the inner `NewService`'s services in `InjectHeaders`.
+pub struct Init<F> {
+    future: F,
+    headers: ResponseHeaders,
+}
+
+/// Adds the headers to the response once it is received.
+pub struct ResponseFuture<F> {
+    inner: F,
+    headers: ResponseHeaders,
+}
+
+// ===== impl ResponseHeaders =====
+
+impl ResponseHeaders {
+    /// Adds `value` for `name` to every response, according to `policy`.
+    ///
+    /// Headers are added in the order they're configured, so a header
+    /// configured more than once may have several values appended, or
+    /// override those added before it.
+    pub fn with_header(self, name: HeaderName, value: HeaderValue, policy: Policy) -> Self {
+        let mut headers = (*self.headers).clone();
+        headers.push((name, value, policy));
+        ResponseHeaders {
+            headers: Arc::new(headers),
+        }
+    }
+
+    fn inject(&self, headers: &mut HeaderMap) {
+        for &(ref name, ref value, policy) in self.headers.iter() {
+            match policy {
+                Policy::Override => {
+                    headers.insert(name.clone(), value.clone());
+                }
+                Policy::Append => {
+                    if headers.contains_key(name) {
+                        if !is_list(name) || contains(headers, name, value) {
+                            continue;
+                        }
+                    }
+                    headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Returns whether `name`'s values are comma-separated lists, or, like
+/// `Set-Cookie`, it may be sent more than once, so that an appended value
+/// may be sent along with the upstream's.
+fn is_list(name: &HeaderName) -> bool {
+    [
+        ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS,
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        ALLOW,
+        CACHE_CONTROL,
+        CONTENT_LANGUAGE,
+        LINK,
+        SET_COOKIE,
+        VARY,
+        VIA,
+        WARNING,
+    ].contains(name)
+}
+
+/// Returns whether any of the values of `name` in `headers` is `value`,
+/// including as an element of a comma-separated list.
+fn contains(headers: &HeaderMap, name: &HeaderName, value: &HeaderValue) -> bool {
+    let value = match value.to_str() {
+        Ok(value) => value.trim(),
+        Err(_) => return headers.get_all(name).iter().any(|v| v == value),
+    };
+    headers.get_all(name).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case(value))
+}
+
+// ===== impl InjectHeaders =====
+
+impl<S> InjectHeaders<S> {
+    pub fn new(inner: S, headers: ResponseHeaders) -> Self {
+        InjectHeaders { inner, headers }
+    }
+}
+
+impl<N, A, B> NewService for InjectHeaders<N>
+where
+    N: NewService<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+{
+    type Request = N::Request;
+    type Response = N::Response;
+    type Error = N::Error;
+    type Service = InjectHeaders<N::Service>;
+    type InitError = N::InitError;
+    type Future = Init<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        Init {
+            future: self.inner.new_service(),
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+impl<S, A, B> Service for InjectHeaders<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(req),
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+// ===== impl Init =====
+
+impl<F> Future for Init<F>
+where
+    F: Future,
+{
+    type Item = InjectHeaders<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(InjectHeaders::new(inner, self.headers.clone())))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+        self.headers.inject(rsp.headers_mut());
+        Ok(Async::Ready(rsp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{SERVER, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS};
+
+    use super::*;
+    use test_support::{headers, Upstream};
+
+    /// Responds to each request with the same headers.
+    fn upstream(pairs: &[(&'static str, &'static str)]) -> Upstream {
+        Upstream::with_body(headers(pairs), &[])
+    }
+
+    fn injected() -> ResponseHeaders {
+        let value = HeaderValue::from_static;
+        ResponseHeaders::default()
+            .with_header(SERVER, value("conduit"), Policy::Append)
+            .with_header(X_CONTENT_TYPE_OPTIONS, value("nosniff"), Policy::Override)
+            .with_header(STRICT_TRANSPORT_SECURITY, value("max-age=31536000"), Policy::Override)
+            .with_header(VARY, value("origin"), Policy::Append)
+    }
+
+    fn respond(upstream: Upstream, headers: ResponseHeaders) -> HeaderMap {
+        let mut svc = InjectHeaders::new(upstream, headers);
+        let rsp = svc.call(http::Request::new(())).wait().unwrap();
+        rsp.headers().clone()
+    }
+
+    fn values(headers: &HeaderMap, name: HeaderName) -> Vec<&str> {
+        headers.get_all(name).iter().map(|v| v.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn headers_are_added_to_responses() {
+        let headers = respond(upstream(&[("content-type", "text/html")]), injected());
+        assert_eq!(values(&headers, SERVER), vec!["conduit"]);
+        assert_eq!(values(&headers, X_CONTENT_TYPE_OPTIONS), vec!["nosniff"]);
+        assert_eq!(values(&headers, STRICT_TRANSPORT_SECURITY), vec!["max-age=31536000"]);
+        assert_eq!(values(&headers, VARY), vec!["origin"]);
+        assert_eq!(values(&headers, http::header::CONTENT_TYPE), vec!["text/html"]);
+    }
+
+    #[test]
+    fn overridden_headers_replace_the_upstreams() {
+        let headers = respond(upstream(&[
+            ("x-content-type-options", "sniff"),
+            ("strict-transport-security", "max-age=0"),
+            ("strict-transport-security", "max-age=60"),
+        ]), injected());
+        assert_eq!(values(&headers, X_CONTENT_TYPE_OPTIONS), vec!["nosniff"]);
+        assert_eq!(values(&headers, STRICT_TRANSPORT_SECURITY), vec!["max-age=31536000"]);
+    }
+
+    #[test]
+    fn appended_headers_are_not_duplicated() {
+        // Single-valued headers keep the upstream's value.
+        let headers = respond(upstream(&[("server", "nginx")]), injected());
+        assert_eq!(values(&headers, SERVER), vec!["nginx"]);
+
+        // Values are appended to list headers, unless the upstream sent them.
+        let headers = respond(upstream(&[("vary", "accept-encoding")]), injected());
+        assert_eq!(values(&headers, VARY), vec!["accept-encoding", "origin"]);
+        let headers = respond(upstream(&[("vary", "Accept-Encoding, Origin")]), injected());
+        assert_eq!(values(&headers, VARY), vec!["Accept-Encoding, Origin"]);
+    }
+
+    #[test]
+    fn no_headers_are_injected_by_default() {
+        let upstream = upstream(&[("server", "nginx"), ("vary", "origin")]);
+        let expected = headers(&[("server", "nginx"), ("vary", "origin")]);
+        assert_eq!(respond(upstream, ResponseHeaders::default()), expected);
+    }
+}
@@ -0,0 +1,1164 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::{task, Async, Future, Poll};
+use h2;
+use http;
+use tower::Service;
+use tower_h2::{self, Body};
+use tower_reconnect::Error as ReconnectError;
+
+use bound_endpoint::BoundEndpoint;
+use budget::RequestBudget;
+use ctx;
+use replay::{Buffered, FromBuffered};
+use telemetry::sensor::http::RequestOpen;
+use telemetry::sensor::{spans, Attempts};
+use timeout::TimeoutError;
+
+/// Determines which requests may be retried, and how often.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    methods: Vec<http::Method>,
+    statuses: Vec<http::StatusCode>,
+    budget_percent: u32,
+    min_retries_per_sec: u32,
+}
+
+/// Limits the retries made by every `Retry` that shares it, so that retries
+/// stay under a percentage of the requests sent, plus a minimum number of
+/// retries each second.
+///
+/// Clones share the same balance.
+#[derive(Clone, Debug)]
+pub struct RetryBudget(Rc<RefCell<Budget>>);
+
+/// Retries failed requests according to a `RetryPolicy`.
+///
+/// A request is retried if it failed with an error or a response with one of
+/// the policy's retryable statuses; it uses one of the policy's methods; and
+/// its body is empty or has been buffered, so that it can be replayed. All
+/// other requests are sent only once.
+///
+/// Retries are limited by a `RetryBudget`, which may be shared with other
+/// services, so that a failing upstream doesn't receive many times the load
+/// it would without retries. Once the budget is exhausted, failed requests
+/// fail without being retried until more requests replenish it. Each retry
+/// also spends an attempt from the request's own `RequestBudget`, if it has
+/// one.
+///
+/// Retries are dispatched by the task that owns the service, when it next
+/// polls it for readiness, once the inner service is ready again. So an inner
+/// service that replaces failed connections, like `Reconnect`, retries on a
+/// new connection. The service isn't ready for new requests until it has
+/// dispatched every retry waiting for it.
+pub struct Retry<S: Service> {
+    inner: S,
+    policy: Rc<RetryPolicy>,
+    budget: RetryBudget,
+    queue: RetryQueue<S>,
+}
+
+pub struct ResponseFuture<S: Service> {
+    queue: Requeue<S>,
+    policy: Rc<RetryPolicy>,
+    budget: RetryBudget,
+    replay: Option<Replay>,
+    retries: usize,
+    state: State<S>,
+}
+
+enum State<S: Service> {
+    /// Waiting for a response to the current attempt.
+    Pending(S::Future),
+    /// Waiting for the service to dispatch another attempt, with the outcome
+    /// of the previous attempt, in case the service is dropped first.
+    Retrying(Rc<RefCell<Attempt<S>>>, Result<S::Response, S::Error>),
+    Done,
+}
+
+/// The attempts that a `Retry` or `RetryRefused` dispatches the next time
+/// that it's polled for readiness.
+struct RetryQueue<S: Service>(Rc<RefCell<Queue<S>>>);
+
+/// A response future's handle on the queue of the service that dispatched
+/// it, which doesn't keep the queue alive once the service is dropped.
+struct Requeue<S: Service>(Weak<RefCell<Queue<S>>>);
+
+struct Queue<S: Service> {
+    attempts: VecDeque<Rc<RefCell<Attempt<S>>>>,
+    /// The task that polls the service for readiness, which is notified when
+    /// an attempt is queued.
+    owner: Option<task::Task>,
+}
+
+/// An attempt queued by a response future.
+struct Attempt<S: Service> {
+    request: Option<S::Request>,
+    future: Option<S::Future>,
+    /// The task awaiting the response, which is notified once the attempt is
+    /// dispatched.
+    waiter: task::Task,
+}
+
+/// Resends requests that were refused by a connection that is going away, so
+/// that they are sent on a new connection.
+///
+/// A server that sends a `GOAWAY` fails the requests in flight that it has
+/// not processed, as it does those it refuses with `REFUSED_STREAM`. Such a
+/// request is resent once if the `RetryPolicy` considers it replayable,
+/// whether or not the policy permits retries, unless its `RequestBudget` has
+/// no attempts left. The inner service must replace connections that fail,
+/// since the refusing connection can't be reused. As with `Retry`, requests
+/// are resent by the task that owns the service.
+pub struct RetryRefused<S: Service> {
+    inner: S,
+    policy: Rc<RetryPolicy>,
+    queue: RetryQueue<S>,
+}
+
+pub struct RefusedFuture<S: Service> {
+    queue: Requeue<S>,
+    replay: Option<Replay>,
+    state: State<S>,
+}
+
+/// Indicates whether an error means that a request was refused without
+/// being processed.
+pub trait Refused {
+    fn is_refused(&self) -> bool;
+}
+
+/// The parts of a request needed to send it again.
+pub struct Replay {
+    method: http::Method,
+    uri: http::Uri,
+    version: http::Version,
+    headers: http::HeaderMap,
+    server_ctx: Option<Arc<ctx::transport::Server>>,
+    request_open: Option<RequestOpen>,
+    spans: Option<spans::Request>,
+    attempts: Option<Attempts>,
+    budget: Option<RequestBudget>,
+    endpoint: Option<BoundEndpoint>,
+    body: Option<Buffered>,
+}
+
+/// Limits retries to a fraction of the requests sent.
+///
+/// Each request deposits `percent` into the balance, and `100 *
+/// min_per_sec` is deposited each second, up to a maximum. Each retry
+/// withdraws `100`.
+#[derive(Debug)]
+struct Budget {
+    percent: u32,
+    min_per_sec: u32,
+    balance: u32,
+    max_balance: u32,
+    refilled_at: Instant,
+}
+
+/// The number of retries that may be made before any requests have been
+/// sent, and the most that may accumulate while requests succeed, unless
+/// more are allowed each second.
+const MAX_BUDGETED_RETRIES: u32 = 10;
+
+const DEFAULT_BUDGET_PERCENT: u32 = 20;
+
+const RETRY_COST: u32 = 100;
+
+// ===== impl RetryPolicy =====
+
+impl RetryPolicy {
+    /// Creates a policy that retries idempotent requests up to `max_retries`
+    /// times after errors or `502`, `503`, and `504` responses.
+    pub fn new(max_retries: usize) -> Self {
+        RetryPolicy {
+            max_retries,
+            methods: vec![
+                http::Method::GET,
+                http::Method::HEAD,
+                http::Method::PUT,
+                http::Method::DELETE,
+            ],
+            statuses: vec![
+                http::StatusCode::BAD_GATEWAY,
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                http::StatusCode::GATEWAY_TIMEOUT,
+            ],
+            budget_percent: DEFAULT_BUDGET_PERCENT,
+            min_retries_per_sec: 0,
+        }
+    }
+
+    /// Retries requests up to `max_retries` times, rather than the number of
+    /// times the policy was created with.
+    pub fn with_max_retries(self, max_retries: usize) -> Self {
+        Self {
+            max_retries,
+            ..self
+        }
+    }
+
+    /// Limits retries to `percent` of the requests sent, plus
+    /// `min_retries_per_sec` each second, so that some requests are retried
+    /// even while few are sent.
+    pub fn with_budget(self, percent: u32, min_retries_per_sec: u32) -> Self {
+        Self {
+            budget_percent: percent,
+            min_retries_per_sec,
+            ..self
+        }
+    }
+
+    /// Also retries requests with `method`.
+    pub fn with_method(mut self, method: http::Method) -> Self {
+        if !self.methods.contains(&method) {
+            self.methods.push(method);
+        }
+        self
+    }
+
+    fn is_retryable<B: Body>(&self, req: &http::Request<B>) -> bool {
+        self.max_retries > 0 && self.is_replayable(req)
+    }
+
+    /// Returns true if `req` uses one of the policy's methods and can be sent
+    /// more than once.
+    pub fn is_replayable<B: Body>(&self, req: &http::Request<B>) -> bool {
+        self.replays(req.method()) &&
+            // Only empty or buffered bodies can be replayed, since other
+            // bodies are streamed to the upstream as they're received.
+            (req.body().is_end_stream() || Buffered::of(req).is_some())
+    }
+
+    /// Returns true if requests with `method` may be sent more than once.
+    pub fn replays(&self, method: &http::Method) -> bool {
+        self.methods.contains(method)
+    }
+}
+
+/// The default policy never retries requests.
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(0)
+    }
+}
+
+// ===== impl RetryBudget =====
+
+impl RetryBudget {
+    /// Creates a budget with the percentage and per-second minimum of
+    /// `policy`.
+    pub fn new(policy: &RetryPolicy) -> Self {
+        let budget = Budget::new(policy.budget_percent, policy.min_retries_per_sec, Instant::now());
+        RetryBudget(Rc::new(RefCell::new(budget)))
+    }
+
+    fn deposit(&self) {
+        self.0.borrow_mut().deposit()
+    }
+
+    fn withdraw(&self) -> bool {
+        self.0.borrow_mut().withdraw(Instant::now())
+    }
+}
+
+// ===== impl Retry =====
+
+impl<S: Service> Retry<S> {
+    pub fn new(inner: S, policy: RetryPolicy, budget: RetryBudget) -> Self {
+        Retry {
+            inner,
+            policy: Rc::new(policy),
+            budget,
+            queue: RetryQueue::new(),
+        }
+    }
+}
+
+impl<S, A, B> Service for Retry<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    A: Body + FromBuffered,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.queue.poll_dispatch(&mut self.inner)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.budget.deposit();
+
+        let replay = if self.policy.is_retryable(&req) {
+            Some(Replay::new(&req))
+        } else {
+            None
+        };
+
+        let future = self.inner.call(req);
+        ResponseFuture {
+            queue: self.queue.requeue(),
+            policy: self.policy.clone(),
+            budget: self.budget.clone(),
+            replay,
+            retries: 0,
+            state: State::Pending(future),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, A, B> ResponseFuture<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+{
+    fn can_retry(&mut self) -> bool {
+        let replay = match self.replay {
+            Some(ref replay) if self.retries < self.policy.max_retries => replay,
+            _ => return false,
+        };
+        // The request's own budget is spent first, so that a request that
+        // has no attempts left doesn't withdraw from the shared budget.
+        replay.spend_attempt(true) && self.budget.withdraw()
+    }
+}
+
+impl<S, A, B> Future for ResponseFuture<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    A: FromBuffered,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let mut future = match mem::replace(&mut self.state, State::Done) {
+                State::Pending(f) => f,
+                State::Retrying(attempt, last) => match self.queue.poll_dispatched(&attempt) {
+                    Ok(Async::Ready(f)) => f,
+                    Ok(Async::NotReady) => {
+                        self.state = State::Retrying(attempt, last);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(()) => return last.map(Async::Ready),
+                },
+                State::Done => panic!("polled after completion"),
+            };
+
+            let last = match future.poll() {
+                Ok(Async::NotReady) => {
+                    self.state = State::Pending(future);
+                    return Ok(Async::NotReady);
+                },
+                Ok(Async::Ready(rsp)) => {
+                    if !self.policy.statuses.contains(&rsp.status()) || !self.can_retry() {
+                        return Ok(Async::Ready(rsp));
+                    }
+                    debug!("retrying request after {} response", rsp.status());
+                    Ok(rsp)
+                },
+                Err(e) => {
+                    if !self.can_retry() {
+                        return Err(e);
+                    }
+                    debug!("retrying request after error");
+                    Err(e)
+                },
+            };
+
+            let req = self.replay.as_ref()
+                .expect("only replayable requests are retried")
+                .attempt();
+            self.retries += 1;
+            match self.queue.push(req) {
+                Some(attempt) => self.state = State::Retrying(attempt, last),
+                None => return last.map(Async::Ready),
+            }
+        }
+    }
+}
+
+// ===== impl RetryRefused =====
+
+impl<S: Service> RetryRefused<S> {
+    pub fn new(inner: S, policy: RetryPolicy) -> Self {
+        RetryRefused {
+            inner,
+            policy: Rc::new(policy),
+            queue: RetryQueue::new(),
+        }
+    }
+}
+
+impl<S, A, B> Service for RetryRefused<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    S::Error: Refused,
+    A: Body + FromBuffered,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = RefusedFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.queue.poll_dispatch(&mut self.inner)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let replay = if self.policy.is_replayable(&req) {
+            Some(Replay::new(&req))
+        } else {
+            None
+        };
+
+        let future = self.inner.call(req);
+        RefusedFuture {
+            queue: self.queue.requeue(),
+            replay,
+            state: State::Pending(future),
+        }
+    }
+}
+
+// ===== impl RefusedFuture =====
+
+impl<S: Service> RefusedFuture<S> {
+    fn can_resend(&self) -> bool {
+        self.replay.as_ref().map_or(false, |replay| replay.spend_attempt(true))
+    }
+}
+
+impl<S, A, B> Future for RefusedFuture<S>
+where
+    S: Service<
+        Request = http::Request<A>,
+        Response = http::Response<B>,
+    >,
+    S::Error: Refused,
+    A: FromBuffered,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let mut future = match mem::replace(&mut self.state, State::Done) {
+                State::Pending(f) => f,
+                State::Retrying(attempt, last) => match self.queue.poll_dispatched(&attempt) {
+                    Ok(Async::Ready(f)) => f,
+                    Ok(Async::NotReady) => {
+                        self.state = State::Retrying(attempt, last);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(()) => return last.map(Async::Ready),
+                },
+                State::Done => panic!("polled after completion"),
+            };
+
+            let last = match future.poll() {
+                Ok(Async::NotReady) => {
+                    self.state = State::Pending(future);
+                    return Ok(Async::NotReady);
+                },
+                Err(e) => {
+                    if !e.is_refused() || !self.can_resend() {
+                        return Err(e);
+                    }
+                    debug!("resending refused request");
+                    Err(e)
+                },
+                res => return res,
+            };
+
+            let req = self.replay.take()
+                .expect("only replayable requests are resent")
+                .attempt();
+            match self.queue.push(req) {
+                Some(attempt) => self.state = State::Retrying(attempt, last),
+                None => return last.map(Async::Ready),
+            }
+        }
+    }
+}
+
+// ===== impl RetryQueue =====
+
+impl<S: Service> RetryQueue<S> {
+    fn new() -> Self {
+        RetryQueue(Rc::new(RefCell::new(Queue {
+            attempts: VecDeque::new(),
+            owner: None,
+        })))
+    }
+
+    fn requeue(&self) -> Requeue<S> {
+        Requeue(Rc::downgrade(&self.0))
+    }
+
+    /// Dispatches the queued attempts to `inner` as it becomes ready, and is
+    /// ready once every attempt has been dispatched.
+    fn poll_dispatch(&self, inner: &mut S) -> Poll<(), S::Error> {
+        let mut queue = self.0.borrow_mut();
+        if !queue.owner.as_ref().map_or(false, |t| t.will_notify_current()) {
+            queue.owner = Some(task::current());
+        }
+
+        loop {
+            try_ready!(inner.poll_ready());
+
+            let attempt = match queue.attempts.pop_front() {
+                Some(attempt) => attempt,
+                None => return Ok(Async::Ready(())),
+            };
+            // An attempt whose response future was dropped is abandoned.
+            if Rc::strong_count(&attempt) == 1 {
+                continue;
+            }
+            let mut attempt = attempt.borrow_mut();
+            if let Some(req) = attempt.request.take() {
+                attempt.future = Some(inner.call(req));
+                attempt.waiter.notify();
+            }
+        }
+    }
+}
+
+// ===== impl Requeue =====
+
+impl<S: Service> Requeue<S> {
+    /// Queues `req` to be dispatched by the service, notifying the task that
+    /// owns it, unless the service has been dropped.
+    fn push(&self, req: S::Request) -> Option<Rc<RefCell<Attempt<S>>>> {
+        let queue = self.0.upgrade()?;
+        let mut queue = queue.borrow_mut();
+        let attempt = Rc::new(RefCell::new(Attempt {
+            request: Some(req),
+            future: None,
+            waiter: task::current(),
+        }));
+        queue.attempts.push_back(attempt.clone());
+        if let Some(ref owner) = queue.owner {
+            owner.notify();
+        }
+        Some(attempt)
+    }
+
+    /// Takes the response future of `attempt` once it's been dispatched,
+    /// failing if the service was dropped first.
+    fn poll_dispatched(&self, attempt: &Rc<RefCell<Attempt<S>>>) -> Poll<S::Future, ()> {
+        let mut attempt = attempt.borrow_mut();
+        if let Some(future) = attempt.future.take() {
+            return Ok(Async::Ready(future));
+        }
+        if self.0.upgrade().is_none() {
+            return Err(());
+        }
+        if !attempt.waiter.will_notify_current() {
+            attempt.waiter = task::current();
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+// ===== impl Refused =====
+
+impl Refused for tower_h2::client::Error {
+    fn is_refused(&self) -> bool {
+        // The requests failed by a `GOAWAY` fail with its reason, which is
+        // `NO_ERROR` when the server is shutting down gracefully.
+        self.reason() == Some(h2::Reason::REFUSED_STREAM) ||
+            self.reason() == Some(h2::Reason::NO_ERROR)
+    }
+}
+
+impl<E: Refused> Refused for TimeoutError<E> {
+    fn is_refused(&self) -> bool {
+        match *self {
+            TimeoutError::Error(ref e) => e.is_refused(),
+            TimeoutError::Timeout(_) => false,
+        }
+    }
+}
+
+impl<E: Refused, C> Refused for ReconnectError<E, C> {
+    fn is_refused(&self) -> bool {
+        match *self {
+            ReconnectError::Inner(ref e) => e.is_refused(),
+            _ => false,
+        }
+    }
+}
+
+// ===== impl Replay =====
+
+impl Replay {
+    pub fn new<B>(req: &http::Request<B>) -> Self {
+        Replay {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            headers: req.headers().clone(),
+            server_ctx: req.extensions().get::<Arc<ctx::transport::Server>>().cloned(),
+            request_open: req.extensions().get::<RequestOpen>().cloned(),
+            spans: req.extensions().get::<spans::Request>().map(spans::Request::replay),
+            attempts: req.extensions().get::<Attempts>().cloned(),
+            budget: RequestBudget::of(req).cloned(),
+            endpoint: BoundEndpoint::of(req).cloned(),
+            body: Buffered::of(req).cloned(),
+        }
+    }
+
+    /// Returns the request to send as another attempt, counted in its
+    /// `Attempts`.
+    ///
+    /// The attempt shares the request's `RequestBudget`, from which it
+    /// should first be spent with `spend_attempt`.
+    pub fn attempt<B: FromBuffered>(&self) -> http::Request<B> {
+        let mut req = self.request();
+        if let Some(ref attempts) = self.attempts {
+            attempts.retry();
+            req.extensions_mut().insert(attempts.clone());
+        }
+        if let Some(ref budget) = self.budget {
+            req.extensions_mut().insert(budget.clone());
+        }
+        req
+    }
+
+    /// Spends another attempt from the request's `RequestBudget`, if it has
+    /// one, returning false if every attempt has been spent.
+    ///
+    /// If `required`, a request that can't be attempted again fails because
+    /// it exhausted its budget, rather than with the outcome of its last
+    /// attempt.
+    pub fn spend_attempt(&self, required: bool) -> bool {
+        let budget = match self.budget {
+            Some(ref budget) => budget,
+            None => return true,
+        };
+        match budget.spend_attempt() {
+            Ok(()) => true,
+            Err(exhausted) => {
+                if required {
+                    budget.exhaust(exhausted);
+                }
+                false
+            }
+        }
+    }
+
+    /// Returns a copy of the request, which isn't an attempt to send it,
+    /// such as a mirror of it.
+    pub fn request<B: FromBuffered>(&self) -> http::Request<B> {
+        let body = match self.body {
+            Some(ref buffered) => B::from_buffered(buffered.clone()),
+            None => B::default(),
+        };
+        let mut req = http::Request::new(body);
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = self.uri.clone();
+        *req.version_mut() = self.version;
+        *req.headers_mut() = self.headers.clone();
+        if let Some(ref ctx) = self.server_ctx {
+            req.extensions_mut().insert(ctx.clone());
+        }
+        if let Some(request_open) = self.request_open {
+            req.extensions_mut().insert(request_open);
+        }
+        if let Some(ref spans) = self.spans {
+            req.extensions_mut().insert(spans.replay());
+        }
+        if let Some(ref endpoint) = self.endpoint {
+            req.extensions_mut().insert(endpoint.clone());
+        }
+        if let Some(ref buffered) = self.body {
+            req.extensions_mut().insert(buffered.clone());
+        }
+        req
+    }
+}
+
+// ===== impl Budget =====
+
+impl Budget {
+    fn new(percent: u32, min_per_sec: u32, now: Instant) -> Self {
+        let max_balance = cmp::max(MAX_BUDGETED_RETRIES, min_per_sec).saturating_mul(RETRY_COST);
+        Budget {
+            percent,
+            min_per_sec,
+            balance: max_balance,
+            max_balance,
+            refilled_at: now,
+        }
+    }
+
+    fn deposit(&mut self) {
+        self.balance = cmp::min(self.balance.saturating_add(self.percent), self.max_balance);
+    }
+
+    /// Deposits the per-second minimum for the time since the last refill.
+    fn refill(&mut self, now: Instant) {
+        if self.min_per_sec == 0 || now <= self.refilled_at {
+            return;
+        }
+        let elapsed = now.duration_since(self.refilled_at);
+        let elapsed_ms = elapsed.as_secs()
+            .saturating_mul(1_000)
+            .saturating_add(u64::from(elapsed.subsec_nanos() / 1_000_000));
+        let amount = elapsed_ms
+            .saturating_mul(u64::from(self.min_per_sec) * u64::from(RETRY_COST)) / 1_000;
+        // Until at least one unit has accrued, the elapsed time is carried
+        // over, so that frequent refills don't round every deposit to zero.
+        if amount == 0 {
+            return;
+        }
+        let balance = u64::from(self.balance).saturating_add(amount);
+        self.balance = cmp::min(balance, u64::from(self.max_balance)) as u32;
+        self.refilled_at = now;
+    }
+
+    fn withdraw(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.balance < RETRY_COST {
+            debug!("retry budget exhausted");
+            return false;
+        }
+        self.balance -= RETRY_COST;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use futures::{future, Async, Future, Poll};
+    use futures::future::FutureResult;
+    use h2;
+    use http;
+    use tower::Service;
+    use tower_h2::{self, Body};
+
+    use test_support::send;
+
+    use super::*;
+
+    /// A service that responds with a scripted sequence of results, recording
+    /// the requests it receives.
+    struct Scripted<E = ()> {
+        results: VecDeque<Result<http::StatusCode, E>>,
+        requests: Rc<RefCell<Vec<http::Method>>>,
+    }
+
+    /// A request body that is either empty or still streaming.
+    #[derive(Debug, Default)]
+    struct TestBody {
+        streaming: bool,
+    }
+
+    impl<E> Service for Scripted<E> {
+        type Request = http::Request<TestBody>;
+        type Response = http::Response<()>;
+        type Error = E;
+        type Future = FutureResult<Self::Response, E>;
+
+        fn poll_ready(&mut self) -> Poll<(), E> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            self.requests.borrow_mut().push(req.method().clone());
+            let result = self.results.pop_front().expect("unexpected request");
+            future::result(result.map(|status| {
+                let mut rsp = http::Response::new(());
+                *rsp.status_mut() = status;
+                rsp
+            }))
+        }
+    }
+
+    impl FromBuffered for TestBody {
+        fn from_buffered(_: Buffered) -> Self {
+            TestBody::default()
+        }
+    }
+
+    impl Body for TestBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            !self.streaming
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    fn retry(
+        policy: RetryPolicy,
+        results: Vec<Result<http::StatusCode, ()>>,
+    ) -> (Retry<Scripted>, Rc<RefCell<Vec<http::Method>>>) {
+        let budget = RetryBudget::new(&policy);
+        retry_with_budget(policy, budget, results)
+    }
+
+    fn retry_with_budget(
+        policy: RetryPolicy,
+        budget: RetryBudget,
+        results: Vec<Result<http::StatusCode, ()>>,
+    ) -> (Retry<Scripted>, Rc<RefCell<Vec<http::Method>>>) {
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let inner = Scripted {
+            results: results.into(),
+            requests: requests.clone(),
+        };
+        (Retry::new(inner, policy, budget), requests)
+    }
+
+    /// Sends a `GET` request, returning the number of times it was sent.
+    fn attempts(
+        svc: &mut Retry<Scripted>,
+        requests: &Rc<RefCell<Vec<http::Method>>>,
+    ) -> usize {
+        let before = requests.borrow().len();
+        let _ = send(svc, request(http::Method::GET)).wait();
+        requests.borrow().len() - before
+    }
+
+    fn retry_refused(
+        results: Vec<Result<http::StatusCode, tower_h2::client::Error>>,
+    ) -> (
+        RetryRefused<Scripted<tower_h2::client::Error>>,
+        Rc<RefCell<Vec<http::Method>>>,
+    ) {
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let inner = Scripted {
+            results: results.into(),
+            requests: requests.clone(),
+        };
+        (RetryRefused::new(inner, RetryPolicy::default()), requests)
+    }
+
+    fn reset(reason: h2::Reason) -> Result<http::StatusCode, tower_h2::client::Error> {
+        Err(reason.into())
+    }
+
+    fn request(method: http::Method) -> http::Request<TestBody> {
+        let mut req = http::Request::new(TestBody::default());
+        *req.method_mut() = method;
+        req
+    }
+
+    #[test]
+    fn retries_after_error() {
+        let (mut svc, requests) = retry(
+            RetryPolicy::new(1),
+            vec![Err(()), Ok(http::StatusCode::OK)],
+        );
+
+        let rsp = send(&mut svc, request(http::Method::GET)).wait().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(requests.borrow().len(), 2);
+    }
+
+    #[test]
+    fn retries_are_dispatched_by_the_owning_task() {
+        let (mut svc, requests) = retry(
+            RetryPolicy::new(1),
+            vec![Err(()), Ok(http::StatusCode::OK)],
+        );
+
+        future::lazy(|| {
+            let mut rsp = svc.call(request(http::Method::GET));
+            assert!(rsp.poll().expect("retried").is_not_ready());
+            assert_eq!(requests.borrow().len(), 1);
+
+            // The retry is dispatched when the service is next polled for
+            // readiness, rather than by the response future.
+            assert!(svc.poll_ready().expect("ready").is_ready());
+            assert_eq!(requests.borrow().len(), 2);
+            let rsp = rsp.poll().expect("response");
+            assert_eq!(rsp.map(|rsp| rsp.status()), Async::Ready(http::StatusCode::OK));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn retries_fail_with_the_last_outcome_if_the_service_is_dropped() {
+        let (mut svc, requests) = retry(
+            RetryPolicy::new(1),
+            vec![Ok(http::StatusCode::BAD_GATEWAY)],
+        );
+
+        future::lazy(move || {
+            let mut rsp = svc.call(request(http::Method::GET));
+            assert!(rsp.poll().expect("retried").is_not_ready());
+            drop(svc);
+
+            let rsp = rsp.poll().expect("response");
+            assert_eq!(rsp.map(|rsp| rsp.status()), Async::Ready(http::StatusCode::BAD_GATEWAY));
+            assert_eq!(requests.borrow().len(), 1);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn retries_after_retryable_status() {
+        let (mut svc, requests) = retry(
+            RetryPolicy::new(2),
+            vec![
+                Ok(http::StatusCode::SERVICE_UNAVAILABLE),
+                Ok(http::StatusCode::BAD_GATEWAY),
+                Ok(http::StatusCode::OK),
+            ],
+        );
+
+        let rsp = send(&mut svc, request(http::Method::PUT)).wait().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(requests.borrow().len(), 3);
+    }
+
+    #[test]
+    fn does_not_retry_other_statuses() {
+        let (mut svc, requests) = retry(
+            RetryPolicy::new(1),
+            vec![Ok(http::StatusCode::INTERNAL_SERVER_ERROR)],
+        );
+
+        let rsp = send(&mut svc, request(http::Method::GET)).wait().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn stops_after_max_retries() {
+        let (mut svc, requests) = retry(
+            RetryPolicy::new(1),
+            vec![Err(()), Err(())],
+        );
+
+        assert!(send(&mut svc, request(http::Method::GET)).wait().is_err());
+        assert_eq!(requests.borrow().len(), 2);
+    }
+
+    #[test]
+    fn does_not_retry_non_idempotent_methods() {
+        let (mut svc, requests) = retry(RetryPolicy::new(1), vec![Err(())]);
+        assert!(send(&mut svc, request(http::Method::POST)).wait().is_err());
+        assert_eq!(requests.borrow().len(), 1);
+
+        let (mut svc, requests) = retry(
+            RetryPolicy::new(1).with_method(http::Method::POST),
+            vec![Err(()), Ok(http::StatusCode::OK)],
+        );
+        assert!(send(&mut svc, request(http::Method::POST)).wait().is_ok());
+        assert_eq!(*requests.borrow(), vec![http::Method::POST, http::Method::POST]);
+    }
+
+    #[test]
+    fn does_not_retry_streaming_bodies() {
+        let (mut svc, requests) = retry(RetryPolicy::new(1), vec![Err(())]);
+
+        let mut req = request(http::Method::PUT);
+        *req.body_mut() = TestBody { streaming: true };
+        assert!(send(&mut svc, req).wait().is_err());
+        assert_eq!(requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn retries_are_budgeted() {
+        let results = (0..(2 * MAX_BUDGETED_RETRIES + 1))
+            .map(|_| Err(()))
+            .collect();
+        let policy = RetryPolicy {
+            budget_percent: 0,
+            ..RetryPolicy::new(1)
+        };
+        let (mut svc, requests) = retry(policy, results);
+
+        for _ in 0..(MAX_BUDGETED_RETRIES + 1) {
+            assert!(send(&mut svc, request(http::Method::GET)).wait().is_err());
+        }
+
+        // Each of the first requests is retried once, until the budget is
+        // exhausted.
+        let expected = 2 * MAX_BUDGETED_RETRIES as usize + 1;
+        assert_eq!(requests.borrow().len(), expected);
+    }
+
+    #[test]
+    fn exhausted_budget_is_replenished_by_requests() {
+        let policy = RetryPolicy::new(1).with_budget(20, 0);
+        let mut results: Vec<_> = (0..36).map(|_| Err(())).collect();
+        results.extend((0..50).map(|_| Ok(http::StatusCode::OK)));
+        results.extend((0..20).map(|_| Err(())));
+        let (mut svc, requests) = retry(policy, results);
+
+        // The initial allowance, topped up by the failing requests
+        // themselves, lets the first requests be retried.
+        for _ in 0..12 {
+            assert_eq!(attempts(&mut svc, &requests), 2);
+        }
+
+        // Once the budget is exhausted, failures are retried only as often
+        // as the percentage allows: once every five requests.
+        let retried = (0..10)
+            .filter(|_| attempts(&mut svc, &requests) == 2)
+            .count();
+        assert_eq!(retried, 2);
+
+        // Successful requests replenish the budget, so retries resume.
+        for _ in 0..50 {
+            assert_eq!(attempts(&mut svc, &requests), 1);
+        }
+        for _ in 0..10 {
+            assert_eq!(attempts(&mut svc, &requests), 2);
+        }
+    }
+
+    #[test]
+    fn budget_is_shared_by_services() {
+        let policy = RetryPolicy::new(1).with_budget(0, 0);
+        let budget = RetryBudget::new(&policy);
+        let results = (0..(2 * MAX_BUDGETED_RETRIES)).map(|_| Err(())).collect();
+        let (mut a, a_requests) = retry_with_budget(policy.clone(), budget.clone(), results);
+        let (mut b, b_requests) = retry_with_budget(policy, budget, vec![Err(())]);
+
+        for _ in 0..MAX_BUDGETED_RETRIES {
+            assert_eq!(attempts(&mut a, &a_requests), 2);
+        }
+        assert_eq!(attempts(&mut b, &b_requests), 1);
+    }
+
+    #[test]
+    fn budget_allows_min_retries_per_second() {
+        let start = Instant::now();
+        let mut budget = Budget::new(0, 2, start);
+        for _ in 0..MAX_BUDGETED_RETRIES {
+            assert!(budget.withdraw(start));
+        }
+        assert!(!budget.withdraw(start));
+
+        // Half a second later, another retry has been allowed.
+        let mut now = start + Duration::from_millis(500);
+        assert!(budget.withdraw(now));
+        assert!(!budget.withdraw(now));
+
+        // Frequent refills add up to the same allowance.
+        let mut retried = 0;
+        for _ in 0..100 {
+            now += Duration::from_millis(10);
+            if budget.withdraw(now) {
+                retried += 1;
+            }
+        }
+        assert_eq!(retried, 2);
+    }
+
+    #[test]
+    fn resends_requests_refused_by_goaway_once() {
+        let (mut svc, requests) = retry_refused(vec![
+            reset(h2::Reason::NO_ERROR),
+            Ok(http::StatusCode::OK),
+        ]);
+        let rsp = send(&mut svc, request(http::Method::GET)).wait().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(requests.borrow().len(), 2);
+
+        let (mut svc, requests) = retry_refused(vec![
+            reset(h2::Reason::REFUSED_STREAM),
+            reset(h2::Reason::REFUSED_STREAM),
+        ]);
+        assert!(send(&mut svc, request(http::Method::GET)).wait().is_err());
+        assert_eq!(requests.borrow().len(), 2);
+    }
+
+    #[test]
+    fn does_not_resend_unreplayable_or_failed_requests() {
+        let (mut svc, requests) = retry_refused(vec![reset(h2::Reason::REFUSED_STREAM)]);
+        assert!(send(&mut svc, request(http::Method::POST)).wait().is_err());
+        assert_eq!(requests.borrow().len(), 1);
+
+        let (mut svc, requests) = retry_refused(vec![reset(h2::Reason::INTERNAL_ERROR)]);
+        assert!(send(&mut svc, request(http::Method::GET)).wait().is_err());
+        assert_eq!(requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn counts_the_attempts_of_each_request() {
+        use telemetry::sensor::RequestAttempts;
+
+        let recorded = RequestAttempts::default();
+        let counted = |method| {
+            let mut req = request(method);
+            req.extensions_mut().insert(recorded.start());
+            req
+        };
+
+        let (mut svc, _) = retry(RetryPolicy::new(2), vec![
+            Err(()),
+            Ok(http::StatusCode::SERVICE_UNAVAILABLE),
+            Ok(http::StatusCode::OK),
+            Ok(http::StatusCode::OK),
+            Err(()),
+        ]);
+        send(&mut svc, counted(http::Method::GET)).wait().expect("response");
+        send(&mut svc, counted(http::Method::GET)).wait().expect("response");
+        // Requests that can't be retried are attempted once.
+        assert!(send(&mut svc, counted(http::Method::POST)).wait().is_err());
+
+        // A request that's resent after its retries were refused is counted
+        // once, with every attempt made by either layer.
+        let inner = Scripted {
+            results: vec![
+                reset(h2::Reason::REFUSED_STREAM),
+                reset(h2::Reason::REFUSED_STREAM),
+                Ok(http::StatusCode::OK),
+            ].into(),
+            requests: Rc::new(RefCell::new(Vec::new())),
+        };
+        let policy = RetryPolicy::new(1);
+        let budget = RetryBudget::new(&policy);
+        let retry = Retry::new(inner, policy, budget);
+        let mut svc = RetryRefused::new(retry, RetryPolicy::default());
+        send(&mut svc, counted(http::Method::GET)).wait().expect("response");
+
+        let histogram = recorded.histogram().into_iter().collect::<Vec<_>>();
+        assert_eq!(histogram, vec![(1, 2), (3, 2)]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::mem;
+
+use futures::{Async, Future, Poll};
+use http;
+use http::header::{HeaderValue, HOST};
+use http::uri::{Authority, Parts, Uri};
+use tower::{NewService, Service};
+
+/// Replaces the authority of each request, and its `Host` header, with a
+/// configured authority before the request is sent to the upstream.
+///
+/// Only what is sent changes: the connection is still made to the endpoint
+/// that the request was routed to, and telemetry recorded outside of this
+/// service sees the request's original authority.
+///
+/// If constructed without an authority, this is a no-op.
+#[derive(Clone, Debug)]
+pub struct RewriteHost<S> {
+    inner: S,
+    host: Option<Host>,
+}
+
+/// Wraps the inner `NewService`'s services in `RewriteHost`s.
+pub struct Init<F> {
+    future: F,
+    host: Option<Host>,
+}
+
+#[derive(Clone, Debug)]
+struct Host {
+    authority: Authority,
+    header: HeaderValue,
+}
+
+// ===== impl RewriteHost =====
+
+impl<S> RewriteHost<S> {
+    pub fn new(inner: S, authority: Option<Authority>) -> Self {
+        let host = authority.map(|authority| {
+            let header = HeaderValue::from_str(authority.as_str())
+                .expect("authority is a valid header value");
+            Host { authority, header }
+        });
+        RewriteHost { inner, host }
+    }
+}
+
+impl<N, A> NewService for RewriteHost<N>
+where
+    N: NewService<Request = http::Request<A>>,
+{
+    type Request = N::Request;
+    type Response = N::Response;
+    type Error = N::Error;
+    type Service = RewriteHost<N::Service>;
+    type InitError = N::InitError;
+    type Future = Init<N::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        Init {
+            future: self.inner.new_service(),
+            host: self.host.clone(),
+        }
+    }
+}
+
+impl<S, A> Service for RewriteHost<S>
+where
+    S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: Self::Request) -> Self::Future {
+        if let Some(ref host) = self.host {
+            host.rewrite(&mut req);
+        }
+        self.inner.call(req)
+    }
+}
+
+// ===== impl Init =====
+
+impl<F> Future for Init<F>
+where
+    F: Future,
+{
+    type Item = RewriteHost<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        Ok(Async::Ready(RewriteHost {
+            inner,
+            host: self.host.clone(),
+        }))
+    }
+}
+
+// ===== impl Host =====
+
+impl Host {
+    fn rewrite<B>(&self, req: &mut http::Request<B>) {
+        // A `CONNECT` request's authority is where it's tunneled to, rather
+        // than the host it's addressed to.
+        if *req.method() == http::Method::CONNECT {
+            return;
+        }
+
+        if req.uri().scheme_part().is_some() && req.uri().authority_part().is_some() {
+            let mut parts = Parts::from(mem::replace(req.uri_mut(), Uri::default()));
+            parts.authority = Some(self.authority.clone());
+            *req.uri_mut() = Uri::from_parts(parts).expect("absolute uri");
+        }
+
+        // HTTP/2 requests are addressed by their `:authority`, and usually
+        // have no `Host` header, so one is only added to HTTP/1 requests.
+        if req.version() != http::Version::HTTP_2 || req.headers().contains_key(HOST) {
+            req.headers_mut().insert(HOST, self.header.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::{self, FutureResult};
+
+    use super::*;
+
+    /// Responds with the requests it receives.
+    struct Echo;
+
+    impl Service for Echo {
+        type Request = http::Request<()>;
+        type Response = http::Request<()>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            future::ok(req)
+        }
+    }
+
+    fn rewrite(req: http::Request<()>) -> http::Request<()> {
+        let authority = "backend.example.com:8080".parse().unwrap();
+        RewriteHost::new(Echo, Some(authority)).call(req).wait().unwrap()
+    }
+
+    #[test]
+    fn rewrites_http1_uri_and_host() {
+        let req = http::Request::get("http://web.example.com/path?q=1")
+            .header(HOST, "web.example.com")
+            .body(())
+            .unwrap();
+        let req = rewrite(req);
+        assert_eq!(req.uri(), "http://backend.example.com:8080/path?q=1");
+        assert_eq!(req.headers()[HOST], "backend.example.com:8080");
+    }
+
+    #[test]
+    fn rewrites_http2_authority_without_adding_host() {
+        let req = http::Request::get("https://web.example.com/")
+            .version(http::Version::HTTP_2)
+            .body(())
+            .unwrap();
+        let req = rewrite(req);
+        assert_eq!(req.uri(), "https://backend.example.com:8080/");
+        assert!(req.headers().get(HOST).is_none());
+    }
+
+    #[test]
+    fn does_not_rewrite_connect_targets() {
+        let req = http::Request::connect("web.example.com:443")
+            .header(HOST, "web.example.com:443")
+            .body(())
+            .unwrap();
+        let req = rewrite(req);
+        assert_eq!(req.uri(), "web.example.com:443");
+        assert_eq!(req.headers()[HOST], "web.example.com:443");
+    }
+
+    #[test]
+    fn does_nothing_without_an_authority() {
+        let req = http::Request::get("http://web.example.com/")
+            .header(HOST, "web.example.com")
+            .body(())
+            .unwrap();
+        let req = RewriteHost::new(Echo, None).call(req).wait().unwrap();
+        assert_eq!(req.uri(), "http://web.example.com/");
+        assert_eq!(req.headers()[HOST], "web.example.com");
+    }
+}
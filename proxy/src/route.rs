@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use http::uri::Authority;
+
+use bind::ProtocolPolicy;
+use control::FullyQualifiedAuthority;
+use fault::FaultConfig;
+use priority::Priority;
+use transport::DnsNameAndPort;
+
+/// Settings that override the proxy-wide defaults for requests to a single
+/// destination.
+///
+/// Settings that are not set fall back to the defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoutePolicy {
+    pub request_timeout: Option<Duration>,
+    pub max_retries: Option<usize>,
+    pub concurrency_limit: Option<usize>,
+    pub gzip_responses: Option<bool>,
+    /// Replaces the authority and `Host` header of requests sent to the
+    /// destination's endpoints.
+    pub rewrite_host: Option<Authority>,
+    /// Faults to inject into requests sent to the destination's endpoints.
+    pub faults: Option<FaultConfig>,
+    /// Overrides the protocol with which requests are sent to the
+    /// destination's endpoints, according to their paths.
+    pub path_protocols: Option<PathProtocols>,
+    /// The priority of requests to the destination that aren't classified
+    /// by header.
+    pub priority: Option<Priority>,
+}
+
+/// Selects the protocol with which requests are sent by their paths, such as
+/// for a server that speaks HTTP/2 for its gRPC services but HTTP/1 for its
+/// other paths.
+///
+/// Prefixes are matched in the order in which they were added, and the
+/// first to match a request's path applies. The protocol of a request that
+/// matches no prefix is chosen as if there were none.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathProtocols(Vec<(String, ProtocolPolicy)>);
+
+/// `RoutePolicy`s keyed by destination authority.
+///
+/// Authorities local to the cluster are normalized, so that e.g. `web` and
+/// `web.default.svc.cluster.local` match the same route in the `default`
+/// namespace. Authorities outside the cluster must match exactly.
+#[derive(Clone, Debug)]
+pub struct RoutePolicies {
+    default_namespace: String,
+    local: HashMap<FullyQualifiedAuthority, RoutePolicy>,
+    external: HashMap<DnsNameAndPort, RoutePolicy>,
+}
+
+// ===== impl PathProtocols =====
+
+impl PathProtocols {
+    /// Sends requests whose paths start with `prefix` according to `policy`,
+    /// unless an earlier prefix matches them.
+    pub fn with_prefix(mut self, prefix: String, policy: ProtocolPolicy) -> Self {
+        self.0.push((prefix, policy));
+        self
+    }
+
+    /// Returns the policy for requests to `path`, if a prefix matches it.
+    pub fn get(&self, path: &str) -> Option<ProtocolPolicy> {
+        self.0.iter()
+            .find(|&&(ref prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|&(_, policy)| policy)
+    }
+}
+
+// ===== impl RoutePolicies =====
+
+impl RoutePolicies {
+    pub fn new(default_namespace: String) -> Self {
+        RoutePolicies {
+            default_namespace,
+            local: HashMap::new(),
+            external: HashMap::new(),
+        }
+    }
+
+    /// Applies `policy` to requests for `authority`, replacing any policy
+    /// previously registered for it.
+    pub fn with_route(mut self, authority: DnsNameAndPort, policy: RoutePolicy) -> Self {
+        match FullyQualifiedAuthority::normalize(&authority, &self.default_namespace) {
+            Some(name) => {
+                self.local.insert(name, policy);
+            }
+            None => {
+                self.external.insert(authority, policy);
+            }
+        }
+        self
+    }
+
+    /// Returns the policy for requests to `authority`, if one has been
+    /// registered.
+    pub fn get(&self, authority: &DnsNameAndPort) -> Option<&RoutePolicy> {
+        match FullyQualifiedAuthority::normalize(authority, &self.default_namespace) {
+            Some(ref name) => self.local.get(name),
+            None => self.external.get(authority),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::uri::Authority;
+
+    use transport::{Host, HostAndPort};
+    use super::*;
+
+    fn authority(s: &str) -> DnsNameAndPort {
+        let authority = s.parse::<Authority>().unwrap();
+        match HostAndPort::normalize(&authority, Some(80)).unwrap() {
+            HostAndPort { host: Host::DnsName(host), port } => DnsNameAndPort { host, port },
+            HostAndPort { host: Host::Ip(_), .. } => unreachable!("not a DNS name: {}", s),
+        }
+    }
+
+    fn routes() -> RoutePolicies {
+        let slow = RoutePolicy {
+            request_timeout: Some(Duration::from_secs(30)),
+            ..RoutePolicy::default()
+        };
+        let flaky = RoutePolicy {
+            max_retries: Some(3),
+            concurrency_limit: Some(10),
+            ..RoutePolicy::default()
+        };
+        RoutePolicies::new("default".into())
+            .with_route(authority("reports.default.svc.cluster.local:8080"), slow)
+            .with_route(authority("api.example.com"), flaky)
+    }
+
+    #[test]
+    fn each_authority_uses_its_own_policy() {
+        let routes = routes();
+
+        let reports = routes.get(&authority("reports.default.svc.cluster.local:8080"))
+            .expect("reports policy");
+        assert_eq!(reports.request_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(reports.max_retries, None);
+
+        let api = routes.get(&authority("api.example.com:80")).expect("api policy");
+        assert_eq!(api.request_timeout, None);
+        assert_eq!(api.max_retries, Some(3));
+        assert_eq!(api.concurrency_limit, Some(10));
+    }
+
+    #[test]
+    fn local_authorities_are_normalized() {
+        let routes = routes();
+        let expected = routes.get(&authority("reports.default.svc.cluster.local:8080")).cloned();
+        assert!(expected.is_some());
+
+        assert_eq!(routes.get(&authority("reports:8080")).cloned(), expected);
+        assert_eq!(routes.get(&authority("reports.default:8080")).cloned(), expected);
+        assert_eq!(routes.get(&authority("reports.default.svc:8080")).cloned(), expected);
+    }
+
+    #[test]
+    fn unmatched_authorities_have_no_policy() {
+        let routes = routes();
+
+        // A different port is a different route.
+        assert!(routes.get(&authority("reports:80")).is_none());
+        // The same name in another namespace is a different service.
+        assert!(routes.get(&authority("reports.other:8080")).is_none());
+        assert!(routes.get(&authority("www.example.com")).is_none());
+    }
+
+    #[test]
+    fn first_matching_path_prefix_selects_the_protocol() {
+        use http::{self, Version};
+
+        use bind::{self, Protocol};
+
+        let protocols = PathProtocols::default()
+            .with_prefix("/package.Service/".into(), ProtocolPolicy::ForceHttp2)
+            .with_prefix("/package.".into(), ProtocolPolicy::Detect)
+            .with_prefix("/".into(), ProtocolPolicy::ForceHttp1);
+        let protocol = |path: &str, version: Version| {
+            let uri = format!("http://example.com{}", path);
+            let req = http::Request::builder()
+                .uri(uri.as_str())
+                .version(version)
+                .body(())
+                .unwrap();
+            let policy = protocols.get(req.uri().path()).unwrap_or(ProtocolPolicy::Detect);
+            Protocol::detect(&req).coerce(&req, policy)
+        };
+        let h1 = Protocol::Http1(bind::Host::Authority("example.com".parse().unwrap()));
+
+        // gRPC requests are sent over HTTP/2, even if they were received
+        // over HTTP/1.
+        assert_eq!(protocol("/package.Service/Method", Version::HTTP_11), Protocol::Http2);
+        assert_eq!(protocol("/package.Service/Method", Version::HTTP_2), Protocol::Http2);
+        // Earlier prefixes win over later ones.
+        assert_eq!(protocol("/package.Other/Method", Version::HTTP_2), Protocol::Http2);
+        assert_eq!(protocol("/package.Other/Method", Version::HTTP_11), h1);
+        // REST requests are sent over HTTP/1, even if they were received over
+        // HTTP/2.
+        assert_eq!(protocol("/users/1", Version::HTTP_2), h1);
+        assert_eq!(protocol("/users/1", Version::HTTP_11), h1);
+
+        let rest = PathProtocols::default()
+            .with_prefix("/api/".into(), ProtocolPolicy::ForceHttp1);
+        assert_eq!(rest.get("/package.Service/Method"), None);
+        assert_eq!(rest.get("/api/users"), Some(ProtocolPolicy::ForceHttp1));
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use http;
+use rand::Rng;
+
+use balance;
+use transport::{DnsNameAndPort, Host, HostAndPort};
+
+/// Splits the requests for an authority between backend authorities, in
+/// proportion to their weights, such as 90% to `books-v1` and 10% to
+/// `books-v2`.
+///
+/// Each request is sent to a single backend, chosen at random, which is
+/// resolved through discovery and balanced over as if the request had been
+/// sent to it. The request's own authority isn't rewritten.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Split {
+    backends: Vec<(DnsNameAndPort, u32)>,
+}
+
+/// The `Split`s of each authority that is split.
+///
+/// Splits may be changed while requests are routed, e.g. through the admin
+/// API, and clones share the same splits.
+#[derive(Clone, Debug, Default)]
+pub struct TrafficSplits(Arc<RwLock<HashMap<DnsNameAndPort, Split>>>);
+
+// ===== impl Split =====
+
+impl Split {
+    /// Parses a comma-separated list of backends, each an authority and a
+    /// weight, such as `books-v1:8080=90,books-v2:8080=10`.
+    ///
+    /// Port 80 is assumed if a backend has no port. A split must have at
+    /// least one backend with a non-zero weight.
+    pub fn parse(s: &str) -> Option<Self> {
+        let backends = s.split(',')
+            .map(|backend| {
+                let mut parts = backend.rsplitn(2, '=');
+                let weight = parts.next()?.trim().parse::<u32>().ok()?;
+                let authority = parse_authority(parts.next()?)?;
+                Some((authority, weight))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if backends.iter().all(|&(_, weight)| weight == 0) {
+            return None;
+        }
+        Some(Split { backends })
+    }
+
+    /// Chooses the backend that a request is sent to.
+    fn choose<R: Rng>(&self, rng: &mut R) -> &DnsNameAndPort {
+        let backends = &self.backends;
+        let i = balance::select(rng, backends.len(), |i| backends[i].1)
+            .expect("splits have a backend with a non-zero weight");
+        &backends[i].0
+    }
+}
+
+/// Parses an authority with a DNS name, such as `books.default:8080`.
+/// Port 80 is assumed if the authority has no port.
+pub fn parse_authority(s: &str) -> Option<DnsNameAndPort> {
+    let authority = s.trim().parse::<http::uri::Authority>().ok()?;
+    match HostAndPort::normalize(&authority, Some(80)) {
+        Ok(HostAndPort { host: Host::DnsName(host), port }) => Some(DnsNameAndPort { host, port }),
+        _ => None,
+    }
+}
+
+// ===== impl TrafficSplits =====
+
+impl TrafficSplits {
+    /// Splits the requests for `authority` by `split`, replacing its
+    /// previous split, if any.
+    pub fn set(&self, authority: DnsNameAndPort, split: Split) {
+        debug!("splitting {:?}: {:?}", authority, split);
+        self.0.write().expect("traffic splits lock poisoned").insert(authority, split);
+    }
+
+    /// Stops splitting the requests for `authority`.
+    pub fn remove(&self, authority: &DnsNameAndPort) {
+        self.0.write().expect("traffic splits lock poisoned").remove(authority);
+    }
+
+    /// Returns the backend that a request for `authority` is sent to, or
+    /// `None` if `authority` isn't split.
+    pub fn route<R: Rng>(&self, authority: &DnsNameAndPort, rng: &mut R) -> Option<DnsNameAndPort> {
+        let splits = self.0.read().expect("traffic splits lock poisoned");
+        splits.get(authority).map(|split| split.choose(rng).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+
+    const REQUESTS: usize = 10_000;
+
+    fn authority(s: &str) -> DnsNameAndPort {
+        parse_authority(s).expect("authority")
+    }
+
+    /// Routes `REQUESTS` requests for `apex`, returning the number sent to
+    /// each of `backends`, and then the number that weren't split.
+    fn distribution(splits: &TrafficSplits, apex: &str, backends: &[&str]) -> Vec<usize> {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut counts = vec![0; backends.len() + 1];
+        for _ in 0..REQUESTS {
+            let i = match splits.route(&authority(apex), &mut rng) {
+                Some(backend) => backends.iter()
+                    .position(|b| authority(b) == backend)
+                    .expect("routed to a backend"),
+                None => backends.len(),
+            };
+            counts[i] += 1;
+        }
+        counts
+    }
+
+    fn assert_share(count: usize, percent: usize) {
+        let expected = REQUESTS * percent / 100;
+        let tolerance = REQUESTS / 50;
+        assert!(
+            (count as isize - expected as isize).abs() as usize <= tolerance,
+            "expected ~{} requests, got {}", expected, count
+        );
+    }
+
+    #[test]
+    fn parses_splits() {
+        let split = Split::parse("books-v1:8080=90, books-v2=10").expect("split");
+        assert_eq!(split.backends, vec![
+            (authority("books-v1:8080"), 90),
+            (authority("books-v2:80"), 10),
+        ]);
+
+        assert_eq!(Split::parse("books-v1:8080"), None);
+        assert_eq!(Split::parse("books-v1:8080=ninety"), None);
+        assert_eq!(Split::parse("10.1.1.1:8080=90"), None);
+        assert_eq!(Split::parse("books-v1:8080=0,books-v2:8080=0"), None);
+    }
+
+    #[test]
+    fn requests_are_split_by_weight() {
+        let splits = TrafficSplits::default();
+        let split = Split::parse("books-v1.test:8080=90,books-v2.test:8080=10").unwrap();
+        splits.set(authority("books.test:8080"), split);
+
+        let backends = ["books-v1.test:8080", "books-v2.test:8080"];
+        let counts = distribution(&splits, "books.test:8080", &backends);
+        assert_share(counts[0], 90);
+        assert_share(counts[1], 10);
+        assert_eq!(counts[2], 0);
+
+        // Other authorities, and other ports, aren't split.
+        let counts = distribution(&splits, "books.test:9090", &backends);
+        assert_eq!(counts, vec![0, 0, REQUESTS]);
+    }
+
+    #[test]
+    fn splits_may_be_updated() {
+        let splits = TrafficSplits::default();
+        let backends = ["books-v1.test:8080", "books-v2.test:8080"];
+        let apex = authority("books.test:8080");
+        let split = |s: &str| Split::parse(s).expect("split");
+        splits.set(apex.clone(), split("books-v1.test:8080=90,books-v2.test:8080=10"));
+
+        // Clones share the same splits.
+        let updated = splits.clone();
+        updated.set(apex.clone(), split("books-v1.test:8080=1,books-v2.test:8080=1"));
+        let counts = distribution(&splits, "books.test:8080", &backends);
+        assert_share(counts[0], 50);
+        assert_share(counts[1], 50);
+
+        updated.set(apex.clone(), split("books-v1.test:8080=0,books-v2.test:8080=1"));
+        let counts = distribution(&splits, "books.test:8080", &backends);
+        assert_eq!(counts, vec![0, REQUESTS, 0]);
+
+        updated.remove(&apex);
+        let counts = distribution(&splits, "books.test:8080", &backends);
+        assert_eq!(counts, vec![0, 0, REQUESTS]);
+    }
+}
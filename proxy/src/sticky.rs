@@ -0,0 +1,269 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use futures::{Async, Future, Poll};
+use http;
+use http::header::COOKIE;
+use tower::Service;
+
+use balance::{self, Choices};
+
+/// The part of a request that identifies its session.
+#[derive(Clone, Debug)]
+pub enum AffinityKey {
+    /// The value of a request header.
+    Header(http::header::HeaderName),
+    /// The value of a cookie with this name.
+    Cookie(String),
+}
+
+/// Sends each request with an affinity key to the endpoint with which the
+/// key has affinity, so that requests in the same session are sent to the
+/// same endpoint as long as it remains available.
+///
+/// The inner service is expected to be a balancer whose `Chooser` uses
+/// `choices`. The balancer is readied again for each request with a key,
+/// preferring the key's endpoint. Requests without a key are balanced as
+/// normal. If constructed without an `AffinityKey`, this is a no-op.
+pub struct Sticky<S> {
+    inner: Rc<RefCell<S>>,
+    key: Option<AffinityKey>,
+    choices: Choices,
+}
+
+pub enum ResponseFuture<S: Service> {
+    /// Waiting for the service to become ready for a request with a key.
+    Dispatching {
+        service: Rc<RefCell<S>>,
+        choices: Choices,
+        key: u64,
+        request: Option<S::Request>,
+    },
+    Pending(S::Future),
+    Failed(Option<S::Error>),
+}
+
+// ===== impl AffinityKey =====
+
+impl AffinityKey {
+    /// Returns a hash of the key's value in `req`, if it has one.
+    fn hash<B>(&self, req: &http::Request<B>) -> Option<u64> {
+        match *self {
+            AffinityKey::Header(ref name) => req.headers()
+                .get(name)
+                .map(|value| balance::hash_key(value.as_bytes())),
+            AffinityKey::Cookie(ref name) => req.headers()
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|cookies| cookies.split(';'))
+                .filter_map(|cookie| {
+                    let mut kv = cookie.splitn(2, '=');
+                    match (kv.next().map(str::trim), kv.next()) {
+                        (Some(n), Some(value)) if n == name.as_str() => Some(value.trim()),
+                        _ => None,
+                    }
+                })
+                .next()
+                .map(|value| balance::hash_key(value.as_bytes())),
+        }
+    }
+}
+
+// ===== impl Sticky =====
+
+impl<S> Sticky<S> {
+    pub fn new(inner: S, key: Option<AffinityKey>, choices: Choices) -> Self {
+        Sticky {
+            inner: Rc::new(RefCell::new(inner)),
+            key,
+            choices,
+        }
+    }
+}
+
+impl<S, A> Service for Sticky<S>
+where
+    S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.borrow_mut().poll_ready()
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let key = self.key.as_ref().and_then(|key| key.hash(&req));
+        let fut = match key {
+            // The endpoint chosen while becoming ready already serves
+            // requests without a key.
+            None => ResponseFuture::Pending(self.inner.borrow_mut().call(req)),
+            Some(key) => ResponseFuture::Dispatching {
+                service: self.inner.clone(),
+                choices: self.choices.clone(),
+                key,
+                request: Some(req),
+            },
+        };
+        // Dispatching now, rather than once polled, keeps requests in the
+        // order they were buffered unless the balancer isn't ready.
+        fut.dispatch()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S: Service> ResponseFuture<S> {
+    /// Sends the request once the service is ready with the endpoint chosen
+    /// for its key.
+    fn dispatch(self) -> Self {
+        match self {
+            ResponseFuture::Dispatching { service, choices, key, mut request } => {
+                choices.prefer(key);
+                let ready = service.borrow_mut().poll_ready();
+                choices.stop_preferring();
+
+                match ready {
+                    Ok(Async::NotReady) => ResponseFuture::Dispatching {
+                        service,
+                        choices,
+                        key,
+                        request,
+                    },
+                    Ok(Async::Ready(())) => {
+                        let req = request.take().expect("dispatched after completed");
+                        let fut = service.borrow_mut().call(req);
+                        ResponseFuture::Pending(fut)
+                    }
+                    Err(e) => ResponseFuture::Failed(Some(e)),
+                }
+            }
+            fut => fut,
+        }
+    }
+}
+
+impl<S: Service> Future for ResponseFuture<S> {
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match mem::replace(self, ResponseFuture::Failed(None)) {
+                fut @ ResponseFuture::Dispatching { .. } => match fut.dispatch() {
+                    fut @ ResponseFuture::Dispatching { .. } => {
+                        *self = fut;
+                        return Ok(Async::NotReady);
+                    }
+                    fut => fut,
+                },
+                ResponseFuture::Pending(mut fut) => {
+                    let poll = fut.poll();
+                    *self = ResponseFuture::Pending(fut);
+                    return poll;
+                }
+                ResponseFuture::Failed(e) => {
+                    return Err(e.expect("polled after failed"));
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::future::{self, FutureResult};
+
+    use super::*;
+
+    /// Responds with the affinity that it was readied with, if it was.
+    struct Preferred {
+        choices: Choices,
+        ready: Rc<Cell<bool>>,
+        chosen: Option<Option<u64>>,
+    }
+
+    impl Service for Preferred {
+        type Request = http::Request<()>;
+        type Response = Option<u64>;
+        type Error = ();
+        type Future = FutureResult<Option<u64>, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            if !self.ready.get() {
+                return Ok(Async::NotReady);
+            }
+            self.chosen = Some(self.choices.affinity());
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            future::ok(self.chosen.take().expect("called before ready"))
+        }
+    }
+
+    fn sticky(key: AffinityKey) -> (Sticky<Preferred>, Rc<Cell<bool>>) {
+        let choices = Choices::default();
+        let ready = Rc::new(Cell::new(true));
+        let svc = Preferred {
+            choices: choices.clone(),
+            ready: ready.clone(),
+            chosen: None,
+        };
+        (Sticky::new(svc, Some(key), choices), ready)
+    }
+
+    fn send(svc: &mut Sticky<Preferred>, req: http::Request<()>) -> Option<u64> {
+        assert!(svc.poll_ready().unwrap().is_ready());
+        svc.call(req).wait().unwrap()
+    }
+
+    #[test]
+    fn requests_with_a_header_prefer_its_endpoint() {
+        let (mut svc, _) = sticky(AffinityKey::Header("x-session".parse().unwrap()));
+
+        let req = http::Request::get("/").header("x-session", "abc").body(()).unwrap();
+        assert_eq!(send(&mut svc, req), Some(balance::hash_key(b"abc")));
+
+        let req = http::Request::get("/").body(()).unwrap();
+        assert_eq!(send(&mut svc, req), None);
+    }
+
+    #[test]
+    fn requests_with_a_cookie_prefer_its_endpoint() {
+        let (mut svc, _) = sticky(AffinityKey::Cookie("session".to_owned()));
+
+        let req = http::Request::get("/")
+            .header(COOKIE, "theme=dark")
+            .header(COOKIE, "lang=en; session=abc")
+            .body(())
+            .unwrap();
+        assert_eq!(send(&mut svc, req), Some(balance::hash_key(b"abc")));
+
+        let req = http::Request::get("/")
+            .header(COOKIE, "sessions=abc; theme=dark")
+            .body(())
+            .unwrap();
+        assert_eq!(send(&mut svc, req), None);
+    }
+
+    #[test]
+    fn requests_wait_for_the_balancer_to_be_ready() {
+        let (mut svc, ready) = sticky(AffinityKey::Header("x-session".parse().unwrap()));
+        assert!(svc.poll_ready().unwrap().is_ready());
+
+        ready.set(false);
+        let req = http::Request::get("/").header("x-session", "abc").body(()).unwrap();
+        let mut rsp = svc.call(req);
+        assert!(rsp.poll().unwrap().is_not_ready());
+
+        ready.set(true);
+        assert_eq!(rsp.poll(), Ok(Async::Ready(Some(balance::hash_key(b"abc")))));
+    }
+}
@@ -0,0 +1,516 @@
+//! Logs a record of each completed request, for ingestion by log pipelines.
+//!
+//! Records are written as lines of JSON, or of text in the style of the
+//! Common Log Format, to stdout unless another sink is provided. Logged
+//! headers are captured by the sensors, so their values are redacted if the
+//! sensors redact them.
+
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::{Async, Future, Poll, Stream};
+use h2;
+use http;
+use http::header::{HeaderName, HOST};
+
+use ctx;
+use super::events::{LifecycleEvent, RequestEvent, Subscription};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// How access log records are written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line.
+    Json,
+    /// A line in the Common Log Format, followed by the fields that it omits
+    /// as `key=value` pairs.
+    Common,
+}
+
+/// A future that writes an access log record for each request that ends
+/// after it subscribed.
+pub struct AccessLog {
+    subscription: Subscription,
+    format: Format,
+    /// The request headers included in each record.
+    headers: Vec<HeaderName>,
+    sink: Box<io::Write>,
+}
+
+/// Describes a completed request.
+#[derive(Debug)]
+struct Record<'a> {
+    timestamp: SystemTime,
+    request: &'a ctx::http::Request,
+    status: Option<http::StatusCode>,
+    error: Option<h2::Reason>,
+    duration: Duration,
+    request_bytes: u64,
+    response_bytes: u64,
+}
+
+// ===== impl AccessLog =====
+
+impl AccessLog {
+    /// Logs the requests received by `subscription` to stdout.
+    pub fn new(subscription: Subscription, format: Format) -> Self {
+        AccessLog {
+            subscription,
+            format,
+            headers: Vec::new(),
+            sink: Box::new(io::stdout()),
+        }
+    }
+
+    /// Includes the values of the request headers named in `headers` in each
+    /// record.
+    pub fn with_headers(self, headers: Vec<HeaderName>) -> Self {
+        AccessLog { headers, ..self }
+    }
+
+    /// Writes records to `sink`, rather than stdout.
+    pub fn with_sink(self, sink: Box<io::Write>) -> Self {
+        AccessLog { sink, ..self }
+    }
+
+    fn log(&mut self, record: &Record) {
+        let mut line = match self.format {
+            Format::Json => record.json(&self.headers),
+            Format::Common => record.common(&self.headers),
+        };
+        line.push('\n');
+        let written = self.sink.write_all(line.as_bytes()).and_then(|_| self.sink.flush());
+        if let Err(e) = written {
+            warn!("failed to write access log: {}", e);
+        }
+    }
+}
+
+impl Future for AccessLog {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let mut dropped = self.subscription.dropped();
+        while let Some(event) = try_ready!(self.subscription.poll()) {
+            let now_dropped = self.subscription.dropped();
+            if now_dropped > dropped {
+                warn!("dropped access log records for {} events", now_dropped - dropped);
+                dropped = now_dropped;
+            }
+
+            if let LifecycleEvent::Request(RequestEvent::Ended {
+                ref request,
+                status,
+                error,
+                duration,
+                request_bytes,
+                response_bytes,
+            }) = event {
+                self.log(&Record {
+                    timestamp: SystemTime::now(),
+                    request,
+                    status,
+                    error,
+                    duration,
+                    request_bytes,
+                    response_bytes,
+                });
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+// ===== impl Format =====
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(Format::Json),
+            "common" => Some(Format::Common),
+            _ => None,
+        }
+    }
+}
+
+// ===== impl Record =====
+
+impl<'a> Record<'a> {
+    fn direction(&self) -> &'static str {
+        if self.request.server.proxy.is_inbound() {
+            "inbound"
+        } else {
+            "outbound"
+        }
+    }
+
+    /// The request's authority, from its URI or else its `Host` header.
+    fn authority(&self) -> Option<&str> {
+        self.request.uri.authority_part()
+            .map(|a| a.as_str())
+            .or_else(|| self.request.headers.get(HOST).and_then(|h| h.to_str().ok()))
+    }
+
+    fn client(&self) -> &SocketAddr {
+        &self.request.server.remote
+    }
+
+    fn endpoint(&self) -> &SocketAddr {
+        &self.request.client.remote
+    }
+
+    fn duration_ms(&self) -> u64 {
+        self.duration.as_secs() * 1_000 + u64::from(self.duration.subsec_nanos() / 1_000_000)
+    }
+
+    /// Returns the values of the request's `name` headers, joined by commas.
+    fn header(&self, name: &HeaderName) -> Option<String> {
+        let values = self.request.headers.get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap_or("<non-ascii>"))
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    fn json(&self, headers: &[HeaderName]) -> String {
+        let mut out = String::from("{");
+        let _ = write!(out, "\"timestamp\":\"{}\"", Rfc3339(self.timestamp));
+        let _ = write!(out, ",\"direction\":\"{}\"", self.direction());
+        let _ = write!(out, ",\"request_id\":{}", self.request.id);
+        out.push_str(",\"method\":");
+        push_json_str(&mut out, self.request.method.as_str());
+        out.push_str(",\"authority\":");
+        match self.authority() {
+            Some(authority) => push_json_str(&mut out, authority),
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"path\":");
+        push_json_str(&mut out, self.request.uri.path());
+        match self.status {
+            Some(status) => { let _ = write!(out, ",\"status\":{}", status.as_u16()); }
+            None => out.push_str(",\"status\":null"),
+        }
+        match self.error {
+            Some(error) => { let _ = write!(out, ",\"error\":\"{:?}\"", error); }
+            None => out.push_str(",\"error\":null"),
+        }
+        let _ = write!(out, ",\"duration_ms\":{}", self.duration_ms());
+        let _ = write!(out, ",\"bytes_in\":{}", self.request_bytes);
+        let _ = write!(out, ",\"bytes_out\":{}", self.response_bytes);
+        let _ = write!(out, ",\"client\":\"{}\"", self.client());
+        let _ = write!(out, ",\"endpoint\":\"{}\"", self.endpoint());
+        if !headers.is_empty() {
+            out.push_str(",\"headers\":{");
+            let mut first = true;
+            for name in headers {
+                if let Some(value) = self.header(name) {
+                    if !first {
+                        out.push(',');
+                    }
+                    first = false;
+                    push_json_str(&mut out, name.as_str());
+                    out.push(':');
+                    push_json_str(&mut out, &value);
+                }
+            }
+            out.push('}');
+        }
+        out.push('}');
+        out
+    }
+
+    fn common(&self, headers: &[HeaderName]) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{} - - [{}] \"{} {} {:?}\" ",
+            self.client().ip(),
+            CommonDate(self.timestamp),
+            self.request.method,
+            self.request.uri.path(),
+            self.request.version,
+        );
+        match self.status {
+            Some(status) => { let _ = write!(out, "{}", status.as_u16()); }
+            None => out.push('-'),
+        }
+        let _ = write!(out, " {}", self.response_bytes);
+        let _ = write!(out, " direction={}", self.direction());
+        let _ = write!(out, " request_id={}", self.request.id);
+        let _ = write!(out, " authority={}", self.authority().unwrap_or("-"));
+        if let Some(error) = self.error {
+            let _ = write!(out, " error={:?}", error);
+        }
+        let _ = write!(out, " duration_ms={}", self.duration_ms());
+        let _ = write!(out, " bytes_in={}", self.request_bytes);
+        let _ = write!(out, " endpoint={}", self.endpoint());
+        for name in headers {
+            if let Some(value) = self.header(name) {
+                let _ = write!(out, " {}={:?}", name, value);
+            }
+        }
+        out
+    }
+}
+
+/// Appends `s` to `out` as a JSON string.
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Formats a time in UTC, as in `2018-06-01T12:30:45.123Z`.
+struct Rfc3339(SystemTime);
+
+/// Formats a time in UTC as in the Common Log Format, as in
+/// `01/Jun/2018:12:30:45 +0000`.
+struct CommonDate(SystemTime);
+
+/// A time in UTC, broken into its calendar date and time of day.
+struct Civil {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    millis: u32,
+}
+
+impl Civil {
+    fn new(time: SystemTime) -> Self {
+        // Times before the epoch aren't expected from the system clock.
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let days = (secs / 86_400) as i64;
+        let secs_of_day = secs % 86_400;
+
+        // Converts days since the epoch to a proleptic Gregorian date, per
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Civil {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3_600,
+            minute: secs_of_day % 3_600 / 60,
+            second: secs_of_day % 60,
+            millis: since_epoch.subsec_nanos() / 1_000_000,
+        }
+    }
+}
+
+impl ::std::fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let t = Civil::new(self.0);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            t.year, t.month, t.day, t.hour, t.minute, t.second, t.millis,
+        )
+    }
+}
+
+impl ::std::fmt::Display for CommonDate {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let t = Civil::new(self.0);
+        write!(
+            f,
+            "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+            t.day, MONTHS[t.month as usize - 1], t.year, t.hour, t.minute, t.second,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    use conduit_proxy_controller_grpc::common::Protocol;
+    use futures::future;
+
+    use super::*;
+    use telemetry::classify::Classification;
+    use telemetry::event::{self, Event};
+    use telemetry::events::Events;
+
+    /// Collects the records that are written to it.
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone())
+                .expect("utf-8")
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    fn request(id: u64) -> Arc<ctx::http::Request> {
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let server_addr = "127.0.0.1:5432".parse().unwrap();
+        let endpoint = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(
+            &proxy, &server_addr, &server_addr, &None, Protocol::Http,
+        );
+        let client = ctx::transport::Client::new(&proxy, &endpoint, Protocol::Http, false);
+
+        let mut req = http::Request::new(());
+        *req.method_mut() = http::Method::POST;
+        *req.uri_mut() = "http://example.com/users/1?secret=1".parse().unwrap();
+        let mut headers = http::HeaderMap::new();
+        headers.insert("authorization", "[REDACTED]".parse().unwrap());
+        headers.insert("user-agent", "test \"agent\"".parse().unwrap());
+        ctx::http::Request::new(&req, &server, &client, id, ctx::http::Headers::new(headers))
+    }
+
+    fn response_end(id: u64) -> Event {
+        let mut rsp = http::Response::new(());
+        *rsp.status_mut() = http::StatusCode::CREATED;
+        let rsp = ctx::http::Response::new(&rsp, &request(id), Default::default());
+        Event::StreamResponseEnd(rsp, event::StreamResponseEnd {
+            grpc_status: None,
+            classification: Classification::Success,
+            since_request_open: Duration::from_millis(15),
+            since_response_open: Duration::from_millis(2),
+            bytes_sent: 42,
+            frames_sent: 1,
+            request_bytes: 7,
+            grpc_stream: None,
+        })
+    }
+
+    fn request_fail(id: u64) -> Event {
+        Event::StreamRequestFail(request(id), event::StreamRequestFail {
+            since_request_open: Duration::from_millis(3),
+            error: h2::Reason::REFUSED_STREAM,
+            request_bytes: 0,
+        })
+    }
+
+    fn log(format: Format, published: Vec<Event>) -> Vec<String> {
+        let events = Events::default();
+        let capture = Capture::default();
+        let mut log = AccessLog::new(events.subscribe(10), format)
+            .with_headers(vec![
+                HeaderName::from_static("authorization"),
+                HeaderName::from_static("user-agent"),
+                HeaderName::from_static("x-missing"),
+            ])
+            .with_sink(Box::new(capture.clone()));
+        for ev in &published {
+            events.publish(ev);
+        }
+        future::lazy(|| {
+            assert!(log.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+        capture.lines()
+    }
+
+    #[test]
+    fn logs_completed_requests_as_json() {
+        let lines = log(Format::Json, vec![
+            Event::StreamRequestOpen(request(1)),
+            response_end(1),
+            request_fail(2),
+        ]);
+        assert_eq!(lines.len(), 2, "unexpected records: {:?}", lines);
+
+        assert!(lines[0].starts_with("{\"timestamp\":\""), "{}", lines[0]);
+        let (_, fields) = lines[0].split_at(lines[0].find("Z\",").expect("timestamp") + 3);
+        assert_eq!(
+            fields,
+            "\"direction\":\"outbound\",\"request_id\":1,\"method\":\"POST\",\
+             \"authority\":\"example.com\",\"path\":\"/users/1\",\"status\":201,\
+             \"error\":null,\"duration_ms\":15,\"bytes_in\":7,\"bytes_out\":42,\
+             \"client\":\"127.0.0.1:5432\",\"endpoint\":\"10.1.1.1:8080\",\
+             \"headers\":{\"authorization\":\"[REDACTED]\",\"user-agent\":\"test \\\"agent\\\"\"}}"
+        );
+
+        assert!(lines[1].contains("\"request_id\":2,"), "{}", lines[1]);
+        assert!(lines[1].contains("\"status\":null,\"error\":\"REFUSED_STREAM\""), "{}", lines[1]);
+    }
+
+    #[test]
+    fn logs_completed_requests_as_common_log_format() {
+        let lines = log(Format::Common, vec![response_end(1)]);
+        assert_eq!(lines.len(), 1, "unexpected records: {:?}", lines);
+
+        let (host, rest) = lines[0].split_at(lines[0].find(" [").expect("date"));
+        assert_eq!(host, "127.0.0.1 - -");
+        let (_, rest) = rest.split_at(rest.find("] ").expect("date") + 2);
+        assert_eq!(
+            rest,
+            "\"POST /users/1 HTTP/1.1\" 201 42 direction=outbound request_id=1 \
+             authority=example.com duration_ms=15 bytes_in=7 endpoint=10.1.1.1:8080 \
+             authorization=\"[REDACTED]\" user-agent=\"test \\\"agent\\\"\""
+        );
+    }
+
+    #[test]
+    fn formats_timestamps_in_utc() {
+        // 2018-06-01T12:30:45.123Z
+        let time = UNIX_EPOCH + Duration::from_millis(1_527_856_245_123);
+        assert_eq!(Rfc3339(time).to_string(), "2018-06-01T12:30:45.123Z");
+        assert_eq!(CommonDate(time).to_string(), "01/Jun/2018:12:30:45 +0000");
+
+        // A leap day.
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(Rfc3339(time).to_string(), "2000-02-29T00:00:00.000Z");
+        assert_eq!(Rfc3339(UNIX_EPOCH).to_string(), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!(Format::parse("json"), Some(Format::Json));
+        assert_eq!(Format::parse("common"), Some(Format::Common));
+        assert_eq!(Format::parse("xml"), None);
+    }
+}
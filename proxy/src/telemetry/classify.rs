@@ -0,0 +1,107 @@
+use std::fmt;
+use std::sync::Arc;
+
+use http;
+
+/// Whether a response indicates that its request succeeded.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Classification {
+    Success,
+    Failure,
+    /// The response was injected as a fault by the proxy, so it describes
+    /// neither the success nor the failure of an endpoint.
+    Injected,
+}
+
+/// Classifies completed responses as successes or failures.
+pub trait Classifier: fmt::Debug {
+    /// Classifies a response by its HTTP status and, for gRPC responses, the
+    /// value of its `grpc-status` trailer.
+    fn classify(&self, status: http::StatusCode, grpc_status: Option<u32>) -> Classification;
+}
+
+/// A shared `Classifier`.
+pub type SharedClassifier = Arc<Classifier + Send + Sync>;
+
+/// Classifies responses by their HTTP status.
+///
+/// By default, only server errors (5xx) are failures. gRPC responses are
+/// classified by their `grpc-status` instead, since gRPC errors are sent with
+/// a `200 OK` status; any status other than `OK` (0) is a failure.
+#[derive(Clone, Debug, Default)]
+pub struct StatusClassifier {
+    /// If set, the only HTTP statuses that are failures.
+    failures: Option<Vec<http::StatusCode>>,
+}
+
+// ===== impl StatusClassifier =====
+
+impl StatusClassifier {
+    /// Classifies only responses with one of `statuses` as failures, rather
+    /// than all server errors.
+    pub fn with_failure_statuses(statuses: Vec<http::StatusCode>) -> Self {
+        StatusClassifier {
+            failures: Some(statuses),
+        }
+    }
+}
+
+impl Classifier for StatusClassifier {
+    fn classify(&self, status: http::StatusCode, grpc_status: Option<u32>) -> Classification {
+        let failed = match (grpc_status, self.failures.as_ref()) {
+            (Some(code), _) => code != 0,
+            (None, Some(failures)) => failures.contains(&status),
+            (None, None) => status.is_server_error(),
+        };
+        if failed {
+            Classification::Failure
+        } else {
+            Classification::Success
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_errors_are_failures_by_default() {
+        let classifier = StatusClassifier::default();
+        for &status in &[200, 204, 302, 404, 429] {
+            let status = http::StatusCode::from_u16(status).unwrap();
+            assert_eq!(classifier.classify(status, None), Classification::Success);
+        }
+        for &status in &[500, 502, 503] {
+            let status = http::StatusCode::from_u16(status).unwrap();
+            assert_eq!(classifier.classify(status, None), Classification::Failure);
+        }
+    }
+
+    #[test]
+    fn configured_failure_statuses() {
+        let classifier = StatusClassifier::with_failure_statuses(vec![
+            http::StatusCode::TOO_MANY_REQUESTS,
+            http::StatusCode::SERVICE_UNAVAILABLE,
+        ]);
+        let classify = |status| classifier.classify(status, None);
+        assert_eq!(classify(http::StatusCode::OK), Classification::Success);
+        assert_eq!(classify(http::StatusCode::NOT_FOUND), Classification::Success);
+        assert_eq!(classify(http::StatusCode::INTERNAL_SERVER_ERROR), Classification::Success);
+        assert_eq!(classify(http::StatusCode::TOO_MANY_REQUESTS), Classification::Failure);
+        assert_eq!(classify(http::StatusCode::SERVICE_UNAVAILABLE), Classification::Failure);
+    }
+
+    #[test]
+    fn grpc_responses_are_classified_by_grpc_status() {
+        let classifier = StatusClassifier::default();
+        let ok = http::StatusCode::OK;
+        assert_eq!(classifier.classify(ok, Some(0)), Classification::Success);
+        // UNAVAILABLE
+        assert_eq!(classifier.classify(ok, Some(14)), Classification::Failure);
+
+        // Configured failure statuses do not apply to gRPC responses.
+        let classifier = StatusClassifier::with_failure_statuses(vec![ok]);
+        assert_eq!(classifier.classify(ok, Some(0)), Classification::Success);
+    }
+}
@@ -1,14 +1,26 @@
 use std::io;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use futures::{future, Async, Future, Poll, Stream};
+use futures::future::Either;
 use futures_mpsc_lossy::Receiver;
+use hyper;
+use hyper::server::{
+    Service as HyperService,
+    Request as HyperRequest,
+    Response as HyperResponse
+};
 use tokio_core::reactor::Handle;
 
 use super::event::Event;
+use super::export::{self, Exporter};
 use super::metrics;
+use super::recent::{self, RecentRequests};
+use super::sensor::{ByteCounts, DnsLookups, InFlight, RequestAttempts, Terminations};
 use super::tap::Taps;
 use connection;
+use control::state::{self, DiscoveryState};
 use ctx;
 
 /// A `Control` which has been configured but not initialized.
@@ -18,6 +30,24 @@ pub struct MakeControl {
     rx: Receiver<Event>,
 
     process_ctx: Arc<ctx::Process>,
+
+    metrics_config: metrics::Config,
+
+    in_flight: InFlight,
+
+    byte_counts: ByteCounts,
+
+    terminations: Terminations,
+
+    dns_lookups: DnsLookups,
+
+    request_attempts: RequestAttempts,
+
+    /// Serves the admin API for recent requests, if they are recorded.
+    recent_requests: Option<recent::Serve>,
+
+    /// Serves the admin API for discovery state, if it is reported.
+    endpoints: Option<state::Serve>,
 }
 
 /// Handles the receipt of events.
@@ -39,6 +69,12 @@ pub struct Control {
     /// Serves scrapable metrics.
     metrics_service: metrics::Serve,
 
+    /// Serves the admin API for recent requests, if they are recorded.
+    recent_requests: Option<recent::Serve>,
+
+    /// Serves the admin API for discovery state, if it is reported.
+    endpoints: Option<state::Serve>,
+
     /// Receives telemetry events.
     rx: Option<Receiver<Event>>,
 
@@ -48,6 +84,15 @@ pub struct Control {
     handle: Handle,
 }
 
+/// Serves metrics and, if they are recorded, recent requests and discovery
+/// state.
+#[derive(Clone, Debug)]
+struct Admin {
+    metrics: metrics::Serve,
+    recent_requests: Option<recent::Serve>,
+    endpoints: Option<state::Serve>,
+}
+
 // ===== impl MakeControl =====
 
 impl MakeControl {
@@ -56,13 +101,49 @@ impl MakeControl {
     /// # Arguments
     /// - `rx`: the `Receiver` side of the channel on which events are sent.
     /// - `process_ctx`: runtime process metadata.
+    /// - `metrics_config`: configures metrics aggregation.
+    /// - `in_flight`: counts the requests in flight to each endpoint.
+    /// - `byte_counts`: counts the bytes transferred by the proxy.
+    /// - `terminations`: counts how servers terminate client connections.
+    /// - `dns_lookups`: records the latency and failures of DNS lookups.
+    /// - `request_attempts`: counts the attempts made to send each request.
     pub(super) fn new(
         rx: Receiver<Event>,
         process_ctx: &Arc<ctx::Process>,
+        metrics_config: metrics::Config,
+        in_flight: &InFlight,
+        byte_counts: &ByteCounts,
+        terminations: &Terminations,
+        dns_lookups: &DnsLookups,
+        request_attempts: &RequestAttempts,
     ) -> Self {
         Self {
             rx,
             process_ctx: Arc::clone(process_ctx),
+            metrics_config,
+            in_flight: in_flight.clone(),
+            byte_counts: byte_counts.clone(),
+            terminations: terminations.clone(),
+            dns_lookups: dns_lookups.clone(),
+            request_attempts: request_attempts.clone(),
+            recent_requests: None,
+            endpoints: None,
+        }
+    }
+
+    /// Serves the admin API for `recent` requests alongside metrics.
+    pub fn with_recent_requests(self, recent: &RecentRequests) -> Self {
+        Self {
+            recent_requests: Some(recent.serve()),
+            ..self
+        }
+    }
+
+    /// Serves the admin API for discovery `state` alongside metrics.
+    pub fn with_discovery_state(self, state: &DiscoveryState) -> Self {
+        Self {
+            endpoints: Some(state.serve()),
+            ..self
         }
     }
 
@@ -76,12 +157,21 @@ impl MakeControl {
     /// - `Ok(())` if the timeout was successfully created.
     /// - `Err(io::Error)` if the timeout could not be created.
     pub fn make_control(self, taps: &Arc<Mutex<Taps>>, handle: &Handle) -> io::Result<Control> {
-        let (metrics_aggregate, metrics_service) =
-            metrics::new(&self.process_ctx);
+        let (metrics_aggregate, metrics_service) = metrics::new(
+            &self.process_ctx,
+            &self.metrics_config,
+            &self.in_flight,
+            &self.byte_counts,
+            &self.terminations,
+            &self.dns_lookups,
+            &self.request_attempts,
+        );
 
         Ok(Control {
             metrics_aggregate,
             metrics_service,
+            recent_requests: self.recent_requests,
+            endpoints: self.endpoints,
             rx: Some(self.rx),
             taps: Some(taps.clone()),
             handle: handle.clone(),
@@ -110,8 +200,11 @@ impl Control {
     pub fn serve_metrics(&self, bound_port: connection::BoundPort)
         -> Box<Future<Item = (), Error = io::Error> + 'static>
     {
-        use hyper;
-        let service = self.metrics_service.clone();
+        let service = Admin {
+            metrics: self.metrics_service.clone(),
+            recent_requests: self.recent_requests.clone(),
+            endpoints: self.endpoints.clone(),
+        };
         let hyper = hyper::server::Http::<hyper::Chunk>::new();
         bound_port.listen_and_fold(
             &self.handle,
@@ -130,6 +223,14 @@ impl Control {
             })
     }
 
+    /// Returns a future that exports metrics through `exporter` every
+    /// `interval`.
+    pub fn export_metrics(&self, exporter: Box<Exporter>, interval: Duration)
+        -> io::Result<export::Export>
+    {
+        export::Export::new(self.metrics_service.clone(), exporter, interval, &self.handle)
+    }
+
 }
 
 impl Future for Control {
@@ -156,3 +257,32 @@ impl Future for Control {
         }
     }
 }
+
+// ===== impl Admin =====
+
+impl HyperService for Admin {
+    type Request = HyperRequest;
+    type Response = HyperResponse;
+    type Error = hyper::Error;
+    type Future = Either<
+        Either<
+            <metrics::Serve as HyperService>::Future,
+            <recent::Serve as HyperService>::Future,
+        >,
+        <state::Serve as HyperService>::Future,
+    >;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        if let Some(ref endpoints) = self.endpoints {
+            if state::Serve::serves(req.path()) {
+                return Either::B(endpoints.call(req));
+            }
+        }
+        match self.recent_requests {
+            Some(ref recent) if recent::Serve::serves(req.path()) => {
+                Either::A(Either::B(recent.call(req)))
+            }
+            _ => Either::A(Either::A(self.metrics.call(req))),
+        }
+    }
+}
@@ -4,6 +4,7 @@ use std::time::Duration;
 use h2;
 
 use ctx;
+use telemetry::classify::Classification;
 
 #[derive(Clone, Debug)]
 pub enum Event {
@@ -27,15 +28,18 @@ pub struct TransportClose {
 
     pub duration: Duration,
 
-    // TODO
-    //pub rx_bytes: usize,
-    //pub tx_bytes: usize,
+    /// The number of bytes read from the transport.
+    pub rx_bytes: u64,
+    /// The number of bytes written to the transport.
+    pub tx_bytes: u64,
 }
 
 #[derive(Clone, Debug)]
 pub struct StreamRequestFail {
     pub since_request_open: Duration,
     pub error: h2::Reason,
+    /// The bytes of the request body that were sent before it failed.
+    pub request_bytes: u64,
 }
 
 #[derive(Clone, Debug)]
@@ -55,15 +59,31 @@ pub struct StreamResponseFail {
     pub error: h2::Reason,
     pub bytes_sent: u64,
     pub frames_sent: u32,
+    /// The bytes of the request body that were sent before the response
+    /// failed.
+    pub request_bytes: u64,
 }
 
 #[derive(Clone, Debug)]
 pub struct StreamResponseEnd {
     pub grpc_status: Option<u32>,
+    pub classification: Classification,
     pub since_request_open: Duration,
     pub since_response_open: Duration,
     pub bytes_sent: u64,
     pub frames_sent: u32,
+    /// The bytes of the request body that were sent before the response
+    /// ended.
+    pub request_bytes: u64,
+    /// Set if the response was a gRPC stream of more than one message.
+    pub grpc_stream: Option<GrpcStream>,
+}
+
+/// Describes the messages of a streaming gRPC response.
+#[derive(Clone, Debug)]
+pub struct GrpcStream {
+    pub messages: u32,
+    pub first_message_since_request_open: Duration,
 }
 
 // ===== impl Event =====
@@ -0,0 +1,345 @@
+//! A stream of individual connection and request lifecycle events.
+//!
+//! Unlike metrics, which aggregate telemetry, lifecycle events describe each
+//! connection and request as it occurs, for debugging and ad-hoc analysis.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use futures::{task, Async, Future, Poll, Stream};
+use h2;
+use http;
+
+use ctx;
+use super::event::Event;
+
+/// Publishes lifecycle events to all subscriptions.
+#[derive(Clone, Default)]
+pub struct Events {
+    subscribers: Arc<Mutex<Vec<Weak<Mutex<Buffer>>>>>,
+}
+
+/// A `Stream` of the lifecycle events published after it subscribed.
+///
+/// At most `capacity` events are buffered. If the subscription falls behind,
+/// the oldest buffered events are dropped to make room for new ones.
+pub struct Subscription(Arc<Mutex<Buffer>>);
+
+/// A future that logs each event received by a `Subscription`.
+pub struct Log(Subscription);
+
+#[derive(Clone, Debug)]
+pub enum LifecycleEvent {
+    Connection(ConnectionEvent),
+    Request(RequestEvent),
+}
+
+#[derive(Clone, Debug)]
+pub enum ConnectionEvent {
+    Opened(Arc<ctx::transport::Ctx>),
+    Closed {
+        ctx: Arc<ctx::transport::Ctx>,
+        /// Indicates that the connection was closed without error.
+        clean: bool,
+        duration: Duration,
+        rx_bytes: u64,
+        tx_bytes: u64,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub enum RequestEvent {
+    Started(Arc<ctx::http::Request>),
+    Ended {
+        request: Arc<ctx::http::Request>,
+        /// The response's status, if a response was received.
+        status: Option<http::StatusCode>,
+        /// The reason the request or response failed, if it did.
+        error: Option<h2::Reason>,
+        /// The time from when the request started until its response ended.
+        duration: Duration,
+        /// The bytes of the request body that were sent.
+        request_bytes: u64,
+        /// The bytes of the response body that were received.
+        response_bytes: u64,
+    },
+}
+
+struct Buffer {
+    capacity: usize,
+    events: VecDeque<LifecycleEvent>,
+    dropped: usize,
+    task: Option<task::Task>,
+}
+
+// ===== impl Events =====
+
+impl Events {
+    /// Returns a new subscription that buffers up to `capacity` events.
+    pub fn subscribe(&self, capacity: usize) -> Subscription {
+        assert!(capacity > 0, "subscription capacity must be positive");
+        let buffer = Arc::new(Mutex::new(Buffer {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+            dropped: 0,
+            task: None,
+        }));
+        self.subscribers.lock().expect("events lock").push(Arc::downgrade(&buffer));
+        Subscription(buffer)
+    }
+
+    /// Returns true if any subscription may receive events.
+    pub fn is_subscribed(&self) -> bool {
+        !self.subscribers.lock().expect("events lock").is_empty()
+    }
+
+    /// Publishes the lifecycle event described by `event`, if any, to all
+    /// subscriptions.
+    pub fn publish(&self, event: &Event) {
+        let mut subscribers = self.subscribers.lock().expect("events lock");
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let event = match LifecycleEvent::from_event(event) {
+            Some(event) => event,
+            None => return,
+        };
+        subscribers.retain(|subscriber| match subscriber.upgrade() {
+            Some(buffer) => {
+                buffer.lock().expect("subscription lock").push(event.clone());
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let subscribers = self.subscribers.lock().expect("events lock").len();
+        f.debug_struct("Events")
+            .field("subscribers", &subscribers)
+            .finish()
+    }
+}
+
+// ===== impl Subscription =====
+
+impl Subscription {
+    /// Returns the number of events that have been dropped because this
+    /// subscription fell behind.
+    pub fn dropped(&self) -> usize {
+        self.0.lock().expect("subscription lock").dropped
+    }
+
+    /// Logs each event received by this subscription.
+    pub fn log(self) -> Log {
+        Log(self)
+    }
+}
+
+impl Stream for Subscription {
+    type Item = LifecycleEvent;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut buffer = self.0.lock().expect("subscription lock");
+        match buffer.events.pop_front() {
+            Some(event) => Ok(Async::Ready(Some(event))),
+            None => {
+                buffer.task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let buffer = self.0.lock().expect("subscription lock");
+        f.debug_struct("Subscription")
+            .field("capacity", &buffer.capacity)
+            .field("buffered", &buffer.events.len())
+            .field("dropped", &buffer.dropped)
+            .finish()
+    }
+}
+
+// ===== impl Log =====
+
+impl Future for Log {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let mut dropped = self.0.dropped();
+        while let Some(event) = try_ready!(self.0.poll()) {
+            let now_dropped = self.0.dropped();
+            if now_dropped > dropped {
+                warn!("dropped {} lifecycle events", now_dropped - dropped);
+                dropped = now_dropped;
+            }
+            info!("{:?}", event);
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+// ===== impl LifecycleEvent =====
+
+impl LifecycleEvent {
+    fn from_event(event: &Event) -> Option<Self> {
+        let event = match *event {
+            Event::TransportOpen(ref ctx) => {
+                LifecycleEvent::Connection(ConnectionEvent::Opened(ctx.clone()))
+            }
+            Event::TransportClose(ref ctx, ref close) => {
+                LifecycleEvent::Connection(ConnectionEvent::Closed {
+                    ctx: ctx.clone(),
+                    clean: close.clean,
+                    duration: close.duration,
+                    rx_bytes: close.rx_bytes,
+                    tx_bytes: close.tx_bytes,
+                })
+            }
+            Event::StreamRequestOpen(ref req) => {
+                LifecycleEvent::Request(RequestEvent::Started(req.clone()))
+            }
+            Event::StreamRequestFail(ref req, ref fail) => {
+                LifecycleEvent::Request(RequestEvent::Ended {
+                    request: req.clone(),
+                    status: None,
+                    error: Some(fail.error),
+                    duration: fail.since_request_open,
+                    request_bytes: fail.request_bytes,
+                    response_bytes: 0,
+                })
+            }
+            Event::StreamResponseFail(ref rsp, ref fail) => {
+                LifecycleEvent::Request(RequestEvent::Ended {
+                    request: rsp.request.clone(),
+                    status: Some(rsp.status),
+                    error: Some(fail.error),
+                    duration: fail.since_request_open,
+                    request_bytes: fail.request_bytes,
+                    response_bytes: fail.bytes_sent,
+                })
+            }
+            Event::StreamResponseEnd(ref rsp, ref end) => {
+                LifecycleEvent::Request(RequestEvent::Ended {
+                    request: rsp.request.clone(),
+                    status: Some(rsp.status),
+                    error: None,
+                    duration: end.since_request_open,
+                    request_bytes: end.request_bytes,
+                    response_bytes: end.bytes_sent,
+                })
+            }
+            // A request's end is described by its response's end.
+            Event::StreamRequestEnd(..) |
+            Event::StreamResponseOpen(..) => return None,
+        };
+        Some(event)
+    }
+}
+
+// ===== impl Buffer =====
+
+impl Buffer {
+    fn push(&mut self, event: LifecycleEvent) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use conduit_proxy_controller_grpc::common::Protocol;
+    use futures::future;
+
+    use super::*;
+    use telemetry::event::TransportClose;
+
+    fn transport() -> Arc<ctx::transport::Ctx> {
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::inbound(&process);
+        let addr: SocketAddr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        Arc::new(ctx::transport::Ctx::Server(server))
+    }
+
+    fn close(rx_bytes: u64) -> Event {
+        Event::TransportClose(transport(), TransportClose {
+            clean: true,
+            duration: Duration::from_millis(1),
+            rx_bytes,
+            tx_bytes: 0,
+        })
+    }
+
+    fn received(sub: &mut Subscription) -> Vec<LifecycleEvent> {
+        future::lazy(|| {
+            let mut events = Vec::new();
+            while let Async::Ready(Some(ev)) = sub.poll()? {
+                events.push(ev);
+            }
+            Ok::<_, ()>(events)
+        }).wait().unwrap()
+    }
+
+    #[test]
+    fn subscriptions_receive_events_published_after_subscribing() {
+        let events = Events::default();
+        assert!(!events.is_subscribed());
+        events.publish(&close(1));
+
+        let subs = vec![events.subscribe(10), events.subscribe(10)];
+        events.publish(&Event::TransportOpen(transport()));
+
+        for mut sub in subs {
+            let evs = received(&mut sub);
+            assert_eq!(evs.len(), 1, "unexpected events: {:?}", evs);
+            match evs[0] {
+                LifecycleEvent::Connection(ConnectionEvent::Opened(_)) => {}
+                ref ev => panic!("unexpected event: {:?}", ev),
+            }
+        }
+    }
+
+    #[test]
+    fn lagging_subscription_drops_oldest_events() {
+        let events = Events::default();
+        let mut sub = events.subscribe(2);
+        for rx_bytes in 0..5 {
+            events.publish(&close(rx_bytes));
+        }
+
+        assert_eq!(sub.dropped(), 3);
+        let rx_bytes = received(&mut sub).into_iter()
+            .map(|ev| match ev {
+                LifecycleEvent::Connection(ConnectionEvent::Closed { rx_bytes, .. }) => rx_bytes,
+                ev => panic!("unexpected event: {:?}", ev),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rx_bytes, vec![3, 4]);
+    }
+
+    #[test]
+    fn dropped_subscriptions_are_forgotten() {
+        let events = Events::default();
+        drop(events.subscribe(1));
+        events.publish(&close(0));
+        assert!(!events.is_subscribed());
+    }
+}
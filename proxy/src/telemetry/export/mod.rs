@@ -0,0 +1,121 @@
+//! Pushes the proxy's metrics to external systems.
+//!
+//! The metrics that are served for Prometheus to scrape may also be exported
+//! periodically to any backend that implements `Exporter`. Each export
+//! describes every metric's current, cumulative value; it is up to an
+//! `Exporter` to convert these into the form its backend expects.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, Stream};
+use tokio_core::reactor::{Handle, Interval};
+
+use super::metrics;
+
+mod statsd;
+
+pub use self::statsd::Statsd;
+
+/// Receives snapshots of the proxy's metrics, to send to an external system.
+///
+/// Metrics are identified by their name and by their labels, which are
+/// formatted as in Prometheus' text format, e.g. `direction="inbound"`.
+pub trait Exporter {
+    /// Exports a counter's cumulative value.
+    fn counter(&mut self, name: &str, labels: &str, value: u64);
+
+    /// Exports a gauge's current value.
+    fn gauge(&mut self, name: &str, labels: &str, value: u64);
+
+    /// Exports a latency histogram's cumulative state.
+    fn histogram(&mut self, name: &str, labels: &str, histogram: &Histogram);
+
+    /// Sends the metrics exported since the last flush.
+    ///
+    /// Metrics that could not be sent are discarded, so that an unavailable
+    /// backend doesn't cause exports to accumulate.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// The cumulative state of a latency histogram.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Each bucket's inclusive upper bound in milliseconds, or `None` for the
+    /// unbounded last bucket, and the number of values observed in it.
+    ///
+    /// Unlike Prometheus buckets, a bucket's count excludes the counts of the
+    /// buckets below it.
+    pub buckets: Vec<(Option<f64>, u64)>,
+
+    /// The sum of all observed values, in milliseconds.
+    pub sum_ms: f64,
+}
+
+/// A future that exports metrics on an interval.
+///
+/// Failures to export are logged, and exports continue on the next interval.
+pub struct Export {
+    metrics: metrics::Serve,
+    exporter: Box<Exporter>,
+    interval: Interval,
+}
+
+// ===== impl Histogram =====
+
+impl Histogram {
+    /// Returns the number of values observed.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|&(_, count)| count).sum()
+    }
+}
+
+// ===== impl Export =====
+
+impl Export {
+    pub(super) fn new(
+        metrics: metrics::Serve,
+        exporter: Box<Exporter>,
+        interval: Duration,
+        handle: &Handle,
+    ) -> io::Result<Self> {
+        Ok(Export {
+            metrics,
+            exporter,
+            interval: Interval::new(interval, handle)?,
+        })
+    }
+}
+
+impl Future for Export {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            match self.interval.poll() {
+                Ok(Async::Ready(Some(()))) => {}
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    error!("metrics export interval failed: {}", e);
+                    return Err(());
+                }
+            }
+
+            self.metrics.export(&mut *self.exporter);
+            if let Err(e) = self.exporter.flush() {
+                warn!("failed to export metrics: {}", e);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Export {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Export")
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
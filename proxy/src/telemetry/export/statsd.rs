@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use super::{Exporter, Histogram};
+
+/// The default largest datagram sent, which fits in an Ethernet frame.
+const DEFAULT_MAX_PACKET_SIZE: usize = 1432;
+
+/// Exports metrics to a StatsD collector over UDP.
+///
+/// Labels are sent as DogStatsD tags. Since StatsD counters count the
+/// increments since the last flush, counters are sent as the difference
+/// from their last exported value, and aren't sent if they haven't changed.
+/// StatsD can't receive pre-aggregated histograms, so each histogram is sent
+/// as counters of its `_count` and `_sum`.
+///
+/// Metrics are batched into newline-delimited datagrams of at most
+/// `max_packet_size` bytes. The socket is non-blocking, so that a slow or
+/// dead collector can't stall the proxy; datagrams that can't be sent are
+/// dropped.
+#[derive(Debug)]
+pub struct Statsd {
+    socket: UdpSocket,
+    collector: SocketAddr,
+    prefix: String,
+    max_packet_size: usize,
+    /// The datagrams to be sent on the next flush.
+    packets: Vec<Vec<u8>>,
+    /// Each counter's value as of its last export, keyed by name and labels.
+    last: HashMap<(String, String), Value>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Value {
+    Count(u64),
+    Sum(f64),
+}
+
+// ===== impl Statsd =====
+
+impl Statsd {
+    /// Exports metrics to `collector`, with each metric's name prefixed by
+    /// `prefix` and a period, unless `prefix` is empty.
+    pub fn new(collector: SocketAddr, prefix: &str) -> io::Result<Self> {
+        let unspecified = if collector.is_ipv4() {
+            IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+        } else {
+            IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0))
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+        socket.set_nonblocking(true)?;
+        Ok(Statsd {
+            socket,
+            collector,
+            prefix: prefix.to_owned(),
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            packets: Vec::new(),
+            last: HashMap::new(),
+        })
+    }
+
+    /// Limits the size of each datagram to `max_packet_size` bytes.
+    ///
+    /// A metric that is larger than this on its own is sent in a datagram by
+    /// itself.
+    pub fn with_max_packet_size(self, max_packet_size: usize) -> Self {
+        Self {
+            max_packet_size,
+            ..self
+        }
+    }
+
+    /// Returns how much `value` has increased since it was last exported,
+    /// remembering it for the next export.
+    fn delta(&mut self, name: &str, labels: &str, value: Value) -> Option<Value> {
+        let last = self.last.insert((name.to_owned(), labels.to_owned()), value);
+        let delta = match (last, value) {
+            (None, value) => value,
+            // Counters may wrap on overflow.
+            (Some(Value::Count(last)), Value::Count(value)) => {
+                Value::Count(value.wrapping_sub(last))
+            }
+            (Some(Value::Sum(last)), Value::Sum(value)) => Value::Sum(value - last),
+            (Some(_), value) => value,
+        };
+        match delta {
+            Value::Count(0) => None,
+            Value::Sum(sum) if sum == 0.0 => None,
+            delta => Some(delta),
+        }
+    }
+
+    fn push(&mut self, name: &str, labels: &str, value: &str, kind: &str) {
+        let mut line = String::new();
+        if !self.prefix.is_empty() {
+            line.push_str(&self.prefix);
+            line.push('.');
+        }
+        line.push_str(name);
+        line.push(':');
+        line.push_str(value);
+        line.push('|');
+        line.push_str(kind);
+        push_tags(&mut line, labels);
+
+        let fits = self.packets.last()
+            .map(|packet| packet.len() + 1 + line.len() <= self.max_packet_size)
+            .unwrap_or(false);
+        match self.packets.last_mut() {
+            Some(ref mut packet) if fits => {
+                packet.push(b'\n');
+                packet.extend_from_slice(line.as_bytes());
+            }
+            _ => self.packets.push(line.into_bytes()),
+        }
+    }
+
+    fn push_delta(&mut self, name: &str, labels: &str, value: Value) {
+        match self.delta(name, labels, value) {
+            Some(Value::Count(delta)) => self.push(name, labels, &delta.to_string(), "c"),
+            Some(Value::Sum(delta)) => self.push(name, labels, &delta.to_string(), "c"),
+            None => {}
+        }
+    }
+}
+
+impl Exporter for Statsd {
+    fn counter(&mut self, name: &str, labels: &str, value: u64) {
+        self.push_delta(name, labels, Value::Count(value));
+    }
+
+    fn gauge(&mut self, name: &str, labels: &str, value: u64) {
+        self.push(name, labels, &value.to_string(), "g");
+    }
+
+    fn histogram(&mut self, name: &str, labels: &str, histogram: &Histogram) {
+        let count = format!("{}_count", name);
+        self.push_delta(&count, labels, Value::Count(histogram.count()));
+        let sum = format!("{}_sum", name);
+        self.push_delta(&sum, labels, Value::Sum(histogram.sum_ms));
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for packet in self.packets.drain(..) {
+            if let Err(e) = self.socket.send_to(&packet, &self.collector) {
+                // Keep trying the remaining packets, but report the first
+                // failure.
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Appends Prometheus-formatted `labels` to `line` as DogStatsD tags.
+///
+/// Characters that delimit StatsD lines and tags are replaced in label
+/// values.
+fn push_tags(line: &mut String, labels: &str) {
+    let mut first = true;
+    let mut rest = labels;
+    while let Some(eq) = rest.find("=\"") {
+        let name = rest[..eq].trim_left_matches(',');
+        let value_start = eq + 2;
+        let value_end = match rest[value_start..].find('"') {
+            Some(end) => value_start + end,
+            None => break,
+        };
+
+        line.push_str(if first { "|#" } else { "," });
+        first = false;
+        line.push_str(name);
+        line.push(':');
+        line.extend(rest[value_start..value_end].chars().map(|c| match c {
+            ',' | '|' | '#' | '\n' => '_',
+            c => c,
+        }));
+
+        rest = &rest[value_end + 1..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A fake StatsD collector.
+    struct Sink(UdpSocket);
+
+    impl Sink {
+        fn new() -> Self {
+            let socket = UdpSocket::bind("127.0.0.1:0").expect("bind sink");
+            socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            Sink(socket)
+        }
+
+        fn statsd(&self) -> Statsd {
+            Statsd::new(self.0.local_addr().unwrap(), "conduit").expect("statsd")
+        }
+
+        /// Receives a datagram, returning its lines.
+        fn recv(&self) -> Vec<String> {
+            let mut buf = [0; 2048];
+            let (n, _) = self.0.recv_from(&mut buf).expect("datagram");
+            String::from_utf8(buf[..n].to_vec())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+
+        /// Asserts that no datagram is received.
+        fn assert_empty(&self) {
+            self.0.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+            let mut buf = [0; 2048];
+            assert!(self.0.recv_from(&mut buf).is_err(), "unexpected datagram");
+            self.0.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        }
+    }
+
+    #[test]
+    fn exports_metrics_as_tagged_lines() {
+        let sink = Sink::new();
+        let mut statsd = sink.statsd();
+
+        let labels = "authority=\"a.test:8080\",direction=\"inbound\"";
+        statsd.counter("request_total", labels, 3);
+        statsd.gauge("endpoint_requests_in_flight", "dst_addr=\"10.1.1.1:8080\"", 2);
+        statsd.histogram("response_latency_ms", "", &Histogram {
+            buckets: vec![(Some(10.0), 2), (None, 1)],
+            sum_ms: 25.5,
+        });
+        statsd.flush().expect("flush");
+
+        assert_eq!(sink.recv(), vec![
+            "conduit.request_total:3|c|#authority:a.test:8080,direction:inbound",
+            "conduit.endpoint_requests_in_flight:2|g|#dst_addr:10.1.1.1:8080",
+            "conduit.response_latency_ms_count:3|c",
+            "conduit.response_latency_ms_sum:25.5|c",
+        ]);
+    }
+
+    #[test]
+    fn counters_are_sent_as_deltas() {
+        let sink = Sink::new();
+        let mut statsd = sink.statsd();
+
+        statsd.counter("request_total", "direction=\"inbound\"", 3);
+        statsd.counter("request_total", "direction=\"outbound\"", 1);
+        statsd.flush().expect("flush");
+        assert_eq!(sink.recv().len(), 2);
+
+        statsd.counter("request_total", "direction=\"inbound\"", 5);
+        statsd.counter("request_total", "direction=\"outbound\"", 1);
+        statsd.flush().expect("flush");
+        assert_eq!(sink.recv(), vec!["conduit.request_total:2|c|#direction:inbound"]);
+
+        // Nothing is sent if nothing has changed.
+        statsd.counter("request_total", "direction=\"inbound\"", 5);
+        statsd.flush().expect("flush");
+        sink.assert_empty();
+    }
+
+    #[test]
+    fn batches_lines_into_bounded_datagrams() {
+        let sink = Sink::new();
+        let mut statsd = sink.statsd().with_max_packet_size(64);
+
+        for i in 0..5 {
+            statsd.gauge(&format!("gauge_{}", i), "", i);
+        }
+        statsd.flush().expect("flush");
+
+        // Each line is 19 bytes, so three fit in each datagram.
+        assert_eq!(sink.recv(), vec![
+            "conduit.gauge_0:0|g",
+            "conduit.gauge_1:1|g",
+            "conduit.gauge_2:2|g",
+        ]);
+        assert_eq!(sink.recv(), vec!["conduit.gauge_3:3|g", "conduit.gauge_4:4|g"]);
+    }
+
+    #[test]
+    fn tags_replace_delimiters() {
+        let mut line = String::new();
+        push_tags(&mut line, "a=\"x,y|z\",b=\"#\"");
+        assert_eq!(line, "|#a:x_y_z,b:_");
+
+        let mut line = String::new();
+        push_tags(&mut line, "");
+        assert_eq!(line, "");
+    }
+}
@@ -2,12 +2,15 @@
 use futures::Poll;
 use futures_watch::Watch;
 use http;
+use indexmap::IndexSet;
 use tower::Service;
 
 use std::fmt::{self, Write};
 use std::sync::Arc;
 
 use ctx;
+use telemetry::classify::Classification;
+use telemetry::event;
 
 /// Middleware that adds an extension containing an optional set of metric
 /// labels to requests.
@@ -23,6 +26,9 @@ pub struct RequestLabels {
     /// Was the request in the inbound or outbound direction?
     direction: Direction,
 
+    /// Was the request received over HTTP/1 or HTTP/2?
+    protocol: Protocol,
+
     // Additional labels identifying the destination service of an outbound
     // request, provided by the Conduit control plane's service discovery.
     outbound_labels: Option<DstLabels>,
@@ -48,10 +54,36 @@ pub struct ResponseLabels {
     classification: Classification,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-enum Classification {
-    Success,
-    Failure,
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TransportLabels {
+
+    /// Was the transport opened in the inbound or outbound direction?
+    direction: Direction,
+
+    /// Was the transport accepted from a source, or connected to a
+    /// destination?
+    peer: Peer,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TransportCloseLabels {
+
+    transport: TransportLabels,
+
+    /// Was the transport closed cleanly?
+    classification: Classification,
+}
+
+/// Limits the number of distinct values of the `authority` label.
+///
+/// Authorities are taken from requests, so a client could otherwise cause
+/// an unbounded number of metrics to be created. Once `max` distinct
+/// authorities have been seen, any other authority is labeled as
+/// `"overflow"`.
+#[derive(Clone, Debug)]
+pub struct Authorities {
+    max: usize,
+    known: IndexSet<String>,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
@@ -60,6 +92,20 @@ enum Direction {
     Outbound,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Protocol {
+    Http1,
+    Http2,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Peer {
+    Src,
+    Dst,
+}
+
+const OVERFLOW_AUTHORITY: &str = "overflow";
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub struct DstLabels(Arc<str>);
 
@@ -109,9 +155,11 @@ where
 // ===== impl RequestLabels =====
 
 impl<'a> RequestLabels {
-    pub fn new(req: &ctx::http::Request) -> Self {
+    pub fn new(req: &ctx::http::Request, authorities: &mut Authorities) -> Self {
         let direction = Direction::from_context(req.server.proxy.as_ref());
 
+        let protocol = Protocol::from_version(req.version);
+
         let outbound_labels = req.dst_labels.as_ref().cloned();
 
         let authority = req.uri
@@ -121,15 +169,20 @@ impl<'a> RequestLabels {
 
         RequestLabels {
             direction,
+            protocol,
             outbound_labels,
-            authority,
+            authority: authorities.label(authority),
         }
     }
 }
 
 impl fmt::Display for RequestLabels {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "authority=\"{}\",{}", self.authority, self.direction)?;
+        write!(f, "authority=\"{}\",{},{}",
+            self.authority,
+            self.direction,
+            self.protocol,
+        )?;
 
         if let Some(ref outbound) = self.outbound_labels {
             // leading comma added between the direction label and the
@@ -145,9 +198,13 @@ impl fmt::Display for RequestLabels {
 
 impl ResponseLabels {
 
-    pub fn new(rsp: &ctx::http::Response, grpc_status_code: Option<u32>) -> Self {
-        let request_labels = RequestLabels::new(&rsp.request);
-        let classification = Classification::classify(rsp, grpc_status_code);
+    pub fn new(
+        rsp: &ctx::http::Response,
+        grpc_status_code: Option<u32>,
+        classification: Classification,
+        authorities: &mut Authorities,
+    ) -> Self {
+        let request_labels = RequestLabels::new(&rsp.request, authorities);
         ResponseLabels {
             request_labels,
             status_code: rsp.status.as_u16(),
@@ -157,8 +214,8 @@ impl ResponseLabels {
     }
 
     /// Called when the response stream has failed.
-    pub fn fail(rsp: &ctx::http::Response) -> Self {
-        let request_labels = RequestLabels::new(&rsp.request);
+    pub fn fail(rsp: &ctx::http::Response, authorities: &mut Authorities) -> Self {
+        let request_labels = RequestLabels::new(&rsp.request, authorities);
         ResponseLabels {
             request_labels,
             // TODO: is it correct to always treat this as 500?
@@ -188,40 +245,81 @@ impl fmt::Display for ResponseLabels {
     }
 }
 
-// ===== impl Classification =====
+// ===== impl TransportLabels =====
+
+impl TransportLabels {
+    pub fn new(ctx: &ctx::transport::Ctx) -> Self {
+        let peer = match *ctx {
+            ctx::transport::Ctx::Server(_) => Peer::Src,
+            ctx::transport::Ctx::Client(_) => Peer::Dst,
+        };
+        TransportLabels {
+            direction: Direction::from_context(ctx.proxy().as_ref()),
+            peer,
+        }
+    }
+}
 
-impl Classification {
+impl fmt::Display for TransportLabels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.direction, self.peer)
+    }
+}
+
+// ===== impl TransportCloseLabels =====
 
-    fn grpc_status(code: u32) -> Self {
-        if code == 0 {
-            // XXX: are gRPC status codes indicating client side errors
-            //      "successes" or "failures?
+impl TransportCloseLabels {
+    pub fn new(ctx: &ctx::transport::Ctx, close: &event::TransportClose) -> Self {
+        let classification = if close.clean {
             Classification::Success
         } else {
             Classification::Failure
+        };
+        TransportCloseLabels {
+            transport: TransportLabels::new(ctx),
+            classification,
         }
     }
+}
 
-    fn http_status(status: &http::StatusCode) -> Self {
-        if status.is_server_error() {
-            Classification::Failure
-        } else {
-            Classification::Success
-        }
+impl fmt::Display for TransportCloseLabels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.transport, self.classification)
     }
+}
 
-    fn classify(rsp: &ctx::http::Response, grpc_status: Option<u32>) -> Self {
-        grpc_status.map(Classification::grpc_status)
-            .unwrap_or_else(|| Classification::http_status(&rsp.status))
+// ===== impl Authorities =====
+
+impl Authorities {
+    pub fn new(max: usize) -> Self {
+        Authorities {
+            max,
+            known: IndexSet::new(),
+        }
     }
 
+    fn label(&mut self, authority: String) -> String {
+        if self.known.contains(&authority) {
+            return authority;
+        }
+
+        if self.known.len() < self.max {
+            self.known.insert(authority.clone());
+            return authority;
+        }
+
+        OVERFLOW_AUTHORITY.to_owned()
+    }
 }
 
+// ===== impl Classification =====
+
 impl fmt::Display for Classification {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             &Classification::Success => f.pad("classification=\"success\""),
             &Classification::Failure => f.pad("classification=\"failure\""),
+            &Classification::Injected => f.pad("classification=\"injected\""),
         }
     }
 }
@@ -247,6 +345,38 @@ impl fmt::Display for Direction {
 }
 
 
+// ===== impl Protocol =====
+
+impl Protocol {
+    fn from_version(version: http::Version) -> Self {
+        if version == http::Version::HTTP_2 {
+            Protocol::Http2
+        } else {
+            Protocol::Http1
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Protocol::Http1 => f.pad("protocol=\"http1\""),
+            &Protocol::Http2 => f.pad("protocol=\"http2\""),
+        }
+    }
+}
+
+// ===== impl Peer =====
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Peer::Src => f.pad("peer=\"src\""),
+            &Peer::Dst => f.pad("peer=\"dst\""),
+        }
+    }
+}
+
 // ===== impl DstLabels ====
 
 impl DstLabels {
@@ -1,13 +1,14 @@
 #![deny(missing_docs)]
 use std::{fmt, iter, ops, slice, u32};
 use std::num::Wrapping;
+use std::sync::Arc;
 use std::time::Duration;
 use super::Counter;
 
-/// The number of buckets in a  latency histogram.
+/// The number of buckets in a default latency histogram.
 pub const NUM_BUCKETS: usize = 26;
 
-/// The maximum value (inclusive) for each latency bucket in
+/// The default maximum value (inclusive) for each latency bucket in
 /// tenths of a millisecond.
 pub const BUCKET_BOUNDS: [Latency; NUM_BUCKETS] = [
     // The controller telemetry server creates 5 sets of 5 linear buckets
@@ -56,14 +57,23 @@ pub const BUCKET_BOUNDS: [Latency; NUM_BUCKETS] = [
     Latency(u32::MAX),
 ];
 
+/// The maximum value (inclusive) for each bucket of a latency histogram.
+///
+/// The last bucket is always unbounded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Bounds(Arc<[Latency]>);
+
 /// A series of latency values and counts.
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct Histogram {
 
-    /// Array of buckets in which to count latencies.
+    /// The upper bounds of the histogram's buckets.
+    bounds: Bounds,
+
+    /// Vector of buckets in which to count latencies.
     ///
-    /// The upper bound of a given bucket `i` is given in `BUCKET_BOUNDS[i]`.
-    buckets: [Counter; NUM_BUCKETS],
+    /// The upper bound of a given bucket `i` is given in `bounds[i]`.
+    buckets: Vec<Counter>,
 
     /// The total sum of all observed latency values.
     ///
@@ -93,17 +103,51 @@ pub struct Histogram {
 pub struct Latency(u32);
 
 
+// ===== impl Bounds =====
+
+impl Bounds {
+
+    /// Creates bucket bounds from a list of upper bounds in milliseconds.
+    ///
+    /// The bounds are sorted, and an unbounded bucket is added after the
+    /// largest of them.
+    pub fn from_ms(bounds_ms: &[u32]) -> Self {
+        let mut bounds = bounds_ms.iter()
+            .map(|&ms| Latency(ms.saturating_mul(MS_TO_TENTHS_OF_MS)))
+            .collect::<Vec<_>>();
+        bounds.push(Latency(u32::MAX));
+        bounds.sort();
+        bounds.dedup();
+        Bounds(Arc::from(bounds))
+    }
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds(Arc::from(&BUCKET_BOUNDS[..]))
+    }
+}
+
 // ===== impl Histogram =====
 
 impl Histogram {
 
+    /// Creates an empty histogram with the given buckets.
+    pub fn new(bounds: &Bounds) -> Self {
+        Histogram {
+            bounds: bounds.clone(),
+            buckets: vec![Counter::default(); bounds.0.len()],
+            sum: Wrapping(0),
+        }
+    }
+
     /// Observe a measurement
     pub fn observe<I>(&mut self, measurement: I)
     where
         I: Into<Latency>,
     {
         let measurement = measurement.into();
-        let i = BUCKET_BOUNDS.iter()
+        let i = self.bounds.0.iter()
             .position(|max| &measurement <= max)
             .expect("latency value greater than u32::MAX; this shouldn't be \
                      possible.");
@@ -134,14 +178,19 @@ where
 }
 
 impl<'a> IntoIterator for &'a Histogram {
-    type Item = u64;
-    type IntoIter = iter::Map<
-        slice::Iter<'a, Counter>,
-        fn(&'a Counter) -> u64
+    /// Each bucket's upper bound and count.
+    type Item = (&'a Latency, u64);
+    type IntoIter = iter::Zip<
+        slice::Iter<'a, Latency>,
+        iter::Map<
+            slice::Iter<'a, Counter>,
+            fn(&'a Counter) -> u64
+        >,
     >;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.buckets.iter().map(|&count| count.into())
+        let counts: fn(&'a Counter) -> u64 = |&count| count.into();
+        self.bounds.0.iter().zip(self.buckets.iter().map(counts))
     }
 
 }
@@ -200,6 +249,18 @@ impl From<u32> for Latency {
     }
 }
 
+impl Latency {
+    /// Returns this latency in milliseconds, or `None` if it is the unbounded
+    /// upper bound of the last bucket.
+    pub fn as_ms(&self) -> Option<f64> {
+        if self.0 == u32::MAX {
+            None
+        } else {
+            Some(f64::from(self.0) / f64::from(MS_TO_TENTHS_OF_MS))
+        }
+    }
+}
+
 impl Into<u32> for Latency {
     fn into(self) -> u32 {
         self.0
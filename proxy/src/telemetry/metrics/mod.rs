@@ -26,13 +26,16 @@
 //! labels, we can add new labels or modify the existing ones without having
 //! to worry about missing commas, double commas, or trailing commas at the
 //! end of the label set (all of which will make Prometheus angry).
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
 use std::{fmt, ops, time};
 use std::hash::Hash;
+use std::net::SocketAddr;
 use std::num::Wrapping;
 use std::sync::{Arc, Mutex};
 
 use futures::future::{self, FutureResult};
+use h2;
 use hyper;
 use hyper::header::{ContentLength, ContentType};
 use hyper::StatusCode;
@@ -45,23 +48,59 @@ use indexmap::{IndexMap};
 
 use ctx;
 use telemetry::event::Event;
+use telemetry::export::{self, Exporter};
+use telemetry::sensor::{
+    Authority,
+    AuthorityLookups,
+    ByteCounts,
+    Bytes,
+    Direction,
+    DnsLookups,
+    InFlight,
+    Peer,
+    RequestAttempts,
+    Termination,
+    Terminations,
+};
 
 mod labels;
-mod latency;
-
-use self::labels::{RequestLabels, ResponseLabels};
-use self::latency::{BUCKET_BOUNDS, Histogram};
+pub(super) mod latency;
+
+use self::labels::{
+    Authorities,
+    RequestLabels,
+    ResponseLabels,
+    TransportLabels,
+    TransportCloseLabels,
+};
+use self::latency::{Bounds, Histogram};
 pub use self::labels::{DstLabels, Labeled};
 
+/// Configures how metrics are aggregated.
+#[derive(Clone, Debug)]
+pub struct Config {
+    latency_bounds: Bounds,
+    max_authorities: usize,
+}
+
 #[derive(Debug, Clone)]
 struct Metrics {
     request_total: Metric<Counter, Arc<RequestLabels>>,
     request_duration: Metric<Histogram, Arc<RequestLabels>>,
+    request_cancel_total: Metric<Counter, Arc<RequestLabels>>,
 
     response_total: Metric<Counter, Arc<ResponseLabels>>,
     response_duration: Metric<Histogram, Arc<ResponseLabels>>,
     response_latency: Metric<Histogram, Arc<ResponseLabels>>,
 
+    response_stream_messages_total: Metric<Counter, Arc<ResponseLabels>>,
+    response_stream_first_message_latency: Metric<Histogram, Arc<ResponseLabels>>,
+
+    tcp_open_total: Metric<Counter, Arc<TransportLabels>>,
+    tcp_close_total: Metric<Counter, Arc<TransportCloseLabels>>,
+
+    latency_bounds: Bounds,
+
     start_time: u64,
 }
 
@@ -98,30 +137,112 @@ pub struct Counter(Wrapping<u64>);
 #[derive(Debug)]
 pub struct Aggregate {
     metrics: Arc<Mutex<Metrics>>,
+    authorities: Authorities,
 }
 
 /// Serve Prometheues metrics.
 #[derive(Debug, Clone)]
 pub struct Serve {
     metrics: Arc<Mutex<Metrics>>,
+    in_flight: InFlight,
+    byte_counts: ByteCounts,
+    terminations: Terminations,
+    dns_lookups: DnsLookups,
+    request_attempts: RequestAttempts,
 }
 
+/// A gauge of the requests in flight to each endpoint.
+///
+/// Unlike the other metrics, this is not aggregated from events; it is read
+/// from the `InFlight` sensor each time metrics are served.
+struct InFlightGauge(HashMap<SocketAddr, usize>);
+
+/// Counters of the bytes transferred with each peer and for each authority.
+///
+/// Like `InFlightGauge`, these are read from the `ByteCounts` sensor each
+/// time metrics are served. A peer's counters are no longer reported once it
+/// has no open connections.
+struct ByteCounters {
+    peers: HashMap<Peer, Bytes>,
+    authorities: HashMap<Authority, Bytes>,
+}
+
+/// Counters of the ways in which servers have terminated client connections
+/// and streams, read from the `Terminations` sensor.
+struct TerminationCounters(HashMap<(Direction, Termination), u64>);
+
+/// The latency and failures of each authority's DNS lookups, and a gauge of
+/// the lookups cached, read from the `DnsLookups` sensor.
+struct DnsMetrics {
+    authorities: HashMap<String, AuthorityLookups>,
+    cached: usize,
+}
+
+/// A counter of the requests that were attempted each number of times, read
+/// from the `RequestAttempts` sensor.
+struct AttemptCounters(BTreeMap<usize, u64>);
+
+/// Labels a DNS lookup's metrics with the authority looked up.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct DnsLabels(String);
+
 /// Construct the Prometheus metrics.
 ///
 /// Returns the `Aggregate` and `Serve` sides. The `Serve` side
 /// is a Hyper service which can be used to create the server for the
 /// scrape endpoint, while the `Aggregate` side can receive updates to the
-/// metrics by calling `record_event`.
-pub fn new(process: &Arc<ctx::Process>) -> (Aggregate, Serve) {
-    let metrics = Arc::new(Mutex::new(Metrics::new(process)));
-    (Aggregate::new(&metrics), Serve::new(&metrics))
+/// metrics by calling `record_event`. The `Serve` side also reports the
+/// requests counted by `in_flight`, the bytes counted by `byte_counts`, the
+/// connection terminations counted by `terminations`, the DNS lookups
+/// recorded by `dns_lookups`, and the attempts counted by `request_attempts`.
+pub fn new(
+    process: &Arc<ctx::Process>,
+    config: &Config,
+    in_flight: &InFlight,
+    byte_counts: &ByteCounts,
+    terminations: &Terminations,
+    dns_lookups: &DnsLookups,
+    request_attempts: &RequestAttempts,
+) -> (Aggregate, Serve) {
+    let metrics = Arc::new(Mutex::new(Metrics::new(process, &config.latency_bounds)));
+    let authorities = Authorities::new(config.max_authorities);
+    let serve = Serve::new(
+        &metrics,
+        in_flight,
+        byte_counts,
+        terminations,
+        dns_lookups,
+        request_attempts,
+    );
+    (Aggregate::new(&metrics, authorities), serve)
+}
+
+// ===== impl Config =====
+
+impl Config {
+    /// Limits the number of distinct `authority` labels to `max_authorities`.
+    pub fn new(max_authorities: usize) -> Self {
+        Config {
+            latency_bounds: Bounds::default(),
+            max_authorities,
+        }
+    }
+
+    /// Uses histogram buckets with the given upper bounds, in milliseconds,
+    /// for latency metrics.
+    pub fn with_latency_buckets_ms(self, bounds_ms: &[u32]) -> Self {
+        Config {
+            latency_bounds: Bounds::from_ms(bounds_ms),
+            ..self
+        }
+    }
 }
 
 // ===== impl Metrics =====
 
 impl Metrics {
 
-    pub fn new(process: &Arc<ctx::Process>) -> Self {
+    pub fn new(process: &Arc<ctx::Process>, latency_bounds: &Bounds) -> Self {
 
         let start_time = process.start_time
             .duration_since(time::UNIX_EPOCH)
@@ -143,6 +264,13 @@ impl Metrics {
              stream has completed.",
         );
 
+        let request_cancel_total = Metric::<Counter, Arc<RequestLabels>>::new(
+            "request_cancel_total",
+            "A counter of the number of requests that were canceled before \
+             their responses completed, as when the client disconnects. \
+             Canceled responses are not counted by `response_total`.",
+        );
+
         let response_total = Metric::<Counter, Arc<ResponseLabels>>::new(
             "response_total",
             "A counter of the number of responses the proxy has received.",
@@ -162,12 +290,43 @@ impl Metrics {
             stream has completed.",
         );
 
+        let response_stream_messages_total = Metric::<Counter, Arc<ResponseLabels>>::new(
+            "response_stream_messages_total",
+            "A counter of the number of messages received in streaming gRPC \
+             responses. Streaming responses are not included in \
+             `response_duration_ms` or `response_latency_ms`.",
+        );
+
+        let response_stream_first_message_latency = Metric::<Histogram, Arc<ResponseLabels>>::new(
+            "response_stream_first_message_latency_ms",
+            "A histogram of the latency of the first message of a streaming \
+             gRPC response. This is measured from when the request headers \
+             are received to when the first message has been received.",
+        );
+
+        let tcp_open_total = Metric::<Counter, Arc<TransportLabels>>::new(
+            "tcp_open_total",
+            "A counter of the number of TCP connections opened.",
+        );
+
+        let tcp_close_total = Metric::<Counter, Arc<TransportCloseLabels>>::new(
+            "tcp_close_total",
+            "A counter of the number of TCP connections closed, classified \
+             by whether they were closed cleanly.",
+        );
+
         Metrics {
             request_total,
             request_duration,
+            request_cancel_total,
             response_total,
             response_duration,
             response_latency,
+            response_stream_messages_total,
+            response_stream_first_message_latency,
+            tcp_open_total,
+            tcp_close_total,
+            latency_bounds: latency_bounds.clone(),
             start_time,
         }
     }
@@ -183,7 +342,16 @@ impl Metrics {
     fn request_duration(&mut self,
                         labels: &Arc<RequestLabels>)
                         -> &mut Histogram {
+        let bounds = &self.latency_bounds;
         self.request_duration.values
+            .entry(labels.clone())
+            .or_insert_with(|| Histogram::new(bounds))
+    }
+
+    fn request_cancel_total(&mut self,
+                            labels: &Arc<RequestLabels>)
+                            -> &mut Counter {
+        self.request_cancel_total.values
             .entry(labels.clone())
             .or_insert_with(Default::default)
     }
@@ -191,19 +359,38 @@ impl Metrics {
     fn response_duration(&mut self,
                          labels: &Arc<ResponseLabels>)
                          -> &mut Histogram {
+        let bounds = &self.latency_bounds;
         self.response_duration.values
             .entry(labels.clone())
-            .or_insert_with(Default::default)
+            .or_insert_with(|| Histogram::new(bounds))
     }
 
     fn response_latency(&mut self,
                         labels: &Arc<ResponseLabels>)
                         -> &mut Histogram {
+        let bounds = &self.latency_bounds;
         self.response_latency.values
+            .entry(labels.clone())
+            .or_insert_with(|| Histogram::new(bounds))
+    }
+
+    fn response_stream_messages_total(&mut self,
+                                      labels: &Arc<ResponseLabels>)
+                                      -> &mut Counter {
+        self.response_stream_messages_total.values
             .entry(labels.clone())
             .or_insert_with(Default::default)
     }
 
+    fn response_stream_first_message_latency(&mut self,
+                                             labels: &Arc<ResponseLabels>)
+                                             -> &mut Histogram {
+        let bounds = &self.latency_bounds;
+        self.response_stream_first_message_latency.values
+            .entry(labels.clone())
+            .or_insert_with(|| Histogram::new(bounds))
+    }
+
     fn response_total(&mut self,
                       labels: &Arc<ResponseLabels>)
                       -> &mut Counter {
@@ -211,17 +398,38 @@ impl Metrics {
             .entry(labels.clone())
             .or_insert_with(Default::default)
     }
+
+    fn tcp_open_total(&mut self,
+                      labels: &Arc<TransportLabels>)
+                      -> &mut Counter {
+        self.tcp_open_total.values
+            .entry(labels.clone())
+            .or_insert_with(Default::default)
+    }
+
+    fn tcp_close_total(&mut self,
+                       labels: &Arc<TransportCloseLabels>)
+                       -> &mut Counter {
+        self.tcp_close_total.values
+            .entry(labels.clone())
+            .or_insert_with(Default::default)
+    }
 }
 
 
 impl fmt::Display for Metrics {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}\n{}\n{}\n{}\n{}\nprocess_start_time_seconds {}\n",
+        write!(f, "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\nprocess_start_time_seconds {}\n",
             self.request_total,
             self.request_duration,
+            self.request_cancel_total,
             self.response_total,
             self.response_duration,
             self.response_latency,
+            self.response_stream_messages_total,
+            self.response_stream_first_message_latency,
+            self.tcp_open_total,
+            self.tcp_close_total,
             self.start_time,
         )
     }
@@ -258,6 +466,14 @@ impl Counter {
         (*self).0 += Wrapping(1);
         self
     }
+
+    /// Increment the counter by `n`.
+    ///
+    /// This function wraps on overflows.
+    pub fn incr_by(&mut self, n: u64) -> &mut Self {
+        (*self).0 += Wrapping(n);
+        self
+    }
 }
 
 // ===== impl Metric =====
@@ -274,6 +490,37 @@ impl<M, L: Hash + Eq> Metric<M, L> {
 
 }
 
+impl<L> Metric<Counter, L>
+where
+    L: fmt::Display,
+    L: Hash + Eq,
+{
+    fn export(&self, exporter: &mut Exporter) {
+        for (labels, &value) in &self.values {
+            exporter.counter(self.name, &labels.to_string(), value.into());
+        }
+    }
+}
+
+impl<L> Metric<Histogram, L>
+where
+    L: fmt::Display,
+    L: Hash + Eq,
+{
+    fn export(&self, exporter: &mut Exporter) {
+        for (labels, histogram) in &self.values {
+            let buckets = histogram.into_iter()
+                .map(|(le, count)| (le.as_ms(), count))
+                .collect();
+            let histogram = export::Histogram {
+                buckets,
+                sum_ms: histogram.sum_in_ms(),
+            };
+            exporter.histogram(self.name, &labels.to_string(), &histogram);
+        }
+    }
+}
+
 impl<L> fmt::Display for Metric<Counter, L>
 where
     L: fmt::Display,
@@ -310,17 +557,11 @@ impl<L> fmt::Display for Metric<Histogram, L> where
         )?;
 
         for (labels, histogram) in &self.values {
-            // Look up the bucket numbers against the BUCKET_BOUNDS array
-            // to turn them into upper bounds.
-            let bounds_and_counts = histogram.into_iter()
-                .enumerate()
-                .map(|(num, count)| (BUCKET_BOUNDS[num], count));
-
             // Since Prometheus expects each bucket's value to be the sum of
             // the number of values in this bucket and all lower buckets,
             // track the total count here.
             let mut total_count = 0;
-            for (le, count) in bounds_and_counts {
+            for (le, count) in histogram {
                 // Add this bucket's count to the total count.
                 total_count += count;
                 write!(f, "{name}_bucket{{{labels},le=\"{le}\"}} {count}\n",
@@ -351,9 +592,10 @@ impl<L> fmt::Display for Metric<Histogram, L> where
 
 impl Aggregate {
 
-    fn new(metrics: &Arc<Mutex<Metrics>>) -> Self {
+    fn new(metrics: &Arc<Mutex<Metrics>>, authorities: Authorities) -> Self {
         Aggregate {
             metrics: metrics.clone(),
+            authorities,
         }
     }
 
@@ -375,16 +617,20 @@ impl Aggregate {
             },
 
             Event::StreamRequestFail(ref req, ref fail) => {
-                let labels = Arc::new(RequestLabels::new(req));
+                let labels = Arc::new(RequestLabels::new(req, &mut self.authorities));
+                let canceled = fail.error == h2::Reason::CANCEL;
                 self.update(|metrics| {
                     *metrics.request_duration(&labels) +=
                         fail.since_request_open;
                     *metrics.request_total(&labels).incr();
+                    if canceled {
+                        *metrics.request_cancel_total(&labels).incr();
+                    }
                 })
             },
 
             Event::StreamRequestEnd(ref req, ref end) => {
-                let labels = Arc::new(RequestLabels::new(req));
+                let labels = Arc::new(RequestLabels::new(req, &mut self.authorities));
                 self.update(|metrics| {
                     *metrics.request_total(&labels).incr();
                     *metrics.request_duration(&labels) +=
@@ -396,17 +642,40 @@ impl Aggregate {
                 let labels = Arc::new(ResponseLabels::new(
                     res,
                     end.grpc_status,
+                    end.classification,
+                    &mut self.authorities,
                 ));
                 self.update(|metrics| {
                     *metrics.response_total(&labels).incr();
-                    *metrics.response_duration(&labels) +=  end.since_response_open;
-                    *metrics.response_latency(&labels) += end.since_request_open;
+                    match end.grpc_stream {
+                        // A stream's duration describes how long it was
+                        // open, rather than how quickly it was served.
+                        Some(ref stream) => {
+                            metrics.response_stream_messages_total(&labels)
+                                .incr_by(u64::from(stream.messages));
+                            *metrics.response_stream_first_message_latency(&labels) +=
+                                stream.first_message_since_request_open;
+                        },
+                        None => {
+                            *metrics.response_duration(&labels) += end.since_response_open;
+                            *metrics.response_latency(&labels) += end.since_request_open;
+                        },
+                    }
                 });
             },
 
             Event::StreamResponseFail(ref res, ref fail) => {
-                // TODO: do we care about the failure's error code here?
-                let labels = Arc::new(ResponseLabels::fail(res));
+                // A canceled response is neither a success nor a failure.
+                if fail.error == h2::Reason::CANCEL {
+                    let labels = Arc::new(RequestLabels::new(&res.request, &mut self.authorities));
+                    self.update(|metrics| {
+                        *metrics.request_cancel_total(&labels).incr();
+                    });
+                    return;
+                }
+
+                // TODO: do we care about the failure's other error codes here?
+                let labels = Arc::new(ResponseLabels::fail(res, &mut self.authorities));
                 self.update(|metrics| {
                     *metrics.response_total(&labels).incr();
                     *metrics.response_duration(&labels) += fail.since_response_open;
@@ -414,19 +683,312 @@ impl Aggregate {
                 });
             },
 
-            Event::TransportOpen(_) | Event::TransportClose(_, _) => {
-                // TODO: we don't collect any metrics around transport events.
+            Event::TransportOpen(ref ctx) => {
+                let labels = Arc::new(TransportLabels::new(ctx));
+                self.update(|metrics| {
+                    *metrics.tcp_open_total(&labels).incr();
+                })
+            },
+
+            Event::TransportClose(ref ctx, ref close) => {
+                let labels = Arc::new(TransportCloseLabels::new(ctx, close));
+                self.update(|metrics| {
+                    *metrics.tcp_close_total(&labels).incr();
+                })
             },
         };
     }
 }
 
 
+// ===== impl InFlightGauge =====
+
+impl fmt::Display for InFlightGauge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+            "# HELP {name} {help}\n# TYPE {name} gauge\n",
+            name = "endpoint_requests_in_flight",
+            help = "A gauge of the number of requests in flight to each endpoint.",
+        )?;
+
+        // Sorted, so that endpoints are listed in a stable order.
+        let mut endpoints = self.0.iter().collect::<Vec<_>>();
+        endpoints.sort();
+        for (addr, count) in endpoints {
+            write!(f, "endpoint_requests_in_flight{{dst_addr=\"{}\"}} {}\n", addr, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+// ===== impl ByteCounters =====
+
+impl ByteCounters {
+    fn fmt_header(f: &mut fmt::Formatter, name: &str, help: &str) -> fmt::Result {
+        write!(f, "# HELP {name} {help}\n# TYPE {name} counter\n", name = name, help = help)
+    }
+}
+
+impl fmt::Display for ByteCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Sorted, so that peers and authorities are listed in a stable order.
+        let mut peers = self.peers.iter().collect::<Vec<_>>();
+        peers.sort_by_key(|&(peer, _)| peer);
+        let mut authorities = self.authorities.iter().collect::<Vec<_>>();
+        authorities.sort_by_key(|&(authority, _)| authority);
+
+        Self::fmt_header(f,
+            "peer_received_bytes_total",
+            "A counter of the bytes received from each peer with open connections.",
+        )?;
+        for &(peer, bytes) in &peers {
+            write!(f, "peer_received_bytes_total{{direction=\"{}\",peer_addr=\"{}\"}} {}\n",
+                direction(peer.direction), peer.addr, bytes.rx)?;
+        }
+        Self::fmt_header(f,
+            "peer_sent_bytes_total",
+            "A counter of the bytes sent to each peer with open connections.",
+        )?;
+        for &(peer, bytes) in &peers {
+            write!(f, "peer_sent_bytes_total{{direction=\"{}\",peer_addr=\"{}\"}} {}\n",
+                direction(peer.direction), peer.addr, bytes.tx)?;
+        }
+
+        Self::fmt_header(f,
+            "authority_received_body_bytes_total",
+            "A counter of the response body bytes received for each authority.",
+        )?;
+        for &(authority, bytes) in &authorities {
+            write!(f,
+                "authority_received_body_bytes_total{{authority=\"{}\",direction=\"{}\"}} {}\n",
+                authority.authority, direction(authority.direction), bytes.rx)?;
+        }
+        Self::fmt_header(f,
+            "authority_sent_body_bytes_total",
+            "A counter of the request body bytes sent for each authority.",
+        )?;
+        for &(authority, bytes) in &authorities {
+            write!(f,
+                "authority_sent_body_bytes_total{{authority=\"{}\",direction=\"{}\"}} {}\n",
+                authority.authority, direction(authority.direction), bytes.tx)?;
+        }
+
+        Ok(())
+    }
+}
+
+// ===== impl TerminationCounters =====
+
+impl fmt::Display for TerminationCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+            "# HELP {name} {help}\n# TYPE {name} counter\n",
+            name = "upstream_terminations_total",
+            help = "A counter of the ways in which servers terminated client connections.",
+        )?;
+
+        let mut counts = self.0.iter().collect::<Vec<_>>();
+        counts.sort();
+        for (&(dir, termination), count) in counts {
+            write!(f, "upstream_terminations_total{{cause=\"{}\",direction=\"{}\"}} {}\n",
+                termination.as_str(), direction(dir), count)?;
+        }
+
+        Ok(())
+    }
+}
+
+// ===== impl DnsMetrics =====
+
+impl DnsMetrics {
+    /// Returns the latency histogram of each authority's lookups.
+    fn latency(&self) -> Metric<Histogram, DnsLabels> {
+        let mut latency = Metric::<Histogram, DnsLabels>::new(
+            "dns_lookup_duration_ms",
+            "A histogram of the duration of DNS lookups, whether they \
+             succeeded or failed.",
+        );
+        for (authority, lookups) in self.sorted() {
+            latency.values.insert(DnsLabels(authority.clone()), lookups.latency.clone());
+        }
+        latency
+    }
+
+    /// Sorted, so that authorities are listed in a stable order.
+    fn sorted(&self) -> Vec<(&String, &AuthorityLookups)> {
+        let mut authorities = self.authorities.iter().collect::<Vec<_>>();
+        authorities.sort_by_key(|&(authority, _)| authority);
+        authorities
+    }
+}
+
+impl fmt::Display for DnsMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.latency())?;
+
+        write!(f,
+            "# HELP {name} {help}\n# TYPE {name} counter\n",
+            name = "dns_lookup_failures_total",
+            help = "A counter of the DNS lookups that could not be answered.",
+        )?;
+        for (authority, lookups) in self.sorted() {
+            write!(f, "dns_lookup_failures_total{{{}}} {}\n",
+                DnsLabels(authority.clone()), lookups.failures)?;
+        }
+
+        write!(f,
+            "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n",
+            name = "dns_cache_entries",
+            help = "A gauge of the number of DNS lookups cached.",
+            value = self.cached,
+        )
+    }
+}
+
+impl fmt::Display for DnsLabels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "authority=\"{}\"", self.0)
+    }
+}
+
+// ===== impl AttemptCounters =====
+
+impl fmt::Display for AttemptCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+            "# HELP {name} {help}\n# TYPE {name} counter\n",
+            name = "request_attempts_total",
+            help = "A counter of the requests that were attempted each number \
+                    of times, including retries.",
+        )?;
+        for (attempts, count) in &self.0 {
+            write!(f, "request_attempts_total{{attempts=\"{}\"}} {}\n", attempts, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn direction(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Inbound => "inbound",
+        Direction::Outbound => "outbound",
+    }
+}
+
 // ===== impl Serve =====
 
 impl Serve {
-    fn new(metrics: &Arc<Mutex<Metrics>>) -> Self {
-        Serve { metrics: metrics.clone() }
+    fn new(
+        metrics: &Arc<Mutex<Metrics>>,
+        in_flight: &InFlight,
+        byte_counts: &ByteCounts,
+        terminations: &Terminations,
+        dns_lookups: &DnsLookups,
+        request_attempts: &RequestAttempts,
+    ) -> Self {
+        Serve {
+            metrics: metrics.clone(),
+            in_flight: in_flight.clone(),
+            byte_counts: byte_counts.clone(),
+            terminations: terminations.clone(),
+            dns_lookups: dns_lookups.clone(),
+            request_attempts: request_attempts.clone(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let metrics = self.metrics.lock()
+            .expect("metrics lock poisoned");
+        let byte_counters = ByteCounters {
+            peers: self.byte_counts.peers(),
+            authorities: self.byte_counts.authorities(),
+        };
+        let terminations = TerminationCounters(self.terminations.snapshot());
+        format!(
+            "{}{}{}{}{}{}",
+            *metrics,
+            InFlightGauge(self.in_flight.snapshot()),
+            byte_counters,
+            terminations,
+            self.dns_metrics(),
+            AttemptCounters(self.request_attempts.histogram()),
+        )
+    }
+
+    fn dns_metrics(&self) -> DnsMetrics {
+        DnsMetrics {
+            authorities: self.dns_lookups.authorities(),
+            cached: self.dns_lookups.cached(),
+        }
+    }
+}
+
+impl Serve {
+    /// Exports the current value of each metric to `exporter`.
+    pub fn export(&self, exporter: &mut Exporter) {
+        {
+            let metrics = self.metrics.lock()
+                .expect("metrics lock poisoned");
+            metrics.request_total.export(exporter);
+            metrics.request_duration.export(exporter);
+            metrics.request_cancel_total.export(exporter);
+            metrics.response_total.export(exporter);
+            metrics.response_duration.export(exporter);
+            metrics.response_latency.export(exporter);
+            metrics.response_stream_messages_total.export(exporter);
+            metrics.response_stream_first_message_latency.export(exporter);
+            metrics.tcp_open_total.export(exporter);
+            metrics.tcp_close_total.export(exporter);
+            exporter.gauge("process_start_time_seconds", "", metrics.start_time);
+        }
+
+        for (addr, count) in self.in_flight.snapshot() {
+            let labels = format!("dst_addr=\"{}\"", addr);
+            exporter.gauge("endpoint_requests_in_flight", &labels, count as u64);
+        }
+
+        for (peer, bytes) in self.byte_counts.peers() {
+            let labels = format!(
+                "direction=\"{}\",peer_addr=\"{}\"",
+                direction(peer.direction),
+                peer.addr,
+            );
+            exporter.counter("peer_received_bytes_total", &labels, bytes.rx);
+            exporter.counter("peer_sent_bytes_total", &labels, bytes.tx);
+        }
+        for (authority, bytes) in self.byte_counts.authorities() {
+            let labels = format!(
+                "authority=\"{}\",direction=\"{}\"",
+                authority.authority,
+                direction(authority.direction),
+            );
+            exporter.counter("authority_received_body_bytes_total", &labels, bytes.rx);
+            exporter.counter("authority_sent_body_bytes_total", &labels, bytes.tx);
+        }
+
+        for ((dir, termination), count) in self.terminations.snapshot() {
+            let labels = format!(
+                "cause=\"{}\",direction=\"{}\"",
+                termination.as_str(),
+                direction(dir),
+            );
+            exporter.counter("upstream_terminations_total", &labels, count);
+        }
+
+        let dns = self.dns_metrics();
+        dns.latency().export(exporter);
+        for (authority, lookups) in dns.authorities {
+            let labels = DnsLabels(authority).to_string();
+            exporter.counter("dns_lookup_failures_total", &labels, lookups.failures);
+        }
+        exporter.gauge("dns_cache_entries", "", dns.cached as u64);
+
+        for (attempts, count) in self.request_attempts.histogram() {
+            let labels = format!("attempts=\"{}\"", attempts);
+            exporter.counter("request_attempts_total", &labels, count);
+        }
     }
 }
 
@@ -442,14 +1004,534 @@ impl HyperService for Serve {
                 .with_status(StatusCode::NotFound));
         }
 
-        let body = {
-            let metrics = self.metrics.lock()
-                .expect("metrics lock poisoned");
-            format!("{}", *metrics)
-        };
+        let body = self.render();
         future::ok(HyperResponse::new()
             .with_header(ContentLength(body.len() as u64))
             .with_header(ContentType::plaintext())
             .with_body(body))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use conduit_proxy_controller_grpc::common::Protocol;
+    use http;
+
+    use ctx;
+    use telemetry::event::{self, Event};
+    use super::*;
+
+    fn request(
+        proxy: &Arc<ctx::Proxy>,
+        authority: &str,
+        version: http::Version,
+    ) -> Arc<ctx::http::Request> {
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(proxy, &addr, &addr, &None, Protocol::Http);
+        let client = ctx::transport::Client::new(proxy, &addr, Protocol::Http, false);
+        let mut req = http::Request::new(());
+        *req.uri_mut() = format!("http://{}/", authority).parse().unwrap();
+        *req.version_mut() = version;
+        ctx::http::Request::new(&req, &server, &client, 0, Default::default())
+    }
+
+    fn request_end(req: &Arc<ctx::http::Request>) -> Event {
+        Event::StreamRequestEnd(Arc::clone(req), event::StreamRequestEnd {
+            since_request_open: Duration::from_millis(3),
+        })
+    }
+
+    fn render(serve: &Serve) -> String {
+        serve.render()
+    }
+
+    /// Asserts that each line of `text` is valid in the Prometheus text
+    /// exposition format, returning the samples as `(series, value)` pairs.
+    fn parse(text: &str) -> Vec<(String, f64)> {
+        let mut samples = Vec::new();
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            if line.starts_with("# HELP ") || line.starts_with("# TYPE ") {
+                continue;
+            }
+            assert!(!line.starts_with('#'), "unexpected comment: {}", line);
+
+            let split = line.rfind(' ').expect("sample should have a value");
+            let (series, value) = line.split_at(split);
+            let value = value.trim().parse::<f64>()
+                .unwrap_or_else(|_| panic!("invalid value: {}", line));
+
+            let name_end = series.find('{').unwrap_or(series.len());
+            let name = &series[..name_end];
+            assert!(!name.is_empty(), "missing metric name: {}", line);
+            assert!(
+                name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                "invalid metric name: {}", line,
+            );
+
+            if name_end < series.len() {
+                let labels = &series[name_end..];
+                assert!(labels.ends_with('}'), "unterminated labels: {}", line);
+                let labels = &labels[1..labels.len() - 1];
+                assert!(!labels.contains(",,"), "double comma: {}", line);
+                for label in labels.split("\",") {
+                    let mut kv = label.splitn(2, '=');
+                    let k = kv.next().unwrap();
+                    let v = kv.next().unwrap_or_else(|| panic!("invalid label: {}", line));
+                    assert!(!k.is_empty(), "empty label name: {}", line);
+                    assert!(v.starts_with('"'), "unquoted label value: {}", line);
+                }
+            }
+
+            samples.push((series.to_owned(), value));
+        }
+        samples
+    }
+
+    fn value(samples: &[(String, f64)], series: &str) -> Option<f64> {
+        samples.iter()
+            .find(|&&(ref s, _)| s == series)
+            .map(|&(_, v)| v)
+    }
+
+    #[test]
+    fn renders_request_metrics() {
+        let process = ctx::Process::test("test");
+        let (mut aggregate, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let inbound = ctx::Proxy::inbound(&process);
+        let outbound = ctx::Proxy::outbound(&process);
+        aggregate.record_event(&request_end(&request(&inbound, "a.test", http::Version::HTTP_11)));
+        aggregate.record_event(&request_end(&request(&outbound, "b.test", http::Version::HTTP_2)));
+
+        let samples = parse(&render(&serve));
+        assert_eq!(
+            value(&samples, "request_total{\
+                authority=\"a.test\",direction=\"inbound\",protocol=\"http1\"}"),
+            Some(1.0),
+        );
+        assert_eq!(
+            value(&samples, "request_total{\
+                authority=\"b.test\",direction=\"outbound\",protocol=\"http2\"}"),
+            Some(1.0),
+        );
+        assert_eq!(
+            value(&samples, "request_duration_ms_count{\
+                authority=\"a.test\",direction=\"inbound\",protocol=\"http1\"}"),
+            Some(1.0),
+        );
+    }
+
+    #[test]
+    fn counts_canceled_requests_separately() {
+        let process = ctx::Process::test("test");
+        let (mut aggregate, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let outbound = ctx::Proxy::outbound(&process);
+        let canceled_request = request(&outbound, "a.test", http::Version::HTTP_2);
+        aggregate.record_event(&Event::StreamRequestFail(
+            canceled_request,
+            event::StreamRequestFail {
+                since_request_open: Duration::from_millis(3),
+                error: h2::Reason::CANCEL,
+                request_bytes: 0,
+            },
+        ));
+
+        let rsp = ctx::http::Response::new(
+            &http::Response::new(()),
+            &request(&outbound, "a.test", http::Version::HTTP_2),
+            Default::default(),
+        );
+        let fail = |error| Event::StreamResponseFail(Arc::clone(&rsp), event::StreamResponseFail {
+            since_request_open: Duration::from_millis(5),
+            since_response_open: Duration::from_millis(2),
+            error,
+            bytes_sent: 0,
+            frames_sent: 0,
+            request_bytes: 0,
+        });
+        aggregate.record_event(&fail(h2::Reason::CANCEL));
+        aggregate.record_event(&fail(h2::Reason::INTERNAL_ERROR));
+
+        let samples = parse(&render(&serve));
+        let labels = "authority=\"a.test\",direction=\"outbound\",protocol=\"http2\"";
+        assert_eq!(value(&samples, &format!("request_cancel_total{{{}}}", labels)), Some(2.0));
+        assert_eq!(value(&samples, &format!("request_total{{{}}}", labels)), Some(1.0));
+        // Only the response that failed is counted as a failure.
+        assert_eq!(
+            value(&samples, &format!(
+                "response_total{{{},classification=\"failure\",status_code=\"500\"}}",
+                labels,
+            )),
+            Some(1.0),
+        );
+    }
+
+    #[test]
+    fn records_streaming_grpc_responses_by_message() {
+        use telemetry::classify::Classification;
+
+        let process = ctx::Process::test("test");
+        let (mut aggregate, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let outbound = ctx::Proxy::outbound(&process);
+        let end = |authority: &str, grpc_stream| {
+            let rsp = ctx::http::Response::new(
+                &http::Response::new(()),
+                &request(&outbound, authority, http::Version::HTTP_2),
+                Default::default(),
+            );
+            Event::StreamResponseEnd(rsp, event::StreamResponseEnd {
+                grpc_status: Some(0),
+                classification: Classification::Success,
+                since_request_open: Duration::from_secs(60),
+                since_response_open: Duration::from_secs(59),
+                bytes_sent: 0,
+                frames_sent: 0,
+                request_bytes: 0,
+                grpc_stream,
+            })
+        };
+        aggregate.record_event(&end("unary.test", None));
+        aggregate.record_event(&end("stream.test", Some(event::GrpcStream {
+            messages: 3,
+            first_message_since_request_open: Duration::from_millis(5),
+        })));
+
+        let samples = parse(&render(&serve));
+        let labels = |authority: &str| format!(
+            "authority=\"{}\",direction=\"outbound\",protocol=\"http2\",\
+             classification=\"success\",status_code=\"200\",grpc_status_code=\"0\"",
+            authority,
+        );
+        let series = |name: &str, authority: &str| format!("{}{{{}}}", name, labels(authority));
+
+        // Both responses are counted with their final gRPC status.
+        assert_eq!(value(&samples, &series("response_total", "unary.test")), Some(1.0));
+        assert_eq!(value(&samples, &series("response_total", "stream.test")), Some(1.0));
+
+        // The stream is measured by its messages, rather than its duration.
+        assert_eq!(
+            value(&samples, &series("response_stream_messages_total", "stream.test")),
+            Some(3.0),
+        );
+        let first_message = "response_stream_first_message_latency_ms";
+        assert_eq!(
+            value(&samples, &series(&format!("{}_count", first_message), "stream.test")),
+            Some(1.0),
+        );
+        assert_eq!(
+            value(&samples, &series(&format!("{}_sum", first_message), "stream.test")),
+            Some(5.0),
+        );
+        assert_eq!(value(&samples, &series("response_latency_ms_count", "stream.test")), None);
+
+        // Unary responses are measured as before.
+        assert_eq!(value(&samples, &series("response_latency_ms_count", "unary.test")), Some(1.0));
+        assert_eq!(
+            value(&samples, &series("response_stream_messages_total", "unary.test")),
+            None,
+        );
+    }
+
+    #[test]
+    fn renders_transport_metrics() {
+        let process = ctx::Process::test("test");
+        let (mut aggregate, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let proxy = ctx::Proxy::inbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let transport = Arc::new(ctx::transport::Ctx::Server(server));
+        aggregate.record_event(&Event::TransportOpen(Arc::clone(&transport)));
+        aggregate.record_event(&Event::TransportClose(transport, event::TransportClose {
+            clean: false,
+            duration: Duration::from_millis(10),
+            rx_bytes: 0,
+            tx_bytes: 0,
+        }));
+
+        let samples = parse(&render(&serve));
+        assert_eq!(
+            value(&samples, "tcp_open_total{direction=\"inbound\",peer=\"src\"}"),
+            Some(1.0),
+        );
+        assert_eq!(
+            value(&samples, "tcp_close_total{\
+                direction=\"inbound\",peer=\"src\",classification=\"failure\"}"),
+            Some(1.0),
+        );
+    }
+
+    #[test]
+    fn renders_requests_in_flight() {
+        let process = ctx::Process::test("test");
+        let in_flight = InFlight::default();
+        let (_, serve) = new(
+            &process,
+            &Config::new(100),
+            &in_flight,
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let endpoint = in_flight.endpoint(&addr);
+        let pending = (endpoint.start(), endpoint.start());
+
+        let series = "endpoint_requests_in_flight{dst_addr=\"10.1.1.1:8080\"}";
+        assert_eq!(value(&parse(&render(&serve)), series), Some(2.0));
+
+        drop(pending);
+        assert_eq!(value(&parse(&render(&serve)), series), Some(0.0));
+    }
+
+    #[test]
+    fn renders_termination_counts() {
+        let process = ctx::Process::test("test");
+        let terminations = Terminations::default();
+        let (_, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &terminations,
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+        terminations.record(Direction::Outbound, Termination::GoAway);
+        terminations.record(Direction::Outbound, Termination::GoAway);
+        terminations.record(Direction::Inbound, Termination::TcpReset);
+
+        let samples = parse(&render(&serve));
+        let series = |cause: &str, direction: &str| format!(
+            "upstream_terminations_total{{cause=\"{}\",direction=\"{}\"}}",
+            cause,
+            direction,
+        );
+        assert_eq!(value(&samples, &series("goaway", "outbound")), Some(2.0));
+        assert_eq!(value(&samples, &series("tcp_reset", "inbound")), Some(1.0));
+        assert_eq!(value(&samples, &series("eof", "inbound")), None);
+    }
+
+    #[test]
+    fn renders_byte_counts() {
+        let process = ctx::Process::test("test");
+        let byte_counts = ByteCounts::default();
+        let (_, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &byte_counts,
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let proxy = ctx::Proxy::inbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let peer = byte_counts.peer(&ctx::transport::Ctx::Server(server));
+        peer.received(100);
+        peer.sent(200);
+        let req = request(&proxy, "a.test", http::Version::HTTP_11);
+        let authority = byte_counts.authority(&req).expect("authority");
+        authority.received(5);
+        authority.sent(7);
+
+        let samples = parse(&render(&serve));
+        let peer_series = |name: &str| format!(
+            "{}{{direction=\"inbound\",peer_addr=\"10.1.1.1:8080\"}}",
+            name,
+        );
+        let authority_series = |name: &str| format!(
+            "{}{{authority=\"a.test\",direction=\"inbound\"}}",
+            name,
+        );
+        assert_eq!(value(&samples, &peer_series("peer_received_bytes_total")), Some(100.0));
+        assert_eq!(value(&samples, &peer_series("peer_sent_bytes_total")), Some(200.0));
+        assert_eq!(
+            value(&samples, &authority_series("authority_received_body_bytes_total")),
+            Some(5.0),
+        );
+        assert_eq!(
+            value(&samples, &authority_series("authority_sent_body_bytes_total")),
+            Some(7.0),
+        );
+
+        // Peers are no longer reported once their connections close.
+        drop(peer);
+        let samples = parse(&render(&serve));
+        assert_eq!(value(&samples, &peer_series("peer_received_bytes_total")), None);
+        assert_eq!(
+            value(&samples, &authority_series("authority_received_body_bytes_total")),
+            Some(5.0),
+        );
+    }
+
+    #[test]
+    fn uses_configured_latency_buckets() {
+        let process = ctx::Process::test("test");
+        let config = Config::new(100).with_latency_buckets_ms(&[100, 1, 10]);
+        let (mut aggregate, serve) = new(
+            &process,
+            &config,
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let proxy = ctx::Proxy::inbound(&process);
+        aggregate.record_event(&request_end(&request(&proxy, "a.test", http::Version::HTTP_2)));
+
+        let samples = parse(&render(&serve));
+        let buckets = samples.iter()
+            .filter(|&&(ref s, _)| s.starts_with("request_duration_ms_bucket{"))
+            .map(|&(ref s, v)| {
+                let le = s.rsplit("le=\"").next().unwrap();
+                (le.trim_right_matches("\"}").to_owned(), v)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(buckets, vec![
+            ("1".to_owned(), 0.0),
+            ("10".to_owned(), 1.0),
+            ("100".to_owned(), 1.0),
+            ("+Inf".to_owned(), 1.0),
+        ]);
+    }
+
+    #[test]
+    fn bounds_authority_cardinality() {
+        let process = ctx::Process::test("test");
+        let (mut aggregate, serve) = new(
+            &process,
+            &Config::new(2),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &RequestAttempts::default(),
+        );
+
+        let proxy = ctx::Proxy::inbound(&process);
+        for authority in &["a.test", "b.test", "c.test", "d.test", "a.test"] {
+            let req = request(&proxy, authority, http::Version::HTTP_2);
+            aggregate.record_event(&request_end(&req));
+        }
+
+        let samples = parse(&render(&serve));
+        let series = |authority: &str| format!(
+            "request_total{{authority=\"{}\",direction=\"inbound\",protocol=\"http2\"}}",
+            authority,
+        );
+        assert_eq!(value(&samples, &series("a.test")), Some(2.0));
+        assert_eq!(value(&samples, &series("b.test")), Some(1.0));
+        assert_eq!(value(&samples, &series("overflow")), Some(2.0));
+        assert_eq!(value(&samples, &series("c.test")), None);
+    }
+
+    #[test]
+    fn renders_dns_lookups() {
+        let process = ctx::Process::test("test");
+        let dns_lookups = DnsLookups::default();
+        let (_, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &dns_lookups,
+            &RequestAttempts::default(),
+        );
+
+        dns_lookups.record("a.test", Duration::from_millis(3), false);
+        dns_lookups.record("a.test", Duration::from_millis(5), true);
+        dns_lookups.record("b.test", Duration::from_millis(2), false);
+        dns_lookups.set_cached(2);
+
+        let samples = parse(&render(&serve));
+        let failures = |authority: &str| value(
+            &samples,
+            &format!("dns_lookup_failures_total{{authority=\"{}\"}}", authority),
+        );
+        let count = |authority: &str| value(
+            &samples,
+            &format!("dns_lookup_duration_ms_count{{authority=\"{}\"}}", authority),
+        );
+        assert_eq!(failures("a.test"), Some(1.0));
+        assert_eq!(failures("b.test"), Some(0.0));
+        assert_eq!(count("a.test"), Some(2.0));
+        assert_eq!(count("b.test"), Some(1.0));
+        assert_eq!(value(&samples, "dns_cache_entries"), Some(2.0));
+    }
+
+    #[test]
+    fn renders_request_attempts() {
+        let process = ctx::Process::test("test");
+        let request_attempts = RequestAttempts::default();
+        let (_, serve) = new(
+            &process,
+            &Config::new(100),
+            &InFlight::default(),
+            &ByteCounts::default(),
+            &Terminations::default(),
+            &DnsLookups::default(),
+            &request_attempts,
+        );
+
+        drop(request_attempts.start());
+        let retried = request_attempts.start();
+        retried.retry();
+        retried.retry();
+        drop(retried);
+        drop(request_attempts.start());
+
+        let samples = parse(&render(&serve));
+        let attempted = |attempts: usize| value(
+            &samples,
+            &format!("request_attempts_total{{attempts=\"{}\"}}", attempts),
+        );
+        assert_eq!(attempted(1), Some(2.0));
+        assert_eq!(attempted(2), None);
+        assert_eq!(attempted(3), Some(1.0));
+    }
+}
@@ -4,14 +4,20 @@ use futures_mpsc_lossy;
 
 use ctx;
 
+pub mod access_log;
+pub mod classify;
 mod control;
 pub mod event;
+pub mod events;
+pub mod export;
 pub mod metrics;
+pub mod recent;
 pub mod sensor;
 pub mod tap;
 
 pub use self::control::{Control, MakeControl};
 pub use self::event::Event;
+pub use self::recent::RecentRequests;
 pub use self::sensor::Sensors;
 
 /// Creates proxy-specific runtime telemetry.
@@ -24,15 +30,26 @@ pub use self::sensor::Sensors;
 ///
 /// # Arguments
 /// - `capacity`: the size of the event queue.
+/// - `metrics_config`: configures the aggregation of Prometheus metrics.
 ///
 /// [`Sensors`]: struct.Sensors.html
 /// [`Control`]: struct.Control.html
 pub fn new(
     process: &Arc<ctx::Process>,
     capacity: usize,
+    metrics_config: metrics::Config,
 ) -> (Sensors, MakeControl) {
     let (tx, rx) = futures_mpsc_lossy::channel(capacity);
     let s = Sensors::new(tx);
-    let c = MakeControl::new(rx, process);
+    let c = MakeControl::new(
+        rx,
+        process,
+        metrics_config,
+        s.in_flight(),
+        s.byte_counts(),
+        s.terminations(),
+        s.dns_lookups(),
+        s.request_attempts(),
+    );
     (s, c)
 }
@@ -0,0 +1,413 @@
+//! Records the most recent requests, for debugging.
+//!
+//! Unlike the tap, which streams requests matching a query to a subscriber
+//! as they occur, recent requests are kept in memory by the proxy, so that
+//! they may be inspected after the fact through the metrics server:
+//!
+//! - `GET /requests` lists the recorded requests, oldest first.
+//! - `POST /requests/enable` starts recording requests.
+//! - `POST /requests/disable` stops recording requests.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{self, FutureResult};
+use h2;
+use http;
+use hyper::{self, Method, StatusCode};
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::{
+    Service as HyperService,
+    Request as HyperRequest,
+    Response as HyperResponse
+};
+
+use ctx;
+use super::events::{LifecycleEvent, RequestEvent, Subscription};
+use timeout::HumanDuration;
+
+/// Holds the most recently completed requests, while recording is enabled.
+///
+/// Clones share the same recorded requests and the same flag enabling
+/// recording, so that recording may be enabled and disabled at runtime.
+#[derive(Clone, Debug)]
+pub struct RecentRequests {
+    enabled: Arc<AtomicBool>,
+    records: Arc<Mutex<Records>>,
+}
+
+/// Describes a completed request.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub id: u64,
+    pub inbound: bool,
+    /// The address to which the request was dispatched.
+    pub endpoint: SocketAddr,
+    pub method: http::Method,
+    pub uri: http::Uri,
+    /// The request's headers, with any sensitive values redacted.
+    pub headers: ctx::http::Headers,
+    /// The response's status, if a response was received.
+    pub status: Option<http::StatusCode>,
+    /// The reason the request or response failed, if it did.
+    pub error: Option<h2::Reason>,
+    /// The time from when the request started until its response ended.
+    pub duration: Duration,
+}
+
+/// A future that records the requests received by a `Subscription`.
+#[derive(Debug)]
+pub struct Recorder {
+    recent: RecentRequests,
+    subscription: Subscription,
+}
+
+/// Serves the admin API for recent requests.
+#[derive(Clone, Debug)]
+pub struct Serve(RecentRequests);
+
+#[derive(Debug)]
+struct Records {
+    capacity: usize,
+    records: VecDeque<Record>,
+}
+
+// ===== impl RecentRequests =====
+
+impl RecentRequests {
+    /// Returns a new, disabled recording of up to `capacity` requests.
+    ///
+    /// Once `capacity` requests have been recorded, the oldest are evicted
+    /// to make room for new ones.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "recent requests capacity must be positive");
+        RecentRequests {
+            enabled: Arc::new(AtomicBool::new(false)),
+            records: Arc::new(Mutex::new(Records {
+                capacity,
+                records: VecDeque::with_capacity(capacity),
+            })),
+        }
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Release);
+    }
+
+    /// Stops recording requests. Requests that were already recorded are kept.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Records the requests that end after recording is enabled, as they are
+    /// received by `subscription`.
+    pub fn recorder(&self, subscription: Subscription) -> Recorder {
+        Recorder {
+            recent: self.clone(),
+            subscription,
+        }
+    }
+
+    /// Returns the recorded requests, oldest first.
+    pub fn snapshot(&self) -> Vec<Record> {
+        let records = self.records.lock().expect("recent requests lock poisoned");
+        records.records.iter().cloned().collect()
+    }
+
+    /// Serves the admin API for these requests.
+    pub fn serve(&self) -> Serve {
+        Serve(self.clone())
+    }
+
+    fn record(&self, event: &RequestEvent) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let record = match *event {
+            RequestEvent::Ended { ref request, status, error, duration, .. } => Record {
+                id: request.id,
+                inbound: request.server.proxy.is_inbound(),
+                endpoint: request.client.remote,
+                method: request.method.clone(),
+                uri: request.uri.clone(),
+                headers: request.headers.clone(),
+                status,
+                error,
+                duration,
+            },
+            RequestEvent::Started(..) => return,
+        };
+
+        let mut records = self.records.lock().expect("recent requests lock poisoned");
+        if records.records.len() == records.capacity {
+            records.records.pop_front();
+        }
+        records.records.push_back(record);
+    }
+
+    fn render(&self) -> String {
+        let state = if self.is_enabled() { "enabled" } else { "disabled" };
+        let mut out = format!("recording {}\n", state);
+        for record in self.snapshot() {
+            out.push_str(&record.to_string());
+        }
+        out
+    }
+}
+
+// ===== impl Record =====
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = if self.inbound { "inbound" } else { "outbound" };
+        write!(
+            f,
+            "id={} direction={} endpoint={} method={} uri={}",
+            self.id,
+            direction,
+            self.endpoint,
+            self.method,
+            self.uri,
+        )?;
+        if let Some(status) = self.status {
+            write!(f, " status={}", status.as_u16())?;
+        }
+        if let Some(error) = self.error {
+            write!(f, " error={:?}", error)?;
+        }
+        writeln!(f, " duration={}", HumanDuration(self.duration))?;
+
+        for (name, value) in self.headers.iter() {
+            let value = value.to_str().unwrap_or("<non-ascii>");
+            writeln!(f, "  {}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+// ===== impl Recorder =====
+
+impl Future for Recorder {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        while let Some(event) = try_ready!(self.subscription.poll()) {
+            if let LifecycleEvent::Request(ref event) = event {
+                self.recent.record(event);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+// ===== impl Serve =====
+
+impl Serve {
+    /// Returns true if `path` is served by the admin API for recent requests.
+    pub fn serves(path: &str) -> bool {
+        path == "/requests" || path.starts_with("/requests/")
+    }
+}
+
+impl HyperService for Serve {
+    type Request = HyperRequest;
+    type Response = HyperResponse;
+    type Error = hyper::Error;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let status = match (req.method(), req.path()) {
+            (&Method::Get, "/requests") => None,
+            (&Method::Post, "/requests/enable") => {
+                info!("recording recent requests");
+                self.0.enable();
+                None
+            },
+            (&Method::Post, "/requests/disable") => {
+                info!("no longer recording recent requests");
+                self.0.disable();
+                None
+            },
+            (_, "/requests") |
+            (_, "/requests/enable") |
+            (_, "/requests/disable") => Some(StatusCode::MethodNotAllowed),
+            _ => Some(StatusCode::NotFound),
+        };
+        if let Some(status) = status {
+            return future::ok(HyperResponse::new().with_status(status));
+        }
+
+        let body = self.0.render();
+        future::ok(HyperResponse::new()
+            .with_header(ContentLength(body.len() as u64))
+            .with_header(ContentType::plaintext())
+            .with_body(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conduit_proxy_controller_grpc::common::Protocol;
+
+    use super::*;
+    use telemetry::classify::Classification;
+    use telemetry::event::{self, Event};
+    use telemetry::events::Events;
+
+    fn request(id: u64, path: &str) -> Arc<ctx::http::Request> {
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let server_addr = "127.0.0.1:5432".parse().unwrap();
+        let endpoint = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(
+            &proxy, &server_addr, &server_addr, &None, Protocol::Http,
+        );
+        let client = ctx::transport::Client::new(&proxy, &endpoint, Protocol::Http, false);
+
+        let mut req = http::Request::new(());
+        *req.method_mut() = http::Method::POST;
+        *req.uri_mut() = format!("http://example.com{}", path).parse().unwrap();
+        let mut headers = http::HeaderMap::new();
+        headers.insert("authorization", "<redacted>".parse().unwrap());
+        ctx::http::Request::new(&req, &server, &client, id, ctx::http::Headers::new(headers))
+    }
+
+    fn response_end(id: u64, path: &str, status: u16) -> Event {
+        let mut rsp = http::Response::new(());
+        *rsp.status_mut() = http::StatusCode::from_u16(status).unwrap();
+        let rsp = ctx::http::Response::new(&rsp, &request(id, path), Default::default());
+        Event::StreamResponseEnd(rsp, event::StreamResponseEnd {
+            grpc_status: None,
+            classification: Classification::Success,
+            since_request_open: Duration::from_millis(5),
+            since_response_open: Duration::from_millis(2),
+            bytes_sent: 0,
+            frames_sent: 0,
+            request_bytes: 0,
+            grpc_stream: None,
+        })
+    }
+
+    fn request_fail(id: u64, path: &str) -> Event {
+        Event::StreamRequestFail(request(id, path), event::StreamRequestFail {
+            since_request_open: Duration::from_millis(7),
+            error: h2::Reason::REFUSED_STREAM,
+            request_bytes: 0,
+        })
+    }
+
+    /// Publishes `events`, and then drives `recorder` until it has recorded
+    /// them all.
+    fn publish(events: &Events, recorder: &mut Recorder, published: Vec<Event>) {
+        for ev in &published {
+            events.publish(ev);
+        }
+        future::lazy(|| {
+            assert!(recorder.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn records_requests_in_order_while_enabled() {
+        let events = Events::default();
+        let recent = RecentRequests::new(10);
+        let mut recorder = recent.recorder(events.subscribe(10));
+
+        publish(&events, &mut recorder, vec![response_end(0, "/ignored", 200)]);
+        assert!(recent.snapshot().is_empty());
+
+        recent.enable();
+        publish(&events, &mut recorder, vec![
+            Event::StreamRequestOpen(request(1, "/started")),
+            response_end(1, "/first", 503),
+            request_fail(2, "/second"),
+        ]);
+
+        let records = recent.snapshot();
+        assert_eq!(records.len(), 2, "unexpected records: {:?}", records);
+
+        let first = &records[0];
+        assert_eq!(first.id, 1);
+        assert!(!first.inbound);
+        assert_eq!(first.endpoint, "10.1.1.1:8080".parse().unwrap());
+        assert_eq!(first.method, http::Method::POST);
+        assert_eq!(first.uri.path(), "/first");
+        assert_eq!(first.headers.get("authorization").unwrap(), "<redacted>");
+        assert_eq!(first.status, Some(http::StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(first.error, None);
+        assert_eq!(first.duration, Duration::from_millis(5));
+
+        let second = &records[1];
+        assert_eq!(second.id, 2);
+        assert_eq!(second.uri.path(), "/second");
+        assert_eq!(second.status, None);
+        assert_eq!(second.error, Some(h2::Reason::REFUSED_STREAM));
+        assert_eq!(second.duration, Duration::from_millis(7));
+
+        // Disabling recording keeps the requests already recorded.
+        recent.disable();
+        publish(&events, &mut recorder, vec![response_end(3, "/third", 200)]);
+        let ids = recent.snapshot().iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn evicts_oldest_requests() {
+        let events = Events::default();
+        let recent = RecentRequests::new(2);
+        let mut recorder = recent.recorder(events.subscribe(10));
+        recent.enable();
+
+        publish(&events, &mut recorder, (0..5).map(|id| response_end(id, "/", 200)).collect());
+        let ids = recent.snapshot().iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 4]);
+    }
+
+    #[test]
+    fn admin_api_toggles_recording() {
+        let recent = RecentRequests::new(1);
+        let serve = recent.serve();
+        let call = |method: Method, path: &str| {
+            let req = HyperRequest::new(method, path.parse().unwrap());
+            serve.call(req).wait().expect("response").status()
+        };
+
+        assert_eq!(call(Method::Post, "/requests/enable"), StatusCode::Ok);
+        assert!(recent.is_enabled());
+        assert_eq!(call(Method::Get, "/requests/disable"), StatusCode::MethodNotAllowed);
+        assert!(recent.is_enabled());
+        assert_eq!(call(Method::Post, "/requests/disable"), StatusCode::Ok);
+        assert!(!recent.is_enabled());
+        assert_eq!(call(Method::Get, "/requests"), StatusCode::Ok);
+        assert_eq!(call(Method::Get, "/requests/other"), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn renders_records() {
+        let events = Events::default();
+        let recent = RecentRequests::new(1);
+        let mut recorder = recent.recorder(events.subscribe(1));
+        recent.enable();
+        publish(&events, &mut recorder, vec![response_end(7, "/path?q=1", 200)]);
+
+        assert_eq!(
+            recent.render(),
+            "recording enabled\n\
+             id=7 direction=outbound endpoint=10.1.1.1:8080 method=POST \
+             uri=http://example.com/path?q=1 status=200 duration=5ms\n  \
+             authorization: <redacted>\n"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A histogram of the number of attempts made to send each request, so that
+/// retries that amplify the load on upstreams stand out.
+///
+/// Each request is counted once it's done with, after its last attempt.
+#[derive(Clone, Debug, Default)]
+pub struct RequestAttempts(Arc<Mutex<BTreeMap<usize, u64>>>);
+
+/// Counts the attempts made to send a request, carried in its extensions.
+///
+/// A request is attempted once when it's first sent, and again each time it
+/// is retried. Replays of a request share its `Attempts`, so that the count
+/// survives the layers between the proxy's server and each attempt. Once
+/// every clone is dropped, the count is recorded in `RequestAttempts`.
+#[derive(Clone, Debug)]
+pub struct Attempts(Arc<Counter>);
+
+#[derive(Debug)]
+struct Counter {
+    attempts: AtomicUsize,
+    recorded: RequestAttempts,
+}
+
+// ===== impl RequestAttempts =====
+
+impl RequestAttempts {
+    /// Starts counting the attempts of a new request.
+    pub fn start(&self) -> Attempts {
+        Attempts(Arc::new(Counter {
+            attempts: AtomicUsize::new(1),
+            recorded: self.clone(),
+        }))
+    }
+
+    /// Returns the number of requests that were attempted each number of
+    /// times, by the number of attempts.
+    pub fn histogram(&self) -> BTreeMap<usize, u64> {
+        self.0.lock().expect("request attempts lock poisoned").clone()
+    }
+
+    fn record(&self, attempts: usize) {
+        let mut histogram = self.0.lock().expect("request attempts lock poisoned");
+        *histogram.entry(attempts).or_insert(0) += 1;
+    }
+}
+
+// ===== impl Attempts =====
+
+impl Attempts {
+    /// Counts another attempt to send the request.
+    pub fn retry(&self) {
+        self.0.attempts.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+// ===== impl Counter =====
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        self.recorded.record(self.attempts.load(Ordering::Acquire));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_attempts_once_requests_are_dropped() {
+        let recorded = RequestAttempts::default();
+
+        let once = recorded.start();
+        let thrice = recorded.start();
+        let replay = thrice.clone();
+        replay.retry();
+        thrice.retry();
+        assert!(recorded.histogram().is_empty());
+
+        drop(once);
+        drop(thrice);
+        assert_eq!(recorded.histogram().into_iter().collect::<Vec<_>>(), vec![(1, 1)]);
+
+        // Replays share the request's count.
+        drop(replay);
+        let also_once = recorded.start();
+        drop(also_once);
+        assert_eq!(recorded.histogram().into_iter().collect::<Vec<_>>(), vec![(1, 2), (3, 1)]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ctx;
+
+/// Counts the bytes transferred by the proxy.
+///
+/// The bytes read from and written to each connection are counted as they
+/// are transferred, and are totaled by the connection's peer. Peers are
+/// forgotten once they have no open connections.
+///
+/// The body bytes of each request and response are also totaled by the
+/// request's authority. Unlike connection bytes, these exclude headers and
+/// framing, and these totals are never forgotten.
+#[derive(Clone, Debug, Default)]
+pub struct ByteCounts {
+    peers: Arc<Mutex<HashMap<Peer, Arc<Counts>>>>,
+    authorities: Arc<Mutex<HashMap<Authority, Arc<Counts>>>>,
+}
+
+/// Identifies the process on the other end of a connection.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Peer {
+    pub direction: Direction,
+    pub addr: SocketAddr,
+}
+
+/// Identifies the authority to which requests are sent.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Authority {
+    pub direction: Direction,
+    pub authority: String,
+}
+
+/// Whether bytes were transferred by the inbound or outbound proxy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// The bytes received from and sent to a peer or authority.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bytes {
+    pub rx: u64,
+    pub tx: u64,
+}
+
+/// Counts the bytes transferred with a single peer or authority.
+#[derive(Clone, Debug)]
+pub struct ByteCounter(Arc<Counts>);
+
+#[derive(Debug, Default)]
+struct Counts {
+    rx: AtomicUsize,
+    tx: AtomicUsize,
+}
+
+// ===== impl ByteCounts =====
+
+impl ByteCounts {
+    /// Returns the counter for the peer of a connection.
+    ///
+    /// Connections to the same peer share a counter.
+    pub fn peer(&self, ctx: &ctx::transport::Ctx) -> ByteCounter {
+        let (proxy, addr) = match *ctx {
+            ctx::transport::Ctx::Client(ref ctx) => (&ctx.proxy, ctx.remote),
+            ctx::transport::Ctx::Server(ref ctx) => (&ctx.proxy, ctx.remote),
+        };
+        let peer = Peer {
+            direction: Direction::from(proxy.as_ref()),
+            addr,
+        };
+        let mut peers = self.peers.lock().expect("byte counts lock poisoned");
+        ByteCounter(peers.entry(peer).or_insert_with(Default::default).clone())
+    }
+
+    /// Returns the counter for the authority of a request, if it has one.
+    pub fn authority(&self, req: &ctx::http::Request) -> Option<ByteCounter> {
+        let authority = Authority {
+            direction: Direction::from(req.server.proxy.as_ref()),
+            authority: req.uri.authority_part()?.as_str().to_owned(),
+        };
+        let mut authorities = self.authorities.lock().expect("byte counts lock poisoned");
+        let counts = authorities.entry(authority).or_insert_with(Default::default);
+        Some(ByteCounter(counts.clone()))
+    }
+
+    /// Returns the bytes transferred with each peer that has open
+    /// connections.
+    pub fn peers(&self) -> HashMap<Peer, Bytes> {
+        let mut peers = self.peers.lock().expect("byte counts lock poisoned");
+        peers.retain(|_, counts| Arc::strong_count(counts) > 1);
+        snapshot(&peers)
+    }
+
+    /// Returns the body bytes transferred in requests for each authority.
+    pub fn authorities(&self) -> HashMap<Authority, Bytes> {
+        let authorities = self.authorities.lock().expect("byte counts lock poisoned");
+        snapshot(&authorities)
+    }
+}
+
+fn snapshot<K: Clone + Eq + Hash>(
+    counts: &HashMap<K, Arc<Counts>>,
+) -> HashMap<K, Bytes> {
+    counts.iter()
+        .map(|(key, counts)| {
+            let bytes = Bytes {
+                rx: counts.rx.load(Ordering::Acquire) as u64,
+                tx: counts.tx.load(Ordering::Acquire) as u64,
+            };
+            (key.clone(), bytes)
+        })
+        .collect()
+}
+
+// ===== impl ByteCounter =====
+
+impl ByteCounter {
+    pub fn received(&self, n: usize) {
+        self.0.rx.fetch_add(n, Ordering::AcqRel);
+    }
+
+    pub fn sent(&self, n: usize) {
+        self.0.tx.fetch_add(n, Ordering::AcqRel);
+    }
+}
+
+// ===== impl Direction =====
+
+impl<'a> From<&'a ctx::Proxy> for Direction {
+    fn from(proxy: &'a ctx::Proxy) -> Self {
+        if proxy.is_inbound() {
+            Direction::Inbound
+        } else {
+            Direction::Outbound
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conduit_proxy_controller_grpc::common::Protocol;
+
+    use super::*;
+
+    #[test]
+    fn totals_bytes_by_peer_until_connections_close() {
+        let counts = ByteCounts::default();
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let client = |tls| {
+            let ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, tls);
+            ctx::transport::Ctx::Client(ctx)
+        };
+
+        let first = counts.peer(&client(false));
+        let second = counts.peer(&client(true));
+        first.received(10);
+        second.received(5);
+        second.sent(3);
+
+        let peer = Peer {
+            direction: Direction::Outbound,
+            addr,
+        };
+        assert_eq!(counts.peers()[&peer], Bytes { rx: 15, tx: 3 });
+
+        drop(first);
+        assert_eq!(counts.peers()[&peer], Bytes { rx: 15, tx: 3 });
+        drop(second);
+        assert!(counts.peers().is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use http::header::HeaderName;
+
+use telemetry::classify::{SharedClassifier, StatusClassifier};
+use super::{Decompress, RedactHeaders};
+
+/// The configuration of HTTP sensors that may be changed while the proxy
+/// runs, with `Sensors::update_config`.
+#[derive(Clone, Debug)]
+pub struct SensorConfig {
+    pub(super) redact_headers: RedactHeaders,
+    pub(super) classifier: SharedClassifier,
+    pub(super) decompress: Decompress,
+    pub(super) max_decompressed_bytes: usize,
+}
+
+/// The most bytes that a response may decompress into, by default, before
+/// it fails.
+const DEFAULT_MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+/// Holds the current `SensorConfig`, so that it may be replaced without
+/// disturbing the sensors that read it.
+///
+/// Sensors don't read the configuration from here directly. Each holds a
+/// `Cached` copy, which is only refreshed once the configuration's version
+/// changes, so that reading it doesn't contend on a lock: the lock is only
+/// taken to replace the configuration, and once by each sensor to copy a
+/// replacement.
+///
+/// Clones share the same configuration.
+#[derive(Clone, Debug)]
+pub(super) struct SharedConfig(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    version: AtomicUsize,
+    current: Mutex<Arc<SensorConfig>>,
+}
+
+/// A sensor's copy of a `SharedConfig`.
+#[derive(Debug)]
+pub(super) struct Cached {
+    shared: SharedConfig,
+    version: usize,
+    config: Arc<SensorConfig>,
+}
+
+// ===== impl SensorConfig =====
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            redact_headers: RedactHeaders::default(),
+            classifier: Arc::new(StatusClassifier::default()),
+            decompress: Decompress::default(),
+            max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+        }
+    }
+}
+
+impl SensorConfig {
+    /// Redacts the values of headers named in `names` from HTTP telemetry.
+    pub fn with_redacted_headers(self, names: Vec<HeaderName>) -> Self {
+        Self {
+            redact_headers: RedactHeaders::new(names),
+            ..self
+        }
+    }
+
+    /// Classifies completed responses with `classifier`.
+    pub fn with_classifier(self, classifier: SharedClassifier) -> Self {
+        Self {
+            classifier,
+            ..self
+        }
+    }
+
+    /// Handles responses that upstreams compressed with gzip according to
+    /// `decompress`.
+    pub fn with_decompression(self, decompress: Decompress) -> Self {
+        Self {
+            decompress,
+            ..self
+        }
+    }
+
+    /// Fails responses that decompress into more than `max` bytes, so that
+    /// a small response can't exhaust the proxy's memory.
+    pub fn with_max_decompressed_bytes(self, max: usize) -> Self {
+        Self {
+            max_decompressed_bytes: max,
+            ..self
+        }
+    }
+}
+
+// ===== impl SharedConfig =====
+
+impl Default for SharedConfig {
+    fn default() -> Self {
+        SharedConfig::new(SensorConfig::default())
+    }
+}
+
+impl SharedConfig {
+    pub(super) fn new(config: SensorConfig) -> Self {
+        SharedConfig(Arc::new(Inner {
+            version: AtomicUsize::new(0),
+            current: Mutex::new(Arc::new(config)),
+        }))
+    }
+
+    pub(super) fn load(&self) -> Arc<SensorConfig> {
+        self.0.current.lock().expect("sensor config lock poisoned").clone()
+    }
+
+    /// Replaces the configuration, for all of the sensors that share it.
+    ///
+    /// Each sensor picks up the replacement when it next reads its `Cached`
+    /// copy.
+    pub(super) fn store(&self, config: SensorConfig) {
+        let mut current = self.0.current.lock().expect("sensor config lock poisoned");
+        *current = Arc::new(config);
+        // The version is advanced while the lock is held, so that a sensor
+        // that sees the new version also copies the new configuration.
+        self.0.version.fetch_add(1, Ordering::Release);
+    }
+
+    pub(super) fn cached(&self) -> Cached {
+        let current = self.0.current.lock().expect("sensor config lock poisoned");
+        Cached {
+            shared: self.clone(),
+            version: self.0.version.load(Ordering::Acquire),
+            config: current.clone(),
+        }
+    }
+}
+
+// ===== impl Cached =====
+
+impl Cached {
+    /// Returns the current configuration, copying it only if it's been
+    /// replaced since it was last read.
+    pub(super) fn get(&mut self) -> &Arc<SensorConfig> {
+        if self.shared.0.version.load(Ordering::Acquire) != self.version {
+            *self = self.shared.cached();
+        }
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::AUTHORIZATION;
+
+    use super::*;
+
+    #[test]
+    fn cached_copies_see_replaced_configs() {
+        let shared = SharedConfig::default();
+        let mut cached = shared.cached();
+        let first = cached.get().clone();
+        assert!(Arc::ptr_eq(&first, cached.get()), "unchanged configs aren't copied");
+
+        shared.store(SensorConfig::default().with_redacted_headers(vec![AUTHORIZATION]));
+        let second = cached.get().clone();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert!(Arc::ptr_eq(&second, &shared.load()));
+
+        // Clones share the replacement.
+        let clone = shared.clone();
+        clone.store(SensorConfig::default());
+        assert!(!Arc::ptr_eq(&second, cached.get()));
+    }
+}
@@ -0,0 +1,416 @@
+use std::fmt;
+use std::io::{self, Cursor};
+
+use bytes::{Buf, Bytes};
+use flate2::{self, Crc, FlushDecompress, Status};
+use http;
+use http::header::CONTENT_ENCODING;
+
+/// How the HTTP sensors handle responses that upstreams compressed with
+/// gzip, so that telemetry which inspects the contents of responses, such as
+/// the counting of gRPC messages, sees their decompressed contents.
+///
+/// Byte counts always describe responses as the upstream sent them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Decompress {
+    /// Compressed responses are inspected as they are.
+    Off,
+    /// Compressed responses are decompressed for inspection, and forwarded
+    /// to the client compressed.
+    Inspect,
+    /// Compressed responses are decompressed for inspection, and forwarded
+    /// to the client decompressed, without their `Content-Encoding`.
+    Forward,
+}
+
+/// Decompresses a gzipped response body as its frames are received.
+///
+/// The body fails to decompress once it exceeds its maximum length, so that
+/// a small response can't decompress into an unbounded amount of memory.
+pub(super) struct Inflate {
+    /// Compressed data that can't be decompressed until more is received,
+    /// such as a partially received gzip header.
+    pending: Vec<u8>,
+    member: Member,
+    deflate: flate2::Decompress,
+    crc: Crc,
+    max_bytes: usize,
+    forward: bool,
+    finished: bool,
+}
+
+/// The part of a gzip member that is being received.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Member {
+    Header,
+    Deflate,
+    Trailer,
+    Done,
+}
+
+/// How much data is decompressed at a time.
+const CHUNK: usize = 32 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const GZIP_DEFLATE: u8 = 8;
+const GZIP_HEADER_LEN: usize = 10;
+const GZIP_TRAILER_LEN: usize = 8;
+
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+const FRESERVED: u8 = 0xe0;
+
+/// The data of a body, which may have been decompressed.
+#[derive(Debug)]
+pub enum Data<D> {
+    Identity(D),
+    Decompressed(Cursor<Bytes>),
+}
+
+// ===== impl Decompress =====
+
+impl Default for Decompress {
+    fn default() -> Self {
+        Decompress::Off
+    }
+}
+
+impl Decompress {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(Decompress::Off),
+            "inspect" => Some(Decompress::Inspect),
+            "forward" => Some(Decompress::Forward),
+            _ => None,
+        }
+    }
+
+    /// Starts decompressing `rsp`, if it's compressed with gzip and should
+    /// be decompressed, into at most `max_bytes`.
+    ///
+    /// If its decompressed body is forwarded, the response's headers no
+    /// longer describe it as compressed.
+    pub(super) fn inflate<B>(
+        &self,
+        rsp: &mut http::Response<B>,
+        max_bytes: usize,
+    ) -> Option<Inflate> {
+        let forward = match *self {
+            Decompress::Off => return None,
+            Decompress::Inspect => false,
+            Decompress::Forward => true,
+        };
+        if !is_gzip(rsp.headers()) {
+            return None;
+        }
+
+        trace!("decompressing response with gzip; forward={}", forward);
+        if forward {
+            let headers = rsp.headers_mut();
+            headers.remove(CONTENT_ENCODING);
+            // The length of the decompressed body isn't known until it's
+            // been decompressed.
+            headers.remove(http::header::CONTENT_LENGTH);
+        }
+        Some(Inflate {
+            pending: Vec::new(),
+            member: Member::Header,
+            deflate: flate2::Decompress::new(false),
+            crc: Crc::new(),
+            max_bytes,
+            forward,
+            finished: false,
+        })
+    }
+}
+
+/// Returns true if `headers` describe a body that is only encoded with gzip.
+fn is_gzip(headers: &http::HeaderMap) -> bool {
+    let mut codings = headers.get_all(CONTENT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|coding| coding.trim())
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"));
+    match (codings.next(), codings.next()) {
+        (Some(coding), None) => {
+            coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip")
+        }
+        _ => false,
+    }
+}
+
+// ===== impl Inflate =====
+
+impl Inflate {
+    /// Returns true if the decompressed body is sent to the client, rather
+    /// than the compressed body.
+    pub(super) fn is_forwarded(&self) -> bool {
+        self.forward
+    }
+
+    pub(super) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Decompresses `data`, returning as much of the body as can be
+    /// decompressed before more data is received.
+    ///
+    /// Fails if the body decompresses into more than its maximum.
+    pub(super) fn decompress(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        self.pending.extend_from_slice(data);
+        let mut out = Vec::new();
+        loop {
+            match self.member {
+                Member::Header => match gzip_header_len(&self.pending)? {
+                    Some(len) => {
+                        self.pending.drain(..len);
+                        self.member = Member::Deflate;
+                    }
+                    None => break,
+                },
+                Member::Deflate => {
+                    if !self.inflate(&mut out)? {
+                        break;
+                    }
+                    self.member = Member::Trailer;
+                }
+                Member::Trailer => {
+                    if self.pending.len() < GZIP_TRAILER_LEN {
+                        break;
+                    }
+                    let crc = le_u32(&self.pending[..4]);
+                    let len = le_u32(&self.pending[4..GZIP_TRAILER_LEN]);
+                    if crc != self.crc.sum() || len != self.crc.amount() {
+                        return Err(invalid_data("gzip trailer doesn't match the body"));
+                    }
+                    self.member = Member::Done;
+                }
+                Member::Done => {
+                    // Anything after the body is ignored.
+                    self.pending.clear();
+                    break;
+                }
+            }
+        }
+        Ok(out.into())
+    }
+
+    /// Ends the compressed stream, returning the rest of the body.
+    ///
+    /// Fails if the compressed stream was truncated.
+    pub(super) fn finish(&mut self) -> io::Result<Bytes> {
+        self.finished = true;
+        let rest = self.decompress(&[])?;
+        if self.member != Member::Done {
+            let e = io::Error::new(io::ErrorKind::UnexpectedEof, "gzip body was truncated");
+            return Err(e);
+        }
+        Ok(rest)
+    }
+
+    /// Decompresses as much of the pending deflate stream as possible into
+    /// `out`, returning true once the stream has ended.
+    fn inflate(&mut self, out: &mut Vec<u8>) -> io::Result<bool> {
+        let mut consumed = 0;
+        let ended = loop {
+            out.reserve(CHUNK);
+            let (total_in, len) = (self.deflate.total_in(), out.len());
+            let status = self.deflate
+                .decompress_vec(&self.pending[consumed..], out, FlushDecompress::None)
+                .map_err(invalid_data)?;
+            consumed += (self.deflate.total_in() - total_in) as usize;
+            self.crc.update(&out[len..]);
+
+            // The output is checked after each chunk, so that no more than
+            // a chunk past the maximum is ever decompressed.
+            if self.deflate.total_out() > self.max_bytes as u64 {
+                let msg = format!("decompressed body exceeds {} bytes", self.max_bytes);
+                return Err(invalid_data(msg));
+            }
+
+            match status {
+                Status::StreamEnd => break true,
+                // All of the input has been decompressed, if there was room
+                // for more output.
+                Status::Ok if out.len() < out.capacity() => break false,
+                Status::Ok => {}
+                Status::BufError => break false,
+            }
+        };
+        self.pending.drain(..consumed);
+        Ok(ended)
+    }
+}
+
+impl fmt::Debug for Inflate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Inflate")
+            .field("forward", &self.forward)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+/// Returns the length of the gzip header that `data` begins with, or `None`
+/// if more of the header must be received.
+fn gzip_header_len(data: &[u8]) -> io::Result<Option<usize>> {
+    if data.len() < GZIP_HEADER_LEN {
+        return Ok(None);
+    }
+    let flags = data[3];
+    if &data[..2] != GZIP_MAGIC || data[2] != GZIP_DEFLATE || flags & FRESERVED != 0 {
+        return Err(invalid_data("invalid gzip header"));
+    }
+
+    let mut len = GZIP_HEADER_LEN;
+    if flags & FEXTRA != 0 {
+        if data.len() < len + 2 {
+            return Ok(None);
+        }
+        len += 2 + (data[len] as usize | (data[len + 1] as usize) << 8);
+    }
+    for &flag in &[FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            // The field ends with a zero byte.
+            match data.get(len..).and_then(|field| field.iter().position(|&b| b == 0)) {
+                Some(end) => len += end + 1,
+                None => return Ok(None),
+            }
+        }
+    }
+    if flags & FHCRC != 0 {
+        len += 2;
+    }
+
+    if data.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(len))
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().rev().fold(0, |n, &b| n << 8 | u32::from(b))
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<::std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// ===== impl Data =====
+
+impl<D: Buf> Buf for Data<D> {
+    fn remaining(&self) -> usize {
+        match *self {
+            Data::Identity(ref d) => d.remaining(),
+            Data::Decompressed(ref d) => d.remaining(),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Data::Identity(ref d) => d.bytes(),
+            Data::Decompressed(ref d) => d.bytes(),
+        }
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        match *self {
+            Data::Identity(ref mut d) => d.advance(cnt),
+            Data::Decompressed(ref mut d) => d.advance(cnt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    const MAX: usize = 1024;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn response(content_encoding: &str) -> http::Response<()> {
+        http::Response::builder()
+            .header(CONTENT_ENCODING, content_encoding)
+            .header(http::header::CONTENT_LENGTH, "12")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn only_gzipped_responses_are_decompressed() {
+        let mut rsp = response("gzip");
+        assert!(Decompress::Off.inflate(&mut rsp, MAX).is_none());
+        let inflate = Decompress::Inspect.inflate(&mut rsp, MAX).expect("inflate");
+        assert!(!inflate.is_forwarded());
+        assert_eq!(rsp.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+
+        assert!(Decompress::Forward.inflate(&mut response("br"), MAX).is_none());
+        assert!(Decompress::Forward.inflate(&mut response("gzip, br"), MAX).is_none());
+        assert!(Decompress::Forward.inflate(&mut http::Response::new(()), MAX).is_none());
+
+        // Forwarded responses are no longer described as compressed.
+        let mut rsp = response("identity, X-GZIP");
+        assert!(Decompress::Forward.inflate(&mut rsp, MAX).expect("inflate").is_forwarded());
+        assert!(rsp.headers().get(CONTENT_ENCODING).is_none());
+        assert!(rsp.headers().get(http::header::CONTENT_LENGTH).is_none());
+    }
+
+    #[test]
+    fn decompresses_bodies_as_they_are_received() {
+        let compressed = gzip(b"hello hello hello world");
+        let mut inflate = Decompress::Inspect.inflate(&mut response("gzip"), MAX).unwrap();
+
+        let mut decompressed = Vec::new();
+        for chunk in compressed.chunks(5) {
+            decompressed.extend_from_slice(&inflate.decompress(chunk).expect("decompress"));
+        }
+        decompressed.extend_from_slice(&inflate.finish().expect("finish"));
+        assert!(inflate.is_finished());
+        assert_eq!(&decompressed[..], &b"hello hello hello world"[..]);
+    }
+
+    #[test]
+    fn invalid_bodies_fail() {
+        let compressed = gzip(b"hello world");
+
+        let mut truncated = Decompress::Inspect.inflate(&mut response("gzip"), MAX).unwrap();
+        truncated.decompress(&compressed[..compressed.len() - 4]).expect("decompress");
+        assert!(truncated.finish().is_err());
+
+        let mut invalid = Decompress::Inspect.inflate(&mut response("gzip"), MAX).unwrap();
+        assert!(invalid.decompress(b"hello world, uncompressed").is_err());
+    }
+
+    #[test]
+    fn bodies_fail_once_they_decompress_past_the_maximum() {
+        let body = vec![b'a'; 64 * MAX];
+        let compressed = gzip(&body);
+        assert!(compressed.len() < MAX, "{} bytes should compress well", body.len());
+
+        let mut inflate = Decompress::Forward.inflate(&mut response("gzip"), MAX).unwrap();
+        let err = inflate.decompress(&compressed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A body of exactly the maximum decompresses.
+        let mut inflate = Decompress::Forward.inflate(&mut response("gzip"), MAX).unwrap();
+        let mut decompressed = inflate.decompress(&gzip(&body[..MAX])).expect("decompress");
+        decompressed.extend_from_slice(&inflate.finish().expect("finish"));
+        assert_eq!(decompressed.len(), MAX);
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use telemetry::metrics::latency::{Bounds, Histogram};
+
+/// Records how long the DNS lookups of each authority take, how many of
+/// them fail, and how many lookups are cached.
+///
+/// Lookups that find that a name doesn't exist succeed; only those that
+/// couldn't be answered, such as when the resolver times out, fail. Latency
+/// is recorded in the default latency buckets, whatever buckets are
+/// configured for other metrics.
+#[derive(Clone, Debug, Default)]
+pub struct DnsLookups(Arc<Mutex<Inner>>);
+
+/// The lookups of a single authority.
+#[derive(Clone, Debug)]
+pub struct AuthorityLookups {
+    /// The latency of every lookup, whether it succeeded or failed.
+    pub latency: Histogram,
+    pub failures: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    authorities: HashMap<String, AuthorityLookups>,
+    cached: usize,
+}
+
+// ===== impl DnsLookups =====
+
+impl DnsLookups {
+    /// Records a lookup of `authority` that completed after `latency`.
+    pub fn record(&self, authority: &str, latency: Duration, failed: bool) {
+        let mut inner = self.0.lock().expect("DNS lookups lock poisoned");
+        if !inner.authorities.contains_key(authority) {
+            let lookups = AuthorityLookups {
+                latency: Histogram::new(&Bounds::default()),
+                failures: 0,
+            };
+            inner.authorities.insert(authority.to_owned(), lookups);
+        }
+
+        let lookups = inner.authorities.get_mut(authority).expect("inserted above");
+        lookups.latency += latency;
+        if failed {
+            lookups.failures += 1;
+        }
+    }
+
+    /// Records that the resolver has `cached` lookups cached.
+    pub fn set_cached(&self, cached: usize) {
+        self.0.lock().expect("DNS lookups lock poisoned").cached = cached;
+    }
+
+    /// Returns the lookups of each authority that has been looked up.
+    pub fn authorities(&self) -> HashMap<String, AuthorityLookups> {
+        self.0.lock().expect("DNS lookups lock poisoned").authorities.clone()
+    }
+
+    /// Returns the number of lookups that the resolver has cached.
+    pub fn cached(&self) -> usize {
+        self.0.lock().expect("DNS lookups lock poisoned").cached
+    }
+}
@@ -0,0 +1,160 @@
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use http;
+use http::header::CONTENT_TYPE;
+
+use telemetry::event;
+
+/// The length of the prefix of each gRPC message: a compression flag, and
+/// the message's length as a big-endian `u32`.
+const PREFIX_LEN: usize = 5;
+
+/// Counts the messages in a gRPC response body as its data is received.
+///
+/// A response with more than one message is a stream, as from a server
+/// streaming or bidirectional call. Unary calls, and streams that ended
+/// after a single message, can't be told apart, and are treated as unary.
+#[derive(Debug)]
+pub struct Messages {
+    /// The bytes of the current message's prefix that have been received.
+    prefix: [u8; PREFIX_LEN],
+    prefix_len: usize,
+    /// The bytes of the current message that have yet to be received, once
+    /// its prefix has been.
+    remaining: u64,
+    count: u32,
+    first_message_at: Option<Instant>,
+}
+
+/// Returns true if `headers` describe a gRPC message.
+pub fn is_grpc(headers: &http::HeaderMap) -> bool {
+    headers.get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|ct| ct.starts_with("application/grpc"))
+        .unwrap_or(false)
+}
+
+// ===== impl Messages =====
+
+impl Messages {
+    pub fn new() -> Self {
+        Messages {
+            prefix: [0; PREFIX_LEN],
+            prefix_len: 0,
+            remaining: 0,
+            count: 0,
+            first_message_at: None,
+        }
+    }
+
+    /// Records that `data` was received at `now`.
+    pub fn received(&mut self, mut data: &[u8], now: Instant) {
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let n = cmp::min(self.remaining, data.len() as u64);
+                self.remaining -= n;
+                data = &data[n as usize..];
+                if self.remaining == 0 {
+                    self.message_received(now);
+                }
+                continue;
+            }
+
+            let n = cmp::min(PREFIX_LEN - self.prefix_len, data.len());
+            self.prefix[self.prefix_len..self.prefix_len + n].copy_from_slice(&data[..n]);
+            self.prefix_len += n;
+            data = &data[n..];
+            if self.prefix_len == PREFIX_LEN {
+                self.prefix_len = 0;
+                self.remaining = u64::from(self.prefix[1]) << 24 |
+                    u64::from(self.prefix[2]) << 16 |
+                    u64::from(self.prefix[3]) << 8 |
+                    u64::from(self.prefix[4]);
+                if self.remaining == 0 {
+                    self.message_received(now);
+                }
+            }
+        }
+    }
+
+    fn message_received(&mut self, now: Instant) {
+        self.count = self.count.saturating_add(1);
+        if self.first_message_at.is_none() {
+            self.first_message_at = Some(now);
+        }
+    }
+
+    /// Describes the response as a stream, if it had more than one message.
+    pub fn stream(&self, request_open: Instant) -> Option<event::GrpcStream> {
+        if self.count < 2 {
+            return None;
+        }
+        let first_message_at = self.first_message_at?;
+        let since_request_open = if first_message_at > request_open {
+            first_message_at.duration_since(request_open)
+        } else {
+            Duration::from_secs(0)
+        };
+        Some(event::GrpcStream {
+            messages: self.count,
+            first_message_since_request_open: since_request_open,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(len: u8) -> Vec<u8> {
+        let mut msg = vec![0, 0, 0, 0, len];
+        msg.extend(0..len);
+        msg
+    }
+
+    #[test]
+    fn counts_messages_split_across_frames() {
+        let start = Instant::now();
+        let mut data = message(3);
+        data.extend(message(0));
+        data.extend(message(10));
+
+        let mut messages = Messages::new();
+        for (i, chunk) in data.chunks(2).enumerate() {
+            messages.received(chunk, start + Duration::from_millis(i as u64));
+        }
+
+        let stream = messages.stream(start).expect("stream");
+        assert_eq!(stream.messages, 3);
+        // The first message ends in the fourth frame.
+        assert_eq!(stream.first_message_since_request_open, Duration::from_millis(3));
+    }
+
+    #[test]
+    fn single_messages_are_not_streams() {
+        let start = Instant::now();
+        let mut messages = Messages::new();
+        assert!(messages.stream(start).is_none());
+
+        messages.received(&message(4), start);
+        assert!(messages.stream(start).is_none());
+
+        // A message is counted only once it has been received in full.
+        let second = message(4);
+        messages.received(&second[..6], start);
+        assert!(messages.stream(start).is_none());
+        messages.received(&second[6..], start);
+        assert_eq!(messages.stream(start).map(|s| s.messages), Some(2));
+    }
+
+    #[test]
+    fn detects_grpc_content_types() {
+        let mut headers = http::HeaderMap::new();
+        assert!(!is_grpc(&headers));
+        headers.insert(CONTENT_TYPE, "application/grpc+proto".parse().unwrap());
+        assert!(is_grpc(&headers));
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        assert!(!is_grpc(&headers));
+    }
+}
@@ -1,8 +1,9 @@
-use bytes::{Buf, IntoBuf};
-use futures::{future, Async, Future, Poll, Stream};
+use bytes::{Buf, Bytes, IntoBuf};
+use futures::{Async, Future, Poll, Stream};
 use h2;
 use http;
 use std::default::Default;
+use std::io::{self, Cursor};
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -11,10 +12,25 @@ use tower::{NewService, Service};
 use tower_h2::{client, Body};
 
 use ctx;
+use fault;
+use rand;
+use telemetry::classify::{Classification, SharedClassifier};
 use telemetry::event::{self, Event};
+use super::ByteCounter;
+use super::config::{Cached, SensorConfig, SharedConfig};
+use super::decompress::{self, Inflate};
+use super::grpc;
+use super::in_flight;
+use super::request_id::{self, RequestIdGen, SharedRequestIdGen};
+use super::spans;
+use super::RequestAttempts;
+use super::trace;
 
 const GRPC_STATUS: &str = "grpc-status";
 
+/// Replaces the values of redacted headers in telemetry.
+const REDACTED: &str = "[REDACTED]";
+
 /// A `RequestOpen` timestamp.
 ///
 /// This is added to a request's `Extensions` by the `TimestampRequestOpen`
@@ -29,34 +45,65 @@ pub struct RequestOpen(pub Instant);
 /// to install it at the earliest point in the stack. This is in order
 /// to ensure that request latency metrics cover the overhead added by
 /// the proxy as accurately as possible.
-#[derive(Copy, Clone, Debug)]
+///
+/// If configured with `RequestAttempts`, each request's `Attempts` start
+/// being counted here too, so that every attempt beneath it is counted.
+#[derive(Clone, Debug)]
 pub struct TimestampRequestOpen<S> {
     inner: S,
+    attempts: Option<RequestAttempts>,
+}
+
+/// Wraps the inner `NewService`'s services in `TimestampRequestOpen`.
+pub struct TimestampInit<F> {
+    future: F,
+    attempts: Option<RequestAttempts>,
 }
 
+/// Redacts the values of sensitive headers before they are captured for
+/// telemetry.
+///
+/// Only the captured copies of headers are redacted; proxied requests and
+/// responses are not modified.
+#[derive(Clone, Debug, Default)]
+pub struct RedactHeaders(Arc<Vec<http::header::HeaderName>>);
+
 pub struct NewHttp<N, A, B> {
-    next_id: Arc<AtomicUsize>,
+    req_ids: SharedRequestIdGen,
     new_service: N,
     handle: super::Handle,
+    config: SharedConfig,
+    trace: Option<trace::Propagation>,
+    request_id_header: Option<http::header::HeaderName>,
     client_ctx: Arc<ctx::transport::Client>,
+    in_flight: in_flight::Endpoint,
     _p: PhantomData<(A, B)>,
 }
 
 pub struct Init<F, A, B> {
-    next_id: Arc<AtomicUsize>,
+    req_ids: SharedRequestIdGen,
     future: F,
     handle: super::Handle,
+    config: SharedConfig,
+    trace: Option<trace::Propagation>,
+    request_id_header: Option<http::header::HeaderName>,
     client_ctx: Arc<ctx::transport::Client>,
+    in_flight: in_flight::Endpoint,
     _p: PhantomData<(A, B)>,
 }
 
 /// Wraps a transport with telemetry.
 #[derive(Debug)]
 pub struct Http<S, A, B> {
-    next_id: Arc<AtomicUsize>,
+    req_ids: SharedRequestIdGen,
     service: S,
     handle: super::Handle,
+    /// The configuration that requests are described with.
+    config: Cached,
+    trace: Option<trace::Propagation>,
+    request_id_header: Option<http::header::HeaderName>,
     client_ctx: Arc<ctx::transport::Client>,
+    in_flight: in_flight::Endpoint,
     _p: PhantomData<(A, B)>,
 }
 
@@ -64,13 +111,20 @@ pub struct Http<S, A, B> {
 pub struct Respond<F, B> {
     future: F,
     inner: Option<RespondInner>,
+    /// Counts the request as in flight until its response ends.
+    in_flight: Option<in_flight::Guard>,
+    /// The request's spans, polled in while awaiting the response.
+    spans: Option<spans::Dispatch>,
     _p: PhantomData<(B)>,
 }
 
 #[derive(Debug)]
 struct RespondInner {
     handle: super::Handle,
+    /// The configuration that was current when the request was sent.
+    config: Arc<SensorConfig>,
     ctx: Arc<ctx::http::Request>,
+    request_bytes: RequestBytes,
     request_open: Instant,
 }
 
@@ -81,6 +135,11 @@ pub type RequestBody<B> = MeasuredBody<B, RequestBodyInner>;
 pub struct MeasuredBody<B, I: BodySensor> {
     body: B,
     inner: Option<I>,
+    in_flight: Option<in_flight::Guard>,
+    /// Keeps the request's spans open until its response ends.
+    _spans: Option<spans::Dispatch>,
+    /// Decompresses a response that the upstream compressed.
+    inflate: Option<Inflate>,
     _p: PhantomData<(B)>,
 }
 
@@ -89,16 +148,29 @@ pub struct MeasuredBody<B, I: BodySensor> {
 pub trait BodySensor: Sized {
     fn fail(self, reason: h2::Reason);
     fn end(self, grpc_status: Option<u32>);
-    fn frames_sent(&mut self) -> &mut u32;
-    fn bytes_sent(&mut self) -> &mut u64;
+    /// Records that the body was dropped before it ended.
+    fn cancel(self);
+    /// Records that a data frame was sent.
+    fn data_sent<D: Buf>(&mut self, data: &D);
+    /// Inspects the contents of the body, decompressed if the body was
+    /// compressed and is being decompressed.
+    fn inspect(&mut self, _data: &[u8]) {}
 }
 
 #[derive(Debug)]
 pub struct ResponseBodyInner {
     handle: super::Handle,
+    classifier: SharedClassifier,
     ctx: Arc<ctx::http::Response>,
+    /// Whether the response was injected as a fault.
+    injected: bool,
     bytes_sent: u64,
     frames_sent: u32,
+    /// Totals the body bytes received from the request's authority.
+    authority_bytes: Option<ByteCounter>,
+    /// Counts the messages of gRPC responses.
+    grpc_messages: Option<grpc::Messages>,
+    request_bytes: RequestBytes,
     request_open: Instant,
     response_open: Instant,
 }
@@ -108,11 +180,83 @@ pub struct ResponseBodyInner {
 pub struct RequestBodyInner {
     handle: super::Handle,
     ctx: Arc<ctx::http::Request>,
-    bytes_sent: u64,
+    bytes_sent: RequestBytes,
     frames_sent: u32,
+    /// Totals the body bytes sent to the request's authority.
+    authority_bytes: Option<ByteCounter>,
     request_open: Instant,
 }
 
+/// Totals the bytes of a request's body as it is sent, so that they may be
+/// described when its response ends.
+#[derive(Clone, Debug, Default)]
+struct RequestBytes(Arc<AtomicUsize>);
+
+/// Classifies a response with `classifier`, unless the proxy injected it as
+/// a fault.
+fn classify(
+    classifier: &SharedClassifier,
+    injected: bool,
+    status: http::StatusCode,
+    grpc_status: Option<u32>,
+) -> Classification {
+    if injected {
+        Classification::Injected
+    } else {
+        classifier.classify(status, grpc_status)
+    }
+}
+
+/// Decompresses `frame`, and, if the body has ended, the rest of the body.
+fn decompress_frame<D: Buf>(
+    inflate: &mut Inflate,
+    frame: &Option<D>,
+    end: bool,
+) -> io::Result<Bytes> {
+    let mut data = match *frame {
+        Some(ref frame) => inflate.decompress(frame.bytes())?,
+        None => Bytes::new(),
+    };
+    if end {
+        data.extend_from_slice(&inflate.finish()?);
+    }
+    Ok(data)
+}
+
+// === RedactHeaders ===
+
+impl RedactHeaders {
+    pub(super) fn new(names: Vec<http::header::HeaderName>) -> Self {
+        RedactHeaders(Arc::new(names))
+    }
+
+    /// Copies `headers` for telemetry, replacing the values of any redacted
+    /// headers.
+    ///
+    /// Header names are always lowercase, so matching is case-insensitive.
+    fn capture(&self, headers: &http::HeaderMap) -> ctx::http::Headers {
+        let mut captured = headers.clone();
+        for name in self.0.iter() {
+            if captured.contains_key(name) {
+                captured.insert(name.clone(), http::header::HeaderValue::from_static(REDACTED));
+            }
+        }
+        ctx::http::Headers::new(captured)
+    }
+}
+
+// === RequestBytes ===
+
+impl RequestBytes {
+    fn add(&self, bytes: usize) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed) as u64
+    }
+}
+
 // === NewHttp ===
 
 impl<N, A, B> NewHttp<N, A, B>
@@ -127,16 +271,24 @@ where
         + 'static,
 {
     pub(super) fn new(
-        next_id: Arc<AtomicUsize>,
+        req_ids: SharedRequestIdGen,
         new_service: N,
         handle: &super::Handle,
+        config: &SharedConfig,
+        trace: Option<trace::Propagation>,
+        request_id_header: Option<http::header::HeaderName>,
         client_ctx: &Arc<ctx::transport::Client>,
+        in_flight: in_flight::Endpoint,
     ) -> Self {
         Self {
-            next_id,
+            req_ids,
             new_service,
             handle: handle.clone(),
+            config: config.clone(),
+            trace,
+            request_id_header,
             client_ctx: Arc::clone(client_ctx),
+            in_flight,
             _p: PhantomData,
         }
     }
@@ -162,10 +314,14 @@ where
 
     fn new_service(&self) -> Self::Future {
         Init {
-            next_id: self.next_id.clone(),
+            req_ids: self.req_ids.clone(),
             future: self.new_service.new_service(),
             handle: self.handle.clone(),
+            config: self.config.clone(),
+            trace: self.trace,
+            request_id_header: self.request_id_header.clone(),
             client_ctx: Arc::clone(&self.client_ctx),
+            in_flight: self.in_flight.clone(),
             _p: PhantomData,
         }
     }
@@ -192,8 +348,12 @@ where
         Ok(Async::Ready(Http {
             service,
             handle: self.handle.clone(),
-            next_id: self.next_id.clone(),
+            config: self.config.cached(),
+            trace: self.trace,
+            request_id_header: self.request_id_header.clone(),
+            req_ids: self.req_ids.clone(),
             client_ctx: self.client_ctx.clone(),
+            in_flight: self.in_flight.clone(),
             _p: PhantomData,
         }))
     }
@@ -222,21 +382,40 @@ where
     }
 
     fn call(&mut self, mut req: Self::Request) -> Self::Future {
+        // Trace context is propagated before the request's headers are
+        // captured, so that telemetry describes the headers that are sent.
+        if let Some(propagation) = self.trace {
+            let span = propagation.propagate(req.headers_mut(), &mut rand::thread_rng());
+            req.extensions_mut().insert(span);
+        }
+
         let metadata = (
             req.extensions_mut().remove::<Arc<ctx::transport::Server>>(),
             req.extensions_mut().remove::<RequestOpen>()
         );
+        let mut dispatch = None;
         let (inner, body_inner) = match metadata {
             (Some(ctx), Some(RequestOpen(request_open))) => {
-                let id = self.next_id.fetch_add(1, Ordering::SeqCst);
-                let ctx = ctx::http::Request::new(&req, &ctx, &self.client_ctx, id);
+                let id = self.req_ids.next_id();
+                dispatch = req.extensions_mut()
+                    .get_mut::<spans::Request>()
+                    .map(|spans| spans.dispatch(id, &self.client_ctx.remote));
+                if let Some(ref header) = self.request_id_header {
+                    request_id::set_header(req.headers_mut(), header, id);
+                }
+                let config = self.config.get().clone();
+                let headers = config.redact_headers.capture(req.headers());
+                let ctx = ctx::http::Request::new(&req, &ctx, &self.client_ctx, id, headers);
 
                 self.handle
                     .send(|| Event::StreamRequestOpen(Arc::clone(&ctx)));
 
+                let request_bytes = RequestBytes::default();
                 let respond_inner = Some(RespondInner {
                     ctx: ctx.clone(),
                     handle: self.handle.clone(),
+                    config,
+                    request_bytes: request_bytes.clone(),
                     request_open,
                 });
                 let body_inner =
@@ -252,11 +431,12 @@ where
                         None
                     } else {
                         Some(RequestBodyInner {
+                            authority_bytes: self.handle.byte_counts.authority(&ctx),
                             ctx,
                             handle: self.handle.clone(),
                             request_open,
                             frames_sent: 0,
-                            bytes_sent: 0,
+                            bytes_sent: request_bytes,
                         })
                     };
                 (respond_inner, body_inner)
@@ -275,11 +455,17 @@ where
             http::Request::from_parts(parts, body)
         };
 
-        let future = self.service.call(req);
+        let in_flight = Some(self.in_flight.start());
+        let future = match dispatch {
+            Some(ref spans) => spans.in_scope(|| self.service.call(req)),
+            None => self.service.call(req),
+        };
 
         Respond {
             future,
             inner,
+            in_flight,
+            spans: dispatch,
             _p: PhantomData,
         }
     }
@@ -296,18 +482,39 @@ where
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.future.poll() {
+        let poll = {
+            let future = &mut self.future;
+            match self.spans {
+                Some(ref spans) => spans.in_scope(|| future.poll()),
+                None => future.poll(),
+            }
+        };
+        match poll {
             Ok(Async::NotReady) => Ok(Async::NotReady),
 
-            Ok(Async::Ready(rsp)) => {
+            Ok(Async::Ready(mut rsp)) => {
+                // Responses are decompressed before their headers are
+                // captured, so that telemetry describes the headers that are
+                // sent.
+                let inflate = match self.inner {
+                    Some(ref i) if !rsp.body().is_end_stream() => {
+                        let max_bytes = i.config.max_decompressed_bytes;
+                        i.config.decompress.inflate(&mut rsp, max_bytes)
+                    }
+                    _ => None,
+                };
                 let inner = self.inner.take().and_then(|i| {
                     let RespondInner {
                         ctx,
                         mut handle,
+                        config,
+                        request_bytes,
                         request_open,
                     } = i;
 
-                    let ctx = ctx::http::Response::new(&rsp, &ctx);
+                    let headers = config.redact_headers.capture(rsp.headers());
+                    let ctx = ctx::http::Response::new(&rsp, &ctx, headers);
+                    let injected = fault::is_injected(&rsp);
 
                     handle.send(|| {
                         Event::StreamResponseOpen(
@@ -324,35 +531,62 @@ where
                                 .get(GRPC_STATUS)
                                 .and_then(|v| v.to_str().ok())
                                 .and_then(|s| s.parse::<u32>().ok());
+                            let classification =
+                                classify(&config.classifier, injected, ctx.status, grpc_status);
 
                             event::Event::StreamResponseEnd(
                                 Arc::clone(&ctx),
                                 event::StreamResponseEnd {
                                     grpc_status,
+                                    classification,
                                     since_request_open: request_open.elapsed(),
                                     since_response_open: Duration::default(),
                                     bytes_sent: 0,
                                     frames_sent: 0,
+                                    request_bytes: request_bytes.get(),
+                                    grpc_stream: None,
                                 },
                             )
                         });
 
                         None
                     } else {
+                        let authority_bytes = handle.byte_counts.authority(&ctx.request);
+                        let grpc_messages = if grpc::is_grpc(rsp.headers()) {
+                            Some(grpc::Messages::new())
+                        } else {
+                            None
+                        };
                         Some(ResponseBodyInner {
                             handle: handle,
+                            classifier: config.classifier.clone(),
                             ctx,
+                            injected,
                             bytes_sent: 0,
                             frames_sent: 0,
+                            authority_bytes,
+                            grpc_messages,
+                            request_bytes,
                             request_open,
                             response_open: Instant::now(),
                         })
                     }
                 });
 
+                // The request remains in flight while its response body is
+                // streamed.
+                let in_flight = self.in_flight.take();
+                let in_flight = if rsp.body().is_end_stream() { None } else { in_flight };
+                let spans = self.spans.take().and_then(|mut spans| {
+                    spans.responded();
+                    if rsp.body().is_end_stream() { None } else { Some(spans) }
+                });
                 let rsp = {
                     let (parts, body) = rsp.into_parts();
-                    let body = ResponseBody::new(body, inner);
+                    let mut body = ResponseBody::new(body, inner);
+                    body.in_flight = in_flight;
+                    body._spans = spans;
+                    body.inflate = inflate;
                     http::Response::from_parts(parts, body)
                 };
 
@@ -360,12 +594,16 @@ where
             }
 
             Err(e) => {
-                if let Some(error) = e.reason() {
-                    if let Some(i) = self.inner.take() {
+                self.in_flight = None;
+                self.spans = None;
+                if let Some(i) = self.inner.take() {
+                    if let Some(error) = e.reason() {
                         let RespondInner {
                             ctx,
                             mut handle,
+                            request_bytes,
                             request_open,
+                            ..
                         } = i;
 
                         handle.send(|| {
@@ -374,6 +612,7 @@ where
                                 event::StreamRequestFail {
                                     error,
                                     since_request_open: request_open.elapsed(),
+                                    request_bytes: request_bytes.get(),
                                 },
                             )
                         });
@@ -386,6 +625,26 @@ where
     }
 }
 
+impl<F, B> Drop for Respond<F, B> {
+    fn drop(&mut self) {
+        // If the response future is dropped before it completes, e.g. because
+        // a hedged request was answered first, the request is canceled.
+        if let Some(inner) = self.inner.take() {
+            let RespondInner { ctx, mut handle, request_bytes, request_open, .. } = inner;
+            handle.send(|| {
+                Event::StreamRequestFail(
+                    Arc::clone(&ctx),
+                    event::StreamRequestFail {
+                        error: h2::Reason::CANCEL,
+                        since_request_open: request_open.elapsed(),
+                        request_bytes: request_bytes.get(),
+                    },
+                )
+            });
+        }
+    }
+}
+
 // === MeasuredBody ===
 
 impl<B, I: BodySensor> MeasuredBody<B, I> {
@@ -393,6 +652,9 @@ impl<B, I: BodySensor> MeasuredBody<B, I> {
         Self {
             body,
             inner,
+            in_flight: None,
+            _spans: None,
+            inflate: None,
             _p: PhantomData,
         }
     }
@@ -408,6 +670,8 @@ impl<B, I: BodySensor> MeasuredBody<B, I> {
         match op(&mut self.body) {
             Ok(v) => Ok(v),
             Err(e) => {
+                self.in_flight = None;
+                self._spans = None;
                 if let Some(error) = e.reason() {
                     if let Some(i) = self.inner.take() {
                         i.fail(error);
@@ -420,29 +684,101 @@ impl<B, I: BodySensor> MeasuredBody<B, I> {
     }
 }
 
+impl<B, I: BodySensor> Drop for MeasuredBody<B, I> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.cancel();
+        }
+    }
+}
+
 impl<B, I> Body for MeasuredBody<B, I>
 where
     B: Body + 'static,
     I: BodySensor,
 {
     /// The body chunk type
-    type Data = <B::Data as IntoBuf>::Buf;
+    type Data = decompress::Data<<B::Data as IntoBuf>::Buf>;
 
     fn is_end_stream(&self) -> bool {
-        self.body.is_end_stream()
+        match self.inflate {
+            // The rest of the decompressed body must still be sent.
+            Some(ref inflate) if inflate.is_forwarded() && !inflate.is_finished() => false,
+            _ => self.body.is_end_stream(),
+        }
     }
 
     fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
-        let frame = try_ready!(self.sense_err(|b| b.poll_data()));
-        let frame = frame.map(|frame| {
-            let frame = frame.into_buf();
+        loop {
+            let (inflating, forward) = match self.inflate {
+                Some(ref inflate) => (!inflate.is_finished(), inflate.is_forwarded()),
+                None => (false, false),
+            };
+            // Once a forwarded body has been decompressed, all of it's been
+            // sent.
+            let frame = if forward && !inflating {
+                None
+            } else {
+                try_ready!(self.sense_err(|b| b.poll_data())).map(IntoBuf::into_buf)
+            };
+            let end = frame.is_none() || self.body.is_end_stream();
+
+            let decompressed = match self.inflate {
+                Some(ref mut inflate) if inflating => Some(decompress_frame(inflate, &frame, end)),
+                _ => None,
+            };
+            let decompressed = match decompressed {
+                Some(Err(e)) => {
+                    warn!("failed to decompress response body: {}", e);
+                    self.inflate = None;
+                    self.in_flight = None;
+                    self._spans = None;
+                    if let Some(i) = self.inner.take() {
+                        i.fail(h2::Reason::INTERNAL_ERROR);
+                    }
+                    return Err(h2::Reason::INTERNAL_ERROR.into());
+                }
+                Some(Ok(data)) => Some(data),
+                None => None,
+            };
+
             if let Some(ref mut inner) = self.inner {
-                *inner.frames_sent() += 1;
-                *inner.bytes_sent() += frame.remaining() as u64;
+                if let Some(ref frame) = frame {
+                    inner.data_sent(frame);
+                }
+                match (&decompressed, &frame) {
+                    (&Some(ref data), _) => inner.inspect(data),
+                    // Body data is contiguous, so its first chunk is all of it.
+                    (&None, &Some(ref frame)) => inner.inspect(frame.bytes()),
+                    (&None, &None) => {}
+                }
             }
-            frame
-        });
-        Ok(Async::Ready(frame))
+
+            // A body that ends without trailers may not have them polled, and
+            // mustn't be considered canceled when it's dropped.
+            if self.body.is_end_stream() {
+                self.in_flight = None;
+                self._spans = None;
+                if let Some(i) = self.inner.take() {
+                    i.end(None);
+                }
+            }
+
+            if !forward {
+                return Ok(Async::Ready(frame.map(decompress::Data::Identity)));
+            }
+            match decompressed {
+                // Frames that only decompress into the decoder's buffer
+                // aren't sent.
+                Some(ref data) if data.is_empty() && !end => continue,
+                Some(ref data) if data.is_empty() => return Ok(Async::Ready(None)),
+                Some(data) => {
+                    let data = decompress::Data::Decompressed(Cursor::new(data));
+                    return Ok(Async::Ready(Some(data)));
+                }
+                None => return Ok(Async::Ready(None)),
+            }
+        }
     }
 
     fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
@@ -450,6 +786,8 @@ where
             Err(e) => Err(e),
             Ok(Async::NotReady) => Ok(Async::NotReady),
             Ok(Async::Ready(trls)) => {
+                self.in_flight = None;
+                self._spans = None;
                 if let Some(i) = self.inner.take() {
                     let grpc_status = trls.as_ref()
                         .and_then(|t| t.get(GRPC_STATUS))
@@ -473,6 +811,9 @@ where
         Self {
             body: B::default(),
             inner: None,
+            in_flight: None,
+            _spans: None,
+            inflate: None,
             _p: PhantomData,
         }
     }
@@ -504,6 +845,7 @@ impl BodySensor for ResponseBodyInner {
             response_open,
             bytes_sent,
             frames_sent,
+            request_bytes,
             ..
         } = self;
 
@@ -516,6 +858,7 @@ impl BodySensor for ResponseBodyInner {
                     since_response_open: response_open.elapsed(),
                     bytes_sent,
                     frames_sent,
+                    request_bytes: request_bytes.get(),
                 },
             )
         });
@@ -525,32 +868,55 @@ impl BodySensor for ResponseBodyInner {
         let ResponseBodyInner {
             ctx,
             mut handle,
+            classifier,
+            injected,
             request_open,
             response_open,
             bytes_sent,
             frames_sent,
+            grpc_messages,
+            request_bytes,
+            ..
         } = self;
 
+        let classification = classify(&classifier, injected, ctx.status, grpc_status);
+        let grpc_stream = grpc_messages.and_then(|m| m.stream(request_open));
         handle.send(||
             event::Event::StreamResponseEnd(
                 Arc::clone(&ctx),
                 event::StreamResponseEnd {
                     grpc_status,
+                    classification,
                     since_request_open: request_open.elapsed(),
                     since_response_open: response_open.elapsed(),
                     bytes_sent,
                     frames_sent,
+                    request_bytes: request_bytes.get(),
+                    grpc_stream,
                 },
             )
         )
     }
 
-    fn frames_sent(&mut self) -> &mut u32 {
-        &mut self.frames_sent
+    /// A response that is dropped before it ends is canceled, as when the
+    /// client disconnects.
+    fn cancel(self) {
+        self.fail(h2::Reason::CANCEL);
     }
 
-    fn bytes_sent(&mut self) -> &mut u64 {
-        &mut self.bytes_sent
+    fn data_sent<D: Buf>(&mut self, data: &D) {
+        let bytes = data.remaining();
+        self.frames_sent += 1;
+        self.bytes_sent += bytes as u64;
+        if let Some(ref authority) = self.authority_bytes {
+            authority.received(bytes);
+        }
+    }
+
+    fn inspect(&mut self, data: &[u8]) {
+        if let Some(ref mut messages) = self.grpc_messages {
+            messages.received(data, Instant::now());
+        }
     }
 }
 
@@ -560,6 +926,7 @@ impl BodySensor for RequestBodyInner {
         let RequestBodyInner {
             ctx,
             mut handle,
+            bytes_sent,
             request_open,
             ..
         } = self;
@@ -570,6 +937,7 @@ impl BodySensor for RequestBodyInner {
                 event::StreamRequestFail {
                     error,
                     since_request_open: request_open.elapsed(),
+                    request_bytes: bytes_sent.get(),
                 },
             )
         )
@@ -593,18 +961,31 @@ impl BodySensor for RequestBodyInner {
         )
     }
 
-    fn frames_sent(&mut self) -> &mut u32 {
-        &mut self.frames_sent
-    }
+    /// Upstreams may respond without reading their requests' bodies, so a
+    /// request body that is dropped isn't necessarily canceled.
+    fn cancel(self) {}
 
-    fn bytes_sent(&mut self) -> &mut u64 {
-        &mut self.bytes_sent
+    fn data_sent<D: Buf>(&mut self, data: &D) {
+        let bytes = data.remaining();
+        self.frames_sent += 1;
+        self.bytes_sent.add(bytes);
+        if let Some(ref authority) = self.authority_bytes {
+            authority.sent(bytes);
+        }
     }
 }
 
 impl<S> TimestampRequestOpen<S> {
     pub fn new(inner: S) -> Self {
-        Self { inner }
+        Self { inner, attempts: None }
+    }
+
+    /// Counts the attempts made to send each request in `attempts`.
+    pub fn with_attempts(self, attempts: RequestAttempts) -> Self {
+        Self {
+            attempts: Some(attempts),
+            ..self
+        }
     }
 }
 
@@ -624,6 +1005,12 @@ where
     fn call(&mut self, mut req: Self::Request) -> Self::Future {
         let request_open = Instant::now();
         req.extensions_mut().insert(RequestOpen(request_open));
+        if let Some(spans) = spans::Request::open() {
+            req.extensions_mut().insert(spans);
+        }
+        if let Some(ref attempts) = self.attempts {
+            req.extensions_mut().insert(attempts.start());
+        }
         self.inner.call(req)
     }
 }
@@ -636,13 +1023,1181 @@ where
     type Response = S::Response;
     type Error = S::Error;
     type InitError = S::InitError;
-    type Future = future::Map<
-        S::Future,
-        fn(S::Service) -> Self::Service
-    >;
+    type Future = TimestampInit<S::Future>;
     type Service = TimestampRequestOpen<S::Service>;
 
     fn new_service(&self) -> Self::Future {
-        self.inner.new_service().map(TimestampRequestOpen::new)
+        TimestampInit {
+            future: self.inner.new_service(),
+            attempts: self.attempts.clone(),
+        }
+    }
+}
+
+impl<F: Future> Future for TimestampInit<F> {
+    type Item = TimestampRequestOpen<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll());
+        let attempts = self.attempts.clone();
+        Ok(Async::Ready(TimestampRequestOpen { inner, attempts }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    use conduit_proxy_controller_grpc::common::Protocol;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use futures::{future, Future, Poll, Stream};
+    use futures::future::FutureResult;
+    use futures::sync::oneshot;
+    use futures_mpsc_lossy;
+    use http;
+    use tokio_core::reactor::Core;
+    use tower::Service;
+    use tower_h2::client;
+
+    use ctx;
+    use fault::{Fault, FaultConfig};
+    use telemetry::classify::Classification;
+    use telemetry::event::Event;
+    use telemetry::sensor::Decompress;
+    use telemetry::sensor::in_flight::InFlight;
+    use telemetry::sensor::request_id::{RequestIdGen, Sequential};
+    use super::*;
+
+    /// An upstream that records the headers it receives, and responds with
+    /// a `set-cookie` header.
+    struct Upstream(Rc<RefCell<Option<http::HeaderMap>>>);
+
+    impl Service for Upstream {
+        type Request = http::Request<RequestBody<()>>;
+        type Response = http::Response<()>;
+        type Error = client::Error;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            *self.0.borrow_mut() = Some(req.headers().clone());
+            let mut rsp = http::Response::new(());
+            rsp.headers_mut().insert("set-cookie", "session=secret".parse().unwrap());
+            future::ok(rsp)
+        }
+    }
+
+    #[test]
+    fn redacts_telemetry_headers_but_not_proxied_headers() {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+        let received = Rc::new(RefCell::new(None));
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+
+        // Names are matched regardless of the case in which they were
+        // configured or sent.
+        let redact = vec![
+            http::header::HeaderName::from_bytes(b"Authorization").unwrap(),
+            http::header::HeaderName::from_bytes(b"SET-COOKIE").unwrap(),
+        ];
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Upstream(received.clone()),
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::new(SensorConfig::default().with_redacted_headers(redact))
+                .cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        let mut req = http::Request::new(());
+        req.headers_mut().insert("AUTHORIZATION", "Bearer secret".parse().unwrap());
+        req.headers_mut().insert("x-request-id", "1234".parse().unwrap());
+        req.extensions_mut().insert(server);
+        req.extensions_mut().insert(RequestOpen(Instant::now()));
+
+        let rsp = svc.call(req).wait().expect("response");
+        drop(svc);
+
+        // The real values are proxied in both directions.
+        let received = received.borrow_mut().take().expect("upstream request");
+        assert_eq!(received["authorization"], "Bearer secret");
+        assert_eq!(rsp.headers()["set-cookie"], "session=secret");
+        drop(rsp);
+
+        let events = rx.collect().wait().expect("events");
+        let req_ctx = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamRequestOpen(ref req) => Some(req.clone()),
+                _ => None,
+            })
+            .next()
+            .expect("request open event");
+        assert_eq!(req_ctx.headers["authorization"], REDACTED);
+        assert_eq!(req_ctx.headers["x-request-id"], "1234");
+
+        let rsp_ctx = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamResponseOpen(ref rsp, _) => Some(rsp.clone()),
+                _ => None,
+            })
+            .next()
+            .expect("response open event");
+        assert_eq!(rsp_ctx.headers["set-cookie"], REDACTED);
+    }
+
+    /// An upstream that never responds.
+    struct Unresponsive;
+
+    impl Service for Unresponsive {
+        type Request = http::Request<RequestBody<()>>;
+        type Response = http::Response<()>;
+        type Error = client::Error;
+        type Future = future::Empty<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    /// Assigns IDs counting down from the given ID.
+    #[derive(Debug)]
+    struct Countdown(AtomicUsize);
+
+    impl RequestIdGen for Countdown {
+        fn next_id(&self) -> u64 {
+            self.0.fetch_sub(1, Ordering::SeqCst) as u64
+        }
+    }
+
+    #[test]
+    fn assigns_request_ids_from_generator() {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+        let received = Rc::new(RefCell::new(None));
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Countdown(AtomicUsize::new(10))),
+            service: Upstream(received.clone()),
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: Some(http::header::HeaderName::from_static("x-request-id")),
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(server.clone());
+        req.extensions_mut().insert(RequestOpen(Instant::now()));
+        drop(svc.call(req).wait().expect("response"));
+        let sent = received.borrow_mut().take().expect("upstream request");
+        assert_eq!(sent["x-request-id"], "10");
+
+        // An ID assigned by a previous hop is forwarded unchanged.
+        let mut req = http::Request::new(());
+        req.headers_mut().insert("x-request-id", "abc".parse().unwrap());
+        req.extensions_mut().insert(server);
+        req.extensions_mut().insert(RequestOpen(Instant::now()));
+        drop(svc.call(req).wait().expect("response"));
+        let sent = received.borrow_mut().take().expect("upstream request");
+        assert_eq!(sent["x-request-id"], "abc");
+        drop(svc);
+
+        let events = rx.collect().wait().expect("events");
+        let ids = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamRequestOpen(ref req) => Some(req.id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![10, 9]);
+    }
+
+    #[test]
+    fn dropped_requests_are_recorded_as_canceled() {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Unresponsive,
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(server);
+        req.extensions_mut().insert(RequestOpen(Instant::now()));
+        drop(svc.call(req));
+        drop(svc);
+
+        let events = rx.collect().wait().expect("events");
+        let failure = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamRequestFail(_, ref fail) => Some(fail.error),
+                _ => None,
+            })
+            .next();
+        assert_eq!(failure, Some(h2::Reason::CANCEL));
+        assert!(
+            !events.iter().any(|ev| match *ev {
+                Event::StreamResponseOpen(..) => true,
+                _ => false,
+            }),
+            "no response was received"
+        );
+    }
+
+    #[test]
+    fn requests_are_mirrored_into_spans() {
+        use telemetry::sensor::spans::recorder::Recorder;
+
+        fn sensor<S>(
+            service: S,
+            req_ids: &SharedRequestIdGen,
+            client_ctx: &Arc<ctx::transport::Client>,
+        ) -> TimestampRequestOpen<Http<S, (), ()>> {
+            TimestampRequestOpen::new(Http {
+                req_ids: req_ids.clone(),
+                service,
+                handle: super::super::Handle {
+                    tx: None,
+                    events: Default::default(),
+                    byte_counts: Default::default(),
+                    terminations: Default::default(),
+                },
+                config: SharedConfig::default().cached(),
+                trace: None,
+                request_id_header: None,
+                client_ctx: client_ctx.clone(),
+                in_flight: InFlight::default().endpoint(&client_ctx.remote),
+                _p: PhantomData,
+            })
+        }
+
+        let recorder = Recorder::default();
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let req_ids: SharedRequestIdGen = Arc::new(Sequential::default());
+        let request = || {
+            let mut req = http::Request::new(());
+            req.extensions_mut().insert(server.clone());
+            req
+        };
+
+        recorder.record(|| {
+            let mut svc = sensor(Upstream(Rc::new(RefCell::new(None))), &req_ids, &client_ctx);
+            drop(svc.call(request()).wait().expect("response"));
+
+            // The spans of canceled requests are closed too.
+            let mut svc = sensor(Unresponsive, &req_ids, &client_ctx);
+            let mut canceled = svc.call(request());
+            assert!(canceled.poll().expect("poll").is_not_ready());
+            drop(canceled);
+        });
+
+        let spans = recorder.spans();
+        let (requests, upstreams) = (recorder.named("request"), recorder.named("upstream"));
+        assert_eq!(requests.len(), 2);
+        assert_eq!(upstreams.len(), 2);
+        for (id, (&request, &upstream)) in requests.iter().zip(&upstreams).enumerate() {
+            assert_eq!(spans[request].parent, None);
+            assert_eq!(spans[upstream].parent, Some(request));
+            for &span in &[request, upstream] {
+                assert_eq!(spans[span].fields["request_id"], id.to_string());
+                assert_eq!(spans[span].fields["endpoint"], "10.1.1.1:8080");
+            }
+        }
+        let waits = recorder.named("buffer_wait");
+        assert_eq!(waits.len(), 2);
+        assert!(waits.iter().all(|&wait| requests.contains(&spans[wait].parent.unwrap())));
+        assert!(spans.iter().all(|span| span.closed), "{:?}", spans);
+    }
+
+    /// An upstream whose responses are sent through `oneshot` channels. A
+    /// request fails if its sender is dropped.
+    struct Pending(Rc<RefCell<Vec<oneshot::Sender<http::Response<Streaming>>>>>);
+
+    /// A response body that is complete only once its trailers are read.
+    #[derive(Debug, Default)]
+    struct Streaming;
+
+    impl Service for Pending {
+        type Request = http::Request<RequestBody<()>>;
+        type Response = http::Response<Streaming>;
+        type Error = client::Error;
+        type Future = Box<Future<Item = Self::Response, Error = client::Error>>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let (tx, rx) = oneshot::channel();
+            self.0.borrow_mut().push(tx);
+            Box::new(rx.map_err(|_| -> client::Error { h2::Reason::REFUSED_STREAM.into() }))
+        }
+    }
+
+    impl Body for Streaming {
+        type Data = &'static [u8];
+
+        fn is_end_stream(&self) -> bool {
+            false
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    #[test]
+    fn dropped_responses_cancel_upstream_requests() {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+        let responders = Rc::new(RefCell::new(Vec::new()));
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Pending(responders.clone()),
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+        let request = || {
+            let mut req = http::Request::new(());
+            req.extensions_mut().insert(server.clone());
+            req.extensions_mut().insert(RequestOpen(Instant::now()));
+            req
+        };
+
+        // Dropping a response future, as when the client disconnects,
+        // cancels the upstream request.
+        drop(svc.call(request()));
+        assert!(responders.borrow()[0].is_canceled());
+
+        // So does dropping a response body before it ends.
+        let streamed = svc.call(request());
+        let _ = responders.borrow_mut().remove(1).send(http::Response::new(Streaming));
+        drop(streamed.wait().expect("response").into_body());
+        drop(svc);
+
+        let events = rx.collect().wait().expect("events");
+        let canceled = events.iter()
+            .filter(|ev| match **ev {
+                Event::StreamRequestFail(_, ref fail) => fail.error == h2::Reason::CANCEL,
+                Event::StreamResponseFail(_, ref fail) => fail.error == h2::Reason::CANCEL,
+                _ => false,
+            })
+            .count();
+        assert_eq!(canceled, 2);
+        assert!(!events.iter().any(|ev| match *ev {
+            Event::StreamResponseEnd(..) => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn counts_requests_in_flight_until_responses_end() {
+        let in_flight = InFlight::default();
+        let responders = Rc::new(RefCell::new(Vec::new()));
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Pending(responders.clone()),
+            handle: super::super::Handle {
+                tx: None,
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: in_flight.endpoint(&addr),
+            _p: PhantomData,
+        };
+        let in_flight_to = |addr: &SocketAddr| in_flight.snapshot()[addr];
+
+        let streamed = svc.call(http::Request::new(()));
+        let canceled = svc.call(http::Request::new(()));
+        let failed = svc.call(http::Request::new(()));
+        assert_eq!(in_flight_to(&addr), 3);
+
+        // A request is no longer in flight once it's canceled...
+        drop(canceled);
+        assert_eq!(in_flight_to(&addr), 2);
+
+        // ...or once it fails...
+        drop(responders.borrow_mut().pop());
+        assert!(failed.wait().is_err());
+        assert_eq!(in_flight_to(&addr), 1);
+
+        // ...but a request whose response is streaming remains in flight
+        // until the response ends.
+        let _ = responders.borrow_mut().remove(0).send(http::Response::new(Streaming));
+        let mut body = streamed.wait().expect("response").into_body();
+        assert_eq!(in_flight_to(&addr), 1);
+        assert!(body.poll_data().unwrap().is_ready());
+        assert_eq!(in_flight_to(&addr), 1);
+        assert!(body.poll_trailers().unwrap().is_ready());
+        assert_eq!(in_flight_to(&addr), 0);
+    }
+
+    /// An upstream that responds with each of the given statuses in turn,
+    /// followed by a `grpc-status` trailer if one is given.
+    struct Responds(Vec<(u16, Option<&'static str>)>);
+
+    /// A response body that consists only of trailers.
+    #[derive(Debug, Default)]
+    struct Trailers(Option<http::HeaderMap>);
+
+    impl Service for Responds {
+        type Request = http::Request<RequestBody<()>>;
+        type Response = http::Response<Trailers>;
+        type Error = client::Error;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let (status, grpc_status) = self.0.remove(0);
+            let trailers = grpc_status.map(|code| {
+                let mut trailers = http::HeaderMap::new();
+                trailers.insert(GRPC_STATUS, code.parse().unwrap());
+                trailers
+            });
+            let mut rsp = http::Response::new(Trailers(trailers));
+            *rsp.status_mut() = http::StatusCode::from_u16(status).unwrap();
+            future::ok(rsp)
+        }
+    }
+
+    impl Body for Trailers {
+        type Data = &'static [u8];
+
+        fn is_end_stream(&self) -> bool {
+            false
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.0.take()))
+        }
+    }
+
+    #[test]
+    fn classifies_completed_responses() {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Responds(vec![
+                (200, None),
+                (503, None),
+                // gRPC errors are sent with a 200 status.
+                (200, Some("14")),
+                (200, Some("0")),
+            ]),
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        for _ in 0..4 {
+            let mut req = http::Request::new(());
+            req.extensions_mut().insert(server.clone());
+            req.extensions_mut().insert(RequestOpen(Instant::now()));
+            let mut body = svc.call(req).wait().expect("response").into_body();
+            assert!(body.poll_data().unwrap().is_ready());
+            assert!(body.poll_trailers().unwrap().is_ready());
+        }
+        drop(svc);
+
+        let events = rx.collect().wait().expect("events");
+        let classifications = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamResponseEnd(_, ref end) => Some(end.classification),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(classifications, vec![
+            Classification::Success,
+            Classification::Failure,
+            Classification::Failure,
+            Classification::Success,
+        ]);
+    }
+
+    #[test]
+    fn classifies_injected_responses() {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+
+        let core = Core::new().unwrap();
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        // Every other request is aborted with a 503, as the endpoint
+        // responds to the rest.
+        let faults = FaultConfig::default().with_abort(50, http::StatusCode::SERVICE_UNAVAILABLE);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Fault::new(
+                Responds(vec![(503, None), (503, None)]),
+                Some(faults),
+                &core.handle(),
+            ),
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        for _ in 0..4 {
+            let mut req = http::Request::new(());
+            req.extensions_mut().insert(server.clone());
+            req.extensions_mut().insert(RequestOpen(Instant::now()));
+            let mut body = svc.call(req).wait().expect("response").into_body();
+            assert!(body.poll_data().unwrap().is_ready());
+            assert!(body.poll_trailers().unwrap().is_ready());
+        }
+        drop(svc);
+
+        let events = rx.collect().wait().expect("events");
+        let classifications = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamResponseEnd(_, ref end) => Some(end.classification),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(classifications, vec![
+            Classification::Failure,
+            Classification::Injected,
+            Classification::Failure,
+            Classification::Injected,
+        ]);
+    }
+
+    /// An upstream that responds to each request with a gRPC response of
+    /// the given data frames, followed by an `OK` status.
+    struct Grpc(Vec<Vec<&'static [u8]>>);
+
+    /// A gRPC response body.
+    #[derive(Debug, Default)]
+    struct GrpcBody(Vec<&'static [u8]>);
+
+    impl Service for Grpc {
+        type Request = http::Request<RequestBody<()>>;
+        type Response = http::Response<GrpcBody>;
+        type Error = client::Error;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let mut rsp = http::Response::new(GrpcBody(self.0.remove(0)));
+            rsp.headers_mut().insert("content-type", "application/grpc".parse().unwrap());
+            future::ok(rsp)
+        }
+    }
+
+    impl Body for GrpcBody {
+        type Data = &'static [u8];
+
+        fn is_end_stream(&self) -> bool {
+            false
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+            if self.0.is_empty() {
+                return Ok(Async::Ready(None));
+            }
+            Ok(Async::Ready(Some(self.0.remove(0))))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert(GRPC_STATUS, "0".parse().unwrap());
+            Ok(Async::Ready(Some(trailers)))
+        }
+    }
+
+    #[test]
+    fn measures_streaming_grpc_responses_by_message() {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Grpc(vec![
+                // Three messages, the second split across frames.
+                vec![&b"\0\0\0\0\x02hi\0\0\0"[..], &b"\0\x01!\0\0\0\0\0"[..]],
+                // A single message.
+                vec![&b"\0\0\0\0\x02hi"[..]],
+            ]),
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        for _ in 0..2 {
+            let mut req = http::Request::new(());
+            req.extensions_mut().insert(server.clone());
+            req.extensions_mut().insert(RequestOpen(Instant::now()));
+            let mut body = svc.call(req).wait().expect("response").into_body();
+            while let Async::Ready(Some(_)) = body.poll_data().expect("response body") {}
+            assert!(body.poll_trailers().expect("response trailers").is_ready());
+        }
+        drop(svc);
+
+        let events = rx.collect().wait().expect("events");
+        let ends = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamResponseEnd(_, ref end) => Some(end.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ends.len(), 2);
+
+        let stream = ends[0].grpc_stream.as_ref().expect("streaming response");
+        assert_eq!(stream.messages, 3);
+        assert!(stream.first_message_since_request_open <= ends[0].since_request_open);
+        assert_eq!(ends[0].grpc_status, Some(0));
+
+        assert!(ends[1].grpc_stream.is_none(), "unary response");
+        assert_eq!(ends[1].grpc_status, Some(0));
+    }
+
+    /// An upstream that responds with a gRPC response that is compressed with
+    /// gzip, sent in the given frames.
+    struct Gzipped(Vec<Bytes>);
+
+    /// A response body of the given frames, followed by an `OK` status.
+    #[derive(Debug, Default)]
+    struct Frames(Vec<Bytes>);
+
+    impl Service for Gzipped {
+        type Request = http::Request<RequestBody<()>>;
+        type Response = http::Response<Frames>;
+        type Error = client::Error;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let mut rsp = http::Response::new(Frames(self.0.clone()));
+            rsp.headers_mut().insert("content-type", "application/grpc".parse().unwrap());
+            rsp.headers_mut().insert("content-encoding", "gzip".parse().unwrap());
+            future::ok(rsp)
+        }
+    }
+
+    impl Body for Frames {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            false
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+            if self.0.is_empty() {
+                return Ok(Async::Ready(None));
+            }
+            Ok(Async::Ready(Some(self.0.remove(0))))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert(GRPC_STATUS, "0".parse().unwrap());
+            Ok(Async::Ready(Some(trailers)))
+        }
+    }
+
+    /// Proxies a request to `Gzipped(frames)`, decompressing its response as
+    /// `config` configures, and returns the response's headers, the body
+    /// that the client receives, and the events that describe the response.
+    fn proxy_gzipped(
+        config: SensorConfig,
+        frames: Vec<Bytes>,
+    ) -> (http::HeaderMap, Result<Vec<u8>, h2::Error>, Vec<Event>) {
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Gzipped(frames),
+            handle: super::super::Handle {
+                tx: Some(tx),
+                events: Default::default(),
+                byte_counts: Default::default(),
+                terminations: Default::default(),
+            },
+            config: SharedConfig::new(config).cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(server);
+        req.extensions_mut().insert(RequestOpen(Instant::now()));
+        let (parts, mut body) = svc.call(req).wait().expect("response").into_parts();
+        let mut data = Vec::new();
+        let read = loop {
+            match body.poll_data() {
+                Ok(Async::Ready(Some(chunk))) => data.extend_from_slice(chunk.bytes()),
+                Ok(Async::Ready(None)) => break body.poll_trailers().map(|_| ()),
+                Ok(Async::NotReady) => panic!("body should be ready"),
+                Err(e) => break Err(e),
+            }
+        };
+        drop((svc, body));
+
+        let events = rx.collect().wait().expect("events");
+        (parts.headers, read.map(|()| data), events)
+    }
+
+    #[test]
+    fn decompresses_gzipped_responses_for_inspection() {
+        // Three messages, which are compressed into frames that don't line
+        // up with them.
+        let messages = &b"\0\0\0\0\x02hi\0\0\0\0\x01!\0\0\0\0\0"[..];
+        let compressed = {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(messages).unwrap();
+            encoder.finish().unwrap()
+        };
+        let frames = compressed.chunks(4).map(Bytes::from).collect::<Vec<_>>();
+        let config = |decompress| SensorConfig::default().with_decompression(decompress);
+        let grpc_messages = |events: &[Event]| {
+            events.iter()
+                .filter_map(|ev| match *ev {
+                    Event::StreamResponseEnd(_, ref end) => {
+                        Some(end.grpc_stream.as_ref().map(|stream| stream.messages))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (headers, body, _) = proxy_gzipped(config(Decompress::Off), frames.clone());
+        assert_eq!(headers.get("content-encoding").unwrap(), "gzip");
+        assert_eq!(body.expect("body"), compressed);
+
+        let (headers, body, events) = proxy_gzipped(config(Decompress::Inspect), frames.clone());
+        assert_eq!(headers.get("content-encoding").unwrap(), "gzip");
+        assert_eq!(body.expect("body"), compressed);
+        assert_eq!(grpc_messages(&events), vec![Some(3)]);
+
+        let (headers, body, events) = proxy_gzipped(config(Decompress::Forward), frames.clone());
+        assert!(headers.get("content-encoding").is_none());
+        assert_eq!(body.expect("body"), messages);
+        assert_eq!(grpc_messages(&events), vec![Some(3)]);
+
+        // Truncated responses, and responses that decompress past the
+        // maximum, fail rather than ending.
+        let truncated = frames[..frames.len() - 1].to_vec();
+        let bomb = {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&vec![0; 1024 * 1024]).unwrap();
+            vec![Bytes::from(encoder.finish().unwrap())]
+        };
+        for &decompress in &[Decompress::Inspect, Decompress::Forward] {
+            let cases = vec![
+                (config(decompress), truncated.clone()),
+                (config(decompress).with_max_decompressed_bytes(64 * 1024), bomb.clone()),
+            ];
+            for (config, frames) in cases {
+                let (_, body, events) = proxy_gzipped(config, frames);
+                assert_eq!(body.unwrap_err().reason(), Some(h2::Reason::INTERNAL_ERROR));
+                assert!(grpc_messages(&events).is_empty());
+                let failed = events.iter().any(|ev| match *ev {
+                    Event::StreamResponseFail(_, ref fail) => {
+                        fail.error == h2::Reason::INTERNAL_ERROR
+                    }
+                    _ => false,
+                });
+                assert!(failed, "{:?}: {:?}", decompress, events);
+            }
+        }
+    }
+
+    #[test]
+    fn publishes_request_lifecycle_events() {
+        use telemetry::events::{LifecycleEvent, RequestEvent};
+
+        let sensors = super::super::Sensors::null();
+        let mut events = sensors.subscribe(10);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Countdown(AtomicUsize::new(7))),
+            service: Upstream(Rc::new(RefCell::new(None))),
+            handle: sensors.handle.clone(),
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        let mut req = http::Request::new(());
+        *req.uri_mut() = "http://example.com/path".parse().unwrap();
+        req.extensions_mut().insert(server);
+        req.extensions_mut().insert(RequestOpen(Instant::now()));
+        let rsp = svc.call(req).wait().expect("response");
+        drop(rsp);
+
+        let (started, ended) = future::lazy(|| {
+            let started = events.poll().map(|a| a.map(Option::unwrap))?;
+            let ended = events.poll().map(|a| a.map(Option::unwrap))?;
+            assert!(events.poll()?.is_not_ready(), "no further events");
+            Ok::<_, ()>((started, ended))
+        }).wait().unwrap();
+
+        match started {
+            Async::Ready(LifecycleEvent::Request(RequestEvent::Started(ref req))) => {
+                assert_eq!(req.id, 7);
+                assert_eq!(req.uri, "http://example.com/path");
+                assert_eq!(req.method, http::Method::GET);
+            }
+            ev => panic!("expected a request start event: {:?}", ev),
+        }
+        match ended {
+            Async::Ready(LifecycleEvent::Request(RequestEvent::Ended {
+                ref request, status, error, ..
+            })) => {
+                assert_eq!(request.id, 7);
+                assert_eq!(status, Some(http::StatusCode::OK));
+                assert_eq!(error, None);
+            }
+            ev => panic!("expected a request end event: {:?}", ev),
+        }
+    }
+
+    #[test]
+    fn propagates_trace_context_recorded_in_telemetry() {
+        use telemetry::events::{LifecycleEvent, RequestEvent};
+
+        let sensors = super::super::Sensors::null();
+        let mut events = sensors.subscribe(10);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let received = Rc::new(RefCell::new(None));
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Upstream(received.clone()),
+            handle: sensors.handle.clone(),
+            config: SharedConfig::default().cached(),
+            trace: Some(trace::Propagation::W3c),
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(server);
+        req.extensions_mut().insert(RequestOpen(Instant::now()));
+        drop(svc.call(req).wait().expect("response"));
+
+        let span = future::lazy(|| match events.poll()? {
+            Async::Ready(Some(LifecycleEvent::Request(RequestEvent::Started(ref req)))) => {
+                Ok::<_, ()>(req.span.clone().expect("request span"))
+            }
+            ev => panic!("expected a request start event: {:?}", ev),
+        }).wait().unwrap();
+
+        // The span recorded in telemetry is the one that was sent upstream.
+        let received = received.borrow_mut().take().expect("upstream request");
+        let traceparent = format!("00-{}-{:016x}-01", span.trace_id, span.span_id);
+        assert_eq!(received["traceparent"], traceparent.as_str());
+        assert_eq!(span.parent_id, None);
+    }
+
+    /// A body that consists of the given data frames.
+    #[derive(Debug, Default)]
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Body for Chunks {
+        type Data = &'static [u8];
+
+        fn is_end_stream(&self) -> bool {
+            false
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+            if self.0.is_empty() {
+                return Ok(Async::Ready(None));
+            }
+            Ok(Async::Ready(Some(self.0.remove(0))))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    /// An upstream that reads each request body before responding with
+    /// `hello world`.
+    struct Drains;
+
+    impl Service for Drains {
+        type Request = http::Request<RequestBody<Chunks>>;
+        type Response = http::Response<Chunks>;
+        type Error = client::Error;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            let mut body = req.into_body();
+            while let Async::Ready(Some(_)) = body.poll_data().expect("request body") {}
+            assert!(body.poll_trailers().expect("request trailers").is_ready());
+            future::ok(http::Response::new(Chunks(vec![&b"hello"[..], b" ", b"world"])))
+        }
+    }
+
+    #[test]
+    fn counts_body_bytes_by_authority() {
+        use telemetry::sensor::{Authority, Bytes, Direction};
+
+        let sensors = super::super::Sensors::null();
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Drains,
+            handle: sensors.handle.clone(),
+            config: SharedConfig::default().cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        for _ in 0..2 {
+            let mut req = http::Request::new(Chunks(vec![&b"ab"[..], b"cde"]));
+            *req.uri_mut() = "http://example.com/path".parse().unwrap();
+            req.extensions_mut().insert(server.clone());
+            req.extensions_mut().insert(RequestOpen(Instant::now()));
+            let mut body = svc.call(req).wait().expect("response").into_body();
+            while let Async::Ready(Some(_)) = body.poll_data().expect("response body") {}
+            assert!(body.poll_trailers().expect("response trailers").is_ready());
+        }
+
+        let authority = Authority {
+            direction: Direction::Outbound,
+            authority: "example.com".to_owned(),
+        };
+        let bytes = sensors.byte_counts().authorities()[&authority];
+        assert_eq!(bytes, Bytes { rx: 22, tx: 10 });
+    }
+
+    #[test]
+    fn reconfigures_redaction_without_resetting_counters() {
+        use http::header::AUTHORIZATION;
+        use telemetry::sensor::{Authority, Bytes, Direction, SensorConfig};
+
+        let (tx, rx) = futures_mpsc_lossy::channel(100);
+        let sensors = super::super::Sensors::new(tx);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let server = ctx::transport::Server::new(&proxy, &addr, &addr, &None, Protocol::Http);
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let mut svc = Http {
+            req_ids: Arc::new(Sequential::default()),
+            service: Drains,
+            handle: sensors.handle.clone(),
+            config: sensors.config.cached(),
+            trace: None,
+            request_id_header: None,
+            client_ctx,
+            in_flight: InFlight::default().endpoint(&addr),
+            _p: PhantomData,
+        };
+
+        let send = |svc: &mut Http<Drains, Chunks, Chunks>| {
+            let mut req = http::Request::new(Chunks(vec![&b"ab"[..], b"cde"]));
+            *req.uri_mut() = "http://example.com/path".parse().unwrap();
+            req.headers_mut().insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+            req.extensions_mut().insert(server.clone());
+            req.extensions_mut().insert(RequestOpen(Instant::now()));
+            let mut body = svc.call(req).wait().expect("response").into_body();
+            while let Async::Ready(Some(_)) = body.poll_data().expect("response body") {}
+            assert!(body.poll_trailers().expect("response trailers").is_ready());
+        };
+
+        send(&mut svc);
+
+        // The service that's already serving requests is reconfigured.
+        sensors.update_config(SensorConfig::default().with_redacted_headers(vec![AUTHORIZATION]));
+        send(&mut svc);
+        sensors.update_config(SensorConfig::default());
+        send(&mut svc);
+        drop(svc);
+
+        let events = rx.collect().wait().expect("events");
+        let authorizations = events.iter()
+            .filter_map(|ev| match *ev {
+                Event::StreamRequestOpen(ref req) => Some(req.headers["authorization"].clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(authorizations, vec!["Bearer secret", REDACTED, "Bearer secret"]);
+
+        let authority = Authority {
+            direction: Direction::Outbound,
+            authority: "example.com".to_owned(),
+        };
+        let bytes = sensors.byte_counts().authorities()[&authority];
+        assert_eq!(bytes, Bytes { rx: 33, tx: 15 });
     }
 }
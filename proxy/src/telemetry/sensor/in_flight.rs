@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the requests in flight to each endpoint.
+///
+/// A request is in flight from when it is dispatched to an endpoint until its
+/// response has been received in full, it fails, or it is canceled.
+#[derive(Clone, Debug, Default)]
+pub struct InFlight(Arc<Mutex<HashMap<SocketAddr, Arc<AtomicUsize>>>>);
+
+/// The requests in flight to a single endpoint.
+#[derive(Clone, Debug)]
+pub struct Endpoint(Arc<AtomicUsize>);
+
+/// Counts a single request as in flight until it is dropped.
+#[derive(Debug)]
+pub struct Guard(Arc<AtomicUsize>);
+
+// ===== impl InFlight =====
+
+impl InFlight {
+    /// Returns the gauge for requests to `addr`.
+    ///
+    /// Services bound to the same address share a gauge.
+    pub fn endpoint(&self, addr: &SocketAddr) -> Endpoint {
+        let mut endpoints = self.0.lock().expect("in-flight lock poisoned");
+        let count = endpoints.entry(*addr).or_insert_with(Default::default);
+        Endpoint(count.clone())
+    }
+
+    /// Returns the number of requests currently in flight to each endpoint.
+    ///
+    /// Endpoints that no longer have any bound services are forgotten.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, usize> {
+        let mut endpoints = self.0.lock().expect("in-flight lock poisoned");
+        endpoints.retain(|_, count| Arc::strong_count(count) > 1);
+        endpoints.iter()
+            .map(|(addr, count)| (*addr, count.load(Ordering::Acquire)))
+            .collect()
+    }
+}
+
+// ===== impl Endpoint =====
+
+impl Endpoint {
+    /// Counts a request as in flight until the returned `Guard` is dropped.
+    pub fn start(&self) -> Guard {
+        self.0.fetch_add(1, Ordering::AcqRel);
+        Guard(self.0.clone())
+    }
+}
+
+// ===== impl Guard =====
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_requests_until_guards_are_dropped() {
+        let in_flight = InFlight::default();
+        let a = "10.1.1.1:8080".parse().unwrap();
+        let b = "10.1.1.2:8080".parse().unwrap();
+
+        let first = in_flight.endpoint(&a).start();
+        let second = in_flight.endpoint(&a).start();
+        let other = in_flight.endpoint(&b);
+        let third = other.start();
+        assert_eq!(in_flight.snapshot()[&a], 2);
+        assert_eq!(in_flight.snapshot()[&b], 1);
+
+        drop(first);
+        drop(third);
+        assert_eq!(in_flight.snapshot()[&a], 1);
+        assert_eq!(in_flight.snapshot()[&b], 0);
+
+        // Once nothing refers to an endpoint's gauge, it is forgotten.
+        drop(second);
+        assert!(!in_flight.snapshot().contains_key(&a));
+        assert_eq!(in_flight.snapshot()[&b], 0);
+    }
+}
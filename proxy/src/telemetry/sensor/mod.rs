@@ -1,43 +1,91 @@
 use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
 use std::time::Instant;
 
 use futures_mpsc_lossy::Sender;
 use http::{Request, Response};
+use http::header::HeaderName;
 use tokio_connect;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tower::NewService;
 use tower_h2::{client, Body};
 
 use ctx;
-use telemetry::event;
+use telemetry::{event, events};
+use telemetry::classify::SharedClassifier;
 
+mod attempts;
+mod byte_counts;
+mod config;
+mod decompress;
+mod dns;
+mod grpc;
 pub mod http;
+mod in_flight;
+mod request_id;
+pub mod spans;
+mod terminations;
+pub mod trace;
 mod transport;
 
-pub use self::http::{Http, NewHttp};
+pub use self::attempts::{Attempts, RequestAttempts};
+pub use self::byte_counts::{Authority, ByteCounter, ByteCounts, Bytes, Direction, Peer};
+pub use self::config::SensorConfig;
+pub use self::decompress::Decompress;
+pub use self::dns::{AuthorityLookups, DnsLookups};
+pub use self::http::{Http, NewHttp, RedactHeaders};
+pub use self::in_flight::InFlight;
+pub use self::request_id::{RequestIdGen, Sequential, SharedRequestIdGen};
+pub use self::terminations::{Termination, Terminations};
 pub use self::transport::{Connect, Transport};
 
 /// Accepts events from sensors.
 #[derive(Clone, Debug)]
-struct Handle(Option<Sender<event::Event>>);
+struct Handle {
+    tx: Option<Sender<event::Event>>,
+    /// Publishes lifecycle events derived from sensor events.
+    events: events::Events,
+    /// Counts the bytes transferred by transports and bodies.
+    byte_counts: ByteCounts,
+    /// Counts how servers terminate client transports and streams.
+    terminations: Terminations,
+}
 
 /// Supports the creation of telemetry scopes.
+///
+/// Clones share the same `SensorConfig`, so that updating it with
+/// `update_config` reconfigures the sensors created by each of them. The
+/// `with_redacted_headers`, `with_classifier`, `with_decompression` and
+/// `with_max_decompressed_bytes` builders instead configure only the
+/// `Sensors` they're called on.
 #[derive(Clone, Debug)]
-pub struct Sensors(Handle);
+pub struct Sensors {
+    handle: Handle,
+    config: config::SharedConfig,
+    trace_propagation: Option<trace::Propagation>,
+    request_id_header: Option<HeaderName>,
+    in_flight: InFlight,
+    dns_lookups: DnsLookups,
+    request_attempts: RequestAttempts,
+}
 
 impl Handle {
     fn send<F>(&mut self, mk: F)
     where
         F: FnOnce() -> event::Event,
     {
-        if let Some(tx) = self.0.as_mut() {
-            // We may want to capture timestamps here instead of on the consumer-side...  That
-            // level of precision doesn't necessarily seem worth it yet.
+        if self.tx.is_none() && !self.events.is_subscribed() {
+            return;
+        }
 
-            let ev = mk();
-            trace!("event: {:?}", ev);
+        // We may want to capture timestamps here instead of on the consumer-side...  That
+        // level of precision doesn't necessarily seem worth it yet.
 
+        let ev = mk();
+        trace!("event: {:?}", ev);
+
+        self.events.publish(&ev);
+
+        if let Some(tx) = self.tx.as_mut() {
             if tx.lossy_send(ev).is_err() {
                 debug!("dropped event");
             }
@@ -47,11 +95,145 @@ impl Handle {
 
 impl Sensors {
     pub(super) fn new(h: Sender<event::Event>) -> Self {
-        Sensors(Handle(Some(h)))
+        Sensors {
+            handle: Handle {
+                tx: Some(h),
+                events: events::Events::default(),
+                byte_counts: ByteCounts::default(),
+                terminations: Terminations::default(),
+            },
+            config: config::SharedConfig::default(),
+            trace_propagation: None,
+            request_id_header: None,
+            in_flight: InFlight::default(),
+            dns_lookups: DnsLookups::default(),
+            request_attempts: RequestAttempts::default(),
+        }
     }
 
     pub fn null() -> Sensors {
-        Sensors(Handle(None))
+        Sensors {
+            handle: Handle {
+                tx: None,
+                events: events::Events::default(),
+                byte_counts: ByteCounts::default(),
+                terminations: Terminations::default(),
+            },
+            config: config::SharedConfig::default(),
+            trace_propagation: None,
+            request_id_header: None,
+            in_flight: InFlight::default(),
+            dns_lookups: DnsLookups::default(),
+            request_attempts: RequestAttempts::default(),
+        }
+    }
+
+    /// Redacts the values of headers named in `names` from HTTP telemetry.
+    ///
+    /// Requests and responses are proxied with their original headers.
+    pub fn with_redacted_headers(self, names: Vec<HeaderName>) -> Self {
+        let config = (*self.config.load()).clone().with_redacted_headers(names);
+        Sensors {
+            config: config::SharedConfig::new(config),
+            ..self
+        }
+    }
+
+    /// Classifies completed responses as successes or failures with
+    /// `classifier`, rather than by the default HTTP status rules.
+    pub fn with_classifier(self, classifier: SharedClassifier) -> Self {
+        let config = (*self.config.load()).clone().with_classifier(classifier);
+        Sensors {
+            config: config::SharedConfig::new(config),
+            ..self
+        }
+    }
+
+    /// Decompresses the responses that upstreams compress with gzip, so that
+    /// telemetry may inspect their contents, as `decompress` configures.
+    pub fn with_decompression(self, decompress: Decompress) -> Self {
+        let config = (*self.config.load()).clone().with_decompression(decompress);
+        Sensors {
+            config: config::SharedConfig::new(config),
+            ..self
+        }
+    }
+
+    /// Fails responses that decompress into more than `max` bytes.
+    pub fn with_max_decompressed_bytes(self, max: usize) -> Self {
+        let config = (*self.config.load()).clone().with_max_decompressed_bytes(max);
+        Sensors {
+            config: config::SharedConfig::new(config),
+            ..self
+        }
+    }
+
+    /// Propagates trace context across each proxied request, beginning new
+    /// traces in the `propagation` format.
+    pub fn with_trace_propagation(self, propagation: trace::Propagation) -> Self {
+        Sensors {
+            trace_propagation: Some(propagation),
+            ..self
+        }
+    }
+
+    /// Sends each proxied request with its ID in the `header` header, unless
+    /// the request already has one.
+    pub fn with_request_id_header(self, header: HeaderName) -> Self {
+        Sensors {
+            request_id_header: Some(header),
+            ..self
+        }
+    }
+
+    /// Returns the current configuration of HTTP sensors.
+    pub fn config(&self) -> Arc<SensorConfig> {
+        self.config.load()
+    }
+
+    /// Replaces the configuration of the HTTP sensors created by this
+    /// `Sensors`, and by its clones, including those already serving
+    /// requests.
+    ///
+    /// Each request is described with the configuration that was current
+    /// when it was sent. Metrics, and other state such as byte counts, are
+    /// unaffected.
+    pub fn update_config(&self, config: SensorConfig) {
+        debug!("updating sensor config: {:?}", config);
+        self.config.store(config);
+    }
+
+    /// Counts the requests in flight to each endpoint.
+    pub fn in_flight(&self) -> &InFlight {
+        &self.in_flight
+    }
+
+    /// Records the latency and failures of DNS lookups, and the size of the
+    /// resolver's cache.
+    pub fn dns_lookups(&self) -> &DnsLookups {
+        &self.dns_lookups
+    }
+
+    /// Counts the attempts made to send each request, including retries.
+    pub fn request_attempts(&self) -> &RequestAttempts {
+        &self.request_attempts
+    }
+
+    /// Counts the bytes transferred with each peer and for each authority.
+    pub fn byte_counts(&self) -> &ByteCounts {
+        &self.handle.byte_counts
+    }
+
+    /// Counts the GOAWAYs, stream resets, connection resets, and EOFs with
+    /// which servers terminate client transports and streams.
+    pub fn terminations(&self) -> &Terminations {
+        &self.handle.terminations
+    }
+
+    /// Subscribes to a stream of connection and request lifecycle events,
+    /// buffering up to `capacity` events.
+    pub fn subscribe(&self, capacity: usize) -> events::Subscription {
+        self.handle.events.subscribe(capacity)
     }
 
     pub fn accept<T>(
@@ -65,19 +247,19 @@ impl Sensors {
     {
         debug!("server connection open");
         let ctx = Arc::new(ctx::transport::Ctx::Server(Arc::clone(ctx)));
-        Transport::open(io, opened_at, &self.0, ctx)
+        Transport::open(io, opened_at, &self.handle, ctx)
     }
 
     pub fn connect<C>(&self, connect: C, ctx: &Arc<ctx::transport::Client>) -> Connect<C>
     where
         C: tokio_connect::Connect,
     {
-        Connect::new(connect, &self.0, ctx)
+        Connect::new(connect, &self.handle, ctx)
     }
 
     pub fn http<N, A, B>(
         &self,
-        next_id: Arc<AtomicUsize>,
+        req_ids: SharedRequestIdGen,
         new_service: N,
         client_ctx: &Arc<ctx::transport::Client>,
     ) -> NewHttp<N, A, B>
@@ -91,6 +273,15 @@ impl Sensors {
         >
             + 'static,
     {
-        NewHttp::new(next_id, new_service, &self.0, client_ctx)
+        NewHttp::new(
+            req_ids,
+            new_service,
+            &self.handle,
+            &self.config,
+            self.trace_propagation,
+            self.request_id_header.clone(),
+            client_ctx,
+            self.in_flight.endpoint(&client_ctx.remote),
+        )
     }
 }
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// Generates the IDs that identify requests in telemetry.
+///
+/// IDs need only be unique among the requests proxied by a single process,
+/// but a generator may also make them unique across restarts or proxy
+/// instances, e.g. so that they may be correlated across hops.
+pub trait RequestIdGen: fmt::Debug {
+    /// Returns the ID for a new request.
+    fn next_id(&self) -> u64;
+}
+
+/// A shared `RequestIdGen`.
+pub type SharedRequestIdGen = Arc<RequestIdGen + Send + Sync>;
+
+/// Numbers requests sequentially, starting from zero when the process starts.
+#[derive(Debug, Default)]
+pub struct Sequential(AtomicUsize);
+
+impl RequestIdGen for Sequential {
+    fn next_id(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst) as u64
+    }
+}
+
+/// Sets `header` to `id`, unless the request already has a value for it.
+///
+/// A request ID assigned by a previous hop is kept, so that the request may
+/// be correlated across hops.
+pub(super) fn set_header(headers: &mut HeaderMap, header: &HeaderName, id: u64) {
+    if !headers.contains_key(header) {
+        headers.insert(header.clone(), HeaderValue::from(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential_ids_are_unique_and_increasing() {
+        let ids: SharedRequestIdGen = Arc::new(Sequential::default());
+        let shared = ids.clone();
+        let generated = vec![ids.next_id(), shared.next_id(), ids.next_id()];
+        assert_eq!(generated, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn ids_assigned_by_previous_hops_are_kept() {
+        let header = HeaderName::from_static("x-request-id");
+        let mut headers = HeaderMap::new();
+        set_header(&mut headers, &header, 7);
+        assert_eq!(headers[&header], "7");
+
+        set_header(&mut headers, &header, 8);
+        assert_eq!(headers[&header], "7");
+    }
+}
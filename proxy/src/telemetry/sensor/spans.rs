@@ -0,0 +1,382 @@
+//! Mirrors proxied requests into a tree of spans, so that the phases of each
+//! request may be timed, correlated with its telemetry by request ID.
+//!
+//! Each request has a `request` span, opened as soon as it's received, with
+//! a child `buffer_wait` span that lasts until the request is dispatched to
+//! an endpoint. Each dispatch, of which a retried request has several, is an
+//! `upstream` span, which lasts until the endpoint responds, and in which
+//! connections to the endpoint are opened in `connect` spans. The `request`
+//! span lasts until the response ends. The `request` and `upstream` spans
+//! carry the request's ID and endpoint.
+//!
+//! Spans are closed once they're dropped, so the spans of requests that fail
+//! or are canceled are closed too.
+//!
+//! Each span is logged at the `debug` level as it opens, with its ID, its
+//! parent's ID, and its fields, and again as it closes, with how long it was
+//! open, so that the span tree may be rebuilt from the proxy's log.
+//!
+//! These are log-based spans, rather than those of the `tracing` crate, and
+//! they're only opened while this module logs at the `debug` level, so that
+//! requests don't pay for spans that nothing would see.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The number of spans that have been opened, from which span IDs are
+/// assigned.
+static OPENED: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The IDs of the spans being run in on this thread, innermost last.
+    static CURRENT: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+/// The spans of a request, added to its extensions when it's received.
+#[derive(Debug)]
+pub struct Request {
+    span: Span,
+    _buffer_wait: Option<Span>,
+}
+
+/// The spans of a request's dispatch to an endpoint.
+#[derive(Debug)]
+pub struct Dispatch {
+    _request: Span,
+    upstream: Option<Span>,
+}
+
+/// The span of a connection being opened, if spans are enabled.
+#[derive(Clone, Debug)]
+pub struct Connect {
+    _span: Option<Span>,
+}
+
+/// A span, which closes once it and all of its clones are dropped.
+#[derive(Clone, Debug)]
+struct Span(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    id: usize,
+    name: &'static str,
+    opened: Instant,
+    fields: Mutex<Vec<(&'static str, String)>>,
+}
+
+/// Leaves the innermost span being run in when dropped.
+struct Exit;
+
+/// Formats a span's fields as ` name=value` pairs.
+struct Fields<'a>(&'a [(&'static str, String)]);
+
+// ===== impl Request =====
+
+impl Request {
+    /// Opens the spans of a request that has just been received, unless
+    /// spans are disabled.
+    pub fn open() -> Option<Self> {
+        if !enabled() {
+            return None;
+        }
+        let span = Span::open("request", None, Vec::new());
+        let buffer_wait = Span::open("buffer_wait", Some(span.id()), Vec::new());
+        Some(Request {
+            span,
+            _buffer_wait: Some(buffer_wait),
+        })
+    }
+
+    /// Returns the spans of a replay of the request, which isn't waiting
+    /// to be dispatched.
+    pub fn replay(&self) -> Self {
+        Request {
+            span: self.span.clone(),
+            _buffer_wait: None,
+        }
+    }
+
+    /// Ends the request's wait, now that it's dispatched to `endpoint`
+    /// as request `id`.
+    pub fn dispatch(&mut self, id: u64, endpoint: &SocketAddr) -> Dispatch {
+        self._buffer_wait = None;
+        let (id, endpoint) = (id.to_string(), endpoint.to_string());
+        self.span.record("request_id", id.clone());
+        self.span.record("endpoint", endpoint.clone());
+        let fields = vec![("request_id", id), ("endpoint", endpoint)];
+        let upstream = Span::open("upstream", Some(self.span.id()), fields);
+        Dispatch {
+            _request: self.span.clone(),
+            upstream: Some(upstream),
+        }
+    }
+}
+
+// ===== impl Dispatch =====
+
+impl Dispatch {
+    /// Runs `f` in the `upstream` span, if the endpoint hasn't yet
+    /// responded.
+    pub fn in_scope<F: FnOnce() -> T, T>(&self, f: F) -> T {
+        match self.upstream {
+            Some(ref span) => span.in_scope(f),
+            None => f(),
+        }
+    }
+
+    /// Ends the `upstream` span, now that the endpoint has responded.
+    pub fn responded(&mut self) {
+        self.upstream = None;
+    }
+}
+
+// ===== impl Connect =====
+
+impl Connect {
+    /// Starts a connection to `endpoint`, as a child of the span being run
+    /// in, if there is one.
+    pub fn start(endpoint: &SocketAddr) -> Self {
+        if !enabled() {
+            return Connect { _span: None };
+        }
+        let parent = CURRENT.with(|current| current.borrow().last().cloned());
+        let fields = vec![("endpoint", endpoint.to_string())];
+        Connect {
+            _span: Some(Span::open("connect", parent, fields)),
+        }
+    }
+}
+
+/// Returns true if spans are logged, or recorded by a test.
+fn enabled() -> bool {
+    log_enabled!(::log::Level::Debug) || recorder::is_recording()
+}
+
+// ===== impl Span =====
+
+impl Span {
+    fn open(
+        name: &'static str,
+        parent: Option<usize>,
+        fields: Vec<(&'static str, String)>,
+    ) -> Self {
+        let id = OPENED.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!("opened {} span {} (parent {:?}){}", name, id, parent, Fields(&fields));
+        recorder::opened(id, name, parent, &fields);
+        Span(Arc::new(Inner {
+            id,
+            name,
+            opened: Instant::now(),
+            fields: Mutex::new(fields),
+        }))
+    }
+
+    fn id(&self) -> usize {
+        self.0.id
+    }
+
+    fn record(&self, field: &'static str, value: String) {
+        recorder::recorded(self.0.id, field, &value);
+        self.0.fields.lock().unwrap().push((field, value));
+    }
+
+    /// Runs `f` in this span, so that spans it starts are its children.
+    fn in_scope<F: FnOnce() -> T, T>(&self, f: F) -> T {
+        CURRENT.with(|current| current.borrow_mut().push(self.0.id));
+        let _exit = Exit;
+        f()
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let elapsed = self.opened.elapsed();
+        let fields = self.fields.get_mut().unwrap();
+        debug!("closed {} span {} after {:?}{}", self.name, self.id, elapsed, Fields(fields));
+        recorder::closed(self.id);
+    }
+}
+
+// ===== impl Exit =====
+
+impl Drop for Exit {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().pop());
+    }
+}
+
+// ===== impl Fields =====
+
+impl<'a> fmt::Display for Fields<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(name, ref value) in self.0 {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outside of tests, spans are only logged.
+#[cfg(not(test))]
+mod recorder {
+    pub fn is_recording() -> bool {
+        false
+    }
+
+    pub fn opened(_: usize, _: &'static str, _: Option<usize>, _: &[(&'static str, String)]) {}
+
+    pub fn recorded(_: usize, _: &'static str, _: &str) {}
+
+    pub fn closed(_: usize) {}
+}
+
+/// Records the spans opened on a thread, for tests.
+#[cfg(test)]
+pub mod recorder {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    thread_local! {
+        /// The recorder of the spans opened on this thread, if any.
+        static RECORDER: RefCell<Option<Recorder>> = RefCell::new(None);
+    }
+
+    /// Records the spans opened while it's recording.
+    #[derive(Clone, Default)]
+    pub struct Recorder(Rc<RefCell<Inner>>);
+
+    /// A span that was opened.
+    #[derive(Clone, Debug)]
+    pub struct Span {
+        pub name: &'static str,
+        /// The index of the span's parent, if it has one.
+        pub parent: Option<usize>,
+        pub fields: HashMap<&'static str, String>,
+        pub closed: bool,
+    }
+
+    #[derive(Default)]
+    struct Inner {
+        spans: Vec<Span>,
+        /// The index of each span, by its ID.
+        indices: HashMap<usize, usize>,
+    }
+
+    /// Stops recording when dropped.
+    struct Stop;
+
+    impl Recorder {
+        /// Records the spans opened on this thread while `f` runs.
+        pub fn record<F: FnOnce() -> T, T>(&self, f: F) -> T {
+            RECORDER.with(|recorder| *recorder.borrow_mut() = Some(self.clone()));
+            let _stop = Stop;
+            f()
+        }
+
+        pub fn spans(&self) -> Vec<Span> {
+            self.0.borrow().spans.clone()
+        }
+
+        /// Returns the indices of the spans named `name`, in the order they
+        /// were opened.
+        pub fn named(&self, name: &str) -> Vec<usize> {
+            self.spans().iter()
+                .enumerate()
+                .filter(|&(_, span)| span.name == name)
+                .map(|(i, _)| i)
+                .collect()
+        }
+    }
+
+    impl Drop for Stop {
+        fn drop(&mut self) {
+            RECORDER.with(|recorder| *recorder.borrow_mut() = None);
+        }
+    }
+
+    pub fn is_recording() -> bool {
+        RECORDER.with(|recorder| recorder.borrow().is_some())
+    }
+
+    /// Runs `f` on the recording, if there is one.
+    fn with_recording<F: FnOnce(&mut Inner)>(f: F) {
+        RECORDER.with(|recorder| {
+            if let Some(ref recorder) = *recorder.borrow() {
+                f(&mut recorder.0.borrow_mut());
+            }
+        });
+    }
+
+    pub fn opened(
+        id: usize,
+        name: &'static str,
+        parent: Option<usize>,
+        fields: &[(&'static str, String)],
+    ) {
+        with_recording(|inner| {
+            let parent = parent.and_then(|id| inner.indices.get(&id).cloned());
+            let index = inner.spans.len();
+            inner.indices.insert(id, index);
+            inner.spans.push(Span {
+                name,
+                parent,
+                fields: fields.iter().cloned().collect(),
+                closed: false,
+            });
+        });
+    }
+
+    pub fn recorded(id: usize, field: &'static str, value: &str) {
+        with_recording(|inner| {
+            if let Some(&index) = inner.indices.get(&id) {
+                inner.spans[index].fields.insert(field, value.to_owned());
+            }
+        });
+    }
+
+    pub fn closed(id: usize) {
+        with_recording(|inner| {
+            if let Some(&index) = inner.indices.get(&id) {
+                inner.spans[index].closed = true;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::recorder::Recorder;
+
+    #[test]
+    fn connections_are_opened_in_their_dispatches() {
+        let recorder = Recorder::default();
+        let endpoint = "10.1.1.1:8080".parse().unwrap();
+
+        recorder.record(|| {
+            let mut request = Request::open().expect("spans are recorded");
+            let mut dispatch = request.dispatch(7, &endpoint);
+            let connect = dispatch.in_scope(|| Connect::start(&endpoint));
+            drop(connect);
+            dispatch.responded();
+
+            // Connections opened outside of a dispatch have no parent.
+            drop(dispatch.in_scope(|| Connect::start(&endpoint)));
+        });
+
+        let spans = recorder.spans();
+        let (request, upstream) = (recorder.named("request")[0], recorder.named("upstream")[0]);
+        let connects = recorder.named("connect");
+        assert_eq!(connects.len(), 2);
+        assert_eq!(spans[connects[0]].parent, Some(upstream));
+        assert_eq!(spans[connects[0]].fields["endpoint"], "10.1.1.1:8080");
+        assert_eq!(spans[connects[1]].parent, None);
+        assert_eq!(spans[upstream].parent, Some(request));
+        assert!(spans.iter().all(|span| span.closed), "{:?}", spans);
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::Direction;
+
+/// Counts the ways in which servers terminate the proxy's client connections
+/// and streams.
+///
+/// These are counted separately from other failures, so that an upstream
+/// that is shutting down or resetting connections may be told apart from
+/// one that fails requests.
+#[derive(Clone, Debug, Default)]
+pub struct Terminations(Arc<Mutex<HashMap<(Direction, Termination), u64>>>);
+
+/// How a server terminated a client connection or stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Termination {
+    /// The server sent an HTTP/2 `GOAWAY` frame.
+    GoAway,
+    /// The server reset an HTTP/2 stream with a `RST_STREAM` frame.
+    ResetStream,
+    /// The connection was reset.
+    TcpReset,
+    /// The server closed the connection.
+    Eof,
+}
+
+// ===== impl Terminations =====
+
+impl Terminations {
+    pub fn record(&self, direction: Direction, termination: Termination) {
+        let mut counts = self.0.lock().expect("terminations lock poisoned");
+        *counts.entry((direction, termination)).or_insert(0) += 1;
+    }
+
+    /// Returns the number of times connections or streams have been
+    /// terminated in each way.
+    pub fn snapshot(&self) -> HashMap<(Direction, Termination), u64> {
+        self.0.lock().expect("terminations lock poisoned").clone()
+    }
+}
+
+// ===== impl Termination =====
+
+impl Termination {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Termination::GoAway => "goaway",
+            Termination::ResetStream => "reset_stream",
+            Termination::TcpReset => "tcp_reset",
+            Termination::Eof => "eof",
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Propagates distributed tracing context across the proxy's hop.
+//!
+//! Each proxied request is described by a `Span`. If the request was sent
+//! with W3C `traceparent` or B3 `X-B3-*` headers, the proxy's span is a child
+//! of the span they describe; otherwise, the proxy's span begins a new trace.
+//! The request is forwarded with headers describing the proxy's span, so that
+//! the next hop's span is a child of it.
+//!
+//! The span is recorded in the request's telemetry context, so it covers the
+//! same interval as the request's recorded latency.
+
+use http::header::{HeaderMap, HeaderValue};
+use rand::Rng;
+
+const TRACEPARENT: &str = "traceparent";
+const B3_TRACE_ID: &str = "x-b3-traceid";
+const B3_SPAN_ID: &str = "x-b3-spanid";
+const B3_PARENT_SPAN_ID: &str = "x-b3-parentspanid";
+const B3_SAMPLED: &str = "x-b3-sampled";
+const B3_FLAGS: &str = "x-b3-flags";
+
+/// The only `traceparent` version that is understood.
+const W3C_VERSION: &str = "00";
+const W3C_SAMPLED: u8 = 0x01;
+
+/// The header format in which new traces are propagated.
+///
+/// Requests that already have trace context are forwarded in the format in
+/// which it was received.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Propagation {
+    /// W3C Trace Context `traceparent` headers.
+    W3c,
+    /// Zipkin B3 `X-B3-*` headers.
+    B3,
+}
+
+/// Describes the proxy's hop of a traced request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// Identifies the trace, as lowercase hex.
+    pub trace_id: String,
+
+    /// Identifies the proxy's span within the trace.
+    pub span_id: u64,
+
+    /// Identifies the span of the request's sender, if the request was sent
+    /// with trace context.
+    pub parent_id: Option<u64>,
+
+    /// Whether the trace is sampled, or `None` if the sender deferred the
+    /// decision.
+    pub sampled: Option<bool>,
+
+    format: Format,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Format {
+    W3c,
+    B3 { debug: bool },
+}
+
+/// Trace context read from a request's headers.
+struct Parent {
+    trace_id: String,
+    span_id: u64,
+    sampled: Option<bool>,
+    format: Format,
+}
+
+// ===== impl Propagation =====
+
+impl Propagation {
+    /// Replaces the trace context in `headers` with that of a new `Span`,
+    /// which is returned.
+    ///
+    /// The new span is a child of the span described by `headers`, if any.
+    /// Otherwise, it begins a new trace in this propagation's format. New
+    /// traces are sampled, since the proxy records every request's span.
+    pub fn propagate<R: Rng>(self, headers: &mut HeaderMap, rng: &mut R) -> Span {
+        let span = match Parent::from_w3c(headers).or_else(|| Parent::from_b3(headers)) {
+            Some(parent) => Span {
+                trace_id: parent.trace_id,
+                span_id: span_id(rng),
+                parent_id: Some(parent.span_id),
+                sampled: parent.sampled,
+                format: parent.format,
+            },
+            // A span ID is never zero, so neither is the trace ID.
+            None => Span {
+                trace_id: format!("{:016x}{:016x}", span_id(rng), rng.gen::<u64>()),
+                span_id: span_id(rng),
+                parent_id: None,
+                sampled: Some(true),
+                format: match self {
+                    Propagation::W3c => Format::W3c,
+                    Propagation::B3 => Format::B3 { debug: false },
+                },
+            },
+        };
+
+        span.write(headers);
+        span
+    }
+}
+
+// ===== impl Span =====
+
+impl Span {
+    fn write(&self, headers: &mut HeaderMap) {
+        match self.format {
+            Format::W3c => {
+                let flags = if self.sampled == Some(true) { W3C_SAMPLED } else { 0 };
+                let traceparent = format!(
+                    "{}-{}-{:016x}-{:02x}",
+                    W3C_VERSION, self.trace_id, self.span_id, flags
+                );
+                headers.insert(TRACEPARENT, header_value(traceparent));
+            }
+            Format::B3 { debug } => {
+                headers.insert(B3_TRACE_ID, header_value(self.trace_id.clone()));
+                headers.insert(B3_SPAN_ID, header_value(format!("{:016x}", self.span_id)));
+                match self.parent_id {
+                    Some(parent_id) => {
+                        let parent_id = header_value(format!("{:016x}", parent_id));
+                        headers.insert(B3_PARENT_SPAN_ID, parent_id);
+                    }
+                    None => {
+                        headers.remove(B3_PARENT_SPAN_ID);
+                    }
+                }
+                // Debug implies an accept decision, so it is not also sent.
+                if !debug {
+                    if let Some(sampled) = self.sampled {
+                        let sampled = if sampled { "1" } else { "0" };
+                        headers.insert(B3_SAMPLED, HeaderValue::from_static(sampled));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ===== impl Parent =====
+
+impl Parent {
+    /// Reads a `traceparent` header of the form
+    /// `{version}-{trace-id}-{parent-id}-{trace-flags}`.
+    fn from_w3c(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(TRACEPARENT)?.to_str().ok()?;
+        let parts = value.trim().split('-').collect::<Vec<_>>();
+        if parts.len() != 4 || parts[0] != W3C_VERSION {
+            return None;
+        }
+
+        let trace_id = hex_id(parts[1], &[32])?;
+        let span_id = span_id_from_hex(parts[2])?;
+        if parts[3].len() != 2 || !parts[3].chars().all(|c| c.is_digit(16)) {
+            return None;
+        }
+        let flags = u8::from_str_radix(parts[3], 16).ok()?;
+
+        Some(Parent {
+            trace_id,
+            span_id,
+            sampled: Some(flags & W3C_SAMPLED == W3C_SAMPLED),
+            format: Format::W3c,
+        })
+    }
+
+    fn from_b3(headers: &HeaderMap) -> Option<Self> {
+        let trace_id = hex_id(b3_header(headers, B3_TRACE_ID)?, &[16, 32])?;
+        let span_id = span_id_from_hex(b3_header(headers, B3_SPAN_ID)?)?;
+        let debug = b3_header(headers, B3_FLAGS) == Some("1");
+        let sampled = if debug {
+            Some(true)
+        } else {
+            match b3_header(headers, B3_SAMPLED) {
+                Some("1") | Some("true") => Some(true),
+                Some("0") | Some("false") => Some(false),
+                _ => None,
+            }
+        };
+
+        Some(Parent {
+            trace_id,
+            span_id,
+            sampled,
+            format: Format::B3 { debug },
+        })
+    }
+}
+
+fn b3_header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim)
+}
+
+/// Returns `s` in lowercase if it is a nonzero hex ID of one of `lens`.
+fn hex_id(s: &str, lens: &[usize]) -> Option<String> {
+    let valid = lens.contains(&s.len())
+        && s.chars().all(|c| c.is_digit(16))
+        && s.chars().any(|c| c != '0');
+    if valid {
+        Some(s.to_lowercase())
+    } else {
+        None
+    }
+}
+
+fn span_id_from_hex(s: &str) -> Option<u64> {
+    let s = hex_id(s, &[16])?;
+    u64::from_str_radix(&s, 16).ok()
+}
+
+/// Generates a random span ID. Zero is not a valid ID.
+fn span_id<R: Rng>(rng: &mut R) -> u64 {
+    loop {
+        let id = rng.gen::<u64>();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+fn header_value(s: String) -> HeaderValue {
+    HeaderValue::from_str(&s).expect("trace headers are valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use rand;
+
+    use super::*;
+
+    fn propagate(propagation: Propagation, headers: &mut HeaderMap) -> Span {
+        propagation.propagate(headers, &mut rand::thread_rng())
+    }
+
+    fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+        headers.get(name).expect(name).to_str().unwrap()
+    }
+
+    #[test]
+    fn traceparent_is_propagated_as_a_child_span() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        headers.insert("tracestate", "vendor=value".parse().unwrap());
+
+        // The propagation format does not apply to requests that are already
+        // traced.
+        let span = propagate(Propagation::B3, &mut headers);
+        assert_eq!(span.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span.parent_id, Some(0x00f067aa0ba902b7));
+        assert_ne!(span.span_id, 0x00f067aa0ba902b7);
+        assert_eq!(span.sampled, Some(true));
+
+        let expected = format!("00-4bf92f3577b34da6a3ce929d0e0e4736-{:016x}-01", span.span_id);
+        assert_eq!(header(&headers, TRACEPARENT), expected);
+        assert_eq!(header(&headers, "tracestate"), "vendor=value");
+        assert!(!headers.contains_key(B3_TRACE_ID));
+    }
+
+    #[test]
+    fn unsampled_traceparent_is_not_sampled() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00".parse().unwrap(),
+        );
+
+        let span = propagate(Propagation::W3c, &mut headers);
+        assert_eq!(span.sampled, Some(false));
+        assert!(header(&headers, TRACEPARENT).ends_with("-00"));
+    }
+
+    #[test]
+    fn b3_headers_are_propagated_as_a_child_span() {
+        let mut headers = HeaderMap::new();
+        headers.insert(B3_TRACE_ID, "463ac35c9f6413ad".parse().unwrap());
+        headers.insert(B3_SPAN_ID, "a2fb4a1d1a96d312".parse().unwrap());
+        headers.insert(B3_PARENT_SPAN_ID, "0020000000000001".parse().unwrap());
+        headers.insert(B3_SAMPLED, "0".parse().unwrap());
+
+        let span = propagate(Propagation::W3c, &mut headers);
+        assert_eq!(span.trace_id, "463ac35c9f6413ad");
+        assert_eq!(span.parent_id, Some(0xa2fb4a1d1a96d312));
+        assert_eq!(span.sampled, Some(false));
+
+        assert_eq!(header(&headers, B3_TRACE_ID), "463ac35c9f6413ad");
+        assert_eq!(header(&headers, B3_SPAN_ID), format!("{:016x}", span.span_id));
+        assert_eq!(header(&headers, B3_PARENT_SPAN_ID), "a2fb4a1d1a96d312");
+        assert_eq!(header(&headers, B3_SAMPLED), "0");
+        assert!(!headers.contains_key(TRACEPARENT));
+    }
+
+    #[test]
+    fn deferred_b3_sampling_remains_deferred() {
+        let mut headers = HeaderMap::new();
+        headers.insert(B3_TRACE_ID, "463ac35c9f6413ad".parse().unwrap());
+        headers.insert(B3_SPAN_ID, "a2fb4a1d1a96d312".parse().unwrap());
+
+        let span = propagate(Propagation::B3, &mut headers);
+        assert_eq!(span.sampled, None);
+        assert!(!headers.contains_key(B3_SAMPLED));
+    }
+
+    #[test]
+    fn new_traces_are_generated_without_trace_context() {
+        let mut headers = HeaderMap::new();
+        let span = propagate(Propagation::W3c, &mut headers);
+        assert_eq!(span.trace_id.len(), 32);
+        assert_eq!(span.parent_id, None);
+        assert_eq!(span.sampled, Some(true));
+        let expected = format!("00-{}-{:016x}-01", span.trace_id, span.span_id);
+        assert_eq!(header(&headers, TRACEPARENT), expected);
+
+        let mut headers = HeaderMap::new();
+        let span = propagate(Propagation::B3, &mut headers);
+        assert_eq!(header(&headers, B3_TRACE_ID), span.trace_id);
+        assert_eq!(header(&headers, B3_SPAN_ID), format!("{:016x}", span.span_id));
+        assert_eq!(header(&headers, B3_SAMPLED), "1");
+        assert!(!headers.contains_key(B3_PARENT_SPAN_ID));
+    }
+
+    #[test]
+    fn invalid_trace_context_begins_a_new_trace() {
+        let invalid = [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+            "garbage",
+        ];
+        for traceparent in &invalid {
+            let mut headers = HeaderMap::new();
+            headers.insert(TRACEPARENT, traceparent.parse().unwrap());
+            let span = propagate(Propagation::W3c, &mut headers);
+            assert_eq!(span.parent_id, None, "{} should not be a parent", traceparent);
+            assert_ne!(header(&headers, TRACEPARENT), *traceparent);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use futures::{Future, Poll};
+use std::cmp;
 use std::io;
 use std::sync::Arc;
 use std::time::Instant;
@@ -7,6 +8,14 @@ use tokio_io::{AsyncRead, AsyncWrite};
 
 use ctx;
 use telemetry::event;
+use super::{ByteCounter, Direction, Termination, Terminations};
+use super::spans;
+
+const FRAME_HEADER_LEN: usize = 9;
+
+const RST_STREAM_TYPE: u8 = 0x3;
+
+const GOAWAY_TYPE: u8 = 0x7;
 
 /// Wraps a transport with telemetry.
 #[derive(Debug)]
@@ -17,10 +26,31 @@ struct Inner {
     handle: super::Handle,
     ctx: Arc<ctx::transport::Ctx>,
     opened_at: Instant,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    /// Totals bytes with those of other connections to the same peer.
+    peer_bytes: ByteCounter,
+    /// Classifies how the server terminates a client transport.
+    upstream: Option<Upstream>,
+}
+
+#[derive(Debug)]
+struct Upstream {
+    terminations: Terminations,
+    direction: Direction,
+    /// Reads the headers of the HTTP/2 frames sent by the server, if the
+    /// transport carries HTTP/2.
+    frames: Option<FrameHeaders>,
+    eof: bool,
+}
 
-    // TODO
-    //rx_bytes: usize,
-    //tx_bytes: usize,
+/// Reads the headers of a stream of HTTP/2 frames.
+#[derive(Debug, Default)]
+struct FrameHeaders {
+    header: [u8; FRAME_HEADER_LEN],
+    header_len: usize,
+    /// The payload bytes of the current frame that remain to be read.
+    payload: usize,
 }
 
 /// Builds client transports with telemetry.
@@ -29,6 +59,7 @@ pub struct Connect<C> {
     underlying: C,
     handle: super::Handle,
     ctx: Arc<ctx::transport::Client>,
+    h2: bool,
 }
 
 /// Adds telemetry to a pending client transport.
@@ -37,6 +68,9 @@ pub struct Connecting<C: tokio_connect::Connect> {
     underlying: C::Future,
     handle: super::Handle,
     ctx: Arc<ctx::transport::Client>,
+    h2: bool,
+    /// Lasts until the connection is opened, or fails.
+    _span: spans::Connect,
 }
 
 // === impl Transport ===
@@ -52,6 +86,16 @@ impl<T: AsyncRead + AsyncWrite> Transport<T> {
         let mut handle = handle.clone();
 
         handle.send(|| event::Event::TransportOpen(Arc::clone(&ctx)));
+        let peer_bytes = handle.byte_counts.peer(&ctx);
+        let upstream = match *ctx {
+            ctx::transport::Ctx::Client(ref client) => Some(Upstream {
+                terminations: handle.terminations.clone(),
+                direction: Direction::from(client.proxy.as_ref()),
+                frames: None,
+                eof: false,
+            }),
+            ctx::transport::Ctx::Server(_) => None,
+        };
 
         Transport(
             io,
@@ -59,10 +103,24 @@ impl<T: AsyncRead + AsyncWrite> Transport<T> {
                 ctx,
                 handle,
                 opened_at,
+                rx_bytes: 0,
+                tx_bytes: 0,
+                peer_bytes,
+                upstream,
             }),
         )
     }
 
+    /// Counts the `GOAWAY` and `RST_STREAM` frames read from the transport.
+    fn read_h2_frames(mut self) -> Self {
+        if let Some(ref mut inner) = self.1 {
+            if let Some(ref mut upstream) = inner.upstream {
+                upstream.frames = Some(FrameHeaders::default());
+            }
+        }
+        self
+    }
+
     /// Wraps an operation on the underlying transport with error telemetry.
     ///
     /// If the transport operation results in a non-recoverable error, a transport close
@@ -75,20 +133,13 @@ impl<T: AsyncRead + AsyncWrite> Transport<T> {
             Ok(v) => Ok(v),
             Err(e) => {
                 if e.kind() != io::ErrorKind::WouldBlock {
-                    if let Some(Inner {
-                        mut handle,
-                        ctx,
-                        opened_at,
-                    }) = self.1.take()
-                    {
-                        handle.send(move || {
-                            let duration = opened_at.elapsed();
-                            let ev = event::TransportClose {
-                                duration,
-                                clean: false,
-                            };
-                            event::Event::TransportClose(ctx, ev)
-                        });
+                    if let Some(inner) = self.1.take() {
+                        if let Some(ref upstream) = inner.upstream {
+                            if e.kind() == io::ErrorKind::ConnectionReset {
+                                upstream.record(Termination::TcpReset);
+                            }
+                        }
+                        inner.close(false);
                     }
                 }
 
@@ -100,27 +151,24 @@ impl<T: AsyncRead + AsyncWrite> Transport<T> {
 
 impl<T> Drop for Transport<T> {
     fn drop(&mut self) {
-        if let Some(Inner {
-            mut handle,
-            ctx,
-            opened_at,
-        }) = self.1.take()
-        {
-            handle.send(move || {
-                let duration = opened_at.elapsed();
-                let ev = event::TransportClose {
-                    clean: true,
-                    duration,
-                };
-                event::Event::TransportClose(ctx, ev)
-            });
+        if let Some(inner) = self.1.take() {
+            inner.close(true);
         }
     }
 }
 
 impl<T: AsyncRead + AsyncWrite> io::Read for Transport<T> {
-    fn read(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
-        self.sense_err(move |io| io.read(buf))
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let requested = !buf.is_empty();
+        let n = self.sense_err(|io| io.read(&mut *buf))?;
+        if let Some(ref mut inner) = self.1 {
+            inner.rx_bytes += n as u64;
+            inner.peer_bytes.received(n);
+            if let Some(ref mut upstream) = inner.upstream {
+                upstream.read(&buf[..n], n == 0 && requested);
+            }
+        }
+        Ok(n)
     }
 }
 
@@ -130,7 +178,12 @@ impl<T: AsyncRead + AsyncWrite> io::Write for Transport<T> {
     }
 
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.sense_err(move |io| io.write(buf))
+        let n = self.sense_err(move |io| io.write(buf))?;
+        if let Some(ref mut inner) = self.1 {
+            inner.tx_bytes += n as u64;
+            inner.peer_bytes.sent(n);
+        }
+        Ok(n)
     }
 }
 
@@ -146,6 +199,88 @@ impl<T: AsyncRead + AsyncWrite> AsyncWrite for Transport<T> {
     }
 }
 
+// === impl Inner ===
+
+impl Inner {
+    /// Emits a transport close event.
+    fn close(self, clean: bool) {
+        let Inner {
+            mut handle,
+            ctx,
+            opened_at,
+            rx_bytes,
+            tx_bytes,
+            ..
+        } = self;
+        handle.send(move || {
+            let ev = event::TransportClose {
+                clean,
+                duration: opened_at.elapsed(),
+                rx_bytes,
+                tx_bytes,
+            };
+            event::Event::TransportClose(ctx, ev)
+        });
+    }
+}
+
+// === impl Upstream ===
+
+impl Upstream {
+    fn record(&self, termination: Termination) {
+        debug!("client transport terminated: {:?}", termination);
+        self.terminations.record(self.direction, termination);
+    }
+
+    fn read(&mut self, buf: &[u8], eof: bool) {
+        if eof {
+            if !self.eof {
+                self.eof = true;
+                self.record(Termination::Eof);
+            }
+            return;
+        }
+
+        let mut frames = self.frames.take();
+        if let Some(ref mut frames) = frames {
+            frames.read(buf, |kind| match kind {
+                GOAWAY_TYPE => self.record(Termination::GoAway),
+                RST_STREAM_TYPE => self.record(Termination::ResetStream),
+                _ => {},
+            });
+        }
+        self.frames = frames;
+    }
+}
+
+// === impl FrameHeaders ===
+
+impl FrameHeaders {
+    /// Reads `buf`, calling `on_frame` with the type of each frame whose
+    /// header is completed.
+    fn read<F: FnMut(u8)>(&mut self, mut buf: &[u8], mut on_frame: F) {
+        while !buf.is_empty() {
+            if self.payload > 0 {
+                let n = cmp::min(self.payload, buf.len());
+                self.payload -= n;
+                buf = &buf[n..];
+                continue;
+            }
+
+            let n = cmp::min(FRAME_HEADER_LEN - self.header_len, buf.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&buf[..n]);
+            self.header_len += n;
+            buf = &buf[n..];
+            if self.header_len == FRAME_HEADER_LEN {
+                let h = &self.header;
+                self.payload = (h[0] as usize) << 16 | (h[1] as usize) << 8 | h[2] as usize;
+                self.header_len = 0;
+                on_frame(h[3]);
+            }
+        }
+    }
+}
+
 // === impl Connect ===
 
 impl<C: tokio_connect::Connect> Connect<C> {
@@ -159,6 +294,16 @@ impl<C: tokio_connect::Connect> Connect<C> {
             underlying,
             handle: handle.clone(),
             ctx: Arc::clone(ctx),
+            h2: false,
+        }
+    }
+
+    /// Counts the `GOAWAY` and `RST_STREAM` frames sent by servers on these
+    /// transports, which carry HTTP/2.
+    pub fn with_h2_frames(self) -> Self {
+        Connect {
+            h2: true,
+            ..self
         }
     }
 }
@@ -173,6 +318,8 @@ impl<C: tokio_connect::Connect> tokio_connect::Connect for Connect<C> {
             underlying: self.underlying.connect(),
             handle: self.handle.clone(),
             ctx: Arc::clone(&self.ctx),
+            h2: self.h2,
+            _span: spans::Connect::start(&self.ctx.remote),
         }
     }
 }
@@ -188,6 +335,214 @@ impl<C: tokio_connect::Connect> Future for Connecting<C> {
         debug!("client connection open");
         let ctx = Arc::new(Arc::clone(&self.ctx).into());
         let trans = Transport::open(io, Instant::now(), &self.handle, ctx);
+        let trans = if self.h2 { trans.read_h2_frames() } else { trans };
         Ok(trans.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use conduit_proxy_controller_grpc::common::Protocol;
+    use futures::{future, Async, Stream};
+    use tokio_connect::Connect as TokioConnect;
+
+    use telemetry::events::{ConnectionEvent, LifecycleEvent};
+    use telemetry::Sensors;
+    use telemetry::sensor::{Bytes, Direction, Peer};
+    use super::*;
+
+    /// A SETTINGS frame, then a RST_STREAM frame and a GOAWAY frame.
+    const H2_FRAMES: &[u8] = &[
+        0, 0, 0, 0x4, 0, 0, 0, 0, 0,
+        0, 0, 4, 0x3, 0, 0, 0, 0, 1, 0, 0, 0, 0x8,
+        0, 0, 8, 0x7, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0,
+    ];
+
+    /// Connects to an in-memory transport that has `rx` to be read.
+    struct ConnectCursor(&'static [u8]);
+
+    impl tokio_connect::Connect for ConnectCursor {
+        type Connected = Cursor<Vec<u8>>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Connected, io::Error>;
+
+        fn connect(&self) -> Self::Future {
+            future::ok(Cursor::new(self.0.to_vec()))
+        }
+    }
+
+    /// Connects to an in-memory transport that is reset by its peer.
+    struct ConnectReset;
+
+    struct Reset;
+
+    impl tokio_connect::Connect for ConnectReset {
+        type Connected = Reset;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Connected, io::Error>;
+
+        fn connect(&self) -> Self::Future {
+            future::ok(Reset)
+        }
+    }
+
+    impl Read for Reset {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::ConnectionReset.into())
+        }
+    }
+
+    impl Write for Reset {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::ConnectionReset.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for Reset {}
+
+    impl AsyncWrite for Reset {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(().into())
+        }
+    }
+
+    fn outbound_client() -> Arc<ctx::transport::Client> {
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false)
+    }
+
+    /// Reads everything from `io`, in small reads, and then reads the EOF
+    /// again.
+    fn read_to_eof<T: Read>(io: &mut T) {
+        let mut buf = [0; 5];
+        while io.read(&mut buf).unwrap() > 0 {}
+        assert_eq!(io.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn counts_goaways_stream_resets_and_eofs_from_h2_servers() {
+        let sensors = Sensors::null();
+        let connect = sensors.connect(ConnectCursor(H2_FRAMES), &outbound_client())
+            .with_h2_frames();
+
+        let mut io = connect.connect().wait().expect("connect");
+        read_to_eof(&mut io);
+
+        let counts = sensors.terminations().snapshot();
+        assert_eq!(counts.len(), 3, "unexpected terminations: {:?}", counts);
+        assert_eq!(counts[&(Direction::Outbound, Termination::GoAway)], 1);
+        assert_eq!(counts[&(Direction::Outbound, Termination::ResetStream)], 1);
+        assert_eq!(counts[&(Direction::Outbound, Termination::Eof)], 1);
+    }
+
+    #[test]
+    fn does_not_read_frames_from_other_transports() {
+        let sensors = Sensors::null();
+        let connect = sensors.connect(ConnectCursor(H2_FRAMES), &outbound_client());
+
+        let mut io = connect.connect().wait().expect("connect");
+        read_to_eof(&mut io);
+
+        let counts = sensors.terminations().snapshot();
+        assert_eq!(counts.len(), 1, "unexpected terminations: {:?}", counts);
+        assert_eq!(counts[&(Direction::Outbound, Termination::Eof)], 1);
+    }
+
+    #[test]
+    fn counts_connection_resets() {
+        let sensors = Sensors::null();
+        let connect = sensors.connect(ConnectReset, &outbound_client());
+
+        let mut io = connect.connect().wait().expect("connect");
+        let mut buf = [0; 16];
+        let err = io.read(&mut buf).expect_err("read should fail");
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert!(io.write(b"hi").is_err());
+
+        let counts = sensors.terminations().snapshot();
+        assert_eq!(counts.len(), 1, "unexpected terminations: {:?}", counts);
+        assert_eq!(counts[&(Direction::Outbound, Termination::TcpReset)], 1);
+    }
+
+    #[test]
+    fn publishes_connection_lifecycle_events_with_byte_counts() {
+        let sensors = Sensors::null();
+        let mut events = sensors.subscribe(10);
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let connect = sensors.connect(ConnectCursor(b"hello"), &client_ctx);
+
+        let mut io = connect.connect().wait().expect("connect");
+        let mut buf = [0; 16];
+        assert_eq!(io.read(&mut buf).unwrap(), 5);
+        io.write_all(b"hi").unwrap();
+        drop(io);
+
+        let evs = future::lazy(|| {
+            let mut evs = Vec::new();
+            while let Async::Ready(Some(ev)) = events.poll()? {
+                evs.push(ev);
+            }
+            Ok::<_, ()>(evs)
+        }).wait().unwrap();
+        assert_eq!(evs.len(), 2, "unexpected events: {:?}", evs);
+        match evs[0] {
+            LifecycleEvent::Connection(ConnectionEvent::Opened(_)) => {}
+            ref ev => panic!("expected a connection open event: {:?}", ev),
+        }
+        match evs[1] {
+            LifecycleEvent::Connection(ConnectionEvent::Closed {
+                clean, rx_bytes, tx_bytes, ..
+            }) => {
+                assert!(clean);
+                assert_eq!(rx_bytes, 5);
+                assert_eq!(tx_bytes, 2);
+            }
+            ref ev => panic!("expected a connection close event: {:?}", ev),
+        }
+    }
+
+    #[test]
+    fn counts_bytes_by_peer_across_partial_reads_and_writes() {
+        let sensors = Sensors::null();
+
+        let process = ctx::Process::test("test");
+        let proxy = ctx::Proxy::outbound(&process);
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let client_ctx = ctx::transport::Client::new(&proxy, &addr, Protocol::Http, false);
+        let connect = sensors.connect(ConnectCursor(b"hello"), &client_ctx);
+
+        let mut io = connect.connect().wait().expect("connect");
+        let mut buf = [0; 2];
+        let mut reads = Vec::new();
+        loop {
+            match io.read(&mut buf).unwrap() {
+                0 => break,
+                n => reads.push(n),
+            }
+        }
+        assert_eq!(reads, vec![2, 2, 1]);
+        io.write_all(b"h").unwrap();
+        io.write_all(b"i").unwrap();
+
+        let peer = Peer {
+            direction: Direction::Outbound,
+            addr,
+        };
+        assert_eq!(sensors.byte_counts().peers()[&peer], Bytes { rx: 5, tx: 2 });
+
+        drop(io);
+        assert!(sensors.byte_counts().peers().is_empty());
+    }
+}
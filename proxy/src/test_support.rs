@@ -0,0 +1,283 @@
+//! Mock upstreams and bodies shared by the tests of HTTP middleware.
+//!
+//! Each mock accepts `http::Request<()>`s. An `Upstream` responds
+//! immediately with `Chunks`, and an `Unresponsive` upstream never responds,
+//! keeping the requests it's sent so that tests may inspect them. An
+//! `Endpoint` stands in for a bound endpoint, whose readiness and failures
+//! tests control.
+//!
+//! `send` drives a request through a service as the task that owns the
+//! service would.
+
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use bytes::Bytes;
+use futures::{future, Async, Future, Poll};
+use futures::future::{Empty, FutureResult};
+use h2;
+use http;
+use tower::Service;
+use tower_h2::Body;
+
+/// An upstream that responds immediately to each request, with the response
+/// that `respond` returns for the number of requests it has received,
+/// counting from 1.
+#[derive(Clone)]
+pub struct Upstream {
+    respond: Rc<Fn(usize) -> http::Response<Chunks>>,
+    calls: Rc<Cell<usize>>,
+}
+
+/// A body that sends a series of chunks, followed by trailers, if it has
+/// any.
+#[derive(Debug, Default)]
+pub struct Chunks {
+    chunks: VecDeque<Bytes>,
+    trailers: Option<http::HeaderMap>,
+}
+
+/// An upstream that never responds, keeping the requests it receives.
+///
+/// Clones share the same requests.
+#[derive(Clone, Debug, Default)]
+pub struct Unresponsive(Rc<RefCell<Vec<http::Request<()>>>>);
+
+/// A body that never ends.
+#[derive(Debug, Default)]
+pub struct PendingBody;
+
+/// An endpoint that responds to each request with `200 OK`, or with the
+/// statuses it was scripted with, keeping the requests it receives.
+///
+/// It's ready, and doesn't fail, until it's set otherwise. Clones share the
+/// same state.
+#[derive(Clone, Debug, Default)]
+pub struct Endpoint(Rc<EndpointState>);
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    unready: Cell<bool>,
+    failing: Cell<bool>,
+    statuses: RefCell<VecDeque<http::StatusCode>>,
+    requests: RefCell<Vec<http::Request<()>>>,
+}
+
+/// Builds headers from `(name, value)` pairs.
+pub fn headers(headers: &[(&'static str, &'static str)]) -> http::HeaderMap {
+    let mut map = http::HeaderMap::new();
+    for &(name, value) in headers {
+        map.append(name, value.parse().unwrap());
+    }
+    map
+}
+
+/// Sends `req` to `svc`, polling `svc` for readiness while its response is
+/// pending, as the task that owns a service does, so that the service may
+/// dispatch retries.
+pub fn send<'a, S>(
+    svc: &'a mut S,
+    req: S::Request,
+) -> Box<Future<Item = S::Response, Error = S::Error> + 'a>
+where
+    S: Service + 'a,
+    S::Request: 'a,
+    S::Future: 'a,
+{
+    let mut req = Some(req);
+    let mut rsp = None;
+    Box::new(future::poll_fn(move || {
+        if rsp.is_none() {
+            rsp = Some(svc.call(req.take().expect("request sent once")));
+        }
+        let rsp = rsp.as_mut().expect("request sent");
+        if let Async::Ready(rsp) = rsp.poll()? {
+            return Ok(Async::Ready(rsp));
+        }
+        svc.poll_ready()?;
+        rsp.poll()
+    }))
+}
+
+// ===== impl Upstream =====
+
+impl Upstream {
+    pub fn new<F>(respond: F) -> Self
+    where
+        F: Fn(usize) -> http::Response<Chunks> + 'static,
+    {
+        Upstream {
+            respond: Rc::new(respond),
+            calls: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Responds to every request with `headers` and a body of `chunks`.
+    pub fn with_body(headers: http::HeaderMap, chunks: &'static [&'static str]) -> Self {
+        Upstream::new(move |_| {
+            let mut rsp = http::Response::new(Chunks::new(chunks));
+            *rsp.headers_mut() = headers.clone();
+            rsp
+        })
+    }
+
+    /// Returns the number of requests received, which is updated as more
+    /// are.
+    pub fn calls(&self) -> Rc<Cell<usize>> {
+        self.calls.clone()
+    }
+}
+
+impl Service for Upstream {
+    type Request = http::Request<()>;
+    type Response = http::Response<Chunks>;
+    type Error = h2::Error;
+    type Future = FutureResult<Self::Response, h2::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: Self::Request) -> Self::Future {
+        self.calls.set(self.calls.get() + 1);
+        future::ok((self.respond)(self.calls.get()))
+    }
+}
+
+// ===== impl Chunks =====
+
+impl Chunks {
+    pub fn new<S: AsRef<str>>(chunks: &[S]) -> Self {
+        Chunks {
+            chunks: chunks.iter().map(|c| Bytes::from(c.as_ref())).collect(),
+            trailers: None,
+        }
+    }
+
+    pub fn with_trailers(self, trailers: http::HeaderMap) -> Self {
+        Chunks {
+            trailers: Some(trailers),
+            ..self
+        }
+    }
+}
+
+impl Body for Chunks {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.chunks.is_empty() && self.trailers.is_none()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        Ok(Async::Ready(self.chunks.pop_front()))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        Ok(Async::Ready(self.trailers.take()))
+    }
+}
+
+// ===== impl Unresponsive =====
+
+impl Unresponsive {
+    /// Returns the requests received so far.
+    pub fn requests(&self) -> Ref<Vec<http::Request<()>>> {
+        self.0.borrow()
+    }
+}
+
+impl Service for Unresponsive {
+    type Request = http::Request<()>;
+    type Response = http::Response<PendingBody>;
+    type Error = ();
+    type Future = Empty<Self::Response, ()>;
+
+    fn poll_ready(&mut self) -> Poll<(), ()> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.0.borrow_mut().push(req);
+        future::empty()
+    }
+}
+
+// ===== impl Endpoint =====
+
+impl Endpoint {
+    /// Responds with each of `statuses` in turn, and then with `200 OK`.
+    pub fn with_statuses(statuses: &[u16]) -> Self {
+        let endpoint = Endpoint::default();
+        endpoint.0.statuses.borrow_mut().extend(statuses.iter().map(|&status| {
+            http::StatusCode::from_u16(status).expect("valid status")
+        }));
+        endpoint
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.0.unready.set(!ready);
+    }
+
+    /// Fails requests with an error, rather than responding, while
+    /// `failing` is set.
+    pub fn set_failing(&self, failing: bool) {
+        self.0.failing.set(failing);
+    }
+
+    /// Returns the requests received so far.
+    pub fn requests(&self) -> Ref<Vec<http::Request<()>>> {
+        self.0.requests.borrow()
+    }
+
+    /// Returns the number of requests received so far.
+    pub fn calls(&self) -> usize {
+        self.0.requests.borrow().len()
+    }
+}
+
+impl Service for Endpoint {
+    type Request = http::Request<()>;
+    type Response = http::Response<()>;
+    type Error = ();
+    type Future = FutureResult<Self::Response, ()>;
+
+    fn poll_ready(&mut self) -> Poll<(), ()> {
+        if self.0.unready.get() {
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.0.requests.borrow_mut().push(req);
+        if self.0.failing.get() {
+            return future::err(());
+        }
+        let mut rsp = http::Response::new(());
+        if let Some(status) = self.0.statuses.borrow_mut().pop_front() {
+            *rsp.status_mut() = status;
+        }
+        future::ok(rsp)
+    }
+}
+
+// ===== impl PendingBody =====
+
+impl Body for PendingBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        false
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        Ok(Async::NotReady)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        Ok(Async::NotReady)
+    }
+}
@@ -1,3 +1,6 @@
+use std::{error, fmt};
+use std::time::Duration;
+
 use futures::{Async, Future, Poll};
 use h2;
 use http;
@@ -9,11 +12,53 @@ use tower_h2::{self, Body};
 
 use bind;
 use telemetry::sensor::http::RequestBody;
+use transport::keepalive::{self, Liveness, PingConfig};
+use super::expect::{ContinueTimeout, ExpectContinue};
 use super::glue::{BodyStream, HttpBody, HyperConnect};
 use super::h1::UriIsAbsoluteForm;
+use super::idle::{Active, Idle, IdleTimeout};
+use super::pool::{ConnLimit, Dispatched, Dispatchers, IdleLimit};
 
 type HyperClient<C, B> =
-    hyper::Client<HyperConnect<C>, BodyStream<RequestBody<B>>>;
+    hyper::Client<HyperConnect<C>, BodyStream<ExpectContinue<RequestBody<B>>>>;
+
+/// The largest flow-control window permitted by RFC 7540.
+const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/// The size of a new connection's flow-control window, which may only be
+/// grown, per RFC 7540.
+const DEFAULT_CONNECTION_WINDOW_SIZE: u32 = 65_535;
+
+/// The smallest `SETTINGS_MAX_CONCURRENT_STREAMS` that RFC 7540 recommends.
+const MIN_MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// Settings for the pool of HTTP/1 client connections.
+///
+/// Settings that are not configured use the hyper defaults, which retain
+/// every idle connection for up to 90 seconds.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct H1Settings {
+    max_idle: Option<usize>,
+    idle_timeout: Option<Duration>,
+    expect_continue_timeout: Option<Duration>,
+}
+
+/// HTTP/2 settings for client connections.
+///
+/// Settings that are not configured use the `h2` defaults.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct H2Settings {
+    initial_stream_window_size: Option<u32>,
+    initial_connection_window_size: Option<u32>,
+    max_concurrent_streams: Option<u32>,
+}
+
+/// Indicates that an `H2Settings` value is not permitted by RFC 7540.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InvalidH2Setting {
+    setting: &'static str,
+    value: u32,
+}
 
 /// A `NewService` that can speak either HTTP/1 or HTTP/2.
 pub struct Client<C, B>
@@ -27,8 +72,24 @@ enum ClientInner<C, B>
 where
     B: tower_h2::Body + 'static,
 {
-    Http1(HyperClient<C, B>),
-    Http2(tower_h2::client::Connect<C, Handle, RequestBody<B>>),
+    Http1(HyperClient<C, B>, Option<IdleLimit>, Option<ContinueTimeout>, Dispatchers),
+    Http2(H2Connect<C>, IdleTimeout),
+}
+
+/// Connects to an HTTP/2 upstream, giving each connection its own `Liveness`
+/// if idle connections are validated before they're reused.
+struct H2Connect<C> {
+    connect: keepalive::Connect<C>,
+    builder: h2::client::Builder,
+    executor: Handle,
+    idle_validation: Option<Duration>,
+}
+
+/// Validates an HTTP/2 connection that has been idle for `idle` before it's
+/// reused.
+struct Validate {
+    liveness: Liveness,
+    idle: Duration,
 }
 
 /// A `Future` returned from `Client::new_service()`.
@@ -45,8 +106,12 @@ where
     B: tower_h2::Body + 'static,
     C: Connect + 'static,
 {
-    Http1(Option<HyperClient<C, B>>),
-    Http2(tower_h2::client::ConnectFuture<C, Handle, RequestBody<B>>),
+    Http1(Option<(HyperClient<C, B>, Option<IdleLimit>, Option<ContinueTimeout>, Dispatchers)>),
+    Http2(
+        tower_h2::client::ConnectFuture<keepalive::Connect<C>, Handle, RequestBody<B>>,
+        IdleTimeout,
+        Option<Validate>,
+    ),
 }
 
 /// The `Service` yielded by `Client::new_service()`.
@@ -63,12 +128,15 @@ where
     B: tower_h2::Body + 'static,
     C: Connect
 {
-    Http1(HyperClient<C, B>),
-    Http2(tower_h2::client::Connection<
-        <C as Connect>::Connected,
-        Handle,
-        RequestBody<B>,
-    >),
+    Http1(HyperClient<C, B>, Option<IdleLimit>, Option<ContinueTimeout>, Dispatchers),
+    Http2(
+        Idle<tower_h2::client::Connection<
+            keepalive::Pinging<<C as Connect>::Connected>,
+            Handle,
+            RequestBody<B>,
+        >>,
+        Option<Validate>,
+    ),
 }
 
 impl<C, B> Client<C, B>
@@ -78,22 +146,56 @@ where
     B: tower_h2::Body + 'static,
 {
     /// Create a new `Client`, bound to a specific protocol (HTTP/1 or HTTP/2).
+    ///
+    /// `h1_settings` apply only to HTTP/1 clients, and `h2_settings` only to
+    /// HTTP/2 clients.
+    ///
+    /// If an `idle_timeout` is set, an HTTP/2 connection is closed once it
+    /// has had no active streams for that long. It is also the keep-alive
+    /// timeout of pooled HTTP/1 connections, unless `h1_settings` has one.
+    ///
+    /// If a `PingConfig` is provided, HTTP/2 connections are PINGed, and are
+    /// closed when a PING is not acknowledged in time. If it validates idle
+    /// connections, a connection that has idled is PINGed again before it's
+    /// reused, and is replaced if the PING isn't acknowledged.
+    ///
+    /// If a `ConnLimit` is provided, HTTP/1 clients wait for one of its slots
+    /// before each new connection.
     pub fn new(protocol: &bind::Protocol,
                connect: C,
+               h1_settings: &H1Settings,
+               conn_limit: Option<ConnLimit>,
+               h2_settings: &H2Settings,
+               idle_timeout: Option<Duration>,
+               ping: Option<PingConfig>,
                executor: Handle)
                -> Self
     {
         match *protocol {
-            bind::Protocol::Http1(_) => {
-                let h1 = hyper::Client::configure()
-                    .connector(HyperConnect::new(connect))
+            bind::Protocol::Http1(_) | bind::Protocol::Http1Upgrade(_) => {
+                let dispatchers = Dispatchers::default();
+                let mut h1 = hyper::Client::configure()
+                    .connector(HyperConnect::new(connect, conn_limit, &dispatchers))
                     .body()
                     // hyper should never try to automatically set the Host
                     // header, instead always just passing whatever we received.
                     .set_host(false)
-                    .build(&executor);
+                    // An upgraded connection can't be reused for other
+                    // requests, so each upgrade gets its own connection.
+                    .keep_alive(!protocol.is_upgrade());
+                if let Some(timeout) = h1_settings.idle_timeout.or(idle_timeout) {
+                    h1 = h1.keep_alive_timeout(Some(timeout));
+                }
+                let idle_limit = if protocol.is_upgrade() {
+                    None
+                } else {
+                    h1_settings.max_idle.map(IdleLimit::new)
+                };
+                let continue_timeout = h1_settings.expect_continue_timeout
+                    .map(|timeout| ContinueTimeout::new(timeout, &executor));
+                let h1 = h1.build(&executor);
                 Client {
-                    inner: ClientInner::Http1(h1),
+                    inner: ClientInner::Http1(h1, idle_limit, continue_timeout, dispatchers),
                 }
             },
             bind::Protocol::Http2 => {
@@ -101,16 +203,169 @@ where
                 // h2 currently doesn't handle PUSH_PROMISE that well, so we just
                 // disable it for now.
                 h2_builder.enable_push(false);
-                let h2 = tower_h2::client::Connect::new(connect, h2_builder, executor);
+                h2_settings.configure(&mut h2_builder);
+                let idle_timeout = IdleTimeout::new(idle_timeout, executor.clone());
+                let h2 = H2Connect {
+                    connect: keepalive::Connect::new(connect, ping, &executor),
+                    builder: h2_builder,
+                    executor,
+                    idle_validation: ping.and_then(|ping| ping.idle_validation()),
+                };
 
                 Client {
-                    inner: ClientInner::Http2(h2),
+                    inner: ClientInner::Http2(h2, idle_timeout),
                 }
             }
         }
     }
 }
 
+// ===== impl H2Connect =====
+
+impl<C> H2Connect<C>
+where
+    C: Connect + Clone + 'static,
+    C::Future: 'static,
+{
+    fn connect<B>(
+        &self,
+    ) -> (
+        tower_h2::client::ConnectFuture<keepalive::Connect<C>, Handle, RequestBody<B>>,
+        Option<Validate>,
+    )
+    where
+        B: tower_h2::Body + 'static,
+    {
+        let validate = self.idle_validation.map(|idle| Validate {
+            liveness: Liveness::default(),
+            idle,
+        });
+        let connect = match validate {
+            Some(ref validate) => self.connect.clone().with_liveness(validate.liveness.clone()),
+            None => self.connect.clone(),
+        };
+        let h2 = tower_h2::client::Connect::new(
+            connect,
+            self.builder.clone(),
+            self.executor.clone(),
+        );
+        (h2.new_service(), validate)
+    }
+}
+
+// ===== impl H1Settings =====
+
+impl H1Settings {
+    /// Sets the number of idle connections that may be kept alive for reuse.
+    ///
+    /// Connections beyond this number are closed once their responses
+    /// complete.
+    pub fn with_max_idle(self, max_idle: usize) -> Self {
+        Self {
+            max_idle: Some(max_idle),
+            ..self
+        }
+    }
+
+    /// Sets how long an idle connection is kept alive for reuse.
+    pub fn with_idle_timeout(self, idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout: Some(idle_timeout),
+            ..self
+        }
+    }
+
+    /// Sets how long a request that expects `100 Continue` waits for the
+    /// server to respond before its body is sent anyway.
+    ///
+    /// By default, the body is withheld until the server responds.
+    pub fn with_expect_continue_timeout(self, timeout: Duration) -> Self {
+        Self {
+            expect_continue_timeout: Some(timeout),
+            ..self
+        }
+    }
+}
+
+// ===== impl H2Settings =====
+
+impl H2Settings {
+    /// Sets the initial flow-control window of each stream, by which the
+    /// proxy limits how much response data a server may send before it is
+    /// read.
+    pub fn with_initial_stream_window_size(self, size: u32) -> Result<Self, InvalidH2Setting> {
+        if size > MAX_WINDOW_SIZE {
+            return Err(InvalidH2Setting::new("initial stream window size", size));
+        }
+        Ok(Self {
+            initial_stream_window_size: Some(size),
+            ..self
+        })
+    }
+
+    /// Sets the flow-control window shared by all streams on a connection.
+    ///
+    /// The connection window may not be smaller than its initial size of
+    /// 65,535 bytes.
+    pub fn with_initial_connection_window_size(
+        self,
+        size: u32,
+    ) -> Result<Self, InvalidH2Setting> {
+        if size < DEFAULT_CONNECTION_WINDOW_SIZE || size > MAX_WINDOW_SIZE {
+            return Err(InvalidH2Setting::new("initial connection window size", size));
+        }
+        Ok(Self {
+            initial_connection_window_size: Some(size),
+            ..self
+        })
+    }
+
+    /// Sets the `SETTINGS_MAX_CONCURRENT_STREAMS` advertised to servers.
+    ///
+    /// Values below the minimum of 100 that RFC 7540 recommends are rejected.
+    pub fn with_max_concurrent_streams(self, max: u32) -> Result<Self, InvalidH2Setting> {
+        if max < MIN_MAX_CONCURRENT_STREAMS {
+            return Err(InvalidH2Setting::new("max concurrent streams", max));
+        }
+        Ok(Self {
+            max_concurrent_streams: Some(max),
+            ..self
+        })
+    }
+
+    fn configure(&self, builder: &mut h2::client::Builder) {
+        if let Some(size) = self.initial_stream_window_size {
+            builder.initial_window_size(size);
+        }
+        if let Some(size) = self.initial_connection_window_size {
+            builder.initial_connection_window_size(size);
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+    }
+}
+
+// ===== impl InvalidH2Setting =====
+
+impl InvalidH2Setting {
+    fn new(setting: &'static str, value: u32) -> Self {
+        InvalidH2Setting { setting, value }
+    }
+}
+
+impl fmt::Display for InvalidH2Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid HTTP/2 {}: {}", self.setting, self.value)
+    }
+}
+
+impl error::Error for InvalidH2Setting {
+    fn description(&self) -> &str {
+        "invalid HTTP/2 setting"
+    }
+}
+
 impl<C, B> NewService for Client<C, B>
 where
     C: Connect + Clone + 'static,
@@ -126,11 +381,19 @@ where
 
     fn new_service(&self) -> Self::Future {
         let inner = match self.inner {
-            ClientInner::Http1(ref h1) => {
-                ClientNewServiceFutureInner::Http1(Some(h1.clone()))
+            ClientInner::Http1(ref h1, ref idle_limit, ref continue_timeout, ref dispatchers) => {
+                let h1 = (
+                    h1.clone(),
+                    idle_limit.clone(),
+                    continue_timeout.clone(),
+                    dispatchers.clone(),
+                );
+                ClientNewServiceFutureInner::Http1(Some(h1))
+            },
+            ClientInner::Http2(ref h2, ref idle_timeout) => {
+                let (connecting, validate) = h2.connect();
+                ClientNewServiceFutureInner::Http2(connecting, idle_timeout.clone(), validate)
             },
-            ClientInner::Http2(ref h2) => {
-                ClientNewServiceFutureInner::Http2(h2.new_service())                        },
         };
         ClientNewServiceFuture {
             inner,
@@ -149,11 +412,13 @@ where
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let inner = match self.inner {
             ClientNewServiceFutureInner::Http1(ref mut h1) => {
-                ClientServiceInner::Http1(h1.take().expect("poll more than once"))
+                let (h1, idle_limit, continue_timeout, dispatchers) =
+                    h1.take().expect("poll more than once");
+                ClientServiceInner::Http1(h1, idle_limit, continue_timeout, dispatchers)
             },
-            ClientNewServiceFutureInner::Http2(ref mut h2) => {
+            ClientNewServiceFutureInner::Http2(ref mut h2, ref idle_timeout, ref mut validate) => {
                 let s = try_ready!(h2.poll());
-                ClientServiceInner::Http2(s)
+                ClientServiceInner::Http2(idle_timeout.watch(s), validate.take())
             },
         };
         Ok(Async::Ready(ClientService {
@@ -164,7 +429,7 @@ where
 
 impl<C, B> Service for ClientService<C, B>
 where
-    C: Connect + 'static,
+    C: Connect + Clone + 'static,
     C::Future: 'static,
     B: tower_h2::Body + 'static,
 {
@@ -175,8 +440,29 @@ where
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         match self.inner {
-            ClientServiceInner::Http1(_) => Ok(Async::Ready(())),
-            ClientServiceInner::Http2(ref mut h2) => h2.poll_ready(),
+            ClientServiceInner::Http1(..) => Ok(Async::Ready(())),
+            ClientServiceInner::Http2(ref h2, ref validate) => {
+                // Once the connection has been closed for idling, this
+                // service fails, so that it is replaced by a new connection.
+                try_ready!(h2.with_conn(|conn| conn.poll_ready())
+                    .unwrap_or_else(|| Err(h2::Reason::NO_ERROR.into())));
+
+                // A connection that has idled may have been lost without
+                // being closed, so it's only reused once it has answered a
+                // PING. One that doesn't is replaced, like a closed one.
+                if let Some(ref validate) = *validate {
+                    if h2.idle_for().map(|idle| idle >= validate.idle).unwrap_or(false) {
+                        let alive: Poll<(), Self::Error> = validate.liveness
+                            .poll_alive(validate.idle)
+                            .map_err(|e| {
+                                debug!("idle connection failed validation: {}", e);
+                                h2::Reason::NO_ERROR.into()
+                            });
+                        try_ready!(alive);
+                    }
+                }
+                Ok(Async::Ready(()))
+            },
         }
     }
 
@@ -184,7 +470,21 @@ where
         use http::header::CONTENT_LENGTH;
 
         match self.inner {
-            ClientServiceInner::Http1(ref h1) => {
+            ClientServiceInner::Http1(
+                ref h1,
+                ref idle_limit,
+                ref continue_timeout,
+                ref dispatchers,
+            ) => {
+                // An HTTP/2 request may be sent to an upstream that is forced
+                // to HTTP/1. As with HTTP/2 upstreams, its response is given
+                // the request's version.
+                let version = req.version();
+                let mut req = req;
+                if version == http::Version::HTTP_2 {
+                    super::h1::translate_to_h1(&mut req);
+                }
+
                 // This sentinel may be set in h1::normalize_our_view_of_uri
                 // when the original request-target was in absolute-form. In
                 // that case, for hyper 0.11.x, we need to call `req.set_proxy`.
@@ -216,23 +516,66 @@ where
                 // `HttpBody::is_end_stream()` manages that distinction for us.
                 let should_take_body = req.body().is_end_stream()
                     && !req.headers().contains_key(CONTENT_LENGTH);
-                let mut req = hyper::Request::from(req.map(BodyStream::new));
+                // A request that expects `100 Continue` sends its body only
+                // once the upstream has accepted it, so that the client is
+                // told to continue only then, or once the upstream has
+                // failed to respond in time.
+                let (parts, body) = req.into_parts();
+                let body = ExpectContinue::new(body, &parts.headers, continue_timeout.clone());
+                let req = http::Request::from_parts(parts, BodyStream::new(body));
+                let mut req = hyper::Request::from(req);
                 if should_take_body {
                     req.body_mut().take();
                 }
                 req.set_proxy(was_absolute_form);
-                ClientServiceFuture::Http1(h1.request(req))
+                let in_flight = idle_limit.as_ref().map(|limit| {
+                    let (in_flight, pool) = limit.dispatch();
+                    if !pool {
+                        req.headers_mut().set(hyper::header::Connection::close());
+                    }
+                    in_flight
+                });
+                let dispatched = Dispatched::new(in_flight, dispatchers);
+                // Requests are never pipelined: hyper dispatches a request
+                // only on an idle connection, and a connection isn't idle
+                // again until its response, including the body, has been
+                // read. While every pooled connection is busy, the request
+                // is sent on a new connection instead, once the `ConnLimit`
+                // permits one.
+                ClientServiceFuture::Http1(h1.request(req), Some(dispatched), version)
             },
-            ClientServiceInner::Http2(ref mut h2) => {
-                ClientServiceFuture::Http2(h2.call(req))
+            ClientServiceInner::Http2(ref h2, _) => {
+                // An HTTP/1 request may be sent to an upstream known to speak
+                // HTTP/2. Its response is given the request's version, so
+                // that it is served to the client over HTTP/1.
+                let version = req.version();
+                let mut req = req;
+                if version != http::Version::HTTP_2 {
+                    super::h1::translate_to_h2(&mut req);
+                }
+
+                let active = h2.dispatch();
+                match h2.with_conn(|conn| conn.call(req)) {
+                    Some(f) => ClientServiceFuture::Http2(f, Some(active), version),
+                    None => ClientServiceFuture::Closed,
+                }
             },
         }
     }
 }
 
+/// A response from an upstream.
+///
+/// Dropping this before it completes, as when the client disconnects,
+/// cancels the request: an HTTP/2 stream is reset with `CANCEL`, and hyper
+/// closes an HTTP/1 connection, since it can't be reused until the response
+/// would have been read. The same is true of a response body dropped before
+/// it has been read to its end.
 pub enum ClientServiceFuture {
-    Http1(hyper::client::FutureResponse),
-    Http2(tower_h2::client::ResponseFuture),
+    Http1(hyper::client::FutureResponse, Option<Dispatched>, http::Version),
+    Http2(tower_h2::client::ResponseFuture, Option<Active>, http::Version),
+    /// The connection was closed for idling before the request was sent.
+    Closed,
 }
 
 impl Future for ClientServiceFuture {
@@ -241,11 +584,18 @@ impl Future for ClientServiceFuture {
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         match *self {
-            ClientServiceFuture::Http1(ref mut f) => {
+            ClientServiceFuture::Http1(ref mut f, ref mut dispatched, version) => {
                 match f.poll() {
                     Ok(Async::Ready(res)) => {
-                        let res = http::Response::from(res);
-                        let res = res.map(HttpBody::Http1);
+                        let mut res = http::Response::from(res);
+                        if version == http::Version::HTTP_2 {
+                            // Responses served over HTTP/1 are stripped by the
+                            // server, but HTTP/2 forbids hop-by-hop headers.
+                            super::h1::strip_connection_headers(res.headers_mut());
+                            *res.version_mut() = version;
+                        }
+                        let dispatched = dispatched.take();
+                        let res = res.map(move |body| HttpBody::Http1(body, dispatched));
                         Ok(Async::Ready(res))
                     },
                     Ok(Async::NotReady) => Ok(Async::NotReady),
@@ -255,12 +605,889 @@ impl Future for ClientServiceFuture {
                     }
                 }
             },
-            ClientServiceFuture::Http2(ref mut f) => {
-                let res = try_ready!(f.poll());
-                let res = res.map(HttpBody::Http2);
+            ClientServiceFuture::Http2(ref mut f, ref mut active, version) => {
+                let mut res = try_ready!(f.poll());
+                *res.version_mut() = version;
+                let active = active.take();
+                let res = res.map(move |body| HttpBody::Http2(body, active));
                 Ok(Async::Ready(res))
             }
+            ClientServiceFuture::Closed => Err(h2::Reason::REFUSED_STREAM.into()),
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashSet;
+    use std::io;
+    use std::io::{Read, Write};
+    use std::net::SocketAddr;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    use bytes::Bytes;
+    use futures::{future, Future, Stream};
+    use tokio_core::net::{TcpListener, TcpStream};
+    use tokio_core::reactor::{Core, Handle as ReactorHandle, Timeout as ReactorTimeout};
+    use tokio_io::{AsyncRead, AsyncWrite};
+    use tokio_io::io::read_exact;
+    use tower::NewService;
+    use tower_reconnect::Reconnect;
+
+    use bind;
+    use telemetry::sensor::http::RequestBody;
+    use transport;
+    use transport::keepalive::KeepaliveConfig;
+    use super::*;
+    use super::super::pool::ConnLimits;
+
+    const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+    const SETTINGS: u8 = 0x4;
+    const WINDOW_UPDATE: u8 = 0x8;
+    const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+    const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+    struct Frame {
+        kind: u8,
+        stream_id: u32,
+        payload: Vec<u8>,
+    }
+
+    fn read_frame(sock: TcpStream) -> Box<Future<Item = (TcpStream, Frame), Error = io::Error>> {
+        let frame = read_exact(sock, [0u8; 9]).and_then(|(sock, head)| {
+            let len = (head[0] as usize) << 16 | (head[1] as usize) << 8 | head[2] as usize;
+            let kind = head[3];
+            let stream_id = read_u32(&head[5..]) & !(1 << 31);
+            read_exact(sock, vec![0; len]).map(move |(sock, payload)| {
+                (sock, Frame { kind, stream_id, payload })
+            })
+        });
+        Box::new(frame)
+    }
+
+    fn read_u32(b: &[u8]) -> u32 {
+        (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+    }
+
+    fn settings(frame: &Frame) -> Vec<(u16, u32)> {
+        assert_eq!(frame.kind, SETTINGS, "expected a SETTINGS frame");
+        frame.payload
+            .chunks(6)
+            .map(|s| ((s[0] as u16) << 8 | s[1] as u16, read_u32(&s[2..])))
+            .collect()
+    }
+
+    /// Connects an HTTP/2 client configured with `h2_settings` to a server,
+    /// returning the first `n` frames the client sends after its preface.
+    fn handshake(h2_settings: H2Settings, n: usize) -> Vec<Frame> {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle)
+            .expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+
+        let client = Client::<_, HttpBody>::new(
+            &bind::Protocol::Http2,
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default(),
+            None,
+            &h2_settings,
+            None,
+            None,
+            handle.clone(),
+        );
+        // Hold on to the client's service so that its connection isn't
+        // closed before the frames are read.
+        let service = Rc::new(RefCell::new(None));
+        let svc = service.clone();
+        handle.spawn(client.new_service()
+            .map(move |s| *svc.borrow_mut() = Some(s))
+            .map_err(|_| panic!("connect failed")));
+
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(conn, _)| {
+                let (sock, _) = conn.expect("accept");
+                read_exact(sock, [0u8; 24])
+            })
+            .and_then(move |(sock, preface)| {
+                assert_eq!(&preface[..], PREFACE);
+                let frames = (0..n).fold(
+                    Box::new(future::ok((sock, Vec::new())))
+                        as Box<Future<Item = _, Error = io::Error>>,
+                    |f, _| Box::new(f.and_then(|(sock, mut frames)| {
+                        read_frame(sock).map(|(sock, frame)| {
+                            frames.push(frame);
+                            (sock, frames)
+                        })
+                    })),
+                );
+                frames.map(|(_, frames)| frames)
+            });
+        core.run(server).expect("read frames")
+    }
+
+    #[test]
+    fn settings_frame_reflects_configuration() {
+        let h2_settings = H2Settings::default()
+            .with_initial_stream_window_size(1_048_576)
+            .and_then(|s| s.with_max_concurrent_streams(500))
+            .expect("valid settings");
+        let frames = handshake(h2_settings, 1);
+        let settings = settings(&frames[0]);
+        assert!(settings.contains(&(SETTINGS_INITIAL_WINDOW_SIZE, 1_048_576)), "{:?}", settings);
+        assert!(settings.contains(&(SETTINGS_MAX_CONCURRENT_STREAMS, 500)), "{:?}", settings);
+    }
+
+    #[test]
+    fn connection_window_is_grown_after_settings() {
+        let h2_settings = H2Settings::default()
+            .with_initial_connection_window_size(4_194_304)
+            .expect("valid settings");
+        let frames = handshake(h2_settings, 2);
+        settings(&frames[0]);
+        assert_eq!(frames[1].kind, WINDOW_UPDATE);
+        assert_eq!(frames[1].stream_id, 0);
+        assert_eq!(read_u32(&frames[1].payload), 4_194_304 - DEFAULT_CONNECTION_WINDOW_SIZE);
+    }
+
+    #[test]
+    fn rejects_settings_outside_spec_limits() {
+        let s = H2Settings::default();
+        assert!(s.with_initial_stream_window_size(MAX_WINDOW_SIZE).is_ok());
+        assert!(s.with_initial_stream_window_size(MAX_WINDOW_SIZE + 1).is_err());
+        assert!(s.with_initial_connection_window_size(65_535).is_ok());
+        assert!(s.with_initial_connection_window_size(65_534).is_err());
+        assert!(s.with_initial_connection_window_size(MAX_WINDOW_SIZE + 1).is_err());
+        assert!(s.with_max_concurrent_streams(100).is_ok());
+        assert!(s.with_max_concurrent_streams(99).is_err());
+    }
+
+    /// Responds to HTTP/1 requests after a short delay, so that concurrent
+    /// requests are in flight at once, and records the connection on which
+    /// each request was served.
+    struct DelayedService {
+        conn: usize,
+        served: Rc<RefCell<HashSet<usize>>>,
+        handle: ReactorHandle,
+    }
+
+    impl hyper::server::Service for DelayedService {
+        type Request = hyper::server::Request;
+        type Response = hyper::server::Response;
+        type Error = hyper::Error;
+        type Future = Box<Future<Item = Self::Response, Error = hyper::Error>>;
+
+        fn call(&self, _: Self::Request) -> Self::Future {
+            self.served.borrow_mut().insert(self.conn);
+            let rsp = ReactorTimeout::new(Duration::from_millis(50), &self.handle)
+                .expect("timeout")
+                .map(|_| hyper::server::Response::new())
+                .map_err(hyper::Error::from);
+            Box::new(rsp)
+        }
+    }
+
+    /// The connections accepted by `serve_h1` or `serve_h2`.
+    #[derive(Clone, Default)]
+    struct Conns {
+        open: Rc<Cell<usize>>,
+        /// The most connections that have been open at once.
+        max_open: Rc<Cell<usize>>,
+        served: Rc<RefCell<HashSet<usize>>>,
+    }
+
+    fn serve_h1(handle: &ReactorHandle) -> (SocketAddr, Conns) {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle)
+            .expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let conns = Conns::default();
+
+        let h1 = hyper::server::Http::<hyper::Chunk>::new();
+        let (c, handle2) = (conns.clone(), handle.clone());
+        let mut accepted = 0;
+        let serve = listener.incoming().for_each(move |(sock, _)| {
+            accepted += 1;
+            let svc = DelayedService {
+                conn: accepted,
+                served: c.served.clone(),
+                handle: handle2.clone(),
+            };
+            let open = c.open.clone();
+            open.set(open.get() + 1);
+            c.max_open.set(::std::cmp::max(c.max_open.get(), open.get()));
+            let serve = h1.serve_connection(sock, svc).then(move |_| {
+                open.set(open.get() - 1);
+                Ok(())
+            });
+            handle2.spawn(serve);
+            Ok(())
+        });
+        handle.spawn(serve.map_err(|e| panic!("server failed: {}", e)));
+
+        (addr, conns)
+    }
+
+    fn get(addr: SocketAddr) -> bind::HttpRequest<HttpBody> {
+        let body = HttpBody::Http1(hyper::Body::empty(), None);
+        http::Request::get(format!("http://{}/", addr).as_str())
+            .body(RequestBody::new(body, None))
+            .unwrap()
+    }
+
+    #[test]
+    fn concurrent_h1_requests_open_connections_and_pool_up_to_max_idle() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, conns) = serve_h1(&handle);
+
+        let client = Client::<_, HttpBody>::new(
+            &bind::Protocol::Http1(bind::Host::NoAuthority),
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default().with_max_idle(2),
+            None,
+            &H2Settings::default(),
+            None,
+            None,
+            handle.clone(),
+        );
+        let mut service = core.run(client.new_service()).ok().expect("new service");
+
+        let burst = (0..4).map(|_| service.call(get(addr))).collect::<Vec<_>>();
+        let rsps = core.run(future::join_all(burst)).expect("burst");
+        assert!(rsps.iter().all(|rsp| rsp.status() == http::StatusCode::OK));
+        drop(rsps);
+        assert_eq!(conns.served.borrow().len(), 4, "each request used its own connection");
+
+        // Give the connections that weren't pooled a chance to close.
+        let wait = ReactorTimeout::new(Duration::from_millis(100), &handle).unwrap();
+        core.run(wait).unwrap();
+        assert_eq!(conns.open.get(), 2, "only max_idle connections are kept alive");
+
+        for _ in 0..3 {
+            let rsp = core.run(service.call(get(addr))).expect("request");
+            assert_eq!(rsp.status(), http::StatusCode::OK);
+        }
+        assert_eq!(conns.served.borrow().len(), 4, "sequential requests reuse the pool");
+        assert_eq!(conns.open.get(), 2);
+    }
+
+    #[test]
+    fn concurrent_h1_requests_are_queued_at_max_conns() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, conns) = serve_h1(&handle);
+
+        let limits = ConnLimits::default();
+        let client = |host: &str| Client::<_, HttpBody>::new(
+            &bind::Protocol::Http1(bind::Host::Authority(host.parse().unwrap())),
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default().with_max_idle(0),
+            Some(limits.limit(&addr, 2)),
+            &H2Settings::default(),
+            None,
+            None,
+            handle.clone(),
+        );
+        // Clients for different hosts don't share a pool, but do share the
+        // endpoint's limit.
+        let mut a = core.run(client("a.test").new_service()).ok().expect("new service");
+        let mut b = core.run(client("b.test").new_service()).ok().expect("new service");
+
+        // Responses are dropped as they're received, so that their
+        // connections may be closed.
+        let burst = (0..6)
+            .map(|i| {
+                let svc = if i % 2 == 0 { &mut a } else { &mut b };
+                svc.call(get(addr)).map(|rsp| rsp.status())
+            })
+            .collect::<Vec<_>>();
+        let statuses = core.run(future::join_all(burst)).expect("burst");
+        assert!(statuses.iter().all(|s| *s == http::StatusCode::OK));
+        assert_eq!(
+            conns.max_open.get(), 2,
+            "no more than max_conns connections are open at once"
+        );
+    }
+
+    /// Serves HTTP/1 requests on blocking sockets, responding to each with
+    /// its path as the body, written in two halves so that the response is
+    /// still incomplete for a while after its head has been sent.
+    ///
+    /// The returned flag is set if a request is ever received on a
+    /// connection before the previous response on it was complete.
+    fn serve_h1_slowly() -> (SocketAddr, Arc<AtomicBool>) {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let pipelined = Arc::new(AtomicBool::new(false));
+
+        let p = pipelined.clone();
+        thread::spawn(move || for sock in listener.incoming() {
+            let mut sock = sock.expect("accept");
+            let pipelined = p.clone();
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                loop {
+                    let end = loop {
+                        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break i + 4;
+                        }
+                        match sock.read(&mut chunk) {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                        }
+                    };
+                    let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+                    buf.drain(..end);
+                    if !buf.is_empty() {
+                        pipelined.store(true, Ordering::SeqCst);
+                    }
+                    let path = head.split(' ').nth(1).unwrap_or("").to_owned();
+                    let (first, second) = path.as_bytes().split_at(path.len() / 2);
+
+                    let rsp_head = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n",
+                        path.len()
+                    );
+                    let written = sock.write_all(rsp_head.as_bytes())
+                        .and_then(|_| sock.write_all(first));
+                    if written.is_err() {
+                        return;
+                    }
+                    thread::sleep(Duration::from_millis(20));
+
+                    // Anything the client has sent since is a pipelined request.
+                    sock.set_nonblocking(true).expect("set nonblocking");
+                    if let Ok(n) = sock.read(&mut chunk) {
+                        if n > 0 {
+                            pipelined.store(true, Ordering::SeqCst);
+                            buf.extend_from_slice(&chunk[..n]);
+                        }
+                    }
+                    sock.set_nonblocking(false).expect("set blocking");
+                    if sock.write_all(second).is_err() {
+                        return;
+                    }
+                }
+            });
+        });
+
+        (addr, pipelined)
+    }
+
+    fn read_body(rsp: http::Response<HttpBody>) -> Box<Future<Item = Vec<u8>, Error = String>> {
+        let (_, mut body) = rsp.into_parts();
+        let mut bytes = Vec::new();
+        Box::new(future::poll_fn(move || {
+            while let Some(data) = try_ready!(body.poll_data().map_err(|e| format!("{:?}", e))) {
+                bytes.extend_from_slice(&data);
+            }
+            Ok(Async::Ready(::std::mem::replace(&mut bytes, Vec::new())))
+        }))
+    }
+
+    #[test]
+    fn h1_requests_are_not_pipelined_on_a_busy_connection() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, pipelined) = serve_h1_slowly();
+
+        let limits = ConnLimits::default();
+        let client = Client::<_, HttpBody>::new(
+            &bind::Protocol::Http1(bind::Host::NoAuthority),
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default().with_max_idle(1),
+            Some(limits.limit(&addr, 1)),
+            &H2Settings::default(),
+            None,
+            None,
+            handle.clone(),
+        );
+        let mut service = core.run(client.new_service()).ok().expect("new service");
+
+        // Every request is sent at once, but the pool has only one
+        // connection, so each waits for the previous response to complete.
+        let requests = ["/first", "/second", "/third"].iter()
+            .map(|path| {
+                let body = HttpBody::Http1(hyper::Body::empty(), None);
+                let req = http::Request::get(format!("http://{}{}", addr, path).as_str())
+                    .body(RequestBody::new(body, None))
+                    .unwrap();
+                service.call(req)
+                    .map_err(|e| format!("{:?}", e))
+                    .and_then(read_body)
+                    .map(move |body| (path.to_string(), String::from_utf8(body).unwrap()))
+            })
+            .collect::<Vec<_>>();
+        let rsps = core.run(future::join_all(requests)).expect("responses");
+
+        for (path, body) in rsps {
+            assert_eq!(path, body, "response matches its request");
+        }
+        assert!(!pipelined.load(Ordering::SeqCst), "no request was pipelined");
+    }
+
+    /// Serves HTTP/1 requests on blocking sockets. A request for `/partial`
+    /// is answered with only the first half of its body, after which the
+    /// upstream is silent until the connection is closed; every other
+    /// request is answered with an empty response.
+    ///
+    /// Returns the number of connections accepted, and the number closed by
+    /// the client.
+    fn serve_h1_partially() -> (SocketAddr, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let closed = Arc::new(AtomicUsize::new(0));
+
+        let (a, c) = (accepted.clone(), closed.clone());
+        thread::spawn(move || for sock in listener.incoming() {
+            let mut sock = sock.expect("accept");
+            a.fetch_add(1, Ordering::SeqCst);
+            let closed = c.clone();
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                loop {
+                    let end = loop {
+                        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break i + 4;
+                        }
+                        match sock.read(&mut chunk) {
+                            Ok(0) | Err(_) => {
+                                closed.fetch_add(1, Ordering::SeqCst);
+                                return;
+                            }
+                            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                        }
+                    };
+                    let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+                    buf.drain(..end);
+                    let rsp: &[u8] = if head.starts_with("GET /partial ") {
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello"
+                    } else {
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"
+                    };
+                    if sock.write_all(rsp).is_err() {
+                        return;
+                    }
+                }
+            });
+        });
+
+        (addr, accepted, closed)
+    }
+
+    #[test]
+    fn abandoned_h1_responses_close_their_connections() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, accepted, closed) = serve_h1_partially();
+
+        let limits = ConnLimits::default();
+        let client = Client::<_, HttpBody>::new(
+            &bind::Protocol::Http1(bind::Host::NoAuthority),
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default().with_max_idle(1),
+            Some(limits.limit(&addr, 1)),
+            &H2Settings::default(),
+            None,
+            None,
+            handle.clone(),
+        );
+        let mut service = core.run(client.new_service()).ok().expect("new service");
+
+        let body = HttpBody::Http1(hyper::Body::empty(), None);
+        let req = http::Request::get(format!("http://{}/partial", addr).as_str())
+            .body(RequestBody::new(body, None))
+            .unwrap();
+        let rsp = core.run(service.call(req)).ok().expect("response");
+        let (_, mut body) = rsp.into_parts();
+        let data = core.run(future::poll_fn(|| body.poll_data())).ok().expect("data");
+        assert_eq!(data, Some(Bytes::from("hello")));
+
+        // The rest of the body never arrives, so the connection can't be
+        // reused, and is closed once the body is dropped, rather than
+        // holding the endpoint's only slot.
+        drop(body);
+        sleep(&mut core, Duration::from_millis(100));
+        assert_eq!(closed.load(Ordering::SeqCst), 1, "the connection should be closed");
+
+        let rsp = core.run(service.call(get(addr))).ok().expect("response");
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        let body = core.run(read_body(rsp)).expect("body");
+        assert!(body.is_empty());
+        assert_eq!(accepted.load(Ordering::SeqCst), 2, "a new connection should be opened");
+    }
+
+    #[test]
+    fn complete_h1_responses_reuse_their_connections() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, accepted, closed) = serve_h1_partially();
+
+        let client = Client::<_, HttpBody>::new(
+            &bind::Protocol::Http1(bind::Host::NoAuthority),
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default().with_max_idle(1),
+            None,
+            &H2Settings::default(),
+            None,
+            None,
+            handle.clone(),
+        );
+        let mut service = core.run(client.new_service()).ok().expect("new service");
+
+        for _ in 0..3 {
+            let rsp = core.run(service.call(get(addr))).ok().expect("response");
+            core.run(read_body(rsp)).expect("body");
+            sleep(&mut core, Duration::from_millis(20));
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1, "the connection should be reused");
+        assert_eq!(closed.load(Ordering::SeqCst), 0);
+    }
+
+    /// Serves HTTP/2 requests with empty responses.
+    fn serve_h2(handle: &ReactorHandle) -> (SocketAddr, Conns) {
+        serve_h2_with_status(handle, http::StatusCode::OK)
+    }
+
+    /// Serves HTTP/2, responding to each request with `status`.
+    fn serve_h2_with_status(
+        handle: &ReactorHandle,
+        status: http::StatusCode,
+    ) -> (SocketAddr, Conns) {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle)
+            .expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let conns = Conns::default();
+
+        let (c, handle2) = (conns.clone(), handle.clone());
+        let mut accepted = 0;
+        let serve = listener.incoming().for_each(move |(sock, _)| {
+            accepted += 1;
+            let conn = accepted;
+            let (open, served) = (c.open.clone(), c.served.clone());
+            open.set(open.get() + 1);
+            let serve = h2::server::handshake(sock)
+                .and_then(move |h2| h2.for_each(move |(_, mut respond)| {
+                    served.borrow_mut().insert(conn);
+                    let mut rsp = http::Response::new(());
+                    *rsp.status_mut() = status;
+                    respond.send_response(rsp, true).map(|_| ())
+                }))
+                .then(move |_| {
+                    open.set(open.get() - 1);
+                    Ok(())
+                });
+            handle2.spawn(serve);
+            Ok(())
+        });
+        handle.spawn(serve.map_err(|e| panic!("server failed: {}", e)));
+
+        (addr, conns)
+    }
+
+    fn send<S>(core: &mut Core, service: &mut S, req: S::Request) -> S::Response
+    where
+        S: Service,
+    {
+        core.run(future::poll_fn(|| service.poll_ready())).ok().expect("ready");
+        core.run(service.call(req)).ok().expect("response")
+    }
+
+    fn sleep(core: &mut Core, duration: Duration) {
+        let timeout = ReactorTimeout::new(duration, &core.handle()).unwrap();
+        core.run(timeout).unwrap();
+    }
+
+    /// Connects an HTTP/2 client whose connections idle for up to 100ms.
+    fn h2_client(
+        addr: SocketAddr,
+        handle: &ReactorHandle,
+    ) -> Reconnect<Client<transport::Connect, HttpBody>> {
+        let client = Client::new(
+            &bind::Protocol::Http2,
+            transport::Connect::new(addr, handle),
+            &H1Settings::default(),
+            None,
+            &H2Settings::default(),
+            Some(Duration::from_millis(100)),
+            None,
+            handle.clone(),
+        );
+        Reconnect::new(client)
+    }
+
+    #[test]
+    fn idle_h2_connections_are_closed_and_reconnected() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, conns) = serve_h2(&handle);
+        let mut service = h2_client(addr, &handle);
+
+        let rsp = send(&mut core, &mut service, get(addr));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        drop(rsp);
+        assert_eq!(conns.open.get(), 1);
+
+        sleep(&mut core, Duration::from_millis(300));
+        assert_eq!(conns.open.get(), 0, "the idle connection should be closed");
+
+        let rsp = send(&mut core, &mut service, get(addr));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(conns.served.borrow().len(), 2, "the request should reconnect");
+        assert_eq!(conns.open.get(), 1);
+    }
+
+    #[test]
+    fn h2_connections_with_active_streams_are_not_idle() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, conns) = serve_h2(&handle);
+        let mut service = h2_client(addr, &handle);
+
+        // The stream is active until its response body is dropped.
+        let rsp = send(&mut core, &mut service, get(addr));
+        sleep(&mut core, Duration::from_millis(300));
+        assert_eq!(conns.open.get(), 1, "the active connection should be kept open");
+
+        drop(rsp);
+        sleep(&mut core, Duration::from_millis(300));
+        assert_eq!(conns.open.get(), 0, "the idle connection should be closed");
+    }
+
+    #[test]
+    fn acknowledged_pings_keep_h2_connections_open() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, conns) = serve_h2(&handle);
+        let ms = Duration::from_millis;
+        let client = Client::new(
+            &bind::Protocol::Http2,
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default(),
+            None,
+            &H2Settings::default(),
+            None,
+            KeepaliveConfig::default().with_ping(ms(20), ms(100)).ping(),
+            handle.clone(),
+        );
+        let mut service = Reconnect::new(client);
+
+        let rsp = send(&mut core, &mut service, get(addr));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        drop(rsp);
+
+        // Several PINGs are sent and acknowledged in this time.
+        sleep(&mut core, ms(300));
+        assert_eq!(conns.open.get(), 1, "the connection should be kept open");
+
+        let rsp = send(&mut core, &mut service, get(addr));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(conns.served.borrow().len(), 1, "the connection should be reused");
+    }
+
+    #[test]
+    fn error_responses_do_not_reconnect() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, conns) = serve_h2_with_status(&handle, http::StatusCode::INTERNAL_SERVER_ERROR);
+        let client = Client::new(
+            &bind::Protocol::Http2,
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default(),
+            None,
+            &H2Settings::default(),
+            None,
+            None,
+            handle.clone(),
+        );
+        let mut service = Reconnect::new(client);
+
+        // Only a failure of the connection itself, and not an error response
+        // received over it, causes `Reconnect` to reconnect.
+        for _ in 0..3 {
+            let rsp = send(&mut core, &mut service, get(addr));
+            assert_eq!(rsp.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        assert_eq!(conns.served.borrow().len(), 1, "the connection should be reused");
+        assert_eq!(conns.open.get(), 1);
+    }
+
+    /// A connection that, once it's lost, reads nothing more and discards
+    /// what's written to it, as if its peer had gone away without closing it.
+    struct Lossy {
+        io: TcpStream,
+        lost: Rc<Cell<bool>>,
+    }
+
+    impl Read for Lossy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.lost.get() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.io.read(buf)
+        }
+    }
+
+    impl Write for Lossy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.lost.get() {
+                return Ok(buf.len());
+            }
+            self.io.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if self.lost.get() {
+                return Ok(());
+            }
+            self.io.flush()
+        }
+    }
+
+    impl AsyncRead for Lossy {}
+
+    impl AsyncWrite for Lossy {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            AsyncWrite::shutdown(&mut self.io)
+        }
+    }
+
+    /// Flags with which each of the `Lossy` connections accepted so far may
+    /// be lost.
+    type Losses = Rc<RefCell<Vec<Rc<Cell<bool>>>>>;
+
+    /// Serves HTTP/2 requests with empty responses over `Lossy` connections.
+    fn serve_h2_lossy(handle: &ReactorHandle) -> (SocketAddr, Conns, Losses) {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle)
+            .expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let conns = Conns::default();
+        let lost = Losses::default();
+
+        let (c, l, handle2) = (conns.clone(), lost.clone(), handle.clone());
+        let serve = listener.incoming().for_each(move |(sock, _)| {
+            let conn = l.borrow().len() + 1;
+            let flag = Rc::new(Cell::new(false));
+            l.borrow_mut().push(flag.clone());
+            let served = c.served.clone();
+            let sock = Lossy { io: sock, lost: flag };
+            let serve = h2::server::handshake(sock)
+                .and_then(move |h2| h2.for_each(move |(_, mut respond)| {
+                    served.borrow_mut().insert(conn);
+                    respond.send_response(http::Response::new(()), true).map(|_| ())
+                }))
+                .then(|_| Ok(()));
+            handle2.spawn(serve);
+            Ok(())
+        });
+        handle.spawn(serve.map_err(|e| panic!("server failed: {}", e)));
+
+        (addr, conns, lost)
+    }
+
+    #[test]
+    fn lost_idle_h2_connections_are_validated_and_reconnected() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, conns, lost) = serve_h2_lossy(&handle);
+        let ms = Duration::from_millis;
+        // Periodic PINGs are sent too rarely to notice that a connection was
+        // lost before it's reused.
+        let keepalive = KeepaliveConfig::default()
+            .with_ping(Duration::from_secs(60), ms(100))
+            .with_idle_validation(ms(50));
+        let client = Client::new(
+            &bind::Protocol::Http2,
+            transport::Connect::new(addr, &handle),
+            &H1Settings::default(),
+            None,
+            &H2Settings::default(),
+            None,
+            keepalive.ping(),
+            handle.clone(),
+        );
+        let mut service = Reconnect::new(client);
+
+        let rsp = send(&mut core, &mut service, get(addr));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        drop(rsp);
+
+        // An idle connection that answers the validation PING is reused.
+        sleep(&mut core, ms(100));
+        let rsp = send(&mut core, &mut service, get(addr));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        drop(rsp);
+        assert_eq!(conns.served.borrow().len(), 1, "the connection should be reused");
+
+        // One that was lost while idle is replaced before the request is sent.
+        for conn in lost.borrow().iter() {
+            conn.set(true);
+        }
+        sleep(&mut core, ms(100));
+        let rsp = send(&mut core, &mut service, get(addr));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(conns.served.borrow().len(), 2, "the request should reconnect");
+    }
+
+    type PartialStreams = Rc<RefCell<Vec<h2::SendStream<Bytes>>>>;
+
+    /// Serves HTTP/2 requests with responses whose bodies are never
+    /// finished, returning the streams on which the rest of each body would
+    /// be sent.
+    fn serve_h2_partially(handle: &ReactorHandle) -> (SocketAddr, PartialStreams) {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle)
+            .expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let streams = PartialStreams::default();
+
+        let (s, handle2) = (streams.clone(), handle.clone());
+        let serve = listener.incoming().for_each(move |(sock, _)| {
+            let streams = s.clone();
+            let serve = h2::server::handshake(sock)
+                .and_then(move |h2| h2.for_each(move |(_, mut respond)| {
+                    let mut stream = respond.send_response(http::Response::new(()), false)?;
+                    stream.send_data(Bytes::from("hello"), false)?;
+                    streams.borrow_mut().push(stream);
+                    Ok(())
+                }))
+                .then(|_| Ok(()));
+            handle2.spawn(serve);
+            Ok(())
+        });
+        handle.spawn(serve.map_err(|e| panic!("server failed: {}", e)));
+
+        (addr, streams)
+    }
+
+    #[test]
+    fn abandoned_h2_responses_reset_their_streams() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (addr, streams) = serve_h2_partially(&handle);
+        let mut service = h2_client(addr, &handle);
+
+        let rsp = send(&mut core, &mut service, get(addr));
+        let (_, mut body) = rsp.into_parts();
+        let data = core.run(future::poll_fn(|| body.poll_data())).ok().expect("data");
+        assert_eq!(data, Some(Bytes::from("hello")));
+
+        // Once the body is dropped, the stream is reset, so the upstream
+        // can't send the rest of it.
+        drop(body);
+        sleep(&mut core, Duration::from_millis(50));
+        let mut streams = streams.borrow_mut();
+        assert_eq!(streams.len(), 1);
+        assert!(streams[0].send_data(Bytes::from("world"), true).is_err());
+    }
+}
@@ -0,0 +1,474 @@
+use std::cell::Cell;
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use futures::{task, Async, Future, Poll};
+use h2;
+use http;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tower_h2::Body;
+
+use super::h1;
+
+/// The longest interim response head that is buffered while waiting for an
+/// upstream to accept a request's body.
+const MAX_INTERIM_HEAD_LEN: usize = 8 * 1024;
+
+/// The progress of an `Expect: 100-continue` handshake on an HTTP/1 client
+/// connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Handshake {
+    /// No request on the connection is waiting to send its body.
+    Idle,
+    /// A request is waiting for the server's interim response.
+    Expecting,
+    /// The server responded `100 Continue`, so the body may be sent.
+    Continued,
+    /// The server sent its final response without asking for the body.
+    Rejected,
+    /// The server didn't respond in time, so the body was sent anyway, and a
+    /// late `100 Continue` is still expected.
+    TimedOut,
+}
+
+// hyper dispatches each HTTP/1 client connection on its own task, which both
+// reads the connection and polls the body of the request being sent on it,
+// so the handshake of a connection's current request is tracked per task.
+task_local! {
+    static HANDSHAKE: Cell<Handshake> = Cell::new(Handshake::Idle)
+}
+
+/// Withholds a request's body until the server accepts it with an interim
+/// `100 Continue` response, if the request expects one.
+///
+/// The body is not polled until then, so that a client whose request was
+/// received by hyper is not told to continue before the server has agreed.
+/// If the server instead responds without asking for the body, the body
+/// fails without ever having been polled, so that the connection is not
+/// reused with a partially sent request, and the client's body is dropped
+/// unread.
+///
+/// If there is a `ContinueTimeout` and the server hasn't responded once it
+/// elapses, the body is sent anyway, as RFC 7231 requires, rather than
+/// stalling on a server that ignores the expectation.
+#[derive(Debug)]
+pub(super) struct ExpectContinue<B> {
+    /// The client's body, until it's discarded.
+    body: Option<B>,
+    expecting: bool,
+    timeout: Option<ContinueTimeout>,
+    timer: Option<Timeout>,
+}
+
+/// How long a request waits for `100 Continue` before sending its body.
+#[derive(Clone, Debug)]
+pub(super) struct ContinueTimeout {
+    timeout: Duration,
+    handle: Handle,
+}
+
+/// Strips the interim `100 Continue` responses read from an HTTP/1 client
+/// connection, advancing the handshake of the request waiting on them.
+///
+/// hyper 0.11's client can't handle interim responses itself. Responses are
+/// only inspected while a request is waiting for one, since that is the only
+/// time the next bytes read are known to start a response head.
+#[derive(Debug)]
+pub(super) struct StripContinue<T> {
+    io: T,
+    head: BytesMut,
+}
+
+// ===== impl ExpectContinue =====
+
+impl<B: Body> ExpectContinue<B> {
+    /// Withholds `body`, if `headers` expect `100 Continue` and there is a
+    /// body to withhold, until the server continues or `timeout` elapses.
+    pub fn new(body: B, headers: &http::HeaderMap, timeout: Option<ContinueTimeout>) -> Self {
+        let expecting = !body.is_end_stream() && h1::expects_continue(headers);
+        ExpectContinue {
+            body: Some(body),
+            expecting,
+            timeout,
+            timer: None,
+        }
+    }
+
+    fn poll_continue(&mut self) -> Poll<(), h2::Error> {
+        if self.body.is_none() {
+            return Err(h2::Reason::CANCEL.into());
+        }
+        if !self.expecting {
+            return Ok(Async::Ready(()));
+        }
+
+        match HANDSHAKE.with(|h| h.replace(Handshake::Expecting)) {
+            Handshake::Continued => {
+                HANDSHAKE.with(|h| h.set(Handshake::Idle));
+                self.expecting = false;
+                return Ok(Async::Ready(()));
+            },
+            Handshake::Rejected => {
+                trace!("server responded before accepting request body");
+                HANDSHAKE.with(|h| h.set(Handshake::Idle));
+                self.body = None;
+                return Err(h2::Reason::CANCEL.into());
+            },
+            Handshake::Idle | Handshake::Expecting | Handshake::TimedOut => {},
+        }
+
+        // The timeout starts once the request's head has been sent, when
+        // its body is first polled.
+        if let Some(ref timeout) = self.timeout {
+            let timer = self.timer.get_or_insert_with(|| timeout.start());
+            if let Ok(Async::NotReady) = timer.poll() {
+                return Ok(Async::NotReady);
+            }
+            trace!("server didn't respond within {:?}; sending request body", timeout.timeout);
+            HANDSHAKE.with(|h| h.set(Handshake::TimedOut));
+            self.expecting = false;
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl<B: Body> Body for ExpectContinue<B> {
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.body.as_ref().map_or(false, Body::is_end_stream)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        try_ready!(self.poll_continue());
+        self.body.as_mut().expect("body is polled until discarded").poll_data()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        try_ready!(self.poll_continue());
+        self.body.as_mut().expect("body is polled until discarded").poll_trailers()
+    }
+}
+
+// ===== impl ContinueTimeout =====
+
+impl ContinueTimeout {
+    pub fn new(timeout: Duration, handle: &Handle) -> Self {
+        ContinueTimeout {
+            timeout,
+            handle: handle.clone(),
+        }
+    }
+
+    fn start(&self) -> Timeout {
+        Timeout::new(self.timeout, &self.handle).expect("Timeout::new() won't fail")
+    }
+}
+
+// ===== impl StripContinue =====
+
+impl<T> StripContinue<T> {
+    pub fn new(io: T) -> Self {
+        StripContinue {
+            io,
+            head: BytesMut::new(),
+        }
+    }
+
+    /// Advances the handshake with the response head read so far.
+    ///
+    /// Returns false if more of the head must be read to do so.
+    fn advance(&mut self) -> bool {
+        let code = match interim_status(&self.head) {
+            Some(code) => code,
+            None => return false,
+        };
+        if code == Some(http::StatusCode::CONTINUE) {
+            let len = match head_len(&self.head) {
+                Some(len) => len,
+                None if self.head.len() < MAX_INTERIM_HEAD_LEN => return false,
+                // Leave the oversized head for hyper to fail on.
+                None => return self.reject(),
+            };
+            self.head.split_to(len);
+            if HANDSHAKE.with(|h| h.get()) == Handshake::TimedOut {
+                trace!("server accepted request body after it was sent");
+                return self.handshake(Handshake::Idle);
+            }
+            trace!("server accepted request body");
+            self.handshake(Handshake::Continued)
+        } else {
+            // Anything else ends the handshake, and is left for hyper to read.
+            self.reject()
+        }
+    }
+
+    /// Ends the handshake without a `100 Continue`, rejecting the body if it
+    /// hasn't been sent.
+    fn reject(&self) -> bool {
+        match HANDSHAKE.with(|h| h.get()) {
+            Handshake::TimedOut => self.handshake(Handshake::Idle),
+            _ => self.handshake(Handshake::Rejected),
+        }
+    }
+
+    fn handshake(&self, handshake: Handshake) -> bool {
+        HANDSHAKE.with(|h| h.set(handshake));
+        // Wake the dispatcher, so that it polls the waiting body again.
+        task::current().notify();
+        true
+    }
+}
+
+impl<T: Read> Read for StripContinue<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let handshake = HANDSHAKE.with(|h| h.get());
+            if handshake != Handshake::Expecting && handshake != Handshake::TimedOut {
+                if self.head.is_empty() {
+                    return self.io.read(buf);
+                }
+                let n = cmp::min(buf.len(), self.head.len());
+                buf[..n].copy_from_slice(&self.head.split_to(n));
+                return Ok(n);
+            }
+
+            if self.head.is_empty() || !self.advance() {
+                let mut chunk = [0; 1024];
+                let n = self.io.read(&mut chunk)?;
+                if n == 0 {
+                    // Leave the truncated head, if any, for hyper to fail on.
+                    self.reject();
+                    continue;
+                }
+                self.head.extend_from_slice(&chunk[..n]);
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for StripContinue<T> {}
+
+impl<T: Write> Write for StripContinue<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for StripContinue<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+/// Parses the status code of a response head, once enough of it has been
+/// read.
+///
+/// Returns `Some(None)` if the head does not start with a valid HTTP/1
+/// status line.
+fn interim_status(head: &[u8]) -> Option<Option<http::StatusCode>> {
+    // "HTTP/1.1 100 "
+    const STATUS_LINE_PREFIX_LEN: usize = 13;
+    if head.len() < STATUS_LINE_PREFIX_LEN {
+        let valid_prefix = b"HTTP/1."
+            .iter()
+            .zip(head)
+            .all(|(a, b)| a == b);
+        return if valid_prefix { None } else { Some(None) };
+    }
+    if !head.starts_with(b"HTTP/1.") || head[8] != b' ' {
+        return Some(None);
+    }
+    Some(http::StatusCode::from_bytes(&head[9..12]).ok())
+}
+
+/// Returns the length of a response head, including the blank line that ends
+/// it, if it has been read entirely.
+fn head_len(head: &[u8]) -> Option<usize> {
+    head.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use bytes::Bytes;
+    use futures::future;
+    use tokio_core::reactor::Core;
+
+    use transport::mock;
+    use super::*;
+
+    /// A body of a single chunk, which records whether it's been dropped.
+    struct Chunk {
+        data: Option<Bytes>,
+        dropped: Rc<Cell<bool>>,
+    }
+
+    impl Body for Chunk {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    impl Drop for Chunk {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    /// Returns a body that expects `100 Continue` for up to `timeout`, and
+    /// whether its inner body has been dropped.
+    fn expecting(timeout: Option<ContinueTimeout>) -> (ExpectContinue<Chunk>, Rc<Cell<bool>>) {
+        let dropped = Rc::new(Cell::new(false));
+        let chunk = Chunk {
+            data: Some(Bytes::from("hello")),
+            dropped: dropped.clone(),
+        };
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::EXPECT, "100-continue".parse().unwrap());
+        (ExpectContinue::new(chunk, &headers, timeout), dropped)
+    }
+
+    fn read_all<T: Read>(io: &mut T) -> Vec<u8> {
+        let mut read = Vec::new();
+        let mut buf = [0; 64];
+        loop {
+            match io.read(&mut buf) {
+                Ok(0) => return read,
+                Ok(n) => read.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return read,
+                Err(e) => panic!("read failed: {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn strips_continue_only_while_expecting() {
+        let mut core = Core::new().unwrap();
+        let (client, mut server) = mock::Io::pair();
+        let mut client = StripContinue::new(client);
+        server.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        server.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").unwrap();
+
+        let (handshake, read) = core.run(future::lazy(|| {
+            HANDSHAKE.with(|h| h.set(Handshake::Expecting));
+            let read = read_all(&mut client);
+            Ok::<_, ()>((HANDSHAKE.with(|h| h.get()), read))
+        })).unwrap();
+        assert_eq!(handshake, Handshake::Continued);
+        assert_eq!(&read[..], &b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"[..]);
+
+        // Without a request waiting, an interim response is left for hyper.
+        server.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        let read = core.run(future::lazy(|| Ok::<_, ()>(read_all(&mut client)))).unwrap();
+        assert_eq!(&read[..], &b"HTTP/1.1 100 Continue\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn final_responses_reject_bodies() {
+        let mut core = Core::new().unwrap();
+        let (client, mut server) = mock::Io::pair();
+        let mut client = StripContinue::new(client);
+
+        // The status line may be split across reads.
+        server.write_all(b"HTTP/1.1 4").unwrap();
+        let (handshake, read) = core.run(future::lazy(|| {
+            HANDSHAKE.with(|h| h.set(Handshake::Expecting));
+            let read = read_all(&mut client);
+            Ok::<_, ()>((HANDSHAKE.with(|h| h.get()), read))
+        })).unwrap();
+        assert_eq!(handshake, Handshake::Expecting);
+        assert!(read.is_empty());
+
+        server.write_all(b"17 Expectation Failed\r\n\r\n").unwrap();
+        let (handshake, read) = core.run(future::lazy(|| {
+            HANDSHAKE.with(|h| h.set(Handshake::Expecting));
+            let read = read_all(&mut client);
+            Ok::<_, ()>((HANDSHAKE.with(|h| h.get()), read))
+        })).unwrap();
+        assert_eq!(handshake, Handshake::Rejected);
+        assert_eq!(&read[..], &b"HTTP/1.1 417 Expectation Failed\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn bodies_are_sent_once_continue_times_out() {
+        let mut core = Core::new().unwrap();
+        let timeout = ContinueTimeout::new(Duration::from_millis(20), &core.handle());
+        let (mut body, _) = expecting(Some(timeout));
+        let (client, mut server) = mock::Io::pair();
+        let mut client = StripContinue::new(client);
+
+        let (data, handshake) = core.run(future::poll_fn(|| {
+            let data = try_ready!(body.poll_data());
+            Ok::<_, h2::Error>(Async::Ready((data, HANDSHAKE.with(|h| h.get()))))
+        })).unwrap();
+        assert_eq!(data, Some(Bytes::from("hello")));
+        assert_eq!(handshake, Handshake::TimedOut);
+
+        // A `100 Continue` sent once the body has been is still stripped.
+        server.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        server.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").unwrap();
+        let (handshake, read) = core.run(future::lazy(|| {
+            HANDSHAKE.with(|h| h.set(Handshake::TimedOut));
+            let read = read_all(&mut client);
+            Ok::<_, ()>((HANDSHAKE.with(|h| h.get()), read))
+        })).unwrap();
+        assert_eq!(handshake, Handshake::Idle);
+        assert_eq!(&read[..], &b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn bodies_are_withheld_without_a_timeout() {
+        let mut core = Core::new().unwrap();
+        let (mut body, _) = expecting(None);
+        let mut timer = Timeout::new(Duration::from_millis(20), &core.handle()).unwrap();
+
+        let polled = core.run(future::poll_fn(|| {
+            if body.poll_data().unwrap().is_ready() {
+                return Ok(Async::Ready(true));
+            }
+            try_ready!(timer.poll());
+            Ok::<_, io::Error>(Async::Ready(false))
+        })).unwrap();
+        assert!(!polled, "the body must not be sent before the server continues");
+    }
+
+    #[test]
+    fn early_final_responses_discard_bodies() {
+        let mut core = Core::new().unwrap();
+        let timeout = ContinueTimeout::new(Duration::from_millis(20), &core.handle());
+        let (mut body, dropped) = expecting(Some(timeout));
+
+        let (first, second, handshake) = core.run(future::lazy(|| {
+            assert!(body.poll_data().unwrap().is_not_ready());
+            HANDSHAKE.with(|h| h.set(Handshake::Rejected));
+            let first = body.poll_data().is_err();
+            let second = body.poll_data().is_err();
+            Ok::<_, ()>((first, second, HANDSHAKE.with(|h| h.get())))
+        })).unwrap();
+        assert!(first && second, "the body must fail once rejected");
+        assert_eq!(handshake, Handshake::Idle);
+        assert!(dropped.get(), "the client's body must be discarded");
+    }
+}
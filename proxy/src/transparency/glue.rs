@@ -14,13 +14,24 @@ use tower::{Service, NewService};
 use tower_h2;
 
 use ctx::transport::{Server as ServerCtx};
+use super::expect::StripContinue;
 use super::h1;
+use super::idle::Active;
+use super::pool::{ConnLimit, Dispatched, Dispatchers, Limited, Slot};
 
 /// Glue between `hyper::Body` and `tower_h2::RecvBody`.
 #[derive(Debug)]
 pub enum HttpBody {
-    Http1(hyper::Body),
-    Http2(tower_h2::RecvBody),
+    /// An HTTP/1 body, which, if it is a client's response body, holds its
+    /// request in flight until dropped. If it's dropped before it has been
+    /// read to its end, its connection is closed.
+    Http1(hyper::Body, Option<Dispatched>),
+    /// An HTTP/2 body, which, if it is a client's response body, holds its
+    /// stream active until dropped.
+    Http2(tower_h2::RecvBody, Option<Active>),
+    /// A body that has been read into memory, so that it may be sent more
+    /// than once.
+    Buffered(Option<Bytes>, Option<http::HeaderMap>),
 }
 
 /// Glue for `tower_h2::Body`s to be used in hyper.
@@ -64,14 +75,26 @@ pub(super) struct HttpBodyNewSvcFuture<F> {
 }
 
 /// Glue for any `tokio_connect::Connect` to implement `hyper::client::Connect`.
+///
+/// If there is a `ConnLimit`, each connection waits for one of its slots
+/// before connecting. Each connection is registered with the client's
+/// `Dispatchers`.
 #[derive(Debug, Clone)]
 pub(super) struct HyperConnect<C> {
     connect: C,
+    limit: Option<ConnLimit>,
+    dispatchers: Dispatchers,
 }
 
 /// Future returned by `HyperConnect`.
-pub(super) struct HyperConnectFuture<F> {
-    inner: F,
+pub(super) struct HyperConnectFuture<C: Connect> {
+    state: ConnectState<C>,
+}
+
+enum ConnectState<C: Connect> {
+    /// Waiting for a slot, so that the connection may be opened.
+    Waiting(C, ConnLimit, Dispatchers),
+    Connecting(C::Future, Option<Slot>, Dispatchers),
 }
 
 // ===== impl HttpBody =====
@@ -81,17 +104,23 @@ impl tower_h2::Body for HttpBody {
 
     fn is_end_stream(&self) -> bool {
         match *self {
-            HttpBody::Http1(ref b) => b.is_empty(),
-            HttpBody::Http2(ref b) => b.is_end_stream(),
+            HttpBody::Http1(ref b, _) => b.is_empty(),
+            HttpBody::Http2(ref b, _) => b.is_end_stream(),
+            HttpBody::Buffered(ref data, ref trailers) => data.is_none() && trailers.is_none(),
         }
     }
 
     fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
         match *self {
-            HttpBody::Http1(ref mut b) => {
+            HttpBody::Http1(ref mut b, ref mut dispatched) => {
                 match b.poll() {
                     Ok(Async::Ready(Some(chunk))) => Ok(Async::Ready(Some(chunk.into()))),
-                    Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+                    Ok(Async::Ready(None)) => {
+                        if let Some(ref mut dispatched) = *dispatched {
+                            dispatched.complete();
+                        }
+                        Ok(Async::Ready(None))
+                    },
                     Ok(Async::NotReady) => Ok(Async::NotReady),
                     Err(e) => {
                         debug!("http/1 body error: {}", e);
@@ -99,21 +128,25 @@ impl tower_h2::Body for HttpBody {
                     }
                 }
             },
-            HttpBody::Http2(ref mut b) => b.poll_data().map(|async| async.map(|opt| opt.map(|data| data.into()))),
+            HttpBody::Http2(ref mut b, _) => b.poll_data().map(|async| async.map(|opt| opt.map(|data| data.into()))),
+            HttpBody::Buffered(ref mut data, _) => Ok(Async::Ready(data.take())),
         }
     }
 
     fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
         match *self {
-            HttpBody::Http1(_) => Ok(Async::Ready(None)),
-            HttpBody::Http2(ref mut b) => b.poll_trailers(),
+            // hyper discards the trailers of chunked HTTP/1 bodies, so there
+            // are never any to forward.
+            HttpBody::Http1(..) => Ok(Async::Ready(None)),
+            HttpBody::Http2(ref mut b, _) => b.poll_trailers(),
+            HttpBody::Buffered(_, ref mut trailers) => Ok(Async::Ready(trailers.take())),
         }
     }
 }
 
 impl Default for HttpBody {
     fn default() -> HttpBody {
-        HttpBody::Http2(Default::default())
+        HttpBody::Http2(Default::default(), None)
     }
 }
 
@@ -215,11 +248,22 @@ where
         }
 
         let mut req: http::Request<hyper::Body> = req.into();
-        req.extensions_mut().insert(self.srv_ctx.clone());
 
+        // A connection that opens with an upgrade is tunneled before it's
+        // served (see `Server::serve`). Any other upgrade would fail once the
+        // upstream switched protocols, as hyper can't hand the connection
+        // over, so it's refused before it's forwarded.
+        if h1::is_upgrade(req.headers()) {
+            debug!("HTTP/1.1 upgrade is only supported as a connection's first request");
+            let res = hyper::Response::new()
+                .with_status(hyper::StatusCode::NotImplemented);
+            return Either::B(future::ok(res));
+        }
+
+        req.extensions_mut().insert(self.srv_ctx.clone());
         h1::strip_connection_headers(req.headers_mut());
 
-        let req = req.map(|b| HttpBody::Http1(b));
+        let req = req.map(|b| HttpBody::Http1(b, None));
         let f = HyperServerSvcFuture {
             inner: self.service.borrow_mut().call(req),
         };
@@ -265,7 +309,7 @@ where
     }
 
     fn call(&mut self, req: Self::Request) -> Self::Future {
-        self.service.call(req.map(|b| HttpBody::Http2(b)))
+        self.service.call(req.map(|b| HttpBody::Http2(b, None)))
     }
 }
 
@@ -320,39 +364,56 @@ where
     C: Connect,
     C::Future: 'static,
 {
-    pub fn new(connect: C) -> Self {
+    pub fn new(connect: C, limit: Option<ConnLimit>, dispatchers: &Dispatchers) -> Self {
         HyperConnect {
             connect,
+            limit,
+            dispatchers: dispatchers.clone(),
         }
     }
 }
 
 impl<C> hyper::client::Service for HyperConnect<C>
 where
-    C: Connect,
+    C: Connect + Clone + 'static,
     C::Future: 'static,
 {
     type Request = hyper::Uri;
-    type Response = C::Connected;
+    type Response = StripContinue<Limited<C::Connected>>;
     type Error = io::Error;
-    type Future = HyperConnectFuture<C::Future>;
+    type Future = HyperConnectFuture<C>;
 
     fn call(&self, _uri: Self::Request) -> Self::Future {
-        HyperConnectFuture {
-            inner: self.connect.connect(),
-        }
+        let dispatchers = self.dispatchers.clone();
+        let state = match self.limit {
+            Some(ref limit) => {
+                ConnectState::Waiting(self.connect.clone(), limit.clone(), dispatchers)
+            }
+            None => ConnectState::Connecting(self.connect.connect(), None, dispatchers),
+        };
+        HyperConnectFuture { state }
     }
 }
 
-impl<F> Future for HyperConnectFuture<F>
-where
-    F: Future,
-{
-    type Item = F::Item;
+impl<C: Connect> Future for HyperConnectFuture<C> {
+    type Item = StripContinue<Limited<C::Connected>>;
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.inner.poll()
-            .map_err(|_| io::ErrorKind::Other.into())
+        loop {
+            let next = match self.state {
+                ConnectState::Waiting(ref connect, ref limit, ref dispatchers) => {
+                    let slot = try_ready!(limit.poll_acquire());
+                    ConnectState::Connecting(connect.connect(), Some(slot), dispatchers.clone())
+                }
+                ConnectState::Connecting(ref mut f, ref mut slot, ref dispatchers) => {
+                    let io = try_ready!(f.poll()
+                        .map_err(|_| io::Error::from(io::ErrorKind::Other)));
+                    let io = Limited::new(io, slot.take(), dispatchers);
+                    return Ok(Async::Ready(StripContinue::new(io)));
+                }
+            };
+            self.state = next;
+        }
     }
 }
@@ -4,10 +4,25 @@ use std::sync::Arc;
 
 use bytes::BytesMut;
 use http;
-use http::header::HOST;
+use http::header::{HOST, TE};
 use http::uri::{Authority, Parts, Scheme, Uri};
 use ctx::transport::{Server as ServerCtx};
 
+/// Headers that describe only a single connection, and so must not be
+/// forwarded by a proxy (RFC 7230, section 6.1). `Proxy-Connection` isn't
+/// standard, but is sent by some old clients as if it were `Connection`.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
 /// Sentinel extension to signal that we should tell hyper to serialize
 /// the `Uri` in absolute-form.
 pub struct UriIsAbsoluteForm;
@@ -30,20 +45,27 @@ pub fn normalize_our_view_of_uri<B>(req: &mut http::Request<B>) {
     }
 
     // last resort is to use the so_original_dst
-    let orig_dst = req.extensions()
-        .get::<Arc<ServerCtx>>()
-        .and_then(|ctx| ctx.orig_dst_if_not_local());
-    if let Some(orig_dst) = orig_dst {
-        let mut bytes = BytesMut::with_capacity(31);
-        write!(&mut bytes, "{}", orig_dst)
-            .expect("socket address display is under 31 bytes");
-        let bytes = bytes.freeze();
-        let auth = Authority::from_shared(bytes)
-            .expect("socket address is valid authority");
+    if let Some(auth) = authority_from_orig_dst(&req) {
         set_authority(req.uri_mut(), auth);
     }
 }
 
+/// Returns an Authority from a request's original destination, according to
+/// `SO_ORIGINAL_DST`.
+pub fn authority_from_orig_dst<B>(req: &http::Request<B>) -> Option<Authority> {
+    let orig_dst = req.extensions()
+        .get::<Arc<ServerCtx>>()
+        .and_then(|ctx| ctx.orig_dst_if_not_local())?;
+    // Large enough for any IPv6 address, with its scope, port, and brackets.
+    let mut bytes = BytesMut::with_capacity(64);
+    write!(&mut bytes, "{}", orig_dst)
+        .expect("socket address display is under 64 bytes");
+    let bytes = bytes.freeze();
+    let auth = Authority::from_shared(bytes)
+        .expect("socket address is valid authority");
+    Some(auth)
+}
+
 /// Returns an Authority from a request's Host header.
 pub fn authority_from_host<B>(req: &http::Request<B>) -> Option<Authority> {
     req.headers().get(HOST)
@@ -70,26 +92,97 @@ fn set_authority(uri: &mut http::Uri, auth: Authority) {
     *uri = new;
 }
 
-pub fn strip_connection_headers(headers: &mut http::HeaderMap) {
-    let conn_val = if let Some(val) = headers.remove(http::header::CONNECTION) {
-        val
-    } else {
-        return
-    };
-
-    let conn_header = if let Ok(s) = conn_val.to_str() {
-        s
-    } else {
-        return
-    };
+/// Returns true if the headers request a protocol upgrade, as with a
+/// WebSocket handshake.
+///
+/// The `Connection` header's tokens are compared case-insensitively, and may
+/// be spread across multiple comma-separated values.
+pub fn is_upgrade(headers: &http::HeaderMap) -> bool {
+    if !headers.contains_key(http::header::UPGRADE) {
+        return false;
+    }
+
+    headers.get_all(http::header::CONNECTION)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
 
+/// Returns true if a request with `headers` waits for an interim `100
+/// Continue` response before sending its body.
+pub fn expects_continue(headers: &http::HeaderMap) -> bool {
+    headers.get(http::header::EXPECT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().eq_ignore_ascii_case("100-continue"))
+        .unwrap_or(false)
+}
+
+/// Prepares an HTTP/1 request to be sent to an upstream that speaks HTTP/2.
+///
+/// HTTP/2 does not permit connection-specific headers (RFC 7540, section
+/// 8.1.2.2), so they are removed. The request's URI must already carry its
+/// authority, as `normalize_our_view_of_uri` ensures, so that it is sent as
+/// the `:authority` pseudo-header instead of a `Host` header.
+pub fn translate_to_h2<B>(req: &mut http::Request<B>) {
+    {
+        // HTTP/2 permits `TE: trailers`, which gRPC servers require.
+        let headers = req.headers_mut();
+        let te = headers.remove(TE);
+        strip_connection_headers(headers);
+        let te_is_trailers = te.as_ref()
+            .and_then(|te| te.to_str().ok())
+            .map(|te| te.trim().eq_ignore_ascii_case("trailers"))
+            .unwrap_or(false);
+        if let (true, Some(te)) = (te_is_trailers, te) {
+            headers.insert(TE, te);
+        }
+    }
+
+    if req.uri().authority_part().is_some() {
+        req.headers_mut().remove(HOST);
+    }
+    *req.version_mut() = http::Version::HTTP_2;
+}
+
+/// Prepares an HTTP/2 request to be sent to an upstream that speaks HTTP/1.
+///
+/// The request's `:authority` is sent as its `Host` header. HTTP/2 requests
+/// may only have a `TE: trailers` hop-by-hop header, which is removed.
+pub fn translate_to_h1<B>(req: &mut http::Request<B>) {
+    strip_connection_headers(req.headers_mut());
+    if !req.headers().contains_key(HOST) {
+        let host = req.uri().authority_part()
+            .and_then(|a| http::header::HeaderValue::from_str(a.as_str()).ok());
+        if let Some(host) = host {
+            req.headers_mut().insert(HOST, host);
+        }
+    }
+    *req.version_mut() = http::Version::HTTP_11;
+}
+
+/// Removes hop-by-hop headers, which must not be forwarded.
+///
+/// These are the standard hop-by-hop headers and any that are named by the
+/// `Connection` header.
+pub fn strip_connection_headers(headers: &mut http::HeaderMap) {
     // A `Connection` header may have a comma-separated list of
-    // names of other headers that are meant for only this specific connection.
+    // names of other headers that are meant for only this specific connection,
+    // and may appear more than once.
     //
     // Iterate these names and remove them as headers.
-    for name in conn_header.split(',') {
-        let name = name.trim();
-        headers.remove(name);
+    let listed: Vec<String> = headers.get_all(http::header::CONNECTION)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .map(|name| name.trim().to_owned())
+        .collect();
+    for name in &listed {
+        headers.remove(name.as_str());
+    }
+
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
     }
 }
 
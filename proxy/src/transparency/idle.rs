@@ -0,0 +1,165 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+
+/// Configures how long a client connection may idle before it is closed.
+#[derive(Clone)]
+pub(super) struct IdleTimeout {
+    timeout: Option<Duration>,
+    handle: Handle,
+}
+
+/// Closes a client connection once it has had no requests in flight for an
+/// idle timeout.
+///
+/// The connection is shared with a task that watches it, so that a quiet
+/// connection is closed even if no further requests are dispatched to it.
+/// The connection is closed by dropping it: once an HTTP/2 connection has
+/// no handles and no active streams, `h2` sends a `GOAWAY` before closing
+/// it.
+pub(super) struct Idle<S> {
+    conn: Rc<RefCell<Option<S>>>,
+    activity: Rc<Activity>,
+}
+
+/// Counts a request as in flight until dropped.
+///
+/// This is held by the response body, since the request's stream remains
+/// active until the response body has been read.
+pub struct Active(Rc<Activity>);
+
+struct Activity {
+    in_flight: Cell<usize>,
+    idle_since: Cell<Instant>,
+}
+
+/// Drops the watched connection once it has been idle for `timeout`.
+struct Watch<S> {
+    conn: Weak<RefCell<Option<S>>>,
+    activity: Rc<Activity>,
+    timeout: Duration,
+    timer: ReactorTimeout,
+}
+
+// ===== impl IdleTimeout =====
+
+impl IdleTimeout {
+    /// If `timeout` is `None`, connections are never closed for idling.
+    pub fn new(timeout: Option<Duration>, handle: Handle) -> Self {
+        IdleTimeout { timeout, handle }
+    }
+
+    /// Wraps `conn`, closing it once it has been idle for the timeout.
+    pub fn watch<S: 'static>(&self, conn: S) -> Idle<S> {
+        let idle = Idle {
+            conn: Rc::new(RefCell::new(Some(conn))),
+            activity: Rc::new(Activity {
+                in_flight: Cell::new(0),
+                idle_since: Cell::new(Instant::now()),
+            }),
+        };
+
+        if let Some(timeout) = self.timeout {
+            match ReactorTimeout::new(timeout, &self.handle) {
+                Ok(timer) => self.handle.spawn(Watch {
+                    conn: Rc::downgrade(&idle.conn),
+                    activity: idle.activity.clone(),
+                    timeout,
+                    timer,
+                }),
+                Err(e) => warn!("could not create idle timer: {}", e),
+            }
+        }
+
+        idle
+    }
+}
+
+// ===== impl Idle =====
+
+impl<S> Idle<S> {
+    /// Applies `f` to the connection, or returns `None` if it has been
+    /// closed for being idle.
+    pub fn with_conn<T, F>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut S) -> T,
+    {
+        self.conn.borrow_mut().as_mut().map(f)
+    }
+
+    /// Counts a new request as in flight.
+    pub fn dispatch(&self) -> Active {
+        let in_flight = self.activity.in_flight.get();
+        self.activity.in_flight.set(in_flight + 1);
+        Active(self.activity.clone())
+    }
+
+    /// Returns how long the connection has had no requests in flight, or
+    /// `None` if it has requests in flight.
+    pub fn idle_for(&self) -> Option<Duration> {
+        if self.activity.in_flight.get() > 0 {
+            return None;
+        }
+        Some(self.activity.idle_since.get().elapsed())
+    }
+}
+
+// ===== impl Active =====
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        let in_flight = self.0.in_flight.get() - 1;
+        self.0.in_flight.set(in_flight);
+        if in_flight == 0 {
+            self.0.idle_since.set(Instant::now());
+        }
+    }
+}
+
+impl fmt::Debug for Active {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Active").finish()
+    }
+}
+
+// ===== impl Watch =====
+
+impl<S> Future for Watch<S> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            match self.timer.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(e) => {
+                    warn!("idle timer failed: {}", e);
+                    return Ok(Async::Ready(()));
+                }
+            }
+
+            let conn = match self.conn.upgrade() {
+                Some(conn) => conn,
+                // The connection has already been dropped.
+                None => return Ok(Async::Ready(())),
+            };
+
+            let now = Instant::now();
+            let deadline = self.activity.idle_since.get() + self.timeout;
+            if self.activity.in_flight.get() > 0 {
+                self.timer.reset(now + self.timeout);
+            } else if deadline > now {
+                self.timer.reset(deadline);
+            } else {
+                debug!("closing connection after idling for {:?}", self.timeout);
+                conn.borrow_mut().take();
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+}
@@ -1,10 +1,14 @@
 mod client;
+mod expect;
 mod glue;
 pub mod h1;
+mod idle;
+mod pool;
 mod protocol;
 mod server;
 mod tcp;
 
-pub use self::client::Client;
+pub use self::client::{Client, H1Settings, H2Settings};
 pub use self::glue::HttpBody;
+pub use self::pool::{ConnLimit, ConnLimits};
 pub use self::server::Server;
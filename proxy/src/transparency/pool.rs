@@ -0,0 +1,386 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::rc::{Rc, Weak};
+
+use futures::{task, Async, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Bounds the number of idle connections retained by an HTTP/1 client.
+///
+/// hyper's client pool returns every keep-alive connection to the pool once
+/// its response completes, so a burst of concurrent requests would otherwise
+/// leave behind as many idle connections as there were requests. Instead,
+/// requests dispatched while more than `max_idle` requests are in flight are
+/// marked `Connection: close`, so that their connections are closed rather
+/// than pooled once the response completes.
+#[derive(Clone, Debug)]
+pub(super) struct IdleLimit {
+    max_idle: usize,
+    in_flight: Rc<Cell<usize>>,
+}
+
+/// Counts a request as in flight until dropped.
+///
+/// This is held by the response body, since an HTTP/1 connection can't be
+/// reused until the response body has been read.
+pub struct InFlight(Rc<Cell<usize>>);
+
+/// The `ConnLimit`s of each endpoint, so that every HTTP/1 client bound to an
+/// endpoint shares its limit.
+#[derive(Clone, Debug, Default)]
+pub struct ConnLimits {
+    limits: Rc<RefCell<HashMap<SocketAddr, Weak<RefCell<Slots>>>>>,
+}
+
+/// Bounds the number of HTTP/1 connections open to an endpoint.
+///
+/// Each connection holds one of `max` slots until it is closed. Once every
+/// slot is held, new connections wait for one to be released. hyper races
+/// each connect against its pool, so a request waiting to connect is instead
+/// dispatched on a pooled connection if one becomes idle first. Idle
+/// connections hold their slots, so clients that don't share a pool may wait
+/// for each other's idle connections to be closed.
+#[derive(Clone, Debug)]
+pub struct ConnLimit(Rc<RefCell<Slots>>);
+
+/// Holds one of a `ConnLimit`'s slots until dropped.
+#[derive(Debug)]
+pub struct Slot(Rc<RefCell<Slots>>);
+
+/// The tasks that dispatch an HTTP/1 client's connections.
+///
+/// hyper only notices that a response body was dropped the next time the
+/// connection's task is polled, and only then closes the connection, since
+/// the rest of the body would otherwise have to be read before it could be
+/// reused. If the upstream is silent, that may never happen, so the
+/// connection and its slot would be held forever. Instead, every connection
+/// task of the client is notified whenever a response is abandoned.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Dispatchers(Rc<RefCell<Vec<Weak<RefCell<Option<task::Task>>>>>>);
+
+/// Holds a dispatched HTTP/1 request in flight until its response is
+/// dropped, and, if the response body wasn't read to its end, notifies the
+/// client's connection tasks so that its connection is closed.
+pub struct Dispatched {
+    _in_flight: Option<InFlight>,
+    dispatchers: Dispatchers,
+    complete: bool,
+}
+
+/// A connection that holds a slot of its endpoint's `ConnLimit`, if it has
+/// one, until it is closed.
+///
+/// The task reading from the connection is registered with its client's
+/// `Dispatchers`.
+#[derive(Debug)]
+pub struct Limited<T> {
+    io: T,
+    _slot: Option<Slot>,
+    task: Rc<RefCell<Option<task::Task>>>,
+}
+
+#[derive(Debug)]
+struct Slots {
+    max: usize,
+    held: usize,
+    waiting: Vec<task::Task>,
+}
+
+// ===== impl IdleLimit =====
+
+impl IdleLimit {
+    pub fn new(max_idle: usize) -> Self {
+        IdleLimit {
+            max_idle,
+            in_flight: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Counts a new request as in flight.
+    ///
+    /// Returns whether the request's connection may be pooled once the
+    /// request completes.
+    pub fn dispatch(&self) -> (InFlight, bool) {
+        let in_flight = self.in_flight.get() + 1;
+        self.in_flight.set(in_flight);
+        (InFlight(self.in_flight.clone()), in_flight <= self.max_idle)
+    }
+}
+
+// ===== impl InFlight =====
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let in_flight = self.0.get();
+        self.0.set(in_flight - 1);
+    }
+}
+
+impl fmt::Debug for InFlight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("InFlight").finish()
+    }
+}
+
+// ===== impl Dispatchers =====
+
+impl Dispatchers {
+    /// Registers a connection, returning where its task is recorded.
+    fn register(&self) -> Rc<RefCell<Option<task::Task>>> {
+        let mut tasks = self.0.borrow_mut();
+
+        // Forget the connections that have been closed.
+        tasks.retain(|task| task.upgrade().is_some());
+
+        let task = Rc::new(RefCell::new(None));
+        tasks.push(Rc::downgrade(&task));
+        task
+    }
+
+    /// Notifies the task of every open connection.
+    ///
+    /// Connections that aren't affected are polled spuriously, which is
+    /// harmless.
+    fn notify_all(&self) {
+        for task in self.0.borrow().iter().filter_map(Weak::upgrade) {
+            if let Some(ref task) = *task.borrow() {
+                task.notify();
+            }
+        }
+    }
+}
+
+// ===== impl Dispatched =====
+
+impl Dispatched {
+    pub(super) fn new(in_flight: Option<InFlight>, dispatchers: &Dispatchers) -> Self {
+        Dispatched {
+            _in_flight: in_flight,
+            dispatchers: dispatchers.clone(),
+            complete: false,
+        }
+    }
+
+    /// Marks the response as read to its end, so that dropping it doesn't
+    /// close its connection.
+    pub fn complete(&mut self) {
+        self.complete = true;
+    }
+}
+
+impl Drop for Dispatched {
+    fn drop(&mut self) {
+        if !self.complete {
+            trace!("http/1 response abandoned; closing its connection");
+            self.dispatchers.notify_all();
+        }
+    }
+}
+
+impl fmt::Debug for Dispatched {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dispatched")
+            .field("complete", &self.complete)
+            .finish()
+    }
+}
+
+// ===== impl ConnLimits =====
+
+impl ConnLimits {
+    /// Returns the limit of `max` connections to `addr`.
+    ///
+    /// The limit is shared with every other client to `addr` while any of
+    /// them remain.
+    pub fn limit(&self, addr: &SocketAddr, max: usize) -> ConnLimit {
+        let mut limits = self.limits.borrow_mut();
+
+        // Drop the limits of endpoints that are no longer bound.
+        limits.retain(|_, slots| slots.upgrade().is_some());
+
+        if let Some(slots) = limits.get(addr).and_then(Weak::upgrade) {
+            return ConnLimit(slots);
+        }
+
+        let slots = Rc::new(RefCell::new(Slots {
+            max,
+            held: 0,
+            waiting: Vec::new(),
+        }));
+        limits.insert(*addr, Rc::downgrade(&slots));
+        ConnLimit(slots)
+    }
+}
+
+// ===== impl ConnLimit =====
+
+impl ConnLimit {
+    /// Acquires a slot, or registers the current task to be notified once
+    /// one is released.
+    pub fn poll_acquire(&self) -> Poll<Slot, io::Error> {
+        let mut slots = self.0.borrow_mut();
+        if slots.held < slots.max {
+            slots.held += 1;
+            return Ok(Async::Ready(Slot(self.0.clone())));
+        }
+
+        slots.waiting.push(task::current());
+        Ok(Async::NotReady)
+    }
+}
+
+// ===== impl Slot =====
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let waiting = {
+            let mut slots = self.0.borrow_mut();
+            slots.held -= 1;
+            ::std::mem::replace(&mut slots.waiting, Vec::new())
+        };
+        // Every waiter is notified, since some may no longer be waiting.
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+// ===== impl Limited =====
+
+impl<T> Limited<T> {
+    pub(super) fn new(io: T, slot: Option<Slot>, dispatchers: &Dispatchers) -> Self {
+        Limited {
+            io,
+            _slot: slot,
+            task: dispatchers.register(),
+        }
+    }
+}
+
+impl<T: Read> Read for Limited<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.io.read(buf);
+        if let Err(ref e) = read {
+            if e.kind() == io::ErrorKind::WouldBlock {
+                // The connection's task waits for it to be readable, so it
+                // may be notified once a response is abandoned.
+                *self.task.borrow_mut() = Some(task::current());
+            }
+        }
+        read
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Limited<T> {}
+
+impl<T: Write> Write for Limited<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Limited<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Future};
+
+    use super::*;
+
+    #[test]
+    fn requests_beyond_max_idle_are_not_pooled() {
+        let limit = IdleLimit::new(2);
+        let (a, pool_a) = limit.dispatch();
+        let (b, pool_b) = limit.dispatch();
+        let (c, pool_c) = limit.dispatch();
+        assert!(pool_a && pool_b);
+        assert!(!pool_c);
+
+        drop(a);
+        drop(c);
+        let (_d, pool_d) = limit.dispatch();
+        assert!(pool_d);
+        drop(b);
+    }
+
+    #[test]
+    fn conn_limits_are_shared_per_endpoint() {
+        let limits = ConnLimits::default();
+        let a = "10.1.1.1:80".parse().unwrap();
+        let b = "10.1.1.2:80".parse().unwrap();
+
+        future::lazy(|| {
+            let slot = limits.limit(&a, 1).poll_acquire().unwrap();
+            assert!(slot.is_ready());
+            assert!(limits.limit(&a, 1).poll_acquire().unwrap().is_not_ready());
+            assert!(limits.limit(&b, 1).poll_acquire().unwrap().is_ready());
+
+            drop(slot);
+            assert!(limits.limit(&a, 1).poll_acquire().unwrap().is_ready());
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn no_connections_are_pooled_if_max_idle_is_zero() {
+        let limit = IdleLimit::new(0);
+        let (_a, pool_a) = limit.dispatch();
+        assert!(!pool_a);
+    }
+
+    #[test]
+    fn abandoned_responses_notify_connections() {
+        use futures::executor::{self, Notify};
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio_io::io::read;
+
+        /// A connection that never becomes readable.
+        struct Silent;
+        impl Read for Silent {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+        impl AsyncRead for Silent {}
+
+        struct Count(AtomicUsize);
+        impl Notify for Count {
+            fn notify(&self, _: usize) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let dispatchers = Dispatchers::default();
+        let count = Arc::new(Count(AtomicUsize::new(0)));
+        let conn = Limited::new(Silent, None, &dispatchers);
+        let mut task = executor::spawn(read(conn, [0u8; 8]));
+        assert!(task.poll_future_notify(&count, 0).unwrap().is_not_ready());
+
+        // Responses read to their end leave their connections be.
+        let mut complete = Dispatched::new(None, &dispatchers);
+        complete.complete();
+        drop(complete);
+        assert_eq!(count.0.load(Ordering::SeqCst), 0);
+
+        drop(Dispatched::new(None, &dispatchers));
+        assert_eq!(count.0.load(Ordering::SeqCst), 1);
+
+        // Closed connections are no longer notified.
+        drop(task);
+        drop(Dispatched::new(None, &dispatchers));
+        assert_eq!(count.0.load(Ordering::SeqCst), 1);
+        assert!(dispatchers.0.borrow().iter().all(|t| t.upgrade().is_none()));
+    }
+}
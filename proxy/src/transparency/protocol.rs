@@ -1,5 +1,9 @@
+use http;
+use http::header::{HeaderName, HeaderValue};
 use httparse;
 
+use super::h1;
+
 /// Known protocols that we proxy transparently.
 #[derive(Debug)]
 pub enum Protocol {
@@ -9,6 +13,10 @@ pub enum Protocol {
 
 const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 
+/// The most headers an HTTP/1 request head may have to be recognized as an
+/// upgrade.
+const MAX_HEADERS: usize = 100;
+
 impl Protocol {
     /// Tries to detect a known protocol in the peeked bytes.
     ///
@@ -43,3 +51,26 @@ impl Protocol {
         None
     }
 }
+
+/// Returns true if the peeked bytes hold the head of an HTTP/1 request that
+/// asks to upgrade its connection, as a WebSocket handshake does.
+///
+/// Only a head that was peeked in full is recognized.
+pub fn is_http1_upgrade(bytes: &[u8]) -> bool {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+    match req.parse(bytes) {
+        Ok(httparse::Status::Complete(_)) => {},
+        _ => return false,
+    }
+
+    let mut map = http::HeaderMap::new();
+    for header in req.headers.iter() {
+        let name = HeaderName::from_bytes(header.name.as_bytes());
+        let value = HeaderValue::from_bytes(header.value);
+        if let (Ok(name), Ok(value)) = (name, value) {
+            map.append(name, value);
+        }
+    }
+    h1::is_upgrade(&map)
+}
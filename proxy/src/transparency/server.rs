@@ -1,5 +1,7 @@
+use std::cell::RefCell;
 use std::fmt;
 use std::net::SocketAddr;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -19,9 +21,21 @@ use drain;
 use telemetry::Sensors;
 use transport::GetOriginalDst;
 use super::glue::{HttpBody, HttpBodyNewSvc, HyperServerSvc};
-use super::protocol::Protocol;
+use super::protocol::{self, Protocol};
 use super::tcp;
 
+/// How many bytes of a new connection are peeked to detect its protocol.
+///
+/// This holds the head of most HTTP/1 requests, so that connections opening
+/// with an upgrade can be recognized before they're served.
+const PEEK_CAPACITY: usize = 8192;
+
+/// Buffers that new connections are peeked into.
+///
+/// A buffer is returned once its connection's protocol is detected, so that
+/// buffers are only allocated for connections that are peeked concurrently.
+type PeekBufs = Rc<RefCell<Vec<Vec<u8>>>>;
+
 /// A protocol-transparent Server!
 ///
 /// This type can `serve` new connections, determine what protocol
@@ -41,6 +55,7 @@ where
     h2: tower_h2::Server<HttpBodyNewSvc<S>, Handle, B>,
     listen_addr: SocketAddr,
     new_service: S,
+    peek_bufs: PeekBufs,
     proxy_ctx: Arc<ProxyCtx>,
     sensors: Sensors,
     tcp: tcp::Proxy,
@@ -81,6 +96,7 @@ where
             h2: tower_h2::Server::new(recv_body_svc, Default::default(), executor),
             listen_addr,
             new_service: stack,
+            peek_bufs: PeekBufs::default(),
             proxy_ctx,
             sensors,
             tcp,
@@ -128,7 +144,10 @@ where
 
         // try to sniff protocol
         let proxy_ctx = self.proxy_ctx.clone();
-        let sniff = [0u8; 32];
+        let peek_bufs = self.peek_bufs.clone();
+        let sniff = peek_bufs.borrow_mut()
+            .pop()
+            .unwrap_or_else(|| vec![0u8; PEEK_CAPACITY]);
         let sensors = self.sensors.clone();
         let h1 = self.h1.clone();
         let h2 = self.h2.clone();
@@ -139,7 +158,20 @@ where
             .peek_future(sniff)
             .map_err(|_| ())
             .and_then(move |(connection, sniff, n)| -> Box<Future<Item=(), Error=()>> {
-                if let Some(proto) = Protocol::detect(&sniff[..n]) {
+                let proto = match Protocol::detect(&sniff[..n]) {
+                    // hyper can't hand over a connection once it switches
+                    // protocols, so a connection that opens with an upgrade
+                    // is tunneled to its original destination instead, with
+                    // its handshake and everything after it passed through.
+                    Some(Protocol::Http1) if protocol::is_http1_upgrade(&sniff[..n]) => {
+                        trace!("transparency detected an HTTP/1 upgrade, treating as TCP");
+                        None
+                    },
+                    proto => proto,
+                };
+                peek_bufs.borrow_mut().push(sniff);
+
+                if let Some(proto) = proto {
                     let srv_ctx = ServerCtx::new(
                         &proxy_ctx,
                         &local_addr,
@@ -174,6 +206,12 @@ where
                                 request.extensions_mut().insert(srv_ctx.clone());
                             };
 
+                            // When the proxy drains, the client is sent a
+                            // GOAWAY naming the last stream that was
+                            // processed, so that it opens new streams on
+                            // another connection. The streams in flight may
+                            // complete, within the shutdown grace period,
+                            // and then the connection is closed.
                             let fut = drain_signal
                                 .watch(h2.serve_modified(io, set_ctx), |conn| {
                                     conn.graceful_shutdown();
@@ -240,3 +278,203 @@ fn tcp_serve(
     // 'watch' the TCP future so that the process doesn't close early.
     Box::new(drain_signal.watch(fut, |_| ()))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use futures::{future, Async, Poll, Stream};
+    use futures::future::Shared;
+    use futures::sync::oneshot;
+    use h2;
+    use tokio_core::net::{TcpListener, TcpStream};
+    use tokio_core::reactor::Core;
+    use tokio_io::io::{read_exact, write_all};
+    use tower::Service;
+
+    use ctx;
+    use super::*;
+
+    const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+    const HEADERS: u8 = 0x1;
+    const SETTINGS: u8 = 0x4;
+    const GOAWAY: u8 = 0x7;
+    const END_STREAM: u8 = 0x1;
+    const END_HEADERS: u8 = 0x4;
+
+    struct Frame {
+        kind: u8,
+        stream_id: u32,
+        payload: Vec<u8>,
+    }
+
+    struct NoOrigDst;
+
+    impl GetOriginalDst for NoOrigDst {
+        fn get_original_dst(&self, _: &TcpStream) -> Option<SocketAddr> {
+            None
+        }
+    }
+
+    /// Responds to each request once `release` fires, recording the path of
+    /// each request it's dispatched.
+    #[derive(Clone)]
+    struct Delayed {
+        release: Shared<oneshot::Receiver<()>>,
+        received: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl NewService for Delayed {
+        type Request = http::Request<HttpBody>;
+        type Response = http::Response<HttpBody>;
+        type Error = h2::Error;
+        type InitError = ();
+        type Service = Self;
+        type Future = future::FutureResult<Self, ()>;
+
+        fn new_service(&self) -> Self::Future {
+            future::ok(self.clone())
+        }
+    }
+
+    impl Service for Delayed {
+        type Request = http::Request<HttpBody>;
+        type Response = http::Response<HttpBody>;
+        type Error = h2::Error;
+        type Future = Box<Future<Item = Self::Response, Error = h2::Error>>;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Self::Request) -> Self::Future {
+            self.received.borrow_mut().push(req.uri().path().to_owned());
+            let rsp = self.release.clone()
+                .map(|_| http::Response::new(HttpBody::Buffered(None, None)))
+                .map_err(|_| h2::Error::from(h2::Reason::INTERNAL_ERROR));
+            Box::new(rsp)
+        }
+    }
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let len = payload.len();
+        let mut buf = vec![(len >> 16) as u8, (len >> 8) as u8, len as u8, kind, flags];
+        buf.extend_from_slice(&[
+            (stream_id >> 24) as u8,
+            (stream_id >> 16) as u8,
+            (stream_id >> 8) as u8,
+            stream_id as u8,
+        ]);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    /// An HPACK-encoded `GET http://example.com/`.
+    fn get() -> Vec<u8> {
+        // `:method: GET`, `:scheme: http`, and `:path: /` are in the static
+        // table; `:authority` is a literal that isn't indexed.
+        let mut block = vec![0x82, 0x86, 0x84, 0x01, 11];
+        block.extend_from_slice(b"example.com");
+        block
+    }
+
+    fn read_frame(sock: TcpStream) -> Box<Future<Item = (TcpStream, Frame), Error = io::Error>> {
+        let frame = read_exact(sock, [0u8; 9]).and_then(|(sock, head)| {
+            let len = (head[0] as usize) << 16 | (head[1] as usize) << 8 | head[2] as usize;
+            let kind = head[3];
+            let stream_id = read_u32(&head[5..]) & !(1 << 31);
+            read_exact(sock, vec![0; len]).map(move |(sock, payload)| {
+                (sock, Frame { kind, stream_id, payload })
+            })
+        });
+        Box::new(frame)
+    }
+
+    fn read_u32(b: &[u8]) -> u32 {
+        (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+    }
+
+    /// Reads frames until one of `kind` is read, skipping any others.
+    fn next_frame(core: &mut Core, mut sock: TcpStream, kind: u8) -> (TcpStream, Frame) {
+        loop {
+            let (s, frame) = core.run(read_frame(sock)).expect("frame");
+            if frame.kind == kind {
+                return (s, frame);
+            }
+            sock = s;
+        }
+    }
+
+    #[test]
+    fn drains_h2_connections_with_goaway() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let (release_tx, release_rx) = oneshot::channel();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let stack = Delayed {
+            release: release_rx.shared(),
+            received: received.clone(),
+        };
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle)
+            .expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let (drain_tx, drain_rx) = drain::channel();
+        let server = Server::new(
+            addr,
+            ctx::Proxy::inbound(&ctx::Process::test("test")),
+            Sensors::null(),
+            NoOrigDst,
+            stack,
+            Duration::from_secs(1),
+            IndexSet::new(),
+            drain_rx,
+            handle.clone(),
+        );
+        // Only one connection is served, so that the server's own watch is
+        // dropped and the drain completes once the connection closes.
+        let accept = listener.incoming().into_future()
+            .map(move |(conn, _)| {
+                let (sock, remote) = conn.expect("connection");
+                server.serve(Connection::Plain(sock), remote);
+            })
+            .map_err(|(e, _)| panic!("accept failed: {}", e));
+        handle.spawn(accept);
+
+        let sock = core.run(TcpStream::connect(&addr, &handle)).expect("connect");
+        let mut open = PREFACE.to_vec();
+        open.extend(frame(SETTINGS, 0, 0, &[]));
+        open.extend(frame(HEADERS, END_HEADERS | END_STREAM, 1, &get()));
+        let (sock, _) = core.run(write_all(sock, open)).expect("write");
+        while received.borrow().is_empty() {
+            core.turn(Some(Duration::from_millis(10)));
+        }
+
+        // The stream is in flight when the drain begins.
+        let drained = drain_tx.drain();
+        let (sock, goaway) = next_frame(&mut core, sock, GOAWAY);
+        assert_eq!(read_u32(&goaway.payload[..4]) & !(1 << 31), 1, "last stream ID");
+        assert_eq!(read_u32(&goaway.payload[4..8]), 0, "NO_ERROR");
+
+        release_tx.send(()).expect("release");
+        let (sock, rsp) = next_frame(&mut core, sock, HEADERS);
+        assert_eq!(rsp.stream_id, 1, "the in-flight stream completes");
+
+        // Once its streams complete, the connection closes.
+        let mut sock = sock;
+        loop {
+            match core.run(read_frame(sock)) {
+                Ok((s, _)) => sock = s,
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+                    break;
+                }
+            }
+        }
+        core.run(drained).expect("drained");
+        assert_eq!(received.borrow().len(), 1);
+    }
+}
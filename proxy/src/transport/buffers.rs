@@ -0,0 +1,156 @@
+use tokio_core::net::TcpStream;
+
+/// The smallest socket buffer that may be configured.
+///
+/// Kernels impose their own minimums, which are usually smaller, but a
+/// buffer smaller than this would hold too little data to be useful.
+pub const MIN_SOCKET_BUFFER_SIZE: usize = 4 * 1024;
+
+/// Configures the sizes of client connections' socket buffers, `SO_RCVBUF`
+/// and `SO_SNDBUF`.
+///
+/// The sizes are set as soon as a socket has connected, before any TLS
+/// handshake or tunnel is negotiated on it. By default, the kernel's sizes
+/// are used. The proxy's own buffering of HTTP/2 connections is bounded by
+/// their flow control windows, which are configured by `H2Settings`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SocketBuffers {
+    recv: Option<usize>,
+    send: Option<usize>,
+}
+
+// ===== impl SocketBuffers =====
+
+impl SocketBuffers {
+    /// Sets each socket's receive buffer to `size` bytes.
+    ///
+    /// Sizes smaller than `MIN_SOCKET_BUFFER_SIZE` are raised to it.
+    pub fn with_recv_buffer_size(self, size: usize) -> Self {
+        Self {
+            recv: Some(clamp("SO_RCVBUF", size)),
+            ..self
+        }
+    }
+
+    /// Sets each socket's send buffer to `size` bytes.
+    ///
+    /// Sizes smaller than `MIN_SOCKET_BUFFER_SIZE` are raised to it.
+    pub fn with_send_buffer_size(self, size: usize) -> Self {
+        Self {
+            send: Some(clamp("SO_SNDBUF", size)),
+            ..self
+        }
+    }
+
+    /// Sets the configured buffer sizes on `socket`, warning if they can't
+    /// be set.
+    pub fn apply(&self, socket: &TcpStream) {
+        if let Some(size) = self.recv {
+            if let Err(e) = socket.set_recv_buffer_size(size) {
+                warn!(
+                    "could not set SO_RCVBUF on {:?}/{:?}: {}",
+                    socket.local_addr(),
+                    socket.peer_addr(),
+                    e
+                );
+            }
+        }
+        if let Some(size) = self.send {
+            if let Err(e) = socket.set_send_buffer_size(size) {
+                warn!(
+                    "could not set SO_SNDBUF on {:?}/{:?}: {}",
+                    socket.local_addr(),
+                    socket.peer_addr(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn clamp(option: &str, size: usize) -> usize {
+    if size < MIN_SOCKET_BUFFER_SIZE {
+        warn!(
+            "{} of {} bytes is too small; using {} bytes",
+            option,
+            size,
+            MIN_SOCKET_BUFFER_SIZE
+        );
+        MIN_SOCKET_BUFFER_SIZE
+    } else {
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use tokio_core::reactor::Core;
+
+    use connection::{self, Connection};
+    use transport::SocketLatency;
+    use super::*;
+
+    fn connected(core: &mut Core) -> (TcpStream, TcpListener) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connecting = TcpStream::connect(&addr, &core.handle());
+        let socket = core.run(connecting).unwrap();
+        (socket, listener)
+    }
+
+    #[test]
+    fn sets_socket_buffer_sizes_on_connect() {
+        let mut core = Core::new().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let size = 128 * 1024;
+        let buffers = SocketBuffers::default()
+            .with_recv_buffer_size(size)
+            .with_send_buffer_size(size);
+        let connecting = connection::connect(
+            &addr,
+            &core.handle(),
+            None,
+            None,
+            buffers,
+            SocketLatency::default(),
+        );
+        let socket = match core.run(connecting).unwrap() {
+            Connection::Plain(socket) => socket,
+            c => panic!("connection should be plaintext: {:?}", c),
+        };
+
+        // Linux doubles the sizes it's given, to allow for its own
+        // bookkeeping, so the sizes read back are at least those set.
+        assert!(socket.recv_buffer_size().unwrap() >= size);
+        assert!(socket.send_buffer_size().unwrap() >= size);
+    }
+
+    #[test]
+    fn clamps_small_buffer_sizes() {
+        let buffers = SocketBuffers::default()
+            .with_recv_buffer_size(1)
+            .with_send_buffer_size(MIN_SOCKET_BUFFER_SIZE + 1);
+        assert_eq!(buffers.recv, Some(MIN_SOCKET_BUFFER_SIZE));
+        assert_eq!(buffers.send, Some(MIN_SOCKET_BUFFER_SIZE + 1));
+
+        let mut core = Core::new().unwrap();
+        let (socket, _listener) = connected(&mut core);
+        buffers.apply(&socket);
+        assert!(socket.recv_buffer_size().unwrap() >= MIN_SOCKET_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn leaves_kernel_defaults_unconfigured() {
+        let mut core = Core::new().unwrap();
+        let (socket, _listener) = connected(&mut core);
+        let (recv, send) = (socket.recv_buffer_size().unwrap(), socket.send_buffer_size().unwrap());
+
+        SocketBuffers::default().apply(&socket);
+        assert_eq!(socket.recv_buffer_size().unwrap(), recv);
+        assert_eq!(socket.send_buffer_size().unwrap(), send);
+    }
+}
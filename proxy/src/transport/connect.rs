@@ -1,20 +1,32 @@
-use futures::Future;
+use futures::{Async, Future, Poll};
 use tokio_connect;
-use tokio_core::reactor::Handle;
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
 
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use std::time::Duration;
 
 use http;
 
 use connection;
 use dns;
+use super::{SocketBuffers, SocketLatency};
+use super::tls;
+use super::tunnel::ConnectProxy;
 
 #[derive(Debug, Clone)]
 pub struct Connect {
     addr: SocketAddr,
     handle: Handle,
+    tls: Option<tls::ConnectionConfig>,
+    keepalive: Option<Duration>,
+    buffers: SocketBuffers,
+    latency: SocketLatency,
+    proxy: Option<ConnectProxy>,
 }
 
 #[derive(Clone, Debug)]
@@ -39,12 +51,27 @@ pub enum Host {
 #[derive(Clone, Copy, Debug)]
 pub enum HostAndPortError {
     /// The host is not a valid DNS name or IP address.
+    ///
+    /// IPv6 addresses must be enclosed in brackets, and may not have a zone,
+    /// since a zone names a network interface of the host that chose it.
     InvalidHost,
 
     /// The port is missing.
     MissingPort,
 }
 
+/// Indicates that a connection could not be attempted because its host's
+/// addresses could not be resolved.
+///
+/// Connection failures for this reason are `io::Error`s of kind `NotFound`
+/// that wrap a `ResolutionFailed`, so that they may be told apart from
+/// failures to connect to a resolved address.
+#[derive(Debug)]
+pub struct ResolutionFailed {
+    host: String,
+    error: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct LookupAddressAndConnect {
     host_and_port: HostAndPort,
@@ -52,17 +79,46 @@ pub struct LookupAddressAndConnect {
     handle: Handle,
 }
 
+/// Connects to the first of several addresses to accept a connection, as in
+/// Happy Eyeballs (RFC 8305).
+///
+/// Addresses are tried in order, alternating between address families and
+/// starting with IPv6. Each attempt is started once the previous one fails
+/// or has been pending for `CONNECTION_ATTEMPT_DELAY_MS`, so that an unreachable
+/// address family delays connecting without preventing it. The first
+/// connection established is used, and the other attempts are canceled.
+struct HappyEyeballs {
+    addrs: VecDeque<SocketAddr>,
+    attempts: Vec<connection::Connecting>,
+    delay: Option<ReactorTimeout>,
+    handle: Handle,
+    error: Option<io::Error>,
+}
+
+/// How long a connection attempt may be pending before the next is started,
+/// as recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+
 // ===== impl HostAndPort =====
 
 impl HostAndPort {
     pub fn normalize(a: &http::uri::Authority, default_port: Option<u16>)
         -> Result<Self, HostAndPortError>
     {
-        let host = {
-            match dns::Name::normalize(a.host()) {
+        let host = a.host();
+        let host = if host.starts_with('[') && host.ends_with(']') {
+            // An IPv6 literal. A zone, delimited by `%`, fails to parse.
+            let ip = IpAddr::from_str(&host[1..host.len() - 1])
+                .map_err(|_| HostAndPortError::InvalidHost)?;
+            if !ip.is_ipv6() {
+                return Err(HostAndPortError::InvalidHost);
+            }
+            Host::Ip(ip)
+        } else {
+            match dns::Name::normalize(host) {
                 Ok(host) => Host::DnsName(host),
                 Err(_) => {
-                    let ip: IpAddr = IpAddr::from_str(a.host())
+                    let ip: IpAddr = IpAddr::from_str(host)
                         .map_err(|_| HostAndPortError::InvalidHost)?;
                     Host::Ip(ip)
                 },
@@ -82,7 +138,8 @@ impl<'a> From<&'a HostAndPort> for http::uri::Authority {
     fn from(a: &HostAndPort) -> Self {
         let s = match a.host {
             Host::DnsName(ref n) => format!("{}:{}", n, a.port),
-            Host::Ip(ref ip) => format!("{}:{}", ip, a.port),
+            // IPv6 addresses are bracketed.
+            Host::Ip(ip) => SocketAddr::new(ip, a.port).to_string(),
         };
         http::uri::Authority::from_str(&s).unwrap()
     }
@@ -96,6 +153,55 @@ impl Connect {
         Self {
             addr,
             handle: handle.clone(),
+            tls: None,
+            keepalive: None,
+            buffers: SocketBuffers::default(),
+            latency: SocketLatency::default(),
+            proxy: None,
+        }
+    }
+
+    /// Encrypts connections with TLS, if `tls` is provided.
+    pub fn with_tls(self, tls: Option<tls::ConnectionConfig>) -> Self {
+        Self {
+            tls,
+            ..self
+        }
+    }
+
+    /// Enables TCP keepalive on connections that have been idle for
+    /// `keepalive`, if it is provided.
+    pub fn with_keepalive(self, keepalive: Option<Duration>) -> Self {
+        Self {
+            keepalive,
+            ..self
+        }
+    }
+
+    /// Sizes the socket buffers of connections according to `buffers`.
+    pub fn with_socket_buffers(self, buffers: SocketBuffers) -> Self {
+        Self {
+            buffers,
+            ..self
+        }
+    }
+
+    /// Sets the `latency` options of connections' sockets.
+    pub fn with_socket_latency(self, latency: SocketLatency) -> Self {
+        Self {
+            latency,
+            ..self
+        }
+    }
+
+    /// Tunnels connections through `proxy` with `CONNECT`, if it is
+    /// provided.
+    ///
+    /// TLS, if enabled, is negotiated with `addr` through the tunnel.
+    pub fn with_connect_proxy(self, proxy: Option<ConnectProxy>) -> Self {
+        Self {
+            proxy,
+            ..self
         }
     }
 }
@@ -106,10 +212,43 @@ impl tokio_connect::Connect for Connect {
     type Future = connection::Connecting;
 
     fn connect(&self) -> Self::Future {
-        connection::connect(&self.addr, &self.handle)
+        if let Some(connecting) = connect_mock(&self.addr) {
+            return connecting;
+        }
+
+        match self.proxy {
+            Some(ref proxy) => connection::connect_through(
+                proxy,
+                &self.addr,
+                &self.handle,
+                self.tls.clone(),
+                self.keepalive,
+                self.buffers,
+                self.latency,
+            ),
+            None => connection::connect(
+                &self.addr,
+                &self.handle,
+                self.tls.clone(),
+                self.keepalive,
+                self.buffers,
+                self.latency,
+            ),
+        }
     }
 }
 
+/// In tests, connections to addresses with a mock listening are in-memory.
+#[cfg(test)]
+fn connect_mock(addr: &SocketAddr) -> Option<connection::Connecting> {
+    super::mock::connect(addr)
+}
+
+#[cfg(not(test))]
+fn connect_mock(_: &SocketAddr) -> Option<connection::Connecting> {
+    None
+}
+
 // ===== impl LookupAddressAndConnect =====
 
 impl LookupAddressAndConnect {
@@ -135,17 +274,297 @@ impl tokio_connect::Connect for LookupAddressAndConnect {
         let port = self.host_and_port.port;
         let handle = self.handle.clone();
         let host = self.host_and_port.host.clone();
+        let name = match host {
+            Host::DnsName(ref name) => name.to_string(),
+            Host::Ip(ref ip) => ip.to_string(),
+        };
         let c = self.dns_resolver
-            .resolve_one_ip(&self.host_and_port.host)
-            .map_err(|_| {
-                io::Error::new(io::ErrorKind::NotFound, "DNS resolution failed")
+            .resolve_ips(&self.host_and_port.host)
+            .map_err(move |e| {
+                let failed = ResolutionFailed {
+                    host: name,
+                    error: e.to_string(),
+                };
+                io::Error::new(io::ErrorKind::NotFound, failed)
             })
-            .and_then(move |ip_addr: IpAddr| {
-                info!("DNS resolved {:?} to {}", host, ip_addr);
-                let addr = SocketAddr::from((ip_addr, port));
-                trace!("connect {}", addr);
-                connection::connect(&addr, &handle)
+            .and_then(move |ips: Vec<IpAddr>| {
+                info!("DNS resolved {:?} to {:?}", host, ips);
+                let addrs = ips.into_iter().map(|ip| SocketAddr::new(ip, port));
+                HappyEyeballs::new(addrs, &handle)
             });
         Box::new(c)
     }
 }
+
+// ===== impl ResolutionFailed =====
+
+impl fmt::Display for ResolutionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not resolve {}: {}", self.host, self.error)
+    }
+}
+
+impl Error for ResolutionFailed {
+    fn description(&self) -> &str {
+        "DNS resolution failed"
+    }
+}
+
+// ===== impl HappyEyeballs =====
+
+impl HappyEyeballs {
+    fn new<I: IntoIterator<Item = SocketAddr>>(addrs: I, handle: &Handle) -> Self {
+        HappyEyeballs {
+            addrs: interleave_families(addrs).into(),
+            attempts: Vec::new(),
+            delay: None,
+            handle: handle.clone(),
+            error: None,
+        }
+    }
+
+    /// Starts connecting to the next address, returning false if there are
+    /// none left.
+    fn start_next(&mut self) -> bool {
+        let addr = match self.addrs.pop_front() {
+            Some(addr) => addr,
+            None => {
+                self.delay = None;
+                return false;
+            }
+        };
+
+        trace!("connect {}", addr);
+        self.attempts.push(connection::connect(
+            &addr,
+            &self.handle,
+            None,
+            None,
+            SocketBuffers::default(),
+            SocketLatency::default(),
+        ));
+        self.delay = if self.addrs.is_empty() {
+            None
+        } else {
+            let delay = Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS);
+            // Without a timer, the next attempt waits for this one to fail.
+            ReactorTimeout::new(delay, &self.handle).ok()
+        };
+        true
+    }
+}
+
+impl Future for HappyEyeballs {
+    type Item = connection::Connection;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let delay_elapsed = match self.delay.as_mut().map(Future::poll) {
+                Some(Ok(Async::NotReady)) | None => false,
+                Some(_) => true,
+            };
+            if (self.attempts.is_empty() || delay_elapsed) && self.start_next() {
+                continue;
+            }
+
+            let mut failed = false;
+            let mut i = 0;
+            while i < self.attempts.len() {
+                match self.attempts[i].poll() {
+                    Ok(Async::Ready(conn)) => return Ok(Async::Ready(conn)),
+                    Ok(Async::NotReady) => i += 1,
+                    Err(e) => {
+                        debug!("connection attempt failed: {}", e);
+                        self.attempts.remove(i);
+                        self.error = Some(e);
+                        failed = true;
+                    }
+                }
+            }
+            // Don't wait for the delay to try the next address.
+            if failed && self.start_next() {
+                continue;
+            }
+
+            if self.attempts.is_empty() {
+                return Err(self.error.take().unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")
+                }));
+            }
+            return Ok(Async::NotReady);
+        }
+    }
+}
+
+/// Orders `addrs` so that their address families alternate, starting with
+/// IPv6, while preserving the order of each family's addresses.
+fn interleave_families<I: IntoIterator<Item = SocketAddr>>(addrs: I) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut addrs = Vec::new();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return addrs,
+            (a, b) => addrs.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+    use std::thread;
+
+    use http::header::HeaderValue;
+    use tokio_connect::Connect as TokioConnect;
+    use tokio_core::reactor::Core;
+    use tokio_io::io::{read_exact, write_all};
+
+    use super::*;
+    use transport::TunnelRejected;
+
+    fn authority(s: &str) -> http::uri::Authority {
+        http::uri::Authority::from_str(s).expect("authority")
+    }
+
+    /// Serves a single CONNECT request by sending `response` and then echoing
+    /// whatever is sent through the tunnel, returning the request's head once
+    /// the connection is closed.
+    fn connect_proxy(response: &'static [u8]) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().expect("accept");
+            let mut head = Vec::new();
+            let mut byte = [0; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                socket.read_exact(&mut byte).expect("read request");
+                head.push(byte[0]);
+            }
+            socket.write_all(response).expect("write response");
+            let mut reader = socket.try_clone().unwrap();
+            let _ = io::copy(&mut reader, &mut socket);
+            String::from_utf8(head).unwrap()
+        });
+        (addr, proxy)
+    }
+
+    #[test]
+    fn bracketed_ipv6_authority() {
+        let a = HostAndPort::normalize(&authority("[::1]:8080"), None).expect("normalize");
+        match a.host {
+            Host::Ip(ip) => assert_eq!(ip, IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1))),
+            ref host => panic!("unexpected host: {:?}", host),
+        }
+        assert_eq!(a.port, 8080);
+        assert_eq!(http::uri::Authority::from(&a), authority("[::1]:8080"));
+    }
+
+    #[test]
+    fn ipv6_zones_are_invalid() {
+        // The authority may not be parsed at all.
+        if let Ok(a) = http::uri::Authority::from_str("[fe80::1%25eth0]:80") {
+            assert!(HostAndPort::normalize(&a, None).is_err());
+        }
+    }
+
+    #[test]
+    fn families_are_interleaved_ipv6_first() {
+        let v4 = |n| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)), 80);
+        let v6 = |n| SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, n)), 80);
+
+        let addrs = interleave_families(vec![v4(1), v4(2), v4(3), v6(1), v6(2)]);
+        assert_eq!(addrs, vec![v6(1), v4(1), v6(2), v4(2), v4(3)]);
+
+        let addrs = interleave_families(vec![v4(1), v4(2)]);
+        assert_eq!(addrs, vec![v4(1), v4(2)]);
+    }
+
+    #[test]
+    fn connects_to_ipv6_loopback() {
+        let listener = match TcpListener::bind("[::1]:0") {
+            Ok(listener) => listener,
+            // IPv6 may be disabled in the test environment.
+            Err(_) => return,
+        };
+        let addr = listener.local_addr().unwrap();
+        assert!(addr.is_ipv6());
+
+        let mut core = Core::new().unwrap();
+        let connect = Connect::new(addr, &core.handle());
+        core.run(connect.connect()).expect("connect");
+    }
+
+    #[test]
+    fn happy_eyeballs_falls_back_to_next_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Nothing listens on this port once the listener is dropped.
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let mut core = Core::new().unwrap();
+        let connecting = HappyEyeballs::new(vec![closed, addr], &core.handle());
+        core.run(connecting).expect("connect");
+
+        let connecting = HappyEyeballs::new(vec![closed], &core.handle());
+        assert!(core.run(connecting).is_err());
+
+        let connecting = HappyEyeballs::new(vec![], &core.handle());
+        assert!(core.run(connecting).is_err());
+    }
+
+    #[test]
+    fn connects_through_connect_proxy() {
+        // The target's greeting is sent along with the proxy's response, so
+        // it is lost if the response is over-read.
+        let (proxy_addr, proxy) =
+            connect_proxy(b"HTTP/1.1 200 Connection established\r\n\r\nhello");
+        let proxy_auth = HeaderValue::from_static("Basic dXNlcjpwYXNz");
+        let target = SocketAddr::from(([10, 1, 1, 1], 8080));
+
+        let mut core = Core::new().unwrap();
+        let connect = Connect::new(target, &core.handle())
+            .with_connect_proxy(Some(
+                ConnectProxy::new(proxy_addr).with_authorization(Some(proxy_auth)),
+            ));
+        let conn = core.run(connect.connect()).expect("connect");
+        let (conn, greeting) = core.run(read_exact(conn, [0; 5])).expect("read greeting");
+        assert_eq!(&greeting, b"hello");
+        let (conn, _) = core.run(write_all(conn, b"ping")).expect("write");
+        let (conn, echo) = core.run(read_exact(conn, [0; 4])).expect("read echo");
+        assert_eq!(&echo, b"ping");
+        drop(conn);
+
+        assert_eq!(
+            proxy.join().unwrap(),
+            "CONNECT 10.1.1.1:8080 HTTP/1.1\r\n\
+             Host: 10.1.1.1:8080\r\n\
+             Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn connect_proxy_refusal_is_distinct() {
+        let (proxy_addr, proxy) = connect_proxy(
+            b"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n",
+        );
+        let target = SocketAddr::from(([10, 1, 1, 1], 8080));
+
+        let mut core = Core::new().unwrap();
+        let connect = Connect::new(target, &core.handle())
+            .with_connect_proxy(Some(ConnectProxy::new(proxy_addr)));
+        let e = core.run(connect.connect()).expect_err("tunnel should be refused");
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+        let rejected = e.get_ref()
+            .and_then(|e| e.downcast_ref::<TunnelRejected>())
+            .expect("error should be a TunnelRejected");
+        assert_eq!(rejected.status(), 407);
+
+        let head = proxy.join().unwrap();
+        assert!(!head.contains("Proxy-Authorization"), "unexpected header: {}", head);
+    }
+}
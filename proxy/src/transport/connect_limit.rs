@@ -0,0 +1,298 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::net::SocketAddr;
+use std::rc::{Rc, Weak};
+
+use futures::{task, Async, Future, Poll};
+use tokio_connect;
+
+/// The `ConnectLimit`s of each endpoint, so that every client bound to an
+/// endpoint shares its limit.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectLimits {
+    limits: Rc<RefCell<HashMap<SocketAddr, Weak<RefCell<Permits>>>>>,
+}
+
+/// Bounds the number of connection attempts in flight to an endpoint.
+///
+/// When an endpoint becomes available, every request buffered for it may try
+/// to connect at once. Each attempt instead waits for one of `max` permits,
+/// which it holds until it has connected, or failed to. An HTTP/2 client
+/// serves every request on the one connection that it establishes, so the
+/// services bound to an endpoint connect to it one at a time. hyper races
+/// each HTTP/1 connect against its pool, so a request waiting for a permit
+/// is dispatched on the first pooled connection to become idle, and its
+/// attempt is abandoned without connecting.
+#[derive(Clone, Debug)]
+pub struct ConnectLimit(Rc<RefCell<Permits>>);
+
+/// Connects once its `ConnectLimit`, if it has one, permits it.
+#[derive(Clone, Debug)]
+pub struct LimitConnect<C> {
+    inner: C,
+    limit: Option<ConnectLimit>,
+}
+
+pub struct Connecting<C: tokio_connect::Connect> {
+    state: State<C>,
+}
+
+enum State<C: tokio_connect::Connect> {
+    /// Waiting for a permit, so that the connection may be attempted.
+    Waiting(C, ConnectLimit),
+    Connecting(C::Future, Option<Permit>),
+}
+
+/// Holds one of a `ConnectLimit`'s permits until dropped.
+#[derive(Debug)]
+struct Permit(Rc<RefCell<Permits>>);
+
+#[derive(Debug)]
+struct Permits {
+    max: usize,
+    held: usize,
+    waiting: Vec<task::Task>,
+}
+
+// ===== impl ConnectLimits =====
+
+impl ConnectLimits {
+    /// Returns the limit of `max` concurrent connection attempts to `addr`.
+    ///
+    /// The limit is shared with every other client to `addr` while any of
+    /// them remain. A `max` of zero is treated as one, so that connections
+    /// may be established at all.
+    pub fn limit(&self, addr: &SocketAddr, max: usize) -> ConnectLimit {
+        let mut limits = self.limits.borrow_mut();
+
+        // Drop the limits of endpoints that are no longer bound.
+        limits.retain(|_, permits| permits.upgrade().is_some());
+
+        if let Some(permits) = limits.get(addr).and_then(Weak::upgrade) {
+            return ConnectLimit(permits);
+        }
+
+        let permits = Rc::new(RefCell::new(Permits {
+            max: max.max(1),
+            held: 0,
+            waiting: Vec::new(),
+        }));
+        limits.insert(*addr, Rc::downgrade(&permits));
+        ConnectLimit(permits)
+    }
+}
+
+// ===== impl ConnectLimit =====
+
+impl ConnectLimit {
+    /// Acquires a permit, or registers the current task to be notified once
+    /// one is released.
+    fn poll_acquire(&self) -> Async<Permit> {
+        let mut permits = self.0.borrow_mut();
+        if permits.held < permits.max {
+            permits.held += 1;
+            return Async::Ready(Permit(self.0.clone()));
+        }
+
+        permits.waiting.push(task::current());
+        Async::NotReady
+    }
+}
+
+// ===== impl LimitConnect =====
+
+impl<C> LimitConnect<C> {
+    pub fn new(inner: C, limit: Option<ConnectLimit>) -> Self {
+        LimitConnect { inner, limit }
+    }
+}
+
+impl<C> tokio_connect::Connect for LimitConnect<C>
+where
+    C: tokio_connect::Connect + Clone,
+{
+    type Connected = C::Connected;
+    type Error = C::Error;
+    type Future = Connecting<C>;
+
+    fn connect(&self) -> Self::Future {
+        let state = match self.limit {
+            Some(ref limit) => State::Waiting(self.inner.clone(), limit.clone()),
+            None => State::Connecting(self.inner.connect(), None),
+        };
+        Connecting { state }
+    }
+}
+
+// ===== impl Connecting =====
+
+impl<C: tokio_connect::Connect> Future for Connecting<C> {
+    type Item = C::Connected;
+    type Error = C::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Waiting(ref connect, ref limit) => match limit.poll_acquire() {
+                    Async::Ready(permit) => State::Connecting(connect.connect(), Some(permit)),
+                    Async::NotReady => {
+                        trace!("waiting for another connection attempt to complete");
+                        return Ok(Async::NotReady);
+                    }
+                },
+                State::Connecting(ref mut f, ref mut permit) => {
+                    let connected = f.poll();
+                    if let Ok(Async::NotReady) = connected {
+                        return Ok(Async::NotReady);
+                    }
+                    // The attempt has completed, successfully or not, so the
+                    // next may begin.
+                    permit.take();
+                    return connected;
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+// ===== impl Permit =====
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let waiting = {
+            let mut permits = self.0.borrow_mut();
+            permits.held -= 1;
+            mem::replace(&mut permits.waiting, Vec::new())
+        };
+        // Every waiter is notified, since some may no longer be waiting.
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::future;
+    use futures::sync::oneshot;
+
+    use super::*;
+
+    /// Counts the attempts in flight, each of which completes once its
+    /// sender is sent.
+    #[derive(Clone)]
+    struct Attempts {
+        in_flight: Rc<Cell<usize>>,
+        max_in_flight: Rc<Cell<usize>>,
+        complete: Rc<RefCell<Vec<oneshot::Sender<()>>>>,
+    }
+
+    struct Attempt {
+        rx: oneshot::Receiver<()>,
+        in_flight: Rc<Cell<usize>>,
+    }
+
+    impl tokio_connect::Connect for Attempts {
+        type Connected = ();
+        type Error = ();
+        type Future = Attempt;
+
+        fn connect(&self) -> Attempt {
+            let in_flight = self.in_flight.get() + 1;
+            self.in_flight.set(in_flight);
+            self.max_in_flight.set(self.max_in_flight.get().max(in_flight));
+            let (tx, rx) = oneshot::channel();
+            self.complete.borrow_mut().push(tx);
+            Attempt {
+                rx,
+                in_flight: self.in_flight.clone(),
+            }
+        }
+    }
+
+    impl Future for Attempt {
+        type Item = ();
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<(), ()> {
+            try_ready!(self.rx.poll().map_err(|_| ()));
+            self.in_flight.set(self.in_flight.get() - 1);
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn attempts() -> Attempts {
+        Attempts {
+            in_flight: Rc::new(Cell::new(0)),
+            max_in_flight: Rc::new(Cell::new(0)),
+            complete: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    #[test]
+    fn limits_connection_attempts_in_flight_per_endpoint() {
+        let limits = ConnectLimits::default();
+        let addr = "10.1.1.1:80".parse().unwrap();
+        let other = "10.1.1.2:80".parse().unwrap();
+        let inner = attempts();
+
+        // Clients bound separately to the same endpoint share its limit.
+        let a = LimitConnect::new(inner.clone(), Some(limits.limit(&addr, 1)));
+        let b = LimitConnect::new(inner.clone(), Some(limits.limit(&addr, 1)));
+        let elsewhere = LimitConnect::new(inner.clone(), Some(limits.limit(&other, 1)));
+
+        future::lazy(|| {
+            let mut connecting = (0..5)
+                .map(|i| tokio_connect::Connect::connect(if i % 2 == 0 { &a } else { &b }))
+                .collect::<Vec<_>>();
+            for c in &mut connecting {
+                assert!(c.poll().unwrap().is_not_ready());
+            }
+            assert_eq!(inner.in_flight.get(), 1, "only one attempt is made at a time");
+
+            let mut elsewhere = tokio_connect::Connect::connect(&elsewhere);
+            assert!(elsewhere.poll().unwrap().is_not_ready());
+            assert_eq!(inner.in_flight.get(), 2, "other endpoints aren't limited");
+            inner.complete.borrow_mut().remove(1).send(()).unwrap();
+            assert!(elsewhere.poll().unwrap().is_ready());
+
+            // As each attempt completes, the next begins.
+            for i in 0..5 {
+                inner.complete.borrow_mut().remove(0).send(()).unwrap();
+                assert!(connecting[i].poll().unwrap().is_ready());
+                for c in connecting.iter_mut().skip(i + 1) {
+                    assert!(c.poll().unwrap().is_not_ready());
+                }
+            }
+
+            // The only attempts ever in flight together were to different
+            // endpoints.
+            assert_eq!(inner.max_in_flight.get(), 2);
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn abandoned_attempts_release_their_permits() {
+        let limits = ConnectLimits::default();
+        let addr = "10.1.1.1:80".parse().unwrap();
+        let inner = attempts();
+        let connect = LimitConnect::new(inner.clone(), Some(limits.limit(&addr, 1)));
+
+        future::lazy(|| {
+            let mut first = tokio_connect::Connect::connect(&connect);
+            let mut second = tokio_connect::Connect::connect(&connect);
+            assert!(first.poll().unwrap().is_not_ready());
+            assert!(second.poll().unwrap().is_not_ready());
+
+            drop(first);
+            assert!(second.poll().unwrap().is_not_ready());
+            assert_eq!(inner.complete.borrow().len(), 2, "the second attempt begins");
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,754 @@
+//! Keepalive for client connections.
+//!
+//! A connection whose peer has gone away without closing it, e.g. because
+//! its host was lost or a NAT dropped the flow, otherwise appears healthy
+//! until requests on it time out.
+//!
+//! `h2` does not expose PINGs, so `Pinging` sends them itself: its PING
+//! frames are written between the frames written by `h2`, and their
+//! acknowledgements are removed before `h2` reads them.
+//!
+//! PINGs may also be requested through a connection's `Liveness`, so that a
+//! connection that has idled may be validated before it's reused, rather
+//! than waiting for the next periodic PING to notice that it was lost.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{task, Async, Future, Poll};
+use tokio_connect;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The length of the preface that a client sends before its first frame.
+const PREFACE_LEN: usize = 24;
+
+const FRAME_HEADER_LEN: usize = 9;
+
+const PING_TYPE: u8 = 0x6;
+
+const ACK_FLAG: u8 = 0x1;
+
+/// Distinguishes acknowledgements of our PINGs from those of PINGs sent by
+/// `h2`, which are passed through.
+const PING_PAYLOAD: &[u8; 8] = b"conduit!";
+
+/// Configures keepalive for client connections.
+///
+/// By default, neither TCP keepalive nor HTTP/2 PINGs are enabled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    tcp: Option<Duration>,
+    ping: Option<PingConfig>,
+}
+
+/// Configures the PINGs sent on HTTP/2 connections.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PingConfig {
+    interval: Duration,
+    timeout: Duration,
+    idle_validation: Option<Duration>,
+}
+
+/// Wraps the connections made by a `Connect` in `Pinging`.
+#[derive(Clone, Debug)]
+pub struct Connect<C> {
+    inner: C,
+    ping: Option<PingConfig>,
+    handle: Handle,
+    liveness: Option<Liveness>,
+}
+
+/// A connection attempt made by `Connect`.
+pub struct Connecting<F> {
+    inner: F,
+    ping: Option<PingConfig>,
+    handle: Handle,
+    liveness: Option<Liveness>,
+}
+
+/// Validates that a `Pinging` connection's peer is still there, by PINGing
+/// it on request. Clones share the same connection.
+#[derive(Clone, Debug, Default)]
+pub struct Liveness(Rc<RefCell<Probe>>);
+
+#[derive(Debug, Default)]
+struct Probe {
+    /// When a PING on the connection was last acknowledged.
+    acked_at: Option<Instant>,
+    requested: bool,
+    /// Set once a PING has gone unacknowledged, or the connection is gone.
+    failed: bool,
+    /// The connection's task, which sends requested PINGs.
+    conn: Option<task::Task>,
+    /// The task waiting for a requested PING to be acknowledged.
+    waiting: Option<task::Task>,
+}
+
+/// An HTTP/2 client connection that is PINGed periodically.
+///
+/// If a PING is not acknowledged in time, reads from the connection fail,
+/// so that it is closed and may be re-established. Without a `PingConfig`,
+/// the connection is used as-is.
+pub struct Pinging<T> {
+    io: T,
+    ping: Option<Ping>,
+}
+
+struct Ping {
+    config: PingConfig,
+    timer: Timeout,
+    awaiting_ack: bool,
+    liveness: Option<Liveness>,
+
+    /// How much of a pending PING frame has been written, if one is pending.
+    unsent: Option<usize>,
+
+    /// Tracks the frames written by `h2`, so that PINGs are not written in
+    /// the middle of one.
+    sent: Frames,
+
+    /// The header of the frame being read, until it is complete.
+    header: [u8; FRAME_HEADER_LEN],
+    header_len: usize,
+
+    /// The payload of a PING acknowledgement, until it is complete.
+    payload: [u8; 8],
+    payload_len: usize,
+
+    /// Bytes that have been read but not yet returned.
+    held: Vec<u8>,
+
+    /// The length of the payload being read that remains to be returned.
+    remaining: usize,
+}
+
+/// Tracks the frame boundaries in a stream of written HTTP/2 frames.
+struct Frames {
+    preface: usize,
+    header: [u8; FRAME_HEADER_LEN],
+    header_len: usize,
+    payload: usize,
+}
+
+// ===== impl KeepaliveConfig =====
+
+impl KeepaliveConfig {
+    /// Enables TCP keepalive on connections that have been idle for `idle`.
+    pub fn with_tcp(self, idle: Duration) -> Self {
+        Self {
+            tcp: Some(idle),
+            ..self
+        }
+    }
+
+    /// PINGs HTTP/2 connections every `interval`, closing those on which a
+    /// PING has not been acknowledged within `timeout`.
+    pub fn with_ping(self, interval: Duration, timeout: Duration) -> Self {
+        Self {
+            ping: Some(PingConfig {
+                interval,
+                timeout,
+                idle_validation: None,
+            }),
+            ..self
+        }
+    }
+
+    /// Validates HTTP/2 connections that have been idle for `idle` with a
+    /// PING before they're reused, unless a PING was acknowledged since.
+    ///
+    /// Has no effect unless PINGs are enabled.
+    pub fn with_idle_validation(self, idle: Duration) -> Self {
+        Self {
+            ping: self.ping.map(|ping| PingConfig {
+                idle_validation: Some(idle),
+                ..ping
+            }),
+            ..self
+        }
+    }
+
+    pub fn tcp(&self) -> Option<Duration> {
+        self.tcp
+    }
+
+    pub fn ping(&self) -> Option<PingConfig> {
+        self.ping
+    }
+}
+
+// ===== impl PingConfig =====
+
+impl PingConfig {
+    /// Returns how long a connection may idle before it's validated.
+    pub fn idle_validation(&self) -> Option<Duration> {
+        self.idle_validation
+    }
+}
+
+// ===== impl Connect =====
+
+impl<C> Connect<C> {
+    pub fn new(inner: C, ping: Option<PingConfig>, handle: &Handle) -> Self {
+        Self {
+            inner,
+            ping,
+            handle: handle.clone(),
+            liveness: None,
+        }
+    }
+
+    /// Validates connections through `liveness`, which should therefore be
+    /// used for a single connection.
+    pub fn with_liveness(self, liveness: Liveness) -> Self {
+        Self {
+            liveness: Some(liveness),
+            ..self
+        }
+    }
+}
+
+impl<C: tokio_connect::Connect> tokio_connect::Connect for Connect<C> {
+    type Connected = Pinging<C::Connected>;
+    type Error = C::Error;
+    type Future = Connecting<C::Future>;
+
+    fn connect(&self) -> Self::Future {
+        Connecting {
+            inner: self.inner.connect(),
+            ping: self.ping,
+            handle: self.handle.clone(),
+            liveness: self.liveness.clone(),
+        }
+    }
+}
+
+impl<F: Future> Future for Connecting<F> {
+    type Item = Pinging<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let io = try_ready!(self.inner.poll());
+        let io = Pinging::new(io, self.ping, &self.handle);
+        Ok(Async::Ready(match self.liveness.take() {
+            Some(liveness) => io.with_liveness(liveness),
+            None => io,
+        }))
+    }
+}
+
+// ===== impl Pinging =====
+
+impl<T> Pinging<T> {
+    pub fn new(io: T, ping: Option<PingConfig>, handle: &Handle) -> Self {
+        let ping = ping.and_then(|config| match Timeout::new(config.interval, handle) {
+            Ok(timer) => Some(Ping::new(config, timer)),
+            Err(e) => {
+                warn!("could not schedule keepalive PINGs: {}", e);
+                None
+            }
+        });
+        Pinging { io, ping }
+    }
+
+    /// Sends the PINGs that `liveness` requests. A connection that isn't
+    /// PINGed can't be validated, so it fails validation.
+    pub fn with_liveness(mut self, liveness: Liveness) -> Self {
+        match self.ping {
+            Some(ref mut ping) => ping.liveness = Some(liveness),
+            None => liveness.fail(),
+        }
+        self
+    }
+}
+
+// ===== impl Liveness =====
+
+impl Liveness {
+    /// Succeeds if a PING has been acknowledged `within` the given time.
+    /// Otherwise, a PING is requested, and the current task is notified once
+    /// it has been acknowledged, or the connection has failed.
+    pub fn poll_alive(&self, within: Duration) -> Poll<(), io::Error> {
+        let mut probe = self.0.borrow_mut();
+        if probe.failed {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection failed validation",
+            ));
+        }
+        if probe.acked_at.map(|at| at.elapsed() < within).unwrap_or(false) {
+            return Ok(Async::Ready(()));
+        }
+
+        if !probe.requested {
+            trace!("validating connection with a PING");
+            probe.requested = true;
+            if let Some(ref conn) = probe.conn {
+                conn.notify();
+            }
+        }
+        probe.waiting = Some(task::current());
+        Ok(Async::NotReady)
+    }
+
+    /// Registers the current task as the connection's.
+    fn register(&self) {
+        self.0.borrow_mut().conn = Some(task::current());
+    }
+
+    fn is_requested(&self) -> bool {
+        self.0.borrow().requested
+    }
+
+    fn acked(&self) {
+        let mut probe = self.0.borrow_mut();
+        probe.acked_at = Some(Instant::now());
+        probe.requested = false;
+        if let Some(waiting) = probe.waiting.take() {
+            waiting.notify();
+        }
+    }
+
+    fn fail(&self) {
+        let mut probe = self.0.borrow_mut();
+        probe.failed = true;
+        if let Some(waiting) = probe.waiting.take() {
+            waiting.notify();
+        }
+    }
+}
+
+impl<T: Read + Write> Read for Pinging<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let ping = match self.ping {
+            Some(ref mut ping) => ping,
+            None => return self.io.read(buf),
+        };
+
+        if let Some(ref liveness) = ping.liveness {
+            liveness.register();
+        }
+        ping.poll_timer()?;
+        ping.write_pending_or_block(&mut self.io)?;
+
+        loop {
+            if !ping.held.is_empty() {
+                let n = cmp::min(buf.len(), ping.held.len());
+                buf[..n].copy_from_slice(&ping.held[..n]);
+                ping.held.drain(..n);
+                return Ok(n);
+            }
+
+            if ping.remaining > 0 {
+                let max = cmp::min(buf.len(), ping.remaining);
+                let n = self.io.read(&mut buf[..max])?;
+                ping.remaining -= n;
+                return Ok(n);
+            }
+
+            while ping.header_len < FRAME_HEADER_LEN {
+                let n = self.io.read(&mut ping.header[ping.header_len..])?;
+                if n == 0 {
+                    // Let `h2` see the truncated frame, and then the EOF.
+                    if ping.header_len == 0 {
+                        return Ok(0);
+                    }
+                    ping.held.extend_from_slice(&ping.header[..ping.header_len]);
+                    ping.header_len = 0;
+                    break;
+                }
+                ping.header_len += n;
+            }
+            if ping.header_len < FRAME_HEADER_LEN {
+                continue;
+            }
+
+            let len = frame_len(&ping.header);
+            let (kind, flags) = (ping.header[3], ping.header[4]);
+            if kind != PING_TYPE || flags & ACK_FLAG == 0 || len != PING_PAYLOAD.len() {
+                ping.held.extend_from_slice(&ping.header);
+                ping.header_len = 0;
+                ping.remaining = len;
+                continue;
+            }
+
+            while ping.payload_len < PING_PAYLOAD.len() {
+                let n = self.io.read(&mut ping.payload[ping.payload_len..])?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                ping.payload_len += n;
+            }
+            ping.header_len = 0;
+            ping.payload_len = 0;
+
+            if ping.payload == *PING_PAYLOAD {
+                if ping.awaiting_ack {
+                    ping.acked()?;
+                }
+            } else {
+                ping.held.extend_from_slice(&ping.header);
+                ping.held.extend_from_slice(&ping.payload);
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for Pinging<T> {}
+
+impl<T: Write> Write for Pinging<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let ping = match self.ping {
+            Some(ref mut ping) => ping,
+            None => return self.io.write(buf),
+        };
+
+        ping.write_pending(&mut self.io)?;
+        if ping.unsent.map(|n| n > 0).unwrap_or(false) {
+            // A partially-written PING must be completed first.
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = self.io.write(buf)?;
+        ping.sent.advance(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Pinging<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+// ===== impl Ping =====
+
+impl Ping {
+    fn new(config: PingConfig, timer: Timeout) -> Self {
+        Ping {
+            config,
+            timer,
+            awaiting_ack: false,
+            liveness: None,
+            unsent: None,
+            sent: Frames::new(),
+            header: [0; FRAME_HEADER_LEN],
+            header_len: 0,
+            payload: [0; 8],
+            payload_len: 0,
+            held: Vec::new(),
+            remaining: 0,
+        }
+    }
+
+    /// Queues a PING once the interval has elapsed, or when one has been
+    /// requested, and fails if a PING has not been acknowledged within the
+    /// timeout.
+    fn poll_timer(&mut self) -> io::Result<()> {
+        let requested = self.liveness.as_ref().map(Liveness::is_requested).unwrap_or(false);
+        if requested && !self.awaiting_ack {
+            trace!("sending validation PING");
+            self.send();
+        }
+
+        while let Async::Ready(()) = self.timer.poll()? {
+            if self.awaiting_ack {
+                if let Some(ref liveness) = self.liveness {
+                    liveness.fail();
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "keepalive PING was not acknowledged",
+                ));
+            }
+            trace!("sending keepalive PING");
+            self.send();
+        }
+        Ok(())
+    }
+
+    fn send(&mut self) {
+        self.unsent = Some(0);
+        self.awaiting_ack = true;
+        self.timer.reset(Instant::now() + self.config.timeout);
+    }
+
+    fn acked(&mut self) -> io::Result<()> {
+        trace!("keepalive PING acknowledged");
+        self.awaiting_ack = false;
+        if let Some(ref liveness) = self.liveness {
+            liveness.acked();
+        }
+        self.timer.reset(Instant::now() + self.config.interval);
+        self.poll_timer()
+    }
+
+    /// Writes the pending PING, if there is one, unless doing so would
+    /// split a frame written by `h2`.
+    fn write_pending<W: Write>(&mut self, io: &mut W) -> io::Result<()> {
+        let mut written = match self.unsent {
+            Some(written) => written,
+            None => return Ok(()),
+        };
+        if written == 0 && !self.sent.at_boundary() {
+            return Ok(());
+        }
+
+        let frame = ping_frame();
+        while written < frame.len() {
+            match io.write(&frame[written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) => {
+                    self.unsent = Some(written);
+                    return Err(e);
+                }
+            }
+        }
+        self.unsent = None;
+        io.flush()
+    }
+
+    /// Writes the pending PING from a read, in case `h2` has nothing to
+    /// write; a PING that would block is retried by later reads and writes.
+    fn write_pending_or_block<W: Write>(&mut self, io: &mut W) -> io::Result<()> {
+        match self.write_pending(io) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            res => res,
+        }
+    }
+}
+
+impl Drop for Ping {
+    fn drop(&mut self) {
+        // Anything waiting to validate the connection is told that it's gone.
+        if let Some(ref liveness) = self.liveness {
+            liveness.fail();
+        }
+    }
+}
+
+fn ping_frame() -> [u8; FRAME_HEADER_LEN + 8] {
+    let mut frame = [0; FRAME_HEADER_LEN + 8];
+    frame[2] = PING_PAYLOAD.len() as u8;
+    frame[3] = PING_TYPE;
+    frame[FRAME_HEADER_LEN..].copy_from_slice(PING_PAYLOAD);
+    frame
+}
+
+fn frame_len(header: &[u8; FRAME_HEADER_LEN]) -> usize {
+    (header[0] as usize) << 16 | (header[1] as usize) << 8 | header[2] as usize
+}
+
+// ===== impl Frames =====
+
+impl Frames {
+    fn new() -> Self {
+        Frames {
+            preface: PREFACE_LEN,
+            header: [0; FRAME_HEADER_LEN],
+            header_len: 0,
+            payload: 0,
+        }
+    }
+
+    fn at_boundary(&self) -> bool {
+        self.preface == 0 && self.header_len == 0 && self.payload == 0
+    }
+
+    fn advance(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let n = if self.preface > 0 {
+                let n = cmp::min(self.preface, buf.len());
+                self.preface -= n;
+                n
+            } else if self.payload > 0 {
+                let n = cmp::min(self.payload, buf.len());
+                self.payload -= n;
+                n
+            } else {
+                let n = cmp::min(FRAME_HEADER_LEN - self.header_len, buf.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&buf[..n]);
+                self.header_len += n;
+                if self.header_len == FRAME_HEADER_LEN {
+                    self.header_len = 0;
+                    self.payload = frame_len(&self.header);
+                }
+                n
+            };
+            buf = &buf[n..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use tokio_core::reactor::Core;
+
+    use transport::mock::Io;
+    use super::*;
+
+    const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+    /// A SETTINGS frame with no settings.
+    const SETTINGS: &[u8] = &[0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+
+    fn ping_ack(payload: &[u8; 8]) -> Vec<u8> {
+        let mut frame = ping_frame().to_vec();
+        frame[4] = ACK_FLAG;
+        frame[FRAME_HEADER_LEN..].copy_from_slice(payload);
+        frame
+    }
+
+    fn read_available(io: &mut Io) -> Vec<u8> {
+        let mut buf = [0; 1024];
+        match io.read(&mut buf) {
+            Ok(n) => buf[..n].to_vec(),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Vec::new(),
+            Err(e) => panic!("read failed: {}", e),
+        }
+    }
+
+    /// Reads `len` bytes, failing if the connection fails.
+    fn read_exact(core: &mut Core, io: &mut Pinging<Io>, len: usize) -> io::Result<Vec<u8>> {
+        let mut read = Vec::new();
+        let mut buf = [0; 64];
+        core.run(future::poll_fn(|| {
+            while read.len() < len {
+                match io.read(&mut buf) {
+                    Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => read.extend_from_slice(&buf[..n]),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(Async::Ready(()))
+        }))?;
+        Ok(read)
+    }
+
+    fn pinging(core: &Core, client: Io) -> Pinging<Io> {
+        let ms = Duration::from_millis;
+        let config = KeepaliveConfig::default().with_ping(ms(10), ms(50));
+        let mut io = Pinging::new(client, config.ping(), &core.handle());
+        io.write_all(PREFACE).unwrap();
+        io.write_all(SETTINGS).unwrap();
+        io
+    }
+
+    #[test]
+    fn unacknowledged_ping_fails_the_connection() {
+        let mut core = Core::new().unwrap();
+        let (client, mut server) = Io::pair();
+        let mut io = pinging(&core, client);
+
+        let err = read_exact(&mut core, &mut io, 1).expect_err("read should fail");
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let mut expected = PREFACE.to_vec();
+        expected.extend_from_slice(SETTINGS);
+        expected.extend_from_slice(&ping_frame());
+        assert_eq!(read_available(&mut server), expected);
+    }
+
+    #[test]
+    fn acknowledgements_are_not_read() {
+        let mut core = Core::new().unwrap();
+        let (client, mut server) = Io::pair();
+        let mut io = pinging(&core, client);
+
+        // Wait for the PING to be sent, and acknowledge it.
+        let mut buf = [0; 64];
+        core.run(future::poll_fn(|| {
+            if let Err(e) = io.read(&mut buf) {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    return Err(e);
+                }
+            }
+            let sent = read_available(&mut server);
+            if sent.ends_with(&ping_frame()) {
+                return Ok(Async::Ready(()));
+            }
+            Ok(Async::NotReady)
+        })).unwrap();
+        server.write_all(&ping_ack(PING_PAYLOAD)).unwrap();
+
+        // Acknowledgements of other PINGs are passed through.
+        let other = ping_ack(b"h2 ping!");
+        server.write_all(&other).unwrap();
+        server.write_all(SETTINGS).unwrap();
+
+        let read = read_exact(&mut core, &mut io, other.len() + SETTINGS.len()).unwrap();
+        let mut expected = other;
+        expected.extend_from_slice(SETTINGS);
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn validation_pings_connections_on_request() {
+        let mut core = Core::new().unwrap();
+        let (client, mut server) = Io::pair();
+        let ms = Duration::from_millis;
+        // Periodic PINGs are sent too rarely to be seen here.
+        let config = KeepaliveConfig::default().with_ping(Duration::from_secs(60), ms(50));
+        let liveness = Liveness::default();
+        let mut io = Pinging::new(client, config.ping(), &core.handle())
+            .with_liveness(liveness.clone());
+        io.write_all(PREFACE).unwrap();
+        io.write_all(SETTINGS).unwrap();
+        read_available(&mut server);
+
+        // The connection is valid once the requested PING is acknowledged.
+        let mut buf = [0; 64];
+        core.run(future::poll_fn(|| {
+            if let Err(e) = io.read(&mut buf) {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    return Err(e);
+                }
+            }
+            if read_available(&mut server).ends_with(&ping_frame()) {
+                server.write_all(&ping_ack(PING_PAYLOAD))?;
+            }
+            liveness.poll_alive(ms(50))
+        })).unwrap();
+
+        // Once its peer no longer responds, it fails validation.
+        let err = core.run(future::poll_fn(|| {
+            if let Err(e) = io.read(&mut buf) {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    return Err(e);
+                }
+            }
+            liveness.poll_alive(ms(0))
+        })).expect_err("validation should fail");
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(liveness.poll_alive(Duration::from_secs(60)).is_err());
+    }
+
+    #[test]
+    fn pings_are_not_written_within_frames() {
+        let mut frames = Frames::new();
+        assert!(!frames.at_boundary());
+        frames.advance(PREFACE);
+        assert!(frames.at_boundary());
+
+        // A DATA frame with a 3-byte payload, written in pieces.
+        frames.advance(&[0, 0, 3, 0, 0]);
+        assert!(!frames.at_boundary());
+        frames.advance(&[0, 0, 0, 1, b'a', b'b']);
+        assert!(!frames.at_boundary());
+        frames.advance(&[b'c']);
+        assert!(frames.at_boundary());
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+
+use tokio_core::net::TcpStream;
+
+/// Configures how promptly sockets send, and acknowledge, small amounts of
+/// data.
+///
+/// By default, `TCP_NODELAY` is set, so that Nagle's algorithm doesn't hold
+/// back small writes, such as HTTP/2 frames and HTTP/1 heads, until earlier
+/// ones have been acknowledged.
+///
+/// A connection may also be primed with `TCP_QUICKACK`, so that the first
+/// bytes it receives are acknowledged right away, rather than once the
+/// kernel's delayed acknowledgement timer fires. The kernel may return to
+/// delaying acknowledgements once the connection is established, so this
+/// mostly benefits the first requests on a connection. It's only supported
+/// on Linux.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SocketLatency {
+    nodelay: bool,
+    quickack: bool,
+}
+
+// ===== impl SocketLatency =====
+
+impl Default for SocketLatency {
+    fn default() -> Self {
+        SocketLatency {
+            nodelay: true,
+            quickack: false,
+        }
+    }
+}
+
+impl SocketLatency {
+    /// Sets whether `TCP_NODELAY` is set on each socket.
+    pub fn with_nodelay(self, nodelay: bool) -> Self {
+        Self {
+            nodelay,
+            ..self
+        }
+    }
+
+    /// Sets whether each socket is primed with `TCP_QUICKACK`.
+    pub fn with_quickack(self, quickack: bool) -> Self {
+        Self {
+            quickack,
+            ..self
+        }
+    }
+
+    /// Sets the configured options on `socket`, warning if they can't be
+    /// set.
+    pub fn apply(&self, socket: &TcpStream) {
+        if let Err(e) = socket.set_nodelay(self.nodelay) {
+            warn!(
+                "could not set TCP_NODELAY on {:?}/{:?}: {}",
+                socket.local_addr(),
+                socket.peer_addr(),
+                e
+            );
+        }
+        if self.quickack {
+            if let Err(e) = set_quickack(socket) {
+                warn!(
+                    "could not set TCP_QUICKACK on {:?}/{:?}: {}",
+                    socket.local_addr(),
+                    socket.peer_addr(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_quickack(socket: &TcpStream) -> io::Result<()> {
+    use libc;
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_QUICKACK,
+            &on as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_quickack(_: &TcpStream) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "TCP_QUICKACK is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use tokio_core::reactor::Core;
+
+    use connection::{self, Connection};
+    use transport::SocketBuffers;
+    use super::*;
+
+    fn connect(core: &mut Core, latency: SocketLatency) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connecting = connection::connect(
+            &addr,
+            &core.handle(),
+            None,
+            None,
+            SocketBuffers::default(),
+            latency,
+        );
+        match core.run(connecting).unwrap() {
+            Connection::Plain(socket) => socket,
+            c => panic!("connection should be plaintext: {:?}", c),
+        }
+    }
+
+    #[test]
+    fn sets_nodelay_on_connect_by_default() {
+        let mut core = Core::new().unwrap();
+        let socket = connect(&mut core, SocketLatency::default());
+        assert!(socket.nodelay().unwrap());
+    }
+
+    #[test]
+    fn nodelay_may_be_disabled() {
+        let mut core = Core::new().unwrap();
+        let socket = connect(&mut core, SocketLatency::default().with_nodelay(false));
+        assert!(!socket.nodelay().unwrap());
+    }
+}
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+use std::io;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{task, Async, Future, Poll};
+use tokio_connect;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Bounds the number of connections open to upstreams across every endpoint,
+/// such as to bound the number of file descriptors that the proxy uses.
+///
+/// Unlike a `ConnectLimit`, which bounds the attempts to connect to a single
+/// endpoint, each of `max` permits is held from the time a connection is
+/// attempted until the connection is closed. Attempts beyond the limit wait
+/// for a connection to close, or, if the wait is limited, fail once they've
+/// waited too long. Clones share the same permits.
+#[derive(Clone, Debug)]
+pub struct MaxConnections {
+    permits: Rc<RefCell<Permits>>,
+    wait_timeout: Option<Duration>,
+}
+
+/// Connects once the proxy's `MaxConnections`, if it has any, permits it.
+#[derive(Clone, Debug)]
+pub struct LimitConnections<C> {
+    inner: C,
+    limit: Option<MaxConnections>,
+    handle: Handle,
+}
+
+pub struct Connecting<C: tokio_connect::Connect> {
+    state: State<C>,
+}
+
+enum State<C: tokio_connect::Connect> {
+    /// Waiting for a permit, until the timeout, if any, fires.
+    Waiting(C, MaxConnections, Option<Timeout>),
+    Connecting(C::Future, Option<Permit>),
+}
+
+/// A connection that holds one of the `MaxConnections` permits until it's
+/// dropped.
+#[derive(Debug)]
+pub struct Permitted<T> {
+    io: T,
+    _permit: Option<Permit>,
+}
+
+/// Holds one of a `MaxConnections`'s permits until dropped.
+#[derive(Debug)]
+struct Permit(Rc<RefCell<Permits>>);
+
+#[derive(Debug)]
+struct Permits {
+    max: usize,
+    held: usize,
+    waiting: Vec<task::Task>,
+}
+
+// ===== impl MaxConnections =====
+
+impl MaxConnections {
+    /// Limits the proxy to `max` connections. A `max` of zero is treated as
+    /// one, so that connections may be established at all.
+    pub fn new(max: usize) -> Self {
+        MaxConnections {
+            permits: Rc::new(RefCell::new(Permits {
+                max: max.max(1),
+                held: 0,
+                waiting: Vec::new(),
+            })),
+            wait_timeout: None,
+        }
+    }
+
+    /// Fails connection attempts that have waited `timeout` for a permit,
+    /// rather than letting them wait until one is released.
+    pub fn with_wait_timeout(self, timeout: Duration) -> Self {
+        MaxConnections {
+            wait_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Acquires a permit, or registers the current task to be notified once
+    /// one is released.
+    fn poll_acquire(&self) -> Async<Permit> {
+        let mut permits = self.permits.borrow_mut();
+        if permits.held < permits.max {
+            permits.held += 1;
+            return Async::Ready(Permit(self.permits.clone()));
+        }
+
+        permits.waiting.push(task::current());
+        Async::NotReady
+    }
+}
+
+// ===== impl LimitConnections =====
+
+impl<C> LimitConnections<C> {
+    pub fn new(inner: C, limit: Option<MaxConnections>, handle: &Handle) -> Self {
+        LimitConnections {
+            inner,
+            limit,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<C> tokio_connect::Connect for LimitConnections<C>
+where
+    C: tokio_connect::Connect + Clone,
+    C::Error: From<io::Error>,
+{
+    type Connected = Permitted<C::Connected>;
+    type Error = C::Error;
+    type Future = Connecting<C>;
+
+    fn connect(&self) -> Self::Future {
+        let state = match self.limit {
+            Some(ref limit) => {
+                let timeout = limit.wait_timeout.map(|timeout| {
+                    Timeout::new(timeout, &self.handle).expect("reactor gone")
+                });
+                State::Waiting(self.inner.clone(), limit.clone(), timeout)
+            }
+            None => State::Connecting(self.inner.connect(), None),
+        };
+        Connecting { state }
+    }
+}
+
+// ===== impl Connecting =====
+
+impl<C> Future for Connecting<C>
+where
+    C: tokio_connect::Connect,
+    C::Error: From<io::Error>,
+{
+    type Item = Permitted<C::Connected>;
+    type Error = C::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Waiting(ref connect, ref limit, ref mut timeout) => {
+                    if let Async::Ready(permit) = limit.poll_acquire() {
+                        State::Connecting(connect.connect(), Some(permit))
+                    } else {
+                        let timed_out = match *timeout {
+                            Some(ref mut timeout) => timeout.poll()?.is_ready(),
+                            None => false,
+                        };
+                        if timed_out {
+                            debug!("proxy connection limit reached; giving up on connecting");
+                            let e = io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "timed out waiting for the proxy's connection limit",
+                            );
+                            return Err(e.into());
+                        }
+                        trace!("waiting for a connection to close");
+                        return Ok(Async::NotReady);
+                    }
+                }
+                State::Connecting(ref mut f, ref mut permit) => {
+                    let io = try_ready!(f.poll());
+                    return Ok(Async::Ready(Permitted {
+                        io,
+                        _permit: permit.take(),
+                    }));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+// ===== impl Permitted =====
+
+impl<T: io::Read> io::Read for Permitted<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<T: io::Write> io::Write for Permitted<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Permitted<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Permitted<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+// ===== impl Permit =====
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let waiting = {
+            let mut permits = self.0.borrow_mut();
+            permits.held -= 1;
+            mem::replace(&mut permits.waiting, Vec::new())
+        };
+        // Every waiter is notified, since some may no longer be waiting.
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Instant;
+
+    use futures::future;
+    use futures::sync::oneshot;
+    use tokio_connect::Connect;
+    use tokio_core::reactor::Core;
+
+    use super::*;
+
+    /// Counts the connections open, each of which is established once its
+    /// sender is sent.
+    #[derive(Clone)]
+    struct Connections {
+        open: Rc<Cell<usize>>,
+        established: Rc<RefCell<Vec<oneshot::Sender<()>>>>,
+    }
+
+    struct Establishing {
+        rx: oneshot::Receiver<()>,
+        open: Rc<Cell<usize>>,
+    }
+
+    /// Closes when dropped.
+    struct Conn(Rc<Cell<usize>>);
+
+    impl Connect for Connections {
+        type Connected = Conn;
+        type Error = io::Error;
+        type Future = Establishing;
+
+        fn connect(&self) -> Establishing {
+            let (tx, rx) = oneshot::channel();
+            self.established.borrow_mut().push(tx);
+            Establishing {
+                rx,
+                open: self.open.clone(),
+            }
+        }
+    }
+
+    impl Future for Establishing {
+        type Item = Conn;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Poll<Conn, io::Error> {
+            try_ready!(self.rx.poll().map_err(|_| io::Error::from(io::ErrorKind::Other)));
+            self.open.set(self.open.get() + 1);
+            Ok(Async::Ready(Conn(self.open.clone())))
+        }
+    }
+
+    impl Drop for Conn {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() - 1);
+        }
+    }
+
+    fn connections() -> Connections {
+        Connections {
+            open: Rc::new(Cell::new(0)),
+            established: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Establishes the oldest pending connection.
+    fn establish(inner: &Connections) {
+        inner.established.borrow_mut().remove(0).send(()).unwrap();
+    }
+
+    fn established(connecting: &mut Connecting<Connections>) -> Permitted<Conn> {
+        match connecting.poll().unwrap() {
+            Async::Ready(conn) => conn,
+            Async::NotReady => panic!("connection not established"),
+        }
+    }
+
+    #[test]
+    fn connections_wait_for_others_to_close() {
+        let core = Core::new().unwrap();
+        let inner = connections();
+        let limit = MaxConnections::new(2);
+
+        // Clients to different endpoints share the same limit.
+        let a = LimitConnections::new(inner.clone(), Some(limit.clone()), &core.handle());
+        let b = LimitConnections::new(inner.clone(), Some(limit.clone()), &core.handle());
+
+        future::lazy(|| {
+            let mut first = a.connect();
+            let mut second = b.connect();
+            let mut third = a.connect();
+            assert!(first.poll().unwrap().is_not_ready());
+            assert!(second.poll().unwrap().is_not_ready());
+            assert!(third.poll().unwrap().is_not_ready());
+            assert_eq!(inner.established.borrow().len(), 2, "pending connects hold permits");
+
+            establish(&inner);
+            establish(&inner);
+            let first = established(&mut first);
+            let _second = established(&mut second);
+            assert!(third.poll().unwrap().is_not_ready());
+            assert_eq!(inner.established.borrow().len(), 0, "open connections hold permits");
+
+            // Once a connection closes, the next attempt begins.
+            drop(first);
+            assert!(third.poll().unwrap().is_not_ready());
+            assert_eq!(inner.established.borrow().len(), 1);
+            establish(&inner);
+            let _third = established(&mut third);
+            assert_eq!(inner.open.get(), 2);
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn waiting_connections_time_out() {
+        let mut core = Core::new().unwrap();
+        let inner = connections();
+        let timeout = Duration::from_millis(50);
+        let limit = MaxConnections::new(1).with_wait_timeout(timeout);
+        let connect = LimitConnections::new(inner.clone(), Some(limit), &core.handle());
+
+        let mut first = connect.connect();
+        core.run(future::lazy(|| {
+            assert!(first.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+
+        let started = Instant::now();
+        let err = core.run(connect.connect()).err().expect("connect should time out");
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() >= timeout);
+        assert_eq!(inner.established.borrow().len(), 1, "only the first connect is attempted");
+
+        // Once the first attempt is abandoned, others may connect.
+        drop(first);
+        let mut next = connect.connect();
+        core.run(future::lazy(|| {
+            assert!(next.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(inner.established.borrow().len(), 2);
+    }
+}
@@ -0,0 +1,196 @@
+//! In-memory connections, so that client stacks can be tested without
+//! binding sockets.
+//!
+//! A `Connect` passes the server's end of each connection it makes to a
+//! handler, which typically serves it with an HTTP server. `listen` routes
+//! the connections of `transport::Connect`s on the current thread to a mock,
+//! so that services built by `Bind` can be tested end-to-end.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use futures::{task, Async, Poll};
+use tokio_connect;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use connection;
+
+thread_local! {
+    static LISTENERS: RefCell<HashMap<SocketAddr, Connect>> = RefCell::new(HashMap::new());
+}
+
+/// Connects to an in-memory server.
+#[derive(Clone)]
+pub struct Connect {
+    handler: Rc<Fn(Io)>,
+}
+
+/// Routes connections to an address to a mock until dropped.
+#[derive(Debug)]
+pub struct Listening {
+    addr: SocketAddr,
+}
+
+/// One end of an in-memory connection.
+///
+/// Dropping or shutting down one end closes the connection for writing, so
+/// that reads from the other end complete once they have read everything
+/// that was written.
+pub struct Io {
+    rx: Rc<RefCell<Pipe>>,
+    tx: Rc<RefCell<Pipe>>,
+}
+
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+    reader: Option<task::Task>,
+}
+
+/// Routes the connections of `transport::Connect`s to `addr`, on the current
+/// thread, to `connect` until the returned `Listening` is dropped.
+pub fn listen(addr: SocketAddr, connect: Connect) -> Listening {
+    LISTENERS.with(|l| l.borrow_mut().insert(addr, connect));
+    Listening { addr }
+}
+
+/// Connects to the mock listening on `addr`, if there is one.
+pub(super) fn connect(addr: &SocketAddr) -> Option<connection::Connecting> {
+    LISTENERS.with(|l| {
+        l.borrow().get(addr).map(|connect| tokio_connect::Connect::connect(connect))
+    })
+}
+
+// ===== impl Connect =====
+
+impl Connect {
+    /// Passes the server's end of each connection to `handler`.
+    pub fn new<F: Fn(Io) + 'static>(handler: F) -> Self {
+        Connect {
+            handler: Rc::new(handler),
+        }
+    }
+}
+
+impl tokio_connect::Connect for Connect {
+    type Connected = connection::Connection;
+    type Error = io::Error;
+    type Future = connection::Connecting;
+
+    fn connect(&self) -> Self::Future {
+        let (client, server) = Io::pair();
+        (self.handler)(server);
+        connection::Connecting::mock(client)
+    }
+}
+
+impl fmt::Debug for Connect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connect").finish()
+    }
+}
+
+// ===== impl Listening =====
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        let addr = self.addr;
+        LISTENERS.with(|l| l.borrow_mut().remove(&addr));
+    }
+}
+
+// ===== impl Io =====
+
+impl Io {
+    /// Returns both ends of a new connection.
+    pub fn pair() -> (Io, Io) {
+        let a = Rc::new(RefCell::new(Pipe::default()));
+        let b = Rc::new(RefCell::new(Pipe::default()));
+        let a_end = Io {
+            rx: a.clone(),
+            tx: b.clone(),
+        };
+        let b_end = Io { rx: b, tx: a };
+        (a_end, b_end)
+    }
+}
+
+impl Read for Io {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut rx = self.rx.borrow_mut();
+        if rx.buf.is_empty() {
+            if rx.closed {
+                return Ok(0);
+            }
+            rx.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = ::std::cmp::min(buf.len(), rx.buf.len());
+        for (dst, src) in buf.iter_mut().zip(rx.buf.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl AsyncRead for Io {}
+
+impl Write for Io {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut tx = self.tx.borrow_mut();
+        if tx.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        tx.buf.extend(buf);
+        tx.notify();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for Io {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.tx.borrow_mut().close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for Io {
+    fn drop(&mut self) {
+        self.tx.borrow_mut().close();
+        // Further writes from the other end can't be read.
+        self.rx.borrow_mut().close();
+    }
+}
+
+impl fmt::Debug for Io {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Io")
+            .field("readable", &self.rx.borrow().buf.len())
+            .finish()
+    }
+}
+
+// ===== impl Pipe =====
+
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        self.notify();
+    }
+
+    fn notify(&mut self) {
+        if let Some(task) = self.reader.take() {
+            task.notify();
+        }
+    }
+}
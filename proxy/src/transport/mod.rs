@@ -1,9 +1,24 @@
+mod buffers;
 mod connect;
+mod connect_limit;
+pub mod keepalive;
+mod latency;
+mod max_connections;
+#[cfg(test)]
+pub mod mock;
 mod so_original_dst;
+pub mod tls;
+pub mod tunnel;
 
+pub use self::buffers::SocketBuffers;
 pub use self::connect::{
     Connect,
     DnsNameAndPort, Host, HostAndPort, HostAndPortError,
     LookupAddressAndConnect,
+    ResolutionFailed,
 };
+pub use self::connect_limit::{ConnectLimits, LimitConnect};
+pub use self::latency::SocketLatency;
+pub use self::max_connections::{LimitConnections, MaxConnections};
 pub use self::so_original_dst::{GetOriginalDst, SoOriginalDst};
+pub use self::tunnel::{ConnectProxy, TunnelRejected};
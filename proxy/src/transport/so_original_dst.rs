@@ -26,7 +26,9 @@ impl GetOriginalDst for SoOriginalDst {
 
         trace!("get_original_dst {:?}", sock);
 
-        let res = unsafe { linux::so_original_dst(sock.as_raw_fd()) };
+        // IPv6 sockets' original destinations are read at a different level.
+        let ipv6 = sock.local_addr().map(|addr| addr.is_ipv6()).unwrap_or(false);
+        let res = unsafe { linux::so_original_dst(sock.as_raw_fd(), ipv6) };
         res.ok()
     }
 }
@@ -38,14 +40,22 @@ mod linux {
     use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
     use std::os::unix::io::RawFd;
 
-    pub unsafe fn so_original_dst(fd: RawFd) -> io::Result<SocketAddr> {
+    /// `IP6T_SO_ORIGINAL_DST`, which `libc` doesn't define.
+    const IP6T_SO_ORIGINAL_DST: libc::c_int = 80;
+
+    pub unsafe fn so_original_dst(fd: RawFd, ipv6: bool) -> io::Result<SocketAddr> {
         let mut sockaddr: libc::sockaddr_storage = mem::zeroed();
         let mut socklen: libc::socklen_t = mem::size_of::<libc::sockaddr_storage>() as u32;
 
+        let (level, optname) = if ipv6 {
+            (libc::SOL_IPV6, IP6T_SO_ORIGINAL_DST)
+        } else {
+            (libc::SOL_IP, libc::SO_ORIGINAL_DST)
+        };
         let ret = libc::getsockopt(
             fd,
-            libc::SOL_IP,
-            libc::SO_ORIGINAL_DST,
+            level,
+            optname,
             &mut sockaddr as *mut _ as *mut _,
             &mut socklen as *mut _ as *mut _,
         );
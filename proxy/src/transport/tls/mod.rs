@@ -0,0 +1,548 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use futures::{Future, Poll};
+use rustls;
+use rustls::internal::msgs::enums::AlertDescription;
+use rustls::internal::pemfile;
+use rustls::sign;
+use tokio_rustls::{ClientConfigExt, ConnectAsync, TlsStream};
+use webpki;
+
+use connection::PlaintextSocket;
+use dns;
+
+/// A TLS stream originated by the proxy.
+pub type ClientTlsStream = TlsStream<PlaintextSocket, rustls::ClientSession>;
+
+/// TLS settings for connections originated by the proxy.
+#[derive(Clone)]
+pub struct ClientConfig(Arc<rustls::ClientConfig>);
+
+/// A certificate chain and private key that the proxy presents to servers
+/// that request client authentication.
+///
+/// An identity loaded from files may be reloaded, e.g. when its certificate
+/// is rotated. Handshakes begun after a reload present the new identity, and
+/// clones share reloads.
+#[derive(Clone)]
+pub struct ClientIdentity {
+    paths: Option<(PathBuf, PathBuf)>,
+    key: Arc<RwLock<sign::CertifiedKey>>,
+}
+
+/// Indicates that a server rejected the client identity presented during a
+/// TLS handshake, e.g. because its certificate has expired or does not match
+/// its private key.
+///
+/// Handshake failures for this reason are `io::Error`s of kind
+/// `PermissionDenied` that wrap an `IdentityRejected`.
+#[derive(Debug)]
+pub struct IdentityRejected(AlertDescription);
+
+/// Presents the current `ClientIdentity` when a server requests one.
+struct ResolveIdentity(Arc<RwLock<sign::CertifiedKey>>);
+
+/// The TLS settings for a single connection: the `ClientConfig`, and the name
+/// the server's certificate is validated against.
+///
+/// The name is also sent to the server with the SNI extension.
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    config: ClientConfig,
+    server_name: dns::Name,
+}
+
+/// A future that performs a TLS handshake over an established socket.
+pub struct UpgradeClientToTls(UpgradeInner);
+
+enum UpgradeInner {
+    Handshake {
+        connect: ConnectAsync<PlaintextSocket>,
+        presents_identity: bool,
+    },
+    Failed(Option<io::Error>),
+}
+
+// ===== impl ClientConfig =====
+
+impl ClientConfig {
+    /// Loads the PEM-encoded trust anchors (root CA certificates) in the file
+    /// at `path`.
+    pub fn load_trust_anchors(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Self::from_trust_anchors_pem(&mut BufReader::new(file))
+    }
+
+    /// Reads PEM-encoded trust anchors (root CA certificates) from `pem`.
+    ///
+    /// Fails if `pem` does not contain at least one valid certificate.
+    pub fn from_trust_anchors_pem(pem: &mut io::BufRead) -> io::Result<Self> {
+        let mut config = rustls::ClientConfig::new();
+        match config.root_store.add_pem_file(pem) {
+            Ok((n, _)) if n > 0 => Ok(ClientConfig(Arc::new(config))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no valid trust anchors found",
+            )),
+        }
+    }
+
+    /// Presents `identity` to servers that request client authentication.
+    pub fn with_client_identity(&self, identity: &ClientIdentity) -> Self {
+        let mut config = (*self.0).clone();
+        config.client_auth_cert_resolver = Arc::new(ResolveIdentity(identity.key.clone()));
+        ClientConfig(Arc::new(config))
+    }
+}
+
+impl fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientConfig").finish()
+    }
+}
+
+// ===== impl ClientIdentity =====
+
+impl ClientIdentity {
+    /// Loads a PEM-encoded certificate chain from `cert_path`, and the
+    /// PEM-encoded RSA private key for its first certificate from `key_path`.
+    pub fn load(cert_path: &Path, key_path: &Path) -> io::Result<Self> {
+        let key = load_certified_key(cert_path, key_path)?;
+        Ok(ClientIdentity {
+            paths: Some((cert_path.to_owned(), key_path.to_owned())),
+            key: Arc::new(RwLock::new(key)),
+        })
+    }
+
+    /// Reads a PEM-encoded certificate chain from `certs`, and the
+    /// PEM-encoded RSA private key for its first certificate from `key`.
+    ///
+    /// An identity read this way cannot be reloaded.
+    pub fn from_pem(certs: &mut io::BufRead, key: &mut io::BufRead) -> io::Result<Self> {
+        let key = certified_key_from_pem(certs, key)?;
+        Ok(ClientIdentity {
+            paths: None,
+            key: Arc::new(RwLock::new(key)),
+        })
+    }
+
+    /// Reloads the identity from the files it was loaded from.
+    ///
+    /// If the files cannot be read or are invalid, the current identity is
+    /// kept.
+    pub fn reload(&self) -> io::Result<()> {
+        if let Some((ref cert_path, ref key_path)) = self.paths {
+            let key = load_certified_key(cert_path, key_path)?;
+            *self.key.write().expect("client identity lock") = key;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientIdentity")
+            .field("paths", &self.paths)
+            .finish()
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> io::Result<sign::CertifiedKey> {
+    let mut certs = BufReader::new(File::open(cert_path)?);
+    let mut key = BufReader::new(File::open(key_path)?);
+    certified_key_from_pem(&mut certs, &mut key)
+}
+
+fn certified_key_from_pem(
+    certs: &mut io::BufRead,
+    key: &mut io::BufRead,
+) -> io::Result<sign::CertifiedKey> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let certs = pemfile::certs(certs).map_err(|()| invalid("invalid client certificates"))?;
+    if certs.is_empty() {
+        return Err(invalid("no client certificates found"));
+    }
+
+    let mut keys = pemfile::rsa_private_keys(key)
+        .map_err(|()| invalid("invalid client private key"))?;
+    if keys.is_empty() {
+        return Err(invalid("no client private key found"));
+    }
+    let key = sign::RSASigningKey::new(&keys.remove(0))
+        .map_err(|()| invalid("invalid client private key"))?;
+
+    let key: Box<sign::SigningKey> = Box::new(key);
+    Ok(sign::CertifiedKey::new(certs, Arc::new(key)))
+}
+
+// ===== impl ResolveIdentity =====
+
+impl rustls::ResolvesClientCert for ResolveIdentity {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[rustls::SignatureScheme],
+    ) -> Option<sign::CertifiedKey> {
+        Some(self.0.read().expect("client identity lock").clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Returns whether `name` may be used to validate a server's certificate.
+///
+/// Some DNS names, such as those whose last label is numeric, are not valid
+/// TLS server names.
+pub fn is_valid_server_name(name: &dns::Name) -> bool {
+    webpki::DNSNameRef::try_from_ascii_str(server_name(name)).is_ok()
+}
+
+/// Returns the name used for SNI and certificate validation.
+///
+/// Fully-qualified names may have a trailing dot, which isn't valid in either
+/// context, so it is stripped.
+fn server_name(name: &dns::Name) -> &str {
+    name.as_ref().trim_right_matches('.')
+}
+
+// ===== impl ConnectionConfig =====
+
+impl ConnectionConfig {
+    pub fn new(config: ClientConfig, server_name: dns::Name) -> Self {
+        ConnectionConfig {
+            config,
+            server_name,
+        }
+    }
+
+    fn server_name(&self) -> &str {
+        server_name(&self.server_name)
+    }
+
+    /// Begins a TLS handshake over `socket`.
+    pub fn upgrade(&self, socket: PlaintextSocket) -> UpgradeClientToTls {
+        let name = self.server_name();
+        let inner = match webpki::DNSNameRef::try_from_ascii_str(name) {
+            Ok(name) => UpgradeInner::Handshake {
+                connect: self.config.0.connect_async(name, socket),
+                presents_identity: self.config.0.client_auth_cert_resolver.has_certs(),
+            },
+            Err(()) => UpgradeInner::Failed(Some(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid TLS server name: {}", name),
+            ))),
+        };
+        UpgradeClientToTls(inner)
+    }
+}
+
+// ===== impl UpgradeClientToTls =====
+
+impl Future for UpgradeClientToTls {
+    type Item = ClientTlsStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            UpgradeInner::Handshake { ref mut connect, presents_identity } => {
+                connect.poll().map_err(|e| identity_rejected(e, presents_identity))
+            }
+            UpgradeInner::Failed(ref mut e) => {
+                Err(e.take().expect("polled after failure"))
+            }
+        }
+    }
+}
+
+/// Distinguishes handshake failures caused by the server rejecting the
+/// client's certificate from other failures.
+///
+/// With TLS 1.3, the client's handshake may complete before the server has
+/// validated its certificate, in which case the rejection is only seen once
+/// the connection is used.
+fn identity_rejected(e: io::Error, presents_identity: bool) -> io::Error {
+    let alert = match e.get_ref().and_then(|e| e.downcast_ref::<rustls::TLSError>()) {
+        Some(&rustls::TLSError::AlertReceived(alert)) => Some(alert),
+        _ => None,
+    };
+    match alert {
+        Some(alert) if is_certificate_alert(alert, presents_identity) => {
+            io::Error::new(io::ErrorKind::PermissionDenied, IdentityRejected(alert))
+        }
+        _ => e,
+    }
+}
+
+/// Returns whether a server sends `alert` because of the certificate that the
+/// client presented.
+fn is_certificate_alert(alert: AlertDescription, presents_identity: bool) -> bool {
+    match alert {
+        // Some servers, including rustls, reject client certificates with a
+        // generic alert, which may also indicate that the server and client
+        // have no parameters in common.
+        AlertDescription::HandshakeFailure => presents_identity,
+        AlertDescription::BadCertificate |
+        AlertDescription::UnsupportedCertificate |
+        AlertDescription::CertificateRevoked |
+        AlertDescription::CertificateExpired |
+        AlertDescription::CertificateUnknown |
+        AlertDescription::UnknownCA |
+        AlertDescription::AccessDenied |
+        AlertDescription::DecryptError => true,
+        _ => false,
+    }
+}
+
+// ===== impl IdentityRejected =====
+
+impl fmt::Display for IdentityRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "client identity rejected by server: {:?}", self.0)
+    }
+}
+
+impl Error for IdentityRejected {
+    fn description(&self) -> &str {
+        "client identity rejected by server"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{self, Cursor, Write};
+    use std::sync::Arc;
+
+    use futures::{Future, Stream};
+    use rustls;
+    use rustls::internal::pemfile;
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::Core;
+    use tokio_rustls::ServerConfigExt;
+
+    use connection;
+    use dns;
+    use transport::{SocketBuffers, SocketLatency};
+    use super::*;
+
+    const CA: &[u8] = include_bytes!("testdata/ca.pem");
+    const UNTRUSTED_CA: &[u8] = include_bytes!("testdata/untrusted-ca.pem");
+    const SERVER_CERT: &[u8] = include_bytes!("testdata/server.pem");
+    const SERVER_KEY: &[u8] = include_bytes!("testdata/server-key.pem");
+    const CLIENT_CA: &[u8] = include_bytes!("testdata/client-ca.pem");
+    const CLIENT_CERT: &[u8] = include_bytes!("testdata/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("testdata/client-key.pem");
+    const EXPIRED_CLIENT_CERT: &[u8] = include_bytes!("testdata/client-expired.pem");
+
+    fn server_config() -> Arc<rustls::ServerConfig> {
+        Arc::new(server_config_with(rustls::NoClientAuth::new()))
+    }
+
+    fn server_config_with(
+        client_auth: Arc<rustls::ClientCertVerifier>,
+    ) -> rustls::ServerConfig {
+        let certs = pemfile::certs(&mut Cursor::new(SERVER_CERT)).expect("server cert");
+        let mut keys = pemfile::rsa_private_keys(&mut Cursor::new(SERVER_KEY))
+            .expect("server key");
+        let mut config = rustls::ServerConfig::new(client_auth);
+        let _ = config.set_single_cert(certs, keys.remove(0));
+        config
+    }
+
+    /// A server that requires clients to present certificates issued by the
+    /// client CA.
+    ///
+    /// With TLS 1.3, the client's handshake could complete before the server
+    /// rejects its certificate, so TLS 1.2 is used.
+    fn mtls_server_config() -> Arc<rustls::ServerConfig> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_pem_file(&mut Cursor::new(CLIENT_CA)).expect("client CA");
+        let mut config = server_config_with(rustls::AllowAnyAuthenticatedClient::new(roots));
+        config.versions = vec![rustls::ProtocolVersion::TLSv1_2];
+        Arc::new(config)
+    }
+
+    fn client_config(trust_anchors: &[u8]) -> ClientConfig {
+        ClientConfig::from_trust_anchors_pem(&mut Cursor::new(trust_anchors))
+            .expect("trust anchors")
+    }
+
+    fn client_identity(cert: &[u8], key: &[u8]) -> ClientIdentity {
+        ClientIdentity::from_pem(&mut Cursor::new(cert), &mut Cursor::new(key))
+            .expect("client identity")
+    }
+
+    /// Connects to a TLS server presenting a certificate for `server.test`,
+    /// trusting only the certificates in `trust_anchors`.
+    fn connect(trust_anchors: &[u8]) -> io::Result<connection::Connection> {
+        connect_to(server_config(), client_config(trust_anchors))
+    }
+
+    fn connect_to(
+        server_config: Arc<rustls::ServerConfig>,
+        config: ClientConfig,
+    ) -> io::Result<connection::Connection> {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle)
+            .expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let server = listener.incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(conn, _)| {
+                let (socket, _) = conn.expect("accept");
+                server_config.accept_async(socket)
+            })
+            // The server's result depends on whether the client accepted
+            // its certificate, so only the client's is checked.
+            .then(|_| Ok(()));
+        handle.spawn(server);
+
+        let name = dns::Name::normalize("server.test").unwrap();
+        let tls = ConnectionConfig::new(config, name);
+        core.run(connection::connect(
+            &addr,
+            &handle,
+            Some(tls),
+            None,
+            SocketBuffers::default(),
+            SocketLatency::default(),
+        ))
+    }
+
+    fn is_identity_rejected(e: &io::Error) -> bool {
+        e.kind() == io::ErrorKind::PermissionDenied &&
+            e.get_ref().map_or(false, |e| e.downcast_ref::<IdentityRejected>().is_some())
+    }
+
+    #[test]
+    fn handshake_with_trusted_server() {
+        match connect(CA) {
+            Ok(connection::Connection::TlsClient(_)) => {}
+            Ok(c) => panic!("connection should be encrypted: {:?}", c),
+            Err(e) => panic!("handshake should succeed: {}", e),
+        }
+    }
+
+    #[test]
+    fn handshake_with_untrusted_server_fails() {
+        assert!(connect(UNTRUSTED_CA).is_err(), "handshake should fail");
+    }
+
+    #[test]
+    fn presents_client_identity() {
+        let identity = client_identity(CLIENT_CERT, CLIENT_KEY);
+        let config = client_config(CA).with_client_identity(&identity);
+        match connect_to(mtls_server_config(), config) {
+            Ok(connection::Connection::TlsClient(_)) => {}
+            Ok(c) => panic!("connection should be encrypted: {:?}", c),
+            Err(e) => panic!("handshake should succeed: {}", e),
+        }
+    }
+
+    #[test]
+    fn rejected_client_identities_fail_distinctly() {
+        let expired = client_identity(EXPIRED_CLIENT_CERT, CLIENT_KEY);
+        // The server's key does not match the client's certificate.
+        let mismatched = client_identity(CLIENT_CERT, SERVER_KEY);
+        // The server's certificate is not issued by the client CA.
+        let untrusted = client_identity(SERVER_CERT, SERVER_KEY);
+
+        for identity in &[expired, mismatched, untrusted] {
+            let config = client_config(CA).with_client_identity(identity);
+            match connect_to(mtls_server_config(), config) {
+                Err(ref e) if is_identity_rejected(e) => {}
+                Err(e) => panic!("{:?}: handshake should fail distinctly: {:?}", identity, e),
+                Ok(_) => panic!("{:?}: handshake should fail", identity),
+            }
+        }
+
+        // Server certificates that aren't trusted are not attributed to the
+        // client's identity.
+        let identity = client_identity(CLIENT_CERT, CLIENT_KEY);
+        let config = client_config(UNTRUSTED_CA).with_client_identity(&identity);
+        match connect_to(mtls_server_config(), config) {
+            Err(ref e) if !is_identity_rejected(e) => {}
+            rsp => panic!("handshake should fail: {:?}", rsp.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reloads_client_identity() {
+        let dir = env::temp_dir();
+        let cert_path = dir.join("conduit-proxy-tls-test-client.pem");
+        let key_path = dir.join("conduit-proxy-tls-test-client-key.pem");
+        let write = |cert: &[u8]| {
+            File::create(&cert_path).and_then(|mut f| f.write_all(cert)).expect("write cert");
+        };
+        File::create(&key_path).and_then(|mut f| f.write_all(CLIENT_KEY)).expect("write key");
+
+        write(EXPIRED_CLIENT_CERT);
+        let identity = ClientIdentity::load(&cert_path, &key_path).expect("load identity");
+        let config = client_config(CA).with_client_identity(&identity);
+        match connect_to(mtls_server_config(), config.clone()) {
+            Err(ref e) if is_identity_rejected(e) => {}
+            rsp => panic!("expired certificate should be rejected: {:?}", rsp.map(|_| ())),
+        }
+
+        // Once the certificate is rotated, new handshakes present it.
+        write(CLIENT_CERT);
+        identity.reload().expect("reload identity");
+        connect_to(mtls_server_config(), config.clone()).expect("handshake should succeed");
+
+        // An invalid certificate is not loaded in place of the current one.
+        write(b"not a certificate");
+        assert!(identity.reload().is_err());
+        connect_to(mtls_server_config(), config).expect("handshake should succeed");
+
+        let _ = fs::remove_file(&cert_path);
+        let _ = fs::remove_file(&key_path);
+    }
+
+    #[test]
+    fn requires_client_certificate_and_key() {
+        assert!(ClientIdentity::from_pem(
+            &mut Cursor::new(&b"not a certificate"[..]),
+            &mut Cursor::new(CLIENT_KEY),
+        ).is_err());
+        assert!(ClientIdentity::from_pem(
+            &mut Cursor::new(CLIENT_CERT),
+            &mut Cursor::new(CLIENT_CERT),
+        ).is_err());
+    }
+
+    #[test]
+    fn strips_trailing_dot_from_server_name() {
+        let config = ClientConfig::from_trust_anchors_pem(&mut Cursor::new(CA))
+            .expect("trust anchors");
+        let name = dns::Name::normalize("web.default.svc.cluster.local.").unwrap();
+        let tls = ConnectionConfig::new(config, name);
+        assert_eq!(tls.server_name(), "web.default.svc.cluster.local");
+    }
+
+    #[test]
+    fn numeric_top_level_domains_are_invalid_server_names() {
+        let valid = dns::Name::normalize("server.test.").unwrap();
+        assert!(is_valid_server_name(&valid));
+
+        let invalid = dns::Name::normalize("server.123").unwrap();
+        assert!(!is_valid_server_name(&invalid));
+    }
+
+    #[test]
+    fn requires_trust_anchors() {
+        let pem = b"not a certificate";
+        assert!(ClientConfig::from_trust_anchors_pem(&mut Cursor::new(&pem[..])).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use bytes::Buf;
+use futures::{Async, Future, Poll};
+use http::header::HeaderValue;
+use httparse;
+use tokio_core::net::TcpStream;
+use tokio_io::AsyncWrite;
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::net::SocketAddr;
+
+/// The largest response head a CONNECT proxy may send.
+const MAX_RESPONSE_HEAD_BYTES: usize = 8 * 1024;
+
+/// The most headers a CONNECT proxy's response may have.
+const MAX_RESPONSE_HEADERS: usize = 32;
+
+/// An HTTP proxy through which connections are tunneled with `CONNECT`.
+#[derive(Clone, Debug)]
+pub struct ConnectProxy {
+    addr: SocketAddr,
+    authorization: Option<HeaderValue>,
+}
+
+/// A future that establishes a tunnel over a socket connected to a
+/// `ConnectProxy`, completing with the socket once the tunnel is open.
+///
+/// The proxy's response is read a byte at a time, so that none of the bytes
+/// that the target sends through the tunnel are consumed.
+pub struct Tunnel {
+    socket: Option<TcpStream>,
+    request: Cursor<Vec<u8>>,
+    response: Vec<u8>,
+}
+
+/// Indicates that a `ConnectProxy` refused to open a tunnel.
+///
+/// Tunneling failures for this reason are `io::Error`s of kind
+/// `ConnectionRefused` that wrap a `TunnelRejected`.
+#[derive(Debug)]
+pub struct TunnelRejected {
+    status: u16,
+}
+
+// ===== impl ConnectProxy =====
+
+impl ConnectProxy {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            authorization: None,
+        }
+    }
+
+    /// Sends `authorization` to the proxy in a `Proxy-Authorization` header,
+    /// if it is provided.
+    pub fn with_authorization(self, authorization: Option<HeaderValue>) -> Self {
+        Self {
+            authorization,
+            ..self
+        }
+    }
+
+    /// The address of the proxy itself.
+    pub fn addr(&self) -> &SocketAddr {
+        &self.addr
+    }
+
+    /// Asks the proxy, to which `socket` is connected, for a tunnel to
+    /// `target`.
+    pub fn tunnel(&self, target: &SocketAddr, socket: TcpStream) -> Tunnel {
+        // IPv6 addresses are bracketed.
+        let target = target.to_string();
+        let mut request = Vec::new();
+        request.extend_from_slice(b"CONNECT ");
+        request.extend_from_slice(target.as_bytes());
+        request.extend_from_slice(b" HTTP/1.1\r\nHost: ");
+        request.extend_from_slice(target.as_bytes());
+        request.extend_from_slice(b"\r\n");
+        if let Some(ref authorization) = self.authorization {
+            request.extend_from_slice(b"Proxy-Authorization: ");
+            request.extend_from_slice(authorization.as_bytes());
+            request.extend_from_slice(b"\r\n");
+        }
+        request.extend_from_slice(b"\r\n");
+
+        Tunnel {
+            socket: Some(socket),
+            request: Cursor::new(request),
+            response: Vec::new(),
+        }
+    }
+}
+
+// ===== impl Tunnel =====
+
+impl Tunnel {
+    /// Reads from the socket until the proxy's response head is complete.
+    fn poll_response_head(&mut self) -> Poll<(), io::Error> {
+        let socket = self.socket.as_mut().expect("polled after completed");
+
+        while self.request.has_remaining() {
+            if try_ready!(socket.write_buf(&mut self.request)) == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write CONNECT request",
+                ));
+            }
+        }
+
+        while !self.response.ends_with(b"\r\n\r\n") {
+            if self.response.len() >= MAX_RESPONSE_HEAD_BYTES {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "CONNECT response head is too large",
+                ));
+            }
+            let mut byte = [0; 1];
+            match socket.read(&mut byte) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "proxy closed the connection before responding to CONNECT",
+                    ));
+                }
+                Ok(_) => self.response.push(byte[0]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Future for Tunnel {
+    type Item = TcpStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        try_ready!(self.poll_response_head());
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_RESPONSE_HEADERS];
+        let mut response = httparse::Response::new(&mut headers);
+        let status = match response.parse(&self.response) {
+            Ok(httparse::Status::Complete(_)) => response.code,
+            _ => None,
+        };
+        match status {
+            Some(200) => Ok(Async::Ready(self.socket.take().expect("polled after completed"))),
+            Some(status) => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                TunnelRejected { status },
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed CONNECT response",
+            )),
+        }
+    }
+}
+
+// ===== impl TunnelRejected =====
+
+impl TunnelRejected {
+    /// The status with which the proxy responded.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+}
+
+impl fmt::Display for TunnelRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CONNECT proxy responded with status {}", self.status)
+    }
+}
+
+impl Error for TunnelRejected {
+    fn description(&self) -> &str {
+        "CONNECT proxy refused to open a tunnel"
+    }
+}
@@ -226,3 +226,128 @@ fn outbound_updates_newer_services() {
     let client2 = client::http1(proxy.outbound, "disco.test.svc.cluster.local");
     assert_eq!(client2.get("/h1"), "hello h1");
 }
+
+fn tagged_proxy() -> (proxy::Listening, server::Listening, server::Listening) {
+    let stable = server::http1().route("/", "stable").run();
+    let canary = server::http1().route("/", "canary").run();
+    let ctrl = controller::new()
+        .tagged_destination("tagged.test.svc.cluster.local", vec![
+            (stable.addr, "stable"),
+            (canary.addr, "canary"),
+        ])
+        .run();
+
+    let mut env = config::TestEnv::new();
+    env.put(config::ENV_ROUTE_TAG_HEADER, "x-route-tag".to_owned());
+    let proxy = proxy::new().controller(ctrl).run_with_test_env(env);
+    (proxy, stable, canary)
+}
+
+fn get_tagged(client: &client::Client, tag: &str) -> String {
+    let mut req = client.request_builder("/");
+    let rsp = client.request(req.method("GET").header("x-route-tag", tag));
+    assert_eq!(rsp.status(), http::StatusCode::OK);
+    rsp.into_parts().1
+        .concat2()
+        .map(|body| s(&body).to_owned())
+        .wait()
+        .expect("body")
+}
+
+#[test]
+fn outbound_route_tag_header_pins_requests_to_tagged_endpoints() {
+    let _ = env_logger::try_init();
+    let (proxy, _stable, _canary) = tagged_proxy();
+    let client = client::http1(proxy.outbound, "tagged.test.svc.cluster.local");
+
+    for _ in 0..10 {
+        assert_eq!(get_tagged(&client, "canary"), "canary");
+    }
+}
+
+#[test]
+fn outbound_requests_prefer_endpoints_in_the_proxy_zone() {
+    let _ = env_logger::try_init();
+    let local = server::http1().route("/", "local").run();
+    let remote = server::http1().route("/", "remote").run();
+    let other = server::http1().route("/", "other").run();
+    let ctrl = controller::new()
+        .zoned_destination("zoned.test.svc.cluster.local", vec![
+            (local.addr, "us-east-1a"),
+            (remote.addr, "us-east-1b"),
+        ])
+        .zoned_destination("remote.test.svc.cluster.local", vec![
+            (other.addr, "us-east-1b"),
+        ])
+        .run();
+
+    let mut env = config::TestEnv::new();
+    env.put(config::ENV_ZONE, "us-east-1a".to_owned());
+    let proxy = proxy::new().controller(ctrl).run_with_test_env(env);
+
+    let client = client::http1(proxy.outbound, "zoned.test.svc.cluster.local");
+    for _ in 0..10 {
+        assert_eq!(client.get("/"), "local");
+    }
+
+    // Without any local endpoints, requests spill over to other zones.
+    let client = client::http1(proxy.outbound, "remote.test.svc.cluster.local");
+    assert_eq!(client.get("/"), "other");
+}
+
+#[test]
+fn outbound_affinity_header_sticks_requests_to_an_endpoint() {
+    let _ = env_logger::try_init();
+    let srvs = vec!["a", "b", "c"].into_iter()
+        .map(|name| server::http1().route("/", name).run())
+        .collect::<Vec<_>>();
+    let ctrl = controller::new()
+        .tagged_destination("sticky.test.svc.cluster.local", srvs.iter()
+            .map(|srv| (srv.addr, "sticky"))
+            .collect())
+        .run();
+
+    let mut env = config::TestEnv::new();
+    env.put(config::ENV_OUTBOUND_AFFINITY_HEADER, "x-session".to_owned());
+    let proxy = proxy::new().controller(ctrl).run_with_test_env(env);
+    let client = client::http1(proxy.outbound, "sticky.test.svc.cluster.local");
+
+    let get = |session: &str| {
+        let mut req = client.request_builder("/");
+        let rsp = client.request(req.method("GET").header("x-session", session));
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        rsp.into_parts().1
+            .concat2()
+            .map(|body| s(&body).to_owned())
+            .wait()
+            .expect("body")
+    };
+
+    let mut endpoints = (0..20)
+        .map(|i| {
+            let session = format!("session-{}", i);
+            let endpoint = get(&session);
+            for _ in 0..5 {
+                assert_eq!(get(&session), endpoint, "{} changed endpoints", session);
+            }
+            endpoint
+        })
+        .collect::<Vec<_>>();
+    endpoints.sort();
+    endpoints.dedup();
+    assert!(endpoints.len() > 1, "sessions should be spread over endpoints");
+}
+
+#[test]
+fn outbound_unknown_route_tag_falls_back_to_all_endpoints() {
+    let _ = env_logger::try_init();
+    let (proxy, _stable, _canary) = tagged_proxy();
+    let client = client::http1(proxy.outbound, "tagged.test.svc.cluster.local");
+
+    let mut bodies = (0..20)
+        .map(|_| get_tagged(&client, "unknown"))
+        .collect::<Vec<_>>();
+    bodies.sort();
+    bodies.dedup();
+    assert_eq!(bodies, vec!["canary", "stable"]);
+}
@@ -54,6 +54,20 @@ impl Controller {
         )))
     }
 
+    pub fn tagged_destination(self, dest: &str, endpoints: Vec<(SocketAddr, &str)>) -> Self {
+        let endpoints = endpoints.into_iter()
+            .map(|(addr, tag)| (addr, tag.to_owned()))
+            .collect::<Vec<_>>();
+        self.destination_fn(dest, move || Some(tagged_destination_update(&endpoints)))
+    }
+
+    pub fn zoned_destination(self, dest: &str, endpoints: Vec<(SocketAddr, &str)>) -> Self {
+        let endpoints = endpoints.into_iter()
+            .map(|(addr, zone)| (addr, zone.to_owned()))
+            .collect::<Vec<_>>();
+        self.destination_fn(dest, move || Some(zoned_destination_update(&endpoints)))
+    }
+
     pub fn destination_fn<F>(mut self, dest: &str, f: F) -> Self
     where
         F: Fn() -> Option<pb::destination::Update> + Send + 'static,
@@ -273,8 +287,10 @@ pub fn destination_update(addr: SocketAddr,
                             ip: Some(ip_conv(addr.ip())),
                             port: u32::from(addr.port()),
                         }),
-                        weight: 0,
+                        weight: 1,
                         metric_labels: addr_labels,
+                        tags: Vec::new(),
+                        zone: String::new(),
                     },
                 ],
                 metric_labels: set_labels,
@@ -283,6 +299,50 @@ pub fn destination_update(addr: SocketAddr,
     }
 }
 
+pub fn tagged_destination_update(endpoints: &[(SocketAddr, String)]) -> pb::destination::Update {
+    let addrs = endpoints.iter()
+        .map(|&(addr, ref tag)| pb::destination::WeightedAddr {
+            addr: Some(pb::common::TcpAddress {
+                ip: Some(ip_conv(addr.ip())),
+                port: u32::from(addr.port()),
+            }),
+            weight: 1,
+            tags: vec![tag.clone()],
+            ..Default::default()
+        })
+        .collect();
+    pb::destination::Update {
+        update: Some(pb::destination::update::Update::Add(
+            pb::destination::WeightedAddrSet {
+                addrs,
+                ..Default::default()
+            },
+        )),
+    }
+}
+
+pub fn zoned_destination_update(endpoints: &[(SocketAddr, String)]) -> pb::destination::Update {
+    let addrs = endpoints.iter()
+        .map(|&(addr, ref zone)| pb::destination::WeightedAddr {
+            addr: Some(pb::common::TcpAddress {
+                ip: Some(ip_conv(addr.ip())),
+                port: u32::from(addr.port()),
+            }),
+            weight: 1,
+            zone: zone.clone(),
+            ..Default::default()
+        })
+        .collect();
+    pb::destination::Update {
+        update: Some(pb::destination::update::Update::Add(
+            pb::destination::WeightedAddrSet {
+                addrs,
+                ..Default::default()
+            },
+        )),
+    }
+}
+
 pub fn destination_add_none() -> pb::destination::Update {
     pb::destination::Update {
         update: Some(pb::destination::update::Update::Add(
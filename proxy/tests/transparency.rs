@@ -81,6 +81,78 @@ fn http1_removes_connection_headers() {
     assert!(!res.headers().contains_key("x-server-quux"));
 }
 
+#[test]
+fn http1_removes_hop_by_hop_headers() {
+    let _ = env_logger::try_init();
+
+    let srv = server::http1()
+        .route_fn("/", |req| {
+            for name in &["keep-alive", "proxy-authorization", "te", "trailer", "x-foo-bar"] {
+                assert!(!req.headers().contains_key(*name), "{} was forwarded", name);
+            }
+            assert_eq!(req.headers()["x-end-to-end"], "kept");
+            Response::builder()
+                .header("keep-alive", "timeout=5")
+                .header("proxy-authenticate", "Basic")
+                .header("x-end-to-end", "kept")
+                .body("".into())
+                .unwrap()
+        })
+        .run();
+    let ctrl = controller::new().run();
+    let proxy = proxy::new()
+        .controller(ctrl)
+        .inbound(srv)
+        .run();
+    let client = client::http1(proxy.inbound, "transparency.test.svc.cluster.local");
+
+    // Headers named by any of several `Connection` headers are removed.
+    let res = client.request(client.request_builder("/")
+        .header("connection", "keep-alive")
+        .header("connection", "x-foo-bar")
+        .header("keep-alive", "timeout=5")
+        .header("proxy-authorization", "Basic Zm9vOmJhcg==")
+        .header("te", "trailers")
+        .header("trailer", "x-checksum")
+        .header("x-foo-bar", "baz")
+        .header("x-end-to-end", "kept"));
+
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert!(!res.headers().contains_key("keep-alive"));
+    assert!(!res.headers().contains_key("proxy-authenticate"));
+    assert_eq!(res.headers()["x-end-to-end"], "kept");
+}
+
+#[test]
+fn http2_responses_from_http1_remove_hop_by_hop_headers() {
+    let _ = env_logger::try_init();
+
+    let srv = server::http1()
+        .route_fn("/", |_| {
+            Response::builder()
+                .header("connection", "x-server-quux")
+                .header("keep-alive", "timeout=5")
+                .header("x-server-quux", "lorem ipsum")
+                .body("hello h1".into())
+                .unwrap()
+        })
+        .run();
+    let ctrl = controller::new().run();
+    let mut env = config::TestEnv::new();
+    env.put(config::ENV_INBOUND_PROTOCOL_POLICY, "http1".to_owned());
+    let proxy = proxy::new()
+        .controller(ctrl)
+        .inbound(srv)
+        .run_with_test_env(env);
+    let client = client::http2(proxy.inbound, "transparency.test.svc.cluster.local");
+
+    let res = client.request(&mut client.request_builder("/"));
+    assert_eq!(res.status(), http::StatusCode::OK);
+    for name in &["connection", "keep-alive", "x-server-quux"] {
+        assert!(!res.headers().contains_key(*name), "{} was forwarded", name);
+    }
+}
+
 #[test]
 fn http10_with_host() {
     let _ = env_logger::try_init();
@@ -345,33 +417,55 @@ fn tcp_connections_close_if_client_closes() {
 }
 
 #[test]
-fn http11_upgrade_not_supported() {
+fn http11_upgrade_tunnels_bytes() {
+    use std::sync::mpsc;
+
     let _ = env_logger::try_init();
 
-    // our h1 proxy will strip the Connection header
-    // and headers it mentions
-    let msg1 = "\
+    let handshake = "\
         GET /chat HTTP/1.1\r\n\
         Host: foo.bar\r\n\
         Connection: Upgrade\r\n\
         Upgrade: websocket\r\n\
         \r\n\
         ";
-
-    // but let's pretend the server tries to upgrade
-    // anyways
-    let msg2 = "\
+    let switched = "\
         HTTP/1.1 101 Switching Protocols\r\n\
         Upgrade: websocket\r\n\
         Connection: Upgrade\r\n\
         \r\n\
         ";
+    let msg1 = "custom upgraded client hello";
+    let msg2 = "custom upgraded server bye";
+
+    let (tx, rx) = mpsc::channel();
 
     let srv = server::tcp()
-        .accept(move |read| {
-            let head = s(&read);
-            assert!(!head.contains("Upgrade: websocket"));
-            msg2
+        .accept_fut(move |sock| {
+            tokio_io::io::read(sock, vec![0; 1024])
+                .and_then(move |(sock, vec, n)| {
+                    // the handshake is passed through untouched
+                    assert_eq!(s(&vec[..n]), handshake);
+                    tokio_io::io::write_all(sock, switched.as_bytes())
+                })
+                .and_then(|(sock, _)| {
+                    tokio_io::io::read(sock, vec![0; 1024])
+                })
+                .and_then(move |(sock, vec, n)| {
+                    assert_eq!(&vec[..n], msg1.as_bytes());
+                    tokio_io::io::write_all(sock, msg2.as_bytes())
+                })
+                .map(move |_| tx.send(()).unwrap())
+                .map_err(|e| panic!("tcp server error: {}", e))
+        })
+        .accept_fut(move |sock| {
+            tokio_io::io::read(sock, vec![0; 1024])
+                .and_then(move |(sock, _, _)| {
+                    let ok = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+                    tokio_io::io::write_all(sock, ok.as_bytes())
+                })
+                .map(|_| ())
+                .map_err(|e| panic!("tcp server error: {}", e))
         })
         .run();
     let ctrl = controller::new().run();
@@ -384,9 +478,24 @@ fn http11_upgrade_not_supported() {
 
     let tcp_client = client.connect();
 
+    tcp_client.write(handshake);
+    assert_eq!(s(&tcp_client.read()), switched);
+
+    // once the protocol is switched, bytes flow both ways
     tcp_client.write(msg1);
+    assert_eq!(tcp_client.read(), msg2.as_bytes());
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // an upgrade after a connection's first request can't be tunneled, so
+    // it's refused
+    let tcp_client = client.connect();
 
-    let expected = "HTTP/1.1 500 ";
+    tcp_client.write("GET / HTTP/1.1\r\nHost: foo.bar\r\n\r\n");
+    let expected = "HTTP/1.1 200 OK\r\n";
+    assert_eq!(s(&tcp_client.read()[..expected.len()]), expected);
+
+    tcp_client.write(handshake);
+    let expected = "HTTP/1.1 501 Not Implemented\r\n";
     assert_eq!(s(&tcp_client.read()[..expected.len()]), expected);
 }
 
@@ -768,3 +877,85 @@ fn http1_requests_without_host_have_unique_connections() {
     assert_eq!(res.version(), http::Version::HTTP_11);
     assert_eq!(inbound.connections(), 4);
 }
+
+#[test]
+fn outbound_http1_to_h2c_upstream_by_authority() {
+    let _ = env_logger::try_init();
+
+    let srv = server::http2()
+        .route_fn("/", |req| {
+            assert_eq!(req.version(), http::Version::HTTP_2);
+            assert!(!req.headers().contains_key("x-foo-bar"));
+            Response::new("hello h2c".into())
+        })
+        .run();
+    let ctrl = controller::new()
+        .destination("transparency.test.svc.cluster.local", srv.addr)
+        .run();
+    let mut env = config::TestEnv::new();
+    env.put(
+        config::ENV_OUTBOUND_H2C_UPSTREAMS,
+        "transparency.test.svc.cluster.local".to_owned(),
+    );
+    let proxy = proxy::new()
+        .controller(ctrl)
+        .outbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.outbound, "transparency.test.svc.cluster.local");
+
+    let res = client.request(client.request_builder("/")
+        .header("x-foo-bar", "baz")
+        .header("connection", "x-foo-bar"));
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert_eq!(res.version(), http::Version::HTTP_11);
+    assert_eq!(client.get("/"), "hello h2c");
+}
+
+#[test]
+fn outbound_http1_to_h2c_upstream_by_address() {
+    let _ = env_logger::try_init();
+
+    let srv = server::http2()
+        .route_fn("/", |req| {
+            assert_eq!(req.version(), http::Version::HTTP_2);
+            Response::new("hello h2c".into())
+        })
+        .run();
+    let addr = srv.addr;
+    let ctrl = controller::new().run();
+    let mut env = config::TestEnv::new();
+    env.put(config::ENV_OUTBOUND_H2C_UPSTREAMS, addr.to_string());
+    let proxy = proxy::new()
+        .controller(ctrl)
+        .outbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.outbound, addr.to_string());
+
+    assert_eq!(client.get("/"), "hello h2c");
+}
+
+#[test]
+fn inbound_http2_forced_to_http1() {
+    let _ = env_logger::try_init();
+
+    let srv = server::http1()
+        .route_fn("/", |req| {
+            assert_eq!(req.version(), http::Version::HTTP_11);
+            assert_eq!(req.headers()["host"], "transparency.test.svc.cluster.local");
+            Response::new("hello h1".into())
+        })
+        .run();
+    let ctrl = controller::new().run();
+    let mut env = config::TestEnv::new();
+    env.put(config::ENV_INBOUND_PROTOCOL_POLICY, "http1".to_owned());
+    let proxy = proxy::new()
+        .controller(ctrl)
+        .inbound(srv)
+        .run_with_test_env(env);
+    let client = client::http2(proxy.inbound, "transparency.test.svc.cluster.local");
+
+    let res = client.request(&mut client.request_builder("/"));
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert_eq!(res.version(), http::Version::HTTP_2);
+    assert_eq!(client.get("/"), "hello h1");
+}